@@ -0,0 +1,173 @@
+//! Anthropic's Messages API.
+
+use futures::future::LocalBoxFuture;
+use serde_json::{Value, json};
+
+use super::{AiError, Event, Image, Provider, Reply, Request, stream_reply};
+
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-5";
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+const API_VERSION: &str = "2023-06-01";
+/// The API insists on a limit; long enough for anything a terminal shows
+const DEFAULT_MAX_TOKENS: u32 = 2048;
+
+pub struct Anthropic {
+    client: reqwest::Client,
+    api_key: String,
+    model: String,
+    base_url: String,
+}
+
+impl Anthropic {
+    pub fn new(api_key: String, model: String, base_url: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            api_key,
+            model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn body(&self, request: &Request) -> Value {
+        let messages: Vec<Value> = request
+            .messages
+            .iter()
+            .map(|msg| {
+                let content = match &msg.image {
+                    Some(image) => json!([
+                        {"type": "image", "source": {
+                            "type": "base64",
+                            "media_type": Image::MIME_TYPE,
+                            "data": image.base64(),
+                        }},
+                        {"type": "text", "text": msg.content},
+                    ]),
+                    None => json!(msg.content),
+                };
+                json!({"role": msg.role.api_name(), "content": content})
+            })
+            .collect();
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "max_tokens": request.params.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "stream": true,
+        });
+        if let Some(system) = request.system {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = request.params.temperature {
+            // Anthropic's scale stops at 1
+            body["temperature"] = json!(temperature.min(1.0));
+        }
+        if let Some(top_p) = request.params.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        body
+    }
+}
+
+/// One line of the server-sent event stream (the `event:` lines are
+/// repeated in each `data:` line's `type`, so only those are read)
+fn parse_line(line: &str) -> Vec<Event> {
+    let Some(json) = line
+        .strip_prefix("data:")
+        .and_then(|data| serde_json::from_str::<Value>(data.trim()).ok())
+    else {
+        return Vec::new();
+    };
+    let count = |pointer| {
+        json.pointer(pointer)
+            .and_then(Value::as_u64)
+            .map(|n| n as u32)
+    };
+
+    match json.get("type").and_then(Value::as_str) {
+        Some("content_block_delta") => json
+            .pointer("/delta/text")
+            .and_then(Value::as_str)
+            .map(|text| vec![Event::Text(text.to_string())])
+            .unwrap_or_default(),
+        Some("message_start") => vec![Event::Usage {
+            input: count("/message/usage/input_tokens"),
+            output: count("/message/usage/output_tokens"),
+        }],
+        Some("message_delta") => vec![Event::Usage {
+            input: None,
+            output: count("/usage/output_tokens"),
+        }],
+        Some("error") => {
+            let message = json
+                .pointer("/error/message")
+                .and_then(Value::as_str)
+                .unwrap_or("stream error");
+            vec![Event::Error(message.to_string())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl Provider for Anthropic {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(
+        &'a self,
+        request: Request<'a>,
+        on_chunk: &'a mut dyn FnMut(&str),
+    ) -> LocalBoxFuture<'a, Result<Reply, AiError>> {
+        Box::pin(async move {
+            let http = self
+                .client
+                .post(format!("{}/messages", self.base_url))
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", API_VERSION)
+                .json(&self.body(&request));
+            stream_reply(http, on_chunk, parse_line).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(
+                r#"data: {"type":"message_start","message":{"usage":{"input_tokens":25,"output_tokens":1}}}"#
+            ),
+            vec![Event::Usage {
+                input: Some(25),
+                output: Some(1)
+            }]
+        );
+        assert_eq!(
+            parse_line(
+                r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#
+            ),
+            vec![Event::Text("Hi".into())]
+        );
+        assert_eq!(
+            parse_line(r#"data: {"type":"message_delta","usage":{"output_tokens":15}}"#),
+            vec![Event::Usage {
+                input: None,
+                output: Some(15)
+            }]
+        );
+        assert_eq!(
+            parse_line(
+                r#"data: {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#
+            ),
+            vec![Event::Error("Overloaded".into())]
+        );
+        assert!(parse_line("event: ping").is_empty());
+    }
+}
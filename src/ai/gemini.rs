@@ -0,0 +1,195 @@
+//! Google Gemini. The `gemini-rust` client builds the request, but it has
+//! no way to send safety settings, so it's posted here with them added.
+
+use futures::future::LocalBoxFuture;
+use gemini_rust::{
+    Blob, Content, Gemini, GenerationResponse, HarmBlockThreshold, HarmCategory, Model, Part, Role,
+    SafetySetting,
+};
+use serde_json::Value;
+
+use super::{AiError, Event, Image, MessageRole, Provider, Reply, Request, Safety, stream_reply};
+
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// The filters `safety` applies to
+const HARM_CATEGORIES: [HarmCategory; 4] = [
+    HarmCategory::Harassment,
+    HarmCategory::HateSpeech,
+    HarmCategory::SexuallyExplicit,
+    HarmCategory::DangerousContent,
+];
+
+pub struct GeminiProvider {
+    client: Gemini,
+    http: reqwest::Client,
+    api_key: String,
+    model: String,
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: &str, model: String) -> Result<Self, AiError> {
+        // Parse model string to Model enum and create client with that model
+        let client_model: Model = match model.as_str() {
+            "gemini-2.5-pro" => Model::Gemini25Pro,
+            "gemini-2.5-flash-lite" => Model::Gemini25FlashLite,
+            "gemini-2.5-flash" => Model::Gemini25Flash,
+            custom => Model::Custom(format!("models/{}", custom)),
+        };
+        let client = Gemini::with_model(api_key, client_model)
+            .map_err(|e| AiError::ClientError(e.to_string()))?;
+        Ok(Self {
+            client,
+            http: reqwest::Client::new(),
+            api_key: api_key.to_string(),
+            model,
+        })
+    }
+}
+
+fn threshold(safety: Safety) -> HarmBlockThreshold {
+    match safety {
+        Safety::None => HarmBlockThreshold::BlockNone,
+        Safety::High => HarmBlockThreshold::BlockOnlyHigh,
+        Safety::Medium => HarmBlockThreshold::BlockMediumAndAbove,
+        Safety::Low => HarmBlockThreshold::BlockLowAndAbove,
+    }
+}
+
+/// One line of the server-sent event stream
+fn parse_line(line: &str) -> Vec<Event> {
+    let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+        return Vec::new();
+    };
+    let Ok(json) = serde_json::from_str::<Value>(data) else {
+        return Vec::new();
+    };
+    if let Some(message) = json.pointer("/error/message").and_then(Value::as_str) {
+        return vec![Event::Error(message.to_string())];
+    }
+    let Ok(chunk) = serde_json::from_value::<GenerationResponse>(json) else {
+        return Vec::new();
+    };
+
+    let mut events = Vec::new();
+    let text = chunk.text();
+    if !text.is_empty() {
+        events.push(Event::Text(text));
+    }
+    if let Some(metadata) = chunk.usage_metadata {
+        let input = metadata.prompt_token_count.map(|n| n.max(0) as u32);
+        let total = metadata.total_token_count.map(|n| n.max(0) as u32);
+        events.push(Event::Usage {
+            input,
+            output: total.map(|t| t.saturating_sub(input.unwrap_or(0))),
+        });
+    }
+    events
+}
+
+impl Provider for GeminiProvider {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(
+        &'a self,
+        request: Request<'a>,
+        on_chunk: &'a mut dyn FnMut(&str),
+    ) -> LocalBoxFuture<'a, Result<Reply, AiError>> {
+        Box::pin(async move {
+            let mut builder = self.client.generate_content();
+            if let Some(system) = request.system {
+                builder = builder.with_system_prompt(system);
+            }
+            for msg in request.messages {
+                builder = match (msg.role, &msg.image) {
+                    (MessageRole::User, Some(image)) => {
+                        // The picture and the words about it as one turn
+                        builder.contents.push(Content {
+                            parts: Some(vec![
+                                Part::InlineData {
+                                    inline_data: Blob::new(Image::MIME_TYPE, image.base64()),
+                                },
+                                Part::Text {
+                                    text: msg.content.clone(),
+                                    thought: None,
+                                    thought_signature: None,
+                                },
+                            ]),
+                            role: Some(Role::User),
+                        });
+                        builder
+                    }
+                    (MessageRole::User, None) => builder.with_user_message(&msg.content),
+                    (MessageRole::Assistant, _) => builder.with_model_message(&msg.content),
+                };
+            }
+            let params = &request.params;
+            if let Some(max_tokens) = params.max_tokens {
+                builder = builder.with_max_output_tokens(max_tokens as i32);
+            }
+            if let Some(temperature) = params.temperature {
+                builder = builder.with_temperature(temperature);
+            }
+            if let Some(top_p) = params.top_p {
+                builder = builder.with_top_p(top_p);
+            }
+
+            let mut body = builder.build();
+            body.safety_settings = params.safety.map(|safety| {
+                HARM_CATEGORIES
+                    .into_iter()
+                    .map(|category| SafetySetting {
+                        category,
+                        threshold: threshold(safety),
+                    })
+                    .collect()
+            });
+
+            let http = self
+                .http
+                .post(format!(
+                    "{}/models/{}:streamGenerateContent?alt=sse",
+                    BASE_URL, self.model
+                ))
+                .header("x-goog-api-key", &self.api_key)
+                .json(&body);
+            stream_reply(http, on_chunk, parse_line).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(
+                r#"data: {"candidates":[{"content":{"parts":[{"text":"Hel"}],"role":"model"}}]}"#
+            ),
+            vec![Event::Text("Hel".into())]
+        );
+        assert_eq!(
+            parse_line(
+                r#"data: {"candidates":[],"usageMetadata":{"promptTokenCount":12,"totalTokenCount":42}}"#
+            ),
+            vec![Event::Usage {
+                input: Some(12),
+                output: Some(30)
+            }]
+        );
+        assert_eq!(
+            parse_line(r#"data: {"error":{"code":400,"message":"bad"}}"#),
+            vec![Event::Error("bad".into())]
+        );
+        assert!(parse_line("").is_empty());
+    }
+}
@@ -0,0 +1,1009 @@
+//! The AI tab's assistant, from whichever provider `[ai]` names.
+//!
+//! [`AiChat`] keeps the conversation and the system prompt; a [`Provider`]
+//! turns them into a streamed reply. Gemini goes through its client
+//! library; OpenAI-compatible servers, Anthropic and a local Ollama are
+//! spoken to directly over HTTP, their streamed replies read a line at a
+//! time.
+//!
+//! [`store`] saves conversations to come back to.
+
+mod anthropic;
+mod gemini;
+mod ollama;
+mod openai;
+pub mod store;
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::future::LocalBoxFuture;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AiConfig;
+
+/// Who the AI's answers to `@ai` in Chat are from
+pub const AI_NAME: &str = "AI";
+
+/// Names accepted for the `provider` config option
+pub const PROVIDER_NAMES: &[&str] = &["gemini", "openai", "anthropic", "ollama"];
+
+/// Error type for AI operations
+#[derive(Debug)]
+pub enum AiError {
+    /// No API key configured
+    NoApiKey,
+    /// Client creation failed
+    ClientError(String),
+    /// API request failed
+    RequestError(String),
+}
+
+impl std::fmt::Display for AiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AiError::NoApiKey => write!(f, "No AI API key configured"),
+            AiError::ClientError(e) => write!(f, "AI client error: {}", e),
+            AiError::RequestError(e) => write!(f, "AI request error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AiError {}
+
+impl AiError {
+    /// Whether the provider turned the request down for being longer than
+    /// the model can take
+    pub fn is_context_overflow(&self) -> bool {
+        let AiError::RequestError(message) = self else {
+            return false;
+        };
+        let message = message.to_lowercase();
+        [
+            "context length",
+            "context_length",
+            "context window",
+            "maximum context",
+            "too many tokens",
+            "prompt is too long",
+            "input token count",
+        ]
+        .iter()
+        .any(|phrase| message.contains(phrase))
+    }
+}
+
+impl From<reqwest::Error> for AiError {
+    fn from(e: reqwest::Error) -> Self {
+        AiError::RequestError(e.to_string())
+    }
+}
+
+/// A message in the conversation history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: MessageRole,
+    pub content: String,
+    /// A picture sent with it (not saved with the conversation)
+    #[serde(skip)]
+    pub image: Option<Image>,
+}
+
+/// Longest side of a picture sent to the AI; more costs tokens and upload
+/// time without telling it much more
+const MAX_IMAGE_SIDE: u32 = 1024;
+
+/// A picture for the AI to look at, as JPEG
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub const MIME_TYPE: &'static str = "image/jpeg";
+
+    /// `image`, shrunk to [`MAX_IMAGE_SIDE`] if it's bigger
+    pub fn encode(image: &DynamicImage) -> Result<Self, AiError> {
+        let image = if image.width().max(image.height()) > MAX_IMAGE_SIDE {
+            image.thumbnail(MAX_IMAGE_SIDE, MAX_IMAGE_SIDE)
+        } else {
+            image.clone()
+        };
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)
+            .map_err(|e| AiError::ClientError(e.to_string()))?;
+        Ok(Self { data })
+    }
+
+    /// The picture in base64, as the APIs want it
+    fn base64(&self) -> String {
+        base64_encode(&self.data)
+    }
+}
+
+/// Standard base64, with padding
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    User,
+    Assistant,
+}
+
+impl MessageRole {
+    /// The role's name in the OpenAI, Anthropic and Ollama APIs
+    fn api_name(self) -> &'static str {
+        match self {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+        }
+    }
+}
+
+/// Names `/set` takes, in the order it lists them
+pub const PARAM_NAMES: &[&str] = &["temp", "top_p", "max_tokens", "safety"];
+
+/// How replies are generated; anything unset is left to the provider
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Params {
+    /// Randomness, 0 to 2
+    pub temperature: Option<f32>,
+    /// Nucleus sampling cut-off, 0 to 1
+    pub top_p: Option<f32>,
+    /// Longest reply wanted, in tokens
+    pub max_tokens: Option<u32>,
+    /// Gemini's safety filters (the other providers have no such setting)
+    pub safety: Option<Safety>,
+}
+
+impl Params {
+    /// Set the parameter `name` (one of [`PARAM_NAMES`]) from `value`, or
+    /// back to the provider's default with "default"
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let unset = value.eq_ignore_ascii_case("default");
+        let mut params = *self;
+        match name {
+            "temp" | "temperature" => {
+                params.temperature = if unset {
+                    None
+                } else {
+                    Some(parse(name, value)?)
+                }
+            }
+            "top_p" => {
+                params.top_p = if unset {
+                    None
+                } else {
+                    Some(parse(name, value)?)
+                }
+            }
+            "max_tokens" => {
+                params.max_tokens = if unset {
+                    None
+                } else {
+                    Some(parse(name, value)?)
+                }
+            }
+            "safety" => {
+                params.safety = if unset {
+                    None
+                } else {
+                    Some(Safety::from_name(value).ok_or_else(|| {
+                        format!(
+                            "safety '{}' should be one of: {}",
+                            value,
+                            Safety::NAMES.join(", ")
+                        )
+                    })?)
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unknown setting '{}', expected one of: {}",
+                    name,
+                    PARAM_NAMES.join(", ")
+                ));
+            }
+        }
+        params.check()?;
+        *self = params;
+        Ok(())
+    }
+
+    /// The parameter `name` as `/set` shows it
+    pub fn get(&self, name: &str) -> String {
+        let value = match name {
+            "temp" | "temperature" => self.temperature.map(|t| t.to_string()),
+            "top_p" => self.top_p.map(|p| p.to_string()),
+            "max_tokens" => self.max_tokens.map(|n| n.to_string()),
+            "safety" => self.safety.map(|s| s.name().to_string()),
+            _ => None,
+        };
+        value.unwrap_or_else(|| "default".to_string())
+    }
+
+    /// Whether every parameter is in range
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(t) = self.temperature
+            && !(0.0..=2.0).contains(&t)
+        {
+            return Err(format!("temp {} should be from 0 to 2", t));
+        }
+        if let Some(p) = self.top_p
+            && !(0.0..=1.0).contains(&p)
+        {
+            return Err(format!("top_p {} should be from 0 to 1", p));
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens should be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} '{}' isn't a number", name, value))
+}
+
+/// How much Gemini's filters hold back, from letting everything through
+/// to blocking anything even slightly risky
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Safety {
+    None,
+    High,
+    Medium,
+    Low,
+}
+
+impl Safety {
+    /// Names accepted for `safety`: block none, only high-risk, medium and
+    /// up, or low and up
+    pub const NAMES: &'static [&'static str] = &["none", "high", "medium", "low"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Safety::None),
+            "high" => Some(Safety::High),
+            "medium" => Some(Safety::Medium),
+            "low" => Some(Safety::Low),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Safety::None => "none",
+            Safety::High => "high",
+            Safety::Medium => "medium",
+            Safety::Low => "low",
+        }
+    }
+}
+
+/// What one reply asks of the provider
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub system: Option<&'a str>,
+    /// The conversation so far, oldest first, ending with the user's turn
+    pub messages: &'a [ChatMessage],
+    pub params: Params,
+}
+
+/// Tokens a reply used, as the provider reported them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub input: u32,
+    pub output: u32,
+}
+
+impl Usage {
+    pub fn total(&self) -> u32 {
+        self.input + self.output
+    }
+
+    /// Count `other` in too
+    pub fn add(&mut self, other: Usage) {
+        self.input = self.input.saturating_add(other.input);
+        self.output = self.output.saturating_add(other.output);
+    }
+}
+
+/// A finished reply
+#[derive(Debug, Clone, Default)]
+pub struct Reply {
+    pub text: String,
+    pub usage: Option<Usage>,
+}
+
+/// A service that writes the assistant's replies
+pub trait Provider: Send + Sync {
+    /// Short name, matching the `provider` config option
+    fn name(&self) -> &'static str;
+
+    /// The model asked
+    fn model(&self) -> &str;
+
+    /// Reply to `request`, handing each piece of text to `on_chunk` as it
+    /// arrives
+    fn generate<'a>(
+        &'a self,
+        request: Request<'a>,
+        on_chunk: &'a mut dyn FnMut(&str),
+    ) -> LocalBoxFuture<'a, Result<Reply, AiError>>;
+}
+
+/// The model asked of `provider` when `[ai] model` is unset
+pub fn default_model(provider: &str) -> &'static str {
+    match provider {
+        "openai" => openai::DEFAULT_MODEL,
+        "anthropic" => anthropic::DEFAULT_MODEL,
+        "ollama" => ollama::DEFAULT_MODEL,
+        _ => gemini::DEFAULT_MODEL,
+    }
+}
+
+/// The provider `config` names
+fn provider(config: &AiConfig) -> Result<Box<dyn Provider>, AiError> {
+    let model = config.model().to_string();
+    let api_key = config.api_key.clone();
+    let base_url = config.base_url.clone();
+    Ok(match config.provider.as_str() {
+        "openai" => Box::new(openai::OpenAi::new(api_key, model, base_url)),
+        "anthropic" => Box::new(anthropic::Anthropic::new(
+            api_key.ok_or(AiError::NoApiKey)?,
+            model,
+            base_url,
+        )),
+        "ollama" => Box::new(ollama::Ollama::new(model, base_url)),
+        _ => Box::new(gemini::GeminiProvider::new(
+            &api_key.ok_or(AiError::NoApiKey)?,
+            model,
+        )?),
+    })
+}
+
+/// What a line of a streamed reply said
+#[derive(Debug, PartialEq)]
+enum Event {
+    Text(String),
+    /// Token counts so far (either may be missing)
+    Usage {
+        input: Option<u32>,
+        output: Option<u32>,
+    },
+    /// The provider gave up part way
+    Error(String),
+}
+
+/// Times a busy or rate-limited request is tried again before giving up
+const MAX_RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubling each time after
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest wait between tries, even if the server asks for more
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+/// Whether `status` means "not now" rather than "no": rate limits, and
+/// servers overloaded or briefly down (529 is Anthropic's "overloaded")
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+/// How long to wait before retry `attempt` (from 0): doubling each time,
+/// with up to half as much again at random (`jitter`, 0 to 1) so clients
+/// that failed together don't all come back together
+fn backoff(attempt: u32, jitter: f64) -> Duration {
+    let delay = RETRY_DELAY.saturating_mul(1 << attempt.min(8));
+    delay
+        .mul_f64(1.0 + jitter.clamp(0.0, 1.0) / 2.0)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// The wait a `Retry-After` header asks for, in seconds
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let seconds: u64 = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_RETRY_DELAY))
+}
+
+/// Send `request` and read the streamed reply a line at a time with `parse`,
+/// passing text on to `on_chunk` and adding up what it reports. Busy and
+/// rate-limited answers are tried again, backing off, before anything is
+/// streamed; each wait is announced through `on_chunk` so the screen doesn't
+/// just sit there.
+async fn stream_reply(
+    request: reqwest::RequestBuilder,
+    on_chunk: &mut dyn FnMut(&str),
+    parse: impl Fn(&str) -> Vec<Event>,
+) -> Result<Reply, AiError> {
+    let mut attempt = 0;
+    let response = loop {
+        let Some(retry) = request.try_clone() else {
+            break request.send().await?;
+        };
+        let response = retry.send().await?;
+        if attempt >= MAX_RETRIES || !is_transient(response.status()) {
+            break response;
+        }
+        let delay = retry_after(&response).unwrap_or_else(|| backoff(attempt, rand::random()));
+        on_chunk(&format!(
+            "[Busy, retrying in {}s]\n",
+            delay.as_millis().div_ceil(1000)
+        ));
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(AiError::RequestError(format!(
+            "{}: {}",
+            status,
+            error_message(&body)
+        )));
+    }
+
+    let mut reply = Reply::default();
+    let mut handle = |line: &[u8]| -> Result<(), AiError> {
+        for event in parse(String::from_utf8_lossy(line).trim()) {
+            match event {
+                Event::Text(text) => {
+                    reply.text.push_str(&text);
+                    on_chunk(&text);
+                }
+                Event::Usage { input, output } => {
+                    let usage = reply.usage.get_or_insert_default();
+                    usage.input = input.unwrap_or(usage.input);
+                    usage.output = output.unwrap_or(usage.output);
+                }
+                Event::Error(message) => return Err(AiError::RequestError(message)),
+            }
+        }
+        Ok(())
+    };
+
+    let mut stream = response.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(bytes) = stream.next().await {
+        buffer.extend_from_slice(&bytes?);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            handle(&line)?;
+        }
+    }
+    handle(&buffer)?;
+    Ok(reply)
+}
+
+/// The message in an error reply's JSON, or the reply itself
+fn error_message(body: &str) -> String {
+    let json: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    json.as_ref()
+        .and_then(|j| j.pointer("/error/message").or_else(|| j.get("error")))
+        .and_then(|m| m.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| body.chars().take(200).collect())
+}
+
+/// Most messages kept in the conversation history (oldest exchanges are dropped first)
+const MAX_HISTORY_MESSAGES: usize = 40;
+
+/// Roughly how many characters make a token, for guessing a conversation's size
+const CHARS_PER_TOKEN: usize = 4;
+
+/// What a picture is guessed to cost in tokens
+const IMAGE_TOKENS: usize = 1000;
+
+/// Longest summary of the dropped turns asked for, in tokens
+const SUMMARY_TOKENS: u32 = 400;
+
+/// Asks for the summary of old turns, followed by them
+const SUMMARY_PROMPT: &str = "Summarize the conversation below in one short paragraph, \
+    for you to carry on from. Keep names, facts, decisions and anything the user asked \
+    you to remember. Reply with the summary only.";
+
+/// A guess at how many tokens `text` is
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+fn message_tokens(message: &ChatMessage) -> usize {
+    estimate_tokens(&message.content) + message.image.as_ref().map_or(0, |_| IMAGE_TOKENS)
+}
+
+/// How many of the oldest messages to drop, in whole exchanges, to bring
+/// `history` within [`MAX_HISTORY_MESSAGES`] and `budget` tokens (the newest
+/// message is always kept)
+fn overflow(history: &[ChatMessage], budget: usize) -> usize {
+    let mut tokens: usize = history.iter().map(message_tokens).sum();
+    let mut drop = 0;
+    while history.len() - drop > 1
+        && (history.len() - drop > MAX_HISTORY_MESSAGES || tokens > budget)
+    {
+        let exchange = &history[drop..(drop + 2).min(history.len() - 1)];
+        tokens -= exchange.iter().map(message_tokens).sum::<usize>();
+        drop += exchange.len();
+    }
+    drop
+}
+
+/// AI chat session with conversation history
+pub struct AiChat {
+    provider: Box<dyn Provider>,
+    system_prompt: Option<String>,
+    history: Vec<ChatMessage>,
+    params: Params,
+    /// Tokens the whole request may come to
+    context_tokens: usize,
+    /// Whether turns dropped to fit are summarized first
+    summarize: bool,
+    /// What was said before the turns in `history`, if it was summarized
+    summary: Option<String>,
+    /// The last message sent, for `/retry`
+    last_prompt: Option<ChatMessage>,
+    /// Replies are shown exactly as they come, markdown and all, as a
+    /// simulated computer's are
+    verbatim: bool,
+}
+
+impl AiChat {
+    /// Create a new AI chat session from config
+    /// `terminal_width` is the number of columns available for output
+    /// `terminal_mode` is the terminal type (e.g., "vt100" or "vt220")
+    pub fn new(
+        config: &AiConfig,
+        terminal_width: usize,
+        terminal_mode: &str,
+    ) -> Result<Self, AiError> {
+        let provider = provider(config)?;
+        // Checked when the config was loaded
+        let params = config.params().unwrap_or_default();
+
+        // Build system prompt with terminal information
+        // Account for chat buffer margins (4 chars: left border + padding + right border)
+        let available_cols = terminal_width.saturating_sub(4);
+        let system_prompt = config.system_prompt.as_ref().map(|prompt| {
+            format!(
+                "{} The user is on a {} terminal with {} columns. Keep your responses under {} characters per line to avoid wrapping.",
+                prompt, terminal_mode.to_uppercase(), terminal_width, available_cols
+            )
+        });
+
+        Ok(Self {
+            provider,
+            system_prompt,
+            history: Vec::new(),
+            params,
+            context_tokens: config.context_tokens as usize,
+            summarize: config.summarize,
+            summary: None,
+            last_prompt: None,
+            verbatim: false,
+        })
+    }
+
+    /// Check if the AI is configured and available: a key, or a provider
+    /// that may not need one (Ollama, or an OpenAI-compatible local server)
+    pub fn is_available(config: &AiConfig) -> bool {
+        config.api_key.is_some()
+            || config.provider == "ollama"
+            || (config.provider == "openai" && config.base_url.is_some())
+    }
+
+    /// Send a message and stream the response, calling the callback for each chunk
+    pub async fn send_message_streaming<F>(
+        &mut self,
+        message: &str,
+        on_chunk: F,
+    ) -> Result<Reply, AiError>
+    where
+        F: FnMut(&str),
+    {
+        self.send_streaming(message, None, on_chunk).await
+    }
+
+    /// Send a message, with a picture if there is one, and stream the response
+    pub async fn send_streaming<F>(
+        &mut self,
+        message: &str,
+        image: Option<Image>,
+        mut on_chunk: F,
+    ) -> Result<Reply, AiError>
+    where
+        F: FnMut(&str),
+    {
+        // Add user message to history
+        let prompt = ChatMessage {
+            role: MessageRole::User,
+            content: message.to_string(),
+            image,
+        };
+        self.last_prompt = Some(prompt.clone());
+        self.history.push(prompt);
+
+        // What summarizing cost, to count with the reply
+        let mut summary_usage: Option<Usage> = None;
+        let mut reply = loop {
+            if let Some(usage) = self.fit_context().await {
+                summary_usage.get_or_insert_default().add(usage);
+            }
+            let system = self.system();
+            let request = Request {
+                system: system.as_deref(),
+                messages: &self.history,
+                params: self.params,
+            };
+            match self.provider.generate(request, &mut on_chunk).await {
+                Ok(reply) => break reply,
+                Err(e) if e.is_context_overflow() && self.history.len() > 1 => {
+                    // The guess was too generous for this model: aim lower
+                    // from now on, and try again with less
+                    let used: usize = self.history.iter().map(message_tokens).sum();
+                    self.context_tokens = self.context_tokens.min(used) / 2;
+                }
+                Err(e) => {
+                    // Unanswered, so it can be asked again
+                    self.history.pop();
+                    return Err(e);
+                }
+            }
+        };
+
+        if let Some(usage) = summary_usage {
+            reply.usage.get_or_insert_default().add(usage);
+        }
+
+        // Add assistant response to history
+        self.history.push(ChatMessage {
+            role: MessageRole::Assistant,
+            content: reply.text.clone(),
+            image: None,
+        });
+
+        Ok(reply)
+    }
+
+    /// The system prompt, with the summary of earlier turns if there is one
+    fn system(&self) -> Option<String> {
+        let summary = self
+            .summary
+            .as_ref()
+            .map(|s| format!("Earlier in this conversation: {}", s));
+        match (&self.system_prompt, summary) {
+            (Some(prompt), Some(summary)) => Some(format!("{}\n\n{}", prompt, summary)),
+            (prompt, summary) => summary.or_else(|| prompt.clone()),
+        }
+    }
+
+    /// Drop the oldest turns until the request should fit the model's
+    /// context, summarizing them first if `summarize` is on. Returns what the
+    /// summary took.
+    async fn fit_context(&mut self) -> Option<Usage> {
+        let system_tokens = self.system().as_deref().map_or(0, estimate_tokens);
+        let budget = self.context_tokens.saturating_sub(system_tokens);
+        let drop = overflow(&self.history, budget);
+        if drop == 0 {
+            return None;
+        }
+        let dropped: Vec<ChatMessage> = self.history.drain(..drop).collect();
+        if !self.summarize {
+            return None;
+        }
+        match self.summarize_turns(&dropped).await {
+            Ok(reply) => {
+                let summary = reply.text.trim();
+                if !summary.is_empty() {
+                    self.summary = Some(summary.to_string());
+                }
+                reply.usage
+            }
+            Err(e) => {
+                // Carry on without them rather than not answer
+                eprintln!("Failed to summarize the AI conversation: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Ask the model to fold `turns` into the summary so far
+    async fn summarize_turns(&self, turns: &[ChatMessage]) -> Result<Reply, AiError> {
+        let mut transcript = String::new();
+        if let Some(ref summary) = self.summary {
+            transcript.push_str(&format!("(Before that: {})\n", summary));
+        }
+        for turn in turns {
+            let speaker = match turn.role {
+                MessageRole::User => "User",
+                MessageRole::Assistant => "Assistant",
+            };
+            let picture = if turn.image.is_some() {
+                "[picture] "
+            } else {
+                ""
+            };
+            transcript.push_str(&format!("{}: {}{}\n", speaker, picture, turn.content));
+        }
+        let messages = [ChatMessage {
+            role: MessageRole::User,
+            content: format!("{}\n\n{}", SUMMARY_PROMPT, transcript),
+            image: None,
+        }];
+        let request = Request {
+            system: None,
+            messages: &messages,
+            params: Params {
+                max_tokens: Some(SUMMARY_TOKENS),
+                ..self.params
+            },
+        };
+        self.provider.generate(request, &mut |_| {}).await
+    }
+
+    /// Ask a question outside the conversation, as `@ai` does in Chat, and
+    /// stream the answer (the system prompt applies; the history is left
+    /// alone)
+    pub async fn ask_streaming<F>(&self, question: &str, mut on_chunk: F) -> Result<Reply, AiError>
+    where
+        F: FnMut(&str),
+    {
+        let messages = [ChatMessage {
+            role: MessageRole::User,
+            content: question.to_string(),
+            image: None,
+        }];
+        let request = Request {
+            system: self.system_prompt.as_deref(),
+            messages: &messages,
+            params: self.params,
+        };
+        self.provider.generate(request, &mut on_chunk).await
+    }
+
+    /// Ask a one-off question outside the conversation (no history, no system
+    /// prompt, at most `max_tokens`)
+    pub async fn one_off(&self, prompt: &str, max_tokens: u32) -> Result<Reply, AiError> {
+        let messages = [ChatMessage {
+            role: MessageRole::User,
+            content: prompt.to_string(),
+            image: None,
+        }];
+        let request = Request {
+            system: None,
+            messages: &messages,
+            params: Params {
+                max_tokens: Some(max_tokens),
+                ..self.params
+            },
+        };
+        self.provider.generate(request, &mut |_| {}).await
+    }
+
+    /// The conversation so far, for saving
+    pub fn conversation(&self) -> store::Conversation {
+        store::Conversation {
+            system_prompt: self.system_prompt.clone(),
+            summary: self.summary.clone(),
+            verbatim: self.verbatim,
+            messages: self.history.clone(),
+        }
+    }
+
+    /// Carry on from a saved conversation (keeping the current system
+    /// prompt if it had none)
+    pub fn restore(&mut self, conversation: store::Conversation) {
+        if conversation.system_prompt.is_some() {
+            self.system_prompt = conversation.system_prompt;
+        }
+        self.summary = conversation.summary;
+        self.verbatim = conversation.verbatim;
+        self.history = conversation.messages;
+        trim_history(&mut self.history);
+    }
+
+    /// Number of messages in the conversation history
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// The most recent reply in the conversation
+    pub fn last_response(&self) -> Option<&str> {
+        self.history
+            .iter()
+            .rev()
+            .find(|m| m.role == MessageRole::Assistant)
+            .map(|m| m.content.as_str())
+    }
+
+    /// Clear conversation history
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.summary = None;
+        self.last_prompt = None;
+    }
+
+    /// The last message sent, to send again with `/retry`. If it was
+    /// answered, the answer is taken back out of the conversation first.
+    pub fn retry(&mut self) -> Option<ChatMessage> {
+        let prompt = self.last_prompt.clone()?;
+        if let [.., question, answer] = self.history.as_slice()
+            && answer.role == MessageRole::Assistant
+            && question.content == prompt.content
+        {
+            self.history.truncate(self.history.len() - 2);
+        }
+        Some(prompt)
+    }
+
+    /// How replies are being generated
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Change how replies are generated, as `/set` does
+    pub fn params_mut(&mut self) -> &mut Params {
+        &mut self.params
+    }
+
+    /// Whether replies should be shown exactly as they come
+    pub fn verbatim(&self) -> bool {
+        self.verbatim
+    }
+
+    /// Show replies exactly as they come (or not), as for a simulation
+    pub fn set_verbatim(&mut self, verbatim: bool) {
+        self.verbatim = verbatim;
+    }
+
+    /// Set a new system prompt (clears history as well)
+    pub fn set_system_prompt(&mut self, prompt: String) {
+        self.system_prompt = Some(prompt);
+        self.clear_history();
+    }
+}
+
+/// Drop the oldest user/assistant exchanges beyond [`MAX_HISTORY_MESSAGES`]
+fn trim_history(history: &mut Vec<ChatMessage>) {
+    if history.len() > MAX_HISTORY_MESSAGES {
+        // Remove whole exchanges so the history still starts with a user message
+        let excess = (history.len() - MAX_HISTORY_MESSAGES).next_multiple_of(2);
+        history.drain(..excess.min(history.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error_message(r#"{"error":{"type":"auth","message":"bad key"}}"#),
+            "bad key"
+        );
+        assert_eq!(
+            error_message(r#"{"error":"model not found"}"#),
+            "model not found"
+        );
+        assert_eq!(error_message("Bad Gateway"), "Bad Gateway");
+    }
+
+    #[test]
+    fn test_params_set() {
+        let mut params = Params::default();
+        params.set("temp", "0.9").unwrap();
+        params.set("max_tokens", "500").unwrap();
+        params.set("safety", "None").unwrap();
+        assert_eq!(params.temperature, Some(0.9));
+        assert_eq!(params.get("max_tokens"), "500");
+        assert_eq!(params.safety, Some(Safety::None));
+        assert_eq!(params.get("top_p"), "default");
+
+        // A bad value leaves things as they were
+        assert!(params.set("temp", "3").is_err());
+        assert!(params.set("top_p", "lots").is_err());
+        assert!(params.set("max_tokens", "0").is_err());
+        assert!(params.set("safety", "paranoid").is_err());
+        assert!(params.set("seed", "1").is_err());
+        assert_eq!(params.temperature, Some(0.9));
+
+        params.set("temp", "default").unwrap();
+        assert_eq!(params.temperature, None);
+    }
+
+    fn exchanges(n: usize, words: &str) -> Vec<ChatMessage> {
+        let mut history = Vec::new();
+        for _ in 0..n {
+            for role in [MessageRole::User, MessageRole::Assistant] {
+                history.push(ChatMessage {
+                    role,
+                    content: words.to_string(),
+                    image: None,
+                });
+            }
+        }
+        history
+    }
+
+    #[test]
+    fn test_overflow() {
+        // 100 characters is 25 tokens a message
+        let words = "x".repeat(100);
+        let mut history = exchanges(4, &words);
+        history.pop();
+        assert_eq!(overflow(&history, 1000), 0);
+        // Whole exchanges go, oldest first
+        assert_eq!(overflow(&history, 100), 4);
+        assert_eq!(overflow(&history, 76), 4);
+        // However small the budget, the newest message stays
+        assert_eq!(overflow(&history, 0), 6);
+
+        let mut long = exchanges(30, "hi");
+        long.pop();
+        assert_eq!(overflow(&long, 100_000), 20);
+    }
+
+    #[test]
+    fn test_is_context_overflow() {
+        let too_long = AiError::RequestError(
+            "400 Bad Request: This model's maximum context length is 128000 tokens".into(),
+        );
+        assert!(too_long.is_context_overflow());
+        assert!(
+            AiError::RequestError("prompt is too long: 210000 tokens > 200000 maximum".into())
+                .is_context_overflow()
+        );
+        assert!(!AiError::RequestError("401 Unauthorized: bad key".into()).is_context_overflow());
+        assert!(!AiError::NoApiKey.is_context_overflow());
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff(2, 0.0), Duration::from_secs(2));
+        assert_eq!(backoff(2, 1.0), Duration::from_secs(3));
+        assert_eq!(backoff(10, 0.5), MAX_RETRY_DELAY);
+        assert!(is_transient(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_transient(reqwest::StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"art"), "YXJ0");
+        assert_eq!(base64_encode(b"Hello, world"), "SGVsbG8sIHdvcmxk");
+    }
+}
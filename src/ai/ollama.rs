@@ -0,0 +1,140 @@
+//! A local Ollama server: no key, and nothing leaves the machine.
+
+use futures::future::LocalBoxFuture;
+use serde_json::{Value, json};
+
+use super::{AiError, Event, Provider, Reply, Request, stream_reply};
+
+pub const DEFAULT_MODEL: &str = "llama3.2";
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+pub struct Ollama {
+    client: reqwest::Client,
+    model: String,
+    base_url: String,
+}
+
+impl Ollama {
+    pub fn new(model: String, base_url: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn body(&self, request: &Request) -> Value {
+        let mut messages = Vec::new();
+        if let Some(system) = request.system {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        for msg in request.messages {
+            let mut message = json!({"role": msg.role.api_name(), "content": msg.content});
+            if let Some(image) = &msg.image {
+                message["images"] = json!([image.base64()]);
+            }
+            messages.push(message);
+        }
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": true,
+        });
+        let params = &request.params;
+        let mut options = json!({});
+        if let Some(max_tokens) = params.max_tokens {
+            options["num_predict"] = json!(max_tokens);
+        }
+        if let Some(temperature) = params.temperature {
+            options["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = params.top_p {
+            options["top_p"] = json!(top_p);
+        }
+        if options.as_object().is_some_and(|o| !o.is_empty()) {
+            body["options"] = options;
+        }
+        body
+    }
+}
+
+/// One line of the newline-delimited JSON reply
+fn parse_line(line: &str) -> Vec<Event> {
+    let Ok(json) = serde_json::from_str::<Value>(line) else {
+        return Vec::new();
+    };
+    if let Some(error) = json.get("error").and_then(Value::as_str) {
+        return vec![Event::Error(error.to_string())];
+    }
+
+    let mut events = Vec::new();
+    if let Some(text) = json
+        .pointer("/message/content")
+        .and_then(Value::as_str)
+        .filter(|t| !t.is_empty())
+    {
+        events.push(Event::Text(text.to_string()));
+    }
+    // The counts come with the last line
+    if json.get("done").and_then(Value::as_bool) == Some(true) {
+        let count = |key| json.get(key).and_then(Value::as_u64).map(|n| n as u32);
+        events.push(Event::Usage {
+            input: count("prompt_eval_count"),
+            output: count("eval_count"),
+        });
+    }
+    events
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(
+        &'a self,
+        request: Request<'a>,
+        on_chunk: &'a mut dyn FnMut(&str),
+    ) -> LocalBoxFuture<'a, Result<Reply, AiError>> {
+        Box::pin(async move {
+            let http = self
+                .client
+                .post(format!("{}/api/chat", self.base_url))
+                .json(&self.body(&request));
+            stream_reply(http, on_chunk, parse_line).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(
+                r#"{"model":"llama3.2","message":{"role":"assistant","content":"Hi"},"done":false}"#
+            ),
+            vec![Event::Text("Hi".into())]
+        );
+        assert_eq!(
+            parse_line(
+                r#"{"message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":26,"eval_count":290}"#
+            ),
+            vec![Event::Usage {
+                input: Some(26),
+                output: Some(290)
+            }]
+        );
+        assert_eq!(
+            parse_line(r#"{"error":"model \"nope\" not found"}"#),
+            vec![Event::Error(r#"model "nope" not found"#.into())]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! OpenAI's chat completions API, and the many servers that copy it
+//! (`base_url` points elsewhere; the API key is optional for those).
+
+use futures::future::LocalBoxFuture;
+use serde_json::{Value, json};
+
+use super::{AiError, Event, Image, Provider, Reply, Request, stream_reply};
+
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+pub struct OpenAi {
+    client: reqwest::Client,
+    api_key: Option<String>,
+    model: String,
+    base_url: String,
+}
+
+impl OpenAi {
+    pub fn new(api_key: Option<String>, model: String, base_url: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            api_key,
+            model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn body(&self, request: &Request) -> Value {
+        let mut messages = Vec::new();
+        if let Some(system) = request.system {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        for msg in request.messages {
+            let content = match &msg.image {
+                Some(image) => json!([
+                    {"type": "text", "text": msg.content},
+                    {"type": "image_url", "image_url": {
+                        "url": format!("data:{};base64,{}", Image::MIME_TYPE, image.base64()),
+                    }},
+                ]),
+                None => json!(msg.content),
+            };
+            messages.push(json!({"role": msg.role.api_name(), "content": content}));
+        }
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": true,
+            "stream_options": {"include_usage": true},
+        });
+        let params = &request.params;
+        if let Some(max_tokens) = params.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(temperature) = params.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = params.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        body
+    }
+}
+
+/// One line of the server-sent event stream
+fn parse_line(line: &str) -> Vec<Event> {
+    let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+        return Vec::new();
+    };
+    let Ok(json) = serde_json::from_str::<Value>(data) else {
+        // Including the closing "[DONE]"
+        return Vec::new();
+    };
+
+    let mut events = Vec::new();
+    if let Some(message) = json.pointer("/error/message").and_then(Value::as_str) {
+        events.push(Event::Error(message.to_string()));
+    }
+    if let Some(text) = json
+        .pointer("/choices/0/delta/content")
+        .and_then(Value::as_str)
+        .filter(|t| !t.is_empty())
+    {
+        events.push(Event::Text(text.to_string()));
+    }
+    if let Some(usage) = json.get("usage").filter(|u| u.is_object()) {
+        let count = |key| usage.get(key).and_then(Value::as_u64).map(|n| n as u32);
+        events.push(Event::Usage {
+            input: count("prompt_tokens"),
+            output: count("completion_tokens"),
+        });
+    }
+    events
+}
+
+impl Provider for OpenAi {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(
+        &'a self,
+        request: Request<'a>,
+        on_chunk: &'a mut dyn FnMut(&str),
+    ) -> LocalBoxFuture<'a, Result<Reply, AiError>> {
+        Box::pin(async move {
+            let mut http = self
+                .client
+                .post(format!("{}/chat/completions", self.base_url))
+                .json(&self.body(&request));
+            if let Some(ref key) = self.api_key {
+                http = http.bearer_auth(key);
+            }
+            stream_reply(http, on_chunk, parse_line).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(r#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#),
+            vec![Event::Text("Hel".into())]
+        );
+        assert_eq!(
+            parse_line(
+                r#"data: {"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":30}}"#
+            ),
+            vec![Event::Usage {
+                input: Some(12),
+                output: Some(30)
+            }]
+        );
+        assert!(parse_line("data: [DONE]").is_empty());
+        assert!(parse_line(": keep-alive").is_empty());
+        assert!(parse_line("").is_empty());
+    }
+}
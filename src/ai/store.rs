@@ -0,0 +1,257 @@
+//! Saved AI conversations.
+//!
+//! With a `[logging]` directory, each conversation is written to
+//! `ai-conversations/<started>.json` there after every reply, and the most
+//! recent one is picked up again at startup. `/clear` (or a simulation)
+//! begins a new file and leaves the old one to come back to with
+//! `/history load`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use super::ChatMessage;
+
+/// The folder in the logging directory conversations are saved in
+pub const DIR_NAME: &str = "ai-conversations";
+
+/// How a conversation's start is written in its file name
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Most conversations `/history list` shows
+pub const MAX_LISTED: usize = 20;
+
+/// What is saved of a conversation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Conversation {
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// What was said before `messages`, if it was summarized to fit
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// From a simulation, so shown without markdown rendering
+    #[serde(default)]
+    pub verbatim: bool,
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
+}
+
+/// A line of `/history list`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub id: String,
+    /// When it began, e.g. "2025-12-23 14:05"
+    pub started: String,
+    pub messages: usize,
+    /// The first thing asked
+    pub opening: String,
+}
+
+/// The saved conversations, and which one is being added to
+#[derive(Debug)]
+pub struct Conversations {
+    dir: PathBuf,
+    current: String,
+}
+
+impl Conversations {
+    /// The conversations saved under the logging directory `log_dir`,
+    /// carrying on the most recent
+    pub fn open(log_dir: &Path) -> Self {
+        let mut store = Self {
+            dir: log_dir.join(DIR_NAME),
+            current: String::new(),
+        };
+        store.current = store.ids().pop().unwrap_or_else(|| store.new_id());
+        store
+    }
+
+    /// Begin a fresh conversation (saved once it has something in it)
+    pub fn start_new(&mut self) {
+        self.current = self.new_id();
+    }
+
+    /// The conversation being added to, if it has been saved
+    pub fn current(&self) -> Option<Conversation> {
+        self.read(&self.current).ok()
+    }
+
+    /// Save `conversation` as the current one
+    pub fn save(&self, conversation: &Conversation) {
+        if conversation.messages.is_empty() {
+            return;
+        }
+        let path = self.path(&self.current);
+        let tmp = path.with_extension("json.tmp");
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|_| serde_json::to_string_pretty(conversation).map_err(io::Error::other))
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = result {
+            eprintln!("Failed to save AI conversation {}: {}", path.display(), e);
+        }
+    }
+
+    /// The saved conversations, newest first
+    pub fn list(&self) -> Vec<Summary> {
+        self.ids()
+            .into_iter()
+            .rev()
+            .take(MAX_LISTED)
+            .filter_map(|id| {
+                let conversation = self.read(&id).ok()?;
+                let opening = conversation
+                    .messages
+                    .first()
+                    .map(|m| m.content.lines().next().unwrap_or("").to_string())
+                    .unwrap_or_default();
+                Some(Summary {
+                    started: started(&id),
+                    messages: conversation.messages.len(),
+                    opening,
+                    id,
+                })
+            })
+            .collect()
+    }
+
+    /// Make the `n`th conversation of [`list`](Self::list) (from 1) the
+    /// current one, returning it
+    pub fn load(&mut self, n: usize) -> Result<(Summary, Conversation), String> {
+        let summary = n
+            .checked_sub(1)
+            .and_then(|i| self.list().into_iter().nth(i))
+            .ok_or_else(|| format!("No saved conversation {}", n))?;
+        let conversation = self
+            .read(&summary.id)
+            .map_err(|e| format!("Couldn't read conversation {}: {}", n, e))?;
+        self.current = summary.id.clone();
+        Ok((summary, conversation))
+    }
+
+    /// Whether `id` is the conversation being added to
+    pub fn is_current(&self, id: &str) -> bool {
+        self.current == id
+    }
+
+    /// Which conversation is being added to, to come back to with
+    /// [`resume`](Self::resume)
+    pub fn current_id(&self) -> &str {
+        &self.current
+    }
+
+    /// Add to conversation `id` again, as when `/chat switch` returns to it
+    pub fn resume(&mut self, id: String) {
+        self.current = id;
+    }
+
+    /// Saved conversation ids, oldest first
+    fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.strip_suffix(".json").map(str::to_string)
+            })
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// An id for a conversation starting now, not already taken
+    fn new_id(&self) -> String {
+        let base = Local::now().format(ID_FORMAT).to_string();
+        let mut id = base.clone();
+        let mut n = 1;
+        while id == self.current || self.path(&id).exists() {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        id
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn read(&self, id: &str) -> io::Result<Conversation> {
+        let json = fs::read_to_string(self.path(id))?;
+        serde_json::from_str(&json).map_err(io::Error::other)
+    }
+}
+
+/// When the conversation `id` began, for showing
+fn started(id: &str) -> String {
+    NaiveDateTime::parse_from_str(id.get(..15).unwrap_or(id), ID_FORMAT)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::MessageRole;
+
+    fn message(role: MessageRole, content: &str) -> ChatMessage {
+        ChatMessage {
+            role,
+            content: content.to_string(),
+            image: None,
+        }
+    }
+
+    #[test]
+    fn test_conversations_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Conversations::open(dir.path());
+        assert!(store.current().is_none());
+        assert!(store.list().is_empty());
+
+        let first = Conversation {
+            system_prompt: Some("Be brief.".to_string()),
+            summary: None,
+            verbatim: false,
+            messages: vec![
+                message(MessageRole::User, "What is a VT220?"),
+                message(MessageRole::Assistant, "A DEC terminal."),
+            ],
+        };
+        store.save(&first);
+        store.start_new();
+        // Nothing is saved until there's something to save
+        store.save(&Conversation::default());
+        assert_eq!(store.list().len(), 1);
+        let second = Conversation {
+            system_prompt: None,
+            summary: None,
+            verbatim: false,
+            messages: vec![message(MessageRole::User, "Hello")],
+        };
+        store.save(&second);
+
+        // A restart carries on with the newest
+        let mut store = Conversations::open(dir.path());
+        assert_eq!(store.current().unwrap().messages.len(), 1);
+        let list = store.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].opening, "What is a VT220?");
+        assert_eq!(list[1].messages, 2);
+
+        let (summary, loaded) = store.load(2).unwrap();
+        assert!(store.is_current(&summary.id));
+        assert_eq!(loaded.system_prompt.as_deref(), Some("Be brief."));
+        assert!(store.load(3).is_err());
+        assert!(store.load(0).is_err());
+    }
+
+    #[test]
+    fn test_started() {
+        assert_eq!(started("20251223-140501"), "2025-12-23 14:05");
+        assert_eq!(started("20251223-140501-2"), "2025-12-23 14:05");
+    }
+}
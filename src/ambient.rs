@@ -0,0 +1,205 @@
+//! Ambient AI: a fortune-cookie line or a small doodle after a quiet spell.
+//!
+//! With `ambient = true` under `[ai]`, once nobody has typed or chatted
+//! for `ambient_idle` minutes the AI is asked for a one-liner (or, every third
+//! time, a tiny ASCII drawing) in keeping with the terminal's era. It's shown
+//! in Chat on this terminal only, never sent to peers, and once per quiet
+//! spell. Nothing is asked for during `quiet_hours` or once the day's
+//! `ambient_tokens` are spent.
+
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDate, NaiveDateTime, Timelike};
+
+/// Longest quip asked for, in tokens
+pub const MAX_QUIP_TOKENS: u32 = 120;
+
+/// Most lines of a doodle shown
+pub const MAX_QUIP_LINES: usize = 6;
+
+/// Idle tracking and the day's token budget
+pub struct Ambient {
+    idle: Duration,
+    /// Start and end hour (local) of the time with no quips
+    quiet: Option<(u32, u32)>,
+    daily_tokens: u32,
+    /// Tokens spent on `day`
+    used: u32,
+    day: NaiveDate,
+    last_activity: Instant,
+    /// Whether this quiet spell has had its quip
+    spoken: bool,
+    /// Quips so far, to pick a doodle every third time
+    count: u64,
+}
+
+impl Ambient {
+    pub fn new(idle: Duration, quiet: Option<(u32, u32)>, daily_tokens: u32, now: Instant) -> Self {
+        Self {
+            idle,
+            quiet,
+            daily_tokens,
+            used: 0,
+            day: NaiveDate::MIN,
+            last_activity: now,
+            spoken: false,
+            count: 0,
+        }
+    }
+
+    /// Someone typed or chatted: start a new quiet spell
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+        self.spoken = false;
+    }
+
+    /// Whether it's time for a quip. `local` is the wall clock, for quiet hours
+    /// and the daily budget.
+    pub fn due(&mut self, now: Instant, local: NaiveDateTime) -> bool {
+        if local.date() != self.day {
+            self.day = local.date();
+            self.used = 0;
+        }
+        !self.spoken
+            && now.saturating_duration_since(self.last_activity) >= self.idle
+            && !self
+                .quiet
+                .is_some_and(|hours| in_quiet_hours(local.hour(), hours))
+            && self.used + MAX_QUIP_TOKENS <= self.daily_tokens
+    }
+
+    /// The request for the next quip, and mark this quiet spell as done
+    pub fn prompt(&mut self, mode: &str, columns: usize) -> String {
+        self.spoken = true;
+        self.count += 1;
+        let era = era(mode);
+        if self.count.is_multiple_of(3) {
+            format!(
+                "You are the ghost in {}. Nobody has typed anything for a while. \
+                 Draw a tiny ASCII-art doodle of something from that era, at most {} lines \
+                 of up to 30 characters, with a one-line caption. Plain ASCII only, no \
+                 markdown or code fences.",
+                era,
+                MAX_QUIP_LINES - 1
+            )
+        } else {
+            format!(
+                "You are the ghost in {}. Nobody has typed anything for a while. \
+                 Write one short, witty fortune-cookie line in keeping with that era, \
+                 under {} characters. Plain ASCII only, no markdown, no quotes.",
+                era, columns
+            )
+        }
+    }
+
+    /// Count the tokens a quip used against today's budget
+    pub fn spend(&mut self, tokens: u32) {
+        self.used = self.used.saturating_add(tokens);
+    }
+}
+
+/// What the AI is told it's haunting
+fn era(mode: &str) -> &'static str {
+    match mode {
+        "vt100" => "a DEC VT100 terminal in 1978",
+        "vt220" => "a DEC VT220 terminal in 1983",
+        "vt340" => "a DEC VT340 graphics terminal in 1987",
+        "vt525" => "a DEC VT525 colour terminal in 1994",
+        "ansi" => "a dial-up BBS in 1992",
+        _ => "a Unix workstation's xterm in 1991",
+    }
+}
+
+/// Whether `hour` falls in the quiet hours `start-end` (which may run past
+/// midnight)
+fn in_quiet_hours(hour: u32, (start, end): (u32, u32)) -> bool {
+    if start <= end {
+        (start..end).contains(&hour)
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+/// Parse `quiet_hours`: "22-7" (local hours), or empty for none. Returns None
+/// if it doesn't parse.
+pub fn parse_quiet_hours(text: &str) -> Option<Option<(u32, u32)>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(None);
+    }
+    let (start, end) = text.split_once('-')?;
+    let start: u32 = start.trim().parse().ok()?;
+    let end: u32 = end.trim().parse().ok()?;
+    (start < 24 && end < 24).then_some(Some((start, end)))
+}
+
+/// A quip as shown in Chat: control characters and markdown fences gone,
+/// blank lines trimmed, and no more than [`MAX_QUIP_LINES`]
+pub fn clean_quip(text: &str) -> Vec<String> {
+    let lines: Vec<String> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .map(|line| line.chars().filter(|c| !c.is_control()).collect::<String>())
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    let start = lines.iter().position(|l| !l.is_empty()).unwrap_or(0);
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(0, |i| i + 1);
+    lines
+        .into_iter()
+        .take(end)
+        .skip(start)
+        .take(MAX_QUIP_LINES)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        assert_eq!(parse_quiet_hours(""), Some(None));
+        assert_eq!(parse_quiet_hours("22-7"), Some(Some((22, 7))));
+        assert_eq!(parse_quiet_hours("22"), None);
+        assert_eq!(parse_quiet_hours("9-25"), None);
+        assert!(in_quiet_hours(23, (22, 7)));
+        assert!(in_quiet_hours(3, (22, 7)));
+        assert!(!in_quiet_hours(7, (22, 7)));
+        assert!(in_quiet_hours(12, (9, 17)));
+    }
+
+    #[test]
+    fn test_once_per_quiet_spell_within_budget() {
+        let start = Instant::now();
+        let idle = Duration::from_secs(60);
+        let mut ambient = Ambient::new(idle, Some((22, 7)), MAX_QUIP_TOKENS * 2, start);
+        assert!(!ambient.due(start, at(12)));
+        let later = start + idle;
+        assert!(!ambient.due(later, at(23)), "quiet hours");
+        assert!(ambient.due(later, at(12)));
+        assert!(ambient.prompt("vt220", 76).contains("VT220"));
+        ambient.spend(MAX_QUIP_TOKENS + 10);
+        assert!(!ambient.due(later, at(12)), "one per quiet spell");
+
+        ambient.activity(later);
+        let much_later = later + idle;
+        assert!(!ambient.due(much_later, at(13)), "budget spent");
+        // A new day, a new budget
+        assert!(ambient.due(much_later, at(13) + chrono::Duration::days(1)));
+
+        assert_eq!(
+            clean_quip("```\n\n (o o)\n  \\_/ \x1b[5m\n```\n"),
+            vec![" (o o)", "  \\_/ [5m"]
+        );
+    }
+}
@@ -1,21 +1,86 @@
+use chrono::{Local, TimeZone};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Write;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::sync::mpsc;
 
+use crate::ai::store::{Conversation, Conversations};
+use crate::ai::{AI_NAME, AiChat, MessageRole, Usage};
+use crate::ambient::{Ambient, MAX_QUIP_TOKENS, clean_quip, parse_quiet_hours};
+use crate::clock::{ClockEvent, ClockMonitor};
 use crate::config::Config;
-use crate::gemini::GeminiChat;
-use crate::graphics::Frame;
+use crate::graphics::{DrcsFont, Frame, Renderer, TerminalCaps, select_renderer};
+use crate::input::{Compose, KeyMap};
+use crate::keys::Bindings;
 use crate::log::SessionLogger;
+use crate::macros::Macros;
+use crate::motion::MotionDetector;
 use crate::network::{
-    self, DiscoveredPeer, Discovery, Message, NetworkNode, PeerEvent, run_discovery,
+    self, Backfill, CAP_ACCENTS, CAP_AUDIO, CAP_COLOR, CAP_FILES, CAP_VIDEO, Caps, ChatHistory,
+    DiscoveredPeer, Discovery, DiscoverySettings, FileTransfers, HASH_LEN, HistoryEntry,
+    Impairment, Message, NetworkError, NetworkNode, PeerEvent, PeerSocket, ProtocolTap, Receipts,
+    RoomTopic, Roster, SyncTrack, TodoList, TodoOp, TransferEvent, TunesSync, VideoReceiver,
+    VideoSender, WhoisInfo, run_discovery,
 };
+use crate::notify::{Alert, Bell, RING_PATTERN, parse_alert};
+use crate::paste::Paste;
+use crate::qr::QrCode;
+use crate::recall::InputHistory;
+use crate::screensaver::{Screensaver, Style as ScreensaverStyle};
 use crate::serial::Serial;
-use crate::terminal::{ChatBuffer, Tab, init_split_screen_with_tabs};
-use crate::tunes::TunesState;
-use crate::webcam::{RawFrame, Webcam};
+use crate::state::{
+    BufferState, CallState, InputState, PeerState, STATE_DUMP_VERSION, StateDump, redacted_config,
+};
+use crate::terminal::{
+    Charset, ChatBuffer, CollapsedShares, Controls, Markdown, Tab, Theme, Unread, controls,
+    init_split_screen_with_tabs, layout, max_input_length, redraw_input, redraw_separator,
+    redraw_status_bar, redraw_tab_bar, render_stats, stamp, transparent_print,
+};
+use crate::testcard;
+use crate::timer::{POMODORO_BREAK, POMODORO_WORK, Timer, TimerEvent, TimerKind};
+use crate::todo::TodoState;
+use crate::tunes::{AudioPlayer, TunesState};
+use crate::watchdog::{Heartbeat, Watch};
+use crate::webcam::{self, RawFrame, Webcam};
+use crate::windows::{self, Target, Windows};
+
+/// Corrupted video frames are reported in chat at most this often
+const CORRUPT_NOTICE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest timer label, in characters
+pub const MAX_TIMER_LABEL: usize = 24;
+
+/// To-do operations per message when sending a peer the whole list (keeps
+/// each datagram well under the MTU)
+const TODO_OPS_PER_MESSAGE: usize = 4;
+
+/// A peer's `/ring` is ignored if it comes sooner than this after the last
+const RING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long an ambient quip may take before we give up on it
+const AMBIENT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Chat messages that can still be corrected (older ones are forgotten)
+const MAX_EDITABLE: usize = 200;
+
+/// A chat message that its sender may still correct (`/edit`)
+struct Editable {
+    from: String,
+    /// The id the sender gave it
+    id: u64,
+    /// The key its lines are under in the chat buffer
+    key: u64,
+    /// The start of its line: timestamp and name
+    prefix: String,
+    /// What it says now
+    text: String,
+    /// The whole line as shown
+    line: String,
+}
 
 /// Helper macro to print status and flush stdout
 macro_rules! status {
@@ -30,10 +95,41 @@ pub struct App {
     pub serial: Serial,
     pub net_node: NetworkNode,
     pub webcam: Option<Webcam>,
-    pub gemini_chat: Option<GeminiChat>,
+    /// Backend used to draw webcam frames and images on the local terminal
+    pub renderer: Arc<dyn Renderer>,
+    /// Soft font loaded on terminals that have them
+    pub drcs_font: Arc<DrcsFont>,
+    /// How the UI's borders and highlights are drawn
+    pub theme: Theme,
+    /// The screen layout in use, by name (`/layout` changes it)
+    pub layout_name: String,
+    pub screensaver: Screensaver,
+    /// What the keys on the terminal's keyboard stand for
+    pub keymap: KeyMap,
+    /// The keys bound to actions under `[keys]`
+    pub bindings: Bindings,
+    /// Abbreviations and canned messages from `[macros]`
+    pub macros: Macros,
+    /// A compose sequence being typed
+    pub compose: Compose,
+    /// A paste being gathered
+    pub paste: Option<Paste>,
+    /// Lines pasted, waiting for `y` or `n`
+    pub pending_paste: Option<Vec<String>>,
+    pub ai_chat: Option<AiChat>,
+    /// Saved AI conversations (None without a logging directory, or in a
+    /// preview session)
+    pub ai_conversations: Option<Conversations>,
+    /// The name of the AI conversation on the AI tab (`/chat`)
+    pub ai_session: String,
+    /// The other named AI conversations, put aside by `/chat`
+    pub ai_sessions: BTreeMap<String, AiSession>,
     pub tunes_state: Option<TunesState>,
+    /// The chat window shown on the Chat tab
     pub chat_buffer: ChatBuffer,
     pub ai_buffer: ChatBuffer,
+    /// The mesh chat, direct message and channel windows (`/window`)
+    pub windows: Windows,
     pub logger: Option<SessionLogger>,
     pub active_tab: Tab,
     pub active_call: Option<String>,
@@ -44,12 +140,84 @@ pub struct App {
     pub last_rendered_frame: Option<Frame>,
     pub line_buffer: String,
     pub input_cursor: usize,
-    pub input_history: Vec<String>,
+    pub input_history: InputHistory,
     pub history_index: Option<usize>,
     pub ai_processing: bool,
+    /// Tokens the AI has used since startup
+    pub ai_usage: Usage,
+    /// Whether session output is being copied to the terminal's printer port
+    pub capture_active: bool,
     pub running: Arc<AtomicBool>,
+    /// When the session started, for `/whois` uptime
+    pub started: std::time::Instant,
+    /// Notices suspend/resume and wall-clock steps
+    pub clock: ClockMonitor,
     /// Frame ID counter for video transmission (wraps at 255)
     pub video_frame_id: u8,
+    /// Paces the video we send to adapt to congestion
+    pub video_sender: VideoSender,
+    /// Counts the video we receive, for reports back to the sender
+    pub video_receiver: VideoReceiver,
+    /// Watches the idle webcam for movement (`motion` under `[webcam]`)
+    pub motion: MotionDetector,
+
+    /// LAN discovery service (None in a preview session)
+    pub discovery: Option<Arc<Discovery>>,
+
+    /// Recent chat, shared with peers that join mid-conversation
+    pub chat_history: ChatHistory,
+    /// History sent to us by a peer when we joined
+    pub backfill: Backfill,
+    /// Room topic shown on the separator row
+    pub topic: Option<RoomTopic>,
+    /// Line pinned to the top of the chat area
+    pub pin: Option<RoomTopic>,
+    /// Peer calling us and when it rang, until the call is answered or declined
+    pub incoming_call: Option<(String, std::time::Instant)>,
+    /// File transfers with peers (None if no files directory is configured)
+    pub transfers: Option<FileTransfers>,
+    /// `/tunes sync`s under way
+    pub tunes_sync: TunesSync,
+    /// Our chat lines waiting for every peer to confirm delivery
+    pub receipts: Receipts,
+    /// Public addresses from the periodic STUN refresh (None in a preview session)
+    pub public_addr_rx: Option<mpsc::Receiver<SocketAddr>>,
+    /// Peers' tall multi-line shares, shown collapsed until `/expand`ed
+    pub collapsed: CollapsedShares,
+    /// Recent chat messages, ours and peers', that may still be edited
+    editable: std::collections::VecDeque<Editable>,
+    /// What each peer can do, from the card it sent when it joined
+    pub peer_caps: HashMap<String, Caps>,
+    /// Peers we've asked for a `/whois` card to show
+    whois_wanted: HashSet<String>,
+    /// Peers remembered across restarts (None in a preview session or if disabled)
+    pub roster: Option<Roster>,
+    /// The Todo tab's shared list (not saved in a preview session)
+    pub todo: TodoState,
+    /// The `/timer` countdown or pomodoro session, if one is running
+    pub timer: Option<Timer>,
+    /// Quips after a quiet spell (`ambient` under `[ai]`), if enabled
+    pub ambient: Option<Ambient>,
+    /// How each contact's messages are announced, by lowercased name
+    pub alerts: HashMap<String, Alert>,
+    /// Rings of a bell pattern still to be sent
+    pub bell: Bell,
+    /// When each peer last rang our bell with `/ring`
+    pub last_rings: HashMap<String, std::time::Instant>,
+    /// Plays contacts' notification sounds (None if none are set or there's
+    /// no audio output)
+    pub alert_player: Option<AudioPlayer>,
+    /// What the separator row last showed on the right (the timer or the
+    /// search), and on which tab
+    pub separator_shown: (Tab, Option<String>),
+    /// The `/search` status for the separator row, while stepping through matches
+    pub search_label: Option<String>,
+    /// What the status bar on the bottom row last showed
+    pub status_shown: Option<String>,
+    /// What arrived on tabs that weren't showing, for the tab bar badges
+    pub unread: Unread,
+    /// The badges the tab bar last showed
+    unread_shown: Unread,
 
     // Channels
     pub discovery_rx: mpsc::Receiver<DiscoveredPeer>,
@@ -58,7 +226,16 @@ pub struct App {
 
     // Task handles
     pub net_recv_task: tokio::task::JoinHandle<()>,
-    pub _discovery_shutdown_tx: tokio::sync::watch::Sender<bool>,
+    pub discovery_task: Option<tokio::task::JoinHandle<()>>,
+    pub discovery_shutdown_tx: tokio::sync::watch::Sender<bool>,
+    /// The receive and discovery tasks' ends of the channels, to restart them with
+    net_tx: mpsc::Sender<Message>,
+    peer_event_tx: mpsc::Sender<PeerEvent>,
+    discovery_tx: mpsc::Sender<DiscoveredPeer>,
+    /// Looks after the background threads and tasks
+    watches: Watches,
+    /// An offline preview session, which keeps off the LAN
+    preview: bool,
 
     // Stats
     pub stats_last_check: std::time::Instant,
@@ -66,16 +243,88 @@ pub struct App {
     pub stats_frames_rendered: usize,
     pub stats_frames_sent: usize,
     pub stats_frames_received: usize,
+    /// Corrupted video frames dropped since the last notice, and when that was
+    pub corrupt_frames: usize,
+    pub corrupt_notice_at: Option<std::time::Instant>,
+}
+
+/// What `/enable` and `/disable` can turn on and off while the session runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Webcam,
+    Ai,
+    Tunes,
+    Discovery,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 4] = [
+        Subsystem::Webcam,
+        Subsystem::Ai,
+        Subsystem::Tunes,
+        Subsystem::Discovery,
+    ];
+
+    /// The name typed after `/enable`
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Webcam => "webcam",
+            Subsystem::Ai => "ai",
+            Subsystem::Tunes => "tunes",
+            Subsystem::Discovery => "discovery",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// What to call it in notices
+    fn label(self) -> &'static str {
+        match self {
+            Subsystem::Webcam => "The webcam",
+            Subsystem::Ai => "The AI",
+            Subsystem::Tunes => "Tunes",
+            Subsystem::Discovery => "LAN discovery",
+        }
+    }
+}
+
+/// The watchdog's view of each background thread and task
+struct Watches {
+    net: Watch,
+    discovery: Watch,
+    webcam: Watch,
+    tunes: Watch,
+    alerts: Watch,
+}
+
+impl Watches {
+    fn new() -> Self {
+        // Handling a packet takes microseconds; a webcam snapshot, seconds
+        let limit = Duration::from_secs(10);
+        Self {
+            net: Watch::new("network receive task", limit),
+            discovery: Watch::new("LAN discovery task", limit),
+            webcam: Watch::new("webcam thread", Duration::from_secs(20)),
+            tunes: Watch::new("tunes playback thread", limit),
+            alerts: Watch::new("contact sound playback thread", limit),
+        }
+    }
 }
 
 impl App {
     pub async fn new(
         config: Config,
         running: Arc<AtomicBool>,
+        preview: bool,
+        protocol_tap: Option<ProtocolTap>,
+        impairment: Option<Impairment>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Open serial port
         status!("Opening serial port {}... ", config.serial.port);
-        let mut serial = match Serial::open(&config.serial) {
+        let serial = match Serial::open(&config.serial) {
             Ok(s) => {
                 println!("OK");
                 s
@@ -86,123 +335,179 @@ impl App {
                 return Err(e.into());
             }
         };
+        Self::with_serial(config, serial, running, preview, protocol_tap, impairment).await
+    }
 
-        // Set up networking
-        status!("Starting network on port {}... ", config.network.port);
-        let mut net_node =
-            match NetworkNode::new(config.network.name.clone(), config.network.port).await {
-                Ok(n) => {
-                    println!("OK");
-                    n
-                }
-                Err(e) => {
-                    println!("FAILED");
-                    eprintln!("Network error: {}", e);
-                    // Explicitly drop serial before exiting to release the port
-                    drop(serial);
-                    eprintln!("Serial port released.");
-                    return Err(e.into());
+    /// Start a session on a terminal that's already open (a loopback one, in
+    /// the tests)
+    pub async fn with_serial(
+        config: Config,
+        mut serial: Serial,
+        running: Arc<AtomicBool>,
+        preview: bool,
+        protocol_tap: Option<ProtocolTap>,
+        impairment: Option<Impairment>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Pick a graphics renderer, asking the terminal what it supports if needed
+        status!("Selecting renderer... ");
+        let profile = TerminalCaps::from_mode(&config.terminal.mode);
+        let multinational = Charset::for_mode(&config.terminal.mode) == Charset::Multinational;
+        let reply = if config.terminal.renderer == "auto"
+            || (config.terminal.controls == "auto" && multinational)
+        {
+            query_device_attributes(&mut serial)
+        } else {
+            None
+        };
+        let caps = if config.terminal.renderer == "auto" {
+            // DA1 can't report UTF-8 support, so that always comes from the profile
+            reply
+                .as_deref()
+                .and_then(|reply| TerminalCaps::from_da1(&controls::seven_bit(reply)))
+                .map(|caps| TerminalCaps {
+                    unicode: profile.unicode,
+                    ..caps
+                })
+                .unwrap_or(profile)
+        } else {
+            profile
+        };
+        let drcs_font = match &config.terminal.drcs_font {
+            Some(path) => {
+                status!("Loading DRCS font {}... ", path);
+                match DrcsFont::load(Path::new(path)) {
+                    Ok(font) => {
+                        println!("OK");
+                        Arc::new(font)
+                    }
+                    Err(e) => {
+                        println!("FAILED");
+                        eprintln!("Error: {}", e);
+                        return Err(e.into());
+                    }
                 }
-            };
+            }
+            None => Arc::new(DrcsFont::default()),
+        };
+        let renderer = select_renderer(
+            &config.terminal.renderer,
+            &caps,
+            config.webcam.sixel_shades,
+            &drcs_font,
+        );
+        println!("{}", renderer.name());
 
-        // Try STUN discovery
-        status!("Discovering public endpoint via STUN... ");
-        match network::discover_public_endpoint(config.network.port) {
-            Ok(addr) => {
-                println!("{}", addr);
-                net_node.set_public_addr(addr);
+        // Only a VT220 or later can be in 8-bit mode
+        status!("Control sequences... ");
+        let controls = match config.terminal.controls.as_str() {
+            "8bit" => Controls::EightBit,
+            "auto" if multinational => reply
+                .as_deref()
+                .map(Controls::from_reply)
+                .unwrap_or_default(),
+            _ => Controls::SevenBit,
+        };
+        match controls {
+            Controls::SevenBit => println!("7-bit"),
+            Controls::EightBit => println!("8-bit"),
+        }
+
+        // Set up networking (a preview session takes any free port to stay out of the way)
+        let port = if preview { 0 } else { config.network.port };
+        status!("Starting network on port {}... ", port);
+        let mut net_node = match NetworkNode::new(config.network.name.clone(), port).await {
+            Ok(n) => {
+                println!(
+                    "{}",
+                    if n.is_dual_stack() {
+                        "OK (IPv4+IPv6)"
+                    } else {
+                        "OK (IPv4 only)"
+                    }
+                );
+                n
             }
             Err(e) => {
                 println!("FAILED");
-                eprintln!("  {}", e);
-            }
-        }
-
-        // Try UPnP port forwarding if enabled
-        if config.network.upnp {
-            status!("Setting up UPnP port forwarding... ");
-            match network::setup_port_forward(
-                config.network.port,
-                config.network.port,
-                "Wormhole Chat",
-                config.network.bind_ip.as_deref(),
-            ) {
-                Ok(addr) => {
-                    println!("OK (external port {})", addr);
-                }
-                Err(e) => {
-                    println!("FAILED");
-                    eprintln!("  {}", e);
-                }
+                eprintln!("Network error: {}", e);
+                // Explicitly drop serial before exiting to release the port
+                drop(serial);
+                eprintln!("Serial port released.");
+                return Err(e.into());
             }
-        }
-
-        // Connect to configured peers
-        if !config.network.peers.is_empty() {
-            println!("Connecting to peers...");
-            for peer_str in config.network.peers.split(',') {
-                let peer_str = peer_str.trim();
-                if let Ok(addr) = peer_str.parse::<SocketAddr>() {
-                    status!("  {}... ", addr);
-                    match net_node.connect_to_peer(addr).await {
-                        Ok(_) => println!("OK"),
-                        Err(e) => {
-                            println!("FAILED");
-                            eprintln!("    {}", e);
-                        }
+        };
+        let (recv_buffer, send_buffer) = (
+            config.network.recv_buffer * 1024,
+            config.network.send_buffer * 1024,
+        );
+        if recv_buffer > 0 || send_buffer > 0 {
+            status!("Sizing socket buffers... ");
+            match net_node.set_buffer_sizes(recv_buffer, send_buffer) {
+                Ok((recv, send)) => {
+                    println!("{} KiB receive, {} KiB send", recv / 1024, send / 1024);
+                    // Linux silently caps requests at the sysctl limits
+                    if recv < recv_buffer {
+                        eprintln!(
+                            "Warning: asked for a {} KiB receive buffer; raise net.core.rmem_max to allow it",
+                            recv_buffer / 1024
+                        );
+                    }
+                    if send < send_buffer {
+                        eprintln!(
+                            "Warning: asked for a {} KiB send buffer; raise net.core.wmem_max to allow it",
+                            send_buffer / 1024
+                        );
                     }
-                } else {
-                    println!("  {}... INVALID ADDRESS", peer_str);
                 }
+                Err(e) => println!("FAILED ({})", e),
             }
         }
+        if let Some(tap) = protocol_tap {
+            net_node.set_protocol_tap(tap);
+        }
+        if let Some(impairment) = impairment {
+            net_node.set_impairment(impairment);
+        }
 
-        // Set up peer discovery
-        status!("Starting LAN discovery... ");
-        // Parse bind_ip for discovery - determines which interface to broadcast on
-        let discovery_bind_ip: std::net::Ipv4Addr = config
-            .network
-            .bind_ip
-            .as_ref()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(std::net::Ipv4Addr::UNSPECIFIED);
-
-        let discovery = match Discovery::new(
-            config.network.name.clone(),
-            config.network.port,
-            discovery_bind_ip,
-        )
-        .await
-        {
-            Ok(d) => {
-                println!("OK");
-                Arc::new(d)
-            }
-            Err(e) => {
-                println!("FAILED");
-                eprintln!("  {} (continuing without LAN discovery)", e);
-                // Continue without discovery - we can still connect to manual peers
-                Arc::new(
-                    Discovery::new(config.network.name.clone(), 0, discovery_bind_ip)
-                        .await
-                        .unwrap(),
-                )
-            }
+        net_node.set_mesh_relay(config.network.mesh_relay);
+        net_node.set_upload_limit(
+            (config.network.upload_limit > 0).then(|| config.network.upload_limit * 1024),
+        );
+        net_node.set_keepalive(
+            (config.network.keepalive > 0).then(|| Duration::from_secs(config.network.keepalive)),
+        );
+        let (public_addr_rx, roster) = if preview {
+            println!("Preview session: skipping STUN, UPnP, peers, relay and LAN discovery");
+            (None, None)
+        } else {
+            let roster = (!config.network.roster.is_empty())
+                .then(|| Roster::load(Path::new(&config.network.roster)));
+            connect_network(&config, &mut net_node, roster.as_ref()).await;
+            (
+                Some(network::spawn_public_addr_monitor(config.network.port)),
+                roster,
+            )
         };
 
-        // Channels for discovered peers
+        // Channels for discovered peers (also fed by the network receive
+        // task, for DiscoveryAnnounce messages on the main port)
         let (discovery_tx, discovery_rx) = mpsc::channel::<DiscoveredPeer>(32);
-        // Clone sender for use in network receive task (for DiscoveryAnnounce messages on main port)
-        let discovery_tx_clone = discovery_tx.clone();
 
         // Shutdown signal for discovery
         let (discovery_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
-        // Spawn discovery task
-        let discovery_clone = Arc::clone(&discovery);
-        tokio::spawn(async move {
-            run_discovery(discovery_clone, discovery_tx, shutdown_rx).await;
-        });
+        let watches = Watches::new();
+        let (discovery, discovery_task) = if preview {
+            (None, None)
+        } else if !config.network.discovery {
+            println!("LAN discovery disabled");
+            (None, None)
+        } else {
+            let heartbeat = watches.discovery.heartbeat();
+            let (discovery, task) =
+                start_discovery(&config, discovery_tx.clone(), shutdown_rx, heartbeat).await;
+            (Some(discovery), Some(task))
+        };
 
         println!();
         println!("Ready.");
@@ -212,106 +517,49 @@ impl App {
         let (net_tx, net_rx) = mpsc::channel::<Message>(32);
         let (peer_event_tx, peer_event_rx) = mpsc::channel::<PeerEvent>(32);
 
-        let socket = net_node.socket();
-        let running_net = running.clone();
-
-        // Spawn network receive task
-        let net_recv_task = tokio::spawn(async move {
-            let discovery_tx = discovery_tx_clone;
-            let mut buf = [0u8; 65535]; // Increased buffer size for stream frames
-            while running_net.load(Ordering::SeqCst) {
-                // Use a timeout to allow checking the running flag periodically
-                match tokio::time::timeout(Duration::from_millis(500), socket.recv_from(&mut buf))
-                    .await
-                {
-                    Ok(result) => {
-                        match result {
-                            Ok((len, _addr)) => {
-                                if len == 0 {
-                                    tokio::time::sleep(Duration::from_millis(10)).await;
-                                    continue;
-                                }
-                                if let Some(msg) = Message::from_bytes(&buf[..len]) {
-                                    match msg {
-                                        Message::Chat { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::StreamFrame { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::VideoFrame { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::VideoFrameFragment { .. } => {
-                                            // Forward fragments to be reassembled in main loop
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::CallRequest { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::CallHangup { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::CallReject { .. } => {
-                                            let _ = net_tx.send(msg).await;
-                                        }
-                                        Message::Join { name } => {
-                                            let _ = peer_event_tx
-                                                .send(PeerEvent::Joined { name, addr: _addr })
-                                                .await;
-                                        }
-                                        Message::Leave { name } => {
-                                            let _ = peer_event_tx
-                                                .send(PeerEvent::Left { name, addr: _addr })
-                                                .await;
-                                        }
-                                        Message::Ping { seq } => {
-                                            // Respond with pong
-                                            let pong = Message::Pong { seq };
-                                            let _ = socket.send_to(&pong.to_bytes(), _addr).await;
-                                        }
-                                        Message::Pong { .. } => {
-                                            // Latency measurement could go here
-                                        }
-                                        Message::DiscoveryAnnounce { name, port } => {
-                                            // Discovery announce received on main port (bypasses SO_REUSEPORT)
-                                            // Forward to discovery channel as if we received it normally
-                                            let peer_addr = SocketAddr::new(_addr.ip(), port);
-                                            let peer = DiscoveredPeer {
-                                                name,
-                                                addr: peer_addr,
-                                            };
-                                            let _ = discovery_tx.send(peer).await;
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Network receive error: {}", e);
-                                // Avoid spinning on error
-                                tokio::time::sleep(Duration::from_millis(100)).await;
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        // Timeout, check running flag and continue
-                    }
-                }
-            }
-        });
+        let net_recv_task = spawn_net_receiver(
+            net_node.socket(),
+            running.clone(),
+            net_tx.clone(),
+            peer_event_tx.clone(),
+            discovery_tx.clone(),
+            watches.net.heartbeat(),
+        );
 
-        // Calculate terminal width for chat buffers and Gemini
-        let use_drcs = config.terminal.mode == "vt220" || config.terminal.mode == "vt340";
+        // Calculate terminal width for chat buffers and the AI
+        let use_drcs = has_drcs(&config.terminal.mode);
+        crate::terminal::color::set_enabled(crate::terminal::color::mode_has_color(
+            &config.terminal.mode,
+        ));
+        crate::terminal::set_scroll_regions(config.terminal.mode != "ansi");
         let use_132_cols = config.terminal.cols_132;
         let width = if use_132_cols { 132 } else { 80 };
+        let theme = Theme::from_name(&config.terminal.theme).unwrap_or_default();
+        layout::set_input_rows(config.terminal.input_rows);
+        let layout_name = config.startup_layout().to_string();
+        if let Some(startup_layout) = config.layout(&layout_name) {
+            layout::apply(startup_layout);
+        }
+        serial.set_screen_size(width, layout::screen_rows());
+        let screensaver = Screensaver::new(
+            (config.terminal.screensaver > 0)
+                .then(|| Duration::from_secs(config.terminal.screensaver * 60)),
+            ScreensaverStyle::from_name(&config.terminal.screensaver_style)
+                .unwrap_or(ScreensaverStyle::Clock),
+            std::time::Instant::now(),
+        );
+        let keymap = config.keymap();
+        let bindings = config.bindings();
+        let macros = config.macros();
+        stamp::set_format(&config.terminal.timestamp_format);
 
-        // Initialize Gemini chat if configured
-        let gemini_available = GeminiChat::is_available(&config.gemini);
-        let gemini_chat = if gemini_available {
-            match GeminiChat::new(&config.gemini, width, &config.terminal.mode) {
+        // Initialize AI chat if configured
+        let gemini_available = AiChat::is_available(&config.ai);
+        let mut ai_chat = if gemini_available {
+            match AiChat::new(&config.ai, width, &config.terminal.mode) {
                 Ok(chat) => Some(chat),
                 Err(e) => {
-                    eprintln!("Warning: Failed to initialize Gemini: {}", e);
+                    eprintln!("Warning: Failed to initialize AI: {}", e);
                     None
                 }
             }
@@ -325,51 +573,170 @@ impl App {
             Some(TunesState::new(
                 config.tunes.directory.as_ref().unwrap(),
                 width,
+                renderer.clone(),
             ))
         } else {
             None
         };
 
+        // Pick up file transfers interrupted by the last shutdown
+        let transfers = match &config.files.directory {
+            Some(dir) if !preview => {
+                status!("Loading file transfers... ");
+                match FileTransfers::open(Path::new(dir), config.network.name.clone()) {
+                    Ok(mut transfers) => {
+                        transfers.set_rate_limit(config.tunes.sync_rate as u64 * 1024);
+                        match transfers.active() {
+                            0 => println!("OK"),
+                            n => println!("OK ({} to resume)", n),
+                        }
+                        Some(transfers)
+                    }
+                    Err(e) => {
+                        println!("FAILED");
+                        eprintln!("Warning: File transfers disabled: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
         // Tab state
         let active_tab = Tab::Chat;
         let active_call: Option<String> = None;
 
         // Initialize terminal (load DRCS if needed)
-        let _ = serial.write_str(&crate::terminal::get_init_sequence(use_drcs, use_132_cols));
+        let charset = Charset::for_mode(&config.terminal.mode);
+        serial.set_charset(charset);
+        serial.set_controls(controls);
+        let _ = serial.write_str(&crate::terminal::get_init_sequence(
+            use_drcs.then_some(drcs_font.as_ref()),
+            use_132_cols,
+            charset,
+            config.mouse(),
+        ));
 
         // Initialize split-screen terminal UI with tabs
         let _ = serial.write_str(&init_split_screen_with_tabs(
+            theme,
             &config.network.name,
             active_tab,
             gemini_available,
             tunes_available,
             active_call.as_deref(),
+            Unread::default(),
+            None,
+            None,
             None,
             width,
         ));
 
         // Create chat buffers for each tab
-        let chat_buffer = ChatBuffer::new(width);
+        let mut chat_buffer = ChatBuffer::new(width);
+        chat_buffer.set_theme(theme);
         let webcam = if config.webcam.device.is_some() {
-            Some(Webcam::new(config.webcam.device.clone()))
+            let heartbeat = watches.webcam.heartbeat();
+            Some(Webcam::new(
+                config.webcam.device.clone(),
+                config.capture(),
+                heartbeat,
+            ))
         } else {
             None
         };
 
-        let ai_buffer = ChatBuffer::new(width);
+        let mut ai_buffer = ChatBuffer::new(width);
+        ai_buffer.set_theme(theme);
+
+        // Carry on with the last AI conversation
+        let ai_conversations = match &config.logging.directory {
+            Some(dir) if !preview => Some(Conversations::open(Path::new(dir))),
+            _ => None,
+        };
+        if let (Some(ai), Some(conversation)) = (
+            ai_chat.as_mut(),
+            ai_conversations.as_ref().and_then(|c| c.current()),
+        ) {
+            show_conversation(
+                &mut ai_buffer,
+                &config.network.name,
+                &conversation,
+                config.ai.markdown,
+            );
+            ai_buffer.push(format!(
+                "{}*** Earlier conversation restored (/clear for a new one) ***",
+                stamp::now()
+            ));
+            ai.restore(conversation);
+        }
+
+        let todo_list = if preview || config.network.todo.is_empty() {
+            TodoList::default()
+        } else {
+            TodoList::load(Path::new(&config.network.todo))
+        };
+        let todo = TodoState::new(todo_list, width);
 
         // Initialize session logger if configured
         let logger = SessionLogger::new(config.logging.directory.as_deref());
 
+        let chat_history = ChatHistory::new(config.network.history_minutes);
+
+        let input_history = match &config.logging.directory {
+            Some(dir) if !preview => {
+                InputHistory::load(Path::new(dir), config.terminal.input_history)
+            }
+            _ => InputHistory::new(config.terminal.input_history),
+        };
+
+        // Validated when the config was loaded
+        let alerts: HashMap<String, Alert> = config
+            .contacts
+            .iter()
+            .filter_map(|(name, alert)| Some((name.to_lowercase(), parse_alert(alert).ok()?)))
+            .collect();
+        let alert_player = if alerts.values().any(|a| matches!(a, Alert::Sound(_))) {
+            AudioPlayer::new()
+                .map_err(|e| eprintln!("Warning: Contact sounds disabled: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let ambient = (config.ai.ambient && ai_chat.is_some()).then(|| {
+            Ambient::new(
+                Duration::from_secs(config.ai.ambient_idle * 60),
+                parse_quiet_hours(&config.ai.quiet_hours).flatten(),
+                config.ai.ambient_tokens,
+                std::time::Instant::now(),
+            )
+        });
+
         Ok(Self {
             config,
             serial,
             net_node,
             webcam,
-            gemini_chat,
+            renderer,
+            drcs_font,
+            ai_chat,
+            ai_conversations,
+            ai_session: DEFAULT_AI_SESSION.to_string(),
+            ai_sessions: BTreeMap::new(),
             tunes_state,
+            theme,
+            layout_name,
+            screensaver,
+            keymap,
+            bindings,
+            macros,
+            compose: Compose::default(),
+            paste: None,
+            pending_paste: None,
             chat_buffer,
             ai_buffer,
+            windows: Windows::new(),
             logger,
             active_tab,
             active_call,
@@ -379,21 +746,63 @@ impl App {
             last_rendered_frame: None,
             line_buffer: String::new(),
             input_cursor: 0,
-            input_history: Vec::new(),
+            input_history,
             history_index: None,
             ai_processing: false,
+            ai_usage: Usage::default(),
+            capture_active: false,
             running,
+            started: std::time::Instant::now(),
+            clock: ClockMonitor::new(),
             video_frame_id: 0,
+            video_sender: VideoSender::new(),
+            video_receiver: VideoReceiver::new(),
+            motion: MotionDetector::default(),
+            discovery,
+            chat_history,
+            backfill: Backfill::new(),
+            topic: None,
+            pin: None,
+            incoming_call: None,
+            transfers,
+            tunes_sync: TunesSync::new(),
+            receipts: Receipts::new(),
+            public_addr_rx,
+            roster,
+            todo,
+            timer: None,
+            ambient,
+            alerts,
+            bell: Bell::default(),
+            last_rings: HashMap::new(),
+            alert_player,
+            separator_shown: (Tab::Chat, None),
+            search_label: None,
+            status_shown: None,
+            unread: Unread::default(),
+            unread_shown: Unread::default(),
+            collapsed: CollapsedShares::new(),
+            editable: std::collections::VecDeque::new(),
+            peer_caps: HashMap::new(),
+            whois_wanted: HashSet::new(),
             discovery_rx,
             net_rx,
             peer_event_rx,
             net_recv_task,
-            _discovery_shutdown_tx: discovery_shutdown_tx,
+            discovery_task,
+            discovery_shutdown_tx,
+            net_tx,
+            peer_event_tx,
+            discovery_tx,
+            watches,
+            preview,
             stats_last_check: std::time::Instant::now(),
             stats_bytes_sent: 0,
             stats_frames_rendered: 0,
             stats_frames_sent: 0,
             stats_frames_received: 0,
+            corrupt_frames: 0,
+            corrupt_notice_at: None,
         })
     }
 
@@ -402,19 +811,2893 @@ impl App {
         if let Some(ref mut logger) = self.logger {
             logger.log_chat(&message);
         }
+        self.capture(&message);
         self.chat_buffer.push(message);
     }
 
-    /// Push a message to the AI buffer and log it
-    pub fn push_ai(&mut self, message: String) {
+    /// Run `f` with the mesh window's scrollback in `chat_buffer`, for the
+    /// mesh chat arriving while a direct message or channel window is shown
+    pub fn in_mesh<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        if self.windows.is_mesh() {
+            return f(self);
+        }
+        self.windows.swap(0, &mut self.chat_buffer);
+        let result = f(self);
+        self.windows.swap(0, &mut self.chat_buffer);
+        result
+    }
+
+    /// Show a line a peer typed in a direct message or channel window. A
+    /// direct message opens a window for the peer; a channel we haven't
+    /// opened is ignored. Returns true if it's the window shown (the chat
+    /// area needs redrawing).
+    pub fn window_chat(&mut self, from: &str, to: &str, text: &str, width: usize) -> bool {
+        let channel = windows::is_channel(to);
+        let index = match self.windows.find(if channel { to } else { from }) {
+            Some(index) => index,
+            None if channel => return false,
+            // With every window in use, it shows in the one being looked at
+            None => self
+                .windows
+                .open(from, width, self.theme)
+                .unwrap_or(self.windows.current()),
+        };
+        let line = format!("{}{}: {}", stamp::now(), from, text);
         if let Some(ref mut logger) = self.logger {
-            logger.log_ai(&message);
+            logger.log_chat(&line);
         }
-        self.ai_buffer.push(message);
+        self.capture(&line);
+        let buffer = self.windows.buffer_mut(index, &mut self.chat_buffer);
+        buffer.push(line);
+        buffer.scroll_to_bottom();
+        self.mark_unread(Tab::Chat);
+        let mentioned = text
+            .to_lowercase()
+            .contains(&self.config.network.name.to_lowercase());
+        self.notify(from, !channel || mentioned);
+        self.windows.arrived(index)
     }
 
-    /// Check if tunes tab is available
-    pub fn tunes_available(&self) -> bool {
-        self.tunes_state.is_some()
+    /// Send a line typed in a direct message or channel window and show it
+    pub async fn send_window_chat(&mut self, text: &str) {
+        // A channel goes to everyone; a peer needs to be online
+        let (to, addr) = match self.windows.target() {
+            Target::Mesh => return,
+            Target::Channel(name) => (name.to_string(), None),
+            Target::Peer(name) => (
+                name.to_string(),
+                Some(
+                    self.net_node
+                        .peers()
+                        .iter()
+                        .find(|p| p.name == name)
+                        .map(|p| p.addr),
+                ),
+            ),
+        };
+        // A peer whose terminal can't show accented letters gets them
+        // spelled in ASCII
+        let plain = self.peer_caps.get(&to).is_some_and(|caps| !caps.accents);
+        let text = if plain {
+            Charset::Ascii.encode(text)
+        } else {
+            text.into()
+        };
+        let msg = Message::WindowChat {
+            from: self.config.network.name.clone(),
+            to: to.clone(),
+            text: text.to_string(),
+        };
+        let result = match addr {
+            None => self.net_node.broadcast(&msg).await,
+            Some(Some(addr)) => self.net_node.send_to(&msg, addr).await,
+            Some(None) => {
+                let timestamp = stamp::now();
+                self.push_chat(format!("{}*** {} isn't online ***", timestamp, to));
+                return;
+            }
+        };
+        let timestamp = stamp::now();
+        match result {
+            Ok(()) => self.push_chat(format!(
+                "{}{}: {}",
+                timestamp, self.config.network.name, text
+            )),
+            Err(e) => self.push_chat(format!("{}*** Not sent: {} ***", timestamp, e)),
+        }
+    }
+
+    /// Send a line typed in the mesh window to every peer and show it
+    pub async fn send_mesh_chat(&mut self, text: &str) {
+        let timestamp = stamp::now();
+        let prefix = format!("{}{}: ", timestamp, self.config.network.name);
+        let lamport = self.net_node.tick_lamport();
+        self.push_own_chat(format!("{}{}", prefix, text), lamport);
+        self.chat_buffer.scroll_to_bottom();
+        let _ = self.serial.write_str(&self.chat_buffer.render_update());
+
+        // Broadcast to peers
+        self.chat_history.record(&self.config.network.name, text);
+        match self.net_node.send_chat(text, lamport).await {
+            Ok(id) => {
+                let name = self.config.network.name.clone();
+                self.note_editable(&name, id, lamport, prefix, text);
+            }
+            Err(e) => eprintln!("Failed to send message: {}", e),
+        }
+    }
+
+    /// Start gathering a paste, taking over what's in the input line
+    pub fn start_paste(&mut self, bracketed: bool, width: usize) {
+        let now = std::time::Instant::now();
+        self.paste = Some(Paste::new(&self.line_buffer, bracketed, now));
+        self.line_buffer.clear();
+        self.input_cursor = 0;
+        let _ = self.serial.write_str(&redraw_input(
+            self.theme,
+            &self.config.network.name,
+            "",
+            0,
+            width,
+        ));
+    }
+
+    /// The paste is over: one line goes back in the input line to be edited
+    /// and sent as usual, more are held for `y` or `n`
+    pub fn finish_paste(&mut self, width: usize) {
+        let Some(paste) = self.paste.take() else {
+            return;
+        };
+        let mut lines = paste.finish();
+        if lines.len() <= 1 {
+            let max_len = max_input_length(&self.config.network.name, width);
+            self.line_buffer = lines
+                .pop()
+                .unwrap_or_default()
+                .chars()
+                .take(max_len)
+                .collect();
+            self.input_cursor = self.line_buffer.chars().count();
+            let _ = self.serial.write_str(&redraw_input(
+                self.theme,
+                &self.config.network.name,
+                &self.line_buffer,
+                self.input_cursor,
+                width,
+            ));
+            return;
+        }
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** Paste of {} lines: y sends it as one message, n drops it ***",
+            timestamp,
+            lines.len()
+        ));
+        self.pending_paste = Some(lines);
+        if self.active_tab == Tab::Chat {
+            self.chat_buffer.scroll_to_bottom();
+            let _ = self.serial.write_str(&self.chat_buffer.render_update());
+        }
+    }
+
+    /// `y` or `n` to a paste being held
+    pub async fn answer_paste(&mut self, send: bool) {
+        let Some(lines) = self.pending_paste.take() else {
+            return;
+        };
+        if !send {
+            let timestamp = stamp::now();
+            self.push_chat(format!("{}*** Paste dropped ***", timestamp));
+        } else if self.windows.is_mesh() {
+            self.send_mesh_chat(&lines.join("\n")).await;
+        } else {
+            self.send_window_chat(&lines.join("\n")).await;
+        }
+        self.chat_buffer.scroll_to_bottom();
+        let _ = self.serial.write_str(&self.chat_buffer.render_update());
+    }
+
+    /// Show chat window `index` (0-based) and redraw the chat. Returns false
+    /// if there's no such window.
+    pub fn switch_window(&mut self, index: usize, width: usize) -> bool {
+        if !self.windows.switch(index, &mut self.chat_buffer) {
+            return false;
+        }
+        if self.active_tab == Tab::Chat {
+            let _ = self.serial.write_str(&self.chat_buffer.render());
+        }
+        self.tick_status(width);
+        true
+    }
+
+    /// Push a chat message at its Lamport time and log it
+    pub fn push_chat_ordered(&mut self, message: String, lamport: u64) {
+        if let Some(ref mut logger) = self.logger {
+            logger.log_chat(&message);
+        }
+        self.capture(&message);
+        self.chat_buffer.push_ordered(message, lamport);
+    }
+
+    /// Push a peer's message: `header`, then the lines of `body` (collapsed
+    /// to a preview and an `/expand` hint if there are too many)
+    pub fn push_share(&mut self, from: &str, header: String, body: &str, lamport: u64) {
+        self.push_chat_ordered(header, lamport);
+        let lines = body.lines().map(str::to_string).collect();
+        for line in self.collapsed.add(from, lines) {
+            self.push_chat_ordered(line, lamport);
+        }
+    }
+
+    /// Push one of our own chat lines, marked until every peer online has
+    /// confirmed it (with `receipts` on)
+    pub fn push_own_chat(&mut self, message: String, lamport: u64) {
+        if !self.config.network.receipts || self.net_node.peer_count() == 0 {
+            self.push_chat_ordered(message, lamport);
+            return;
+        }
+        if let Some(ref mut logger) = self.logger {
+            logger.log_chat(&message);
+        }
+        self.capture(&message);
+        self.chat_buffer.push_pending(message, lamport);
+        let peers = self.net_node.peers().iter().map(|p| p.name.clone());
+        self.receipts.expect(lamport, peers);
+    }
+
+    /// Note a chat message shown as `prefix` and `text` under `key`, so its
+    /// sender can correct it later
+    pub fn note_editable(&mut self, from: &str, id: u64, key: u64, prefix: String, text: &str) {
+        if self.editable.len() >= MAX_EDITABLE {
+            self.editable.pop_front();
+        }
+        self.editable.push_back(Editable {
+            from: from.to_string(),
+            id,
+            key,
+            line: format!("{}{}", prefix, text),
+            prefix,
+            text: text.to_string(),
+        });
+    }
+
+    /// Our latest message that `/edit` would change: its id and text
+    pub fn last_editable(&self) -> Option<(u64, &str)> {
+        self.editable
+            .iter()
+            .rev()
+            .find(|e| e.from == self.config.network.name)
+            .map(|e| (e.id, e.text.as_str()))
+    }
+
+    /// Show a correction in place of the message: its new text marked
+    /// `[edited]`, or `[deleted]` if the text is empty. Returns false if the
+    /// message isn't known (too old, or from before we joined).
+    pub fn apply_edit(&mut self, from: &str, id: u64, text: &str) -> bool {
+        let Some(i) = self
+            .editable
+            .iter()
+            .rposition(|e| e.id == id && e.from == from)
+        else {
+            return false;
+        };
+        let editable = &self.editable[i];
+        let line = if text.is_empty() {
+            format!("{}[deleted]", editable.prefix)
+        } else {
+            format!("{}{} [edited]", editable.prefix, text)
+        };
+        if !self
+            .chat_buffer
+            .replace(editable.key, &editable.line, &line)
+        {
+            return false;
+        }
+        if let Some(ref mut logger) = self.logger {
+            logger.log_chat(&line);
+        }
+        if text.is_empty() {
+            self.editable.remove(i);
+        } else {
+            let editable = &mut self.editable[i];
+            editable.text = text.to_string();
+            editable.line = line;
+        }
+        true
+    }
+
+    /// A peer confirmed one of our lines. Returns true if the chat changed.
+    pub fn chat_acked(&mut self, lamport: u64, from: &str) -> bool {
+        self.receipts.confirm(lamport, from) && self.chat_buffer.mark_delivered(lamport)
+    }
+
+    /// Stop waiting for receipts from a peer that left or timed out.
+    /// Returns true if the chat changed.
+    pub fn receipts_peer_gone(&mut self, name: &str) -> bool {
+        let mut changed = false;
+        for lamport in self.receipts.peer_gone(name) {
+            changed |= self.chat_buffer.mark_delivered(lamport);
+        }
+        changed
+    }
+
+    /// Drop a video frame that failed its checksum, saying so in chat at most
+    /// every [`CORRUPT_NOTICE_INTERVAL`]. Returns true if the chat changed.
+    pub fn corrupt_frame(&mut self, from: &str) -> bool {
+        self.corrupt_frames += 1;
+        if self
+            .corrupt_notice_at
+            .is_some_and(|t| t.elapsed() < CORRUPT_NOTICE_INTERVAL)
+        {
+            return false;
+        }
+        self.corrupt_notice_at = Some(std::time::Instant::now());
+        let timestamp = stamp::now();
+        let frames = match self.corrupt_frames {
+            1 => "1 frame".to_string(),
+            n => format!("{} frames", n),
+        };
+        self.push_chat(format!(
+            "{}*** Video from {} is arriving corrupted ({} dropped) ***",
+            timestamp, from, frames
+        ));
+        self.corrupt_frames = 0;
+        true
+    }
+
+    /// Follow a change of our public address spotted by the STUN refresh,
+    /// re-announcing to peers. Returns true if the chat changed.
+    pub async fn check_public_addr(&mut self) -> bool {
+        let Some(addr) = self
+            .public_addr_rx
+            .as_mut()
+            .and_then(|rx| rx.try_recv().ok())
+        else {
+            return false;
+        };
+        let Some(old) = self.net_node.refresh_public_addr(addr) else {
+            return false;
+        };
+        self.net_node.reannounce().await;
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** Our public address changed from {} to {} - telling peers ***",
+            timestamp,
+            old.ip(),
+            addr.ip()
+        ));
+        true
+    }
+
+    /// Push a message to the AI buffer and log it
+    pub fn push_ai(&mut self, message: String) {
+        if let Some(ref mut logger) = self.logger {
+            logger.log_ai(&message);
+        }
+        self.capture(&message);
+        self.ai_buffer.push(message);
+    }
+
+    /// Save the AI conversation after a reply
+    pub fn save_ai_conversation(&self) {
+        if let (Some(ai), Some(conversations)) = (&self.ai_chat, &self.ai_conversations) {
+            conversations.save(&ai.conversation());
+        }
+    }
+
+    /// Count in the tokens a reply used, noting them in the AI log
+    pub fn record_ai_usage(&mut self, usage: Option<Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        self.ai_usage.add(usage);
+        let cost = self
+            .config
+            .ai
+            .cost(usage)
+            .map(|dollars| format!(", ${:.4}", dollars))
+            .unwrap_or_default();
+        if let Some(ref mut logger) = self.logger {
+            logger.log_ai(&format!(
+                "{}*** Tokens: {} in, {} out{} ***",
+                stamp::now(),
+                usage.input,
+                usage.output,
+                cost
+            ));
+        }
+    }
+
+    /// Begin a new saved AI conversation, keeping the last one to come back to
+    pub fn new_ai_conversation(&mut self) {
+        if let Some(conversations) = self.ai_conversations.as_mut() {
+            conversations.start_new();
+        }
+    }
+
+    /// Put the AI conversation on the AI tab aside and start another called
+    /// `name`, with its own history and window
+    pub fn new_ai_session(&mut self, name: &str, width: usize) -> Result<(), String> {
+        if self.ai_chat.is_none() {
+            return Err("The AI isn't running".to_string());
+        }
+        if name.is_empty()
+            || name.chars().count() > MAX_AI_SESSION_NAME
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "A conversation name is up to {} letters, digits, - or _",
+                MAX_AI_SESSION_NAME
+            ));
+        }
+        if name == self.ai_session || self.ai_sessions.contains_key(name) {
+            return Err(format!("There's already a conversation called {}", name));
+        }
+        let mut chat = AiChat::new(&self.config.ai, width, &self.config.terminal.mode)
+            .map_err(|e| e.to_string())?;
+        // Keep whatever /set has changed
+        if let Some(ref current) = self.ai_chat {
+            *chat.params_mut() = *current.params();
+        }
+        let mut buffer = ChatBuffer::new(width);
+        buffer.set_theme(self.theme);
+        self.set_aside_ai_session(chat, buffer);
+        self.ai_session = name.to_string();
+        self.new_ai_conversation();
+        Ok(())
+    }
+
+    /// Go back to the AI conversation called `name`
+    pub fn switch_ai_session(&mut self, name: &str) -> Result<(), String> {
+        if self.ai_chat.is_none() {
+            return Err("The AI isn't running".to_string());
+        }
+        if name == self.ai_session {
+            return Err(format!("Already in {}", name));
+        }
+        let Some(session) = self.ai_sessions.remove(name) else {
+            return Err(format!(
+                "No conversation called {} (/chat new {} to start one)",
+                name, name
+            ));
+        };
+        let mut buffer = session.buffer;
+        buffer.set_theme(self.theme);
+        buffer.fit_rows();
+        self.set_aside_ai_session(session.chat, buffer);
+        if let (Some(conversations), Some(id)) =
+            (self.ai_conversations.as_mut(), session.conversation)
+        {
+            conversations.resume(id);
+        }
+        self.ai_session = name.to_string();
+        Ok(())
+    }
+
+    /// Swap `chat` and `buffer` in for the current AI conversation, which
+    /// is kept under its name
+    fn set_aside_ai_session(&mut self, chat: AiChat, buffer: ChatBuffer) {
+        let Some(current) = self.ai_chat.replace(chat) else {
+            return;
+        };
+        let session = AiSession {
+            chat: current,
+            buffer: std::mem::replace(&mut self.ai_buffer, buffer),
+            conversation: self
+                .ai_conversations
+                .as_ref()
+                .map(|c| c.current_id().to_string()),
+        };
+        self.ai_sessions.insert(self.ai_session.clone(), session);
+    }
+
+    /// Carry on with saved AI conversation `n` (from 1, as `/history list`
+    /// numbers them), replacing the AI tab with it
+    pub fn load_ai_conversation(&mut self, n: usize) -> Result<String, String> {
+        let Some(conversations) = self.ai_conversations.as_mut() else {
+            return Err("Conversations are only saved with a [logging] directory".to_string());
+        };
+        let Some(ai) = self.ai_chat.as_mut() else {
+            return Err("The AI isn't running".to_string());
+        };
+        let (summary, conversation) = conversations.load(n)?;
+        self.ai_buffer.clear();
+        show_conversation(
+            &mut self.ai_buffer,
+            &self.config.network.name,
+            &conversation,
+            self.config.ai.markdown,
+        );
+        ai.restore(conversation);
+        self.ai_buffer.scroll_to_bottom();
+        Ok(summary.started)
+    }
+
+    /// Copy a line to the terminal's printer port if capture is active
+    pub fn capture(&mut self, message: &str) {
+        if self.capture_active {
+            let _ = self.serial.write_through(&transparent_print(&[message]));
+        }
+    }
+
+    /// Check if tunes tab is available
+    pub fn tunes_available(&self) -> bool {
+        self.tunes_state.is_some()
+    }
+
+    /// Snapshot the session for `/dump-state`
+    pub fn dump_state(&self) -> StateDump {
+        let buffer_state = |buffer: &ChatBuffer| BufferState {
+            lines: buffer
+                .recent_lines(usize::MAX)
+                .iter()
+                .map(|l| l.to_string())
+                .collect(),
+            scroll_offset: buffer.scroll_offset(),
+        };
+
+        StateDump {
+            version: STATE_DUMP_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: Local::now().to_rfc3339(),
+            active_tab: self.active_tab,
+            renderer: self.renderer.name().to_string(),
+            peers: self
+                .net_node
+                .peers()
+                .iter()
+                .map(|p| PeerState {
+                    name: p.name.clone(),
+                    addr: p.addr.to_string(),
+                    last_seen_secs: p.last_seen.elapsed().as_secs(),
+                })
+                .collect(),
+            chat: buffer_state(&self.chat_buffer),
+            ai: buffer_state(&self.ai_buffer),
+            call: CallState {
+                active_call: self.active_call.clone(),
+                connected: self.call_connected,
+                last_packet_secs: self.call_last_packet.map(|t| t.elapsed().as_secs()),
+            },
+            input: InputState {
+                line: self.line_buffer.clone(),
+                cursor: self.input_cursor,
+                history: self.input_history.entries(self.active_tab).to_vec(),
+            },
+            config: redacted_config(&self.config),
+        }
+    }
+
+    /// Load the tab buffers, active tab and input line from a dump into a preview session.
+    /// Peers and call state are not restored - there is nobody to talk to.
+    pub fn load_state(&mut self, dump: StateDump) {
+        self.chat_buffer
+            .restore(dump.chat.lines, dump.chat.scroll_offset);
+        self.ai_buffer.restore(dump.ai.lines, dump.ai.scroll_offset);
+        self.active_tab = match dump.active_tab {
+            Tab::Tunes if !self.tunes_available() => Tab::Chat,
+            Tab::Call => Tab::Chat,
+            tab => tab,
+        };
+        self.input_cursor = dump.input.cursor.min(dump.input.line.chars().count());
+        self.line_buffer = dump.input.line;
+        self.input_history
+            .restore(self.active_tab, dump.input.history);
+    }
+
+    /// React to a suspend/resume or a wall-clock step
+    pub async fn handle_clock_event(&mut self, event: ClockEvent, width: usize) {
+        let timestamp = stamp::now();
+        match event {
+            ClockEvent::Resumed(gap) => {
+                self.net_node.resume().await;
+                if let Some(discovery) = &self.discovery {
+                    discovery.request_announce();
+                }
+                // Give the call a fresh timeout window too
+                if self.call_last_packet.is_some() {
+                    self.call_last_packet = Some(std::time::Instant::now());
+                }
+                self.push_chat(format!(
+                    "{}*** Resumed after {}s - reconnecting to peers ***",
+                    timestamp,
+                    gap.as_secs()
+                ));
+                self.resync_terminal(width);
+            }
+            ClockEvent::WallClockStep(secs) => {
+                let _ = self.net_node.broadcast_clock().await;
+                self.push_chat(format!(
+                    "{}*** System clock changed by {:+}s ***",
+                    timestamp, secs
+                ));
+                if self.active_tab == Tab::Chat {
+                    let _ = self.serial.write_str(&self.chat_buffer.render_update());
+                }
+            }
+        }
+    }
+
+    /// The soft font to load, if the terminal takes one
+    pub fn drcs(&self) -> Option<&DrcsFont> {
+        has_drcs(&self.config.terminal.mode).then_some(self.drcs_font.as_ref())
+    }
+
+    /// Reopen the serial port and repaint everything, in case the port or the
+    /// terminal went away while we weren't looking. A port that can't be reopened
+    /// is left disconnected for the main loop's reconnect logic.
+    pub fn resync_terminal(&mut self, width: usize) {
+        if self.serial.reconnect().is_err() {
+            return;
+        }
+        let _ = self.serial.write_str(&crate::terminal::get_init_sequence(
+            self.drcs(),
+            self.config.terminal.cols_132,
+            self.serial.charset(),
+            self.config.mouse(),
+        ));
+        self.last_rendered_frame = None;
+        self.redraw_screen(width);
+    }
+
+    /// Switch to another tab. The chat and AI buffers keep their scroll
+    /// positions, and mark the messages that arrive while they're hidden.
+    pub fn switch_tab(&mut self, tab: Tab) {
+        if tab == self.active_tab {
+            return;
+        }
+        match self.active_tab {
+            Tab::Chat => self.chat_buffer.leave(),
+            Tab::Gemini => self.ai_buffer.leave(),
+            Tab::Call | Tab::Tunes | Tab::Stats | Tab::Todo => {}
+        }
+        match tab {
+            Tab::Chat => self.chat_buffer.enter(),
+            Tab::Gemini => self.ai_buffer.enter(),
+            Tab::Tunes => {
+                if let Some(tunes) = &self.tunes_state {
+                    tunes.redraw_cover();
+                }
+            }
+            Tab::Call | Tab::Stats | Tab::Todo => {}
+        }
+        // Each tab has its own input history
+        self.history_index = None;
+        self.active_tab = tab;
+    }
+
+    /// Drop state that is no longer needed, so a node running for months stays flat
+    pub fn trim_caches(&mut self) {
+        // Keep the last received frame only while it belongs to the current call
+        if self
+            .current_video_frame
+            .as_ref()
+            .is_some_and(|(from, _)| self.active_call.as_ref() != Some(from))
+        {
+            self.current_video_frame = None;
+        }
+        self.net_node.expire_caches();
+        self.chat_history.expire();
+        if let Some(roster) = &mut self.roster {
+            roster.update(self.net_node.peers());
+        }
+    }
+
+    /// The room topic text, if one is set
+    pub fn topic_text(&self) -> Option<&str> {
+        self.topic
+            .as_ref()
+            .map(|t| t.text.as_str())
+            .filter(|t| !t.is_empty())
+    }
+
+    /// Adopt a topic if it's newer than ours. Returns true if it changed, after
+    /// announcing it in the chat and redrawing the separator.
+    pub fn set_topic(&mut self, topic: RoomTopic, width: usize) -> bool {
+        if self.topic.as_ref().is_some_and(|t| !topic.supersedes(t)) {
+            return false;
+        }
+        let timestamp = stamp::now();
+        let msg = if topic.text.is_empty() {
+            format!("{}*** {} cleared the topic ***", timestamp, topic.set_by)
+        } else {
+            format!(
+                "{}*** {} set the topic: {} ***",
+                timestamp, topic.set_by, topic.text
+            )
+        };
+        self.topic = Some(topic);
+        self.push_chat(msg);
+        if self.active_tab == Tab::Chat {
+            let _ = self.serial.write_str(&redraw_separator(
+                self.theme,
+                self.topic_text(),
+                self.separator_shown.1.as_deref(),
+                width,
+            ));
+        }
+        true
+    }
+
+    /// Announce a chat message from `from`: their `[contacts]` notification
+    /// if they have one, else a single ring if it mentions us
+    pub fn notify(&mut self, from: &str, mentioned: bool) {
+        match self.alerts.get(&from.to_lowercase()) {
+            Some(Alert::Bells(groups)) => self.bell.ring(groups, std::time::Instant::now()),
+            Some(Alert::Sound(path)) => match &self.alert_player {
+                Some(player) => {
+                    if let Err(e) = player.play(path) {
+                        eprintln!("Failed to play {}: {}", path.display(), e);
+                    }
+                }
+                None => self.bell.ring(&[1], std::time::Instant::now()),
+            },
+            Some(Alert::Silent) => {}
+            None if mentioned => self.bell.ring(&[1], std::time::Instant::now()),
+            None => {}
+        }
+    }
+
+    /// A peer asked to ring our bell. Rings it if our `ring` setting allows
+    /// the peer and it hasn't rung in the last [`RING_INTERVAL`]; returns
+    /// whether it did.
+    pub fn rung(&mut self, from: &str) -> bool {
+        let allowed = match self.config.network.ring.as_str() {
+            "anyone" => true,
+            "contacts" => self.alerts.contains_key(&from.to_lowercase()),
+            _ => false,
+        };
+        let now = std::time::Instant::now();
+        if !allowed
+            || self
+                .last_rings
+                .get(from)
+                .is_some_and(|&at| now.duration_since(at) < RING_INTERVAL)
+        {
+            return false;
+        }
+        self.last_rings.insert(from.to_string(), now);
+        self.bell.ring(RING_PATTERN, now);
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** {} is ringing your bell ***",
+            timestamp, from
+        ));
+        true
+    }
+
+    /// Send the next ring of a bell pattern if it's due
+    pub fn tick_bell(&mut self) {
+        if self.bell.due(std::time::Instant::now()) {
+            let _ = self.serial.write_through("\x07");
+        }
+    }
+
+    /// Blank the screen after a spell without keys, or move the screensaver's
+    /// clock along. Not during a call, which is watched rather than typed in.
+    pub fn tick_screensaver(&mut self, width: usize) {
+        if !self.screensaver.is_active() && (self.active_call.is_some() || self.ai_processing) {
+            self.screensaver.input(std::time::Instant::now());
+            return;
+        }
+        let now = std::time::Instant::now();
+        if let Some(output) = self.screensaver.tick(now, width, layout::screen_rows()) {
+            self.serial.set_held(true);
+            let _ = self.serial.write_through(&output);
+        }
+    }
+
+    /// Keys arrived from the terminal. Returns true if they woke it from the
+    /// screensaver, in which case the full UI has been redrawn and the keys
+    /// should be ignored.
+    pub fn wake_screensaver(&mut self, width: usize) -> bool {
+        if !self.screensaver.input(std::time::Instant::now()) {
+            return false;
+        }
+        self.serial.set_held(false);
+        // The call's picture is drawn afresh, not as changes to the last one
+        self.last_rendered_frame = None;
+        self.redraw_screen(width);
+        true
+    }
+
+    /// Someone typed or chatted, so the quiet spell for ambient quips starts over
+    pub fn note_activity(&mut self) {
+        if let Some(ambient) = &mut self.ambient {
+            ambient.activity(std::time::Instant::now());
+        }
+    }
+
+    /// After a quiet spell, ask the AI for a quip and show it in Chat. It's
+    /// ours alone: never sent to peers.
+    pub async fn ambient_tick(&mut self, width: usize) {
+        let Some(ambient) = &mut self.ambient else {
+            return;
+        };
+        if self.ai_processing || !ambient.due(std::time::Instant::now(), Local::now().naive_local())
+        {
+            return;
+        }
+        let Some(ai) = &self.ai_chat else {
+            return;
+        };
+        // Room for the timestamp and the *** either side
+        let prompt = ambient.prompt(&self.config.terminal.mode, width.saturating_sub(24));
+        let reply =
+            tokio::time::timeout(AMBIENT_TIMEOUT, ai.one_off(&prompt, MAX_QUIP_TOKENS)).await;
+        self.clock.reset();
+        let text = match reply {
+            Ok(Ok(reply)) => {
+                let tokens = reply.usage.map(|u| u.total());
+                ambient.spend(tokens.unwrap_or(MAX_QUIP_TOKENS));
+                self.record_ai_usage(reply.usage);
+                reply.text
+            }
+            Ok(Err(e)) => {
+                eprintln!("Ambient quip failed: {}", e);
+                return;
+            }
+            Err(_) => {
+                eprintln!("Ambient quip timed out");
+                return;
+            }
+        };
+
+        let timestamp = stamp::now();
+        match clean_quip(&text).as_slice() {
+            [] => return,
+            [line] => self.push_chat(format!("{}*** {} ***", timestamp, line)),
+            lines => {
+                self.push_chat(format!("{}*** While you were away: ***", timestamp));
+                for line in lines {
+                    self.push_chat(line.clone());
+                }
+            }
+        }
+        self.mark_unread(Tab::Chat);
+        if self.active_tab == Tab::Chat {
+            let _ = self.serial.write_str(&self.chat_buffer.render_update());
+        }
+    }
+
+    /// Whether a subsystem is running
+    pub fn is_enabled(&self, subsystem: Subsystem) -> bool {
+        match subsystem {
+            Subsystem::Webcam => self.webcam.is_some(),
+            Subsystem::Ai => self.ai_chat.is_some(),
+            Subsystem::Tunes => self.tunes_state.is_some(),
+            Subsystem::Discovery => self.discovery.is_some(),
+        }
+    }
+
+    /// Start or stop a subsystem, as set up in the config, and redraw the tab
+    /// bar for the tabs that come and go with it. Returns what to tell the user.
+    pub async fn set_enabled(&mut self, subsystem: Subsystem, on: bool, width: usize) -> String {
+        let label = subsystem.label();
+        if self.is_enabled(subsystem) == on {
+            return format!("{} is already {}", label, if on { "on" } else { "off" });
+        }
+        match (subsystem, on) {
+            (Subsystem::Webcam, false) => {
+                // Dropping the handle ends the thread, which closes the device
+                if let Some(cam) = self.webcam.take() {
+                    cam.stop().await;
+                }
+            }
+            (Subsystem::Webcam, true) => {
+                let Some(device) = self.config.webcam.device.clone() else {
+                    return "No webcam device is set under [webcam]".to_string();
+                };
+                let cam = Webcam::new(
+                    Some(device),
+                    self.config.capture(),
+                    self.watches.webcam.renew(),
+                );
+                if self.active_tab == Tab::Call || self.active_call.is_some() {
+                    cam.start().await;
+                }
+                self.webcam = Some(cam);
+            }
+            (Subsystem::Ai, false) => {
+                self.ai_chat = None;
+                self.unread.clear(Tab::Gemini);
+            }
+            (Subsystem::Ai, true) => {
+                if !AiChat::is_available(&self.config.ai) {
+                    return "No API key is set under [ai]".to_string();
+                }
+                match AiChat::new(&self.config.ai, width, &self.config.terminal.mode) {
+                    Ok(mut chat) => {
+                        if let Some(conversation) =
+                            self.ai_conversations.as_ref().and_then(|c| c.current())
+                        {
+                            chat.restore(conversation);
+                        }
+                        self.ai_chat = Some(chat);
+                    }
+                    Err(e) => return format!("The AI didn't start: {}", e),
+                }
+            }
+            (Subsystem::Tunes, false) => {
+                if let Some(tunes) = self.tunes_state.take() {
+                    tunes.stop();
+                }
+                self.unread.clear(Tab::Tunes);
+            }
+            (Subsystem::Tunes, true) => {
+                let directory = self.config.tunes.directory.as_deref();
+                let Some(directory) = directory.filter(|d| TunesState::is_available(Some(d)))
+                else {
+                    return "No directory of tunes is set under [tunes]".to_string();
+                };
+                self.tunes_state = Some(TunesState::new(directory, width, self.renderer.clone()));
+            }
+            (Subsystem::Discovery, false) => {
+                self.discovery_shutdown_tx.send_replace(true);
+                if let Some(task) = self.discovery_task.take() {
+                    task.abort();
+                }
+                self.discovery = None;
+            }
+            (Subsystem::Discovery, true) => {
+                if self.preview {
+                    return "A preview session stays off the LAN".to_string();
+                }
+                self.discovery_shutdown_tx.send_replace(false);
+                let (discovery, task) = start_discovery(
+                    &self.config,
+                    self.discovery_tx.clone(),
+                    self.discovery_shutdown_tx.subscribe(),
+                    self.watches.discovery.renew(),
+                )
+                .await;
+                self.discovery = Some(discovery);
+                self.discovery_task = Some(task);
+            }
+        }
+        let _ = self.serial.write_str(&redraw_tab_bar(
+            self.theme,
+            self.active_tab,
+            self.ai_chat.is_some(),
+            self.tunes_available(),
+            self.active_call.as_deref(),
+            self.unread,
+            width,
+        ));
+        // Peers' badges for us change with it
+        let _ = self
+            .net_node
+            .broadcast(&Message::WhoisReply(self.whois_info()))
+            .await;
+        format!("{} is {}", label, if on { "on" } else { "off" })
+    }
+
+    /// Switch to another camera (a device index or path, as for `device`
+    /// under `[webcam]`), reopening the webcam if it's on. Returns what to
+    /// tell the user.
+    pub async fn use_camera(&mut self, device: &str) -> String {
+        if !testcard::is_test_device(device)
+            && let Err(e) = webcam::parse_device_index(device)
+        {
+            return e.to_string();
+        }
+        self.config.webcam.device = Some(device.to_string());
+        let Some(old) = self.webcam.take() else {
+            return format!(
+                "Using camera {} once the webcam is on (/enable webcam)",
+                device
+            );
+        };
+        // Dropping the old handle ends its thread and frees the device
+        old.stop().await;
+        drop(old);
+        let cam = Webcam::new(
+            Some(device.to_string()),
+            self.config.capture(),
+            self.watches.webcam.renew(),
+        );
+        if self.active_tab == Tab::Call || self.active_call.is_some() {
+            cam.start().await;
+        }
+        self.webcam = Some(cam);
+        format!("Using camera {}", device)
+    }
+
+    /// Restart any background thread or task that has died or hung, and say so
+    pub async fn supervise(&mut self) {
+        if !self.running.load(Ordering::SeqCst) {
+            // Shutting down: the tasks are meant to be finishing
+            return;
+        }
+        let now = std::time::Instant::now();
+        let mut restarted = Vec::new();
+
+        // A task waiting for the main loop to empty its channel isn't hung
+        if self.net_tx.capacity() == 0
+            || self.peer_event_tx.capacity() == 0
+            || self.discovery_tx.capacity() == 0
+        {
+            self.watches.net.excuse();
+            self.watches.discovery.excuse();
+        }
+
+        let finished = self.net_recv_task.is_finished();
+        if let Some(failure) = self.watches.net.check(finished, now) {
+            self.net_recv_task.abort();
+            self.net_recv_task = spawn_net_receiver(
+                self.net_node.socket(),
+                self.running.clone(),
+                self.net_tx.clone(),
+                self.peer_event_tx.clone(),
+                self.discovery_tx.clone(),
+                self.watches.net.renew(),
+            );
+            restarted.push((self.watches.net.name, failure));
+        }
+
+        if let (Some(discovery), Some(task)) = (&self.discovery, &mut self.discovery_task)
+            && let Some(failure) = self.watches.discovery.check(task.is_finished(), now)
+        {
+            task.abort();
+            *task = tokio::spawn(run_discovery(
+                Arc::clone(discovery),
+                self.discovery_tx.clone(),
+                self.discovery_shutdown_tx.subscribe(),
+                self.watches.discovery.renew(),
+            ));
+            restarted.push((self.watches.discovery.name, failure));
+        }
+
+        if let Some(cam) = &mut self.webcam
+            && let Some(failure) = self.watches.webcam.check(cam.is_finished(), now)
+        {
+            cam.restart(self.watches.webcam.renew());
+            // Pick the stream up where the old thread left it
+            if self.active_tab == Tab::Call || self.active_call.is_some() {
+                cam.start().await;
+            }
+            restarted.push((self.watches.webcam.name, failure));
+        }
+
+        for (player, watch) in [
+            (
+                self.tunes_state.as_ref().and_then(|t| t.player()),
+                &mut self.watches.tunes,
+            ),
+            (self.alert_player.as_ref(), &mut self.watches.alerts),
+        ] {
+            if let Some(player) = player
+                && let Some(failure) = watch.check(player.monitor_died(), now)
+            {
+                player.restart_monitor();
+                restarted.push((watch.name, failure));
+            }
+        }
+
+        if restarted.is_empty() {
+            return;
+        }
+        let timestamp = stamp::now();
+        for (name, failure) in restarted {
+            eprintln!("Watchdog: the {} {}, restarting it", name, failure);
+            self.push_chat(format!(
+                "{}*** The {} {}, so it was restarted ***",
+                timestamp, name, failure
+            ));
+        }
+        self.mark_unread(Tab::Chat);
+        if self.active_tab == Tab::Chat {
+            let _ = self.serial.write_str(&self.chat_buffer.render_update());
+        }
+    }
+
+    /// Count down the timer: ring the bell when it's done or a pomodoro phase
+    /// ends, and redraw the separator row when the time shown changes
+    pub fn tick_timer(&mut self, width: usize) {
+        let now = std::time::Instant::now();
+        if let Some(timer) = &mut self.timer
+            && let Some(event) = timer.tick(now)
+        {
+            let timestamp = stamp::now();
+            let name = if timer.label.is_empty() {
+                "Timer".to_string()
+            } else {
+                timer.label.clone()
+            };
+            let msg = match event {
+                TimerEvent::Done => {
+                    self.timer = None;
+                    format!("{}*** {} is done ***", timestamp, name)
+                }
+                TimerEvent::BreakStarted => format!(
+                    "{}*** Pomodoro: take a {} min break ***",
+                    timestamp,
+                    POMODORO_BREAK.as_secs() / 60
+                ),
+                TimerEvent::WorkStarted => format!(
+                    "{}*** Pomodoro: back to work for {} min ***",
+                    timestamp,
+                    POMODORO_WORK.as_secs() / 60
+                ),
+            };
+            let _ = self.serial.write_str("\x07");
+            self.push_chat(msg);
+            if self.active_tab == Tab::Chat {
+                let _ = self.serial.write_str(&self.chat_buffer.render_update());
+            }
+        }
+
+        self.refresh_separator(width);
+    }
+
+    /// Redraw the separator row if what it should show on the right has
+    /// changed: the search status while searching the chat, else the timer
+    pub fn refresh_separator(&mut self, width: usize) {
+        if self.chat_buffer.search_query().is_none() {
+            // The match scrolled out of the buffer
+            self.search_label = None;
+        }
+        let text = match &self.search_label {
+            Some(label) if self.active_tab == Tab::Chat => Some(label.clone()),
+            _ => self
+                .timer
+                .as_ref()
+                .map(|timer| timer.display(std::time::Instant::now())),
+        };
+        let shown = (self.active_tab, text);
+        if shown != self.separator_shown {
+            self.separator_shown = shown;
+            if self.active_tab.has_input() {
+                let topic = self.topic_text().filter(|_| self.active_tab == Tab::Chat);
+                let _ = self.serial.write_str(&redraw_separator(
+                    self.theme,
+                    topic,
+                    self.separator_shown.1.as_deref(),
+                    width,
+                ));
+            }
+        }
+    }
+
+    /// The status bar: the time, peers online, the call, unread messages, and
+    /// on the AI tab what the AI has used
+    pub fn status_line(&self) -> String {
+        let mut parts = vec![stamp::clock()];
+        parts.push(match self.net_node.peer_count() {
+            1 => "1 peer".to_string(),
+            n => format!("{} peers", n),
+        });
+        if let Some(peer) = &self.active_call {
+            let state = if self.call_connected {
+                "Call"
+            } else {
+                "Calling"
+            };
+            parts.push(format!("{}: {}", state, peer));
+        } else if let Some((peer, _)) = &self.incoming_call {
+            parts.push(format!("Ringing: {}", peer));
+        }
+        let unread: Vec<String> = [(Tab::Chat, "Chat"), (Tab::Gemini, "AI")]
+            .into_iter()
+            .filter(|&(tab, _)| self.unread.count(tab) > 0)
+            .map(|(tab, name)| format!("{} {}", name, self.unread.count(tab)))
+            .collect();
+        if !unread.is_empty() {
+            parts.push(format!("Unread: {}", unread.join(", ")));
+        }
+        if self.active_tab == Tab::Gemini && self.ai_usage.total() > 0 {
+            let mut usage = format!("{} tokens", self.ai_usage.total());
+            if let Some(dollars) = self.config.ai.cost(self.ai_usage) {
+                usage.push_str(&format!(" (${:.2})", dollars));
+            }
+            parts.push(usage);
+        }
+        if let Some(windows) = self.windows.status() {
+            parts.push(windows);
+        }
+        parts.join(" | ")
+    }
+
+    /// Note something new on `tab`, unless it's showing
+    pub fn mark_unread(&mut self, tab: Tab) {
+        if self.active_tab != tab {
+            self.unread.mark(tab);
+        }
+    }
+
+    /// Redraw the status bar and the tab bar's unread badges if what they
+    /// show has changed (called once a second)
+    pub fn tick_status(&mut self, width: usize) {
+        self.unread.clear(self.active_tab);
+        if self.unread != self.unread_shown {
+            let gemini_available = self.ai_chat.is_some();
+            let tunes_available = self.tunes_available();
+            let _ = self.serial.write_str(&redraw_tab_bar(
+                self.theme,
+                self.active_tab,
+                gemini_available,
+                tunes_available,
+                self.active_call.as_deref(),
+                self.unread,
+                width,
+            ));
+            self.unread_shown = self.unread;
+        }
+        let status = self.status_line();
+        if self.status_shown.as_deref() != Some(status.as_str()) {
+            let _ = self
+                .serial
+                .write_str(&redraw_status_bar(self.theme, Some(&status), width));
+            self.status_shown = Some(status);
+        }
+    }
+
+    /// Search the chat scrollback for `query`, jumping to the newest match.
+    /// Returns false if nothing matched.
+    pub fn search_chat(&mut self, query: &str, width: usize) -> bool {
+        let found = self.chat_buffer.search(query);
+        self.show_search(found, width);
+        found.is_some()
+    }
+
+    /// Step to the next older (n) or newer (p) match
+    pub fn step_search(&mut self, older: bool, width: usize) {
+        let found = self.chat_buffer.search_step(older);
+        self.show_search(found, width);
+    }
+
+    /// Stop searching, leaving the view where it is
+    pub fn end_search(&mut self, width: usize) {
+        self.chat_buffer.end_search();
+        self.show_search(None, width);
+    }
+
+    fn show_search(&mut self, found: Option<(usize, usize)>, width: usize) {
+        self.search_label = found.and_then(|(n, total)| {
+            // Leave room for the topic
+            let query: String = self.chat_buffer.search_query()?.chars().take(20).collect();
+            Some(format!("Search \"{}\" {}/{} n/p", query, n, total))
+        });
+        if self.active_tab == Tab::Chat {
+            let _ = self.serial.write_str(&self.chat_buffer.render());
+        }
+        self.refresh_separator(width);
+    }
+
+    /// Start a timer a peer shared with everyone. `started_ms` is by the
+    /// peer's clock, so it's corrected by how far that clock is off ours.
+    pub fn join_timer(
+        &mut self,
+        from: &str,
+        label: &str,
+        started_ms: i64,
+        length_secs: Option<u32>,
+    ) {
+        let offset_ms = self
+            .net_node
+            .peers()
+            .iter()
+            .find(|p| p.name == from)
+            .and_then(|p| p.clock_offset)
+            .unwrap_or(0)
+            * 1000;
+        let ago_ms = chrono::Utc::now().timestamp_millis() - (started_ms - offset_ms);
+        let started_at = std::time::Instant::now()
+            .checked_sub(Duration::from_millis(ago_ms.max(0) as u64))
+            .unwrap_or_else(std::time::Instant::now);
+        let label: String = label
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_TIMER_LABEL)
+            .collect();
+        let timestamp = stamp::now();
+        let (kind, msg) = match length_secs {
+            Some(secs) => (
+                TimerKind::Countdown(Duration::from_secs(secs as u64)),
+                format!(
+                    "{}*** {} started a {} min timer for everyone{} ***",
+                    timestamp,
+                    from,
+                    secs.div_ceil(60),
+                    if label.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", label)
+                    }
+                ),
+            ),
+            None => (
+                TimerKind::Pomodoro,
+                format!(
+                    "{}*** {} started a pomodoro session for everyone ***",
+                    timestamp, from
+                ),
+            ),
+        };
+        self.timer = Some(Timer::new(&label, kind, started_at, Some(from.to_string())));
+        self.push_chat(msg);
+    }
+
+    /// A peer stopped the timer it shared
+    pub fn stop_shared_timer(&mut self, from: &str) -> bool {
+        if self
+            .timer
+            .as_ref()
+            .is_none_or(|t| t.shared_by.as_deref() != Some(from))
+        {
+            return false;
+        }
+        self.timer = None;
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** {} stopped the shared timer ***",
+            timestamp, from
+        ));
+        true
+    }
+
+    /// Adopt a pinned line if it's newer than ours, announcing it in the chat.
+    /// Returns true if it changed (the chat area needs redrawing).
+    pub fn set_pin(&mut self, pin: RoomTopic) -> bool {
+        if self.pin.as_ref().is_some_and(|p| !pin.supersedes(p)) {
+            return false;
+        }
+        let timestamp = stamp::now();
+        let msg = if pin.text.is_empty() {
+            self.chat_buffer.set_pin(None);
+            format!("{}*** {} unpinned the message ***", timestamp, pin.set_by)
+        } else {
+            self.chat_buffer
+                .set_pin(Some(format!("Pinned by {}: {}", pin.set_by, pin.text)));
+            format!("{}*** {} pinned: {} ***", timestamp, pin.set_by, pin.text)
+        };
+        self.pin = Some(pin);
+        self.push_chat(msg);
+        true
+    }
+
+    /// Bring a peer that just joined up to date with the topic, pinned line
+    /// and to-do list
+    pub async fn send_room_state(&self, addr: SocketAddr) {
+        if let Some(topic) = &self.topic {
+            let _ = self.net_node.send_topic(topic, addr).await;
+        }
+        if let Some(pin) = &self.pin {
+            let _ = self.net_node.send_pin(pin, addr).await;
+        }
+        for ops in self.todo.ops().chunks(TODO_OPS_PER_MESSAGE) {
+            let msg = Message::Todo {
+                from: self.config.network.name.clone(),
+                ops: ops.to_vec(),
+            };
+            let _ = self.net_node.send_to(&msg, addr).await;
+        }
+    }
+
+    /// Make a change to the to-do list and send it to every peer
+    pub async fn change_todo(&mut self, op: TodoOp) {
+        if !self.todo.apply(std::slice::from_ref(&op)) {
+            return;
+        }
+        let msg = Message::Todo {
+            from: self.config.network.name.clone(),
+            ops: vec![op],
+        };
+        if let Err(e) = self.net_node.broadcast(&msg).await {
+            let timestamp = stamp::now();
+            self.push_chat(format!(
+                "{}*** Failed to send to-do change: {} ***",
+                timestamp, e
+            ));
+        }
+        if self.active_tab == Tab::Todo {
+            let _ = self.serial.write_str(&self.todo.render());
+        }
+    }
+
+    /// Remove the checked items from the to-do list, returning how many went
+    pub async fn clear_done_todos(&mut self) -> usize {
+        let done: Vec<u64> = self
+            .todo
+            .items()
+            .iter()
+            .filter(|item| item.done)
+            .map(|item| item.id)
+            .collect();
+        for &id in &done {
+            let op = TodoOp::Remove {
+                id,
+                by: self.config.network.name.clone(),
+                at: chrono::Utc::now().timestamp_millis(),
+            };
+            self.change_todo(op).await;
+        }
+        done.len()
+    }
+
+    /// Apply to-do changes from a peer, redrawing the list if it's showing
+    pub fn apply_todo(&mut self, ops: &[TodoOp]) {
+        if self.todo.apply(ops) {
+            if self.active_tab == Tab::Todo {
+                let _ = self.serial.write_str(&self.todo.render());
+            } else {
+                self.unread.mark(Tab::Todo);
+            }
+        }
+    }
+
+    /// When this node started, in ms since the Unix epoch (settles name clashes)
+    pub fn started_unix_ms(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis() - self.started.elapsed().as_millis() as i64
+    }
+
+    /// Change our name everywhere and tell peers
+    pub async fn change_nick(&mut self, new: String, width: usize) {
+        let old = std::mem::replace(&mut self.config.network.name, new.clone());
+        self.net_node.set_name(new.clone());
+        if let Some(transfers) = &mut self.transfers {
+            transfers.set_name(new.clone());
+        }
+        if let Some(discovery) = &self.discovery {
+            discovery.set_name(&new);
+        }
+        let _ = self
+            .net_node
+            .broadcast(&Message::Nick {
+                old,
+                new: new.clone(),
+            })
+            .await;
+
+        let timestamp = stamp::now();
+        self.push_chat(format!("{}*** You are now known as {} ***", timestamp, new));
+        self.chat_buffer.scroll_to_bottom();
+        // The prompt shows our name
+        self.redraw_screen(width);
+    }
+
+    /// Send whatever the file transfers have queued for peers that are online,
+    /// and report what happened. Returns true if anything was added to the chat.
+    pub async fn pump_transfers(&mut self) -> bool {
+        let Some(transfers) = &mut self.transfers else {
+            return false;
+        };
+        let peers = self.net_node.peers();
+        transfers.poll(|name| peers.iter().any(|p| p.name == name));
+        let outbox = transfers.take_outbox();
+        let events = transfers.take_events();
+        for (peer, chunks) in transfers.take_retransmissions() {
+            self.net_node.note_retransmissions(&peer, chunks);
+        }
+
+        for (peer, msg) in outbox {
+            let addr = self
+                .net_node
+                .peers()
+                .iter()
+                .find(|p| p.name == peer)
+                .map(|p| p.addr);
+            match addr {
+                // File data takes its turn with other peers' bulk traffic
+                Some(addr) if matches!(msg, Message::FileChunk { .. }) => {
+                    self.net_node.queue_to(&msg, addr);
+                }
+                Some(addr) => {
+                    let _ = self.net_node.send_to(&msg, addr).await;
+                }
+                None => {}
+            }
+        }
+
+        let timestamp = stamp::now();
+        for event in &events {
+            if matches!(event, TransferEvent::Offered { .. }) {
+                let _ = self.serial.write_str("\x07");
+            }
+            self.push_chat(format!("{}*** {} ***", timestamp, event));
+        }
+        // Tracks fetched by a sync show up in the Tunes tab straight away
+        let tunes_dir = self.config.tunes.directory.as_deref().map(Path::new);
+        let fetched = events.iter().any(|e| match e {
+            TransferEvent::Received { path, .. } => path.parent() == tunes_dir,
+            _ => false,
+        });
+        if fetched && let Some(tunes) = &mut self.tunes_state {
+            tunes.refresh();
+        }
+        if !events.is_empty() {
+            self.chat_buffer.scroll_to_bottom();
+        }
+        !events.is_empty()
+    }
+
+    fn peer_addr(&self, name: &str) -> Option<SocketAddr> {
+        self.net_node
+            .peers()
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.addr)
+    }
+
+    /// Our Tunes directory, if we take part in syncs and can
+    fn tunes_sync_dir(&self) -> Result<PathBuf, &'static str> {
+        if !self.config.tunes.sync {
+            return Err("Tunes sync is off (sync under [tunes])");
+        }
+        if self.transfers.is_none() {
+            return Err("Tunes sync needs file transfers (directory under [files])");
+        }
+        match self.config.tunes.directory.as_deref().map(PathBuf::from) {
+            Some(dir) if dir.is_dir() => Ok(dir),
+            _ => Err("No directory of tunes is set under [tunes]"),
+        }
+    }
+
+    /// Our tracks, hashed for a sync
+    fn tunes_library(dir: &Path) -> Vec<SyncTrack> {
+        network::scan_library(dir, &TunesState::scan_directory(dir))
+    }
+
+    /// Send a peer our listing for the sync with it
+    async fn send_tunes_list(&mut self, peer: &str, addr: SocketAddr, reply: bool) {
+        for part in network::list_parts(self.tunes_sync.library(peer)) {
+            let msg = Message::TunesList {
+                from: self.config.network.name.clone(),
+                reply,
+                tracks: Some(part),
+            };
+            let _ = self.net_node.send_to(&msg, addr).await;
+        }
+    }
+
+    /// Start a `/tunes sync` with a peer. Returns what to tell the user.
+    pub async fn start_tunes_sync(&mut self, peer: &str) -> String {
+        let dir = match self.tunes_sync_dir() {
+            Ok(dir) => dir,
+            Err(e) => return e.to_string(),
+        };
+        let Some(addr) = self.peer_addr(peer) else {
+            return format!("Peer '{}' not found", peer);
+        };
+        let library = Self::tunes_library(&dir);
+        let count = library.len();
+        if !self.tunes_sync.start(peer, library) {
+            return format!("Already syncing tunes with {}", peer);
+        }
+        self.send_tunes_list(peer, addr, false).await;
+        format!("Comparing {} tracks with {}", count, peer)
+    }
+
+    /// Part of a peer's listing: answer a new sync with ours, and ask for
+    /// the tracks we haven't got. Returns true if anything was added to the chat.
+    pub async fn handle_tunes_list(
+        &mut self,
+        from: String,
+        reply: bool,
+        tracks: Option<Vec<SyncTrack>>,
+    ) -> bool {
+        let Some(addr) = self.peer_addr(&from) else {
+            return false;
+        };
+        let timestamp = stamp::now();
+        let Some(theirs) = tracks else {
+            // They don't sync
+            if reply && self.tunes_sync.is_active(&from) {
+                self.tunes_sync.end(&from);
+                self.push_chat(format!(
+                    "{}*** {} doesn't take part in tunes syncs ***",
+                    timestamp, from
+                ));
+                return true;
+            }
+            return false;
+        };
+        let Ok(dir) = self.tunes_sync_dir() else {
+            if !reply {
+                let msg = Message::TunesList {
+                    from: self.config.network.name.clone(),
+                    reply: true,
+                    tracks: None,
+                };
+                let _ = self.net_node.send_to(&msg, addr).await;
+            }
+            return false;
+        };
+        let mut said = false;
+        if !reply && self.tunes_sync.start(&from, Self::tunes_library(&dir)) {
+            self.send_tunes_list(&from, addr, true).await;
+            self.push_chat(format!(
+                "{}*** {} is syncing tunes with you ***",
+                timestamp, from
+            ));
+            said = true;
+        }
+        let missing = self.tunes_sync.missing(&from, &theirs);
+        if let Some(transfers) = &mut self.transfers {
+            for track in &missing {
+                transfers.expect(&from, track.hash, &dir);
+            }
+        }
+        let hashes: Vec<_> = missing.iter().map(|t| t.hash).collect();
+        for part in network::want_parts(&hashes) {
+            let msg = Message::TunesWant {
+                from: self.config.network.name.clone(),
+                hashes: part,
+            };
+            let _ = self.net_node.send_to(&msg, addr).await;
+        }
+        said
+    }
+
+    /// A syncing peer asked for some of our tracks: offer them
+    pub fn handle_tunes_want(&mut self, from: &str, hashes: &[[u8; HASH_LEN]]) {
+        let Ok(dir) = self.tunes_sync_dir() else {
+            return;
+        };
+        if !self.tunes_sync.is_active(from) {
+            return;
+        }
+        let Some(transfers) = &mut self.transfers else {
+            return;
+        };
+        for hash in hashes {
+            if let Some(track) = self.tunes_sync.track(from, hash)
+                && let Err(e) = transfers.send_limited(from, &dir.join(&track.name))
+            {
+                eprintln!("Failed to offer {} to {}: {}", track.name, from, e);
+            }
+        }
+    }
+
+    /// A peer joined under our name: tell it how long we've had it
+    pub async fn check_name_clash(&mut self, name: &str, addr: SocketAddr) {
+        if name == self.config.network.name {
+            let since = self.started_unix_ms();
+            let _ = self.net_node.send_nick_in_use(since, addr).await;
+        }
+    }
+
+    /// A peer that had our name first objected. The node that started later
+    /// renames itself with a numeric suffix.
+    pub async fn resolve_name_clash(&mut self, name: &str, since: i64, width: usize) {
+        if name != self.config.network.name || self.started_unix_ms() <= since {
+            return;
+        }
+        let new = network::next_free_name(name, |n| self.net_node.name_taken(n, None));
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** Another node is already called {} ***",
+            timestamp, name
+        ));
+        self.change_nick(new, width).await;
+    }
+
+    /// Apply a peer's rename, returning the chat line announcing it
+    pub fn peer_renamed(&mut self, addr: SocketAddr, new: String) -> Option<String> {
+        let old = self.net_node.rename_peer(addr, new.clone())?;
+        if old == new {
+            return None;
+        }
+        if self.active_call.as_deref() == Some(old.as_str()) {
+            self.active_call = Some(new.clone());
+        }
+        if let Some(transfers) = &mut self.transfers {
+            transfers.rename_peer(&old, &new);
+        }
+        self.receipts.rename_peer(&old, &new);
+        self.tunes_sync.rename_peer(&old, &new);
+        self.windows.rename_peer(&old, &new);
+        if let Some(caps) = self.peer_caps.remove(&old) {
+            self.peer_caps.insert(new.clone(), caps);
+        }
+        let timestamp = stamp::now();
+        Some(format!(
+            "{}*** {} is now known as {} ***",
+            timestamp, old, new
+        ))
+    }
+
+    /// Our own `/whois` card
+    pub fn whois_info(&self) -> WhoisInfo {
+        let mut capabilities = Vec::new();
+        if self.webcam.is_some() {
+            capabilities.push(CAP_VIDEO.to_string());
+        }
+        if self.ai_chat.is_some() {
+            capabilities.push("AI".to_string());
+        }
+        if self.tunes_state.is_some() {
+            capabilities.push(CAP_AUDIO.to_string());
+        }
+        if self.transfers.is_some() {
+            capabilities.push(CAP_FILES.to_string());
+        }
+        if crate::terminal::color::enabled() {
+            capabilities.push(CAP_COLOR.to_string());
+        }
+        if Charset::for_mode(&self.config.terminal.mode) != Charset::Ascii {
+            capabilities.push(CAP_ACCENTS.to_string());
+        }
+        if self.config.terminal.cols_132 {
+            capabilities.push("132 columns".to_string());
+        }
+        if self.chat_history.sharing() {
+            capabilities.push("chat history".to_string());
+        }
+        if self.config.webcam.adaptive {
+            capabilities.push("adaptive video".to_string());
+        }
+        WhoisInfo {
+            name: self.config.network.name.clone(),
+            profile: self.config.network.profile.clone().unwrap_or_default(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            terminal: format!("{}, {}", self.config.terminal.mode, self.renderer.name()),
+            uptime_secs: self.started.elapsed().as_secs(),
+            capabilities,
+        }
+    }
+
+    /// Ask a peer for its card, to show when it comes
+    pub async fn request_whois(
+        &mut self,
+        name: &str,
+        addr: SocketAddr,
+    ) -> Result<(), NetworkError> {
+        self.whois_wanted.insert(name.to_string());
+        self.net_node.request_whois(addr).await
+    }
+
+    /// Note what a peer can do from its card, and show the card if it was
+    /// asked for with `/whois`. Returns whether it was shown.
+    pub fn take_whois(&mut self, info: &WhoisInfo) -> bool {
+        self.peer_caps
+            .insert(info.name.clone(), Caps::from_info(info));
+        if !self.whois_wanted.remove(&info.name) {
+            return false;
+        }
+        self.show_whois(info);
+        true
+    }
+
+    /// A peer's name with its capability badge: `bob [VF]`
+    pub fn peer_label(&self, name: &str) -> String {
+        match self.peer_caps.get(name).map(Caps::badge) {
+            Some(badge) if !badge.is_empty() => format!("{} {}", name, badge),
+            _ => name.to_string(),
+        }
+    }
+
+    /// The Call tab's status line while in a call
+    pub fn call_status(&self) -> Option<String> {
+        self.active_call.as_ref().map(|peer_name| {
+            format!(
+                "Call session with {}. Press Space to hang up.",
+                self.peer_label(peer_name)
+            )
+        })
+    }
+
+    /// Show a `/whois` card in the chat
+    pub fn show_whois(&mut self, info: &WhoisInfo) {
+        let timestamp = stamp::now();
+        self.push_chat(format!("{}*** whois {} ***", timestamp, info.name));
+        for line in info.card() {
+            self.push_chat(line);
+        }
+    }
+
+    /// Show history a peer sent us, leaving out messages we've already seen
+    pub fn show_backfill(&mut self, from: &str, entries: Vec<HistoryEntry>) {
+        let entries: Vec<HistoryEntry> = entries
+            .into_iter()
+            .filter(|e| !self.chat_history.contains(&e.from, &e.text))
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+
+        let timestamp = stamp::now();
+        self.push_chat(format!(
+            "{}*** Recent chat from {} ({} messages) ***",
+            timestamp,
+            from,
+            entries.len()
+        ));
+        for entry in entries {
+            let time = Local
+                .timestamp_millis_opt(entry.unix_ms)
+                .single()
+                .map(|t| stamp::at(&t))
+                .unwrap_or_default();
+            let line = if let Some(action) = entry.text.strip_prefix("\x01ACTION ") {
+                format!("{}* {} {}", time, entry.from, action)
+            } else if let Some(text) = entry.text.strip_prefix("\x01QR ") {
+                format!("{}{} shared a QR code for {}", time, entry.from, text)
+            } else if let Some(answer) = entry.text.strip_prefix("\x01AI ") {
+                format!("{}{} (via {}): {}", time, AI_NAME, entry.from, answer)
+            } else {
+                format!("{}{}: {}", time, entry.from, entry.text)
+            };
+            self.push_chat(line);
+        }
+        self.push_chat(format!("{}*** End of recent chat ***", timestamp));
+    }
+
+    /// Redraw the entire screen for the active tab (tab bar, borders and content)
+    /// Give the input area a row more (Ctrl+Up) or less (Ctrl+Down), taking it
+    /// from the chat area, and redraw. `max_input_len` is what the line may
+    /// now hold; a line too long for a smaller area is cut short.
+    pub fn resize_input(&mut self, grow: bool, width: usize) -> usize {
+        let rows = layout::input_rows();
+        let wanted = if grow { rows + 1 } else { rows - 1 };
+        let changed = layout::set_input_rows(wanted) != rows;
+        let max_input_len = max_input_length(&self.config.network.name, width);
+        if changed {
+            self.line_buffer.truncate(max_input_len);
+            self.input_cursor = self.input_cursor.min(self.line_buffer.len());
+            self.chat_buffer.fit_rows();
+            self.ai_buffer.fit_rows();
+            self.redraw_screen(width);
+        }
+        max_input_len
+    }
+
+    /// Switch to the screen layout called `name` and redraw. Returns false
+    /// if there's no such layout.
+    pub fn set_layout(&mut self, name: &str, width: usize) -> bool {
+        let Some(new_layout) = self.config.layout(name) else {
+            return false;
+        };
+        layout::apply(new_layout);
+        self.layout_name = name.to_string();
+        self.serial.set_screen_size(width, layout::screen_rows());
+        let max_input_len = max_input_length(&self.config.network.name, width);
+        self.line_buffer.truncate(max_input_len);
+        self.input_cursor = self.input_cursor.min(self.line_buffer.len());
+        self.chat_buffer.fit_rows();
+        self.ai_buffer.fit_rows();
+        self.redraw_screen(width);
+        true
+    }
+
+    pub fn redraw_screen(&mut self, width: usize) {
+        let status = if self.active_tab == Tab::Call {
+            self.call_status()
+        } else {
+            None
+        };
+        let gemini_available = self.ai_chat.is_some();
+        let tunes_available = self.tunes_available();
+        // The separator is drawn without the timer; put it back on the next tick
+        self.separator_shown.1 = None;
+        let _ = self.serial.write_str(&init_split_screen_with_tabs(
+            self.theme,
+            &self.config.network.name,
+            self.active_tab,
+            gemini_available,
+            tunes_available,
+            self.active_call.as_deref(),
+            self.unread,
+            status.as_deref(),
+            self.topic_text(),
+            self.status_shown.as_deref(),
+            width,
+        ));
+        match self.active_tab {
+            Tab::Chat => {
+                let _ = self.serial.write_str(&self.chat_buffer.render());
+                let _ = self.serial.write_str(&redraw_input(
+                    self.theme,
+                    &self.config.network.name,
+                    &self.line_buffer,
+                    self.input_cursor,
+                    width,
+                ));
+            }
+            Tab::Gemini => {
+                let _ = self.serial.write_str(&self.ai_buffer.render());
+                let _ = self.serial.write_str(&redraw_input(
+                    self.theme,
+                    &self.config.network.name,
+                    &self.line_buffer,
+                    self.input_cursor,
+                    width,
+                ));
+            }
+            Tab::Call => {
+                // Nothing else to render for Call
+            }
+            Tab::Tunes => {
+                if let Some(ref tunes) = self.tunes_state {
+                    tunes.redraw_cover();
+                    let _ = self.serial.write_str(&tunes.render());
+                }
+            }
+            Tab::Stats => {
+                let _ = self.serial.write_str(&render_stats(
+                    &self.net_node.peer_stats(),
+                    &self.peer_caps,
+                    width,
+                ));
+            }
+            Tab::Todo => {
+                let _ = self.serial.write_str(&self.todo.render());
+            }
+        }
+    }
+
+    /// Tidy up after the call with `peer` ends, going back to the chat if the
+    /// call was on screen
+    pub async fn call_ended(&mut self, peer: &str, width: usize) {
+        self.active_call = None;
+        self.net_node.reset_video(peer);
+        self.last_rendered_frame = None;
+        self.call_last_packet = None;
+        self.call_connected = false;
+
+        // Stop webcam
+        if let Some(cam) = &self.webcam {
+            cam.stop().await;
+        }
+
+        if self.active_tab == Tab::Call {
+            self.switch_tab(Tab::Chat);
+            if self.serial.is_connected() {
+                self.redraw_screen(width);
+            }
+        } else if self.serial.is_connected() {
+            // Just update the tab bar
+            let gemini_available = self.ai_chat.is_some();
+            let tunes_available = self.tunes_available();
+            let _ = self.serial.write_str(&redraw_tab_bar(
+                self.theme,
+                self.active_tab,
+                gemini_available,
+                tunes_available,
+                self.active_call.as_deref(),
+                self.unread,
+                width,
+            ));
+        }
+    }
+
+    /// Act on a message from a peer, whether or not the terminal is
+    /// connected. Returns whether the mesh chat changed, for the caller to
+    /// draw.
+    pub async fn handle_message(&mut self, msg: Message, width: usize) -> bool {
+        // Update call timeout if message is from active peer
+        if let Some(peer_name) = &self.active_call {
+            let from_peer = match &msg {
+                Message::Chat { from, .. } => Some(from),
+                Message::StreamFrame { from, .. } => Some(from),
+                Message::VideoFrame { from, .. } => Some(from),
+                Message::VideoFrameFragment { from, .. } => Some(from),
+                Message::CallRequest { from } => Some(from),
+                Message::CallHangup { from } => Some(from),
+                _ => None,
+            };
+
+            if let Some(from) = from_peer
+                && from == peer_name
+            {
+                self.call_last_packet = Some(std::time::Instant::now());
+                self.call_connected = true;
+            }
+        }
+
+        let mut changed = false;
+        match msg {
+            Message::Chat {
+                from,
+                id,
+                text,
+                lamport,
+                ttl,
+            } => {
+                // A copy relayed by another peer
+                if !self
+                    .net_node
+                    .accept_chat(&from, id, &text, lamport, ttl)
+                    .await
+                {
+                    return false;
+                }
+                self.note_activity();
+                self.mark_unread(Tab::Chat);
+                let timestamp = stamp::now();
+                // Confirm delivery to the sender
+                if let Some(sent_at) = lamport
+                    && let Some(peer) = self.net_node.peers().iter().find(|p| p.name == from)
+                {
+                    let _ = self.net_node.send_chat_ack(sent_at, peer.addr).await;
+                }
+                let lamport = self.net_node.observe_lamport(lamport);
+                self.chat_history.record(&from, &text);
+
+                // Ring for contacts, or if our name is mentioned (case-insensitive)
+                let my_name = &self.config.network.name;
+                if from != *my_name {
+                    let mentioned = text.to_lowercase().contains(&my_name.to_lowercase());
+                    self.notify(&from, mentioned);
+                }
+
+                // Mesh chat goes to its window, whichever is shown
+                self.in_mesh(|app| app.show_chat(&from, id, &text, lamport, &timestamp));
+                changed |= self.windows.arrived(0);
+            }
+            Message::ChatEdit {
+                from,
+                id,
+                text,
+                ttl,
+            } if self.net_node.accept_chat_edit(&from, id, &text, ttl).await => {
+                let edited = self.in_mesh(|app| app.apply_edit(&from, id, &text));
+                changed |= edited && self.windows.arrived(0);
+            }
+            Message::HistoryChunk {
+                from,
+                part,
+                total,
+                entries,
+            } => {
+                if let Some(entries) = self.backfill.accept(&from, part, total, entries) {
+                    self.in_mesh(|app| app.show_backfill(&from, entries));
+                    changed |= self.windows.arrived(0);
+                }
+            }
+            Message::WindowChat { from, to, text } => {
+                changed |= self.window_chat(&from, &to, &text, width);
+            }
+            Message::ImageShare {
+                from,
+                width: image_width,
+                height,
+                pixels,
+                lamport,
+            } => {
+                self.note_activity();
+                self.mark_unread(Tab::Chat);
+                let timestamp = stamp::now();
+                let lamport = self.net_node.observe_lamport(Some(lamport));
+                // Drawn our own way, whatever the sender's terminal is
+                let frame = RawFrame {
+                    width: image_width,
+                    height,
+                    pixels,
+                };
+                let lines = webcam::shared_image_to_output(
+                    &frame,
+                    self.renderer.as_ref(),
+                    self.config.dither(),
+                    width,
+                );
+                self.notify(&from, false);
+                self.in_mesh(|app| {
+                    app.push_share(
+                        &from,
+                        format!("{}{} shared an image:", timestamp, from),
+                        &lines.join("\n"),
+                        lamport,
+                    );
+                    app.chat_buffer.scroll_to_bottom();
+                });
+                changed |= self.windows.arrived(0);
+            }
+            Message::VideoReport {
+                from,
+                fragments,
+                bytes,
+            } if self.active_call.as_deref() == Some(&from) => {
+                self.video_sender.on_report(fragments, bytes);
+            }
+            Message::WhoisRequest { from } => {
+                let info = Message::WhoisReply(self.whois_info());
+                if let Some(peer) = self.net_node.peers().iter().find(|p| p.name == from) {
+                    let _ = self.net_node.send_to(&info, peer.addr).await;
+                }
+            }
+            Message::WhoisReply(info) => {
+                // Cards we didn't ask for only update the peer's badge
+                let shown = self.take_whois(&info);
+                if shown {
+                    self.chat_buffer.scroll_to_bottom();
+                    changed = true;
+                }
+            }
+            Message::NickInUse { name, since } => {
+                self.resolve_name_clash(&name, since, width).await;
+            }
+            Message::Topic(topic) => {
+                changed |= self.set_topic(topic, width);
+            }
+            Message::Pin(pin) => {
+                changed |= self.in_mesh(|app| app.set_pin(pin)) && self.windows.arrived(0);
+            }
+            Message::Todo { ops, .. } => {
+                self.apply_todo(&ops);
+            }
+            Message::TimerStart {
+                from,
+                label,
+                started_ms,
+                length_secs,
+            } => {
+                self.join_timer(&from, &label, started_ms, length_secs);
+                changed = true;
+            }
+            Message::TimerStop { from } => {
+                changed |= self.stop_shared_timer(&from);
+            }
+            Message::Ring { from } => {
+                let rang = self.rung(&from);
+                changed |= rang;
+                if let Some(peer) = self.net_node.peers().iter().find(|p| p.name == from) {
+                    let reply = Message::RingReply {
+                        from: self.config.network.name.clone(),
+                        rang,
+                    };
+                    let _ = self.net_node.send_to(&reply, peer.addr).await;
+                }
+            }
+            Message::RingReply { from, rang } => {
+                let timestamp = stamp::now();
+                let msg = if rang {
+                    format!("{}*** {}'s terminal is ringing ***", timestamp, from)
+                } else {
+                    format!("{}*** {} isn't taking rings right now ***", timestamp, from)
+                };
+                self.push_chat(msg);
+                changed = true;
+            }
+            Message::ChatAck { from, lamport } => {
+                changed |= self.chat_acked(lamport, &from);
+            }
+            Message::CallRequest { from } => {
+                let is_busy = if let Some(current_peer) = &self.active_call {
+                    current_peer != &from
+                } else {
+                    false
+                };
+
+                if is_busy {
+                    // We are busy, reject the call
+                    let msg = Message::CallReject {
+                        from: self.config.network.name.clone(),
+                        reason: "busy".to_string(),
+                    };
+                    if let Some(peer) = self.net_node.peers().iter().find(|p| p.name == from)
+                        && let Err(e) = self.net_node.send_to(&msg, peer.addr).await
+                    {
+                        eprintln!("Failed to send call rejection: {}", e);
+                    }
+                } else {
+                    let timestamp = stamp::now();
+
+                    // If we are already calling them, this is an answer
+                    if self.active_call.as_deref() == Some(&from) {
+                        let msg = format!("{}*** Call connected with {} ***", timestamp, from);
+                        self.push_chat(msg);
+                        self.call_connected = true;
+                    } else {
+                        let msg = format!(
+                            "{}*** {} is calling you - press Y to answer or N to decline ***",
+                            timestamp, from
+                        );
+                        self.push_chat(msg);
+                        self.incoming_call = Some((from.clone(), std::time::Instant::now()));
+                        // Ring the bell (3 times for a ringing effect)
+                        let _ = self.serial.write_str("\x07\x07\x07");
+                    }
+
+                    self.chat_buffer.scroll_to_bottom();
+                    changed = true;
+                }
+            }
+            Message::CallReject { from, reason } => {
+                let timestamp = stamp::now();
+                let msg = if reason == "busy" {
+                    format!("{}*** {} is busy ***", timestamp, from)
+                } else {
+                    format!("{}*** {} {} the call ***", timestamp, from, reason)
+                };
+                self.push_chat(msg);
+                self.chat_buffer.scroll_to_bottom();
+                changed = true;
+
+                // If we are trying to call this person, hang up
+                if self.active_call.as_deref() == Some(&from) {
+                    self.call_ended(&from, width).await;
+                }
+            }
+            Message::CallHangup { from } => {
+                let timestamp = stamp::now();
+                let msg = format!("{}*** {} hung up ***", timestamp, from);
+                self.push_chat(msg);
+                // A caller giving up takes the prompt with it
+                if self
+                    .incoming_call
+                    .as_ref()
+                    .is_some_and(|(caller, _)| *caller == from)
+                {
+                    self.incoming_call = None;
+                }
+                self.chat_buffer.scroll_to_bottom();
+                changed = true;
+
+                // If we are in a call with this person, hang up
+                if self.active_call.as_deref() == Some(&from) {
+                    self.call_ended(&from, width).await;
+                }
+            }
+            Message::StreamFrame { from, .. } => {
+                // Legacy: ignore pre-rendered StreamFrame from older peers
+                eprintln!("Received legacy StreamFrame from {} (ignored)", from);
+            }
+            Message::VideoFrame {
+                from,
+                width: w,
+                height: h,
+                pixels,
+            } => {
+                self.current_video_frame = Some((
+                    from,
+                    RawFrame {
+                        width: w,
+                        height: h,
+                        pixels,
+                    },
+                ));
+                self.stats_frames_received += 1;
+            }
+            Message::VideoFrameFragment {
+                from,
+                width,
+                height,
+                frame_id,
+                fragment_idx,
+                total_fragments,
+                key_id,
+                data,
+                hash,
+            } => {
+                self.video_receiver.record(&from, data.len());
+                // Process the fragment and check if frame is complete
+                match self.net_node.process_fragment(
+                    from.clone(),
+                    width,
+                    height,
+                    frame_id,
+                    fragment_idx,
+                    total_fragments,
+                    key_id,
+                    data,
+                    hash,
+                ) {
+                    Ok(Some(Message::VideoFrame {
+                        from,
+                        width,
+                        height,
+                        pixels,
+                    })) => {
+                        self.current_video_frame = Some((
+                            from,
+                            RawFrame {
+                                width,
+                                height,
+                                pixels,
+                            },
+                        ));
+                        self.stats_frames_received += 1;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("Dropped {}", e);
+                        changed |= self.corrupt_frame(&from);
+                    }
+                }
+            }
+            file_msg @ (Message::FileOffer { .. }
+            | Message::FileRequest { .. }
+            | Message::FileChunk { .. }
+            | Message::FileDone { .. }
+            | Message::FileCancel { .. }) => {
+                if let Some(transfers) = &mut self.transfers {
+                    transfers.handle(file_msg);
+                }
+            }
+            Message::TunesList {
+                from,
+                reply,
+                tracks,
+            } => {
+                changed |= self.handle_tunes_list(from, reply, tracks).await;
+            }
+            Message::TunesWant { from, hashes } => self.handle_tunes_want(&from, &hashes),
+            _ => {}
+        }
+        changed
+    }
+
+    /// Act on a peer joining, leaving or checking in. Returns whether a line
+    /// was added to the chat, for the caller to draw.
+    pub async fn handle_peer_event(&mut self, event: PeerEvent) -> bool {
+        let timestamp = stamp::now();
+        let msg = match event {
+            PeerEvent::Joined {
+                name,
+                addr,
+                version,
+            } => {
+                self.net_node.add_peer(name.clone(), addr);
+                let newer = self.net_node.set_peer_version(addr, version.clone());
+                self.check_name_clash(&name, addr).await;
+                let _ = self.net_node.send_clock(addr).await;
+                let _ = self.net_node.request_whois(addr).await;
+                if self.backfill.wanted() {
+                    let _ = self.net_node.request_history(addr).await;
+                }
+                self.send_room_state(addr).await;
+                let joined = format!("{}*** {} has joined ***", timestamp, name);
+                if !newer {
+                    joined
+                } else {
+                    self.push_chat(joined);
+                    format!(
+                        "{}*** {} runs Wormhole {} (you have {}) - consider upgrading ***",
+                        timestamp,
+                        name,
+                        version,
+                        network::APP_VERSION
+                    )
+                }
+            }
+            PeerEvent::Alive { name, addr } => match self.net_node.peer_alive(&name, addr) {
+                Some(old) => format!(
+                    "{}*** {}'s address changed from {} to {} ***",
+                    timestamp, name, old, addr
+                ),
+                None => return false,
+            },
+            PeerEvent::Renamed { addr, new } => match self.peer_renamed(addr, new) {
+                Some(msg) => msg,
+                None => return false,
+            },
+            PeerEvent::Incompatible { addr, protocol } => {
+                if !self.net_node.note_incompatible(addr, protocol) {
+                    return false;
+                }
+                format!(
+                    "{}*** {} at {} ***",
+                    timestamp,
+                    describe_incompatible(protocol),
+                    addr
+                )
+            }
+            PeerEvent::Left { name, addr } => {
+                self.net_node.remove_peer(addr);
+                self.receipts_peer_gone(&name);
+                format!("{}*** {} has left ***", timestamp, name)
+            }
+            PeerEvent::HistoryRequested { addr } => {
+                if let Some(chunks) = self.chat_history.answer(addr) {
+                    let _ = self.net_node.send_history(chunks, addr).await;
+                }
+                return false;
+            }
+            PeerEvent::Pong {
+                addr,
+                seq,
+                received,
+            } => {
+                self.net_node.record_pong(addr, seq, received);
+                return false;
+            }
+            PeerEvent::Clock {
+                name,
+                addr,
+                unix_ms,
+            } => match self.net_node.record_peer_clock(addr, unix_ms) {
+                Some(offset) => format!(
+                    "{}*** {}'s clock is {} - their message times may not line up with ours ***",
+                    timestamp,
+                    name,
+                    describe_clock_offset(offset)
+                ),
+                None => return false,
+            },
+        };
+        self.push_chat(msg);
+        true
+    }
+
+    /// Show a chat message from `from` in the mesh chat: an image, QR code,
+    /// relayed AI answer, /me action or plain (perhaps multi-line) text
+    pub fn show_chat(&mut self, from: &str, id: u64, text: &str, lamport: u64, timestamp: &str) {
+        if let Some(art) = text.strip_prefix("[IMAGE]\n") {
+            self.push_share(
+                from,
+                format!("{}{} shared an image:", timestamp, from),
+                art,
+                lamport,
+            );
+        } else if let Some(qr_text) = text.strip_prefix("\x01QR ") {
+            let lines = self.qr_lines(qr_text);
+            self.push_share(
+                from,
+                format!("{}{} shared a QR code for {}:", timestamp, from, qr_text),
+                &lines.join("\n"),
+                lamport,
+            );
+        } else if let Some(answer) = text.strip_prefix("\x01AI ") {
+            // The AI answering an @ai question asked at `from`
+            let (first, rest) = answer.split_once('\n').unwrap_or((answer, ""));
+            self.push_share(
+                from,
+                format!("{}{} (via {}): {}", timestamp, AI_NAME, from, first),
+                rest,
+                lamport,
+            );
+        } else if let Some(action) = text.strip_prefix("\x01ACTION ") {
+            // IRC-style /me action
+            let formatted = format!("{}* {} {}", timestamp, from, action);
+            self.push_chat_ordered(formatted, lamport);
+        } else {
+            // Regular chat message (multi-line ones, e.g. figlet, may be collapsed)
+            let (first, rest) = text.split_once('\n').unwrap_or((text, ""));
+            let prefix = format!("{}{}: ", timestamp, from);
+            self.push_share(from, format!("{}{}", prefix, first), rest, lamport);
+            self.note_editable(from, id, lamport, prefix, first);
+        }
+        self.chat_buffer.scroll_to_bottom();
+    }
+
+    /// `text` as a QR code drawn with our renderer
+    pub fn qr_lines(&self, text: &str) -> Vec<String> {
+        match QrCode::encode(text) {
+            Ok(qr) => qr.render(self.renderer.as_ref()),
+            Err(e) => vec![format!("[{}]", e)],
+        }
+    }
+}
+
+/// STUN, UPnP, configured peers and relay registration
+async fn connect_network(config: &Config, net_node: &mut NetworkNode, roster: Option<&Roster>) {
+    // Try STUN discovery
+    status!("Discovering public endpoint via STUN... ");
+    match network::discover_public_endpoint(config.network.port) {
+        Ok(addr) => {
+            println!("{}", addr);
+            net_node.set_public_addr(addr);
+        }
+        Err(e) => {
+            println!("FAILED");
+            eprintln!("  {}", e);
+        }
+    }
+
+    // Try UPnP port forwarding if enabled
+    if config.network.upnp {
+        status!("Setting up UPnP port forwarding... ");
+        match network::setup_port_forward(
+            config.network.port,
+            config.network.port,
+            "Wormhole Chat",
+            config.network.bind_ip.as_deref(),
+        ) {
+            Ok(addr) => {
+                println!("OK (external port {})", addr);
+            }
+            Err(e) => {
+                println!("FAILED");
+                eprintln!("  {}", e);
+            }
+        }
+    }
+
+    // Connect to configured peers
+    let mut configured = Vec::new();
+    if !config.network.peers.is_empty() {
+        println!("Connecting to peers...");
+        for peer_str in config.network.peers.split(',') {
+            let peer_str = peer_str.trim();
+            if let Some(addr) = network::parse_peer_addr(peer_str, config.network.port) {
+                configured.push(addr);
+                status!("  {}... ", addr);
+                match net_node.connect_to_peer(addr).await {
+                    Ok(_) => println!("OK"),
+                    Err(e) => {
+                        println!("FAILED");
+                        eprintln!("    {}", e);
+                    }
+                }
+            } else {
+                println!("  {}... INVALID ADDRESS", peer_str);
+            }
+        }
+    }
+
+    // Say hello to peers from earlier sessions; those online join back
+    let known: Vec<_> = roster
+        .map(|r| r.entries())
+        .unwrap_or_default()
+        .iter()
+        .filter(|entry| !configured.contains(&entry.addr))
+        .collect();
+    if !known.is_empty() {
+        println!("Reconnecting to {} known peer(s)...", known.len());
+        for entry in known {
+            status!("  {} at {}... ", entry.name, entry.addr);
+            match net_node.announce_to(entry.addr).await {
+                Ok(_) => println!("OK"),
+                Err(e) => {
+                    println!("FAILED");
+                    eprintln!("    {}", e);
+                }
+            }
+        }
+    }
+
+    // Register with the relay server if configured
+    if let Some(relay) = &config.network.relay {
+        status!("Registering with relay {}... ", relay);
+        match network::parse_peer_addr(relay, network::DEFAULT_RELAY_PORT) {
+            Some(addr) => {
+                net_node.enable_relay(addr);
+                net_node.maintain_relay().await;
+                println!("OK");
+            }
+            None => println!("INVALID ADDRESS"),
+        }
+    }
+}
+
+/// Spawn the task that reads the network socket and hands what arrives to
+/// the main loop. `heartbeat` is marked busy while it dispatches a batch,
+/// for the watchdog.
+fn spawn_net_receiver(
+    socket: Arc<PeerSocket>,
+    running: Arc<AtomicBool>,
+    net_tx: mpsc::Sender<Message>,
+    peer_event_tx: mpsc::Sender<PeerEvent>,
+    discovery_tx: mpsc::Sender<DiscoveredPeer>,
+    heartbeat: Heartbeat,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut batch = network::RecvBatch::new();
+        // Senders already reported as speaking another protocol version
+        let mut warned = std::collections::HashSet::new();
+        while running.load(Ordering::SeqCst) {
+            heartbeat.idle();
+            // Use a timeout to allow checking the running flag periodically
+            // A burst (a video frame's fragments) is drained from the socket
+            // in one go before any of it is dispatched
+            match tokio::time::timeout(Duration::from_millis(500), socket.recv_batch(&mut batch))
+                .await
+            {
+                Ok(result) => {
+                    match result {
+                        Ok(_) => {
+                            heartbeat.busy();
+                            for (data, _addr) in batch.iter() {
+                                if data.is_empty() {
+                                    continue;
+                                }
+                                if let Some(msg) = Message::from_bytes(data) {
+                                    match msg {
+                                        Message::Chat { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::StreamFrame { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::VideoFrame { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::VideoFrameFragment { .. } => {
+                                            // Forward fragments to be reassembled in main loop
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::CallRequest { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::CallHangup { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::CallReject { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::Join { name, version } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Joined {
+                                                    name,
+                                                    addr: _addr,
+                                                    version,
+                                                })
+                                                .await;
+                                        }
+                                        Message::Leave { name } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Left { name, addr: _addr })
+                                                .await;
+                                        }
+                                        Message::Ping { seq } => {
+                                            // Respond with pong
+                                            let pong = Message::Pong { seq };
+                                            let _ = socket.send_to(&pong.to_bytes(), _addr).await;
+                                        }
+                                        Message::Pong { seq } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Pong {
+                                                    addr: _addr,
+                                                    seq,
+                                                    received: std::time::Instant::now(),
+                                                })
+                                                .await;
+                                        }
+                                        Message::HistoryRequest { .. } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::HistoryRequested { addr: _addr })
+                                                .await;
+                                        }
+                                        Message::HistoryChunk { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::Topic(_)
+                                        | Message::Pin(_)
+                                        | Message::Todo { .. }
+                                        | Message::TimerStart { .. }
+                                        | Message::TimerStop { .. }
+                                        | Message::Ring { .. }
+                                        | Message::RingReply { .. }
+                                        | Message::ChatEdit { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::VideoReport { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::WhoisRequest { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::WhoisReply(_) => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::Nick { new, .. } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Renamed { addr: _addr, new })
+                                                .await;
+                                        }
+                                        Message::NickInUse { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::FileOffer { .. }
+                                        | Message::FileRequest { .. }
+                                        | Message::FileChunk { .. }
+                                        | Message::FileDone { .. }
+                                        | Message::FileCancel { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::ChatAck { .. }
+                                        | Message::TunesList { .. }
+                                        | Message::TunesWant { .. }
+                                        | Message::WindowChat { .. }
+                                        | Message::ImageShare { .. } => {
+                                            let _ = net_tx.send(msg).await;
+                                        }
+                                        Message::Keepalive { from } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Alive {
+                                                    name: from,
+                                                    addr: _addr,
+                                                })
+                                                .await;
+                                        }
+                                        Message::ClockSync { from, unix_ms } => {
+                                            let _ = peer_event_tx
+                                                .send(PeerEvent::Clock {
+                                                    name: from,
+                                                    addr: _addr,
+                                                    unix_ms,
+                                                })
+                                                .await;
+                                        }
+                                        Message::DiscoveryAnnounce { name, port, .. } => {
+                                            // Discovery announce received on main port (bypasses SO_REUSEPORT)
+                                            // Forward to discovery channel as if we received it normally
+                                            let peer_addr = network::with_port(_addr, port);
+                                            let peer = DiscoveredPeer {
+                                                name,
+                                                addr: peer_addr,
+                                            };
+                                            let _ = discovery_tx.send(peer).await;
+                                        }
+                                    }
+                                } else if let Some(protocol) = network::frame_version(data)
+                                    && protocol != network::PROTOCOL_VERSION
+                                    && warned.insert((_addr, protocol))
+                                {
+                                    // Another release's wire format: say so once per sender
+                                    let _ = peer_event_tx
+                                        .send(PeerEvent::Incompatible {
+                                            addr: _addr,
+                                            protocol,
+                                        })
+                                        .await;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Network receive error: {}", e);
+                            // Avoid spinning on error
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                    }
+                }
+                Err(_) => {
+                    // Timeout, check running flag and continue
+                }
+            }
+        }
+    })
+}
+
+/// Start LAN discovery, forwarding discovered peers to `discovery_tx`
+async fn start_discovery(
+    config: &Config,
+    discovery_tx: mpsc::Sender<DiscoveredPeer>,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+    heartbeat: Heartbeat,
+) -> (Arc<Discovery>, tokio::task::JoinHandle<()>) {
+    // Set up peer discovery
+    status!("Starting LAN discovery... ");
+    // Parse bind_ip for discovery - determines which interface to broadcast on
+    let discovery_bind_ip: std::net::Ipv4Addr = config
+        .network
+        .bind_ip
+        .as_ref()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(std::net::Ipv4Addr::UNSPECIFIED);
+
+    let settings = DiscoverySettings {
+        port: config.network.discovery_port,
+        interval: Duration::from_secs(config.network.discovery_interval),
+        broadcast: config
+            .network
+            .broadcast_address
+            .as_ref()
+            .and_then(|s| s.parse().ok()),
+    };
+
+    let discovery = match Discovery::new(
+        config.network.name.clone(),
+        config.network.port,
+        discovery_bind_ip,
+        settings.clone(),
+    )
+    .await
+    {
+        Ok(d) => {
+            println!("OK");
+            Arc::new(d)
+        }
+        Err(e) => {
+            println!("FAILED");
+            eprintln!("  {} (continuing without LAN discovery)", e);
+            // Continue without discovery - we can still connect to manual peers
+            Arc::new(
+                Discovery::new(config.network.name.clone(), 0, discovery_bind_ip, settings)
+                    .await
+                    .unwrap(),
+            )
+        }
+    };
+
+    // Spawn discovery task
+    let task = tokio::spawn(run_discovery(
+        Arc::clone(&discovery),
+        discovery_tx,
+        shutdown_rx,
+        heartbeat,
+    ));
+    (discovery, task)
+}
+
+/// Whether a terminal `mode` takes a DRCS soft font
+/// The AI conversation `/chat` starts in
+pub const DEFAULT_AI_SESSION: &str = "main";
+
+/// Longest name `/chat new` takes
+const MAX_AI_SESSION_NAME: usize = 16;
+
+/// A named AI conversation that isn't on the AI tab just now
+pub struct AiSession {
+    chat: AiChat,
+    buffer: ChatBuffer,
+    /// Where it's saved, to carry on saving there
+    conversation: Option<String>,
+}
+
+/// A saved conversation's messages, as the AI tab shows them (rendering the
+/// replies' markdown if `markdown`)
+fn show_conversation(
+    buffer: &mut ChatBuffer,
+    name: &str,
+    conversation: &Conversation,
+    markdown: bool,
+) {
+    for message in &conversation.messages {
+        match message.role {
+            MessageRole::User => buffer.push(format!("{}: {}", name, message.content)),
+            MessageRole::Assistant => {
+                let content = if markdown && !conversation.verbatim {
+                    Markdown::render(&message.content)
+                } else {
+                    message.content.clone()
+                };
+                for line in content.lines() {
+                    buffer.push(format!("  {}", line));
+                }
+            }
+        }
+    }
+}
+
+/// Explain why a peer speaking `protocol` can't be understood
+fn describe_incompatible(protocol: u8) -> String {
+    if protocol > network::PROTOCOL_VERSION {
+        format!(
+            "A newer Wormhole (protocol {}, you have {}) is trying to talk to us - upgrade to chat with it",
+            protocol,
+            network::PROTOCOL_VERSION
+        )
+    } else {
+        format!(
+            "An older Wormhole (protocol {}, you have {}) is trying to talk to us - it needs upgrading to chat",
+            protocol,
+            network::PROTOCOL_VERSION
+        )
+    }
+}
+
+/// Describe a peer clock offset, e.g. "5m ahead" or "1h 2m behind"
+pub fn describe_clock_offset(offset_secs: i64) -> String {
+    let secs = offset_secs.unsigned_abs();
+    let amount = if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    };
+    let direction = if offset_secs >= 0 { "ahead" } else { "behind" };
+    format!("{} {}", amount, direction)
+}
+
+fn has_drcs(mode: &str) -> bool {
+    matches!(mode, "vt220" | "vt340" | "vt525")
+}
+
+/// Ask the terminal for its Primary Device Attributes (DA1) and return the
+/// reply as it came, in 7-bit or 8-bit form. Returns None if the terminal
+/// doesn't answer within half a second.
+fn query_device_attributes(serial: &mut Serial) -> Option<Vec<u8>> {
+    let _ = serial.clear_input();
+    serial.write_str("\x1b[c").ok()?;
+
+    let deadline = std::time::Instant::now() + Duration::from_millis(500);
+    let mut response = Vec::new();
+    let mut buf = [0u8; 64];
+    while std::time::Instant::now() < deadline {
+        let n = serial.read(&mut buf).ok()?;
+        if n == 0 {
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        response.extend_from_slice(&buf[..n]);
+        if response.contains(&b'c') {
+            return Some(response);
+        }
     }
+    None
 }
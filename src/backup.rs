@@ -0,0 +1,384 @@
+//! `wormhole backup` / `wormhole restore`: a node's state in one tarball.
+//!
+//! The archive holds what a node keeps between runs: the config file (with
+//! its `[contacts]`), the peer roster, the shared to-do list, the chat and AI
+//! logs, the saved AI conversations and the input history, so a node can move
+//! to a new SD card in one step. It's a plain
+//! ustar file, so `tar tf` can look inside. Received files and tunes stay
+//! where they are; they're data rather than state, and usually too big.
+//!
+//! Restore puts the config where `--config` points, then everything else
+//! where that config says it goes. Nothing is overwritten without `--force`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ai::store;
+use crate::config::{Config, ConfigError};
+use crate::recall;
+
+/// Names in the archive
+const CONFIG_NAME: &str = "wormhole.ini";
+const ROSTER_NAME: &str = "peers.json";
+const TODO_NAME: &str = "todo.json";
+const LOG_DIR: &str = "logs/";
+/// Saved AI conversations, under [`LOG_DIR`]
+const CONVERSATIONS_DIR: &str = "ai-conversations/";
+
+const BLOCK: usize = 512;
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Config(ConfigError),
+    /// Not a backup, or a damaged one
+    Corrupt(String),
+    /// Restoring would overwrite this (without `--force`)
+    Exists(PathBuf),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Io { path, source } => {
+                write!(f, "'{}': {}", path.display(), source)
+            }
+            BackupError::Config(e) => write!(f, "{}", e),
+            BackupError::Corrupt(reason) => write!(f, "not a usable backup: {}", reason),
+            BackupError::Exists(path) => write!(
+                f,
+                "'{}' already exists (use --force to overwrite it)",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackupError::Io { source, .. } => Some(source),
+            BackupError::Config(e) => Some(e),
+            BackupError::Corrupt(_) => None,
+            BackupError::Exists(_) => None,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, BackupError> {
+    std::fs::read(path).map_err(|source| BackupError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Read a state file that may not have been written yet
+fn read_if_exists(path: &str) -> Result<Option<Vec<u8>>, BackupError> {
+    if path.is_empty() || !Path::new(path).exists() {
+        return Ok(None);
+    }
+    read(Path::new(path)).map(Some)
+}
+
+/// Write the node's state, as `config` describes it, to `archive`. Returns
+/// the names stored.
+pub fn backup(config: &Path, archive: &Path) -> Result<Vec<String>, BackupError> {
+    let contents = read(config)?;
+    let text = String::from_utf8_lossy(&contents);
+    let parsed = Config::parse(&text, config).map_err(BackupError::Config)?;
+
+    let mut files = vec![(CONFIG_NAME.to_string(), contents)];
+    if let Some(roster) = read_if_exists(&parsed.network.roster)? {
+        files.push((ROSTER_NAME.to_string(), roster));
+    }
+    if let Some(todo) = read_if_exists(&parsed.network.todo)? {
+        files.push((TODO_NAME.to_string(), todo));
+    }
+    if let Some(dir) = &parsed.logging.directory {
+        let dir = Path::new(dir);
+        for name in names_in(dir, is_log_name) {
+            let data = read(&dir.join(&name))?;
+            files.push((format!("{}{}", LOG_DIR, name), data));
+        }
+        let conversations = dir.join(store::DIR_NAME);
+        for name in names_in(&conversations, is_conversation_name) {
+            let data = read(&conversations.join(&name))?;
+            files.push((format!("{}{}{}", LOG_DIR, CONVERSATIONS_DIR, name), data));
+        }
+    }
+
+    let names = files.iter().map(|(name, _)| name.clone()).collect();
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    std::fs::write(archive, write_tar(&files, mtime)).map_err(|source| BackupError::Io {
+        path: archive.to_path_buf(),
+        source,
+    })?;
+    Ok(names)
+}
+
+/// The files in `dir` whose names pass `keep`, sorted
+fn names_in(dir: &Path, keep: fn(&str) -> bool) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| keep(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Put the state in `archive` back: the config at `config`, the rest where it
+/// says. Returns the files written.
+pub fn restore(config: &Path, archive: &Path, force: bool) -> Result<Vec<PathBuf>, BackupError> {
+    let files = read_tar(&read(archive)?).map_err(BackupError::Corrupt)?;
+    let contents = files
+        .iter()
+        .find(|(name, _)| name == CONFIG_NAME)
+        .map(|(_, data)| data)
+        .ok_or_else(|| BackupError::Corrupt(format!("no {} in it", CONFIG_NAME)))?;
+    let text = String::from_utf8_lossy(contents);
+    let parsed = Config::parse(&text, Path::new(CONFIG_NAME)).map_err(BackupError::Config)?;
+
+    // Where each file goes; anything this config has no place for is skipped
+    let mut targets = Vec::new();
+    for (name, data) in &files {
+        let target = if name == CONFIG_NAME {
+            Some(config.to_path_buf())
+        } else if name == ROSTER_NAME {
+            Some(PathBuf::from(&parsed.network.roster)).filter(|p| !p.as_os_str().is_empty())
+        } else if name == TODO_NAME {
+            Some(PathBuf::from(&parsed.network.todo)).filter(|p| !p.as_os_str().is_empty())
+        } else if let Some(log) = name.strip_prefix(LOG_DIR)
+            && is_log_name(log)
+        {
+            parsed
+                .logging
+                .directory
+                .as_ref()
+                .map(|dir| Path::new(dir).join(log))
+        } else if let Some(conversation) = name
+            .strip_prefix(LOG_DIR)
+            .and_then(|name| name.strip_prefix(CONVERSATIONS_DIR))
+            && is_conversation_name(conversation)
+        {
+            parsed
+                .logging
+                .directory
+                .as_ref()
+                .map(|dir| Path::new(dir).join(store::DIR_NAME).join(conversation))
+        } else {
+            None
+        };
+        if let Some(target) = target {
+            targets.push((target, data));
+        }
+    }
+
+    // Check before writing anything, so a refused restore leaves no trace
+    if !force && let Some((path, _)) = targets.iter().find(|(path, _)| path.exists()) {
+        return Err(BackupError::Exists(path.clone()));
+    }
+    for (path, data) in &targets {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|source| BackupError::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+        std::fs::write(path, data).map_err(|source| BackupError::Io {
+            path: path.clone(),
+            source,
+        })?;
+    }
+    Ok(targets.into_iter().map(|(path, _)| path).collect())
+}
+
+/// `chat-20250101.log`, `ai-20250101.log` or the input history: a plain
+/// name, never a path, and short enough for a tar header
+fn is_log_name(name: &str) -> bool {
+    if name == recall::FILE_NAME {
+        return true;
+    }
+    (name.starts_with("chat-") || name.starts_with("ai-"))
+        && name.len() <= 64
+        && name.ends_with(".log")
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+/// `20250101-120000.json`, a saved AI conversation: a plain name, never a
+/// path, and short enough for a tar header
+fn is_conversation_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.ends_with(".json")
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+/// A ustar archive of regular files
+fn write_tar(files: &[(String, Vec<u8>)], mtime: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is summed with its own field as spaces
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        octal(&mut header[148..155], sum as u64);
+        out.extend_from_slice(&header);
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(BLOCK), 0);
+    }
+    // Two empty blocks end the archive
+    out.resize(out.len() + 2 * BLOCK, 0);
+    out
+}
+
+/// Write `value` in octal, zero-padded and NUL-terminated, filling `field`
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+/// The regular files in a ustar archive
+fn read_tar(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = Vec::new();
+    let mut at = 0;
+    while at + BLOCK <= data.len() {
+        let header = &data[at..at + BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            return Ok(files);
+        }
+        let expected = parse_octal(&header[148..156]).ok_or("bad header checksum")?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        if sum != expected {
+            return Err("damaged header".to_string());
+        }
+        let name_len = header[..100].iter().position(|&b| b == 0).unwrap_or(100);
+        let name = String::from_utf8_lossy(&header[..name_len]).into_owned();
+        let size = parse_octal(&header[124..136]).ok_or("bad file size")? as usize;
+        let start = at + BLOCK;
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| format!("{} is cut short", name))?;
+        if matches!(header[156], b'0' | 0) {
+            files.push((name, data[start..end].to_vec()));
+        }
+        at = start + size.next_multiple_of(BLOCK);
+    }
+    Err("no end of archive marker".to_string())
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    u64::from_str_radix(text, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_round_trip() {
+        let files = vec![
+            (CONFIG_NAME.to_string(), b"[network]\nname = Pi\n".to_vec()),
+            ("logs/chat-20260301.log".to_string(), vec![b'x'; BLOCK + 1]),
+            (TODO_NAME.to_string(), Vec::new()),
+        ];
+        let archive = write_tar(&files, 1_700_000_000);
+        assert_eq!(archive.len() % BLOCK, 0);
+        assert_eq!(read_tar(&archive), Ok(files));
+
+        let mut damaged = archive.clone();
+        damaged[0] ^= 1;
+        assert!(read_tar(&damaged).is_err());
+        assert!(read_tar(&archive[..BLOCK * 2]).is_err());
+
+        assert!(is_log_name("ai-20260301.log"));
+        assert!(is_log_name("input-history.json"));
+        assert!(!is_log_name("chat-../../etc/passwd.log"));
+        assert!(!is_log_name("wormhole-protocol-1.log"));
+        assert!(is_conversation_name("20260301-120000-2.json"));
+        assert!(!is_conversation_name("../todo.json"));
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let old = tempfile::tempdir().unwrap();
+        let config = format!(
+            "[serial]\nport = /dev/ttyUSB0\nbaud = 9600\n\n\
+             [network]\nname = Pi\nport = 9999\ntodo = {0}/todo.json\nroster =\n\n\
+             [logging]\ndirectory = {0}/logs\n",
+            old.path().display()
+        );
+        std::fs::write(old.path().join("wormhole.ini"), config).unwrap();
+        std::fs::write(old.path().join("todo.json"), "[]").unwrap();
+        std::fs::create_dir(old.path().join("logs")).unwrap();
+        let log = old.path().join("logs/chat-20260301.log");
+        std::fs::write(&log, "hi\n").unwrap();
+        let history = old.path().join("logs/input-history.json");
+        std::fs::write(&history, r#"{"chat":["/who"]}"#).unwrap();
+        std::fs::create_dir(old.path().join("logs/ai-conversations")).unwrap();
+        let conversation = old
+            .path()
+            .join("logs/ai-conversations/20260301-120000.json");
+        std::fs::write(&conversation, "{}").unwrap();
+
+        let archive = old.path().join("backup.tar");
+        let names = backup(&old.path().join("wormhole.ini"), &archive).unwrap();
+        assert_eq!(
+            names,
+            vec![
+                CONFIG_NAME,
+                TODO_NAME,
+                "logs/chat-20260301.log",
+                "logs/input-history.json",
+                "logs/ai-conversations/20260301-120000.json"
+            ]
+        );
+        std::fs::remove_dir_all(old.path().join("logs")).unwrap();
+
+        // The config goes where asked, the rest where it says
+        let new = tempfile::tempdir().unwrap();
+        let config = new.path().join("wormhole.ini");
+        std::fs::write(&config, "").unwrap();
+        assert!(matches!(
+            restore(&config, &archive, false),
+            Err(BackupError::Exists(_))
+        ));
+        assert!(!log.exists(), "a refused restore writes nothing");
+        let written = restore(&config, &archive, true).unwrap();
+        assert_eq!(written.len(), 5);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "hi\n");
+        assert!(history.exists());
+        assert_eq!(std::fs::read_to_string(&conversation).unwrap(), "{}");
+        assert!(
+            std::fs::read_to_string(&config)
+                .unwrap()
+                .contains("name = Pi")
+        );
+    }
+}
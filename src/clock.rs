@@ -0,0 +1,135 @@
+//! Detection of wall-clock steps and suspend/resume.
+//!
+//! All timeouts use monotonic [`Instant`]s, so NTP adjustments can't expire
+//! peers or calls. The monotonic clock has its own blind spots though: on most
+//! platforms it stops while the machine is suspended, and a stalled main loop
+//! makes everything look timed out at once. [`ClockMonitor`] compares the two
+//! clocks on every tick so the app can tell which of these just happened.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// A main loop gap this long means we were stopped or suspended
+const STALL_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// Wall clock drifting from monotonic time by more than this is a clock step
+const STEP_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Forward wall-clock jumps longer than this are treated as a resume
+const RESUME_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Something happened to the clocks since the last check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockEvent {
+    /// The process was suspended or stalled for about this long
+    Resumed(Duration),
+    /// The wall clock was stepped by this many seconds (negative = backwards)
+    WallClockStep(i64),
+}
+
+/// Watches for disagreements between the monotonic and wall clocks
+pub struct ClockMonitor {
+    last_mono: Instant,
+    last_wall: SystemTime,
+}
+
+impl ClockMonitor {
+    pub fn new() -> Self {
+        Self {
+            last_mono: Instant::now(),
+            last_wall: SystemTime::now(),
+        }
+    }
+
+    /// Compare the clocks against the previous check
+    pub fn check(&mut self) -> Option<ClockEvent> {
+        let now_mono = Instant::now();
+        let now_wall = SystemTime::now();
+        let mono = now_mono.duration_since(self.last_mono);
+        let wall_ms = match now_wall.duration_since(self.last_wall) {
+            Ok(d) => d.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        self.last_mono = now_mono;
+        self.last_wall = now_wall;
+        classify(mono, wall_ms)
+    }
+
+    /// Start comparing from now, after work known to hold up the main loop
+    /// (a file transfer, an AI reply), so the gap isn't taken for a suspend
+    pub fn reset(&mut self) {
+        self.last_mono = Instant::now();
+        self.last_wall = SystemTime::now();
+    }
+}
+
+impl Default for ClockMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Classify the time that passed on each clock between two checks
+fn classify(mono: Duration, wall_ms: i64) -> Option<ClockEvent> {
+    if mono >= STALL_THRESHOLD {
+        return Some(ClockEvent::Resumed(mono));
+    }
+
+    // Time the wall clock gained (or lost) beyond the monotonic clock
+    let drift_ms = wall_ms - mono.as_millis() as i64;
+    if drift_ms.unsigned_abs() <= STEP_THRESHOLD.as_millis() as u64 {
+        None
+    } else if drift_ms > RESUME_THRESHOLD.as_millis() as i64 {
+        // The monotonic clock stood still while the machine slept
+        Some(ClockEvent::Resumed(Duration::from_millis(drift_ms as u64)))
+    } else {
+        Some(ClockEvent::WallClockStep(drift_ms / 1000))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal_tick() {
+        assert_eq!(classify(Duration::from_millis(5), 5), None);
+        // Small NTP slews are ignored
+        assert_eq!(classify(Duration::from_millis(5), 2000), None);
+    }
+
+    #[test]
+    fn test_wall_clock_steps() {
+        assert_eq!(
+            classify(Duration::from_millis(1), 30_001),
+            Some(ClockEvent::WallClockStep(30))
+        );
+        assert_eq!(
+            classify(Duration::from_millis(1), -3_600_000),
+            Some(ClockEvent::WallClockStep(-3600))
+        );
+    }
+
+    #[test]
+    fn test_resume() {
+        // Monotonic clock paused during suspend
+        assert_eq!(
+            classify(Duration::from_millis(1), 600_001),
+            Some(ClockEvent::Resumed(Duration::from_secs(600)))
+        );
+        // Monotonic clock kept counting (or the loop stalled)
+        assert_eq!(
+            classify(Duration::from_secs(120), 120_000),
+            Some(ClockEvent::Resumed(Duration::from_secs(120)))
+        );
+    }
+
+    #[test]
+    fn test_reset_forgets_blocking_work() {
+        let mut monitor = ClockMonitor {
+            last_mono: Instant::now() - Duration::from_secs(30),
+            last_wall: SystemTime::now() - Duration::from_secs(30),
+        };
+        monitor.reset();
+        assert_eq!(monitor.check(), None);
+    }
+}
@@ -1,31 +1,132 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-#[derive(Debug, Deserialize)]
+use crate::ai::{self, PROVIDER_NAMES, Params, Usage};
+use crate::ambient::parse_quiet_hours;
+use crate::graphics::{DITHER_NAMES, Dither, RENDERER_NAMES};
+use crate::input::{KEYBOARDS, KeyMap};
+use crate::keys::Bindings;
+use crate::macros::Macros;
+use crate::mouse::{MOUSE_NAMES, Mouse};
+use crate::network::{DEFAULT_ANNOUNCE_INTERVAL, DEFAULT_DISCOVERY_PORT, DEFAULT_KEEPALIVE_SECS};
+use crate::notify::parse_alert;
+use crate::recall::DEFAULT_INPUT_HISTORY;
+use crate::screensaver::SCREENSAVER_STYLES;
+use crate::terminal::THEME_NAMES;
+use crate::terminal::controls::CONTROL_MODES;
+use crate::terminal::layout::{DEFAULT_LAYOUT, Layout, MAX_INPUT_ROWS, MIN_INPUT_ROWS};
+use crate::terminal::stamp::TIMESTAMP_FORMATS;
+use crate::webcam::CaptureSettings;
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub serial: SerialConfig,
     pub network: NetworkConfig,
     #[serde(default)]
     pub webcam: WebcamConfig,
-    #[serde(default)]
-    pub gemini: GeminiConfig,
+    #[serde(default, alias = "gemini")]
+    pub ai: AiConfig,
     #[serde(default)]
     pub terminal: TerminalConfig,
     #[serde(default)]
     pub logging: LogConfig,
     #[serde(default)]
     pub tunes: TunesConfig,
+    #[serde(default)]
+    pub files: FilesConfig,
+    /// Per-peer notifications: peer name = bell pattern, sound:<file> or none
+    #[serde(default)]
+    pub contacts: HashMap<String, String>,
+    /// Screen layouts: name = rows=48 input_rows=4 status_bar=true (one named
+    /// after the terminal mode is used for that mode)
+    #[serde(default)]
+    pub layouts: HashMap<String, String>,
+    /// Key translations on top of the `keyboard` table: byte received =
+    /// what it stands for (`0x5b = Ä`, `0x1c = 0x0d`)
+    #[serde(default)]
+    pub keyboard: HashMap<String, String>,
+    /// Key bindings: action = its keys (`next_tab = ^N, f2`)
+    #[serde(default)]
+    pub keys: HashMap<String, String>,
+    /// Abbreviations (`;;brb = be right back`) and function keys' canned
+    /// messages (`f5 = Back in five`)
+    #[serde(default)]
+    pub macros: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TerminalConfig {
-    /// Terminal emulation mode: "vt100", "vt220", or "vt340"
+    /// Terminal emulation mode: "vt100", "vt220", "vt340", "vt525" or "ansi"
+    /// (colour), or "xterm" (modern emulator)
     pub mode: String,
 
     /// Enable 132 column mode (false if unset)
     #[serde(rename = "132_cols", default, deserialize_with = "deserialize_bool")]
     pub cols_132: bool,
+
+    /// Image render backend: "auto" (detect from the terminal), "ascii", "drcs",
+    /// "sixel", "regis", or "ansi"
+    #[serde(default = "default_renderer")]
+    pub renderer: String,
+
+    /// UI theme: "dec" (line-drawing borders), "ascii" (ASCII borders), or
+    /// "plain" (ASCII borders, no reverse video or key hints)
+    #[serde(default = "default_theme")]
+    pub theme: String,
+
+    /// Rows in the input area, 2 to 5 (2 if unset; Ctrl+Up/Down changes it)
+    #[serde(default = "default_input_rows")]
+    pub input_rows: usize,
+
+    /// Timestamps on chat lines: "12h" (03:45PM), "24h" (15:45),
+    /// "12h-seconds", "24h-seconds", or "none"
+    #[serde(default = "default_timestamp_format")]
+    pub timestamp_format: String,
+
+    /// Screen layout: one under `[layouts]` or built in (standard, pc, tall,
+    /// bare). If unset, the one under `[layouts]` named after the mode, or
+    /// else standard.
+    #[serde(default)]
+    pub layout: Option<String>,
+
+    /// Minutes without a key from the terminal before the screen is blanked
+    /// to save a CRT from burn-in (0 = never, the default)
+    #[serde(default)]
+    pub screensaver: u64,
+
+    /// What the blanked screen shows: "blank", "clock" or "logo" (both drift
+    /// round the screen)
+    #[serde(default = "default_screensaver_style")]
+    pub screensaver_style: String,
+
+    /// Control sequences as "7bit" (ESC [), "8bit" (C1 bytes, for VT220s set
+    /// up for 8-bit controls) or "auto" (as the terminal's Device Attributes
+    /// reply comes back)
+    #[serde(default = "default_controls")]
+    pub controls: String,
+
+    /// Built-in table translating what the keyboard sends: "us" (as sent),
+    /// "uk", "german", "french" or "swedish" (national replacement
+    /// characters), or "multinational" (8-bit DEC Multinational)
+    #[serde(default = "default_keyboard")]
+    pub keyboard: String,
+
+    /// Lines kept in each tab's input history, and saved with a `[logging]`
+    /// directory (500 if unset)
+    #[serde(default = "default_input_history")]
+    pub input_history: usize,
+
+    /// Click reports to ask for: "off", "xterm" (mouse tracking in modern
+    /// emulators) or "locator" (a VT340's DEC locator)
+    #[serde(default = "default_mouse")]
+    pub mouse: String,
+
+    /// A soft font file to load instead of the built-in DRCS shades (VT220,
+    /// VT340, VT525)
+    #[serde(default)]
+    pub drcs_font: Option<String>,
 }
 
 impl Default for TerminalConfig {
@@ -33,26 +134,105 @@ impl Default for TerminalConfig {
         Self {
             mode: "vt100".to_string(),
             cols_132: false,
+            renderer: default_renderer(),
+            theme: default_theme(),
+            input_rows: default_input_rows(),
+            timestamp_format: default_timestamp_format(),
+            layout: None,
+            screensaver: 0,
+            screensaver_style: default_screensaver_style(),
+            controls: default_controls(),
+            keyboard: default_keyboard(),
+            input_history: default_input_history(),
+            mouse: default_mouse(),
+            drcs_font: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+fn default_renderer() -> String {
+    "auto".to_string()
+}
+
+fn default_theme() -> String {
+    "dec".to_string()
+}
+
+fn default_input_rows() -> usize {
+    MIN_INPUT_ROWS
+}
+
+fn default_controls() -> String {
+    "auto".to_string()
+}
+
+fn default_keyboard() -> String {
+    "us".to_string()
+}
+
+fn default_mouse() -> String {
+    "off".to_string()
+}
+
+fn default_input_history() -> usize {
+    DEFAULT_INPUT_HISTORY
+}
+
+fn default_screensaver_style() -> String {
+    "clock".to_string()
+}
+
+fn default_timestamp_format() -> String {
+    "12h".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct LogConfig {
     /// Directory to write log files to (optional, logging disabled if not set)
     #[serde(default)]
     pub directory: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TunesConfig {
     /// Directory containing audio/tune files to list
     /// If not set, Tunes tab is disabled
     #[serde(default)]
     pub directory: Option<String>,
+
+    /// Take part in `/tunes sync`: list our tracks to peers that ask and
+    /// fetch theirs (needs a `[files]` directory)
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub sync: bool,
+
+    /// Most KB per second spent sending tracks to a syncing peer (0 = no limit)
+    #[serde(default = "default_sync_rate")]
+    pub sync_rate: u32,
+}
+
+fn default_sync_rate() -> u32 {
+    64
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Default for TunesConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            sync: false,
+            sync_rate: default_sync_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct FilesConfig {
+    /// Directory received files are saved in, along with the state that lets
+    /// interrupted transfers resume. If not set, file transfers are disabled.
+    #[serde(default)]
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SerialConfig {
     /// Path to the serial port device (e.g., /dev/ttyUSB0)
     pub port: String,
@@ -62,14 +242,15 @@ pub struct SerialConfig {
     pub baud_rate: u32,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WebcamConfig {
     /// Path to the webcam device (e.g., /dev/video0)
     /// If not set, webcam feature is disabled
     #[serde(default)]
     pub device: Option<String>,
 
-    /// Target FPS for video streaming
+    /// Frames a second captured and sent (0 for as many as the camera
+    /// gives); drawing slows down further when the serial link lags
     #[serde(default = "default_fps")]
     pub fps: u32,
 
@@ -77,28 +258,223 @@ pub struct WebcamConfig {
     /// Range: 2-64, default: 8
     #[serde(default = "default_sixel_shades")]
     pub sixel_shades: u8,
+
+    /// Lower the frame rate and resolution we send when the network is congested
+    #[serde(default = "default_true", deserialize_with = "deserialize_bool")]
+    pub adaptive: bool,
+
+    /// Dithering for the video we show: "none", "floyd-steinberg" or "bayer"
+    #[serde(default = "default_dither")]
+    pub dither: String,
+
+    /// Post a line and a snapshot in chat when the idle webcam sees movement
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub motion: bool,
+
+    /// Capture size to ask the camera for, e.g. "1280x720"
+    #[serde(default = "default_resolution")]
+    pub resolution: String,
+
+    /// What's done with a picture wider than the screen: "fill" or "fit"
+    #[serde(default = "default_aspect")]
+    pub aspect: String,
+
+    /// The part of the picture to keep, "left,top,width,height" in percent
+    #[serde(default)]
+    pub crop: Option<String>,
+}
+
+fn default_dither() -> String {
+    "none".to_string()
+}
+
+fn default_resolution() -> String {
+    "1280x720".to_string()
+}
+
+fn default_aspect() -> String {
+    "fill".to_string()
+}
+
+impl Default for WebcamConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            fps: default_fps(),
+            sixel_shades: default_sixel_shades(),
+            adaptive: true,
+            dither: default_dither(),
+            motion: false,
+            resolution: default_resolution(),
+            aspect: default_aspect(),
+            crop: None,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
-pub struct GeminiConfig {
-    /// Google Gemini API key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AiConfig {
+    /// Who answers: gemini, openai, anthropic or ollama
+    #[serde(default = "default_provider")]
+    pub provider: String,
+
+    /// API key for the provider (Ollama needs none)
     #[serde(default)]
     pub api_key: Option<String>,
 
-    /// Model to use (e.g., "gemini-2.5-flash", "gemini-2.5-pro")
-    #[serde(default = "default_gemini_model")]
-    pub model: String,
+    /// Model to use (e.g., "gemini-2.5-pro", "gpt-4o"; the provider's
+    /// usual one if unset)
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// Where the provider's API lives, for OpenAI-compatible servers or
+    /// an Ollama on another machine
+    #[serde(default)]
+    pub base_url: Option<String>,
 
     /// System prompt for the AI assistant
     #[serde(default)]
     pub system_prompt: Option<String>,
+
+    /// Randomness of replies, 0 to 2 (the model's default if unset)
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling cut-off, 0 to 1 (the model's default if unset)
+    #[serde(default)]
+    pub top_p: Option<f32>,
+
+    /// Longest reply, in tokens (the provider's default if unset)
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+
+    /// Gemini's safety filters: none, high, medium or low (what gets
+    /// blocked; Google's default if unset)
+    #[serde(default)]
+    pub safety: Option<String>,
+
+    /// Draw markdown in replies as bold, reverse video, indented code and
+    /// bullets (true if unset)
+    #[serde(default = "default_true", deserialize_with = "deserialize_bool")]
+    pub markdown: bool,
+
+    /// About how many tokens the model can take at once; older turns are
+    /// dropped to stay under it
+    #[serde(default = "default_context_tokens")]
+    pub context_tokens: u32,
+
+    /// Have the model summarize the turns dropped to fit, rather than
+    /// forget them (false if unset)
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub summarize: bool,
+
+    /// Dollars per million prompt tokens, to show what the AI has cost (only
+    /// tokens are shown if neither price is set)
+    #[serde(default)]
+    pub input_price: Option<f64>,
+
+    /// Dollars per million reply tokens
+    #[serde(default)]
+    pub output_price: Option<f64>,
+
+    /// Show an AI quip or doodle in Chat (on this terminal only) after a
+    /// quiet spell (false if unset)
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub ambient: bool,
+
+    /// Minutes without typing or chat before the quip
+    #[serde(default = "default_ambient_idle")]
+    pub ambient_idle: u64,
+
+    /// Local hours with no quips, e.g. "22-7" (none if unset)
+    #[serde(default)]
+    pub quiet_hours: String,
+
+    /// Most tokens the quips may use in a day
+    #[serde(default = "default_ambient_tokens")]
+    pub ambient_tokens: u32,
 }
 
-fn default_gemini_model() -> String {
-    "gemini-2.5-flash".to_string()
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            provider: default_provider(),
+            api_key: None,
+            model: None,
+            base_url: None,
+            system_prompt: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            safety: None,
+            markdown: true,
+            context_tokens: default_context_tokens(),
+            summarize: false,
+            input_price: None,
+            output_price: None,
+            ambient: false,
+            ambient_idle: default_ambient_idle(),
+            quiet_hours: String::new(),
+            ambient_tokens: default_ambient_tokens(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+fn default_provider() -> String {
+    "gemini".to_string()
+}
+
+impl AiConfig {
+    /// The model to ask for
+    pub fn model(&self) -> &str {
+        self.model
+            .as_deref()
+            .unwrap_or_else(|| ai::default_model(&self.provider))
+    }
+
+    /// How replies are to be generated
+    pub fn params(&self) -> Result<Params, String> {
+        let mut params = Params {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            safety: None,
+        };
+        params.check()?;
+        if let Some(ref safety) = self.safety {
+            params.set("safety", safety)?;
+        }
+        Ok(params)
+    }
+
+    /// What `usage` cost in dollars, if prices are set
+    pub fn cost(&self, usage: Usage) -> Option<f64> {
+        if self.input_price.is_none() && self.output_price.is_none() {
+            return None;
+        }
+        let price = |tokens: u32, per_million: Option<f64>| {
+            tokens as f64 * per_million.unwrap_or(0.0) / 1_000_000.0
+        };
+        Some(price(usage.input, self.input_price) + price(usage.output, self.output_price))
+    }
+}
+
+fn default_context_tokens() -> u32 {
+    32000
+}
+
+/// Smallest `context_tokens` that leaves room for a conversation
+const MIN_CONTEXT_TOKENS: u32 = 1000;
+
+fn default_ambient_idle() -> u64 {
+    30
+}
+
+fn default_ambient_tokens() -> u32 {
+    2000
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct NetworkConfig {
     /// Display name for this node (required)
     pub name: String,
@@ -118,6 +494,74 @@ pub struct NetworkConfig {
     /// Peer addresses to connect to on startup (comma-separated)
     #[serde(default)]
     pub peers: String,
+
+    /// Relay server (host:port) used when peers can't be reached directly
+    #[serde(default)]
+    pub relay: Option<String>,
+
+    /// Minutes of recent chat to send peers that join mid-conversation (0 = don't share)
+    #[serde(default)]
+    pub history_minutes: u32,
+
+    /// Self-description shown to peers by `/whois` (can be changed with `/profile`)
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    /// Mark our own chat lines with `*` until every peer has confirmed delivery
+    #[serde(default = "default_true", deserialize_with = "deserialize_bool")]
+    pub receipts: bool,
+
+    /// Find peers on the LAN by broadcast, multicast and mDNS (off for
+    /// point-to-point setups that only use configured peers)
+    #[serde(default = "default_true", deserialize_with = "deserialize_bool")]
+    pub discovery: bool,
+
+    /// UDP port for discovery announcements (every node on the LAN must agree)
+    #[serde(default = "default_discovery_port")]
+    pub discovery_port: u16,
+
+    /// Seconds between discovery announcements
+    #[serde(default = "default_discovery_interval")]
+    pub discovery_interval: u64,
+
+    /// Broadcast announcements to this IPv4 address instead of each interface's subnet
+    #[serde(default)]
+    pub broadcast_address: Option<String>,
+
+    /// Seconds between keepalives to each peer, holding NAT mappings open (0 = off)
+    #[serde(default = "default_keepalive")]
+    pub keepalive: u64,
+
+    /// File remembering known peers across restarts (empty = don't remember)
+    #[serde(default = "default_roster")]
+    pub roster: String,
+
+    /// File keeping the shared to-do list across restarts (empty = don't keep it)
+    #[serde(default = "default_todo")]
+    pub todo: String,
+
+    /// Who may ring our terminal's bell with `/ring`: "anyone", "contacts"
+    /// (peers under `[contacts]`), or "off"
+    #[serde(default = "default_ring")]
+    pub ring: String,
+
+    /// Pass peers' chat on to our other peers, so a partially connected mesh
+    /// still sees the whole conversation
+    #[serde(default = "default_true", deserialize_with = "deserialize_bool")]
+    pub mesh_relay: bool,
+
+    /// Most KiB per second of video and file data sent, shared fairly
+    /// between peers (0 = no limit)
+    #[serde(default)]
+    pub upload_limit: usize,
+
+    /// Kernel receive buffer for the peer socket in KiB (0 = OS default)
+    #[serde(default = "default_recv_buffer")]
+    pub recv_buffer: usize,
+
+    /// Kernel send buffer for the peer socket in KiB (0 = OS default)
+    #[serde(default = "default_send_buffer")]
+    pub send_buffer: usize,
 }
 
 /// Deserialize a boolean from string (for INI file compatibility)
@@ -146,6 +590,38 @@ fn default_port() -> u16 {
     7890
 }
 
+fn default_discovery_port() -> u16 {
+    DEFAULT_DISCOVERY_PORT
+}
+
+fn default_keepalive() -> u64 {
+    DEFAULT_KEEPALIVE_SECS
+}
+
+fn default_roster() -> String {
+    "wormhole-peers.json".to_string()
+}
+
+fn default_todo() -> String {
+    "wormhole-todo.json".to_string()
+}
+
+fn default_ring() -> String {
+    "contacts".to_string()
+}
+
+fn default_recv_buffer() -> usize {
+    1024
+}
+
+fn default_send_buffer() -> usize {
+    256
+}
+
+fn default_discovery_interval() -> u64 {
+    DEFAULT_ANNOUNCE_INTERVAL.as_secs()
+}
+
 fn default_true() -> bool {
     true
 }
@@ -165,9 +641,13 @@ impl Config {
             path: path.as_ref().to_path_buf(),
             source: e,
         })?;
+        Self::parse(&contents, path.as_ref())
+    }
 
-        let mut config: Self = serde_ini::from_str(&contents).map_err(|e| ConfigError::Parse {
-            path: path.as_ref().to_path_buf(),
+    /// Parse and validate a config file's contents (`path` is for errors)
+    pub fn parse(contents: &str, path: &Path) -> Result<Self, ConfigError> {
+        let mut config: Self = serde_ini::from_str(contents).map_err(|e| ConfigError::Parse {
+            path: path.to_path_buf(),
             source: e,
         })?;
 
@@ -180,17 +660,233 @@ impl Config {
         if config.terminal.mode != "vt100"
             && config.terminal.mode != "vt220"
             && config.terminal.mode != "vt340"
+            && config.terminal.mode != "vt525"
+            && config.terminal.mode != "ansi"
+            && config.terminal.mode != "xterm"
         {
             return Err(ConfigError::InvalidMode(config.terminal.mode));
         }
 
+        // Validate renderer backend
+        if !RENDERER_NAMES.contains(&config.terminal.renderer.as_str()) {
+            return Err(ConfigError::InvalidRenderer(config.terminal.renderer));
+        }
+
+        // Validate UI theme
+        if !THEME_NAMES.contains(&config.terminal.theme.as_str()) {
+            return Err(ConfigError::InvalidTheme(config.terminal.theme));
+        }
+
+        // Validate the input area's size
+        if !(MIN_INPUT_ROWS..=MAX_INPUT_ROWS).contains(&config.terminal.input_rows) {
+            return Err(ConfigError::InvalidInputRows(config.terminal.input_rows));
+        }
+
+        // Validate the timestamp format
+        if !TIMESTAMP_FORMATS.contains(&config.terminal.timestamp_format.as_str()) {
+            return Err(ConfigError::InvalidTimestampFormat(
+                config.terminal.timestamp_format,
+            ));
+        }
+
+        // Validate the AI provider
+        if !PROVIDER_NAMES.contains(&config.ai.provider.as_str()) {
+            return Err(ConfigError::InvalidProvider(config.ai.provider));
+        }
+
+        // Validate the generation parameters
+        if let Err(reason) = config.ai.params() {
+            return Err(ConfigError::InvalidAiParams(reason));
+        }
+        if [config.ai.input_price, config.ai.output_price]
+            .into_iter()
+            .flatten()
+            .any(|price| price.is_nan() || price < 0.0)
+        {
+            return Err(ConfigError::InvalidAiParams(
+                "input_price and output_price can't be negative".to_string(),
+            ));
+        }
+        if config.ai.context_tokens < MIN_CONTEXT_TOKENS {
+            return Err(ConfigError::InvalidAiParams(format!(
+                "context_tokens should be at least {}",
+                MIN_CONTEXT_TOKENS
+            )));
+        }
+
+        // Validate ambient AI settings
+        if parse_quiet_hours(&config.ai.quiet_hours).is_none() {
+            return Err(ConfigError::InvalidAmbient(format!(
+                "quiet_hours '{}' should look like 22-7",
+                config.ai.quiet_hours
+            )));
+        }
+        if config.ai.ambient && config.ai.ambient_idle == 0 {
+            return Err(ConfigError::InvalidAmbient(
+                "ambient_idle must be at least 1 minute".to_string(),
+            ));
+        }
+
+        if !matches!(config.network.ring.as_str(), "anyone" | "contacts" | "off") {
+            return Err(ConfigError::InvalidRing(config.network.ring));
+        }
+
+        // Validate contact notifications
+        for (name, alert) in &config.contacts {
+            if let Err(reason) = parse_alert(alert) {
+                return Err(ConfigError::InvalidContact(name.clone(), reason));
+            }
+        }
+
+        if !CONTROL_MODES.contains(&config.terminal.controls.as_str()) {
+            return Err(ConfigError::InvalidControls(config.terminal.controls));
+        }
+        // 8-bit controls clash with the terminals that take UTF-8 or only know 7 bits
+        if config.terminal.controls == "8bit"
+            && !matches!(config.terminal.mode.as_str(), "vt220" | "vt340" | "vt525")
+        {
+            return Err(ConfigError::InvalidControls(format!(
+                "8bit (in {} mode)",
+                config.terminal.mode
+            )));
+        }
+
+        if Dither::from_name(&config.webcam.dither).is_none() {
+            return Err(ConfigError::InvalidDither(config.webcam.dither));
+        }
+        if let Err(e) = CaptureSettings::parse(
+            &config.webcam.resolution,
+            &config.webcam.aspect,
+            config.webcam.crop.as_deref(),
+        ) {
+            return Err(ConfigError::InvalidCapture(e));
+        }
+        if Mouse::from_name(&config.terminal.mouse).is_none() {
+            return Err(ConfigError::InvalidMouse(config.terminal.mouse));
+        }
+        if !KEYBOARDS.contains(&config.terminal.keyboard.as_str()) {
+            return Err(ConfigError::InvalidKeyboard(config.terminal.keyboard));
+        }
+        for (byte, meaning) in &config.keyboard {
+            if let Err(e) = KeyMap::parse_entry(byte, meaning) {
+                return Err(ConfigError::InvalidKeyMapping(byte.clone(), e));
+            }
+        }
+        for (action, keys) in &config.keys {
+            if let Err(e) = Bindings::parse_entry(action, keys) {
+                return Err(ConfigError::InvalidKeyBinding(action.clone(), e));
+            }
+        }
+        for (name, text) in &config.macros {
+            if let Err(e) = Macros::check_entry(name, text) {
+                return Err(ConfigError::InvalidMacro(name.clone(), e));
+            }
+        }
+
+        if !SCREENSAVER_STYLES.contains(&config.terminal.screensaver_style.as_str()) {
+            return Err(ConfigError::InvalidScreensaver(
+                config.terminal.screensaver_style,
+            ));
+        }
+
+        // Validate layouts
+        for (name, layout) in &config.layouts {
+            if let Err(e) = layout.parse::<Layout>() {
+                return Err(ConfigError::InvalidLayout(name.clone(), e.to_string()));
+            }
+        }
+        if let Some(name) = &config.terminal.layout
+            && config.layout(name).is_none()
+        {
+            return Err(ConfigError::UnknownLayout(name.clone()));
+        }
+
         // Validate 132 columns mode (only allowed for vt220+)
         if config.terminal.mode == "vt100" && config.terminal.cols_132 {
             return Err(ConfigError::InvalidColumnsConfig);
         }
 
+        // Validate discovery settings
+        if config.network.discovery_port == config.network.port {
+            return Err(ConfigError::InvalidDiscovery(format!(
+                "discovery_port must differ from port ({})",
+                config.network.port
+            )));
+        }
+        if config.network.discovery_interval == 0 {
+            return Err(ConfigError::InvalidDiscovery(
+                "discovery_interval must be at least 1 second".to_string(),
+            ));
+        }
+        if let Some(addr) = &config.network.broadcast_address
+            && addr.parse::<std::net::Ipv4Addr>().is_err()
+        {
+            return Err(ConfigError::InvalidDiscovery(format!(
+                "broadcast_address '{}' is not an IPv4 address",
+                addr
+            )));
+        }
+
         Ok(config)
     }
+
+    /// A layout under `[layouts]` or built in, by name
+    pub fn layout(&self, name: &str) -> Option<Layout> {
+        match self.layouts.get(name) {
+            Some(layout) => layout.parse().ok(),
+            None => Layout::builtin(name),
+        }
+    }
+
+    /// How the video shown is dithered
+    pub fn dither(&self) -> Dither {
+        Dither::from_name(&self.webcam.dither).unwrap_or_default()
+    }
+
+    /// How webcam frames are captured
+    pub fn capture(&self) -> CaptureSettings {
+        CaptureSettings::parse(
+            &self.webcam.resolution,
+            &self.webcam.aspect,
+            self.webcam.crop.as_deref(),
+        )
+        .unwrap_or_default()
+    }
+
+    /// The click reports to ask the terminal for
+    pub fn mouse(&self) -> Mouse {
+        Mouse::from_name(&self.terminal.mouse).unwrap_or(Mouse::Off)
+    }
+
+    /// The keyboard's translation table, with the `[keyboard]` entries
+    pub fn keymap(&self) -> KeyMap {
+        let mut keymap = KeyMap::builtin(&self.terminal.keyboard).unwrap_or_default();
+        for (byte, meaning) in &self.keyboard {
+            if let Ok((byte, c)) = KeyMap::parse_entry(byte, meaning) {
+                keymap.set(byte, c);
+            }
+        }
+        keymap
+    }
+
+    /// The key bindings, with the `[keys]` entries
+    pub fn bindings(&self) -> Bindings {
+        Bindings::new(&self.keys)
+    }
+
+    /// The abbreviations and canned messages under `[macros]`
+    pub fn macros(&self) -> Macros {
+        Macros::new(&self.macros)
+    }
+
+    /// The name of the layout to start with
+    pub fn startup_layout(&self) -> &str {
+        match &self.terminal.layout {
+            Some(name) => name,
+            None if self.layouts.contains_key(&self.terminal.mode) => &self.terminal.mode,
+            None => DEFAULT_LAYOUT,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -204,7 +900,28 @@ pub enum ConfigError {
         source: serde_ini::de::Error,
     },
     InvalidMode(String),
+    InvalidRenderer(String),
+    InvalidTheme(String),
+    InvalidInputRows(usize),
+    InvalidTimestampFormat(String),
+    InvalidProvider(String),
+    InvalidAiParams(String),
+    InvalidAmbient(String),
+    InvalidContact(String, String),
+    InvalidRing(String),
     InvalidColumnsConfig,
+    InvalidDiscovery(String),
+    InvalidLayout(String, String),
+    UnknownLayout(String),
+    InvalidScreensaver(String),
+    InvalidControls(String),
+    InvalidKeyboard(String),
+    InvalidMouse(String),
+    InvalidDither(String),
+    InvalidCapture(String),
+    InvalidKeyMapping(String, String),
+    InvalidKeyBinding(String, String),
+    InvalidMacro(String, String),
 }
 
 impl std::fmt::Display for ConfigError {
@@ -229,13 +946,132 @@ impl std::fmt::Display for ConfigError {
             ConfigError::InvalidMode(mode) => {
                 write!(
                     f,
-                    "invalid terminal mode '{}', expected vt100, vt220, or vt340",
+                    "invalid terminal mode '{}', expected vt100, vt220, vt340, vt525, ansi, or xterm",
                     mode
                 )
             }
+            ConfigError::InvalidRenderer(renderer) => {
+                write!(
+                    f,
+                    "invalid renderer '{}', expected one of: {}",
+                    renderer,
+                    RENDERER_NAMES.join(", ")
+                )
+            }
+            ConfigError::InvalidTheme(theme) => {
+                write!(
+                    f,
+                    "invalid theme '{}', expected one of: {}",
+                    theme,
+                    THEME_NAMES.join(", ")
+                )
+            }
+            ConfigError::InvalidInputRows(rows) => {
+                write!(
+                    f,
+                    "invalid input_rows {}, expected {} to {}",
+                    rows, MIN_INPUT_ROWS, MAX_INPUT_ROWS
+                )
+            }
+            ConfigError::InvalidTimestampFormat(format) => {
+                write!(
+                    f,
+                    "invalid timestamp_format '{}', expected one of: {}",
+                    format,
+                    TIMESTAMP_FORMATS.join(", ")
+                )
+            }
+            ConfigError::InvalidProvider(provider) => {
+                write!(
+                    f,
+                    "invalid AI provider '{}', expected one of: {}",
+                    provider,
+                    PROVIDER_NAMES.join(", ")
+                )
+            }
+            ConfigError::InvalidAiParams(reason) => {
+                write!(f, "invalid AI settings: {}", reason)
+            }
+            ConfigError::InvalidAmbient(reason) => {
+                write!(f, "invalid ambient AI settings: {}", reason)
+            }
+            ConfigError::InvalidContact(name, reason) => {
+                write!(f, "invalid notification for contact '{}': {}", name, reason)
+            }
+            ConfigError::InvalidRing(ring) => {
+                write!(
+                    f,
+                    "invalid ring setting '{}', expected anyone, contacts, or off",
+                    ring
+                )
+            }
             ConfigError::InvalidColumnsConfig => {
                 write!(f, "132 column mode is only supported in vt220+ modes")
             }
+            ConfigError::InvalidDiscovery(reason) => {
+                write!(f, "invalid discovery settings: {}", reason)
+            }
+            ConfigError::InvalidLayout(name, reason) => {
+                write!(f, "invalid layout '{}': {}", name, reason)
+            }
+            ConfigError::InvalidControls(controls) => {
+                write!(
+                    f,
+                    "invalid controls '{}', expected auto, 7bit, or 8bit (8bit needs vt220, vt340 or vt525 mode)",
+                    controls
+                )
+            }
+            ConfigError::InvalidKeyboard(keyboard) => {
+                write!(
+                    f,
+                    "invalid keyboard '{}', expected one of: {}",
+                    keyboard,
+                    KEYBOARDS.join(", ")
+                )
+            }
+            ConfigError::InvalidMouse(mouse) => {
+                write!(
+                    f,
+                    "invalid mouse '{}', expected one of: {}",
+                    mouse,
+                    MOUSE_NAMES.join(", ")
+                )
+            }
+            ConfigError::InvalidDither(dither) => {
+                write!(
+                    f,
+                    "invalid dither '{}', expected one of: {}",
+                    dither,
+                    DITHER_NAMES.join(", ")
+                )
+            }
+            ConfigError::InvalidCapture(reason) => {
+                write!(f, "invalid [webcam] setting: {}", reason)
+            }
+            ConfigError::InvalidKeyMapping(byte, reason) => {
+                write!(f, "invalid [keyboard] entry '{}': {}", byte, reason)
+            }
+            ConfigError::InvalidKeyBinding(action, reason) => {
+                write!(f, "invalid [keys] entry '{}': {}", action, reason)
+            }
+            ConfigError::InvalidMacro(name, reason) => {
+                write!(f, "invalid [macros] entry '{}': {}", name, reason)
+            }
+            ConfigError::InvalidScreensaver(style) => {
+                write!(
+                    f,
+                    "invalid screensaver_style '{}', expected one of: {}",
+                    style,
+                    SCREENSAVER_STYLES.join(", ")
+                )
+            }
+            ConfigError::UnknownLayout(name) => {
+                write!(
+                    f,
+                    "unknown layout '{}', expected one under [layouts] or standard, pc, tall, or bare",
+                    name
+                )
+            }
         }
     }
 }
@@ -246,7 +1082,28 @@ impl std::error::Error for ConfigError {
             ConfigError::Io { source, .. } => Some(source),
             ConfigError::Parse { source, .. } => Some(source),
             ConfigError::InvalidMode(_) => None,
+            ConfigError::InvalidRenderer(_) => None,
+            ConfigError::InvalidTheme(_) => None,
+            ConfigError::InvalidInputRows(_) => None,
+            ConfigError::InvalidTimestampFormat(_) => None,
+            ConfigError::InvalidProvider(_) => None,
+            ConfigError::InvalidAiParams(_) => None,
+            ConfigError::InvalidAmbient(_) => None,
+            ConfigError::InvalidContact(..) => None,
+            ConfigError::InvalidRing(_) => None,
             ConfigError::InvalidColumnsConfig => None,
+            ConfigError::InvalidDiscovery(_) => None,
+            ConfigError::InvalidLayout(..) => None,
+            ConfigError::UnknownLayout(_) => None,
+            ConfigError::InvalidScreensaver(_) => None,
+            ConfigError::InvalidControls(_) => None,
+            ConfigError::InvalidKeyboard(_) => None,
+            ConfigError::InvalidMouse(_) => None,
+            ConfigError::InvalidDither(_) => None,
+            ConfigError::InvalidCapture(_) => None,
+            ConfigError::InvalidKeyMapping(..) => None,
+            ConfigError::InvalidKeyBinding(..) => None,
+            ConfigError::InvalidMacro(..) => None,
         }
     }
 }
@@ -254,6 +1111,9 @@ impl std::error::Error for ConfigError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::input::{InputEvent, parse_byte};
+    use crate::keys::{Action, Key};
+    use crate::webcam::{Aspect, Crop};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -276,6 +1136,9 @@ port = 9999
 "#;
         let file = create_temp_config(config_content);
         let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.network.recv_buffer, 1024);
+        assert_eq!(config.network.send_buffer, 256);
+        assert_eq!(config.network.ring, "contacts");
 
         assert_eq!(config.serial.port, "/dev/ttyUSB0");
         assert_eq!(config.serial.baud_rate, 9600);
@@ -284,6 +1147,8 @@ port = 9999
         // Defaults
         assert_eq!(config.terminal.mode, "vt100");
         assert!(!config.terminal.cols_132);
+        assert_eq!(config.terminal.renderer, "auto");
+        assert_eq!(config.terminal.theme, "dec");
     }
 
     #[test]
@@ -308,6 +1173,19 @@ mode = vt220
         assert!(config.terminal.cols_132);
     }
 
+    #[test]
+    fn test_color_modes() {
+        for mode in ["vt525", "ansi"] {
+            let config_content = format!(
+                "[serial]\nport = /dev/ttyUSB0\nbaud = 9600\n\n[network]\nname = TestUser\nport = 9999\n\n[terminal]\nmode = {}\n132_cols = true\n",
+                mode
+            );
+            let file = create_temp_config(&config_content);
+            let config = Config::load(file.path()).unwrap();
+            assert_eq!(config.terminal.mode, mode);
+        }
+    }
+
     #[test]
     fn test_invalid_terminal_mode() {
         let config_content = r#"
@@ -330,6 +1208,214 @@ mode = vt52
         assert!(matches!(err, ConfigError::InvalidMode(_)));
     }
 
+    #[test]
+    fn test_invalid_renderer() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[terminal]
+mode = vt340
+renderer = tektronix
+"#;
+        let file = create_temp_config(config_content);
+        let result = Config::load(file.path());
+
+        assert!(matches!(result, Err(ConfigError::InvalidRenderer(_))));
+    }
+
+    #[test]
+    fn test_contacts() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[contacts]
+alice = 2-1
+garage = sound:/tmp/garage.wav
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.contacts["alice"], "2-1");
+        assert_eq!(config.contacts.len(), 2);
+
+        let file = create_temp_config(&config_content.replace("2-1", "loud"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidContact(..))
+        ));
+    }
+
+    #[test]
+    fn test_layouts() {
+        let config_content = r#"
+[terminal]
+mode = vt525
+
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[layouts]
+vt525 = rows=48 input_rows=4
+short = rows=25 status_bar=false
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        // The one named after the mode is used unless another is chosen
+        assert_eq!(config.startup_layout(), "vt525");
+        assert_eq!(config.layout("vt525").unwrap().rows, 48);
+        assert!(!config.layout("short").unwrap().status_bar);
+        assert_eq!(config.layout("tall"), Layout::builtin("tall"));
+
+        let file = create_temp_config(&config_content.replace("rows=25", "rows=500"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidLayout(..))
+        ));
+        let chosen = config_content.replace("mode = vt525", "mode = vt525\nlayout = wide");
+        let file = create_temp_config(&chosen);
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::UnknownLayout(_))
+        ));
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let config_content = r#"
+[terminal]
+mode = vt220
+keyboard = german
+
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[keyboard]
+0x1c = 0x0d
+0x24 = €
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        let keymap = config.keymap();
+        assert_eq!(parse_byte(b'{', &keymap), InputEvent::Char('ä'));
+        assert_eq!(parse_byte(b'$', &keymap), InputEvent::Char('€'));
+        assert_eq!(parse_byte(0x1c, &keymap), InputEvent::Enter);
+
+        let file = create_temp_config(&config_content.replace("german", "klingon"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidKeyboard(_))
+        ));
+        let file = create_temp_config(
+            &config_content.replace("keyboard = german", "keyboard = german\nmouse = locator"),
+        );
+        assert_eq!(Config::load(file.path()).unwrap().mouse(), Mouse::Locator);
+        let file = create_temp_config(
+            &config_content.replace("keyboard = german", "keyboard = german\nmouse = trackball"),
+        );
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidMouse(_))
+        ));
+        let file = create_temp_config(&config_content.replace("0x1c", "0x1c1c"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidKeyMapping(..))
+        ));
+
+        let file = create_temp_config(&format!("{}\n[keys]\nnext_tab = ^N\n", config_content));
+        let bindings = Config::load(file.path()).unwrap().bindings();
+        assert_eq!(bindings.action(&Key::Byte(0x0e)), Some(Action::NextTab));
+        let file = create_temp_config(&format!("{}\n[keys]\nnext_tab = ctrl-n\n", config_content));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidKeyBinding(..))
+        ));
+    }
+
+    #[test]
+    fn test_theme() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[terminal]
+mode = vt100
+theme = plain
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.terminal.theme, "plain");
+
+        let file = create_temp_config(&config_content.replace("plain", "neon"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidTheme(_))
+        ));
+    }
+
+    #[test]
+    fn test_input_rows_and_timestamps() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[terminal]
+mode = vt100
+input_rows = 4
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.terminal.input_rows, 4);
+        assert_eq!(config.terminal.timestamp_format, "12h");
+        assert_eq!(config.terminal.input_history, DEFAULT_INPUT_HISTORY);
+
+        let file = create_temp_config(&format!("{}timestamp_format = 24h\n", config_content));
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.terminal.timestamp_format, "24h");
+        let file = create_temp_config(&format!("{}timestamp_format = %H\n", config_content));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidTimestampFormat(_))
+        ));
+
+        let file = create_temp_config(&config_content.replace("= 4", "= 6"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidInputRows(6))
+        ));
+    }
+
     #[test]
     fn test_132_cols_requires_vt220() {
         let config_content = r#"
@@ -370,6 +1456,174 @@ port = 9999
         assert_eq!(config.network.name.chars().count(), 16);
     }
 
+    #[test]
+    fn test_discovery_settings() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+
+[network]
+name = TestUser
+discovery = no
+discovery_port = 8891
+discovery_interval = 30
+broadcast_address = 10.1.255.255
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        assert!(!config.network.discovery);
+        assert_eq!(config.network.discovery_port, 8891);
+        assert_eq!(config.network.discovery_interval, 30);
+
+        let bad = create_temp_config(&config_content.replace("10.1.255.255", "everyone"));
+        assert!(matches!(
+            Config::load(bad.path()),
+            Err(ConfigError::InvalidDiscovery(_))
+        ));
+        let clash = create_temp_config(&config_content.replace("8891", "7890"));
+        assert!(matches!(
+            Config::load(clash.path()),
+            Err(ConfigError::InvalidDiscovery(_))
+        ));
+    }
+
+    #[test]
+    fn test_capture_settings() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+
+[network]
+name = TestUser
+
+[webcam]
+resolution = 640x480
+aspect = fit
+crop = 25,0,50,100
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        let capture = config.capture();
+        assert_eq!(capture.resolution, (640, 480));
+        assert_eq!(capture.aspect, Aspect::Fit);
+        assert_eq!(
+            capture.crop,
+            Some(Crop {
+                left: 25,
+                top: 0,
+                width: 50,
+                height: 100
+            })
+        );
+        let defaults = create_temp_config(&config_content.replace("[webcam]", "[elsewhere]"));
+        let config = Config::load(defaults.path()).unwrap();
+        assert_eq!(config.capture(), CaptureSettings::default());
+
+        for (good, bad) in [
+            ("640x480", "640by480"),
+            ("= fit", "= stretch"),
+            ("25,0,50,100", "25,0,80,100"),
+            ("25,0,50,100", "25,0,50"),
+        ] {
+            let file = create_temp_config(&config_content.replace(good, bad));
+            assert!(
+                matches!(
+                    Config::load(file.path()),
+                    Err(ConfigError::InvalidCapture(_))
+                ),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_ai_provider() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[gemini]
+api_key = secret
+"#;
+        // The old section name still works, with Gemini's usual model
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.ai.provider, "gemini");
+        assert_eq!(config.ai.model(), "gemini-2.5-flash");
+
+        let file = create_temp_config(
+            &config_content.replace("[gemini]", "[ai]\nprovider = ollama\nmodel = mistral"),
+        );
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.ai.provider, "ollama");
+        assert_eq!(config.ai.model(), "mistral");
+
+        let file = create_temp_config(&config_content.replace("[gemini]", "[ai]\nprovider = hal"));
+        assert!(matches!(
+            Config::load(file.path()),
+            Err(ConfigError::InvalidProvider(_))
+        ));
+    }
+
+    #[test]
+    fn test_ai_params() {
+        let config_content = r#"
+[serial]
+port = /dev/ttyUSB0
+baud = 9600
+
+[network]
+name = TestUser
+port = 9999
+
+[ai]
+temperature = 0.7
+top_p = 0.95
+max_tokens = 800
+safety = none
+"#;
+        let file = create_temp_config(config_content);
+        let config = Config::load(file.path()).unwrap();
+        let params = config.ai.params().unwrap();
+        assert_eq!(params.temperature, Some(0.7));
+        assert_eq!(params.top_p, Some(0.95));
+        assert_eq!(params.max_tokens, Some(800));
+        assert_eq!(params.safety, Some(ai::Safety::None));
+        assert_eq!(config.ai.cost(Usage::default()), None);
+
+        let priced = format!(
+            "{}input_price = 0.30\noutput_price = 2.50\n",
+            config_content
+        );
+        let file = create_temp_config(&priced);
+        let config = Config::load(file.path()).unwrap();
+        let usage = Usage {
+            input: 1_000_000,
+            output: 200_000,
+        };
+        assert!((config.ai.cost(usage).unwrap() - 0.80).abs() < 1e-9);
+
+        for (good, bad) in [
+            ("temperature = 0.7", "temperature = 2.5"),
+            ("top_p = 0.95", "top_p = -1"),
+            ("safety = none", "safety = lax"),
+            ("max_tokens = 800", "input_price = -1"),
+            ("max_tokens = 800", "context_tokens = 10"),
+        ] {
+            let file = create_temp_config(&config_content.replace(good, bad));
+            assert!(matches!(
+                Config::load(file.path()),
+                Err(ConfigError::InvalidAiParams(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_missing_file() {
         let result = Config::load("/nonexistent/path/config.ini");
@@ -0,0 +1,304 @@
+//! Cover art for the track playing in the Tunes tab.
+//!
+//! The picture comes from the track itself when it has one (an ID3v2 `APIC`
+//! frame in MP3s, a `PICTURE` block in FLAC, a `METADATA_BLOCK_PICTURE`
+//! comment in Ogg Vorbis or Opus), or else from a `folder.jpg` or
+//! `cover.jpg` beside it. It's drawn once per track through the same
+//! renderer as the webcam, so on a slow line it costs one small image per
+//! song rather than one per refresh.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use image::imageops::FilterType;
+
+use crate::graphics::Renderer;
+
+/// Rows of the Tunes tab the picture takes up
+pub const COVER_ROWS: u32 = 8;
+
+/// Most of a track read looking for embedded art (tags come first)
+const MAX_TAG_BYTES: u64 = 4 << 20;
+
+/// Picture files looked for beside a track, in order
+const COVER_FILES: &[&str] = &[
+    "folder.jpg",
+    "cover.jpg",
+    "front.jpg",
+    "folder.png",
+    "cover.png",
+];
+
+/// The ID3v2 and FLAC picture type for the front cover
+const FRONT_COVER: u8 = 3;
+
+/// The picture for a track: embedded, or a cover file in its directory
+pub fn find_cover(track: &Path) -> Option<Vec<u8>> {
+    embedded_cover(track).or_else(|| {
+        let dir = track.parent()?;
+        let names: Vec<String> = fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        COVER_FILES.iter().find_map(|wanted| {
+            let name = names.iter().find(|n| n.eq_ignore_ascii_case(wanted))?;
+            fs::read(dir.join(name)).ok()
+        })
+    })
+}
+
+/// Columns the picture takes up with `renderer`, for a square picture
+pub fn cover_cols(renderer: &dyn Renderer) -> u32 {
+    let (cell_w, cell_h) = renderer.cell_size();
+    (COVER_ROWS * cell_h).div_ceil(cell_w)
+}
+
+/// Render a picture into [`COVER_ROWS`] rows of terminal output (None if it
+/// doesn't decode)
+pub fn render_cover(data: &[u8], renderer: &dyn Renderer) -> Option<Vec<String>> {
+    let image = image::load_from_memory(data).ok()?;
+    let (cell_w, cell_h) = renderer.cell_size();
+    let cols = cover_cols(renderer);
+    let fitted = image.resize_to_fill(cols * cell_w, COVER_ROWS * cell_h, FilterType::Triangle);
+    Some(renderer.render(&fitted.to_luma8(), cols, COVER_ROWS))
+}
+
+fn embedded_cover(track: &Path) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(track)
+        .ok()?
+        .take(MAX_TAG_BYTES)
+        .read_to_end(&mut data)
+        .ok()?;
+    if data.starts_with(b"ID3") {
+        id3_picture(&data)
+    } else if data.starts_with(b"fLaC") {
+        flac_picture(&data)
+    } else if data.starts_with(b"OggS") {
+        ogg_picture(&data)
+    } else {
+        None
+    }
+}
+
+/// A big-endian number of `bytes.len()` bytes
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+/// A 28-bit "syncsafe" ID3v2 number
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 7 | (b & 0x7F) as usize)
+}
+
+/// The front cover (or else the first picture) from an ID3v2 tag
+fn id3_picture(data: &[u8]) -> Option<Vec<u8>> {
+    let version = *data.get(3)?;
+    let flags = *data.get(5)?;
+    let end = (10 + syncsafe(data.get(6..10)?)).min(data.len());
+    let mut pos = 10;
+    if flags & 0x40 != 0 && version >= 3 {
+        // Extended header
+        let size = be(data.get(10..14)?);
+        pos += if version == 4 {
+            syncsafe(data.get(10..14)?)
+        } else {
+            size + 4
+        };
+    }
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut found: Option<Vec<u8>> = None;
+    while pos + header_len <= end {
+        let id = &data[pos..pos + id_len];
+        if id[0] == 0 {
+            break; // Padding
+        }
+        let size_bytes = &data[pos + id_len..pos + id_len + if version == 2 { 3 } else { 4 }];
+        let size = if version == 4 {
+            syncsafe(size_bytes)
+        } else {
+            be(size_bytes)
+        };
+        let body = data.get(pos + header_len..(pos + header_len + size).min(end))?;
+        pos += header_len + size;
+        if id != b"APIC" && id != b"PIC" {
+            continue;
+        }
+        let Some((kind, picture)) = apic_body(body, version == 2) else {
+            continue;
+        };
+        if kind == FRONT_COVER {
+            return Some(picture.to_vec());
+        }
+        found.get_or_insert_with(|| picture.to_vec());
+    }
+    found
+}
+
+/// The picture type and data of an `APIC` (or v2.2 `PIC`) frame
+fn apic_body(body: &[u8], v22: bool) -> Option<(u8, &[u8])> {
+    let encoding = *body.first()?;
+    let mut pos = 1;
+    if v22 {
+        pos += 3; // Image format, e.g. "JPG"
+    } else {
+        pos += body.get(pos..)?.iter().position(|&b| b == 0)? + 1; // MIME type
+    }
+    let kind = *body.get(pos)?;
+    pos += 1;
+    // The description ends in one zero, or two for UTF-16
+    let rest = body.get(pos..)?;
+    let skip = if encoding == 1 || encoding == 2 {
+        rest.chunks(2).position(|c| c == [0, 0])? * 2 + 2
+    } else {
+        rest.iter().position(|&b| b == 0)? + 1
+    };
+    Some((kind, rest.get(skip..)?))
+}
+
+/// The picture from the `PICTURE` metadata block of a FLAC file
+fn flac_picture(data: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 4;
+    let mut found = None;
+    loop {
+        let header = data.get(pos..pos + 4)?;
+        let last = header[0] & 0x80 != 0;
+        let size = be(&header[1..]);
+        let block = data.get(pos + 4..pos + 4 + size)?;
+        if header[0] & 0x7F == 6
+            && let Some((kind, picture)) = picture_block(block)
+        {
+            if kind == FRONT_COVER {
+                return Some(picture.to_vec());
+            }
+            found.get_or_insert_with(|| picture.to_vec());
+        }
+        if last {
+            return found;
+        }
+        pos += 4 + size;
+    }
+}
+
+/// The picture type and data of a FLAC picture block (also what Vorbis
+/// comments carry, base64-encoded)
+fn picture_block(block: &[u8]) -> Option<(u8, &[u8])> {
+    let kind = be(block.get(0..4)?) as u8;
+    let mime_len = be(block.get(4..8)?);
+    let mut pos = 8 + mime_len;
+    let desc_len = be(block.get(pos..pos + 4)?);
+    pos += 4 + desc_len + 16; // Description, width, height, depth, colours
+    let len = be(block.get(pos..pos + 4)?);
+    Some((kind, block.get(pos + 4..pos + 4 + len)?))
+}
+
+/// The picture in the comments of an Ogg Vorbis or Opus file
+fn ogg_picture(data: &[u8]) -> Option<Vec<u8>> {
+    // The comment packet spans pages: join up the first stream's payloads
+    let mut payload = Vec::new();
+    let mut pos = 0;
+    let mut serial = None;
+    // (the last page read is usually cut short, and is left out)
+    while let Some(header) = data.get(pos..pos + 27)
+        && header.starts_with(b"OggS")
+    {
+        let segments = header[26] as usize;
+        let Some(table) = data.get(pos + 27..pos + 27 + segments) else {
+            break;
+        };
+        let len: usize = table.iter().map(|&s| s as usize).sum();
+        let start = pos + 27 + segments;
+        let Some(body) = data.get(start..start + len) else {
+            break;
+        };
+        if *serial.get_or_insert(&header[14..18]) == &header[14..18] {
+            payload.extend_from_slice(body);
+        }
+        pos = start + len;
+    }
+    const FIELD: &[u8] = b"METADATA_BLOCK_PICTURE=";
+    let at = payload
+        .windows(FIELD.len())
+        .position(|w| w.eq_ignore_ascii_case(FIELD))?;
+    // The comment's length comes just before it
+    let len = u32::from_le_bytes(payload.get(at.checked_sub(4)?..at)?.try_into().ok()?) as usize;
+    let encoded = payload.get(at + FIELD.len()..at + len.max(FIELD.len()))?;
+    let block = base64_decode(encoded)?;
+    picture_block(&block).map(|(_, picture)| picture.to_vec())
+}
+
+fn base64_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &c in text {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FLAC picture block around `picture`
+    fn block(kind: u8, picture: &[u8]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&(kind as u32).to_be_bytes());
+        block.extend_from_slice(&10u32.to_be_bytes());
+        block.extend_from_slice(b"image/jpeg");
+        block.extend_from_slice(&0u32.to_be_bytes());
+        block.extend_from_slice(&[0; 16]);
+        block.extend_from_slice(&(picture.len() as u32).to_be_bytes());
+        block.extend_from_slice(picture);
+        block
+    }
+
+    #[test]
+    fn test_embedded_pictures() {
+        // ID3v2.3: a back cover, then the front cover
+        let mut frames = Vec::new();
+        for (kind, picture) in [(4u8, &b"back"[..]), (FRONT_COVER, &b"front"[..])] {
+            let mut body = vec![0];
+            body.extend_from_slice(b"image/jpeg\0");
+            body.push(kind);
+            body.extend_from_slice(b"desc\0");
+            body.extend_from_slice(picture);
+            frames.extend_from_slice(b"APIC");
+            frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            frames.extend_from_slice(&[0, 0]);
+            frames.extend_from_slice(&body);
+        }
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() + 16;
+        tag.extend([21, 14, 7, 0].map(|shift| (size >> shift & 0x7F) as u8));
+        tag.extend_from_slice(&frames);
+        tag.extend_from_slice(&[0; 16]);
+        assert_eq!(id3_picture(&tag).as_deref(), Some(&b"front"[..]));
+
+        let picture = block(FRONT_COVER, b"art");
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(&[0x80 | 6, 0, 0, picture.len() as u8]);
+        flac.extend_from_slice(&picture);
+        assert_eq!(flac_picture(&flac).as_deref(), Some(&b"art"[..]));
+
+        assert_eq!(base64_decode(b"YXJ0").as_deref(), Some(&b"art"[..]));
+        assert_eq!(base64_decode(b"YQ==").as_deref(), Some(&b"a"[..]));
+    }
+}
@@ -0,0 +1,349 @@
+//! `wormhole export`: chat logs as an mbox or a static web page.
+//!
+//! The chat log (`chat-YYYYMMDD.log` in the `[logging]` directory) keeps each
+//! line as it was shown, so it's read back into messages here: when, who, and
+//! what kind (said, `/me`, or a notice such as `*** bob joined ***`). Lines
+//! without a timestamp (long messages, ASCII-art images, shared text) belong
+//! to the message before them and keep their spacing: in the page they go in
+//! a `<pre>`, in the mbox they're the body.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// Longest text before ": " taken for a nickname, as in the chat colours
+const MAX_NICK_LEN: usize = 16;
+
+/// Longest mbox subject, in characters
+const MAX_SUBJECT_LEN: usize = 60;
+
+/// What to export to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Mbox,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mbox" => Ok(Format::Mbox),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("unknown format '{}', expected mbox or html", s)),
+        }
+    }
+}
+
+/// What kind of line started a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// `alice: hello`
+    Said(String),
+    /// `* alice waves`
+    Action(String),
+    /// Anything else: `*** ... ***`, `alice shared an image:`
+    Notice,
+}
+
+/// One message from the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub at: NaiveDateTime,
+    pub kind: Kind,
+    /// The text on the timestamped line (without the name)
+    pub text: String,
+    /// The lines after it
+    pub more: Vec<String>,
+}
+
+impl Entry {
+    fn from(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Said(name) | Kind::Action(name) => Some(name),
+            Kind::Notice => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Not named like a chat log, so there's no date for its times
+    NotALog(PathBuf),
+    /// No chat logs to export
+    NoLogs(PathBuf),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io { path, source } => {
+                write!(f, "failed to read '{}': {}", path.display(), source)
+            }
+            ExportError::NotALog(path) => write!(
+                f,
+                "'{}' isn't a chat log (expected a name like chat-20250101.log)",
+                path.display()
+            ),
+            ExportError::NoLogs(dir) => {
+                write!(f, "no chat logs in '{}'", dir.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io { source, .. } => Some(source),
+            ExportError::NotALog(_) => None,
+            ExportError::NoLogs(_) => None,
+        }
+    }
+}
+
+/// The day a chat log covers, from its name
+pub fn log_date(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?;
+    let day = name.strip_prefix("chat-")?.strip_suffix(".log")?;
+    NaiveDate::parse_from_str(day, "%Y%m%d").ok()
+}
+
+/// The chat logs in the log directory, oldest first
+pub fn chat_logs(dir: &Path) -> Result<Vec<PathBuf>, ExportError> {
+    let io_error = |source| ExportError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut logs = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if log_date(&path).is_some() {
+            logs.push(path);
+        }
+    }
+    if logs.is_empty() {
+        return Err(ExportError::NoLogs(dir.to_path_buf()));
+    }
+    // The date in the name sorts as text
+    logs.sort();
+    Ok(logs)
+}
+
+/// Read a day's chat log back into messages
+pub fn parse_log(date: NaiveDate, text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in text.lines() {
+        match parse_line(line) {
+            Some((time, kind, text)) => entries.push(Entry {
+                at: date.and_time(time),
+                kind,
+                text,
+                more: Vec::new(),
+            }),
+            None => match entries.last_mut() {
+                Some(entry) => entry.more.push(line.to_string()),
+                // The day starts mid-message: show it as a notice of its own
+                None => entries.push(Entry {
+                    at: date.and_time(NaiveTime::MIN),
+                    kind: Kind::Notice,
+                    text: line.to_string(),
+                    more: Vec::new(),
+                }),
+            },
+        }
+    }
+    // A blank line at the end of a message is left over from the screen
+    for entry in &mut entries {
+        while entry.more.last().is_some_and(|l| l.trim().is_empty()) {
+            entry.more.pop();
+        }
+    }
+    entries
+}
+
+/// `[03:45PM] alice: hello` as its time, kind and text
+fn parse_line(line: &str) -> Option<(NaiveTime, Kind, String)> {
+    let rest = line.strip_prefix('[')?;
+    let (stamp, rest) = rest.split_once("] ")?;
+    let time = crate::terminal::stamp::parse(stamp)?;
+    let (kind, text) = if rest.starts_with("***") {
+        let text = rest.trim_start_matches('*').trim_end_matches('*').trim();
+        (Kind::Notice, text)
+    } else if let Some(action) = rest.strip_prefix("* ") {
+        let (name, text) = action.split_once(' ').unwrap_or((action, ""));
+        (Kind::Action(name.to_string()), text)
+    } else if let Some((name, text)) = rest.split_once(": ")
+        && !name.is_empty()
+        && name.chars().count() <= MAX_NICK_LEN
+        && !name.contains(' ')
+    {
+        (Kind::Said(name.to_string()), text)
+    } else {
+        (Kind::Notice, rest)
+    };
+    Some((time, kind, text.to_string()))
+}
+
+/// Read the chat logs and write them out as `format`
+pub fn export(format: Format, logs: &[PathBuf]) -> Result<String, ExportError> {
+    let mut entries = Vec::new();
+    for path in logs {
+        let date = log_date(path).ok_or_else(|| ExportError::NotALog(path.clone()))?;
+        let text = std::fs::read_to_string(path).map_err(|source| ExportError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        entries.extend(parse_log(date, &text));
+    }
+    Ok(match format {
+        Format::Mbox => to_mbox(&entries),
+        Format::Html => to_html(&entries),
+    })
+}
+
+/// One mail per message, from `name@wormhole` (notices from `wormhole`)
+pub fn to_mbox(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let from = entry.from().unwrap_or("wormhole");
+        let address = format!(
+            "{}@wormhole",
+            from.replace(|c: char| c.is_whitespace() || c == '@', "_")
+        );
+        let at = Local
+            .from_local_datetime(&entry.at)
+            .earliest()
+            .unwrap_or_else(|| Local.from_utc_datetime(&entry.at));
+        let first = match &entry.kind {
+            Kind::Action(name) => format!("* {} {}", name, entry.text),
+            _ => entry.text.clone(),
+        };
+        let mut subject: String = first.chars().take(MAX_SUBJECT_LEN).collect();
+        if subject.trim().is_empty() {
+            subject = "(no text)".to_string();
+        }
+
+        let _ = writeln!(
+            out,
+            "From {} {}",
+            address,
+            at.format("%a %b %e %H:%M:%S %Y")
+        );
+        let _ = writeln!(out, "From: {} <{}>", from, address);
+        let _ = writeln!(out, "Date: {}", at.to_rfc2822());
+        let _ = writeln!(out, "Subject: {}", subject);
+        let _ = writeln!(out, "Content-Type: text/plain; charset=utf-8");
+        out.push('\n');
+        for line in std::iter::once(first.as_str()).chain(entry.more.iter().map(String::as_str)) {
+            // mboxrd: a body line that looks like a separator is quoted
+            if line.trim_start_matches('>').starts_with("From ") {
+                out.push('>');
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// A page with the chat as it looked, a heading per day
+pub fn to_html(entries: &[Entry]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Wormhole chat</title>\n<style>\n\
+         body { background: #000; color: #3f3; font-family: monospace; }\n\
+         .time { color: #3cc; }\n.notice { color: #cc3; }\n\
+         pre { margin: 0 0 0 10ch; }\n\
+         </style>\n</head>\n<body>\n<h1>Wormhole chat</h1>\n",
+    );
+    let mut day = None;
+    for entry in entries {
+        if day != Some(entry.at.date()) {
+            day = Some(entry.at.date());
+            let _ = writeln!(out, "<h2>{}</h2>", entry.at.format("%A %e %B %Y"));
+        }
+        let time = entry.at.format("%I:%M%p");
+        let text = escape_html(&entry.text);
+        let line = match &entry.kind {
+            Kind::Said(name) => format!("<b>{}</b>: {}", escape_html(name), text),
+            Kind::Action(name) => format!("* <b>{}</b> {}", escape_html(name), text),
+            Kind::Notice => format!("<span class=\"notice\">{}</span>", text),
+        };
+        let _ = writeln!(
+            out,
+            "<div><span class=\"time\">[{}]</span> {}</div>",
+            time, line
+        );
+        if !entry.more.is_empty() {
+            let more: Vec<String> = entry.more.iter().map(|l| escape_html(l)).collect();
+            let _ = writeln!(out, "<pre>{}</pre>", more.join("\n"));
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+[09:41AM] *** bob joined ***
+[09:42AM] alice: look at this
+[09:42AM] alice shared an image:
+  (o o)
+   <_>
+
+[09:43AM] * bob waves
+[09:44AM] bob: From here it looks fine
+";
+
+    #[test]
+    fn test_parse_and_export() {
+        let date = log_date(Path::new("/var/log/wormhole/chat-20260301.log")).unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2026, 3, 1).unwrap());
+        assert_eq!(log_date(Path::new("ai-20260301.log")), None);
+
+        let entries = parse_log(date, LOG);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].kind, Kind::Notice);
+        assert_eq!(entries[0].text, "bob joined");
+        assert_eq!(entries[1].kind, Kind::Said("alice".to_string()));
+        assert_eq!(entries[2].more, vec!["  (o o)", "   <_>"]);
+        assert_eq!(entries[3].kind, Kind::Action("bob".to_string()));
+        assert_eq!(entries[4].at.format("%H:%M").to_string(), "09:44");
+
+        let html = to_html(&entries);
+        assert!(html.contains("<pre>  (o o)\n   &lt;_&gt;</pre>"));
+        assert!(html.contains("* <b>bob</b> waves"));
+
+        let mbox = to_mbox(&entries);
+        assert_eq!(mbox.matches("\nFrom: ").count(), 5);
+        assert!(mbox.contains("From: alice <alice@wormhole>"));
+        assert!(mbox.contains("\n>From here it looks fine\n"));
+    }
+}
@@ -22,6 +22,23 @@ pub struct Cell {
     pub char: char,
     /// The character set mode
     pub mode: CharMode,
+    /// ANSI 256-colour foreground (None = terminal default)
+    pub fg: Option<u8>,
+    /// ANSI 256-colour background (None = terminal default)
+    pub bg: Option<u8>,
+    /// Bold (SGR 1)
+    pub bold: bool,
+    /// Reverse video (SGR 7), e.g. a search match in the chat
+    pub reverse: bool,
+}
+
+/// How a cell is drawn besides its character
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    fg: Option<u8>,
+    bg: Option<u8>,
+    bold: bool,
+    reverse: bool,
 }
 
 impl Cell {
@@ -30,6 +47,10 @@ impl Cell {
         Self {
             char: c,
             mode: CharMode::Ascii,
+            fg: None,
+            bg: None,
+            bold: false,
+            reverse: false,
         }
     }
 
@@ -39,6 +60,31 @@ impl Cell {
         Self {
             char: c,
             mode: CharMode::DecGraphics,
+            fg: None,
+            bg: None,
+            bold: false,
+            reverse: false,
+        }
+    }
+
+    /// Return this cell with an ANSI 256-colour background
+    #[allow(dead_code)]
+    pub const fn with_bg(self, bg: Option<u8>) -> Self {
+        Self { bg, ..self }
+    }
+
+    /// Return this cell with an ANSI 256-colour foreground
+    #[allow(dead_code)]
+    pub const fn with_fg(self, fg: Option<u8>) -> Self {
+        Self { fg, ..self }
+    }
+
+    fn style(&self) -> Style {
+        Style {
+            fg: self.fg,
+            bg: self.bg,
+            bold: self.bold,
+            reverse: self.reverse,
         }
     }
 
@@ -112,6 +158,7 @@ impl Default for Frame {
 fn row_to_string(row: &[Cell]) -> String {
     let mut output = String::with_capacity(row.len() + 10);
     let mut current_mode = CharMode::Ascii;
+    let mut current_style = Style::default();
 
     for cell in row {
         if cell.mode != current_mode {
@@ -121,29 +168,132 @@ fn row_to_string(row: &[Cell]) -> String {
             }
             current_mode = cell.mode;
         }
+        if cell.style() != current_style {
+            output.push_str(&sgr(cell.style()));
+            current_style = cell.style();
+        }
         output.push(cell.char);
     }
 
-    // Always end in ASCII mode
+    // Always end in ASCII mode with default colours
     if current_mode != CharMode::Ascii {
         output.push_str(SHIFT_IN);
     }
+    if current_style != Style::default() {
+        output.push_str(&sgr(Style::default()));
+    }
 
     output
 }
 
+/// SGR sequence selecting a style, resetting first. The 16 basic colours use
+/// their own codes, which terminals without the 256-colour palette know too.
+fn sgr(style: Style) -> String {
+    let mut sgr = String::from("\x1b[0");
+    if style.bold {
+        sgr.push_str(";1");
+    }
+    if style.reverse {
+        sgr.push_str(";7");
+    }
+    for (color, base, bright, extended) in [(style.fg, 30, 90, 38), (style.bg, 40, 100, 48)] {
+        match color {
+            Some(n @ 0..=7) => sgr.push_str(&format!(";{}", base + n)),
+            Some(n @ 8..=15) => sgr.push_str(&format!(";{}", bright + n - 8)),
+            Some(n) => sgr.push_str(&format!(";{};5;{}", extended, n)),
+            None => {}
+        }
+    }
+    sgr.push('m');
+    sgr
+}
+
+/// Apply the parameters of an SGR sequence to a (foreground, background) pair.
+/// Only resets and colour selections (the basic 16 as palette entries 0 to
+/// 15, and the 256-colour forms) are understood; other attributes are ignored.
+pub fn parse_sgr(params: &str, (fg, bg): (Option<u8>, Option<u8>)) -> (Option<u8>, Option<u8>) {
+    let style = apply_sgr(
+        params,
+        Style {
+            fg,
+            bg,
+            ..Style::default()
+        },
+    );
+    (style.fg, style.bg)
+}
+
+/// Apply the parameters of an SGR sequence to a style
+fn apply_sgr(params: &str, mut style: Style) -> Style {
+    let parts: Vec<&str> = params.split(';').collect();
+    let mut i = 0;
+    while i < parts.len() {
+        match parts[i] {
+            "" | "0" => style = Style::default(),
+            "1" => style.bold = true,
+            "22" => style.bold = false,
+            "7" => style.reverse = true,
+            "27" => style.reverse = false,
+            "39" => style.fg = None,
+            "49" => style.bg = None,
+            n if let Ok(code @ (30..=37 | 40..=47 | 90..=97 | 100..=107)) = n.parse::<u8>() => {
+                let color = Some(code % 10 + if code >= 90 { 8 } else { 0 });
+                if matches!(code, 30..=37 | 90..=97) {
+                    style.fg = color;
+                } else {
+                    style.bg = color;
+                }
+            }
+            "38" | "48" if parts.get(i + 1) == Some(&"5") => {
+                let color = parts.get(i + 2).and_then(|n| n.parse().ok());
+                if parts[i] == "38" {
+                    style.fg = color;
+                } else {
+                    style.bg = color;
+                }
+                i += 2;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    style
+}
+
 /// Parse a string with escape sequences back into cells
 fn parse_row(line: &str) -> Vec<Cell> {
     let mut cells = Vec::with_capacity(line.len());
     let mut current_mode = CharMode::Ascii;
+    let mut current_style = Style::default();
+    let mut chars = line.chars().peekable();
 
-    for c in line.chars() {
+    while let Some(c) = chars.next() {
         match c {
             '\x0E' => current_mode = CharMode::DecGraphics, // SHIFT_OUT
             '\x0F' => current_mode = CharMode::Ascii,       // SHIFT_IN
+            '\x1b' if chars.peek() == Some(&'[') => {
+                // CSI: collect parameters up to the final byte
+                chars.next();
+                let mut params = String::new();
+                let mut final_byte = None;
+                for p in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&p) {
+                        final_byte = Some(p);
+                        break;
+                    }
+                    params.push(p);
+                }
+                if final_byte == Some('m') {
+                    current_style = apply_sgr(&params, current_style);
+                }
+            }
             _ => cells.push(Cell {
                 char: c,
                 mode: current_mode,
+                fg: current_style.fg,
+                bg: current_style.bg,
+                bold: current_style.bold,
+                reverse: current_style.reverse,
             }),
         }
     }
@@ -178,8 +328,9 @@ where
     let can_diff =
         prev.is_some_and(|p| p.height() == current.height() && p.width() == current.width());
 
-    // Track current terminal mode to minimize escape sequences
+    // Track current terminal mode and background to minimize escape sequences
     let mut terminal_mode = CharMode::Ascii;
+    let mut terminal_style = Style::default();
     // Track if we need to reposition cursor
     let mut cursor_row: Option<usize> = None;
     let mut cursor_col: Option<usize> = None;
@@ -213,6 +364,10 @@ where
                         }
                         terminal_mode = cell.mode;
                     }
+                    if cell.style() != terminal_style {
+                        output.push_str(&sgr(cell.style()));
+                        terminal_style = cell.style();
+                    }
 
                     output.push(cell.char);
                     cursor_row = Some(term_row);
@@ -234,6 +389,10 @@ where
                     }
                     terminal_mode = cell.mode;
                 }
+                if cell.style() != terminal_style {
+                    output.push_str(&sgr(cell.style()));
+                    terminal_style = cell.style();
+                }
                 output.push(cell.char);
                 if let Some(ref mut col) = cursor_col {
                     *col += 1;
@@ -242,10 +401,13 @@ where
         }
     }
 
-    // Return to ASCII mode at the end
+    // Return to ASCII mode and default colours at the end
     if terminal_mode != CharMode::Ascii {
         output.push_str(SHIFT_IN);
     }
+    if terminal_style != Style::default() {
+        output.push_str(&sgr(Style::default()));
+    }
 
     output
 }
@@ -291,6 +453,28 @@ mod tests {
         assert_eq!(cells[4], Cell::ascii('!'));
     }
 
+    #[test]
+    fn test_basic_colors_and_attributes() {
+        // As the chat paints them: bold red, then a reversed search match
+        let cells = parse_row("\x1b[1;31mA\x1b[0m\x1b[7mB\x1b[0m");
+        let bold_red = Cell {
+            bold: true,
+            ..Cell::ascii('A').with_fg(Some(1))
+        };
+        assert_eq!(cells[0], bold_red);
+        assert!(cells[1].reverse && cells[1].fg.is_none());
+        assert_eq!(parse_sgr("96;100", (None, None)), (Some(14), Some(8)));
+        // 256-colour parameters aren't taken for attributes
+        assert_eq!(
+            parse_row("\x1b[38;5;1mC")[0],
+            Cell::ascii('C').with_fg(Some(1))
+        );
+
+        let row = row_to_string(&cells);
+        assert!(row.starts_with("\x1b[0;1;31mA\x1b[0;7mB"));
+        assert_eq!(parse_row(&row), cells);
+    }
+
     #[test]
     fn test_roundtrip() {
         let original = Frame {
@@ -308,4 +492,34 @@ mod tests {
         let reconstructed = Frame::from_strings(&strings);
         assert_eq!(original, reconstructed);
     }
+
+    #[test]
+    fn test_parse_row_background() {
+        let line = "\x1b[48;5;240m  \x1b[48;5;250m \x1b[0mA";
+        let cells = parse_row(line);
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], Cell::space().with_bg(Some(240)));
+        assert_eq!(cells[1], Cell::space().with_bg(Some(240)));
+        assert_eq!(cells[2], Cell::space().with_bg(Some(250)));
+        assert_eq!(cells[3], Cell::ascii('A'));
+
+        // Re-encoding produces the same cells
+        assert_eq!(parse_row(&row_to_string(&cells)), cells);
+    }
+
+    #[test]
+    fn test_parse_row_foreground_and_background() {
+        let line = "\x1b[38;5;255;48;5;232m\u{2580}\x1b[49m\u{2580}\x1b[0m ";
+        let cells = parse_row(line);
+        assert_eq!(cells.len(), 3);
+        assert_eq!(
+            cells[0],
+            Cell::ascii('\u{2580}')
+                .with_fg(Some(255))
+                .with_bg(Some(232))
+        );
+        assert_eq!(cells[1], Cell::ascii('\u{2580}').with_fg(Some(255)));
+        assert_eq!(cells[2], Cell::space());
+        assert_eq!(parse_row(&row_to_string(&cells)), cells);
+    }
 }
@@ -76,6 +76,26 @@ impl DecGraphicsChar {
             Self::Bullet => '\x7e',           // ~
         }
     }
+
+    /// The nearest plain ASCII character, for terminals without the graphics set
+    pub const fn as_ascii_char(self) -> char {
+        match self {
+            Self::Diamond => '*',
+            Self::Checkerboard => '#',
+            Self::Degree => 'o',
+            Self::PlusMinus
+            | Self::LowerRightCorner
+            | Self::UpperRightCorner
+            | Self::UpperLeftCorner
+            | Self::LowerLeftCorner
+            | Self::LeftTee
+            | Self::RightTee => '+',
+            Self::ScanLine1 | Self::ScanLine3 | Self::HorizontalLine => '-',
+            Self::ScanLine7 | Self::ScanLine9 => '_',
+            Self::VerticalLine => '|',
+            Self::Bullet => '.',
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,158 @@
+//! Dithering video down to the shades a renderer can draw.
+//!
+//! The ASCII ramp has eight shades and the DRCS glyphs five, so rounding
+//! each cell to the nearest one turns a face into flat bands. Dithering
+//! trades resolution for shades: Floyd–Steinberg carries each cell's
+//! rounding error on to its neighbours, and ordered (Bayer) dithering rounds
+//! against a repeating 4x4 pattern, which stays put from frame to frame and
+//! so shimmers less on a moving picture.
+
+use image::{GrayImage, Luma};
+
+/// Names accepted for the `dither` config option
+pub const DITHER_NAMES: &[&str] = &["none", "floyd-steinberg", "bayer"];
+
+/// 4x4 ordered (Bayer) dither thresholds, scaled to 0-255
+pub(super) const BAYER_4X4: [[u8; 4]; 4] = [
+    [8, 136, 40, 168],
+    [200, 72, 232, 104],
+    [56, 184, 24, 152],
+    [248, 120, 216, 88],
+];
+
+/// How video is rounded to a renderer's shades
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// To the nearest shade
+    #[default]
+    None,
+    /// Error diffusion
+    FloydSteinberg,
+    /// Ordered, with a 4x4 Bayer matrix
+    Bayer,
+}
+
+impl Dither {
+    /// The method named `name`, one of [`DITHER_NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Dither::None),
+            "floyd-steinberg" => Some(Dither::FloydSteinberg),
+            "bayer" => Some(Dither::Bayer),
+            _ => None,
+        }
+    }
+
+    /// `image` with every pixel one of `shades` (sorted, darkest first)
+    pub fn apply(self, image: &GrayImage, shades: &[u8]) -> GrayImage {
+        if shades.is_empty() {
+            return image.clone();
+        }
+        match self {
+            Dither::None => {
+                let mut out = image.clone();
+                for pixel in out.pixels_mut() {
+                    pixel[0] = nearest(shades, pixel[0] as i32);
+                }
+                out
+            }
+            Dither::FloydSteinberg => floyd_steinberg(image, shades),
+            Dither::Bayer => GrayImage::from_fn(image.width(), image.height(), |x, y| {
+                let value = image.get_pixel(x, y)[0];
+                let threshold = BAYER_4X4[(y % 4) as usize][(x % 4) as usize];
+                Luma([ordered(shades, value, threshold)])
+            }),
+        }
+    }
+}
+
+/// The shade closest to `value`
+fn nearest(shades: &[u8], value: i32) -> u8 {
+    shades
+        .iter()
+        .copied()
+        .min_by_key(|&s| (s as i32 - value).abs())
+        .unwrap_or(0)
+}
+
+/// `value` rounded up or down to the shades either side of it, up when how
+/// far it is between them passes `threshold`
+fn ordered(shades: &[u8], value: u8, threshold: u8) -> u8 {
+    let above = shades.iter().position(|&s| s >= value);
+    match above {
+        None => shades[shades.len() - 1],
+        Some(0) => shades[0],
+        Some(i) => {
+            let (low, high) = (shades[i - 1] as u32, shades[i] as u32);
+            let between = (value as u32 - low) * 255 / (high - low);
+            if between > threshold as u32 {
+                high as u8
+            } else {
+                low as u8
+            }
+        }
+    }
+}
+
+/// Round left to right, top to bottom, passing each pixel's error on: 7/16
+/// to the right, and 3/16, 5/16 and 1/16 below left, below and below right
+fn floyd_steinberg(image: &GrayImage, shades: &[u8]) -> GrayImage {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut values: Vec<i32> = image.pixels().map(|p| p[0] as i32).collect();
+    let mut out = GrayImage::new(image.width(), image.height());
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let shade = nearest(shades, values[i]);
+            out.put_pixel(x as u32, y as u32, Luma([shade]));
+            let error = values[i] - shade as i32;
+            let mut spread = |dx: isize, dy: usize, weight: i32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    values[(y + dy) * width + nx as usize] += error * weight / 16;
+                }
+            };
+            spread(1, 0, 7);
+            spread(-1, 1, 3);
+            spread(0, 1, 5);
+            spread(1, 1, 1);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dither_mid_gray() {
+        let gray = GrayImage::from_pixel(16, 16, Luma([128]));
+        let shades = [0, 255];
+
+        // Plain rounding loses the gray altogether
+        let flat = Dither::None.apply(&gray, &shades);
+        assert!(flat.pixels().all(|p| p[0] == 255));
+
+        for dither in [Dither::FloydSteinberg, Dither::Bayer] {
+            let out = dither.apply(&gray, &shades);
+            assert!(out.pixels().all(|p| shades.contains(&p[0])));
+            let white = out.pixels().filter(|p| p[0] == 255).count();
+            assert!((112..=144).contains(&white), "{:?}: {}", dither, white);
+        }
+    }
+
+    #[test]
+    fn test_dither_names() {
+        for name in DITHER_NAMES {
+            assert!(Dither::from_name(name).is_some());
+        }
+        assert_eq!(Dither::from_name("Bayer"), Some(Dither::Bayer));
+        assert_eq!(Dither::from_name("atkinson"), None);
+        // Shades already on the ramp stay as they are
+        let ramp = GrayImage::from_fn(3, 1, |x, _| Luma([[0, 100, 255][x as usize]]));
+        let out = Dither::Bayer.apply(&ramp, &[0, 100, 255]);
+        assert_eq!(out.into_raw(), vec![0, 100, 255]);
+    }
+}
@@ -2,6 +2,27 @@
 //!
 //! This module handles the loading of custom glyphs (Soft Fonts) to the terminal
 //! to improve ASCII art rendering with smooth shading blocks.
+//!
+//! The four built-in shades can be replaced by a font of the user's own
+//! (`drcs_font` under `[terminal]`), a text file of glyphs drawn with `#` for
+//! a lit pixel and `.` for a dark one, each under a `glyph` line:
+//!
+//! ```text
+//! ; A 25% shade
+//! glyph 64
+//! #...#...
+//! ........
+//! ..#...#.
+//! ```
+//!
+//! Glyphs are up to 10 pixels wide and 10 high, and are loaded from `!`
+//! onwards in the order they come. The number after `glyph` is the brightness
+//! (1-255) the glyph stands for in video; glyphs without one, a logo say, are
+//! loaded but left out of the shading ramp. Lines starting with `;` are
+//! comments.
+
+use std::fmt;
+use std::path::Path;
 
 // Escape sequence mechanism:
 // We load our custom font into G1 and shift-out (SO) or use escape sequences to access it.
@@ -20,123 +41,288 @@ pub const SHIFT_OUT: &str = "\x0E";
 /// Shift In (Invoke G0 into GL)
 pub const SHIFT_IN: &str = "\x0F";
 
-/// Generates the DECDLD (Down-Line Load) sequence to load our custom shading glyphs.
-///
-/// We will define 4 glyphs starting at offset 0x21 (!):
-/// 0x21 (!): Light Shade (25%)
-/// 0x22 ("): Medium Shade (50%)
-/// 0x23 (#): Dark Shade (75%)
-/// 0x24 ($): Full Block (100%)
-pub fn get_drcs_load_sequence() -> String {
-    let mut seq = String::new();
-
-    // DCS Pfn; Pcn; Pe; Pcmw; Pss; Pt; Pcmh; Pcss { Dscs
-    // Pfn = 1 (Load DRCS)
-    // Pcn = 1 (Starting char number, but usually we specify start char in the data)
-    // Pe = 1 (Erase all characters in the set)
-    // Pcmw = 0 (Default width, 80-col mode = 10 pixels usually, or 8)
-    // Pss = 0 (Default)
-    // Pt = 1 (Text)
-    // Pcmh = 0 (Default height)
-    // Pcss = 0 (Default size)
-    // Dscs = < (Name of the set)
-
-    seq.push_str("\x1bP1;1;1;0;0;1;0;0{ <");
-
-    // Sixel data for glyphs.
-    // Format: start_char / pattern ;
-    // start_char is ASCII char (e.g. ! is 0x21)
-    // pattern is sixel data.
-    //
-    // VT220 character cell is 10 scanlines high.
-    // Sixel encodes 6 vertical pixels.
-    // So we need 2 sixel rows?
-    // Actually, DECDLD uses a specific format where you send the top 6 rows, then the bottom 4 rows (for 10-pixel height).
-    // Separated by /.
-
-    // Let's define the patterns.
-    // '?' is 0x3F (00111111) -> 6 pixels ON.
-    // '~' is 0x7E (01111110)
-    // Sixel offset is 63 (0x3F).
-    // Value 0 -> ? (0x3F)
-    // Value 63 -> ~ (0x7E) + ...
-
-    // Wait, Sixel encoding:
-    // Char = Value + 63.
-    // Value is 6-bit integer.
-
-    // 1. Light Shade (25%) - 0x21 (!)
-    // Pattern: Every other pixel, every other line.
-    // Top 6 rows:
-    // Row 0: 10101010
-    // Row 1: 00000000
-    // Row 2: 10101010
-    // ...
-    // This is hard to hand-code without a generator.
-    // Let's use a simplified "Full Block" and "Empty" to test, and maybe "Stripes" for shades.
-
-    // Full Block (100%) - 0x24 ($)
-    // All pixels on.
-    // Top 6 rows: All 1s. Value 63 (0x3F). Char = 63+63 = 126 (~).
-    // Bottom 4 rows: All 1s. Value 15 (0x0F). Char = 15+63 = 78 (N).
-    // Width is 10 cols? Let's assume 8 cols for safety.
-    // Pattern: ~~~~~~~~ / NNNNNNNN ;
-
-    // Let's try to construct the string for the 4 chars.
-
-    // Char 0x21 (!): Light Shade (25%)
-    // Use sparse dots.
-    // Top: "A A A A " (Values resulting in dots)
-    // Let's just use a simple approximation.
-    // We will define them as:
-    // ! : Light
-    // " : Medium
-    // # : Dark
-    // $ : Full
-
-    // ! (Light)
-    // Top:  Use char 'W' (0x57 = 87. 87-63 = 24 = 011000) - just random noise?
-    // Let's use specific values.
-    // 25% density.
-    // We'll just use a simple pattern string for now.
-    // "CKCKCKCK" (Just guessing a pattern that looks like shading)
-    // "C" = 67. 67-63 = 4 = 000100.
-    // "K" = 75. 75-63 = 12 = 001100.
-
-    // To be safe and ensure it works, I will use a very simple pattern:
-    // Full Block ($): All pixels on.
-    // Top: ~~~~~~~~ (All 6 bits set)
-    // Bottom: NNNNNNNN (Bottom 4 bits set)
-
-    // Dark Shade (#): 75%
-    // Top: vvvvvvvv (v = 118. 118-63 = 55 = 110111)
-    // Bottom: JJJJJJJJ (J = 74. 74-63 = 11 = 001011)
-
-    // Medium Shade ("): 50%
-    // Top: oooooooo (o = 111. 111-63 = 48 = 110000)
-    // Bottom: FFFFFFFF (F = 70. 70-63 = 7 = 000111)
-
-    // Light Shade (!): 25%
-    // Top: hhhhhhhh (h = 104. 104-63 = 41 = 101001)
-    // Bottom: BBBBBBBB (B = 66. 66-63 = 3 = 000011)
-
-    // Constructing the payload:
-    // ! hhhhhhhh/BBBBBBBB;
-    // " oooooooo/FFFFFFFF;
-    // # vvvvvvvv/JJJJJJJJ;
-    // $ ~~~~~~~~/NNNNNNNN;
-
-    seq.push_str("!hhhhhhhh/BBBBBBBB;");
-    seq.push_str("\"oooooooo/FFFFFFFF;");
-    seq.push_str("#vvvvvvvv/JJJJJJJJ;");
-    seq.push_str("$~~~~~~~~/NNNNNNNN;");
-
-    seq.push_str("\x1b\\"); // ST (String Terminator)
-
-    // Designate the loaded set (<) as G1
-    seq.push_str(DESIGNATE_DRCS_G1);
-
-    seq
+/// Widest and tallest glyph a font can have
+const MAX_GLYPH: usize = 10;
+/// Glyphs go from `!` to `~`
+const MAX_GLYPHS: usize = 94;
+
+/// A soft font: the payload that loads it and its shading ramp
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrcsFont {
+    /// Glyph data for DECDLD
+    payload: String,
+    /// Each character of the ramp, darkest first, with the brightest input it
+    /// stands for
+    ramp: Vec<(u8, char)>,
+    /// The brightness each character of the ramp shows
+    shades: Vec<u8>,
+}
+
+impl Default for DrcsFont {
+    /// The built-in font, defining 4 glyphs starting at offset 0x21 (!):
+    /// 0x21 (!): Light Shade (25%)
+    /// 0x22 ("): Medium Shade (50%)
+    /// 0x23 (#): Dark Shade (75%)
+    /// 0x24 ($): Full Block (100%)
+    fn default() -> Self {
+        // Sixel data for glyphs.
+        // Format: start_char / pattern ;
+        // start_char is ASCII char (e.g. ! is 0x21)
+        // pattern is sixel data.
+        //
+        // VT220 character cell is 10 scanlines high.
+        // Sixel encodes 6 vertical pixels.
+        // So we need 2 sixel rows?
+        // Actually, DECDLD uses a specific format where you send the top 6 rows, then the bottom 4 rows (for 10-pixel height).
+        // Separated by /.
+
+        // Let's define the patterns.
+        // '?' is 0x3F (00111111) -> 6 pixels ON.
+        // '~' is 0x7E (01111110)
+        // Sixel offset is 63 (0x3F).
+        // Value 0 -> ? (0x3F)
+        // Value 63 -> ~ (0x7E) + ...
+
+        // Wait, Sixel encoding:
+        // Char = Value + 63.
+        // Value is 6-bit integer.
+
+        // 1. Light Shade (25%) - 0x21 (!)
+        // Pattern: Every other pixel, every other line.
+        // Top 6 rows:
+        // Row 0: 10101010
+        // Row 1: 00000000
+        // Row 2: 10101010
+        // ...
+        // This is hard to hand-code without a generator.
+        // Let's use a simplified "Full Block" and "Empty" to test, and maybe "Stripes" for shades.
+
+        // Full Block (100%) - 0x24 ($)
+        // All pixels on.
+        // Top 6 rows: All 1s. Value 63 (0x3F). Char = 63+63 = 126 (~).
+        // Bottom 4 rows: All 1s. Value 15 (0x0F). Char = 15+63 = 78 (N).
+        // Width is 10 cols? Let's assume 8 cols for safety.
+        // Pattern: ~~~~~~~~ / NNNNNNNN ;
+
+        // Let's try to construct the string for the 4 chars.
+
+        // Char 0x21 (!): Light Shade (25%)
+        // Use sparse dots.
+        // Top: "A A A A " (Values resulting in dots)
+        // Let's just use a simple approximation.
+        // We will define them as:
+        // ! : Light
+        // " : Medium
+        // # : Dark
+        // $ : Full
+
+        // ! (Light)
+        // Top:  Use char 'W' (0x57 = 87. 87-63 = 24 = 011000) - just random noise?
+        // Let's use specific values.
+        // 25% density.
+        // We'll just use a simple pattern string for now.
+        // "CKCKCKCK" (Just guessing a pattern that looks like shading)
+        // "C" = 67. 67-63 = 4 = 000100.
+        // "K" = 75. 75-63 = 12 = 001100.
+
+        // To be safe and ensure it works, I will use a very simple pattern:
+        // Full Block ($): All pixels on.
+        // Top: ~~~~~~~~ (All 6 bits set)
+        // Bottom: NNNNNNNN (Bottom 4 bits set)
+
+        // Dark Shade (#): 75%
+        // Top: vvvvvvvv (v = 118. 118-63 = 55 = 110111)
+        // Bottom: JJJJJJJJ (J = 74. 74-63 = 11 = 001011)
+
+        // Medium Shade ("): 50%
+        // Top: oooooooo (o = 111. 111-63 = 48 = 110000)
+        // Bottom: FFFFFFFF (F = 70. 70-63 = 7 = 000111)
+
+        // Light Shade (!): 25%
+        // Top: hhhhhhhh (h = 104. 104-63 = 41 = 101001)
+        // Bottom: BBBBBBBB (B = 66. 66-63 = 3 = 000011)
+
+        // Constructing the payload:
+        // ! hhhhhhhh/BBBBBBBB;
+        // " oooooooo/FFFFFFFF;
+        // # vvvvvvvv/JJJJJJJJ;
+        // $ ~~~~~~~~/NNNNNNNN;
+
+        Self {
+            payload: [
+                "!hhhhhhhh/BBBBBBBB;",
+                "\"oooooooo/FFFFFFFF;",
+                "#vvvvvvvv/JJJJJJJJ;",
+                "$~~~~~~~~/NNNNNNNN;",
+            ]
+            .concat(),
+            ramp: vec![(50, ' '), (100, '!'), (150, '"'), (200, '#'), (255, '$')],
+            shades: vec![0, 75, 125, 175, 255],
+        }
+    }
+}
+
+/// Why a font file couldn't be loaded
+#[derive(Debug)]
+pub enum DrcsFontError {
+    Io(std::io::Error),
+    /// A line that isn't a glyph row, `glyph` line or comment
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for DrcsFontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrcsFontError::Io(e) => write!(f, "{}", e),
+            DrcsFontError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for DrcsFontError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DrcsFontError::Io(e) => Some(e),
+            DrcsFontError::Parse { .. } => None,
+        }
+    }
+}
+
+impl DrcsFont {
+    /// Read a font file
+    pub fn load(path: &Path) -> Result<Self, DrcsFontError> {
+        let text = std::fs::read_to_string(path).map_err(DrcsFontError::Io)?;
+        Self::parse(&text)
+    }
+
+    /// Parse a font in the file format above
+    pub fn parse(text: &str) -> Result<Self, DrcsFontError> {
+        let error = |line: usize, message: &str| DrcsFontError::Parse {
+            line: line + 1,
+            message: message.to_string(),
+        };
+        // Each glyph's shade and rows of pixels
+        let mut glyphs: Vec<(Option<u8>, Vec<Vec<bool>>)> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("glyph") {
+                let shade = match rest.trim() {
+                    "" => None,
+                    s => match s.parse::<u8>() {
+                        Ok(shade) if shade > 0 => Some(shade),
+                        _ => return Err(error(n, "a glyph's brightness is 1-255")),
+                    },
+                };
+                if glyphs.len() == MAX_GLYPHS {
+                    return Err(error(n, "a font has at most 94 glyphs"));
+                }
+                glyphs.push((shade, Vec::new()));
+                continue;
+            }
+            let Some((_, rows)) = glyphs.last_mut() else {
+                return Err(error(n, "pixels before the first 'glyph' line"));
+            };
+            if line.len() > MAX_GLYPH || rows.len() == MAX_GLYPH {
+                return Err(error(n, "glyphs are at most 10 pixels each way"));
+            }
+            let row = line
+                .chars()
+                .map(|c| match c {
+                    '#' => Ok(true),
+                    '.' => Ok(false),
+                    _ => Err(error(n, "pixels are '#' (lit) or '.' (dark)")),
+                })
+                .collect::<Result<_, _>>()?;
+            rows.push(row);
+        }
+        if glyphs.is_empty() {
+            return Err(error(0, "no glyphs"));
+        }
+
+        let payload = glyphs.iter().map(|(_, rows)| sixel_glyph(rows)).collect();
+
+        // Space for black, then the shaded glyphs by brightness, each taking
+        // the inputs nearer to it than to its neighbours
+        let mut levels = vec![(0, ' ')];
+        levels.extend(
+            (0x21u8..)
+                .zip(&glyphs)
+                .filter_map(|(c, (shade, _))| shade.map(|s| (s, c as char))),
+        );
+        levels.sort_by_key(|&(shade, _)| shade);
+        let ramp = levels
+            .iter()
+            .enumerate()
+            .map(|(i, &(shade, c))| {
+                let top = levels
+                    .get(i + 1)
+                    .map_or(255, |&(next, _)| ((shade as u16 + next as u16) / 2) as u8);
+                (top, c)
+            })
+            .collect();
+        let shades = levels.iter().map(|&(shade, _)| shade).collect();
+        Ok(Self {
+            payload,
+            ramp,
+            shades,
+        })
+    }
+
+    /// The DECDLD sequence that loads the font and designates it as G1
+    pub fn load_sequence(&self) -> String {
+        // DCS Pfn; Pcn; Pe; Pcmw; Pss; Pt; Pcmh; Pcss { Dscs
+        // Pfn = 1 (Load DRCS)
+        // Pcn = 1 (Starting char number, but usually we specify start char in the data)
+        // Pe = 1 (Erase all characters in the set)
+        // Pcmw = 0 (Default width, 80-col mode = 10 pixels usually, or 8)
+        // Pss = 0 (Default)
+        // Pt = 1 (Text)
+        // Pcmh = 0 (Default height)
+        // Pcss = 0 (Default size)
+        // Dscs = < (Name of the set)
+
+        format!(
+            "\x1bP1;1;1;0;0;1;0;0{{ <{}\x1b\\{}",
+            self.payload, DESIGNATE_DRCS_G1
+        )
+    }
+
+    /// The character for a brightness, in the font's ramp
+    pub fn char_for(&self, brightness: u8) -> char {
+        self.ramp
+            .iter()
+            .find(|&&(top, _)| brightness <= top)
+            .map_or(' ', |&(_, c)| c)
+    }
+
+    /// The brightness each character of the ramp shows, darkest first
+    pub fn shades(&self) -> Vec<u8> {
+        self.shades.clone()
+    }
+}
+
+/// One glyph's DECDLD data: each column's top six pixels as a sixel, then
+/// after a `/` the four below, then `;`
+fn sixel_glyph(rows: &[Vec<bool>]) -> String {
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let band = |range: std::ops::Range<usize>| -> String {
+        (0..width)
+            .map(|x| {
+                let bits = range
+                    .clone()
+                    .filter(|&y| rows.get(y).and_then(|r| r.get(x)) == Some(&true))
+                    .fold(0u8, |bits, y| bits | 1 << (y - range.start));
+                (bits + 63) as char
+            })
+            .collect()
+    };
+    format!("{}/{};", band(0..6), band(6..10))
 }
 
 /// Returns the character to use for a given brightness (0-255)
@@ -157,3 +343,46 @@ pub fn brightness_to_drcs_char(brightness: u8) -> char {
         _ => '$',
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_font() {
+        let font = DrcsFont::default();
+        for brightness in [0, 50, 51, 120, 180, 255] {
+            assert_eq!(
+                font.char_for(brightness),
+                brightness_to_drcs_char(brightness)
+            );
+        }
+        assert!(
+            font.load_sequence()
+                .starts_with("\x1bP1;1;1;0;0;1;0;0{ <!hhhhhhhh/")
+        );
+        assert!(font.load_sequence().ends_with(DESIGNATE_DRCS_G1));
+    }
+
+    #[test]
+    fn test_parse_font() {
+        let text = "; logo, then two shades out of order\n\
+                    glyph\n##\n\n\
+                    glyph 200\n#.\n.#\n#.\n.#\n#.\n.#\n#.\n\
+                    glyph 100\n#\n";
+        let font = DrcsFont::parse(text).unwrap();
+        // Glyphs in file order: the logo at '!', then '"' and '#'
+        assert_eq!(font.payload, "@@/??;Ti/@?;@/?;");
+        assert_eq!(font.shades(), vec![0, 100, 200]);
+        assert_eq!(font.char_for(50), ' ');
+        assert_eq!(font.char_for(51), '#');
+        assert_eq!(font.char_for(151), '"');
+        assert_eq!(font.char_for(255), '"');
+
+        assert!(DrcsFont::parse("#.\n").is_err());
+        assert!(DrcsFont::parse("glyph 0\n#\n").is_err());
+        assert!(DrcsFont::parse("glyph\n#x\n").is_err());
+        assert!(DrcsFont::parse("glyph\n###########\n").is_err());
+        assert!(DrcsFont::parse("; nothing\n").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Banner text for `/figlet`: big letters built out of `#`, in the same 5x7
+//! font the screenshots use.
+//!
+//! Plain ASCII, so every peer's terminal shows it as it was sent, whatever
+//! it can draw. Text too wide for the screen wraps between words into
+//! further banners.
+
+use super::font::{GLYPH_HEIGHT, GLYPH_WIDTH, glyph};
+
+/// Columns taken by one letter, counting the gap after it
+const LETTER_WIDTH: usize = GLYPH_WIDTH + 1;
+
+/// `text` as banner lines no wider than `width`
+pub fn banner(text: &str, width: usize) -> Vec<String> {
+    let per_row = ((width + 1) / LETTER_WIDTH).max(1);
+    let mut lines = Vec::new();
+    for (i, row) in wrap(text, per_row).iter().enumerate() {
+        if i > 0 {
+            lines.push(String::new());
+        }
+        lines.extend(draw(row));
+    }
+    lines
+}
+
+/// Split `text` into rows of at most `per_row` characters, between words
+/// where possible
+fn wrap(text: &str, per_row: usize) -> Vec<String> {
+    let mut rows: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        if !current.is_empty() && current.chars().count() + 1 + word.len() > per_row {
+            rows.push(std::mem::take(&mut current));
+        }
+        // Longer than a whole row: break it up
+        while word.len() > per_row {
+            if !current.is_empty() {
+                rows.push(std::mem::take(&mut current));
+            }
+            rows.push(word.drain(..per_row).collect());
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.extend(word);
+    }
+    if !current.is_empty() {
+        rows.push(current);
+    }
+    rows
+}
+
+/// One row of letters, seven lines tall
+fn draw(text: &str) -> Vec<String> {
+    (0..GLYPH_HEIGHT)
+        .map(|y| {
+            let mut line = String::new();
+            for c in text.chars() {
+                let bits = glyph(c)[y];
+                for x in 0..GLYPH_WIDTH {
+                    line.push(if bits & (0x10 >> x) != 0 { '#' } else { ' ' });
+                }
+                line.push(' ');
+            }
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banner() {
+        let lines = banner("Hi", 80);
+        assert_eq!(lines.len(), GLYPH_HEIGHT);
+        assert_eq!(lines[0], "#   #   #");
+        assert_eq!(lines[3], "#####   #");
+
+        // Wraps between words, then within a word too long for a row
+        assert_eq!(wrap("one two three", 8), vec!["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        let lines = banner("one two three", 50);
+        assert_eq!(lines.len(), GLYPH_HEIGHT * 2 + 1);
+        assert!(lines.iter().all(|l| l.len() <= 50));
+    }
+}
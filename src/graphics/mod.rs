@@ -5,13 +5,25 @@
 //! - DRCS (Dynamically Redefinable Character Set) for custom shading glyphs
 //! - Sixel graphics for bitmap rendering (VT340)
 //! - Cell-based frame representation for efficient differential rendering
+//! - Pluggable render backends selected by terminal capabilities
+//! - Rasterizing cell grids to images (screenshots)
+//! - Banner text built from ASCII characters
 
 mod cell;
 mod dec;
+mod dither;
 mod drcs;
+mod figlet;
+mod font;
+mod renderer;
+mod screenshot;
 mod sixel;
 
-pub use cell::{Cell, Frame, render_frame_diff};
+pub use cell::{Cell, CharMode, Frame, parse_sgr, render_frame_diff};
 pub use dec::{DecGraphicsChar, ENTER_DEC_GRAPHICS, EXIT_DEC_GRAPHICS};
-pub use drcs::{SHIFT_IN, SHIFT_OUT, brightness_to_drcs_char, get_drcs_load_sequence};
-pub use sixel::{SixelConfig, image_to_sixel};
+pub use dither::{DITHER_NAMES, Dither};
+pub use drcs::{DrcsFont, DrcsFontError, SHIFT_IN, SHIFT_OUT, brightness_to_drcs_char};
+pub use figlet::banner;
+pub use renderer::{RENDERER_NAMES, Renderer, TerminalCaps, select_renderer};
+pub use screenshot::save_png;
+pub use sixel::SixelConfig;
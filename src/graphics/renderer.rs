@@ -0,0 +1,732 @@
+//! Pluggable render backends for turning grayscale images into terminal output.
+//!
+//! Each backend implements the [`Renderer`] trait. Character-cell backends
+//! (ASCII, DRCS, ANSI color, Unicode half-block and Braille) produce one
+//! string per terminal row, while bitmap backends produce DCS sequences:
+//! ReGIS a single one holding the whole image, Sixel one per row so video can
+//! be updated a row at a time. The backend in use is picked once at startup
+//! by [`select_renderer`], either from configuration or from the capabilities
+//! the terminal reports, so call sites never need to match on the mode
+//! themselves.
+
+use std::sync::Arc;
+
+use image::{GrayImage, imageops::FilterType};
+
+use super::dither::BAYER_4X4;
+use super::sixel::encode_strips;
+use super::{DecGraphicsChar, DrcsFont, SHIFT_IN, SHIFT_OUT, SixelConfig};
+
+/// Names accepted for the `renderer` config option
+pub const RENDERER_NAMES: &[&str] = &[
+    "auto",
+    "ascii",
+    "drcs",
+    "sixel",
+    "regis",
+    "ansi",
+    "halfblock",
+    "braille",
+];
+
+/// Graphics features a terminal supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TerminalCaps {
+    /// Soft (downloadable) character sets
+    pub drcs: bool,
+    /// Sixel bitmap graphics
+    pub sixel: bool,
+    /// ReGIS vector graphics
+    pub regis: bool,
+    /// ANSI color (SGR 256-color backgrounds)
+    pub ansi_color: bool,
+    /// UTF-8 output with block and Braille glyphs (modern emulators)
+    pub unicode: bool,
+}
+
+impl TerminalCaps {
+    /// Assume the capabilities of a configured terminal mode
+    pub fn from_mode(mode: &str) -> Self {
+        match mode {
+            "vt340" => Self {
+                drcs: true,
+                sixel: true,
+                regis: true,
+                ..Default::default()
+            },
+            // The VT525's colour is limited to the 16 ANSI colours, too few
+            // for the colour renderers
+            "vt220" | "vt525" => Self {
+                drcs: true,
+                ..Default::default()
+            },
+            "ansi" => Self {
+                ansi_color: true,
+                ..Default::default()
+            },
+            "xterm" => Self {
+                ansi_color: true,
+                unicode: true,
+                ..Default::default()
+            },
+            _ => Self::default(),
+        }
+    }
+
+    /// Parse a Primary Device Attributes (DA1) response such as
+    /// `ESC [ ? 63 ; 1 ; 2 ; 3 ; 4 ; 7 c`.
+    ///
+    /// Attribute 3 is ReGIS, 4 is Sixel, 7 is soft character sets and 22 is
+    /// ANSI color. Returns None if the response isn't a DA1 reply.
+    pub fn from_da1(response: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(response).ok()?;
+        let start = text.find("\x1b[?")?;
+        let body = &text[start + 3..];
+        let end = body.find('c')?;
+
+        let mut caps = Self::default();
+        for attr in body[..end].split(';').skip(1) {
+            match attr.parse::<u32>() {
+                Ok(3) => caps.regis = true,
+                Ok(4) => caps.sixel = true,
+                Ok(7) => caps.drcs = true,
+                Ok(22) => caps.ansi_color = true,
+                _ => {}
+            }
+        }
+        Some(caps)
+    }
+}
+
+/// A backend that renders grayscale images for a terminal
+pub trait Renderer: Send + Sync {
+    /// Short name, matching the `renderer` config option
+    fn name(&self) -> &'static str;
+
+    /// Source pixels (width, height) covered by one character cell.
+    /// Images are resized to `cols * width` by `rows * height` before rendering.
+    fn cell_size(&self) -> (u32, u32);
+
+    /// Whether the output is bitmap sequences rather than character rows
+    fn is_bitmap(&self) -> bool {
+        false
+    }
+
+    /// The brightness of each shade it draws, darkest first, for dithering
+    /// to round to. Empty if it has shades enough not to need it.
+    fn shades(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// The separately shaded dots in one character cell (across, down)
+    fn dots_per_cell(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    /// Render a contrast-enhanced grayscale image filling `cols` x `rows` cells
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String>;
+}
+
+/// Pick a renderer by name. "auto" chooses the best backend the terminal supports.
+pub fn select_renderer(
+    name: &str,
+    caps: &TerminalCaps,
+    sixel_shades: u8,
+    drcs_font: &Arc<DrcsFont>,
+) -> Arc<dyn Renderer> {
+    let sixel = || -> Arc<dyn Renderer> {
+        Arc::new(SixelRenderer {
+            shades: sixel_shades.clamp(2, 64),
+        })
+    };
+    let drcs = || -> Arc<dyn Renderer> {
+        Arc::new(DrcsRenderer {
+            font: drcs_font.clone(),
+        })
+    };
+
+    match name {
+        "ascii" => Arc::new(AsciiRenderer),
+        "drcs" => drcs(),
+        "sixel" => sixel(),
+        "regis" => Arc::new(RegisRenderer),
+        "ansi" => Arc::new(AnsiColorRenderer),
+        "halfblock" => Arc::new(HalfBlockRenderer),
+        "braille" => Arc::new(BrailleRenderer),
+        _ => {
+            if caps.sixel {
+                sixel()
+            } else if caps.regis {
+                Arc::new(RegisRenderer)
+            } else if caps.drcs {
+                drcs()
+            } else if caps.unicode && caps.ansi_color {
+                Arc::new(HalfBlockRenderer)
+            } else if caps.unicode {
+                Arc::new(BrailleRenderer)
+            } else if caps.ansi_color {
+                Arc::new(AnsiColorRenderer)
+            } else {
+                Arc::new(AsciiRenderer)
+            }
+        }
+    }
+}
+
+/// Average the image over a `cols` x `rows` grid, one brightness per cell
+fn cell_brightness(image: &GrayImage, cols: u32, rows: u32) -> Vec<Vec<u8>> {
+    let (width, height) = image.dimensions();
+    let mut grid = Vec::with_capacity(rows as usize);
+
+    for row in 0..rows {
+        let y_start = row * height / rows.max(1);
+        let y_end = ((row + 1) * height / rows.max(1))
+            .max(y_start + 1)
+            .min(height);
+        let mut cells = Vec::with_capacity(cols as usize);
+
+        for col in 0..cols {
+            let x_start = col * width / cols.max(1);
+            let x_end = ((col + 1) * width / cols.max(1))
+                .max(x_start + 1)
+                .min(width);
+
+            let mut sum = 0u32;
+            let mut count = 0u32;
+            for y in y_start..y_end {
+                for x in x_start..x_end {
+                    sum += image.get_pixel(x, y)[0] as u32;
+                    count += 1;
+                }
+            }
+            cells.push(sum.checked_div(count).unwrap_or(0) as u8);
+        }
+        grid.push(cells);
+    }
+
+    grid
+}
+
+/// Resize an image to exactly `width` x `height` pixels (no-op if it already is)
+fn resize_exact(image: &GrayImage, width: u32, height: u32) -> GrayImage {
+    if image.dimensions() == (width, height) {
+        image.clone()
+    } else {
+        image::imageops::resize(image, width.max(1), height.max(1), FilterType::Triangle)
+    }
+}
+
+/// Scale an image to fit within `max_width` x `max_height`, keeping its aspect ratio.
+/// Images that are already the fitted size are returned unchanged.
+fn fit_image(image: &GrayImage, max_width: u32, max_height: u32) -> GrayImage {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return image.clone();
+    }
+
+    let scale = (max_width as f32 / w as f32).min(max_height as f32 / h as f32);
+    let width = ((w as f32 * scale) as u32).max(1);
+    let height = ((h as f32 * scale) as u32).max(1);
+    if (width, height) == (w, h) {
+        image.clone()
+    } else {
+        image::imageops::resize(image, width, height, FilterType::Triangle)
+    }
+}
+
+/// Convert a brightness value (0-255) to an enhanced character (char, is_dec_graphics)
+fn brightness_to_enhanced_char(brightness: u8) -> (char, bool) {
+    // Don't invert for light-on-dark terminals
+    // Bright (255) -> Dense char (@) -> White pixel
+    // Dark (0) -> Space ( ) -> Black pixel
+
+    // Enhanced ramp mixing ASCII and DEC graphics
+    // 0: Space (ASCII)
+    // 1: Bullet (DEC) - Small dot
+    // 2: . (ASCII)
+    // 3: : (ASCII)
+    // 4: + (ASCII)
+    // 5: Checkerboard (DEC) - 50%
+    // 6: # (ASCII)
+    // 7: @ (ASCII)
+
+    match brightness {
+        0..=40 => (' ', false),
+        41..=70 => (DecGraphicsChar::Bullet.as_dec_char(), true),
+        71..=100 => ('.', false),
+        101..=130 => (':', false),
+        131..=160 => ('+', false),
+        161..=190 => (DecGraphicsChar::Checkerboard.as_dec_char(), true),
+        191..=220 => ('#', false),
+        _ => ('@', false),
+    }
+}
+
+/// ASCII characters mixed with DEC Special Graphics (VT100 compatible)
+pub struct AsciiRenderer;
+
+impl Renderer for AsciiRenderer {
+    fn name(&self) -> &'static str {
+        "ascii"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn shades(&self) -> Vec<u8> {
+        // One from each step of the ramp, with black and white at the ends
+        vec![0, 55, 85, 115, 145, 175, 205, 255]
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        cell_brightness(image, cols, rows)
+            .into_iter()
+            .map(|cells| {
+                let mut line = String::with_capacity(cells.len() + 10);
+                let mut current_is_dec = false;
+
+                for brightness in cells {
+                    let (char, is_dec) = brightness_to_enhanced_char(brightness);
+
+                    // Switch character set if needed
+                    if is_dec != current_is_dec {
+                        line.push_str(if is_dec { SHIFT_OUT } else { SHIFT_IN });
+                        current_is_dec = is_dec;
+                    }
+                    line.push(char);
+                }
+
+                // End line in normal character set
+                if current_is_dec {
+                    line.push_str(SHIFT_IN);
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// DRCS soft-font shading glyphs (VT220+)
+#[derive(Default)]
+pub struct DrcsRenderer {
+    pub font: Arc<DrcsFont>,
+}
+
+impl Renderer for DrcsRenderer {
+    fn name(&self) -> &'static str {
+        "drcs"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn shades(&self) -> Vec<u8> {
+        self.font.shades()
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        cell_brightness(image, cols, rows)
+            .into_iter()
+            .map(|cells| {
+                let mut line = String::with_capacity(cells.len() + 2);
+                line.push_str(SHIFT_OUT);
+                line.extend(cells.into_iter().map(|b| self.font.char_for(b)));
+                line.push_str(SHIFT_IN);
+                line
+            })
+            .collect()
+    }
+}
+
+/// Sixel bitmap graphics (VT340) with a configurable number of gray levels
+pub struct SixelRenderer {
+    pub shades: u8,
+}
+
+impl Renderer for SixelRenderer {
+    fn name(&self) -> &'static str {
+        "sixel"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (10, 18)
+    }
+
+    fn is_bitmap(&self) -> bool {
+        true
+    }
+
+    fn shades(&self) -> Vec<u8> {
+        // Rounded up so the encoder puts each in its own level
+        let top = self.shades.max(2) as u32 - 1;
+        (0..=top).map(|i| (i * 255).div_ceil(top) as u8).collect()
+    }
+
+    fn dots_per_cell(&self) -> (u32, u32) {
+        self.cell_size()
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        let (cell_w, cell_h) = self.cell_size();
+        let fitted = fit_image(image, cols * cell_w, rows * cell_h);
+        let config = SixelConfig {
+            gray_levels: self.shades,
+            ..Default::default()
+        };
+        // One sequence per row, each drawn where the cursor is put
+        encode_strips(&fitted, &config, cell_h)
+    }
+}
+
+/// ReGIS vector graphics, drawing the image as runs of horizontal lines in
+/// four intensities of the default monochrome palette
+pub struct RegisRenderer;
+
+/// ReGIS screen units per character cell (800x480 screen, 80x24 cells)
+const REGIS_CELL_WIDTH: u32 = 10;
+const REGIS_CELL_HEIGHT: u32 = 20;
+
+impl Renderer for RegisRenderer {
+    fn name(&self) -> &'static str {
+        "regis"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        // Each image pixel is drawn as a 2x2 block of screen units
+        (REGIS_CELL_WIDTH / 2, REGIS_CELL_HEIGHT / 2)
+    }
+
+    fn is_bitmap(&self) -> bool {
+        true
+    }
+
+    fn shades(&self) -> Vec<u8> {
+        vec![0, 85, 170, 255]
+    }
+
+    fn dots_per_cell(&self) -> (u32, u32) {
+        self.cell_size()
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        let (cell_w, cell_h) = self.cell_size();
+        let fitted = fit_image(image, cols * cell_w, rows * cell_h);
+        let (width, height) = fitted.dimensions();
+
+        // Draw inside the content area: row 2, column 2
+        let origin_x = REGIS_CELL_WIDTH;
+        let origin_y = REGIS_CELL_HEIGHT;
+
+        let mut output = String::from("\x1bP0p");
+        let mut current_level: Option<u8> = None;
+
+        for y in 0..height {
+            let mut x = 0;
+            while x < width {
+                let level = fitted.get_pixel(x, y)[0] / 64;
+                let run_start = x;
+                while x < width && fitted.get_pixel(x, y)[0] / 64 == level {
+                    x += 1;
+                }
+
+                if current_level != Some(level) {
+                    output.push_str(&format!("W(I{})", level));
+                    current_level = Some(level);
+                }
+
+                let x0 = origin_x + run_start * 2;
+                let x1 = origin_x + x * 2 - 1;
+                let sy = origin_y + y * 2;
+                output.push_str(&format!(
+                    "P[{},{}]V[{},{}]P[{},{}]V[{},{}]",
+                    x0,
+                    sy,
+                    x1,
+                    sy,
+                    x0,
+                    sy + 1,
+                    x1,
+                    sy + 1
+                ));
+            }
+        }
+
+        output.push_str("\x1b\\");
+        vec![output]
+    }
+}
+
+/// Grayscale blocks using the 24-step ANSI 256-color gray ramp (colors 232-255)
+pub struct AnsiColorRenderer;
+
+impl Renderer for AnsiColorRenderer {
+    fn name(&self) -> &'static str {
+        "ansi"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        cell_brightness(image, cols, rows)
+            .into_iter()
+            .map(|cells| {
+                let mut line = String::with_capacity(cells.len() * 4);
+                let mut current: Option<u8> = None;
+
+                for brightness in cells {
+                    let color = ansi_gray(brightness);
+                    if current != Some(color) {
+                        line.push_str(&format!("\x1b[48;5;{}m", color));
+                        current = Some(color);
+                    }
+                    line.push(' ');
+                }
+
+                line.push_str("\x1b[0m");
+                line
+            })
+            .collect()
+    }
+}
+
+/// Map a brightness (0-255) onto the 24-step ANSI gray ramp (colors 232-255)
+fn ansi_gray(brightness: u8) -> u8 {
+    232 + (brightness as u16 * 23 / 255) as u8
+}
+
+/// Unicode upper half blocks: the foreground colors the top pixel and the
+/// background colors the bottom one, doubling vertical resolution
+pub struct HalfBlockRenderer;
+
+impl Renderer for HalfBlockRenderer {
+    fn name(&self) -> &'static str {
+        "halfblock"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        let pixels = resize_exact(image, cols, rows * 2);
+
+        (0..rows)
+            .map(|row| {
+                let mut line = String::with_capacity(cols as usize * 4);
+                let mut current: Option<(u8, u8)> = None;
+
+                for col in 0..cols {
+                    let top = ansi_gray(pixels.get_pixel(col, row * 2)[0]);
+                    let bottom = ansi_gray(pixels.get_pixel(col, row * 2 + 1)[0]);
+                    if current != Some((top, bottom)) {
+                        line.push_str(&format!("\x1b[0;38;5;{};48;5;{}m", top, bottom));
+                        current = Some((top, bottom));
+                    }
+                    line.push('\u{2580}');
+                }
+
+                line.push_str("\x1b[0m");
+                line
+            })
+            .collect()
+    }
+}
+
+/// Unicode Braille patterns: each cell holds a 2x4 grid of dots, ordered-dithered
+/// so midtones come out as dot density. Needs no color support.
+pub struct BrailleRenderer;
+
+impl Renderer for BrailleRenderer {
+    fn name(&self) -> &'static str {
+        "braille"
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        (2, 4)
+    }
+
+    fn shades(&self) -> Vec<u8> {
+        vec![0, 255]
+    }
+
+    fn dots_per_cell(&self) -> (u32, u32) {
+        self.cell_size()
+    }
+
+    fn render(&self, image: &GrayImage, cols: u32, rows: u32) -> Vec<String> {
+        // Dot bit for (x, y) within a cell, per the Unicode Braille block layout
+        const DOT_BITS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let pixels = resize_exact(image, cols * 2, rows * 4);
+
+        (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        let mut bits = 0;
+                        for (dy, row_bits) in DOT_BITS.iter().enumerate() {
+                            for (dx, bit) in row_bits.iter().enumerate() {
+                                let x = col * 2 + dx as u32;
+                                let y = row * 4 + dy as u32;
+                                let threshold = BAYER_4X4[(y % 4) as usize][(x % 4) as usize];
+                                if pixels.get_pixel(x, y)[0] > threshold {
+                                    bits |= bit;
+                                }
+                            }
+                        }
+                        char::from_u32(0x2800 + bits).unwrap_or(' ')
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::brightness_to_drcs_char;
+
+    fn gradient(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, _| image::Luma([(x * 255 / width) as u8]))
+    }
+
+    #[test]
+    fn test_da1_parsing() {
+        let caps = TerminalCaps::from_da1(b"\x1b[?63;1;2;3;4;7;22c").unwrap();
+        assert!(caps.regis && caps.sixel && caps.drcs && caps.ansi_color);
+
+        let caps = TerminalCaps::from_da1(b"\x1b[?62;1;6c").unwrap();
+        assert_eq!(caps, TerminalCaps::default());
+
+        assert!(TerminalCaps::from_da1(b"garbage").is_none());
+    }
+
+    #[test]
+    fn test_select_renderer() {
+        let font = Arc::new(DrcsFont::default());
+        let vt340 = TerminalCaps::from_mode("vt340");
+        assert_eq!(select_renderer("auto", &vt340, 8, &font).name(), "sixel");
+        assert_eq!(select_renderer("ascii", &vt340, 8, &font).name(), "ascii");
+
+        let vt220 = TerminalCaps::from_mode("vt220");
+        assert_eq!(select_renderer("auto", &vt220, 8, &font).name(), "drcs");
+        let vt525 = TerminalCaps::from_mode("vt525");
+        assert_eq!(select_renderer("auto", &vt525, 8, &font).name(), "drcs");
+        let ansi = TerminalCaps::from_mode("ansi");
+        assert_eq!(select_renderer("auto", &ansi, 8, &font).name(), "ansi");
+
+        let vt100 = TerminalCaps::from_mode("vt100");
+        assert_eq!(select_renderer("auto", &vt100, 8, &font).name(), "ascii");
+
+        let xterm = TerminalCaps::from_mode("xterm");
+        assert_eq!(
+            select_renderer("auto", &xterm, 8, &font).name(),
+            "halfblock"
+        );
+        let mono = TerminalCaps {
+            unicode: true,
+            ..Default::default()
+        };
+        assert_eq!(select_renderer("auto", &mono, 8, &font).name(), "braille");
+    }
+
+    #[test]
+    fn test_shades_are_distinct() {
+        let ascii: Vec<_> = AsciiRenderer
+            .shades()
+            .into_iter()
+            .map(brightness_to_enhanced_char)
+            .collect();
+        let drcs: Vec<_> = DrcsRenderer::default()
+            .shades()
+            .into_iter()
+            .map(brightness_to_drcs_char)
+            .collect();
+        // The sixel encoder's level for each of its shades
+        let sixel: Vec<_> = SixelRenderer { shades: 7 }
+            .shades()
+            .into_iter()
+            .map(|s| s as u16 * 6 / 255)
+            .collect();
+        assert_eq!(ascii.len(), 8);
+        assert_eq!(drcs, vec![' ', '!', '"', '#', '$']);
+        assert_eq!(sixel, (0..7).collect::<Vec<_>>());
+        for (i, c) in ascii.iter().enumerate() {
+            assert!(!ascii[..i].contains(c));
+        }
+    }
+
+    #[test]
+    fn test_cell_brightness_grid() {
+        let grid = cell_brightness(&gradient(40, 10), 4, 5);
+        assert_eq!(grid.len(), 5);
+        assert!(grid.iter().all(|row| row.len() == 4));
+        // Brightness increases left to right
+        assert!(grid[0][0] < grid[0][3]);
+    }
+
+    #[test]
+    fn test_character_renderers_row_count() {
+        let image = gradient(20, 8);
+        for renderer in [
+            &AsciiRenderer as &dyn Renderer,
+            &DrcsRenderer::default(),
+            &AnsiColorRenderer,
+            &HalfBlockRenderer,
+            &BrailleRenderer,
+        ] {
+            let lines = renderer.render(&image, 20, 4);
+            assert_eq!(lines.len(), 4, "{} row count", renderer.name());
+        }
+    }
+
+    #[test]
+    fn test_drcs_renderer_shifts() {
+        let lines = DrcsRenderer::default().render(&gradient(10, 2), 10, 1);
+        assert!(lines[0].starts_with(SHIFT_OUT));
+        assert!(lines[0].ends_with(SHIFT_IN));
+    }
+
+    #[test]
+    fn test_braille_extremes() {
+        let black = GrayImage::new(4, 8);
+        let white = GrayImage::from_pixel(4, 8, image::Luma([255]));
+        assert_eq!(
+            BrailleRenderer.render(&black, 2, 2),
+            vec!["\u{2800}\u{2800}"; 2]
+        );
+        assert_eq!(
+            BrailleRenderer.render(&white, 2, 2),
+            vec!["\u{28FF}\u{28FF}"; 2]
+        );
+    }
+
+    #[test]
+    fn test_halfblock_colors() {
+        // Top pixel white, bottom pixel black
+        let image = GrayImage::from_fn(1, 2, |_, y| image::Luma([if y == 0 { 255 } else { 0 }]));
+        let lines = HalfBlockRenderer.render(&image, 1, 1);
+        assert_eq!(lines, vec!["\x1b[0;38;5;255;48;5;232m\u{2580}\x1b[0m"]);
+    }
+
+    #[test]
+    fn test_bitmap_renderers_sequences() {
+        let image = gradient(100, 36);
+        for (renderer, sequences) in [
+            (&SixelRenderer { shades: 4 } as &dyn Renderer, 2),
+            (&RegisRenderer, 1),
+        ] {
+            let lines = renderer.render(&image, 10, 2);
+            assert_eq!(lines.len(), sequences, "{}", renderer.name());
+            for line in lines {
+                assert!(line.starts_with("\x1bP"));
+                assert!(line.ends_with("\x1b\\"));
+            }
+        }
+    }
+}
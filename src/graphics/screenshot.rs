@@ -0,0 +1,218 @@
+//! Rasterize a cell grid into an image.
+//!
+//! Used by `/screenshot` to turn the shadow copy of the terminal screen into a
+//! PNG. Text is drawn with the embedded 5x7 font; DEC line drawing, DRCS
+//! shading and the Unicode block/Braille characters used by the renderers are
+//! drawn directly so the picture looks like the terminal rather than the font.
+
+use std::path::Path;
+
+use image::{ImageResult, Rgb, RgbImage};
+
+use super::cell::{Cell, CharMode, Frame};
+use super::font::{GLYPH_HEIGHT, GLYPH_WIDTH, glyph};
+
+/// Cell size in font pixels (the 5x7 glyph plus spacing, like a VT220 cell)
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 12;
+/// Each font pixel becomes SCALE x SCALE image pixels
+const SCALE: u32 = 2;
+
+/// Default foreground (ANSI 7) and background (ANSI 0)
+const DEFAULT_FG: u8 = 7;
+const DEFAULT_BG: u8 = 0;
+
+/// Rasterize a frame into an RGB image
+pub fn rasterize(frame: &Frame) -> RgbImage {
+    let cols = frame.width() as u32;
+    let rows = frame.height() as u32;
+    let mut image = RgbImage::new(cols * CELL_WIDTH * SCALE, rows * CELL_HEIGHT * SCALE);
+
+    for (y, row) in frame.rows.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            draw_cell(&mut image, x as u32, y as u32, cell);
+        }
+    }
+    image
+}
+
+/// Rasterize a frame and save it as a PNG
+pub fn save_png(frame: &Frame, path: &Path) -> ImageResult<()> {
+    rasterize(frame).save_with_format(path, image::ImageFormat::Png)
+}
+
+fn draw_cell(image: &mut RgbImage, col: u32, row: u32, cell: &Cell) {
+    let fg = ansi_rgb(cell.fg.unwrap_or(DEFAULT_FG));
+    let bg = ansi_rgb(cell.bg.unwrap_or(DEFAULT_BG));
+
+    for py in 0..CELL_HEIGHT {
+        for px in 0..CELL_WIDTH {
+            let color = match pixel_coverage(cell, px, py) {
+                0 => bg,
+                4 => fg,
+                level => blend(bg, fg, level),
+            };
+            for dy in 0..SCALE {
+                for dx in 0..SCALE {
+                    let x = (col * CELL_WIDTH + px) * SCALE + dx;
+                    let y = (row * CELL_HEIGHT + py) * SCALE + dy;
+                    image.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+/// How much foreground a font pixel of the cell shows, from 0 (none) to 4 (full)
+fn pixel_coverage(cell: &Cell, px: u32, py: u32) -> u8 {
+    let on = |b: bool| if b { 4 } else { 0 };
+
+    if cell.mode == CharMode::DecGraphics {
+        match cell.char {
+            // DRCS shading glyphs (see drcs.rs)
+            '!' => return 1,
+            '"' => return 2,
+            '#' => return 3,
+            '$' => return 4,
+            'a' => return on((px + py).is_multiple_of(2)),
+            c => {
+                if let Some(lines) = line_segments(c) {
+                    return on(on_line(lines, px, py));
+                }
+            }
+        }
+    }
+
+    match cell.char {
+        '\u{2580}' => on(py < CELL_HEIGHT / 2),
+        '\u{2584}' => on(py >= CELL_HEIGHT / 2),
+        '\u{2588}' => 4,
+        c @ '\u{2800}'..='\u{28ff}' => on(braille_dot(c as u32 - 0x2800, px, py)),
+        c => {
+            let (gx, gy) = (px.wrapping_sub(1), py.wrapping_sub(2));
+            if (gx as usize) < GLYPH_WIDTH && (gy as usize) < GLYPH_HEIGHT {
+                let bits = glyph(c)[gy as usize];
+                on(bits & (0x10 >> gx) != 0)
+            } else {
+                0
+            }
+        }
+    }
+}
+
+/// Line drawing characters as (up, down, left, right) arms from the cell centre
+fn line_segments(c: char) -> Option<(bool, bool, bool, bool)> {
+    Some(match c {
+        'j' => (true, false, true, false),
+        'k' => (false, true, true, false),
+        'l' => (false, true, false, true),
+        'm' => (true, false, false, true),
+        'n' => (true, true, true, true),
+        'q' => (false, false, true, true),
+        't' => (true, true, false, true),
+        'u' => (true, true, true, false),
+        'v' => (true, false, true, true),
+        'w' => (false, true, true, true),
+        'x' => (true, true, false, false),
+        _ => return None,
+    })
+}
+
+fn on_line((up, down, left, right): (bool, bool, bool, bool), px: u32, py: u32) -> bool {
+    let (cx, cy) = (CELL_WIDTH / 2, CELL_HEIGHT / 2);
+    (px == cx && ((up && py <= cy) || (down && py >= cy)))
+        || (py == cy && ((left && px <= cx) || (right && px >= cx)))
+}
+
+/// Braille dots are 2 wide and 4 tall; dot bits follow the Unicode layout
+fn braille_dot(bits: u32, px: u32, py: u32) -> bool {
+    const BITS: [[u32; 2]; 4] = [[0, 3], [1, 4], [2, 5], [6, 7]];
+    let dot_x = match px {
+        1..=2 => 0,
+        5..=6 => 1,
+        _ => return false,
+    };
+    let dot_y = match py {
+        1..=2 => 0,
+        4..=5 => 1,
+        7..=8 => 2,
+        10..=11 => 3,
+        _ => return false,
+    };
+    bits & (1 << BITS[dot_y][dot_x]) != 0
+}
+
+fn blend(bg: Rgb<u8>, fg: Rgb<u8>, level: u8) -> Rgb<u8> {
+    let mix = |b: u8, f: u8| ((b as u16 * (4 - level) as u16 + f as u16 * level as u16) / 4) as u8;
+    Rgb([mix(bg[0], fg[0]), mix(bg[1], fg[1]), mix(bg[2], fg[2])])
+}
+
+/// The standard xterm 256-colour palette
+fn ansi_rgb(n: u8) -> Rgb<u8> {
+    const BASIC: [[u8; 3]; 16] = [
+        [0, 0, 0],
+        [205, 0, 0],
+        [0, 205, 0],
+        [205, 205, 0],
+        [0, 0, 238],
+        [205, 0, 205],
+        [0, 205, 205],
+        [229, 229, 229],
+        [127, 127, 127],
+        [255, 0, 0],
+        [0, 255, 0],
+        [255, 255, 0],
+        [92, 92, 255],
+        [255, 0, 255],
+        [0, 255, 255],
+        [255, 255, 255],
+    ];
+    match n {
+        0..=15 => Rgb(BASIC[n as usize]),
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            Rgb([level(n / 36), level(n / 6 % 6), level(n % 6)])
+        }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            Rgb([gray, gray, gray])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rasterize_dimensions() {
+        let frame = Frame::with_dimensions(80, 24);
+        let image = rasterize(&frame);
+        assert_eq!(
+            image.dimensions(),
+            (80 * CELL_WIDTH * SCALE, 24 * CELL_HEIGHT * SCALE)
+        );
+    }
+
+    #[test]
+    fn test_glyph_and_line_drawing() {
+        // The centre column of 'I' is lit, its right edge isn't
+        let cell = Cell::ascii('I');
+        assert_eq!(pixel_coverage(&cell, 3, 4), 4);
+        assert_eq!(pixel_coverage(&cell, 7, 4), 0);
+
+        // DEC 'q' is a horizontal line, the same letter in ASCII is not
+        let line = Cell::dec_graphics('q');
+        assert_eq!(pixel_coverage(&line, 0, CELL_HEIGHT / 2), 4);
+        assert_eq!(pixel_coverage(&line, 0, 0), 0);
+        assert_eq!(pixel_coverage(&Cell::ascii('q'), 0, CELL_HEIGHT / 2), 0);
+    }
+
+    #[test]
+    fn test_ansi_rgb() {
+        assert_eq!(ansi_rgb(16), Rgb([0, 0, 0]));
+        assert_eq!(ansi_rgb(196), Rgb([255, 0, 0]));
+        assert_eq!(ansi_rgb(255), Rgb([238, 238, 238]));
+    }
+}
@@ -15,8 +15,16 @@
 //! - Enter Sixel mode: `ESC P <params> q <sixel-data> ESC \`
 //! - DCS (Device Control String) introducer: `ESC P` or `0x90`
 //! - ST (String Terminator): `ESC \` or `0x9C`
+//!
+//! ## Strips
+//!
+//! Video goes out as one sequence per character row ([`encode_strips`]), so
+//! a frame can be updated a row at a time: only the strips that changed
+//! since the last frame are sent again, each after a cursor move to its row.
+//! With someone sitting still, that is a fraction of the picture at a time.
 
-use image::{DynamicImage, GenericImageView, GrayImage, imageops::FilterType};
+use image::GrayImage;
+use image::imageops;
 
 /// DCS (Device Control String) introducer for Sixel
 pub const DCS: &str = "\x1bP";
@@ -24,11 +32,6 @@ pub const DCS: &str = "\x1bP";
 /// ST (String Terminator)
 pub const ST: &str = "\x1b\\";
 
-/// Pixels per terminal row for sixel output
-/// VT340: 10 scanlines per character cell
-/// Using 18 gives good size while fitting in 22-row display area (396 pixels)
-const PIXELS_PER_ROW: u32 = 18;
-
 /// Configuration for sixel encoding
 #[derive(Debug, Clone)]
 pub struct SixelConfig {
@@ -182,6 +185,20 @@ pub fn encode_grayscale(image: &GrayImage, config: &SixelConfig) -> String {
     output
 }
 
+/// Encode a grayscale image as one sixel sequence per `strip_height` pixel
+/// rows, top to bottom, each a complete image to be drawn on its own row
+pub fn encode_strips(image: &GrayImage, config: &SixelConfig, strip_height: u32) -> Vec<String> {
+    let (width, height) = image.dimensions();
+    let strip_height = strip_height.max(1);
+    (0..height.div_ceil(strip_height))
+        .map(|i| {
+            let top = i * strip_height;
+            let strip = imageops::crop_imm(image, 0, top, width, strip_height.min(height - top));
+            encode_grayscale(&strip.to_image(), config)
+        })
+        .collect()
+}
+
 /// Encode a run of identical characters using RLE
 fn encode_run(ch: char, count: u32) -> String {
     if count == 0 {
@@ -198,116 +215,6 @@ fn encode_run(ch: char, count: u32) -> String {
     }
 }
 
-/// Convert a DynamicImage to sixel format for terminal display.
-///
-/// # Arguments
-/// * `image` - The source image
-/// * `height_rows` - Target height in terminal rows
-/// * `display_width` - Maximum width in characters/pixels
-/// * `config` - Optional sixel configuration (uses default if None)
-///
-/// # Returns
-/// A string containing the complete sixel sequence
-pub fn image_to_sixel(
-    image: &DynamicImage,
-    height_rows: u32,
-    display_width: usize,
-    config: Option<&SixelConfig>,
-) -> String {
-    let default_config = SixelConfig::default();
-    let config = config.unwrap_or(&default_config);
-
-    // Calculate target dimensions in pixels
-    // Each terminal row is approximately PIXELS_PER_ROW pixels
-    let target_height = height_rows * PIXELS_PER_ROW;
-
-    // VT340 character cell is approximately 10 pixels wide
-    // So display_width columns = display_width * 10 pixels
-    const PIXELS_PER_COL: u32 = 10;
-    // Use nearly full width, leaving small margin for UI
-    let max_width_pixels = ((display_width.saturating_sub(2)) as u32) * PIXELS_PER_COL;
-
-    // Calculate aspect-correct width
-    let (img_w, img_h) = image.dimensions();
-    let aspect = img_w as f32 / img_h as f32;
-
-    // For wide displays, ensure at least 16:9
-    let aspect = if display_width > 100 {
-        aspect.max(1.77)
-    } else {
-        aspect
-    };
-
-    let ideal_width = (target_height as f32 * aspect) as u32;
-    let target_width = ideal_width.min(max_width_pixels);
-
-    // Resize image to target dimensions
-    let resized = image.resize_to_fill(target_width, target_height, FilterType::Triangle);
-
-    // Convert to grayscale and enhance contrast
-    let gray = resized.to_luma8();
-    let enhanced = enhance_contrast(&gray);
-
-    encode_grayscale(&enhanced, config)
-}
-
-/// Apply contrast enhancement to a grayscale image
-/// Uses histogram stretching + S-curve for extra punch
-fn enhance_contrast(image: &GrayImage) -> GrayImage {
-    // Find min and max pixel values (use percentiles to ignore outliers)
-    let mut histogram = [0u32; 256];
-    let total_pixels = image.width() * image.height();
-
-    for pixel in image.pixels() {
-        histogram[pixel[0] as usize] += 1;
-    }
-
-    // Find 2nd and 98th percentile for robust stretching
-    let low_threshold = total_pixels / 50; // 2%
-    let high_threshold = total_pixels - (total_pixels / 50); // 98%
-
-    let mut count = 0u32;
-    let mut min_val: u8 = 0;
-    let mut max_val: u8 = 255;
-
-    for (i, &freq) in histogram.iter().enumerate() {
-        count += freq;
-        if count >= low_threshold && min_val == 0 {
-            min_val = i as u8;
-        }
-        if count >= high_threshold {
-            max_val = i as u8;
-            break;
-        }
-    }
-
-    // Avoid division by zero and extreme stretching of noise
-    if max_val <= min_val {
-        max_val = min_val + 1;
-    }
-
-    // Prevent over-stretching of dark images
-    if max_val < 100 {
-        max_val = 100;
-    }
-
-    let range = (max_val - min_val) as f32;
-    let mut result = image.clone();
-
-    for pixel in result.pixels_mut() {
-        let val = pixel[0];
-        let clamped = val.max(min_val).min(max_val);
-        let normalized = (clamped - min_val) as f32 / range;
-
-        // S-curve: 3x^2 - 2x^3 (smoothstep)
-        let curved = normalized * normalized * (3.0 - 2.0 * normalized);
-
-        pixel[0] = (curved * 255.0) as u8;
-    }
-
-    result
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -359,6 +266,22 @@ mod tests {
         assert!(result.contains("#3"));
     }
 
+    #[test]
+    fn test_encode_strips() {
+        let img = GrayImage::from_fn(4, 40, |_, y| image::Luma([if y < 18 { 0 } else { 255 }]));
+        let config = SixelConfig::default();
+        let strips = encode_strips(&img, &config, 18);
+        assert_eq!(strips.len(), 3);
+        assert!(strips.iter().all(|s| s.starts_with(DCS) && s.ends_with(ST)));
+        assert!(strips[0].contains("\"1;1;4;18"));
+        assert!(strips[2].contains("\"1;1;4;4"));
+        // Rows of the same picture come out the same
+        assert_eq!(
+            strips[1],
+            encode_grayscale(&GrayImage::from_pixel(4, 18, image::Luma([255])), &config)
+        );
+    }
+
     #[test]
     fn test_empty_image() {
         let img = GrayImage::new(0, 0);
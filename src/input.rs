@@ -2,9 +2,251 @@
 //!
 //! This module handles parsing of keyboard input from the serial terminal,
 //! including escape sequences for special keys like arrows and Page Up/Down.
+//!
+//! Not every keyboard sends ASCII. A German LK201 on a VT220 in national
+//! mode sends `[` for Ä and `~` for ß, one in 8-bit mode sends DEC
+//! Multinational codes, and protocol converters have quirks of their own. A
+//! [`KeyMap`] (`keyboard` under `[terminal]`, plus any `[keyboard]` entries)
+//! turns each byte into what the key meant before it's parsed. Escape
+//! sequences are left alone, since their bytes are the same on every
+//! keyboard.
+//!
+//! A US keyboard can still type accented letters with [`Compose`], as on a
+//! VT220: the compose key (Ctrl+K unless `[keys]` moves it) and two
+//! characters, in either order, so `e` and `'` make é and `"` and `u` make ü.
+//!
+//! The Escape key sends the same ESC that starts every sequence, so it's told
+//! apart by what follows: nothing for [`ESCAPE_TIMEOUT`] is the key itself.
+//! A sequence cut short by line noise is given up on the same way, rather
+//! than eating the next keys typed.
 
-/// Parsed escape sequences from terminal input
+use std::time::{Duration, Instant};
+
+use crate::terminal::from_supplemental;
+
+/// How long an ESC waits for the rest of its sequence. The bytes of one
+/// arrive together even at 300 baud, so after this a lone ESC is the Escape
+/// key.
+pub const ESCAPE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The longest sequence waited for, long enough for a mouse or locator
+/// report; anything longer is line noise
+const MAX_SEQUENCE: usize = 24;
+
+/// Keyboards with a built-in translation table
+pub const KEYBOARDS: &[&str] = &["us", "uk", "german", "french", "swedish", "multinational"];
+
+/// What the national replacement character sets put in place of ASCII
+const UK: &[(u8, char)] = &[(b'#', '£')];
+const GERMAN: &[(u8, char)] = &[
+    (b'@', '§'),
+    (b'[', 'Ä'),
+    (b'\\', 'Ö'),
+    (b']', 'Ü'),
+    (b'{', 'ä'),
+    (b'|', 'ö'),
+    (b'}', 'ü'),
+    (b'~', 'ß'),
+];
+const FRENCH: &[(u8, char)] = &[
+    (b'#', '£'),
+    (b'@', 'à'),
+    (b'[', '°'),
+    (b'\\', 'ç'),
+    (b']', '§'),
+    (b'{', 'é'),
+    (b'|', 'ù'),
+    (b'}', 'è'),
+    (b'~', '¨'),
+];
+const SWEDISH: &[(u8, char)] = &[
+    (b'@', 'É'),
+    (b'[', 'Ä'),
+    (b'\\', 'Ö'),
+    (b']', 'Å'),
+    (b'^', 'Ü'),
+    (b'`', 'é'),
+    (b'{', 'ä'),
+    (b'|', 'ö'),
+    (b'}', 'å'),
+    (b'~', 'ü'),
+];
+
+/// What each byte from the keyboard stands for
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    /// The character for each byte (None: the byte means itself)
+    table: [Option<char>; 256],
+}
+
+impl Default for KeyMap {
+    /// Every byte as itself (a US keyboard)
+    fn default() -> Self {
+        Self { table: [None; 256] }
+    }
+}
+
+impl KeyMap {
+    /// A built-in table by name
+    pub fn builtin(name: &str) -> Option<Self> {
+        let mut keymap = Self::default();
+        let pairs = match name {
+            "us" => &[][..],
+            "uk" => UK,
+            "german" => GERMAN,
+            "french" => FRENCH,
+            "swedish" => SWEDISH,
+            "multinational" => {
+                for code in 0xA0..=0xFF {
+                    keymap.table[code as usize] = from_supplemental(code);
+                }
+                &[][..]
+            }
+            _ => return None,
+        };
+        for &(byte, c) in pairs {
+            keymap.set(byte, c);
+        }
+        Some(keymap)
+    }
+
+    /// Translate `byte` to `c` (a control character to make it act as that
+    /// key, e.g. `\r` for Enter)
+    pub fn set(&mut self, byte: u8, c: char) {
+        self.table[byte as usize] = Some(c);
+    }
+
+    /// Parse a `[keyboard]` entry: the byte received, as `0x5b`, a decimal
+    /// number or the character itself, and what it stands for, as a
+    /// character or a code in the same forms
+    pub fn parse_entry(byte: &str, meaning: &str) -> Result<(u8, char), String> {
+        let code = |s: &str| -> Option<u32> {
+            let s = s.trim();
+            if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                return u32::from_str_radix(hex, 16).ok();
+            }
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_ascii_digit() => Some(c as u32),
+                _ => s.parse().ok(),
+            }
+        };
+        let from = code(byte)
+            .and_then(|n| u8::try_from(n).ok())
+            .ok_or_else(|| format!("'{}' is not a byte", byte))?;
+        let to = code(meaning)
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("'{}' is not a character", meaning))?;
+        Ok((from, to))
+    }
+}
+
+/// Compose sequences: a mark and the letters it goes on
+const COMPOSE_MARKS: &[(char, &str, &str)] = &[
+    ('\'', "AEIOUYaeiouy", "ÁÉÍÓÚÝáéíóúý"),
+    ('`', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    ('^', "AEIOUaeiou", "ÂÊÎÔÛâêîôû"),
+    ('"', "AEIOUaeiouy", "ÄËÏÖÜäëïöüÿ"),
+    ('~', "ANOano", "ÃÑÕãñõ"),
+    (',', "Cc", "Çç"),
+    ('*', "Aa", "Åå"),
+    ('/', "Oo", "Øø"),
+];
+
+/// Compose sequences for the letters and symbols without a mark
+const COMPOSE_PAIRS: &[(char, char, char)] = &[
+    ('s', 's', 'ß'),
+    ('A', 'E', 'Æ'),
+    ('a', 'e', 'æ'),
+    ('O', 'E', 'Œ'),
+    ('o', 'e', 'œ'),
+    ('<', '<', '«'),
+    ('>', '>', '»'),
+    ('!', '!', '¡'),
+    ('?', '?', '¿'),
+    ('c', 'o', '©'),
+    ('r', 'o', '®'),
+    ('s', 'o', '§'),
+    ('p', '!', '¶'),
+    ('0', '^', '°'),
+    ('x', 'x', '×'),
+    ('-', ':', '÷'),
+    ('1', '2', '½'),
+    ('1', '4', '¼'),
+    ('L', '-', '£'),
+    ('Y', '=', '¥'),
+    ('C', '|', '¢'),
+    ('E', '=', '€'),
+];
+
+/// The character two composed ones make, in either order
+pub fn compose(a: char, b: char) -> Option<char> {
+    let one_way = |mark: char, base: char| {
+        COMPOSE_MARKS
+            .iter()
+            .find(|(m, _, _)| *m == mark)
+            .and_then(|(_, bases, results)| {
+                let i = bases.chars().position(|c| c == base)?;
+                results.chars().nth(i)
+            })
+            .or_else(|| {
+                COMPOSE_PAIRS
+                    .iter()
+                    .find(|(x, y, _)| (*x, *y) == (mark, base))
+                    .map(|(_, _, c)| *c)
+            })
+    };
+    one_way(a, b).or_else(|| one_way(b, a))
+}
+
+/// Where a compose sequence has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Composed {
+    /// Waiting for the second character
+    Pending,
+    /// The sequence made this
+    Char(char),
+    /// The two characters don't make anything
+    Invalid,
+}
+
+/// A compose sequence being typed
+#[derive(Debug, Default)]
+pub struct Compose {
+    /// None when not composing; Some(None) after the compose key
+    state: Option<Option<char>>,
+}
+
+impl Compose {
+    /// The compose key was pressed
+    pub fn start(&mut self) {
+        self.state = Some(None);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Give up on the sequence
+    pub fn cancel(&mut self) {
+        self.state = None;
+    }
+
+    /// The next character of the sequence
+    pub fn feed(&mut self, c: char) -> Composed {
+        match self.state.take() {
+            Some(None) => {
+                self.state = Some(Some(c));
+                Composed::Pending
+            }
+            Some(Some(first)) => compose(first, c).map_or(Composed::Invalid, Composed::Char),
+            None => Composed::Char(c),
+        }
+    }
+}
+
+/// Parsed escape sequences from terminal input
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum EscapeSequence {
     /// Page Up key (ESC [ 5 ~)
     PageUp,
@@ -18,6 +260,31 @@ pub enum EscapeSequence {
     ArrowRight,
     /// Left arrow key (ESC [ D)
     ArrowLeft,
+    /// Ctrl+Up arrow (ESC [ 1 ; 5 A), on emulators that send it
+    CtrlArrowUp,
+    /// Ctrl+Down arrow (ESC [ 1 ; 5 B)
+    CtrlArrowDown,
+    /// Home (ESC [ 1 ~, ESC [ H or ESC O H)
+    Home,
+    /// End (ESC [ 4 ~, ESC [ F or ESC O F)
+    End,
+    /// Insert (ESC [ 2 ~)
+    Insert,
+    /// Delete, the VT220 editing keypad's Remove (ESC [ 3 ~)
+    Delete,
+    /// F1 to F12 (ESC O P to ESC O S, ESC [ 11 ~ to ESC [ 24 ~)
+    Function(u8),
+    /// Start of a bracketed paste (ESC [ 200 ~)
+    PasteStart,
+    /// End of a bracketed paste (ESC [ 201 ~)
+    PasteEnd,
+    /// Alt+digit (ESC 0-9), to switch chat windows
+    AltDigit(u8),
+    /// The Escape key: an ESC with nothing after it
+    Escape,
+    /// The left mouse button pressed at a screen position (1-based), from
+    /// xterm mouse reporting or a DEC locator
+    Click { row: usize, col: usize },
     /// Unknown or incomplete sequence
     Unknown,
 }
@@ -37,6 +304,8 @@ pub enum InputEvent {
     CtrlC,
     /// Ctrl+R (Refresh)
     CtrlR,
+    /// Ctrl+F (Search the chat)
+    CtrlF,
     /// Escape sequence (arrow keys, page up/down, etc.)
     #[allow(dead_code)]
     Escape(EscapeSequence),
@@ -52,12 +321,14 @@ pub enum InputEvent {
 #[derive(Debug, Default)]
 pub struct EscapeParser {
     buffer: Vec<u8>,
+    /// When the last byte of the sequence so far came
+    last_byte: Option<Instant>,
 }
 
 impl EscapeParser {
     /// Create a new escape parser
     pub fn new() -> Self {
-        Self { buffer: Vec::new() }
+        Self::default()
     }
 
     /// Check if we're in the middle of parsing an escape sequence
@@ -77,65 +348,139 @@ impl EscapeParser {
     /// `None` if more bytes are needed.
     pub fn feed(&mut self, byte: u8) -> Option<EscapeSequence> {
         self.buffer.push(byte);
+        self.last_byte = Some(Instant::now());
 
-        // Check for complete sequences (minimum 3 bytes for arrow keys)
-        if self.buffer.len() >= 3 {
-            let seq = &self.buffer[..];
-
-            // Page Up: ESC [ 5 ~
-            if seq == b"\x1b[5~" {
-                self.buffer.clear();
-                return Some(EscapeSequence::PageUp);
-            }
-
-            // Page Down: ESC [ 6 ~
-            if seq == b"\x1b[6~" {
-                self.buffer.clear();
-                return Some(EscapeSequence::PageDown);
-            }
-
-            // Arrow Up: ESC [ A
-            if seq == b"\x1b[A" {
-                self.buffer.clear();
-                return Some(EscapeSequence::ArrowUp);
-            }
-
-            // Arrow Down: ESC [ B
-            if seq == b"\x1b[B" {
-                self.buffer.clear();
-                return Some(EscapeSequence::ArrowDown);
-            }
+        // Alt+digit: ESC and the digit, with nothing after
+        if self.buffer.len() == 2 && self.buffer[1].is_ascii_digit() {
+            self.buffer.clear();
+            return Some(EscapeSequence::AltDigit(byte - b'0'));
+        }
 
-            // Arrow Right: ESC [ C
-            if seq == b"\x1b[C" {
-                self.buffer.clear();
-                return Some(EscapeSequence::ArrowRight);
+        let sequence = match &self.buffer[..] {
+            [_] => return None,
+            // Escape pressed twice: the first is the key, the second may
+            // start a sequence
+            [_, 0x1b] => {
+                self.buffer.remove(0);
+                return Some(EscapeSequence::Escape);
             }
-
-            // Arrow Left: ESC [ D
-            if seq == b"\x1b[D" {
-                self.buffer.clear();
-                return Some(EscapeSequence::ArrowLeft);
+            // SS3 and one byte: the arrows in application mode, F1-F4, and
+            // Home and End on some emulators
+            [_, b'O'] => return None,
+            [_, b'O', last] => match last {
+                b'A' => EscapeSequence::ArrowUp,
+                b'B' => EscapeSequence::ArrowDown,
+                b'C' => EscapeSequence::ArrowRight,
+                b'D' => EscapeSequence::ArrowLeft,
+                b'H' => EscapeSequence::Home,
+                b'F' => EscapeSequence::End,
+                b'P'..=b'S' => EscapeSequence::Function(last - b'P' + 1),
+                _ => EscapeSequence::Unknown,
+            },
+            // An xterm mouse report without SGR coding: ESC [ M and three
+            // bytes, each offset by 32
+            [_, b'[', b'M'] | [_, b'[', b'M', _] | [_, b'[', b'M', _, _] => return None,
+            [_, b'[', b'M', button, col, row] => {
+                if button.wrapping_sub(32) & 0x63 == 0 {
+                    EscapeSequence::Click {
+                        row: row.saturating_sub(32) as usize,
+                        col: col.saturating_sub(32) as usize,
+                    }
+                } else {
+                    EscapeSequence::Unknown
+                }
             }
+            // CSI, parameters and a final byte
+            [_, b'[', params @ .., last @ 0x40..=0x7e] => csi_key(params, *last),
+            [_, b'[', ..] if self.buffer.len() <= MAX_SEQUENCE => return None,
+            _ => EscapeSequence::Unknown,
+        };
+        self.buffer.clear();
+        Some(sequence)
+    }
 
-            // Check for end of unknown sequence
-            let last = seq[seq.len() - 1];
-            if seq.len() > 6 || last == b'~' || (b'A'..=b'D').contains(&last) {
-                self.buffer.clear();
-                return Some(EscapeSequence::Unknown);
-            }
+    /// Give up waiting once [`ESCAPE_TIMEOUT`] has passed without a byte:
+    /// a lone ESC is the Escape key, and a sequence cut short is dropped as
+    /// `Unknown`
+    pub fn expire(&mut self, now: Instant) -> Option<EscapeSequence> {
+        let last = self.last_byte.filter(|_| self.is_parsing())?;
+        if now.saturating_duration_since(last) < ESCAPE_TIMEOUT {
+            return None;
         }
+        let sequence = if self.buffer.len() == 1 {
+            EscapeSequence::Escape
+        } else {
+            EscapeSequence::Unknown
+        };
+        self.buffer.clear();
+        Some(sequence)
+    }
+}
 
-        // Need more bytes
-        None
+/// The key a CSI sequence stands for
+fn csi_key(params: &[u8], last: u8) -> EscapeSequence {
+    match (params, last) {
+        (b"", b'A') => EscapeSequence::ArrowUp,
+        (b"", b'B') => EscapeSequence::ArrowDown,
+        (b"", b'C') => EscapeSequence::ArrowRight,
+        (b"", b'D') => EscapeSequence::ArrowLeft,
+        (b"1;5", b'A') => EscapeSequence::CtrlArrowUp,
+        (b"1;5", b'B') => EscapeSequence::CtrlArrowDown,
+        (b"" | b"1", b'H') => EscapeSequence::Home,
+        (b"" | b"1", b'F') => EscapeSequence::End,
+        // VT220 editing keypad and function keys: ESC [ n ~
+        (_, b'~') => match std::str::from_utf8(params)
+            .ok()
+            .and_then(|n| n.parse().ok())
+        {
+            Some(1 | 7) => EscapeSequence::Home,
+            Some(2) => EscapeSequence::Insert,
+            Some(3) => EscapeSequence::Delete,
+            Some(4 | 8) => EscapeSequence::End,
+            Some(5) => EscapeSequence::PageUp,
+            Some(6) => EscapeSequence::PageDown,
+            Some(n @ 11..=15) => EscapeSequence::Function(n - 10),
+            Some(n @ 17..=21) => EscapeSequence::Function(n - 11),
+            Some(n @ 23..=24) => EscapeSequence::Function(n - 12),
+            Some(200) => EscapeSequence::PasteStart,
+            Some(201) => EscapeSequence::PasteEnd,
+            _ => EscapeSequence::Unknown,
+        },
+        // SGR mouse report: ESC [ < button ; col ; row M, button 0 for a
+        // left press
+        ([b'<', rest @ ..], b'M') => match numbers(rest)[..] {
+            [Some(0), Some(col), Some(row)] => EscapeSequence::Click { row, col },
+            _ => EscapeSequence::Unknown,
+        },
+        // DEC locator report: ESC [ event ; buttons ; row ; col ; page & w,
+        // event 2 for a left press
+        ([rest @ .., b'&'], b'w') => match numbers(rest)[..] {
+            [Some(2), _, Some(row), Some(col), ..] => EscapeSequence::Click { row, col },
+            _ => EscapeSequence::Unknown,
+        },
+        _ => EscapeSequence::Unknown,
     }
 }
 
-/// Parse a single byte into an input event
+/// The numbers in a sequence's `;`-separated parameters
+fn numbers(params: &[u8]) -> Vec<Option<usize>> {
+    params
+        .split(|&b| b == b';')
+        .map(|n| std::str::from_utf8(n).ok()?.parse().ok())
+        .collect()
+}
+
+/// Parse a single byte into an input event, after translating it with
+/// `keymap`
 ///
 /// Note: This does not handle escape sequences - use `EscapeParser` for those.
 /// Returns `InputEvent::EscapeStart` when an escape byte is encountered.
-pub fn parse_byte(byte: u8) -> InputEvent {
+pub fn parse_byte(byte: u8, keymap: &KeyMap) -> InputEvent {
+    let byte = match keymap.table[byte as usize] {
+        Some(c) if !c.is_ascii() => return InputEvent::Char(c),
+        Some(c) => c as u8,
+        None => byte,
+    };
     match byte {
         0x1b => InputEvent::EscapeStart,
         b'\r' | b'\n' => InputEvent::Enter,
@@ -143,6 +488,7 @@ pub fn parse_byte(byte: u8) -> InputEvent {
         0x09 => InputEvent::Tab,
         0x03 => InputEvent::CtrlC,
         0x12 => InputEvent::CtrlR,
+        0x06 => InputEvent::CtrlF,
         0x20 => InputEvent::Space,
         b if (0x21..0x7f).contains(&b) => InputEvent::Char(b as char),
         _ => InputEvent::Ignore,
@@ -155,20 +501,70 @@ mod tests {
 
     #[test]
     fn test_parse_printable() {
-        assert_eq!(parse_byte(b'a'), InputEvent::Char('a'));
-        assert_eq!(parse_byte(b'Z'), InputEvent::Char('Z'));
-        assert_eq!(parse_byte(b'5'), InputEvent::Char('5'));
+        let us = KeyMap::default();
+        assert_eq!(parse_byte(b'a', &us), InputEvent::Char('a'));
+        assert_eq!(parse_byte(b'Z', &us), InputEvent::Char('Z'));
+        assert_eq!(parse_byte(b'5', &us), InputEvent::Char('5'));
     }
 
     #[test]
     fn test_parse_control() {
-        assert_eq!(parse_byte(b'\r'), InputEvent::Enter);
-        assert_eq!(parse_byte(b'\n'), InputEvent::Enter);
-        assert_eq!(parse_byte(0x7f), InputEvent::Backspace);
-        assert_eq!(parse_byte(0x08), InputEvent::Backspace);
-        assert_eq!(parse_byte(0x09), InputEvent::Tab);
-        assert_eq!(parse_byte(0x03), InputEvent::CtrlC);
-        assert_eq!(parse_byte(0x12), InputEvent::CtrlR);
+        let us = KeyMap::default();
+        assert_eq!(parse_byte(b'\r', &us), InputEvent::Enter);
+        assert_eq!(parse_byte(b'\n', &us), InputEvent::Enter);
+        assert_eq!(parse_byte(0x7f, &us), InputEvent::Backspace);
+        assert_eq!(parse_byte(0x08, &us), InputEvent::Backspace);
+        assert_eq!(parse_byte(0x09, &us), InputEvent::Tab);
+        assert_eq!(parse_byte(0x03, &us), InputEvent::CtrlC);
+        assert_eq!(parse_byte(0x12, &us), InputEvent::CtrlR);
+        assert_eq!(parse_byte(0x06, &us), InputEvent::CtrlF);
+    }
+
+    #[test]
+    fn test_compose() {
+        assert_eq!(compose('e', '\''), Some('é'));
+        assert_eq!(compose('"', 'u'), Some('ü'));
+        assert_eq!(compose('n', '~'), Some('ñ'));
+        assert_eq!(compose('s', 's'), Some('ß'));
+        assert_eq!(compose('o', 'c'), Some('©'));
+        assert_eq!(compose('q', '\''), None);
+
+        let mut keys = Compose::default();
+        assert_eq!(keys.feed('x'), Composed::Char('x'));
+        keys.start();
+        assert!(keys.is_active());
+        assert_eq!(keys.feed('A'), Composed::Pending);
+        assert_eq!(keys.feed('*'), Composed::Char('Å'));
+        assert!(!keys.is_active());
+        keys.start();
+        keys.feed('z');
+        assert_eq!(keys.feed('z'), Composed::Invalid);
+        assert!(!keys.is_active());
+    }
+
+    #[test]
+    fn test_keymap() {
+        let german = KeyMap::builtin("german").unwrap();
+        assert_eq!(parse_byte(b'[', &german), InputEvent::Char('Ä'));
+        assert_eq!(parse_byte(b'~', &german), InputEvent::Char('ß'));
+        assert_eq!(parse_byte(b'a', &german), InputEvent::Char('a'));
+        let multinational = KeyMap::builtin("multinational").unwrap();
+        assert_eq!(parse_byte(0xE9, &multinational), InputEvent::Char('é'));
+        assert_eq!(parse_byte(0xD7, &multinational), InputEvent::Char('Œ'));
+        assert_eq!(parse_byte(0xE9, &german), InputEvent::Ignore);
+
+        // A converter sending 0x1C for Return, and a key sending ¥
+        let mut custom = KeyMap::default();
+        let (byte, c) = KeyMap::parse_entry("0x1c", "0x0d").unwrap();
+        custom.set(byte, c);
+        let (byte, c) = KeyMap::parse_entry("$", "¥").unwrap();
+        custom.set(byte, c);
+        assert_eq!(parse_byte(0x1c, &custom), InputEvent::Enter);
+        assert_eq!(parse_byte(b'$', &custom), InputEvent::Char('¥'));
+        assert_eq!(KeyMap::parse_entry("36", "A"), Ok((b'$', 'A')));
+        assert!(KeyMap::parse_entry("0x100", "A").is_err());
+        assert!(KeyMap::parse_entry("ab", "A").is_err());
+        assert!(KeyMap::builtin("klingon").is_none());
     }
 
     #[test]
@@ -195,6 +591,17 @@ mod tests {
         assert!(parser.feed(0x1b).is_none());
         assert!(parser.feed(b'[').is_none());
         assert_eq!(parser.feed(b'D'), Some(EscapeSequence::ArrowLeft));
+
+        // Ctrl+Up and Ctrl+Down
+        let mut last = None;
+        for &byte in b"\x1b[1;5A" {
+            last = parser.feed(byte);
+        }
+        assert_eq!(last, Some(EscapeSequence::CtrlArrowUp));
+        for &byte in b"\x1b[1;5B" {
+            last = parser.feed(byte);
+        }
+        assert_eq!(last, Some(EscapeSequence::CtrlArrowDown));
     }
 
     #[test]
@@ -213,4 +620,94 @@ mod tests {
         assert!(parser.feed(b'6').is_none());
         assert_eq!(parser.feed(b'~'), Some(EscapeSequence::PageDown));
     }
+
+    #[test]
+    fn test_escape_parser_alt_digit() {
+        let mut parser = EscapeParser::new();
+        assert!(parser.feed(0x1b).is_none());
+        assert_eq!(parser.feed(b'3'), Some(EscapeSequence::AltDigit(3)));
+        assert!(!parser.is_parsing());
+        // Digits inside a CSI sequence are parameters, not Alt
+        assert!(parser.feed(0x1b).is_none());
+        assert!(parser.feed(b'[').is_none());
+        assert!(parser.feed(b'5').is_none());
+        assert_eq!(parser.feed(b'~'), Some(EscapeSequence::PageUp));
+    }
+
+    #[test]
+    fn test_escape_parser_editing_and_function_keys() {
+        let feed = |bytes: &[u8]| {
+            let mut parser = EscapeParser::new();
+            let mut last = None;
+            for &b in bytes {
+                last = parser.feed(b);
+            }
+            assert!(!parser.is_parsing());
+            last
+        };
+        assert_eq!(feed(b"\x1b[1~"), Some(EscapeSequence::Home));
+        assert_eq!(feed(b"\x1b[H"), Some(EscapeSequence::Home));
+        assert_eq!(feed(b"\x1bOF"), Some(EscapeSequence::End));
+        assert_eq!(feed(b"\x1b[2~"), Some(EscapeSequence::Insert));
+        assert_eq!(feed(b"\x1b[3~"), Some(EscapeSequence::Delete));
+        // SS3 for F1-F4, CSI for those and the rest, skipping 16 and 22
+        assert_eq!(feed(b"\x1bOP"), Some(EscapeSequence::Function(1)));
+        assert_eq!(feed(b"\x1b[14~"), Some(EscapeSequence::Function(4)));
+        assert_eq!(feed(b"\x1b[17~"), Some(EscapeSequence::Function(6)));
+        assert_eq!(feed(b"\x1b[24~"), Some(EscapeSequence::Function(12)));
+        assert_eq!(feed(b"\x1b[16~"), Some(EscapeSequence::Unknown));
+        assert_eq!(feed(b"\x1b[200~"), Some(EscapeSequence::PasteStart));
+        // Arrows in application cursor mode
+        assert_eq!(feed(b"\x1bOA"), Some(EscapeSequence::ArrowUp));
+        // Too long for any key: given up on
+        assert_eq!(
+            feed(&[b"\x1b[".as_slice(), &[b';'; MAX_SEQUENCE - 1]].concat()),
+            Some(EscapeSequence::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_escape_parser_clicks() {
+        let feed = |bytes: &[u8]| {
+            let mut parser = EscapeParser::new();
+            bytes.iter().find_map(|&b| parser.feed(b))
+        };
+        let click = Some(EscapeSequence::Click { row: 1, col: 12 });
+        assert_eq!(feed(b"\x1b[<0;12;1M"), click);
+        assert_eq!(feed(b"\x1b[2;4;1;12;1&w"), click);
+        assert_eq!(feed(b"\x1b[M ,!"), click);
+        // Releases, other buttons and the other locator events
+        assert_eq!(feed(b"\x1b[<0;12;1m"), Some(EscapeSequence::Unknown));
+        assert_eq!(feed(b"\x1b[<2;12;1M"), Some(EscapeSequence::Unknown));
+        assert_eq!(feed(b"\x1b[3;0;1;12;1&w"), Some(EscapeSequence::Unknown));
+        assert_eq!(feed(b"\x1b[M#,!"), Some(EscapeSequence::Unknown));
+    }
+
+    #[test]
+    fn test_escape_parser_timeout() {
+        let mut parser = EscapeParser::new();
+        assert_eq!(parser.expire(Instant::now() + ESCAPE_TIMEOUT), None);
+
+        parser.feed(0x1b);
+        assert_eq!(parser.expire(Instant::now()), None);
+        assert!(parser.is_parsing());
+        let later = Instant::now() + ESCAPE_TIMEOUT;
+        assert_eq!(parser.expire(later), Some(EscapeSequence::Escape));
+        assert!(!parser.is_parsing());
+
+        // Cut short: dropped, and the next key parses as usual
+        parser.feed(0x1b);
+        parser.feed(b'[');
+        parser.feed(b'1');
+        let later = Instant::now() + ESCAPE_TIMEOUT;
+        assert_eq!(parser.expire(later), Some(EscapeSequence::Unknown));
+        parser.feed(0x1b);
+        assert_eq!(parser.feed(b'['), None);
+        assert_eq!(parser.feed(b'A'), Some(EscapeSequence::ArrowUp));
+
+        // Escape twice
+        parser.feed(0x1b);
+        assert_eq!(parser.feed(0x1b), Some(EscapeSequence::Escape));
+        assert!(parser.is_parsing());
+    }
 }
@@ -0,0 +1,217 @@
+//! Key bindings (`[keys]` in the config).
+//!
+//! The keys that switch tabs, redraw the screen, hang up, scroll, stop the
+//! music and start a compose sequence can be moved, for a terminal whose Tab
+//! key a protocol converter swallows or whose keyboard has no Page Up. Each
+//! action takes one or more keys, separated by commas: a control character
+//! (`^R`), a byte (`0x12`), a printable character, a key name (`tab`,
+//! `space`, `pageup`, `f5`...) or an escape sequence with `\e` for ESC
+//! (`\e[17~`). Naming an action replaces its default keys.
+//!
+//! Hanging up only applies on the Call tab and stopping the music on the
+//! Tunes tab; elsewhere their keys do what they otherwise would, so Space
+//! still types a space and Ctrl+C still clears the chat.
+
+use std::collections::HashMap;
+
+use crate::input::{EscapeParser, EscapeSequence};
+
+/// What a bound key does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Go to the next tab
+    NextTab,
+    /// Redraw the whole screen
+    Refresh,
+    /// End the call (Call tab)
+    HangUp,
+    /// Page back through the scrollback, or up a list
+    ScrollUp,
+    /// Page forward, or down a list
+    ScrollDown,
+    /// Stop playback (Tunes tab)
+    StopMusic,
+    /// Start a compose sequence for an accented letter
+    Compose,
+}
+
+/// The actions by config name, with their default keys
+pub const ACTIONS: &[(&str, Action, &str)] = &[
+    ("next_tab", Action::NextTab, "tab"),
+    ("refresh", Action::Refresh, "^R"),
+    ("hang_up", Action::HangUp, "space"),
+    ("scroll_up", Action::ScrollUp, "pageup"),
+    ("scroll_down", Action::ScrollDown, "pagedown"),
+    ("stop_music", Action::StopMusic, "^C"),
+    ("compose", Action::Compose, "^K"),
+];
+
+/// A key as the terminal sends it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// One byte: a control character or a printable one
+    Byte(u8),
+    /// A key that sends an escape sequence
+    Escape(EscapeSequence),
+}
+
+/// The keys that can be named, besides the function keys
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("tab", Key::Byte(0x09)),
+    ("space", Key::Byte(b' ')),
+    ("enter", Key::Byte(b'\r')),
+    ("backspace", Key::Byte(0x7f)),
+    ("pageup", Key::Escape(EscapeSequence::PageUp)),
+    ("pagedown", Key::Escape(EscapeSequence::PageDown)),
+    ("up", Key::Escape(EscapeSequence::ArrowUp)),
+    ("down", Key::Escape(EscapeSequence::ArrowDown)),
+    ("left", Key::Escape(EscapeSequence::ArrowLeft)),
+    ("right", Key::Escape(EscapeSequence::ArrowRight)),
+    ("home", Key::Escape(EscapeSequence::Home)),
+    ("end", Key::Escape(EscapeSequence::End)),
+    ("insert", Key::Escape(EscapeSequence::Insert)),
+    ("delete", Key::Escape(EscapeSequence::Delete)),
+    ("esc", Key::Escape(EscapeSequence::Escape)),
+];
+
+impl Key {
+    /// Parse a key as written in `[keys]`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        if let Some((_, key)) = KEY_NAMES.iter().find(|(name, _)| *name == lower) {
+            return Ok(key.clone());
+        }
+        if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok())
+            && (1..=12).contains(&n)
+        {
+            return Ok(Key::Escape(EscapeSequence::Function(n)));
+        }
+        if let Some(rest) = s.strip_prefix("\\e") {
+            return Self::sequence(rest).ok_or_else(|| format!("'{}' is not a known key", s));
+        }
+        let byte = if let Some(hex) = lower.strip_prefix("0x") {
+            u8::from_str_radix(hex, 16).ok()
+        } else {
+            match s.as_bytes() {
+                [b'^', b'?'] => Some(0x7f),
+                [b'^', c @ b'@'..=b'_'] => Some(c - 0x40),
+                [b'^', c @ b'a'..=b'z'] => Some(c - 0x60),
+                [c] if c.is_ascii_graphic() => Some(*c),
+                _ => None,
+            }
+        };
+        match byte {
+            // ESC starts every escape sequence; the Escape key is `esc`
+            Some(0x1b) => Err("ESC is written 'esc'".to_string()),
+            Some(byte) => Ok(Key::Byte(byte)),
+            None => Err(format!("'{}' is not a key", s)),
+        }
+    }
+
+    /// The key an escape sequence (after the ESC) stands for
+    fn sequence(rest: &str) -> Option<Self> {
+        let mut parser = EscapeParser::new();
+        parser.feed(0x1b);
+        let mut bytes = rest.bytes();
+        let seq = bytes.by_ref().find_map(|b| parser.feed(b))?;
+        (bytes.next().is_none() && seq != EscapeSequence::Unknown).then_some(Key::Escape(seq))
+    }
+}
+
+/// Which action each key is bound to
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    keys: HashMap<Key, Action>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::new(&HashMap::new())
+    }
+}
+
+impl Bindings {
+    /// The defaults, with the actions named in `entries` bound to their keys
+    /// instead (entries that don't parse are left out)
+    pub fn new(entries: &HashMap<String, String>) -> Self {
+        let mut keys = HashMap::new();
+        for &(name, action, default) in ACTIONS {
+            let spec = entries.get(name).map_or(default, String::as_str);
+            for key in Self::parse_entry(name, spec).map_or_else(|_| Vec::new(), |(_, k)| k) {
+                keys.insert(key, action);
+            }
+        }
+        Self { keys }
+    }
+
+    /// Parse a `[keys]` entry: an action's name and its keys
+    pub fn parse_entry(name: &str, keys: &str) -> Result<(Action, Vec<Key>), String> {
+        let action = ACTIONS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, action, _)| *action)
+            .ok_or_else(|| format!("unknown action '{}'", name))?;
+        let keys = keys
+            .split(',')
+            .filter(|k| !k.trim().is_empty())
+            .map(Key::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((action, keys))
+    }
+
+    /// The action `key` is bound to
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.keys.get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(Key::parse("^R"), Ok(Key::Byte(0x12)));
+        assert_eq!(Key::parse("^r"), Ok(Key::Byte(0x12)));
+        assert_eq!(Key::parse("0x09"), Key::parse("Tab"));
+        assert_eq!(Key::parse("q"), Ok(Key::Byte(b'q')));
+        assert_eq!(
+            Key::parse("F5"),
+            Ok(Key::Escape(EscapeSequence::Function(5)))
+        );
+        assert_eq!(Key::parse("\\e[15~"), Key::parse("f5"));
+        assert_eq!(Key::parse("\\eOP"), Key::parse("f1"));
+
+        assert!(Key::parse("^[").is_err());
+        assert_eq!(Key::parse("Esc"), Ok(Key::Escape(EscapeSequence::Escape)));
+        assert!(Key::parse("f13").is_err());
+        assert!(Key::parse("\\e[99~").is_err());
+        assert!(Key::parse("\\e[5~x").is_err());
+        assert!(Key::parse("ctrl-r").is_err());
+    }
+
+    #[test]
+    fn test_bindings_replace_defaults() {
+        let defaults = Bindings::default();
+        assert_eq!(defaults.action(&Key::Byte(0x09)), Some(Action::NextTab));
+        assert_eq!(defaults.action(&Key::Byte(b' ')), Some(Action::HangUp));
+
+        let entries = HashMap::from([
+            ("next_tab".to_string(), "^N, f2".to_string()),
+            ("hang_up".to_string(), "".to_string()),
+        ]);
+        let bindings = Bindings::new(&entries);
+        assert_eq!(bindings.action(&Key::Byte(0x09)), None);
+        assert_eq!(bindings.action(&Key::Byte(0x0e)), Some(Action::NextTab));
+        assert_eq!(
+            bindings.action(&Key::Escape(EscapeSequence::Function(2))),
+            Some(Action::NextTab)
+        );
+        assert_eq!(bindings.action(&Key::Byte(b' ')), None);
+        assert_eq!(bindings.action(&Key::Byte(0x12)), Some(Action::Refresh));
+
+        assert!(Bindings::parse_entry("launch_missiles", "^L").is_err());
+        assert!(Bindings::parse_entry("refresh", "^R, nope").is_err());
+    }
+}
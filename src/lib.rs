@@ -0,0 +1,37 @@
+//! Wormhole: P2P chat, video calls, AI and music for serial terminals.
+//!
+//! The binary in `main.rs` drives the session; the modules live here so the
+//! integration tests under `tests/` can put nodes through their paces.
+
+pub mod ai;
+pub mod ambient;
+pub mod app;
+pub mod backup;
+pub mod clock;
+pub mod config;
+pub mod cover;
+pub mod export;
+pub mod graphics;
+pub mod input;
+pub mod keys;
+pub mod log;
+pub mod macros;
+pub mod motion;
+pub mod mouse;
+pub mod network;
+pub mod notify;
+pub mod paste;
+pub mod qr;
+pub mod recall;
+pub mod screensaver;
+pub mod serial;
+pub mod state;
+pub mod terminal;
+pub mod testcard;
+pub mod timer;
+pub mod todo;
+pub mod transfer;
+pub mod tunes;
+pub mod watchdog;
+pub mod webcam;
+pub mod windows;
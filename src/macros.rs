@@ -0,0 +1,104 @@
+//! Macros (`[macros]` in the config): abbreviations and canned messages.
+//!
+//! On a membrane keyboard every word costs. An entry like `;;brb = be right
+//! back` is an abbreviation: wherever the word `;;brb` stands alone in the
+//! input line, it's replaced when Enter is pressed, so it works in commands
+//! and AI prompts too. An entry named after a function key, `f5 = Back in
+//! five`, is a canned message: the key sends it to the chat window shown,
+//! unless `[keys]` has bound it to something else.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Abbreviations and the function keys' messages
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    abbreviations: HashMap<String, String>,
+    /// By function key number
+    canned: HashMap<u8, String>,
+}
+
+/// The function key an entry's name stands for (`f1` to `f12`)
+fn function_key(name: &str) -> Option<u8> {
+    let n = name.strip_prefix(['f', 'F'])?.parse().ok()?;
+    (1..=12).contains(&n).then_some(n)
+}
+
+impl Macros {
+    /// The macros from `[macros]` (entries that don't check out are left out)
+    pub fn new(entries: &HashMap<String, String>) -> Self {
+        let mut macros = Self::default();
+        for (name, text) in entries {
+            if Self::check_entry(name, text).is_err() {
+                continue;
+            }
+            match function_key(name) {
+                Some(n) => macros.canned.insert(n, text.trim().to_string()),
+                None => macros
+                    .abbreviations
+                    .insert(name.clone(), text.trim().to_string()),
+            };
+        }
+        macros
+    }
+
+    /// Check a `[macros]` entry: one word, or a function key, for something
+    /// to say
+    pub fn check_entry(name: &str, text: &str) -> Result<(), String> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err("the name should be one word".to_string());
+        }
+        if text.trim().is_empty() {
+            return Err("nothing to expand to".to_string());
+        }
+        Ok(())
+    }
+
+    /// `line` with every abbreviation standing alone replaced
+    pub fn expand<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if !line
+            .split(' ')
+            .any(|word| self.abbreviations.contains_key(word))
+        {
+            return Cow::Borrowed(line);
+        }
+        let words: Vec<&str> = line
+            .split(' ')
+            .map(|word| self.abbreviations.get(word).map_or(word, String::as_str))
+            .collect();
+        Cow::Owned(words.join(" "))
+    }
+
+    /// The message function key `n` sends, if it has one
+    pub fn canned(&self, n: u8) -> Option<&str> {
+        self.canned.get(&n).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macros() {
+        let entries = HashMap::from([
+            (";;brb".to_string(), "be right back".to_string()),
+            ("afk".to_string(), "away from keyboard".to_string()),
+            ("F5".to_string(), " Back in five ".to_string()),
+            ("two words".to_string(), "nope".to_string()),
+            ("empty".to_string(), " ".to_string()),
+        ]);
+        let macros = Macros::new(&entries);
+        assert_eq!(
+            macros.expand(";;brb, afk  ;;brb"),
+            ";;brb, away from keyboard  be right back"
+        );
+        assert!(matches!(macros.expand("nothing to see"), Cow::Borrowed(_)));
+        assert_eq!(macros.canned(5), Some("Back in five"));
+        assert_eq!(macros.canned(6), None);
+        assert_eq!(macros.expand("empty"), "empty");
+
+        assert!(Macros::check_entry("two words", "nope").is_err());
+        assert!(Macros::check_entry("f13", "a twelve key keyboard").is_ok());
+    }
+}
@@ -1,30 +1,32 @@
-mod app;
-mod config;
-mod gemini;
-mod graphics;
-mod input;
-mod log;
-mod network;
-mod serial;
-mod terminal;
-mod tunes;
-mod webcam;
-
-use app::App;
 use chrono::Local;
-use clap::Parser;
-use config::Config;
-use input::{EscapeParser, EscapeSequence, InputEvent, parse_byte};
-use network::{Message, PEER_TIMEOUT, PeerEvent};
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use terminal::{
-    Tab, cleanup_split_screen, generate_waiting_for_peer_frame, init_split_screen_with_tabs,
-    max_input_length, redraw_input, redraw_tab_bar, render_stream,
+use wormhole::ai::{AI_NAME, AiChat, Image, PARAM_NAMES};
+use wormhole::app::{App, MAX_TIMER_LABEL, Subsystem, describe_clock_offset};
+use wormhole::config::Config;
+use wormhole::export::{self, Format};
+use wormhole::input::{Composed, EscapeParser, EscapeSequence, InputEvent, parse_byte};
+use wormhole::keys::{Action, Key};
+use wormhole::network::{
+    APP_VERSION, Impairment, MAX_PROFILE_LEN, MAX_TODO_LEN, Message, PEER_TIMEOUT,
+    PROTOCOL_VERSION, ProtocolTap, RoomTopic, TodoOp,
 };
-use webcam::{RawFrame, raw_frame_to_output};
+use wormhole::qr::QrCode;
+use wormhole::serial::Serial;
+use wormhole::state::StateDump;
+use wormhole::terminal::{
+    ChatBuffer, FramePacer, Markdown, Tab, cleanup_split_screen, generate_waiting_for_peer_frame,
+    init_split_screen_with_tabs, layout, max_input_length, redraw_input, redraw_tab_bar,
+    render_stats, render_stream, stamp, transparent_print,
+};
+use wormhole::timer::{self, Timer, TimerKind};
+use wormhole::webcam::{
+    RawFrame, image_file_to_raw_frame, raw_frame_to_output, scale_raw_frame, shared_image_to_output,
+};
+use wormhole::{backup, graphics, network, paste, terminal, transfer, webcam, windows};
 
 #[derive(Parser, Debug)]
 #[command(name = "wormhole")]
@@ -33,12 +35,108 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long, default_value = "wormhole.ini")]
     config: PathBuf,
+
+    /// Run as a packet relay for peers behind symmetric NATs (no serial terminal)
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "7892")]
+    relay: Option<u16>,
+
+    /// Developer option: open an offline preview session from a /dump-state file
+    #[arg(long, value_name = "FILE")]
+    load_state: Option<PathBuf>,
+
+    /// Log every peer message sent and received (type, size, peer, decode errors)
+    /// to wormhole-protocol-*.log in the log directory, or to FILE
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "")]
+    debug_protocol: Option<PathBuf>,
+
+    /// Write every peer datagram to a pcap file (for Wireshark)
+    #[arg(long, value_name = "FILE")]
+    pcap: Option<PathBuf>,
+
+    /// Developer option: simulate a bad network, e.g.
+    /// "loss=5%,latency=200ms,jitter=50ms,reorder=2%,seed=1"
+    #[arg(long, value_name = "SPEC")]
+    impair: Option<Impairment>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Export chat logs as an mbox or a static HTML page
+    Export {
+        /// mbox or html
+        #[arg(long, default_value = "html")]
+        format: Format,
+
+        /// Write to FILE instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Chat logs to export (every chat log in the [logging] directory if none)
+        logs: Vec<PathBuf>,
+    },
+    /// Save the config, peer roster, to-do list and logs in one tarball
+    Backup {
+        /// The archive to write
+        file: PathBuf,
+    },
+    /// Put a backup's config, roster, to-do list and logs back in place
+    Restore {
+        /// The archive to read
+        file: PathBuf,
+
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
 async fn main() {
     let args = Args::parse();
 
+    // Before the banner: the export may go to standard output
+    match args.command {
+        Some(Command::Export {
+            format,
+            output,
+            logs,
+        }) => {
+            if let Err(e) = run_export(&args.config, format, output, logs) {
+                eprintln!("Export error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Backup { file }) => {
+            match backup::backup(&args.config, &file) {
+                Ok(names) => println!("Backed up {} to {}", names.join(", "), file.display()),
+                Err(e) => {
+                    eprintln!("Backup error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Restore { file, force }) => {
+            match backup::restore(&args.config, &file, force) {
+                Ok(written) => {
+                    for path in written {
+                        println!("Restored {}", path.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Restore error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        None => {}
+    }
+
     // Show app info
     println!(
         "{} v{} - {}",
@@ -47,6 +145,14 @@ async fn main() {
         env!("CARGO_PKG_AUTHORS")
     );
 
+    if let Some(port) = args.relay {
+        if let Err(e) = network::run_relay(port).await {
+            eprintln!("Relay error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let config = match Config::load(&args.config) {
         Ok(cfg) => cfg,
         Err(e) => {
@@ -80,6 +186,9 @@ async fn main() {
     } else {
         println!("  External Peers: {}", config.network.peers);
     }
+    if let Some(ref relay) = config.network.relay {
+        println!("  Relay: {}", relay);
+    }
     println!();
     println!("Webcam:");
     if let Some(ref device) = config.webcam.device {
@@ -87,17 +196,21 @@ async fn main() {
         if config.webcam.fps > 0 {
             println!("  FPS: {}", config.webcam.fps);
         }
-        if config.terminal.mode == "vt340" {
+        if config.terminal.mode == "vt340" || config.terminal.renderer == "sixel" {
             println!("  Sixel Shades: {}", config.webcam.sixel_shades);
         }
+        if config.dither() != graphics::Dither::None {
+            println!("  Dither: {}", config.webcam.dither);
+        }
     } else {
         println!("  Device: (not configured)");
     }
     println!();
-    println!("Gemini AI:");
-    if config.gemini.api_key.is_some() {
-        println!("  Model: {}", config.gemini.model);
-        if config.gemini.system_prompt.is_some() {
+    println!("AI:");
+    if AiChat::is_available(&config.ai) {
+        println!("  Provider: {}", config.ai.provider);
+        println!("  Model: {}", config.ai.model());
+        if config.ai.system_prompt.is_some() {
             println!("  System Prompt: (configured)");
         }
     } else {
@@ -106,6 +219,7 @@ async fn main() {
     println!();
     println!("Terminal:");
     println!("  Mode: {}", config.terminal.mode);
+    println!("  Renderer: {}", config.terminal.renderer);
     if config.terminal.cols_132 {
         println!("  132 Columns: enabled");
     }
@@ -134,8 +248,92 @@ async fn main() {
     })
     .expect("Error setting Ctrl+C handler");
 
+    // Read the state dump up front so a bad file fails before the terminal is touched
+    let dump = args
+        .load_state
+        .as_ref()
+        .map(|path| match StateDump::load(path) {
+            Ok(dump) => {
+                println!(
+                    "Preview of {} (wormhole v{}, {}):",
+                    path.display(),
+                    dump.app_version,
+                    dump.created
+                );
+                for peer in &dump.peers {
+                    println!(
+                        "  Peer: {} at {} (seen {}s before dump)",
+                        peer.name, peer.addr, peer.last_seen_secs
+                    );
+                }
+                if let Some(ref call) = dump.call.active_call {
+                    println!(
+                        "  Call: {} ({})",
+                        call,
+                        if dump.call.connected {
+                            "connected"
+                        } else {
+                            "ringing"
+                        }
+                    );
+                }
+                println!();
+                dump
+            }
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    // Open protocol debugging output before any traffic flows
+    let protocol_tap = if args.debug_protocol.is_some() || args.pcap.is_some() {
+        let log = args.debug_protocol.as_ref().map(|path| {
+            if !path.as_os_str().is_empty() {
+                return path.clone();
+            }
+            let name = format!(
+                "wormhole-protocol-{}.log",
+                Local::now().format("%Y%m%d-%H%M%S")
+            );
+            match &config.logging.directory {
+                Some(dir) => std::path::Path::new(dir).join(name),
+                None => PathBuf::from(name),
+            }
+        });
+        match ProtocolTap::open(log.as_deref(), args.pcap.as_deref()) {
+            Ok(tap) => {
+                if let Some(log) = &log {
+                    println!("Protocol log: {}", log.display());
+                }
+                if let Some(pcap) = &args.pcap {
+                    println!("Packet capture: {}", pcap.display());
+                }
+                Some(tap)
+            }
+            Err(e) => {
+                eprintln!("Error opening protocol debug output: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(impairment) = &args.impair {
+        println!("Simulating network impairment: {}", impairment);
+    }
+
     // Initialize App
-    let mut app = match App::new(config, running.clone()).await {
+    let mut app = match App::new(
+        config,
+        running.clone(),
+        dump.is_some(),
+        protocol_tap,
+        args.impair.clone(),
+    )
+    .await
+    {
         Ok(app) => app,
         Err(e) => {
             eprintln!("Failed to initialize app: {}", e);
@@ -147,26 +345,27 @@ async fn main() {
     let use_132_cols = app.config.terminal.cols_132;
     let width = if use_132_cols { 132 } else { 80 };
 
+    if let Some(dump) = dump {
+        app.load_state(dump);
+        app.redraw_screen(width);
+    }
+
     // Main loop - handle serial I/O and network messages
-    let max_input_len = max_input_length(&app.config.network.name, width);
+    let mut max_input_len = max_input_length(&app.config.network.name, width);
     let mut serial_buf = [0u8; 256];
     let mut escape_parser = EscapeParser::new(); // Parser for escape sequences
     let mut last_reconnect_attempt = std::time::Instant::now();
     const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
 
-    // Calculate frame delay based on baud rate to avoid flooding the serial link
-    // Frame size ~ 65x20 chars + overhead ~ 1500 bytes.
-    let bytes_per_frame = 2000;
-    let chars_per_sec = std::cmp::max(app.config.serial.baud_rate / 10, 1);
-    let calculated_fps = (chars_per_sec as f64 / bytes_per_frame as f64).clamp(0.5, 30.0);
-
-    let target_fps = if app.config.webcam.fps > 0 {
-        app.config.webcam.fps as f64
-    } else {
-        calculated_fps
+    // Frames are captured at the configured rate (or as fast as a camera
+    // goes) and drawn as fast as the serial link keeps up with
+    const MAX_FPS: u32 = 30;
+    let target_fps = match app.config.webcam.fps {
+        0 => MAX_FPS,
+        fps => fps.min(MAX_FPS),
     };
-
-    let frame_delay = Duration::from_secs_f64(1.0 / target_fps);
+    let frame_delay = Duration::from_secs_f64(1.0 / target_fps as f64);
+    let mut pacer = FramePacer::new(frame_delay, app.config.serial.baud_rate);
     let mut last_frame_time = std::time::Instant::now()
         .checked_sub(frame_delay)
         .unwrap_or_else(std::time::Instant::now);
@@ -174,6 +373,13 @@ async fn main() {
     // Tunes status refresh timer (1 second for MM:SS display)
     let tunes_refresh_delay = Duration::from_secs(1);
     let mut last_tunes_refresh = std::time::Instant::now();
+    let status_refresh_delay = Duration::from_secs(1);
+    let mut last_status_refresh = std::time::Instant::now();
+    let stats_refresh_delay = Duration::from_secs(1);
+    let mut last_stats_refresh = std::time::Instant::now();
+    // The timer is checked often but only redrawn when the time shown changes
+    let timer_refresh_delay = Duration::from_millis(100);
+    let mut last_timer_refresh = std::time::Instant::now();
 
     // Main loop uses tokio::time::sleep to yield properly to the async runtime
     let loop_delay = Duration::from_millis(1);
@@ -181,6 +387,12 @@ async fn main() {
     while app.running.load(Ordering::SeqCst) {
         // Sleep using tokio to properly yield to other tasks
         tokio::time::sleep(loop_delay).await;
+
+        // Recover from suspend/resume before anything can time out
+        if let Some(event) = app.clock.check() {
+            app.handle_clock_event(event, width).await;
+        }
+
         // Handle serial reconnection if disconnected
         if !app.serial.is_connected() {
             if last_reconnect_attempt.elapsed() >= RECONNECT_INTERVAL {
@@ -190,26 +402,30 @@ async fn main() {
                     Ok(()) => {
                         eprintln!("Reconnected to serial port!");
                         // Reinitialize the terminal UI
-                        let call_status = app.active_call.as_ref().map(|peer_name| {
-                            format!("Call session with {}. Press Space to hang up.", peer_name)
-                        });
-                        let gemini_available = app.gemini_chat.is_some();
+                        let call_status = app.call_status();
+                        let gemini_available = app.ai_chat.is_some();
 
                         // Re-send DRCS init if needed
-                        let use_drcs = app.config.terminal.mode == "vt220"
-                            || app.config.terminal.mode == "vt340";
-                        let _ = app
-                            .serial
-                            .write_str(&terminal::get_init_sequence(use_drcs, use_132_cols));
+                        let charset = app.serial.charset();
+                        let _ = app.serial.write_str(&terminal::get_init_sequence(
+                            app.drcs(),
+                            use_132_cols,
+                            charset,
+                            app.config.mouse(),
+                        ));
 
                         let tunes_available = app.tunes_available();
                         let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                            app.theme,
                             &app.config.network.name,
                             app.active_tab,
                             gemini_available,
                             tunes_available,
                             app.active_call.as_deref(),
+                            app.unread,
                             call_status.as_deref(),
+                            app.topic_text(),
+                            app.status_shown.as_deref(),
                             width,
                         ));
                         // Render the active buffer
@@ -217,6 +433,7 @@ async fn main() {
                             Tab::Chat => {
                                 let _ = app.serial.write_str(&app.chat_buffer.render());
                                 let _ = app.serial.write_str(&redraw_input(
+                                    app.theme,
                                     &app.config.network.name,
                                     &app.line_buffer,
                                     app.input_cursor,
@@ -226,6 +443,7 @@ async fn main() {
                             Tab::Gemini => {
                                 let _ = app.serial.write_str(&app.ai_buffer.render());
                                 let _ = app.serial.write_str(&redraw_input(
+                                    app.theme,
                                     &app.config.network.name,
                                     &app.line_buffer,
                                     app.input_cursor,
@@ -234,9 +452,20 @@ async fn main() {
                             }
                             Tab::Tunes => {
                                 if let Some(ref tunes) = app.tunes_state {
+                                    tunes.redraw_cover();
                                     let _ = app.serial.write_str(&tunes.render());
                                 }
                             }
+                            Tab::Stats => {
+                                let _ = app.serial.write_str(&render_stats(
+                                    &app.net_node.peer_stats(),
+                                    &app.peer_caps,
+                                    width,
+                                ));
+                            }
+                            Tab::Todo => {
+                                let _ = app.serial.write_str(&app.todo.render());
+                            }
                             Tab::Call => {}
                         }
                     }
@@ -248,72 +477,57 @@ async fn main() {
             // Yield while disconnected
             tokio::time::sleep(Duration::from_millis(100)).await;
 
-            // Still process network messages while disconnected
+            // Still handle peers while disconnected, just without drawing
             while let Ok(msg) = app.net_rx.try_recv() {
-                match msg {
-                    Message::Chat { from, text } => {
-                        let timestamp = Local::now().format("%I:%M%p");
-
-                        // Check if this is an image message
-                        if text.starts_with("[IMAGE]\n") {
-                            app.push_chat(format!("[{}] {} shared an image:", timestamp, from));
-                            for line in text.strip_prefix("[IMAGE]\n").unwrap_or(&text).lines() {
-                                app.push_chat(line.to_string());
-                            }
-                        } else {
-                            let formatted = format!("[{}] {}: {}", timestamp, from, text);
-                            app.push_chat(formatted);
-                        }
-                        app.chat_buffer.scroll_to_bottom();
-                    }
-                    Message::StreamFrame { from, .. } => {
-                        // Legacy: ignore pre-rendered StreamFrame from older peers
-                        // Peers should upgrade to use VideoFrame for cross-terminal compatibility
-                        eprintln!("Received legacy StreamFrame from {} (ignored)", from);
-                    }
-                    Message::VideoFrame {
-                        from,
-                        width,
-                        height,
-                        pixels,
-                    } => {
-                        app.current_video_frame = Some((
-                            from,
-                            RawFrame {
-                                width,
-                                height,
-                                pixels,
-                            },
-                        ));
-                    }
-                    _ => {}
+                // Settling a name clash may rename us
+                let renamed = matches!(msg, Message::NickInUse { .. });
+                app.handle_message(msg, width).await;
+                if renamed {
+                    max_input_len = max_input_length(&app.config.network.name, width);
                 }
             }
             while let Ok(event) = app.peer_event_rx.try_recv() {
-                let timestamp = Local::now().format("%I:%M%p");
-                let msg = match event {
-                    PeerEvent::Joined { name, addr } => {
-                        app.net_node.add_peer(name.clone(), addr);
-                        format!("[{}] *** {} has joined ***", timestamp, name)
-                    }
-                    PeerEvent::Left { name, addr } => {
-                        app.net_node.remove_peer(addr);
-                        format!("[{}] *** {} has left ***", timestamp, name)
-                    }
-                };
-                app.push_chat(msg);
+                app.handle_peer_event(event).await;
+            }
+            app.pump_transfers().await;
+            if let Err(e) = app.net_node.flush_send_queue().await {
+                eprintln!("Failed to send queued traffic: {}", e);
             }
             continue;
         }
 
+        // Keep our relay allocation alive (no-op without a relay)
+        app.net_node.maintain_relay().await;
+        // Measure round-trip times for /who and the call stats
+        app.net_node.maintain_pings().await;
+        // Keep NAT mappings open, and follow changes to our public address
+        app.net_node.maintain_keepalives().await;
+        if app.check_public_addr().await && app.active_tab == Tab::Chat {
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+        }
+
         // Prune stale peers periodically (allows reconnection after timeout)
         let timed_out_peers = app.net_node.prune_peers(PEER_TIMEOUT);
+        app.trim_caches();
         for peer in timed_out_peers {
-            let timestamp = Local::now().format("%I:%M%p");
-            let msg = format!("[{}] *** {} has timed out ***", timestamp, peer.name);
+            app.receipts_peer_gone(&peer.name);
+            let timestamp = stamp::now();
+            let msg = format!("{}*** {} has timed out ***", timestamp, peer.name);
             app.push_chat(msg);
             if app.active_tab == Tab::Chat {
-                let _ = app.serial.write_str(&app.chat_buffer.render());
+                let _ = app.serial.write_str(&app.chat_buffer.render_update());
+            }
+        }
+
+        // Stop ringing once the caller would have given up
+        if let Some((caller, _)) = app
+            .incoming_call
+            .take_if(|(_, rang_at)| rang_at.elapsed() > CALL_RING_TIMEOUT)
+        {
+            let timestamp = stamp::now();
+            app.push_chat(format!("{}*** Missed call from {} ***", timestamp, caller));
+            if app.active_tab == Tab::Chat {
+                let _ = app.serial.write_str(&app.chat_buffer.render_update());
             }
         }
 
@@ -330,79 +544,28 @@ async fn main() {
                 let is_self_call = app.active_call.as_deref() == Some(&app.config.network.name);
 
                 if !is_self_call && let Some(peer_name) = app.active_call.take() {
-                    let timestamp = Local::now().format("%I:%M%p");
+                    let timestamp = stamp::now();
                     app.push_chat(format!(
-                        "[{}] *** Call with {} timed out ***",
+                        "{}*** Call with {} timed out ***",
                         timestamp, peer_name
                     ));
-                    app.last_rendered_frame = None;
-                    app.call_last_packet = None;
-                    app.call_connected = false;
-
-                    // Stop webcam
-                    if let Some(cam) = &app.webcam {
-                        cam.stop().await;
-                    }
-
-                    // Redraw UI if needed
-                    if app.active_tab == Tab::Call {
-                        // Switch back to Chat
-                        app.active_tab = Tab::Chat;
-                        let gemini_available = app.gemini_chat.is_some();
-                        let tunes_available = app.tunes_available();
-                        let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                            &app.config.network.name,
-                            app.active_tab,
-                            gemini_available,
-                            tunes_available,
-                            app.active_call.as_deref(),
-                            None,
-                            width,
-                        ));
-                        let _ = app.serial.write_str(&app.chat_buffer.render());
-                        let _ = app.serial.write_str(&redraw_input(
-                            &app.config.network.name,
-                            &app.line_buffer,
-                            app.input_cursor,
-                            width,
-                        ));
-                    } else {
-                        // Just update the tab bar
-                        let gemini_available = app.gemini_chat.is_some();
-                        let tunes_available = app.tunes_available();
-                        let _ = app.serial.write_str(&redraw_tab_bar(
-                            app.active_tab,
-                            gemini_available,
-                            tunes_available,
-                            app.active_call.as_deref(),
-                            width,
-                        ));
-                    }
+                    app.call_ended(&peer_name, width).await;
                 }
             }
         }
 
         // Check for peer events (join/leave)
         while let Ok(event) = app.peer_event_rx.try_recv() {
-            let timestamp = Local::now().format("%I:%M%p");
-            let msg = match event {
-                PeerEvent::Joined { name, addr } => {
-                    app.net_node.add_peer(name.clone(), addr);
-                    format!("[{}] *** {} has joined ***", timestamp, name)
-                }
-                PeerEvent::Left { name, addr } => {
-                    app.net_node.remove_peer(addr);
-                    format!("[{}] *** {} has left ***", timestamp, name)
-                }
-            };
-            app.push_chat(msg);
-            if app.active_tab == Tab::Chat {
-                let _ = app.serial.write_str(&app.chat_buffer.render());
+            if app.handle_peer_event(event).await && app.active_tab == Tab::Chat {
+                let _ = app.serial.write_str(&app.chat_buffer.render_update());
             }
         }
 
         // Check for discovered peers
-        while let Ok(peer) = app.discovery_rx.try_recv() {
+        // Peers announced by the relay join the same path as LAN discovery
+        let relay_members = app.net_node.take_relay_members();
+        let discovered: Vec<_> = std::iter::from_fn(|| app.discovery_rx.try_recv().ok()).collect();
+        for peer in discovered.into_iter().chain(relay_members) {
             // Check if this is a peer we already know and is still active
             if app.net_node.has_peer(peer.addr, PEER_TIMEOUT) {
                 // Update last_seen for active peers
@@ -428,6 +591,10 @@ async fn main() {
                     eprintln!("Discovered peer: {} at {}", peer.name, peer.addr);
                 }
                 app.net_node.add_peer(peer.name.clone(), peer.addr);
+                if app.backfill.wanted() {
+                    let _ = app.net_node.request_history(peer.addr).await;
+                }
+                app.send_room_state(peer.addr).await;
             }
         }
 
@@ -439,277 +606,43 @@ async fn main() {
             && let Ok(msg) = app.net_rx.try_recv()
         {
             messages_processed += 1;
-            // Update call timeout if message is from active peer
-            if let Some(peer_name) = &app.active_call {
-                let from_peer = match &msg {
-                    Message::Chat { from, .. } => Some(from),
-                    Message::StreamFrame { from, .. } => Some(from),
-                    Message::VideoFrame { from, .. } => Some(from),
-                    Message::VideoFrameFragment { from, .. } => Some(from),
-                    Message::CallRequest { from } => Some(from),
-                    Message::CallHangup { from } => Some(from),
-                    _ => None,
-                };
-
-                if let Some(from) = from_peer
-                    && from == peer_name
-                {
-                    app.call_last_packet = Some(std::time::Instant::now());
-                    app.call_connected = true;
-                }
-            }
-
-            match msg {
-                Message::Chat { from, text } => {
-                    let timestamp = Local::now().format("%I:%M%p");
-
-                    // Check if our name is mentioned in the message (case-insensitive)
-                    let my_name = &app.config.network.name;
-                    if from != *my_name && text.to_lowercase().contains(&my_name.to_lowercase()) {
-                        let _ = app.serial.write_str("\x07");
-                    }
-
-                    // Check if this is an image message
-                    if text.starts_with("[IMAGE]\n") {
-                        // Add header
-                        app.push_chat(format!("[{}] {} shared an image:", timestamp, from));
-                        // Add each line of the ASCII art
-                        for line in text.strip_prefix("[IMAGE]\n").unwrap_or(&text).lines() {
-                            app.push_chat(line.to_string());
-                        }
-                    } else if text.starts_with("\x01ACTION ") {
-                        // IRC-style /me action
-                        let action = text.strip_prefix("\x01ACTION ").unwrap_or("");
-                        let formatted = format!("[{}] * {} {}", timestamp, from, action);
-                        app.push_chat(formatted);
-                    } else {
-                        // Regular chat message
-                        let formatted = format!("[{}] {}: {}", timestamp, from, text);
-                        app.push_chat(formatted);
-                    }
-                    app.chat_buffer.scroll_to_bottom();
-                    had_messages = true;
-                }
-                Message::CallRequest { from } => {
-                    let is_busy = if let Some(current_peer) = &app.active_call {
-                        current_peer != &from
-                    } else {
-                        false
-                    };
-
-                    if is_busy {
-                        // We are busy, reject the call
-                        let msg = Message::CallReject {
-                            from: app.config.network.name.clone(),
-                        };
-                        if let Some(peer) = app.net_node.peers().iter().find(|p| p.name == from)
-                            && let Err(e) =
-                                futures::executor::block_on(app.net_node.send_to(&msg, peer.addr))
-                        {
-                            eprintln!("Failed to send call rejection: {}", e);
-                        }
-                    } else {
-                        let timestamp = Local::now().format("%I:%M%p");
-
-                        // If we are already calling them, this is an answer
-                        if app.active_call.as_deref() == Some(&from) {
-                            let msg =
-                                format!("[{}] *** Call connected with {} ***", timestamp, from);
-                            app.push_chat(msg);
-                            app.call_connected = true;
-                        } else {
-                            let msg = format!(
-                                "[{}] *** {} has initiated a call with you ***",
-                                timestamp, from
-                            );
-                            app.push_chat(msg);
-                            // Ring the bell (3 times for a ringing effect)
-                            let _ = app.serial.write_str("\x07\x07\x07");
-                        }
-
-                        app.chat_buffer.scroll_to_bottom();
-                        had_messages = true;
-                    }
-                }
-                Message::CallReject { from } => {
-                    let timestamp = Local::now().format("%I:%M%p");
-                    let msg = format!("[{}] *** {} is busy ***", timestamp, from);
-                    app.push_chat(msg);
-                    app.chat_buffer.scroll_to_bottom();
-                    had_messages = true;
-
-                    // If we are trying to call this person, hang up
-                    if let Some(current_peer) = &app.active_call
-                        && current_peer == &from
-                    {
-                        app.active_call = None;
-                        app.last_rendered_frame = None;
-                        app.call_last_packet = None;
-                        app.call_connected = false;
-
-                        // Stop webcam
-                        if let Some(cam) = &app.webcam {
-                            cam.stop().await;
-                        }
-
-                        // Switch back to Chat
-                        if app.active_tab == Tab::Call {
-                            app.active_tab = Tab::Chat;
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                &app.config.network.name,
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                None,
-                                width,
-                            ));
-                            let _ = app.serial.write_str(&app.chat_buffer.render());
-                            let _ = app.serial.write_str(&redraw_input(
-                                &app.config.network.name,
-                                &app.line_buffer,
-                                app.input_cursor,
-                                width,
-                            ));
-                        } else {
-                            // Just update the tab bar
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            let _ = app.serial.write_str(&redraw_tab_bar(
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                width,
-                            ));
-                        }
-                    }
-                }
-                Message::CallHangup { from } => {
-                    let timestamp = Local::now().format("%I:%M%p");
-                    let msg = format!("[{}] *** {} hung up ***", timestamp, from);
-                    app.push_chat(msg);
-                    app.chat_buffer.scroll_to_bottom();
-                    had_messages = true;
-
-                    // If we are in a call with this person, hang up
-                    if let Some(current_peer) = &app.active_call
-                        && current_peer == &from
-                    {
-                        app.active_call = None;
-                        app.last_rendered_frame = None;
-                        app.call_last_packet = None;
-                        app.call_connected = false;
-
-                        // Stop webcam
-                        if let Some(cam) = &app.webcam {
-                            cam.stop().await;
-                        }
-
-                        // If we were in the Call tab, switch back to Chat
-                        if app.active_tab == Tab::Call {
-                            app.active_tab = Tab::Chat;
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                &app.config.network.name,
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                None,
-                                width,
-                            ));
-                            let _ = app.serial.write_str(&app.chat_buffer.render());
-                            let _ = app.serial.write_str(&redraw_input(
-                                &app.config.network.name,
-                                &app.line_buffer,
-                                app.input_cursor,
-                                width,
-                            ));
-                        } else {
-                            // Just update the tab bar
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            let _ = app.serial.write_str(&redraw_tab_bar(
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                width,
-                            ));
-                        }
-                    }
-                }
-                Message::StreamFrame { from, .. } => {
-                    // Legacy: ignore pre-rendered StreamFrame from older peers
-                    eprintln!("Received legacy StreamFrame from {} (ignored)", from);
-                }
-                Message::VideoFrame {
-                    from,
-                    width: w,
-                    height: h,
-                    pixels,
-                } => {
-                    app.current_video_frame = Some((
-                        from,
-                        RawFrame {
-                            width: w,
-                            height: h,
-                            pixels,
-                        },
-                    ));
-                    app.stats_frames_received += 1;
-                }
-                Message::VideoFrameFragment {
-                    from,
-                    width,
-                    height,
-                    frame_id,
-                    fragment_idx,
-                    total_fragments,
-                    data,
-                } => {
-                    // Process the fragment and check if frame is complete
-                    if let Some(Message::VideoFrame {
-                        from,
-                        width,
-                        height,
-                        pixels,
-                    }) = app.net_node.process_fragment(
-                        from,
-                        width,
-                        height,
-                        frame_id,
-                        fragment_idx,
-                        total_fragments,
-                        data,
-                    ) {
-                        app.current_video_frame = Some((
-                            from,
-                            RawFrame {
-                                width,
-                                height,
-                                pixels,
-                            },
-                        ));
-                        app.stats_frames_received += 1;
-                    }
-                }
-                _ => {}
+            // Settling a name clash may rename us
+            let renamed = matches!(msg, Message::NickInUse { .. });
+            had_messages |= app.handle_message(msg, width).await;
+            if renamed {
+                max_input_len = max_input_length(&app.config.network.name, width);
             }
         }
+        had_messages |= app.pump_transfers().await;
+        // Queued video and file data, in turn per peer
+        if let Err(e) = app.net_node.flush_send_queue().await {
+            eprintln!("Failed to send queued traffic: {}", e);
+        }
         // Render once after processing all messages
         if had_messages
             && app.active_tab == Tab::Chat
-            && let Err(e) = app.serial.write_str(&app.chat_buffer.render())
+            && let Err(e) = app.serial.write_str(&app.chat_buffer.render_update())
         {
             eprintln!("Serial write error: {}", e);
             break;
         }
 
+        if app.active_call.is_none() {
+            app.video_sender.end();
+        }
+        // Let the peer we're calling know how much of its video is arriving
+        if let Some(peer) = app.active_call.clone()
+            && let Some((fragments, bytes)) = app.video_receiver.report_due(&peer)
+            && let Some(addr) = app
+                .net_node
+                .peers()
+                .iter()
+                .find(|p| p.name == peer)
+                .map(|p| p.addr)
+        {
+            let _ = app.net_node.send_video_report(fragments, bytes, addr).await;
+        }
+
         // Handle Call/Video logic
         // We process video if we are in the Call tab OR if we have an active call (background processing)
         if (app.active_tab == Tab::Call || app.active_call.is_some())
@@ -719,11 +652,9 @@ async fn main() {
             let mut frame_to_render: Option<Vec<String>> = None;
             let mut sender_name = String::new();
 
-            // Get render mode for local display
-            let render_mode = webcam::RenderMode::from_terminal_mode(
-                &app.config.terminal.mode,
-                app.config.webcam.sixel_shades,
-            );
+            // Get renderer for local display
+            let renderer = app.renderer.clone();
+            let dither = app.config.dither();
 
             // Try to capture from webcam if available
             let mut local_raw_frame: Option<RawFrame> = None;
@@ -744,26 +675,41 @@ async fn main() {
                                 .find(|p| p.name == *target_name)
                                 .map(|p| p.addr);
 
-                            if let Some(addr) = target_addr {
+                            // Skip or shrink frames while the path is congested
+                            let adaptive = app.config.webcam.adaptive;
+                            app.video_sender.begin(target_name);
+                            if let Some(addr) = target_addr
+                                && (!adaptive || app.video_sender.should_send(frame_delay))
+                            {
+                                let scale = if adaptive {
+                                    app.video_sender.scale()
+                                } else {
+                                    1
+                                };
+                                let frame = scale_raw_frame(&raw_frame, scale);
+
                                 // Send raw frame data with fragmentation support
                                 let frame_id = app.video_frame_id;
                                 app.video_frame_id = app.video_frame_id.wrapping_add(1);
 
-                                if let Err(e) = app
+                                match app
                                     .net_node
                                     .send_video_frame(
                                         &app.config.network.name,
-                                        raw_frame.width,
-                                        raw_frame.height,
-                                        &raw_frame.pixels,
+                                        frame.width,
+                                        frame.height,
+                                        &frame.pixels,
                                         frame_id,
                                         addr,
                                     )
                                     .await
                                 {
-                                    eprintln!("Failed to send video frame: {}", e);
-                                } else {
-                                    app.stats_frames_sent += 1;
+                                    Ok(0) => {}
+                                    Ok(fragments) => {
+                                        app.video_sender.record_sent(fragments);
+                                        app.stats_frames_sent += 1;
+                                    }
+                                    Err(e) => eprintln!("Failed to send video frame: {}", e),
                                 }
                             }
                         }
@@ -774,8 +720,20 @@ async fn main() {
                 }
             }
 
-            // Only render if we are actually looking at the Call tab
-            if app.active_tab == Tab::Call {
+            // An idle rig watches for movement
+            if app.config.webcam.motion
+                && let Some(raw_frame) = &local_raw_frame
+            {
+                if app.active_call.is_some() {
+                    app.motion.reset();
+                } else if app.motion.check(raw_frame, std::time::Instant::now()) {
+                    motion_alert(&mut app, raw_frame, width);
+                }
+            }
+
+            // Only render if we are actually looking at the Call tab, and the
+            // last frame has had time to get to the terminal
+            if app.active_tab == Tab::Call && pacer.ready(std::time::Instant::now()) {
                 // Determine what to render
                 // 1. If we are calling someone, try to show their video
                 if let Some(peer_name) = &app.active_call {
@@ -783,11 +741,7 @@ async fn main() {
                         && from == peer_name
                     {
                         // Render received raw frame according to OUR terminal mode
-                        let lines = raw_frame_to_output(
-                            raw_frame,
-                            render_mode,
-                            app.config.webcam.sixel_shades,
-                        );
+                        let lines = raw_frame_to_output(raw_frame, renderer.as_ref(), dither);
                         frame_to_render = Some(lines);
                         sender_name = from.clone();
                     }
@@ -797,11 +751,7 @@ async fn main() {
                         && peer_name == &app.config.network.name
                         && let Some(raw_frame) = &local_raw_frame
                     {
-                        let lines = raw_frame_to_output(
-                            raw_frame,
-                            render_mode,
-                            app.config.webcam.sixel_shades,
-                        );
+                        let lines = raw_frame_to_output(raw_frame, renderer.as_ref(), dither);
                         frame_to_render = Some(lines);
                         sender_name = app.config.network.name.clone();
                     }
@@ -818,21 +768,13 @@ async fn main() {
                 //    OR if we have received a frame from someone else (passive watching)
                 if frame_to_render.is_none() {
                     if let Some((from, raw_frame)) = &app.current_video_frame {
-                        let lines = raw_frame_to_output(
-                            raw_frame,
-                            render_mode,
-                            app.config.webcam.sixel_shades,
-                        );
+                        let lines = raw_frame_to_output(raw_frame, renderer.as_ref(), dither);
                         frame_to_render = Some(lines);
                         sender_name = from.clone();
                     } else if app.active_call.is_none() {
                         // Only show mirror if not in a call
                         if let Some(raw_frame) = &local_raw_frame {
-                            let lines = raw_frame_to_output(
-                                raw_frame,
-                                render_mode,
-                                app.config.webcam.sixel_shades,
-                            );
+                            let lines = raw_frame_to_output(raw_frame, renderer.as_ref(), dither);
                             frame_to_render = Some(lines);
                             sender_name = app.config.network.name.clone();
                         }
@@ -842,7 +784,7 @@ async fn main() {
                 // Render if we have a frame
                 if let Some(lines) = frame_to_render {
                     let (rendered, frame) = render_stream(
-                        &sender_name,
+                        &app.peer_label(&sender_name),
                         &lines,
                         app.last_rendered_frame.as_ref(),
                         width,
@@ -851,9 +793,16 @@ async fn main() {
                     app.stats_bytes_sent += rendered.len();
                     app.stats_frames_rendered += 1;
 
+                    let started = std::time::Instant::now();
                     if let Err(e) = app.serial.write_str(&rendered) {
                         eprintln!("Serial write error in Call tab: {}", e);
                     }
+                    pacer.record(
+                        started,
+                        rendered.len(),
+                        started.elapsed(),
+                        app.serial.backlog(),
+                    );
                     app.last_rendered_frame = Some(frame);
                 }
             }
@@ -866,9 +815,22 @@ async fn main() {
                 let tx_fps = app.stats_frames_sent as f64 / elapsed;
                 let rx_fps = app.stats_frames_received as f64 / elapsed;
 
+                let latency = app
+                    .active_call
+                    .as_deref()
+                    .and_then(|peer| app.net_node.peer_latency(peer))
+                    .map(|l| l.to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+
                 eprintln!(
-                    "[Call Stats] Render: {:.1} FPS, TX: {:.1} FPS, RX: {:.1} FPS, BW: {:.1} KB/s",
-                    fps, tx_fps, rx_fps, kbps
+                    "[Call Stats] Render: {:.1} FPS (serial pace {:.1}), TX: {:.1} FPS, RX: {:.1} FPS, BW: {:.1} KB/s, RTT: {}, Send quality: {}",
+                    fps,
+                    pacer.fps(),
+                    tx_fps,
+                    rx_fps,
+                    kbps,
+                    latency,
+                    app.video_sender
                 );
 
                 app.stats_last_check = std::time::Instant::now();
@@ -889,51 +851,75 @@ async fn main() {
             let _ = app.serial.write_str(&tunes.render());
         }
 
+        // Keep the clock and counts on the status bar current
+        if last_status_refresh.elapsed() >= status_refresh_delay {
+            last_status_refresh = std::time::Instant::now();
+            app.tick_status(width);
+        }
+
+        // Count down the /timer on the separator row
+        if last_timer_refresh.elapsed() >= timer_refresh_delay {
+            last_timer_refresh = std::time::Instant::now();
+            app.tick_timer(width);
+            app.ambient_tick(width).await;
+            app.supervise().await;
+        }
+        app.tick_bell();
+        app.tick_screensaver(width);
+
+        // Keep the Stats tab's counters current
+        if app.active_tab == Tab::Stats && last_stats_refresh.elapsed() >= stats_refresh_delay {
+            last_stats_refresh = std::time::Instant::now();
+            let _ = app.serial.write_str(&render_stats(
+                &app.net_node.peer_stats(),
+                &app.peer_caps,
+                width,
+            ));
+        }
+
         // Check for serial input
         match app.serial.read(&mut serial_buf) {
             Ok(0) => {
                 // No data available - the loop interval already prevents busy-looping
+                // An ESC with nothing after it is the Escape key
+                if escape_parser.expire(std::time::Instant::now()) == Some(EscapeSequence::Escape) {
+                    match app.bindings.action(&Key::Escape(EscapeSequence::Escape)) {
+                        Some(action)
+                            if run_action(&mut app, action, width, &mut last_stats_refresh)
+                                .await => {}
+                        _ => cancel(&mut app, width).await,
+                    }
+                }
+                if app
+                    .paste
+                    .as_ref()
+                    .is_some_and(|p| p.settled(std::time::Instant::now()))
+                {
+                    app.finish_paste(width);
+                }
             }
             Ok(n) => {
+                app.note_activity();
+                // A key that wakes the screen from the screensaver does nothing else
+                let keys = if app.wake_screensaver(width) {
+                    &serial_buf[..0]
+                } else {
+                    &serial_buf[..n]
+                };
                 // Process input character by character
-                for &byte in &serial_buf[..n] {
+                for (i, &byte) in keys.iter().enumerate() {
                     // Handle escape sequences in progress
                     if escape_parser.is_parsing() {
                         if let Some(seq) = escape_parser.feed(byte) {
+                            if let Some(action) = app.bindings.action(&Key::Escape(seq.clone()))
+                                && run_action(&mut app, action, width, &mut last_stats_refresh)
+                                    .await
+                            {
+                                continue;
+                            }
                             match seq {
-                                EscapeSequence::PageUp => {
-                                    // Page Up - scroll up (on active buffer) or page up in tunes
-                                    if app.active_tab == Tab::Tunes {
-                                        if let Some(ref mut tunes) = app.tunes_state {
-                                            tunes.page_up();
-                                            let _ = app.serial.write_str(&tunes.render());
-                                        }
-                                    } else {
-                                        let active_buffer = if app.active_tab == Tab::Chat {
-                                            &mut app.chat_buffer
-                                        } else {
-                                            &mut app.ai_buffer
-                                        };
-                                        active_buffer.scroll_up(10);
-                                        let _ = app.serial.write_str(&active_buffer.render());
-                                    }
-                                }
-                                EscapeSequence::PageDown => {
-                                    // Page Down - scroll down (on active buffer) or page down in tunes
-                                    if app.active_tab == Tab::Tunes {
-                                        if let Some(ref mut tunes) = app.tunes_state {
-                                            tunes.page_down();
-                                            let _ = app.serial.write_str(&tunes.render());
-                                        }
-                                    } else {
-                                        let active_buffer = if app.active_tab == Tab::Chat {
-                                            &mut app.chat_buffer
-                                        } else {
-                                            &mut app.ai_buffer
-                                        };
-                                        active_buffer.scroll_down(10);
-                                        let _ = app.serial.write_str(&active_buffer.render());
-                                    }
+                                EscapeSequence::PageUp | EscapeSequence::PageDown => {
+                                    // Only through [keys]: handled above
                                 }
                                 EscapeSequence::ArrowUp => {
                                     // Up Arrow - navigate tunes or history previous
@@ -942,10 +928,14 @@ async fn main() {
                                             tunes.move_up();
                                             let _ = app.serial.write_str(&tunes.render());
                                         }
-                                    } else if app.active_tab != Tab::Call
+                                    } else if app.active_tab == Tab::Todo {
+                                        app.todo.move_up();
+                                        let _ = app.serial.write_str(&app.todo.render());
+                                    } else if app.active_tab.has_input()
                                         && !app.ai_processing
-                                        && !app.input_history.is_empty()
+                                        && !app.input_history.entries(app.active_tab).is_empty()
                                     {
+                                        let history = app.input_history.entries(app.active_tab);
                                         let new_index = match app.history_index {
                                             Some(i) => {
                                                 if i > 0 {
@@ -954,13 +944,14 @@ async fn main() {
                                                     0
                                                 }
                                             }
-                                            None => app.input_history.len() - 1,
+                                            None => history.len() - 1,
                                         };
 
                                         app.history_index = Some(new_index);
-                                        app.line_buffer = app.input_history[new_index].clone();
+                                        app.line_buffer = history[new_index].clone();
                                         app.input_cursor = app.line_buffer.len();
                                         let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
                                             &app.config.network.name,
                                             &app.line_buffer,
                                             app.input_cursor,
@@ -975,11 +966,15 @@ async fn main() {
                                             tunes.move_down();
                                             let _ = app.serial.write_str(&tunes.render());
                                         }
-                                    } else if app.active_tab != Tab::Call
+                                    } else if app.active_tab == Tab::Todo {
+                                        app.todo.move_down();
+                                        let _ = app.serial.write_str(&app.todo.render());
+                                    } else if app.active_tab.has_input()
                                         && !app.ai_processing
                                         && let Some(i) = app.history_index
                                     {
-                                        if i + 1 >= app.input_history.len() {
+                                        let history = app.input_history.entries(app.active_tab);
+                                        if i + 1 >= history.len() {
                                             // End of history, clear input
                                             app.history_index = None;
                                             app.line_buffer.clear();
@@ -987,10 +982,11 @@ async fn main() {
                                         } else {
                                             let new_index = i + 1;
                                             app.history_index = Some(new_index);
-                                            app.line_buffer = app.input_history[new_index].clone();
+                                            app.line_buffer = history[new_index].clone();
                                             app.input_cursor = app.line_buffer.len();
                                         }
                                         let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
                                             &app.config.network.name,
                                             &app.line_buffer,
                                             app.input_cursor,
@@ -1000,12 +996,13 @@ async fn main() {
                                 }
                                 EscapeSequence::ArrowRight => {
                                     // Right Arrow - Move Cursor Right
-                                    if app.active_tab != Tab::Call
+                                    if app.active_tab.has_input()
                                         && !app.ai_processing
                                         && app.input_cursor < app.line_buffer.len()
                                     {
                                         app.input_cursor += 1;
                                         let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
                                             &app.config.network.name,
                                             &app.line_buffer,
                                             app.input_cursor,
@@ -1015,12 +1012,56 @@ async fn main() {
                                 }
                                 EscapeSequence::ArrowLeft => {
                                     // Left Arrow - Move Cursor Left
-                                    if app.active_tab != Tab::Call
+                                    if app.active_tab.has_input()
                                         && !app.ai_processing
                                         && app.input_cursor > 0
                                     {
                                         app.input_cursor -= 1;
                                         let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
+                                            &app.config.network.name,
+                                            &app.line_buffer,
+                                            app.input_cursor,
+                                            width,
+                                        ));
+                                    }
+                                }
+                                EscapeSequence::CtrlArrowUp | EscapeSequence::CtrlArrowDown => {
+                                    // Ctrl+Up/Down - grow or shrink the input area
+                                    if app.active_tab.has_input() && !app.ai_processing {
+                                        max_input_len = app.resize_input(
+                                            seq == EscapeSequence::CtrlArrowUp,
+                                            width,
+                                        );
+                                    }
+                                }
+                                EscapeSequence::Home | EscapeSequence::End => {
+                                    // Home/End - cursor to the start or end of the input
+                                    if app.active_tab.has_input() && !app.ai_processing {
+                                        app.input_cursor = if seq == EscapeSequence::Home {
+                                            0
+                                        } else {
+                                            app.line_buffer.chars().count()
+                                        };
+                                        let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
+                                            &app.config.network.name,
+                                            &app.line_buffer,
+                                            app.input_cursor,
+                                            width,
+                                        ));
+                                    }
+                                }
+                                EscapeSequence::Delete => {
+                                    // Delete - remove the character under the cursor
+                                    if app.active_tab.has_input()
+                                        && !app.ai_processing
+                                        && let Some((byte_idx, _)) =
+                                            app.line_buffer.char_indices().nth(app.input_cursor)
+                                    {
+                                        app.line_buffer.remove(byte_idx);
+                                        let _ = app.serial.write_str(&redraw_input(
+                                            app.theme,
                                             &app.config.network.name,
                                             &app.line_buffer,
                                             app.input_cursor,
@@ -1028,22 +1069,102 @@ async fn main() {
                                         ));
                                     }
                                 }
-                                EscapeSequence::Unknown => {
-                                    // Unknown sequence, ignore
+                                EscapeSequence::PasteStart => {
+                                    // Bracketed paste - gather it up in the Chat tab
+                                    if app.active_tab == Tab::Chat && !app.ai_processing {
+                                        app.start_paste(true, width);
+                                    }
+                                }
+                                EscapeSequence::PasteEnd => {
+                                    if app.paste.as_ref().is_some_and(|p| p.is_bracketed()) {
+                                        app.finish_paste(width);
+                                    }
+                                }
+                                EscapeSequence::AltDigit(n) => {
+                                    // Alt+1 to Alt+0 - switch chat windows
+                                    if app.active_tab == Tab::Chat {
+                                        let index = (n as usize + 9) % 10;
+                                        app.switch_window(index, width);
+                                    }
+                                }
+                                EscapeSequence::Function(n) => {
+                                    // A function key with a canned message sends it
+                                    if app.active_tab == Tab::Chat
+                                        && let Some(text) = app.macros.canned(n)
+                                    {
+                                        let text = text.to_string();
+                                        if app.windows.is_mesh() {
+                                            app.send_mesh_chat(&text).await;
+                                        } else {
+                                            app.send_window_chat(&text).await;
+                                            app.chat_buffer.scroll_to_bottom();
+                                            let _ = app
+                                                .serial
+                                                .write_str(&app.chat_buffer.render_update());
+                                        }
+                                    }
+                                }
+                                EscapeSequence::Escape => cancel(&mut app, width).await,
+                                EscapeSequence::Click { row, col } => {
+                                    click(&mut app, row, col, width, &mut last_stats_refresh).await;
+                                }
+                                EscapeSequence::Insert | EscapeSequence::Unknown => {
+                                    // Nothing bound, or an unknown sequence: ignore
                                 }
                             }
                         }
                         continue;
                     }
 
-                    // Parse the byte into an input event
-                    match parse_byte(byte) {
-                        InputEvent::EscapeStart => {
-                            // Start of escape sequence
-                            escape_parser.feed(byte);
-                        }
-                        InputEvent::Enter => {
-                            if app.ai_processing {
+                    // Everything but an escape sequence goes into a paste being gathered
+                    if byte != 0x1b
+                        && let Some(paste) = app.paste.as_mut()
+                    {
+                        paste.push(byte, &app.keymap, std::time::Instant::now());
+                        continue;
+                    }
+
+                    if let Some(action) = app.bindings.action(&Key::Byte(byte))
+                        && run_action(&mut app, action, width, &mut last_stats_refresh).await
+                    {
+                        continue;
+                    }
+
+                    // Parse the byte into an input event
+                    let mut event = parse_byte(byte, &app.keymap);
+                    // In a compose sequence, the characters typed make one
+                    if app.compose.is_active() {
+                        let typed = match event {
+                            InputEvent::Char(c) => Some(c),
+                            InputEvent::Space => Some(' '),
+                            _ => None,
+                        };
+                        match typed.map(|c| app.compose.feed(c)) {
+                            Some(Composed::Pending) => continue,
+                            Some(Composed::Char(c)) => event = InputEvent::Char(c),
+                            Some(Composed::Invalid) => {
+                                let _ = app.serial.write_str("\x07");
+                                continue;
+                            }
+                            // Anything else gives up on it
+                            None => app.compose.cancel(),
+                        }
+                    }
+                    match event {
+                        InputEvent::EscapeStart => {
+                            // Start of escape sequence
+                            escape_parser.feed(byte);
+                        }
+                        InputEvent::Enter => {
+                            if app.ai_processing {
+                                continue;
+                            }
+                            // A Return with more text straight after it is a paste
+                            if app.active_tab == Tab::Chat && paste::burst_follows(&keys[i + 1..]) {
+                                app.start_paste(false, width);
+                                if let Some(paste) = app.paste.as_mut() {
+                                    paste.push(byte, &app.keymap, std::time::Instant::now());
+                                }
                                 continue;
                             }
 
@@ -1058,21 +1179,30 @@ async fn main() {
                                 continue;
                             }
 
-                            if app.active_tab == Tab::Call {
-                                // Call tab has no Enter action
+                            // Enter in Todo checks off (or unchecks) the selected item
+                            if app.active_tab == Tab::Todo {
+                                if let Some(item) = app.todo.selected_item() {
+                                    let op = TodoOp::Toggle {
+                                        id: item.id,
+                                        done: !item.done,
+                                        by: app.config.network.name.clone(),
+                                        at: chrono::Utc::now().timestamp_millis(),
+                                    };
+                                    app.change_todo(op).await;
+                                }
+                                continue;
+                            }
+
+                            if !app.active_tab.has_input() {
+                                // Call and Stats tabs have no Enter action
                                 continue;
                             }
 
                             if !app.line_buffer.is_empty() {
-                                let text = app.line_buffer.clone();
+                                let text = app.macros.expand(&app.line_buffer).into_owned();
 
                                 // Add to history
-                                if app.input_history.last() != Some(&text) {
-                                    app.input_history.push(text.clone());
-                                    if app.input_history.len() > 25 {
-                                        app.input_history.remove(0);
-                                    }
-                                }
+                                app.input_history.push(app.active_tab, &text);
                                 app.history_index = None;
                                 app.line_buffer.clear();
                                 app.input_cursor = 0;
@@ -1080,6 +1210,7 @@ async fn main() {
                                 // Redraw empty input line first
                                 if app.active_tab != Tab::Call {
                                     let _ = app.serial.write_str(&redraw_input(
+                                        app.theme,
                                         &app.config.network.name,
                                         "",
                                         0,
@@ -1095,114 +1226,51 @@ async fn main() {
                                             if text.starts_with("/me ") {
                                                 let action =
                                                     text.strip_prefix("/me ").unwrap_or("");
-                                                let timestamp = Local::now().format("%I:%M%p");
+                                                let timestamp = stamp::now();
                                                 let formatted = format!(
-                                                    "[{}] * {} {}",
+                                                    "{}* {} {}",
                                                     timestamp, app.config.network.name, action
                                                 );
-                                                app.push_chat(formatted);
+                                                let lamport = app.net_node.tick_lamport();
+                                                app.push_own_chat(formatted, lamport);
                                                 app.chat_buffer.scroll_to_bottom();
                                                 let _ =
                                                     app.serial.write_str(&app.chat_buffer.render());
 
                                                 // Broadcast to peers
                                                 let action_msg = format!("\x01ACTION {}", action);
+                                                app.chat_history
+                                                    .record(&app.config.network.name, &action_msg);
                                                 if let Err(e) = futures::executor::block_on(
-                                                    app.net_node.send_chat(&action_msg),
+                                                    app.net_node.send_chat(&action_msg, lamport),
                                                 ) {
                                                     eprintln!("Failed to send action: {}", e);
                                                 }
                                             } else {
                                                 match text.as_str() {
                                                     "/image" => {
-                                                        // Capture webcam snapshot
-                                                        let timestamp =
-                                                            Local::now().format("%I:%M%p");
-                                                        let render_mode =
-                                                            webcam::RenderMode::from_terminal_mode(
-                                                                &app.config.terminal.mode,
-                                                                app.config.webcam.sixel_shades,
-                                                            );
-
-                                                        let result = if let Some(cam) = &app.webcam
-                                                        {
-                                                            if let Some(device) =
-                                                                &app.config.webcam.device
-                                                            {
-                                                                cam.take_snapshot(
-                                                                    device.clone(),
-                                                                    render_mode,
-                                                                    width,
-                                                                )
-                                                                .await
-                                                            } else {
-                                                                Err(webcam::WebcamError::NotConfigured)
-                                                            }
-                                                        } else {
-                                                            // Fallback if app.webcam is None (e.g. initialization failed or not configured)
-                                                            webcam::capture_ascii_snapshot(
-                                                                app.config.webcam.device.as_deref(),
-                                                                render_mode,
-                                                                width,
-                                                            )
-                                                        };
-
-                                                        match result {
-                                                            Ok(lines) => {
-                                                                // Add header
-                                                                app.push_chat(format!(
-                                                                    "[{}] {} shared an image:",
-                                                                    timestamp,
-                                                                    app.config.network.name
-                                                                ));
-                                                                // Add each line of the ASCII art
-                                                                for line in &lines {
-                                                                    app.push_chat(line.clone());
-                                                                }
-                                                                app.chat_buffer.scroll_to_bottom();
-                                                                let _ = app.serial.write_str(
-                                                                    &app.chat_buffer.render(),
-                                                                );
-
-                                                                // Also send to peers as multi-line message
-                                                                let img_msg = format!(
-                                                                    "[IMAGE]\n{}",
-                                                                    lines.join("\n")
-                                                                );
-                                                                if let Err(e) =
-                                                                    futures::executor::block_on(
-                                                                        app.net_node
-                                                                            .send_chat(&img_msg),
-                                                                    )
-                                                                {
-                                                                    eprintln!(
-                                                                        "Failed to send image: {}",
-                                                                        e
-                                                                    );
-                                                                }
-                                                            }
-                                                            Err(e) => {
-                                                                let err_msg = format!(
-                                                                    "[{}] *** Webcam error: {} ***",
-                                                                    timestamp, e
-                                                                );
-                                                                app.push_chat(err_msg);
-                                                                app.chat_buffer.scroll_to_bottom();
-                                                                let _ = app.serial.write_str(
-                                                                    &app.chat_buffer.render(),
-                                                                );
-                                                            }
-                                                        }
+                                                        share_snapshot(&mut app, width, None).await;
+                                                    }
+                                                    "/snapshot save" => {
+                                                        handle_snapshot_save(&mut app, width).await;
                                                     }
                                                     "/help" => {
-                                                        let timestamp =
-                                                            Local::now().format("%I:%M%p");
-                                                        app.push_chat(format!("[{}] *** /clear, /who, /image, /me <action>, /call <peer> ***", timestamp));
+                                                        let timestamp = stamp::now();
+                                                        app.push_chat(format!("{}*** /clear, /who, /image, /imagefile <path>, /qr <text>, /figlet <text>, @ai <question>, /snapshot save, /camera list|use <index>, /me <action>, /call <peer>, /ring <peer>, /download [xmodem|kermit] <file>, /capture start|stop|screen|last <n>, /print [ai], /screenshot [file], /topic [text|-], /pin [n|text|-], /todo [add <text>|remove <n>|clear], /timer [share] <25m|pomodoro> [label] | stop, /search <text>, /nick <name>, /whois <peer>, /profile [text|-], /send <peer> <file>, /accept [n], /transfers [cancel <n>], /expand [n], /edit [text|-], /tunes sync <peer>, /window [n|name|new <peer|#channel>|close], /layout [name], /enable|/disable webcam|ai|tunes|discovery, /versions, /dump-state, /memstats ***", timestamp));
                                                         app.chat_buffer.scroll_to_bottom();
                                                         let _ = app
                                                             .serial
                                                             .write_str(&app.chat_buffer.render());
                                                     }
+                                                    "/memstats" => {
+                                                        handle_memstats(&mut app);
+                                                    }
+                                                    "/versions" => {
+                                                        handle_versions(&mut app);
+                                                    }
+                                                    "/dump-state" => {
+                                                        handle_dump_state(&mut app);
+                                                    }
                                                     "/clear" => {
                                                         app.chat_buffer.clear();
                                                         let _ = app
@@ -1210,27 +1278,40 @@ async fn main() {
                                                             .write_str(&app.chat_buffer.render());
                                                     }
                                                     "/who" => {
-                                                        let timestamp =
-                                                            Local::now().format("%I:%M%p");
+                                                        let timestamp = stamp::now();
                                                         let peers = app.net_node.peers();
                                                         if peers.is_empty() {
                                                             app.push_chat(format!(
-                                                                "[{}] *** No peers connected ***",
+                                                                "{}*** No peers connected ***",
                                                                 timestamp
                                                             ));
                                                         } else {
                                                             let peer_count = peers.len();
                                                             let peer_info: Vec<_> = peers
                                                                 .iter()
-                                                                .map(|p| {
-                                                                    format!(
-                                                                        "  - {} ({})",
-                                                                        p.name, p.addr
-                                                                    )
+                                                                .map(|p| match p.clock_offset {
+                                                                    Some(offset)
+                                                                        if offset.abs()
+                                                                            > network::CLOCK_SKEW_WARNING =>
+                                                                    {
+                                                                        format!(
+                                                                            "  - {} ({}, {}, clock {})",
+                                                                            app.peer_label(&p.name),
+                                                                            p.addr,
+                                                                            p.latency,
+                                                                            describe_clock_offset(offset)
+                                                                        )
+                                                                    }
+                                                                    _ => format!(
+                                                                        "  - {} ({}, {})",
+                                                                        app.peer_label(&p.name),
+                                                                        p.addr,
+                                                                        p.latency
+                                                                    ),
                                                                 })
                                                                 .collect();
                                                             app.push_chat(format!(
-                                                                "[{}] *** Connected Peers ({}) ***",
+                                                                "{}*** Connected Peers ({}) ***",
                                                                 timestamp, peer_count
                                                             ));
                                                             for info in peer_info {
@@ -1245,83 +1326,234 @@ async fn main() {
                                                     _ => {
                                                         if text.to_lowercase().starts_with("/call ")
                                                         {
-                                                            let peer_name = text[6..].trim();
+                                                            let peer_name =
+                                                                text[6..].trim().to_string();
                                                             if !peer_name.is_empty() {
-                                                                // Check if peer exists (or is self)
-                                                                let peer_exists = peer_name
-                                                                    == app.config.network.name
-                                                                    || app
-                                                                        .net_node
-                                                                        .peers()
-                                                                        .iter()
-                                                                        .any(|p| {
-                                                                            p.name == peer_name
-                                                                        });
-
-                                                                if peer_exists {
-                                                                    // Send CallRequest if calling a remote peer
-                                                                    if peer_name
-                                                                        != app.config.network.name
-                                                                        && let Some(peer) = app
-                                                                            .net_node
-                                                                            .peers()
-                                                                            .iter()
-                                                                            .find(|p| {
-                                                                                p.name == peer_name
-                                                                            })
-                                                                    {
-                                                                        let msg =
-                                                                            Message::CallRequest {
-                                                                                from: app
-                                                                                    .config
-                                                                                    .network
-                                                                                    .name
-                                                                                    .clone(),
-                                                                            };
-                                                                        if let Err(e) = futures::executor::block_on(app.net_node.send_to(&msg, peer.addr)) {
-                                                                            eprintln!("Failed to send call request: {}", e);
-                                                                        }
-                                                                    }
-
-                                                                    app.active_call =
-                                                                        Some(peer_name.to_string());
-                                                                    app.call_last_packet = Some(
-                                                                        std::time::Instant::now(),
-                                                                    );
-                                                                    app.active_tab = Tab::Call;
-                                                                    app.last_rendered_frame = None;
-
-                                                                    // Start webcam
-                                                                    if let Some(cam) = &app.webcam {
-                                                                        cam.start().await;
-                                                                    }
-
-                                                                    // Redraw UI
-                                                                    let status = format!(
-                                                                        "Call session with {}. Press Space to hang up.",
-                                                                        peer_name
-                                                                    );
-                                                                    let gemini_available =
-                                                                        app.gemini_chat.is_some();
-                                                                    let tunes_available =
-                                                                        app.tunes_available();
-                                                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(&app.config.network.name, app.active_tab, gemini_available, tunes_available, app.active_call.as_deref(), Some(&status), width));
-                                                                } else {
-                                                                    let timestamp = Local::now()
-                                                                        .format("%I:%M%p");
-                                                                    app.push_chat(format!("[{}] *** Peer '{}' not found ***", timestamp, peer_name));
-                                                                    app.chat_buffer
-                                                                        .scroll_to_bottom();
-                                                                    let _ = app.serial.write_str(
-                                                                        &app.chat_buffer.render(),
-                                                                    );
-                                                                }
+                                                                start_call(
+                                                                    &mut app, &peer_name, width,
+                                                                )
+                                                                .await;
                                                             }
+                                                        } else if text == "/capture"
+                                                            || text.starts_with("/capture ")
+                                                        {
+                                                            handle_capture(
+                                                                &mut app,
+                                                                text["/capture".len()..].trim(),
+                                                            );
+                                                        } else if text == "/print"
+                                                            || text.starts_with("/print ")
+                                                        {
+                                                            handle_print(
+                                                                &mut app,
+                                                                text["/print".len()..].trim(),
+                                                            );
+                                                        } else if text == "/screenshot"
+                                                            || text.starts_with("/screenshot ")
+                                                        {
+                                                            handle_screenshot(
+                                                                &mut app,
+                                                                text["/screenshot".len()..].trim(),
+                                                            );
+                                                        } else if text == "/nick"
+                                                            || text.starts_with("/nick ")
+                                                        {
+                                                            handle_nick(
+                                                                &mut app,
+                                                                text["/nick".len()..].trim(),
+                                                                width,
+                                                            );
+                                                            max_input_len = max_input_length(
+                                                                &app.config.network.name,
+                                                                width,
+                                                            );
+                                                        } else if text == "/whois"
+                                                            || text.starts_with("/whois ")
+                                                        {
+                                                            handle_whois(
+                                                                &mut app,
+                                                                text["/whois".len()..].trim(),
+                                                            );
+                                                        } else if text == "/profile"
+                                                            || text.starts_with("/profile ")
+                                                        {
+                                                            handle_profile(
+                                                                &mut app,
+                                                                text["/profile".len()..].trim(),
+                                                            );
+                                                        } else if text == "/camera"
+                                                            || text.starts_with("/camera ")
+                                                        {
+                                                            handle_camera(
+                                                                &mut app,
+                                                                text["/camera".len()..].trim(),
+                                                            )
+                                                            .await;
+                                                        } else if text == "/figlet"
+                                                            || text.starts_with("/figlet ")
+                                                        {
+                                                            handle_figlet(
+                                                                &mut app,
+                                                                text["/figlet".len()..].trim(),
+                                                                width,
+                                                            )
+                                                            .await;
+                                                        } else if text == "/qr"
+                                                            || text.starts_with("/qr ")
+                                                        {
+                                                            handle_qr(
+                                                                &mut app,
+                                                                text["/qr".len()..].trim(),
+                                                            )
+                                                            .await;
+                                                        } else if text == "/imagefile"
+                                                            || text.starts_with("/imagefile ")
+                                                        {
+                                                            handle_imagefile(
+                                                                &mut app,
+                                                                text["/imagefile".len()..].trim(),
+                                                                width,
+                                                            )
+                                                            .await;
+                                                        } else if text == "/send"
+                                                            || text.starts_with("/send ")
+                                                        {
+                                                            handle_send(
+                                                                &mut app,
+                                                                text["/send".len()..].trim(),
+                                                            );
+                                                        } else if text == "/accept"
+                                                            || text.starts_with("/accept ")
+                                                        {
+                                                            handle_accept(
+                                                                &mut app,
+                                                                text["/accept".len()..].trim(),
+                                                            );
+                                                        } else if text == "/expand"
+                                                            || text.starts_with("/expand ")
+                                                        {
+                                                            handle_expand(
+                                                                &mut app,
+                                                                text["/expand".len()..].trim(),
+                                                            );
+                                                        } else if text == "/transfers"
+                                                            || text.starts_with("/transfers ")
+                                                        {
+                                                            handle_transfers(
+                                                                &mut app,
+                                                                text["/transfers".len()..].trim(),
+                                                            );
+                                                        } else if text == "/topic"
+                                                            || text.starts_with("/topic ")
+                                                        {
+                                                            handle_topic(
+                                                                &mut app,
+                                                                text["/topic".len()..].trim(),
+                                                                width,
+                                                            );
+                                                        } else if text == "/pin"
+                                                            || text.starts_with("/pin ")
+                                                        {
+                                                            handle_pin(
+                                                                &mut app,
+                                                                text["/pin".len()..].trim(),
+                                                            );
+                                                        } else if text == "/timer"
+                                                            || text.starts_with("/timer ")
+                                                        {
+                                                            handle_timer(
+                                                                &mut app,
+                                                                text["/timer".len()..].trim(),
+                                                            );
+                                                        } else if text == "/ring"
+                                                            || text.starts_with("/ring ")
+                                                        {
+                                                            handle_ring(
+                                                                &mut app,
+                                                                text["/ring".len()..].trim(),
+                                                            );
+                                                        } else if text == "/search"
+                                                            || text.starts_with("/search ")
+                                                        {
+                                                            handle_search(
+                                                                &mut app,
+                                                                text["/search".len()..].trim(),
+                                                                width,
+                                                            );
+                                                        } else if text == "/todo"
+                                                            || text.starts_with("/todo ")
+                                                        {
+                                                            handle_todo(
+                                                                &mut app,
+                                                                text["/todo".len()..].trim(),
+                                                            );
+                                                        } else if text == "/edit"
+                                                            || text.starts_with("/edit ")
+                                                        {
+                                                            handle_edit(
+                                                                &mut app,
+                                                                text["/edit".len()..].trim(),
+                                                                max_input_len,
+                                                                width,
+                                                            );
+                                                        } else if text == "/tunes"
+                                                            || text.starts_with("/tunes ")
+                                                        {
+                                                            handle_tunes(
+                                                                &mut app,
+                                                                text["/tunes".len()..].trim(),
+                                                            );
+                                                        } else if text == "/window"
+                                                            || text.starts_with("/window ")
+                                                        {
+                                                            handle_window(
+                                                                &mut app,
+                                                                text["/window".len()..].trim(),
+                                                                width,
+                                                            );
+                                                        } else if text == "/layout"
+                                                            || text.starts_with("/layout ")
+                                                        {
+                                                            handle_layout(
+                                                                &mut app,
+                                                                text["/layout".len()..].trim(),
+                                                                width,
+                                                            );
+                                                            max_input_len = max_input_length(
+                                                                &app.config.network.name,
+                                                                width,
+                                                            );
+                                                        } else if text == "/enable"
+                                                            || text.starts_with("/enable ")
+                                                        {
+                                                            handle_enable(
+                                                                &mut app,
+                                                                text["/enable".len()..].trim(),
+                                                                true,
+                                                                width,
+                                                            );
+                                                        } else if text == "/disable"
+                                                            || text.starts_with("/disable ")
+                                                        {
+                                                            handle_enable(
+                                                                &mut app,
+                                                                text["/disable".len()..].trim(),
+                                                                false,
+                                                                width,
+                                                            );
+                                                        } else if text == "/download"
+                                                            || text.starts_with("/download ")
+                                                        {
+                                                            handle_download(
+                                                                &mut app,
+                                                                &text["/download".len()..],
+                                                                width,
+                                                            )
+                                                            .await;
                                                         } else {
-                                                            let timestamp =
-                                                                Local::now().format("%I:%M%p");
+                                                            let timestamp = stamp::now();
                                                             app.push_chat(format!(
-                                                                "[{}] *** Unknown command: {} ***",
+                                                                "{}*** Unknown command: {} ***",
                                                                 timestamp, text
                                                             ));
                                                             app.chat_buffer.scroll_to_bottom();
@@ -1332,49 +1564,75 @@ async fn main() {
                                                     }
                                                 }
                                             }
-                                        } else {
-                                            // Regular chat message
-                                            let timestamp = Local::now().format("%I:%M%p");
-                                            let our_msg = format!(
-                                                "[{}] {}: {}",
-                                                timestamp, app.config.network.name, text
+                                        } else if !app.windows.is_mesh() {
+                                            // A direct message or channel line
+                                            futures::executor::block_on(
+                                                app.send_window_chat(&text),
                                             );
-                                            app.push_chat(our_msg);
                                             app.chat_buffer.scroll_to_bottom();
                                             let _ = app.serial.write_str(&app.chat_buffer.render());
-
-                                            // Broadcast to peers
-                                            if let Err(e) = futures::executor::block_on(
-                                                app.net_node.send_chat(&text),
-                                            ) {
-                                                eprintln!("Failed to send message: {}", e);
-                                            }
+                                        } else if let Some(question) = text
+                                            .strip_prefix("@ai ")
+                                            .map(str::trim)
+                                            .filter(|q| !q.is_empty())
+                                        {
+                                            handle_summon_ai(&mut app, &text, question).await;
+                                        } else {
+                                            // Regular chat message
+                                            futures::executor::block_on(app.send_mesh_chat(&text));
                                         }
                                     }
                                     Tab::Gemini => {
-                                        // Gemini AI tab
-                                        let timestamp = Local::now().format("%I:%M%p");
+                                        // AI tab
+                                        let timestamp = stamp::now();
                                         let network_name = app.config.network.name.clone();
 
                                         // Handle commands
                                         if text == "/clear" {
-                                            if let Some(ref mut gemini) = app.gemini_chat {
-                                                gemini.clear_history();
+                                            if let Some(ref mut ai) = app.ai_chat {
+                                                ai.clear_history();
                                             }
+                                            app.new_ai_conversation();
                                             app.ai_buffer.clear();
                                             app.push_ai(format!(
-                                                "[{}] *** Conversation cleared ***",
+                                                "{}*** Conversation cleared ***",
                                                 timestamp
                                             ));
                                             app.ai_buffer.scroll_to_bottom();
                                             let _ = app.serial.write_str(&app.ai_buffer.render());
                                         } else if text == "/help" {
                                             app.push_ai(format!(
-                                                "[{}] *** /clear, /dos, /unix, /pdp, /apple ***",
+                                                "{}*** /clear, /history [list|load <n>], /chat [new|switch <name>], /look [question], /retry, /set [<setting> <value>], /dos, /unix, /pdp, /apple ***",
                                                 timestamp
                                             ));
                                             app.ai_buffer.scroll_to_bottom();
                                             let _ = app.serial.write_str(&app.ai_buffer.render());
+                                        } else if text == "/history"
+                                            || text.starts_with("/history ")
+                                        {
+                                            handle_ai_history(
+                                                &mut app,
+                                                text["/history".len()..].trim(),
+                                            );
+                                            app.ai_buffer.scroll_to_bottom();
+                                            let _ = app.serial.write_str(&app.ai_buffer.render());
+                                        } else if text == "/look" || text.starts_with("/look ") {
+                                            handle_look(&mut app, text["/look".len()..].trim())
+                                                .await;
+                                        } else if text == "/retry" {
+                                            handle_ai_retry(&mut app).await;
+                                        } else if text == "/set" || text.starts_with("/set ") {
+                                            handle_ai_set(&mut app, text["/set".len()..].trim());
+                                            app.ai_buffer.scroll_to_bottom();
+                                            let _ = app.serial.write_str(&app.ai_buffer.render());
+                                        } else if text == "/chat" || text.starts_with("/chat ") {
+                                            handle_ai_chat(
+                                                &mut app,
+                                                text["/chat".len()..].trim(),
+                                                width,
+                                            );
+                                            app.ai_buffer.scroll_to_bottom();
+                                            let _ = app.serial.write_str(&app.ai_buffer.render());
                                         } else if text == "/dos"
                                             || text == "/unix"
                                             || text == "/pdp"
@@ -1419,21 +1677,22 @@ async fn main() {
                                                 };
 
                                             // Set system prompt first (separate borrow)
-                                            if let Some(ref mut gemini) = app.gemini_chat {
-                                                gemini.set_system_prompt(system_prompt.to_string());
+                                            if let Some(ref mut ai) = app.ai_chat {
+                                                ai.set_system_prompt(system_prompt.to_string());
+                                                ai.set_verbatim(true);
                                             }
+                                            app.new_ai_conversation();
 
                                             app.ai_buffer.clear();
                                             app.ai_buffer.push(format!(
-                                                "[{}] *** {} simulation started ***",
+                                                "{}*** {} simulation started ***",
                                                 timestamp, mode_name
                                             ));
                                             app.ai_buffer.scroll_to_bottom();
                                             let _ = app.serial.write_str(&app.ai_buffer.render());
 
                                             // Prepare AI response line - show "thinking" while waiting for first token
-                                            let ai_prefix =
-                                                format!("[{}] ", Local::now().format("%I:%M%p"));
+                                            let ai_prefix = stamp::now();
 
                                             // Show thinking indicator initially
                                             let mut got_first_token = false;
@@ -1446,50 +1705,44 @@ async fn main() {
 
                                             // Stream the startup response
                                             app.ai_processing = true;
-                                            if let Some(ref mut gemini) = app.gemini_chat {
-                                                let result = gemini.send_message_streaming(startup_prompt, |chunk| {
-                                                    full_response.push_str(chunk);
-                                                    for ch in chunk.chars() {
-                                                        if !got_first_token {
-                                                            got_first_token = true;
-                                                            app.ai_buffer.update_last_line(&ai_prefix);
-                                                        }
-
-                                                        if ch == '\n' {
-                                                            app.ai_buffer.push("  ".to_string());
-                                                            if app.ai_buffer.is_full() {
-                                                                let _ = app.serial.write_str(&app.ai_buffer.render());
-                                                            } else {
-                                                                let _ = app.serial.write_str(&app.ai_buffer.render_bottom_lines(2));
-                                                            }
-                                                        } else if !ch.is_control() {
-                                                            let wrapped = app.ai_buffer.type_char(ch, "  ");
-
-                                                            if wrapped {
-                                                                if app.ai_buffer.is_full() {
-                                                                    let _ = app.serial.write_str(&app.ai_buffer.render());
-                                                                } else {
-                                                                    let _ = app.serial.write_str(&app.ai_buffer.render_bottom_lines(2));
-                                                                }
-                                                            } else {
-                                                                let _ = app.serial.write_str(&app.ai_buffer.render_last_line());
+                                            if let Some(ref mut ai) = app.ai_chat {
+                                                let result = ai
+                                                    .send_message_streaming(
+                                                        startup_prompt,
+                                                        |chunk| {
+                                                            full_response.push_str(chunk);
+                                                            if !got_first_token && !chunk.is_empty()
+                                                            {
+                                                                got_first_token = true;
+                                                                app.ai_buffer
+                                                                    .update_last_line(&ai_prefix);
                                                             }
-
-                                                            std::thread::sleep(Duration::from_millis(10));
-                                                        }
+                                                            type_reply(
+                                                                &mut app.ai_buffer,
+                                                                &mut app.serial,
+                                                                chunk,
+                                                            );
+                                                        },
+                                                    )
+                                                    .await;
+                                                app.clock.reset();
+
+                                                match result {
+                                                    Ok(reply) => {
+                                                        app.save_ai_conversation();
+                                                        app.record_ai_usage(reply.usage);
+                                                    }
+                                                    Err(e) => {
+                                                        let timestamp = stamp::now();
+                                                        app.ai_buffer.push(format!(
+                                                            "{}*** Error: {} ***",
+                                                            timestamp, e
+                                                        ));
+                                                        app.ai_buffer.scroll_to_bottom();
+                                                        let _ = app
+                                                            .serial
+                                                            .write_str(&app.ai_buffer.render());
                                                     }
-                                                }).await;
-
-                                                if let Err(e) = result {
-                                                    let timestamp = Local::now().format("%I:%M%p");
-                                                    app.ai_buffer.push(format!(
-                                                        "[{}] *** Error: {} ***",
-                                                        timestamp, e
-                                                    ));
-                                                    app.ai_buffer.scroll_to_bottom();
-                                                    let _ = app
-                                                        .serial
-                                                        .write_str(&app.ai_buffer.render());
                                                 }
                                             }
                                             app.ai_processing = false;
@@ -1503,12 +1756,17 @@ async fn main() {
                                                     full_response.replace('\n', " ")
                                                 ));
                                             }
-                                        } else if let Some(ref mut gemini) = app.gemini_chat {
+
+                                            // Copy the complete response to the printer if capturing
+                                            let captured =
+                                                format!("{}{}", ai_prefix, full_response);
+                                            for line in captured.lines() {
+                                                app.capture(line);
+                                            }
+                                        } else if app.ai_chat.is_some() {
                                             // Show user message (use client name like in chat tab)
-                                            let user_msg = format!(
-                                                "[{}] {}: {}",
-                                                timestamp, network_name, text
-                                            );
+                                            let user_msg =
+                                                format!("{}{}: {}", timestamp, network_name, text);
                                             if let Some(ref mut logger) = app.logger {
                                                 logger.log_ai(&user_msg);
                                             }
@@ -1516,112 +1774,13 @@ async fn main() {
                                             app.ai_buffer.scroll_to_bottom();
                                             let _ = app.serial.write_str(&app.ai_buffer.render());
 
-                                            // Prepare AI response line - show "thinking" while waiting for first token
-                                            let ai_prefix =
-                                                format!("[{}] ", Local::now().format("%I:%M%p"));
-
-                                            // Show thinking indicator initially
-                                            let mut got_first_token = false;
-                                            app.ai_buffer
-                                                .push(format!("{}<Thinking...>", ai_prefix));
-                                            let _ = app.serial.write_str(&app.ai_buffer.render());
-
-                                            // Collect the full response for logging
-                                            let mut full_response = String::new();
-
-                                            // Stream the response - show characters as they arrive
-                                            app.ai_processing = true;
-                                            let result = gemini
-                                                .send_message_streaming(&text, |chunk| {
-                                                    full_response.push_str(chunk);
-                                                    for ch in chunk.chars() {
-                                                        // On first real character, replace thinking with actual content
-                                                        if !got_first_token {
-                                                            got_first_token = true;
-                                                            // Reset the line to just the prefix (removing <Thinking...>)
-                                                            app.ai_buffer
-                                                                .update_last_line(&ai_prefix);
-                                                        }
-
-                                                        if ch == '\n' {
-                                                            // Handle newline by starting a new indented line
-                                                            app.ai_buffer.push("  ".to_string());
-                                                            if app.ai_buffer.is_full() {
-                                                                let _ = app.serial.write_str(
-                                                                    &app.ai_buffer.render(),
-                                                                );
-                                                            } else {
-                                                                let _ = app.serial.write_str(
-                                                                    &app.ai_buffer
-                                                                        .render_bottom_lines(2),
-                                                                );
-                                                            }
-                                                        } else if !ch.is_control() {
-                                                            let wrapped =
-                                                                app.ai_buffer.type_char(ch, "  ");
-
-                                                            if wrapped {
-                                                                // If we wrapped, we might have modified the previous line (word wrap)
-                                                                // If the buffer is full, we need to redraw everything to show the scroll
-                                                                if app.ai_buffer.is_full() {
-                                                                    let _ = app.serial.write_str(
-                                                                        &app.ai_buffer.render(),
-                                                                    );
-                                                                } else {
-                                                                    // Otherwise just render the last 2 lines
-                                                                    let _ = app.serial.write_str(
-                                                                        &app.ai_buffer
-                                                                            .render_bottom_lines(2),
-                                                                    );
-                                                                }
-                                                            } else {
-                                                                // Otherwise just render the current line
-                                                                let _ = app.serial.write_str(
-                                                                    &app.ai_buffer
-                                                                        .render_last_line(),
-                                                                );
-                                                            }
-
-                                                            // Add a small delay for typing effect
-                                                            std::thread::sleep(
-                                                                Duration::from_millis(10),
-                                                            );
-                                                        }
-                                                    }
-                                                })
-                                                .await;
-                                            app.ai_processing = false;
-                                            let _ = app.serial.clear_input();
-
-                                            // Log the complete AI response
-                                            if let Some(ref mut logger) = app.logger {
-                                                logger.log_ai(&format!(
-                                                    "{}{}",
-                                                    ai_prefix,
-                                                    full_response.replace('\n', " ")
-                                                ));
-                                            }
-
-                                            match result {
-                                                Ok(_) => {
-                                                    // Response is already fully rendered and wrapped by type_char
-                                                }
-                                                Err(e) => {
-                                                    let timestamp = Local::now().format("%I:%M%p");
-                                                    app.push_ai(format!(
-                                                        "[{}] *** Error: {} ***",
-                                                        timestamp, e
-                                                    ));
-                                                    app.ai_buffer.scroll_to_bottom();
-                                                    let _ = app
-                                                        .serial
-                                                        .write_str(&app.ai_buffer.render());
-                                                }
-                                            }
+                                            stream_ai_reply(&mut app, &text, None).await;
                                         }
                                     }
-                                    // Call and Tunes are handled before the line buffer check
-                                    Tab::Call | Tab::Tunes => unreachable!(),
+                                    // Tabs without input are handled before the line buffer check
+                                    Tab::Call | Tab::Tunes | Tab::Stats | Tab::Todo => {
+                                        unreachable!()
+                                    }
                                 }
                             }
                         }
@@ -1630,8 +1789,7 @@ async fn main() {
                             if app.ai_processing {
                                 continue;
                             }
-                            if app.active_tab != Tab::Call
-                                && app.active_tab != Tab::Tunes
+                            if app.active_tab.has_input()
                                 && !app.line_buffer.is_empty()
                                 && app.input_cursor > 0
                             {
@@ -1646,6 +1804,7 @@ async fn main() {
                                 app.input_cursor -= 1;
                                 // Redraw input line
                                 let _ = app.serial.write_str(&redraw_input(
+                                    app.theme,
                                     &app.config.network.name,
                                     &app.line_buffer,
                                     app.input_cursor,
@@ -1661,275 +1820,119 @@ async fn main() {
                                     let _ = app.serial.write_str(&app.chat_buffer.render());
                                 }
                                 Tab::Gemini => {
-                                    if let Some(ref mut gemini) = app.gemini_chat {
-                                        gemini.clear_history();
+                                    if let Some(ref mut ai) = app.ai_chat {
+                                        ai.clear_history();
                                     }
+                                    app.new_ai_conversation();
                                     app.ai_buffer.clear();
-                                    let timestamp = Local::now().format("%I:%M%p");
+                                    let timestamp = stamp::now();
                                     app.push_ai(format!(
-                                        "[{}] *** Conversation cleared ***",
+                                        "{}*** Conversation cleared ***",
                                         timestamp
                                     ));
                                     let _ = app.serial.write_str(&app.ai_buffer.render());
                                 }
-                                Tab::Call => {
-                                    // Do nothing for Call tab
+                                Tab::Call | Tab::Tunes => {
+                                    // Hanging up and stopping playback are bound in [keys]
                                 }
-                                Tab::Tunes => {
-                                    // Ctrl+C in Tunes - stop playback
-                                    if let Some(ref tunes) = app.tunes_state {
-                                        tunes.stop();
-                                        let _ = app.serial.write_str(&tunes.render());
-                                    }
+                                Tab::Todo => {
+                                    // Ctrl+C in Todo - remove the checked items
+                                    app.clear_done_todos().await;
+                                }
+                                Tab::Stats => {
+                                    // Ctrl+C in Stats - start the counters again
+                                    app.net_node.reset_stats();
+                                    let _ = app.serial.write_str(&render_stats(
+                                        &app.net_node.peer_stats(),
+                                        &app.peer_caps,
+                                        width,
+                                    ));
                                 }
                             }
                         }
-                        InputEvent::Tab => {
-                            // Tab key - switch tabs
-                            let prev_tab = app.active_tab;
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            app.active_tab = app.active_tab.next(
-                                gemini_available,
-                                app.active_call.is_some(),
-                                tunes_available,
-                            );
-
-                            // Reset video state when switching tabs
-                            app.last_rendered_frame = None;
-
-                            // Handle webcam state
-                            if let Some(cam) = &app.webcam {
-                                if app.active_tab == Tab::Call {
-                                    cam.start().await;
-                                } else if prev_tab == Tab::Call && app.active_call.is_none() {
-                                    cam.stop().await;
-                                }
+                        InputEvent::CtrlF => {
+                            // Ctrl+F - Start a search of the chat
+                            if app.active_tab == Tab::Chat
+                                && !app.ai_processing
+                                && app.line_buffer.is_empty()
+                            {
+                                app.line_buffer = "/search ".to_string();
+                                app.input_cursor = app.line_buffer.len();
+                                let _ = app.serial.write_str(&redraw_input(
+                                    app.theme,
+                                    &app.config.network.name,
+                                    &app.line_buffer,
+                                    app.input_cursor,
+                                    width,
+                                ));
                             }
-
-                            // Redraw tab bar and content
-                            let _ = app.serial.write_str(&redraw_tab_bar(
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                width,
-                            ));
-
-                            match app.active_tab {
-                                Tab::Chat => {
-                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                        &app.config.network.name,
-                                        app.active_tab,
-                                        gemini_available,
-                                        tunes_available,
-                                        app.active_call.as_deref(),
-                                        None,
-                                        width,
-                                    ));
-                                    let _ = app.serial.write_str(&app.chat_buffer.render());
-                                    let _ = app.serial.write_str(&redraw_input(
-                                        &app.config.network.name,
-                                        &app.line_buffer,
-                                        app.input_cursor,
-                                        width,
-                                    ));
+                        }
+                        InputEvent::Space => {
+                            if app.active_tab == Tab::Tunes {
+                                // Space in Tunes - toggle pause/resume, or play if stopped
+                                if let Some(ref mut tunes) = app.tunes_state {
+                                    if tunes.is_active() {
+                                        tunes.toggle_pause();
+                                    } else {
+                                        // Nothing playing - start playback
+                                        if let Err(e) = tunes.play_selected() {
+                                            eprintln!("Failed to play: {}", e);
+                                        }
+                                    }
+                                    let _ = app.serial.write_str(&tunes.render());
                                 }
-                                Tab::Gemini => {
-                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                        &app.config.network.name,
-                                        app.active_tab,
-                                        gemini_available,
-                                        tunes_available,
-                                        app.active_call.as_deref(),
-                                        None,
-                                        width,
-                                    ));
-                                    let _ = app.serial.write_str(&app.ai_buffer.render());
+                            } else if app.active_tab.has_input() {
+                                // Space is also a printable character in other tabs
+                                if !app.ai_processing && app.line_buffer.len() < max_input_len {
+                                    let byte_idx = app
+                                        .line_buffer
+                                        .chars()
+                                        .take(app.input_cursor)
+                                        .map(|c| c.len_utf8())
+                                        .sum();
+                                    app.line_buffer.insert(byte_idx, ' ');
+                                    app.input_cursor += 1;
                                     let _ = app.serial.write_str(&redraw_input(
+                                        app.theme,
                                         &app.config.network.name,
                                         &app.line_buffer,
                                         app.input_cursor,
                                         width,
                                     ));
                                 }
-                                Tab::Call => {
-                                    let status = app.active_call.as_ref().map(|peer_name| {
-                                        format!(
-                                            "Call session with {}. Press Space to hang up.",
-                                            peer_name
-                                        )
-                                    });
-                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                        &app.config.network.name,
-                                        app.active_tab,
-                                        gemini_available,
-                                        tunes_available,
-                                        app.active_call.as_deref(),
-                                        status.as_deref(),
-                                        width,
-                                    ));
-                                }
-                                Tab::Tunes => {
-                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                        &app.config.network.name,
-                                        app.active_tab,
-                                        gemini_available,
-                                        tunes_available,
-                                        app.active_call.as_deref(),
-                                        None,
-                                        width,
-                                    ));
-                                    if let Some(ref tunes) = app.tunes_state {
-                                        let _ = app.serial.write_str(&tunes.render());
-                                    }
-                                }
                             }
                         }
-                        InputEvent::CtrlR => {
-                            // Ctrl+R - Refresh screen (useful if terminal reconnects)
-                            let status = if app.active_tab == Tab::Call {
-                                app.active_call.as_ref().map(|peer_name| {
-                                    format!(
-                                        "Call session with {}. Press Space to hang up.",
-                                        peer_name
-                                    )
-                                })
-                            } else {
-                                None
-                            };
-                            let gemini_available = app.gemini_chat.is_some();
-                            let tunes_available = app.tunes_available();
-                            let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                &app.config.network.name,
-                                app.active_tab,
-                                gemini_available,
-                                tunes_available,
-                                app.active_call.as_deref(),
-                                status.as_deref(),
-                                width,
-                            ));
-                            match app.active_tab {
-                                Tab::Chat => {
-                                    let _ = app.serial.write_str(&app.chat_buffer.render());
-                                    let _ = app.serial.write_str(&redraw_input(
-                                        &app.config.network.name,
-                                        &app.line_buffer,
-                                        app.input_cursor,
-                                        width,
-                                    ));
-                                }
-                                Tab::Gemini => {
-                                    let _ = app.serial.write_str(&app.ai_buffer.render());
-                                    let _ = app.serial.write_str(&redraw_input(
-                                        &app.config.network.name,
-                                        &app.line_buffer,
-                                        app.input_cursor,
-                                        width,
-                                    ));
-                                }
-                                Tab::Call => {
-                                    // Nothing else to render for Call
-                                }
-                                Tab::Tunes => {
-                                    if let Some(ref tunes) = app.tunes_state {
-                                        let _ = app.serial.write_str(&tunes.render());
-                                    }
-                                }
+                        InputEvent::Char(c) => {
+                            // Y or N on an empty input line answers a ringing call
+                            if app.active_tab == Tab::Chat
+                                && app.incoming_call.is_some()
+                                && app.line_buffer.is_empty()
+                                && matches!(c, 'y' | 'Y' | 'n' | 'N')
+                            {
+                                answer_call(&mut app, c.eq_ignore_ascii_case(&'y'), width).await;
+                                continue;
                             }
-                        }
-                        InputEvent::Space => {
-                            if app.active_tab == Tab::Call {
-                                // Space bar in Call tab - Hang up
-                                if let Some(peer_name) = app.active_call.take() {
-                                    // Send hangup message
-                                    if peer_name != app.config.network.name
-                                        && let Some(peer) = app
-                                            .net_node
-                                            .peers()
-                                            .iter()
-                                            .find(|p| p.name == peer_name)
-                                    {
-                                        let msg = Message::CallHangup {
-                                            from: app.config.network.name.clone(),
-                                        };
-                                        if let Err(e) = futures::executor::block_on(
-                                            app.net_node.send_to(&msg, peer.addr),
-                                        ) {
-                                            eprintln!("Failed to send hangup: {}", e);
-                                        }
-                                    }
-
-                                    // Notify local user
-                                    let timestamp = Local::now().format("%I:%M%p");
-                                    app.push_chat(format!(
-                                        "[{}] *** Call with {} ended ***",
-                                        timestamp, peer_name
-                                    ));
-
-                                    app.last_rendered_frame = None;
-                                    app.call_last_packet = None;
-                                    app.call_connected = false;
-                                    // Stop webcam
-                                    if let Some(cam) = &app.webcam {
-                                        cam.stop().await;
-                                    }
-                                    // Switch back to Chat
-                                    app.active_tab = Tab::Chat;
-                                    let gemini_available = app.gemini_chat.is_some();
-                                    let tunes_available = app.tunes_available();
-                                    let _ = app.serial.write_str(&init_split_screen_with_tabs(
-                                        &app.config.network.name,
-                                        app.active_tab,
-                                        gemini_available,
-                                        tunes_available,
-                                        app.active_call.as_deref(),
-                                        None,
-                                        width,
-                                    ));
-                                    let _ = app.serial.write_str(&app.chat_buffer.render());
-                                    let _ = app.serial.write_str(&redraw_input(
-                                        &app.config.network.name,
-                                        &app.line_buffer,
-                                        app.input_cursor,
-                                        width,
-                                    ));
-                                }
-                            } else if app.active_tab == Tab::Tunes {
-                                // Space in Tunes - toggle pause/resume, or play if stopped
-                                if let Some(ref mut tunes) = app.tunes_state {
-                                    if tunes.is_active() {
-                                        tunes.toggle_pause();
-                                    } else {
-                                        // Nothing playing - start playback
-                                        if let Err(e) = tunes.play_selected() {
-                                            eprintln!("Failed to play: {}", e);
-                                        }
-                                    }
-                                    let _ = app.serial.write_str(&tunes.render());
-                                }
-                            } else if app.active_tab != Tab::Call {
-                                // Space is also a printable character in other tabs
-                                if !app.ai_processing && app.line_buffer.len() < max_input_len {
-                                    let byte_idx = app
-                                        .line_buffer
-                                        .chars()
-                                        .take(app.input_cursor)
-                                        .map(|c| c.len_utf8())
-                                        .sum();
-                                    app.line_buffer.insert(byte_idx, ' ');
-                                    app.input_cursor += 1;
-                                    let _ = app.serial.write_str(&redraw_input(
-                                        &app.config.network.name,
-                                        &app.line_buffer,
-                                        app.input_cursor,
-                                        width,
-                                    ));
+                            // Y or N on an empty input line answers a paste being held
+                            if app.active_tab == Tab::Chat
+                                && app.pending_paste.is_some()
+                                && app.line_buffer.is_empty()
+                                && matches!(c, 'y' | 'Y' | 'n' | 'N')
+                            {
+                                app.answer_paste(c.eq_ignore_ascii_case(&'y')).await;
+                                continue;
+                            }
+                            // While searching, n and p on an empty input line step
+                            // through the matches; typing anything else stops
+                            if app.active_tab == Tab::Chat
+                                && app.chat_buffer.search_query().is_some()
+                            {
+                                if app.line_buffer.is_empty() && matches!(c, 'n' | 'p') {
+                                    app.step_search(c == 'n', width);
+                                    continue;
                                 }
+                                app.end_search(width);
                             }
-                        }
-                        InputEvent::Char(c) => {
-                            if app.active_tab != Tab::Call && app.active_tab != Tab::Tunes {
+                            if app.active_tab.has_input() {
                                 if app.ai_processing {
                                     continue;
                                 }
@@ -1945,6 +1948,7 @@ async fn main() {
                                     app.input_cursor += 1;
                                     // Redraw input area to handle wrapping
                                     let _ = app.serial.write_str(&redraw_input(
+                                        app.theme,
                                         &app.config.network.name,
                                         &app.line_buffer,
                                         app.input_cursor,
@@ -1954,7 +1958,10 @@ async fn main() {
                                 // Silently ignore input when buffer is full
                             }
                         }
-                        InputEvent::Escape(_) | InputEvent::Ignore => {
+                        InputEvent::Tab
+                        | InputEvent::CtrlR
+                        | InputEvent::Escape(_)
+                        | InputEvent::Ignore => {
                             // Handled above or ignored
                         }
                     }
@@ -1996,3 +2003,2038 @@ async fn main() {
     // Clean up
     app.net_recv_task.abort();
 }
+
+/// Handle the /download command: send a file to the terminal's host computer
+/// over the serial link. The user must start a receive in their terminal program.
+/// `wormhole export`: the chat logs named, or all of them in the configured
+/// log directory, as mbox or HTML
+fn run_export(
+    config: &Path,
+    format: Format,
+    output: Option<PathBuf>,
+    logs: Vec<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let logs = if logs.is_empty() {
+        let config = Config::load(config)?;
+        let dir = config
+            .logging
+            .directory
+            .ok_or("no log directory is set under [logging] (or name the chat logs to export)")?;
+        export::chat_logs(Path::new(&dir))?
+    } else {
+        logs
+    };
+    let exported = export::export(format, &logs)?;
+    match output {
+        Some(path) => {
+            std::fs::write(&path, exported)?;
+            eprintln!("Exported {} chat log(s) to {}", logs.len(), path.display());
+        }
+        None => print!("{}", exported),
+    }
+    Ok(())
+}
+
+async fn handle_download(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    let (protocol, file) = transfer::parse_download_args(args);
+
+    if file.is_empty() {
+        app.push_chat(format!(
+            "{}*** Usage: /download [xmodem|kermit] <file> ***",
+            timestamp
+        ));
+        app.chat_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+        return;
+    }
+
+    let path = std::path::Path::new(file);
+    let size = match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        _ => {
+            app.push_chat(format!("{}*** File '{}' not found ***", timestamp, file));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            return;
+        }
+    };
+
+    app.push_chat(format!(
+        "{}*** Sending {} ({} bytes) via {} - start your receive now ***",
+        timestamp,
+        file,
+        size,
+        protocol.name()
+    ));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+
+    // The transfer owns the serial link until it finishes; drop any stray keystrokes first
+    let _ = app.serial.clear_input();
+    let Some(mut port) = app.serial.lend() else {
+        return;
+    };
+    let owned = path.to_path_buf();
+    let task = tokio::task::spawn_blocking(move || {
+        let result = transfer::send_file(&mut port, protocol, &owned);
+        (port, result)
+    });
+
+    // Keep answering peers meanwhile, or they would think we'd gone
+    while !task.is_finished() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        while let Ok(msg) = app.net_rx.try_recv() {
+            app.handle_message(msg, width).await;
+        }
+        while let Ok(event) = app.peer_event_rx.try_recv() {
+            app.handle_peer_event(event).await;
+        }
+    }
+    let result = match task.await {
+        Ok((port, result)) => {
+            app.serial.give_back(port);
+            let _ = app.serial.clear_input();
+            result
+        }
+        // The port went down with the task; the main loop reopens it
+        Err(e) => Err(std::io::Error::other(e).into()),
+    };
+    app.clock.reset();
+
+    let timestamp = stamp::now();
+    match result {
+        Ok(bytes) => app.push_chat(format!(
+            "{}*** Sent {} ({} bytes) via {} ***",
+            timestamp,
+            file,
+            bytes,
+            protocol.name()
+        )),
+        Err(e) => app.push_chat(format!(
+            "{}*** Download of {} failed: {} ***",
+            timestamp, file, e
+        )),
+    }
+    app.chat_buffer.scroll_to_bottom();
+
+    // The terminal program may have drawn its own transfer screen
+    app.redraw_screen(width);
+}
+
+/// Handle the /print command: send the whole chat buffer, or the last AI
+/// reply, to the terminal's printer as one Media Copy job.
+fn handle_print(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let lines: Vec<String> = match args {
+        "" => app
+            .chat_buffer
+            .recent_lines(usize::MAX)
+            .iter()
+            .map(|l| l.to_string())
+            .collect(),
+        "ai" => match app.ai_chat.as_ref().and_then(|g| g.last_response()) {
+            Some(reply) => reply.lines().map(str::to_string).collect(),
+            None => {
+                app.push_chat(format!("{}*** No AI reply to print ***", timestamp));
+                app.chat_buffer.scroll_to_bottom();
+                let _ = app.serial.write_str(&app.chat_buffer.render());
+                return;
+            }
+        },
+        _ => {
+            app.push_chat(format!("{}*** Usage: /print [ai] ***", timestamp));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            return;
+        }
+    };
+
+    let _ = app.serial.write_str(&transparent_print(&lines));
+    app.push_chat(format!(
+        "{}*** Sent {} line(s) to the printer ***",
+        timestamp,
+        lines.len()
+    ));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle the /capture command: copy session text to the terminal's printer port
+/// using DEC transparent print.
+fn handle_capture(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let mut parts = args.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some("start"), None) => {
+            app.capture_active = true;
+            app.push_chat(format!(
+                "{}*** Capture started - output is copied to the printer port ***",
+                timestamp
+            ));
+        }
+        (Some("stop"), None) => {
+            app.push_chat(format!("{}*** Capture stopped ***", timestamp));
+            app.capture_active = false;
+        }
+        (Some("screen"), None) => {
+            let lines: Vec<String> = app
+                .chat_buffer
+                .visible_lines()
+                .iter()
+                .map(|l| l.to_string())
+                .collect();
+            let _ = app.serial.write_str(&transparent_print(&lines));
+            app.push_chat(format!(
+                "{}*** Printed {} line(s) ***",
+                timestamp,
+                lines.len()
+            ));
+        }
+        (Some("last"), Some(n)) if n.parse::<usize>().is_ok() => {
+            let count = n.parse::<usize>().unwrap_or(0);
+            let lines: Vec<String> = app
+                .chat_buffer
+                .recent_lines(count)
+                .iter()
+                .map(|l| l.to_string())
+                .collect();
+            let _ = app.serial.write_str(&transparent_print(&lines));
+            app.push_chat(format!(
+                "{}*** Printed {} line(s) ***",
+                timestamp,
+                lines.len()
+            ));
+        }
+        _ => {
+            let state = if app.capture_active { "on" } else { "off" };
+            app.push_chat(format!(
+                "{}*** Usage: /capture start|stop|screen|last <n> (capture is {}) ***",
+                timestamp, state
+            ));
+        }
+    }
+
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Longest name `/nick` accepts
+const MAX_NICK_LEN: usize = 24;
+
+/// How long an incoming call rings (as long as the caller waits for an answer)
+const CALL_RING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Show or change our name (`/nick`)
+fn handle_nick(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    let error = if args.is_empty() {
+        Some(format!("You are {}", app.config.network.name))
+    } else if args.chars().count() > MAX_NICK_LEN
+        || args.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        Some(format!(
+            "Names are up to {} characters with no spaces",
+            MAX_NICK_LEN
+        ))
+    } else if args == app.config.network.name {
+        Some(format!("You are already {}", args))
+    } else if app.net_node.name_taken(args, None) {
+        Some(format!("{} is already in use", args))
+    } else {
+        None
+    };
+    match error {
+        Some(msg) => {
+            app.push_chat(format!("{}*** {} ***", timestamp, msg));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+        }
+        None => futures::executor::block_on(app.change_nick(args.to_string(), width)),
+    }
+}
+
+/// Handle /edit: correct our last message for everyone, delete it (`-`), or
+/// with nothing after it, bring it back to the input line to be corrected
+fn handle_edit(app: &mut App, args: &str, max_input_len: usize, width: usize) {
+    let timestamp = stamp::now();
+    let Some((id, last)) = app.last_editable() else {
+        app.push_chat(format!("{}*** You have no message to edit ***", timestamp));
+        app.chat_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+        return;
+    };
+    if args.is_empty() {
+        app.line_buffer = format!("/edit {}", last)
+            .chars()
+            .take(max_input_len)
+            .collect();
+        app.input_cursor = app.line_buffer.len();
+        let _ = app.serial.write_str(&redraw_input(
+            app.theme,
+            &app.config.network.name,
+            &app.line_buffer,
+            app.input_cursor,
+            width,
+        ));
+        return;
+    }
+    let text = if args == "-" { "" } else { args };
+    if text == last {
+        return;
+    }
+    let name = app.config.network.name.clone();
+    app.apply_edit(&name, id, text);
+    if let Err(e) = futures::executor::block_on(app.net_node.send_chat_edit(id, text)) {
+        eprintln!("Failed to send edit: {}", e);
+    }
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /tunes sync <peer>: bring our Tunes directory and a peer's into line
+fn handle_tunes(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let msg = match args.split_once(' ') {
+        Some(("sync", peer)) if !peer.trim().is_empty() => {
+            futures::executor::block_on(app.start_tunes_sync(peer.trim()))
+        }
+        _ => "Usage: /tunes sync <peer>".to_string(),
+    };
+    app.push_chat(format!("{}*** {} ***", timestamp, msg));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /layout [name]: show the layouts, or switch to another
+fn handle_layout(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    if args.is_empty() {
+        let mut names: Vec<&str> = app.config.layouts.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        for (name, _) in layout::BUILTIN_LAYOUTS {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+        app.push_chat(format!(
+            "{}*** Layout: {} ({} rows). Available: {} ***",
+            timestamp,
+            app.layout_name,
+            layout::screen_rows(),
+            names.join(", ")
+        ));
+    } else if app.set_layout(args, width) {
+        app.push_chat(format!(
+            "{}*** Layout is now {} ({} rows) ***",
+            timestamp,
+            args,
+            layout::screen_rows()
+        ));
+    } else {
+        app.push_chat(format!("{}*** Unknown layout '{}' ***", timestamp, args));
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /window [n|name|new <peer|#channel>|close]: list the chat windows,
+/// switch to one, open one, or close the one shown
+fn handle_window(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    let (command, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let notice = match command {
+        "" => format!("Windows: {}", app.windows.list_line()),
+        "new" if rest.is_empty() || rest.contains(char::is_whitespace) => {
+            "Usage: /window new <peer|#channel>".to_string()
+        }
+        "new" => match app.windows.open(rest, width, app.theme) {
+            Some(index) => {
+                app.switch_window(index, width);
+                let what = if windows::is_channel(rest) {
+                    "Channel"
+                } else {
+                    "Direct messages with"
+                };
+                format!("Window {}: {} {}", index + 1, what, rest)
+            }
+            None => format!("All {} windows are open", windows::MAX_WINDOWS),
+        },
+        "close" => match app.windows.close(&mut app.chat_buffer) {
+            Some(name) => {
+                app.tick_status(width);
+                format!("Closed the window for {}", name)
+            }
+            None => "The mesh window stays open".to_string(),
+        },
+        _ => {
+            let index = match args.parse::<usize>() {
+                Ok(n) => n.checked_sub(1),
+                Err(_) => app.windows.find(args),
+            };
+            match index {
+                Some(index) if app.switch_window(index, width) => return,
+                _ => format!("No window '{}' (/window lists them)", args),
+            }
+        }
+    };
+    app.push_chat(format!("{}*** {} ***", timestamp, notice));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /enable and /disable: start or stop a subsystem for the session
+fn handle_enable(app: &mut App, args: &str, on: bool, width: usize) {
+    let timestamp = stamp::now();
+    let msg = match Subsystem::from_name(args) {
+        Some(subsystem) => futures::executor::block_on(app.set_enabled(subsystem, on, width)),
+        None => {
+            let state = |s: Subsystem| {
+                format!(
+                    "{} {}",
+                    s.name(),
+                    if app.is_enabled(s) { "on" } else { "off" }
+                )
+            };
+            let states: Vec<String> = Subsystem::ALL.into_iter().map(state).collect();
+            format!(
+                "Usage: /{} webcam|ai|tunes|discovery ({})",
+                if on { "enable" } else { "disable" },
+                states.join(", ")
+            )
+        }
+    };
+    app.push_chat(format!("{}*** {} ***", timestamp, msg));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Ask a peer for its profile card (our own is shown directly)
+fn handle_whois(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    if args.is_empty() {
+        app.push_chat(format!("{}*** Usage: /whois <peer> ***", timestamp));
+    } else if args == app.config.network.name {
+        let info = app.whois_info();
+        app.show_whois(&info);
+    } else {
+        match app.net_node.peers().iter().find(|p| p.name == args) {
+            Some(peer) => {
+                let addr = peer.addr;
+                if let Err(e) = futures::executor::block_on(app.request_whois(args, addr)) {
+                    app.push_chat(format!("{}*** Whois failed: {} ***", timestamp, e));
+                }
+            }
+            None => app.push_chat(format!("{}*** Peer '{}' not found ***", timestamp, args)),
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// List the cameras, or switch to one
+async fn handle_camera(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    match args.split_once(' ').unwrap_or((args, "")) {
+        ("list", "") => match webcam::list_cameras() {
+            Ok(cameras) if cameras.is_empty() => {
+                app.push_chat(format!("{}*** No cameras found ***", timestamp))
+            }
+            Ok(cameras) => {
+                let current = app.config.webcam.device.as_deref().unwrap_or("none");
+                app.push_chat(format!("{}*** Cameras (using {}): ***", timestamp, current));
+                for camera in cameras {
+                    app.push_chat(format!("  {}", camera));
+                }
+            }
+            Err(e) => app.push_chat(format!("{}*** Can't list cameras: {} ***", timestamp, e)),
+        },
+        ("use", device) if !device.trim().is_empty() => {
+            let msg = app.use_camera(device.trim()).await;
+            app.push_chat(format!("{}*** {} ***", timestamp, msg));
+        }
+        _ => app.push_chat(format!(
+            "{}*** Usage: /camera list | use <index> ***",
+            timestamp
+        )),
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Share a picture from disk: peers get the pixels and draw it their own way
+async fn handle_imagefile(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    if args.is_empty() {
+        app.push_chat(format!("{}*** Usage: /imagefile <path> ***", timestamp));
+    } else {
+        match image_file_to_raw_frame(Path::new(args), width) {
+            Ok(frame) => {
+                let lamport = app.net_node.tick_lamport();
+                let lines = shared_image_to_output(
+                    &frame,
+                    app.renderer.as_ref(),
+                    app.config.dither(),
+                    width,
+                );
+                let name = app.config.network.name.clone();
+                app.in_mesh(|app| {
+                    app.push_share(
+                        &name,
+                        format!("{}{} shared an image:", timestamp, name),
+                        &lines.join("\n"),
+                        lamport,
+                    )
+                });
+                if let Err(e) = app
+                    .net_node
+                    .send_image(frame.width, frame.height, &frame.pixels, lamport)
+                    .await
+                {
+                    eprintln!("Failed to send image: {}", e);
+                }
+            }
+            Err(e) => app.push_chat(format!("{}*** Can't share {}: {} ***", timestamp, args, e)),
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Say `text` in big letters: the text itself, then its banner underneath
+async fn handle_figlet(app: &mut App, text: &str, width: usize) {
+    let timestamp = stamp::now();
+    if text.is_empty() {
+        app.push_chat(format!("{}*** Usage: /figlet <text> ***", timestamp));
+    } else {
+        let lamport = app.net_node.tick_lamport();
+        let lines = graphics::banner(text, width.saturating_sub(2));
+        let name = app.config.network.name.clone();
+        app.in_mesh(|app| {
+            app.push_share(
+                &name,
+                format!("{}{}: {}", timestamp, name, text),
+                &lines.join("\n"),
+                lamport,
+            )
+        });
+        let message = format!("{}\n{}", text, lines.join("\n"));
+        app.chat_history.record(&name, &message);
+        if let Err(e) = app.net_node.send_chat(&message, lamport).await {
+            eprintln!("Failed to send banner: {}", e);
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// What `/look` asks about the picture when given nothing else
+const LOOK_PROMPT: &str = "This is a picture from my webcam. Describe what you see.";
+
+/// `/look` on the AI tab: show the AI a webcam picture and stream its answer
+async fn handle_look(app: &mut App, question: &str) {
+    if app.ai_chat.is_none() {
+        return;
+    }
+    let image = match &app.webcam {
+        Some(webcam) => {
+            app.ai_buffer
+                .push(format!("{}*** Taking a picture... ***", stamp::now()));
+            app.ai_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.ai_buffer.render());
+            webcam
+                .take_still()
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|image| Image::encode(&image).map_err(|e| e.to_string()))
+        }
+        None => Err("No webcam is set up".to_string()),
+    };
+    let image = match image {
+        Ok(image) => image,
+        Err(e) => {
+            app.push_ai(format!("{}*** {} ***", stamp::now(), e));
+            app.ai_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.ai_buffer.render());
+            return;
+        }
+    };
+
+    let question = if question.is_empty() {
+        LOOK_PROMPT
+    } else {
+        question
+    };
+    let user_msg = format!(
+        "{}{}: [picture] {}",
+        stamp::now(),
+        app.config.network.name,
+        question
+    );
+    if let Some(ref mut logger) = app.logger {
+        logger.log_ai(&user_msg);
+    }
+    app.ai_buffer.push(user_msg);
+    app.ai_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.ai_buffer.render());
+    stream_ai_reply(app, question, Some(image)).await;
+}
+
+/// Stream the AI's reply to `message` onto the AI tab, logging it and saving the
+/// conversation
+async fn stream_ai_reply(app: &mut App, message: &str, image: Option<Image>) {
+    let Some(ai) = app.ai_chat.as_mut() else {
+        return;
+    };
+
+    // Prepare AI response line - show "thinking" while waiting for first token
+    let ai_prefix = stamp::now();
+
+    // Show thinking indicator initially
+    let mut got_first_token = false;
+    app.ai_buffer.push(format!("{}<Thinking...>", ai_prefix));
+    let _ = app.serial.write_str(&app.ai_buffer.render());
+
+    // Collect the full response for logging
+    let mut full_response = String::new();
+    let mut markdown = (app.config.ai.markdown && !ai.verbatim()).then(Markdown::new);
+
+    // Stream the response - show characters as they arrive
+    app.ai_processing = true;
+    let result = ai
+        .send_streaming(message, image, |chunk| {
+            full_response.push_str(chunk);
+            // On first real character, replace thinking with actual content
+            if !got_first_token && !chunk.is_empty() {
+                got_first_token = true;
+                // Reset the line to just the prefix (removing <Thinking...>)
+                app.ai_buffer.update_last_line(&ai_prefix);
+            }
+            match markdown.as_mut() {
+                Some(markdown) => {
+                    type_reply(&mut app.ai_buffer, &mut app.serial, &markdown.feed(chunk))
+                }
+                None => type_reply(&mut app.ai_buffer, &mut app.serial, chunk),
+            }
+        })
+        .await;
+    if let Some(mut markdown) = markdown {
+        type_reply(&mut app.ai_buffer, &mut app.serial, &markdown.finish());
+    }
+    app.ai_processing = false;
+    app.clock.reset();
+    let _ = app.serial.clear_input();
+
+    // Log the complete AI response
+    if let Some(ref mut logger) = app.logger {
+        logger.log_ai(&format!(
+            "{}{}",
+            ai_prefix,
+            full_response.replace('\n', " ")
+        ));
+    }
+
+    // Copy the complete response to the printer if capturing
+    let captured = format!("{}{}", ai_prefix, full_response);
+    for line in captured.lines() {
+        app.capture(line);
+    }
+
+    match result {
+        Ok(reply) => {
+            // Response is already fully rendered and wrapped by type_char
+            app.save_ai_conversation();
+            app.record_ai_usage(reply.usage);
+        }
+        Err(e) => {
+            let timestamp = stamp::now();
+            app.push_ai(format!("{}*** Error: {} ***", timestamp, e));
+            app.ai_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.ai_buffer.render());
+        }
+    }
+}
+
+/// Type a piece of the AI's reply into `buffer` as it streams in
+fn type_reply(buffer: &mut ChatBuffer, serial: &mut Serial, chunk: &str) {
+    let mut chars = chunk.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' && chars.peek() == Some(&'[') {
+            // An attribute from the markdown, shown with the next character
+            chars.next();
+            let mut sequence = String::from("\x1b[");
+            for p in chars.by_ref() {
+                sequence.push(p);
+                if ('\x40'..='\x7e').contains(&p) {
+                    break;
+                }
+            }
+            buffer.type_attrs(&sequence);
+        } else if ch == '\n' {
+            // Handle newline by starting a new indented line
+            buffer.push("  ".to_string());
+            if buffer.is_full() {
+                let _ = serial.write_str(&buffer.render());
+            } else {
+                let _ = serial.write_str(&buffer.render_bottom_lines(2));
+            }
+        } else if !ch.is_control() {
+            let wrapped = buffer.type_char(ch, "  ");
+
+            if wrapped {
+                // If we wrapped, we might have modified the previous line (word wrap)
+                // If the buffer is full, we need to redraw everything to show the scroll
+                if buffer.is_full() {
+                    let _ = serial.write_str(&buffer.render());
+                } else {
+                    // Otherwise just render the last 2 lines
+                    let _ = serial.write_str(&buffer.render_bottom_lines(2));
+                }
+            } else {
+                // Otherwise just render the current line
+                let _ = serial.write_str(&buffer.render_last_line());
+            }
+
+            // Add a small delay for typing effect
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// `@ai <question>` in Chat: ask the AI in front of everyone. The question
+/// goes out as chat; the answer streams in here and is then sent to the
+/// mesh for peers to show as the AI's.
+async fn handle_summon_ai(app: &mut App, text: &str, question: &str) {
+    if app.ai_chat.is_none() {
+        app.push_chat(format!("{}*** The AI isn't set up here ***", stamp::now()));
+        app.chat_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+        return;
+    }
+    app.send_mesh_chat(text).await;
+
+    let ai_prefix = format!("{}{}: ", stamp::now(), AI_NAME);
+    let mut got_first_token = false;
+    app.chat_buffer.push(format!("{}<Thinking...>", ai_prefix));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+
+    app.ai_processing = true;
+    let Some(ai) = app.ai_chat.as_ref() else {
+        return;
+    };
+    let result = ai
+        .ask_streaming(question, |chunk| {
+            if !got_first_token && !chunk.is_empty() {
+                got_first_token = true;
+                app.chat_buffer.update_last_line(&ai_prefix);
+            }
+            type_reply(&mut app.chat_buffer, &mut app.serial, chunk);
+        })
+        .await;
+    app.ai_processing = false;
+    app.clock.reset();
+    let _ = app.serial.clear_input();
+
+    match result {
+        Ok(reply) => {
+            app.record_ai_usage(reply.usage);
+            let answer = reply.text;
+            if let Some(ref mut logger) = app.logger {
+                logger.log_chat(&format!("{}{}", ai_prefix, answer.replace('\n', " ")));
+            }
+            for line in format!("{}{}", ai_prefix, answer).lines() {
+                app.capture(line);
+            }
+            let ai_msg = format!("\x01AI {}", answer);
+            let name = app.config.network.name.clone();
+            app.chat_history.record(&name, &ai_msg);
+            let lamport = app.net_node.tick_lamport();
+            if let Err(e) = app.net_node.send_chat(&ai_msg, lamport).await {
+                eprintln!("Failed to send AI answer: {}", e);
+            }
+        }
+        Err(e) => {
+            app.chat_buffer
+                .update_last_line(&format!("{}<No answer>", ai_prefix));
+            app.push_chat(format!("{}*** AI error: {} ***", stamp::now(), e));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+        }
+    }
+}
+
+/// `/retry` on the AI tab: send the last message again, for a fresh answer
+/// or after an error
+async fn handle_ai_retry(app: &mut App) {
+    let timestamp = stamp::now();
+    let Some(prompt) = app.ai_chat.as_mut().and_then(|ai| ai.retry()) else {
+        app.push_ai(format!("{}*** Nothing to retry ***", timestamp));
+        app.ai_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.ai_buffer.render());
+        return;
+    };
+    app.push_ai(format!("{}*** Asking again ***", timestamp));
+    app.ai_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.ai_buffer.render());
+    stream_ai_reply(app, &prompt.content, prompt.image).await;
+}
+
+/// `/set` on the AI tab: show the generation settings, or change one
+fn handle_ai_set(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let Some(ref mut ai) = app.ai_chat else {
+        app.push_ai(format!("{}*** The AI isn't running ***", timestamp));
+        return;
+    };
+    let mut words = args.split_whitespace();
+    let message = match (words.next(), words.next(), words.next()) {
+        (None, ..) => PARAM_NAMES
+            .iter()
+            .map(|name| format!("{} {}", name, ai.params().get(name)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        (Some(name), Some(value), None) => match ai.params_mut().set(name, value) {
+            Ok(()) => format!("{} is now {}", name, ai.params().get(name)),
+            Err(e) => e,
+        },
+        _ => format!(
+            "Usage: /set <setting> <value|default>, settings: {}",
+            PARAM_NAMES.join(", ")
+        ),
+    };
+    app.push_ai(format!("{}*** {} ***", timestamp, message));
+}
+
+/// `/chat` on the AI tab: list the named conversations, start one or switch
+fn handle_ai_chat(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    let previous = app.ai_session.clone();
+    let mut words = args.split_whitespace();
+    let result = match (words.next(), words.next(), words.next()) {
+        (None | Some("list"), None, _) => {
+            let mut names: Vec<String> = app.ai_sessions.keys().cloned().collect();
+            names.push(format!("{} (this one)", app.ai_session));
+            names.sort();
+            app.push_ai(format!(
+                "{}*** Conversations: {} ***",
+                timestamp,
+                names.join(", ")
+            ));
+            return;
+        }
+        (Some("new"), Some(name), None) => app.new_ai_session(name, width).map(|_| {
+            format!(
+                "New conversation {} (/chat switch {} to go back)",
+                name, previous
+            )
+        }),
+        (Some("switch"), Some(name), None) => app
+            .switch_ai_session(name)
+            .map(|_| format!("Back to conversation {}", name)),
+        _ => Err("Usage: /chat [list], /chat new <name> or /chat switch <name>".to_string()),
+    };
+    match result {
+        Ok(message) | Err(message) => app.push_ai(format!("{}*** {} ***", timestamp, message)),
+    }
+}
+
+/// `/history` on the AI tab: list the saved conversations, or go back to one
+fn handle_ai_history(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let mut words = args.split_whitespace();
+    match (words.next(), words.next()) {
+        (None | Some("list"), None) => {
+            let Some(conversations) = &app.ai_conversations else {
+                app.push_ai(format!(
+                    "{}*** Conversations are only saved with a [logging] directory ***",
+                    timestamp
+                ));
+                return;
+            };
+            let list = conversations.list();
+            if list.is_empty() {
+                app.push_ai(format!("{}*** No saved conversations yet ***", timestamp));
+                return;
+            }
+            let lines: Vec<String> = list
+                .iter()
+                .enumerate()
+                .map(|(i, summary)| {
+                    let mark = if conversations.is_current(&summary.id) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    format!(
+                        "{}{:>2}. {} {:>3} msgs  {}",
+                        mark,
+                        i + 1,
+                        summary.started,
+                        summary.messages,
+                        summary.opening
+                    )
+                })
+                .collect();
+            app.push_ai(format!(
+                "{}*** Saved conversations, newest first ***",
+                timestamp
+            ));
+            for line in lines {
+                app.push_ai(line);
+            }
+            app.push_ai(format!(
+                "{}*** /history load <n> to carry on with one ***",
+                timestamp
+            ));
+        }
+        (Some("load"), Some(n)) if words.next().is_none() => {
+            let result = match n.parse() {
+                Ok(n) => app.load_ai_conversation(n),
+                Err(_) => Err(format!("'{}' isn't a conversation number", n)),
+            };
+            match result {
+                Ok(started) => app.push_ai(format!(
+                    "{}*** Carrying on with the conversation from {} ***",
+                    timestamp, started
+                )),
+                Err(e) => app.push_ai(format!("{}*** {} ***", timestamp, e)),
+            }
+        }
+        _ => app.push_ai(format!(
+            "{}*** Usage: /history [list] or /history load <n> ***",
+            timestamp
+        )),
+    }
+}
+
+/// Share `text` as a QR code: peers get the text and draw the code their own way
+async fn handle_qr(app: &mut App, text: &str) {
+    let timestamp = stamp::now();
+    if text.is_empty() {
+        app.push_chat(format!("{}*** Usage: /qr <text> ***", timestamp));
+    } else if let Err(e) = QrCode::encode(text) {
+        app.push_chat(format!("{}*** Can't make a QR code: {} ***", timestamp, e));
+    } else {
+        let lamport = app.net_node.tick_lamport();
+        let lines = app.qr_lines(text);
+        let name = app.config.network.name.clone();
+        app.in_mesh(|app| {
+            app.push_share(
+                &name,
+                format!("{}{} shared a QR code for {}:", timestamp, name, text),
+                &lines.join("\n"),
+                lamport,
+            )
+        });
+        let qr_msg = format!("\x01QR {}", text);
+        app.chat_history.record(&name, &qr_msg);
+        if let Err(e) = app.net_node.send_chat(&qr_msg, lamport).await {
+            eprintln!("Failed to send QR code: {}", e);
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Note movement seen by the idle webcam in chat, with a snapshot
+fn motion_alert(app: &mut App, frame: &RawFrame, width: usize) {
+    let timestamp = stamp::now();
+    let time = Local::now().format("%-I:%M%p");
+    app.push_chat(format!("{}*** Motion detected at {} ***", timestamp, time));
+    // Drawn as a shared picture is, two thirds the size of the call's
+    let lines = shared_image_to_output(
+        &scale_raw_frame(frame, 3),
+        app.renderer.as_ref(),
+        app.config.dither(),
+        width,
+    );
+    for line in lines {
+        app.push_chat(line);
+    }
+    app.mark_unread(Tab::Chat);
+    app.chat_buffer.scroll_to_bottom();
+}
+
+/// Share a webcam snapshot in chat, saving it as a PNG at `save` too.
+/// Returns whether the snapshot was taken.
+async fn share_snapshot(app: &mut App, width: usize, save: Option<PathBuf>) -> bool {
+    let timestamp = stamp::now();
+    let renderer = app.renderer.clone();
+
+    let result = if let Some(cam) = &app.webcam {
+        if let Some(device) = &app.config.webcam.device {
+            cam.take_snapshot(device.clone(), renderer, width, save)
+                .await
+        } else {
+            Err(webcam::WebcamError::NotConfigured)
+        }
+    } else if app.config.webcam.device.is_some() {
+        // Configured, so turned off with /disable
+        Err(webcam::WebcamError::Disabled)
+    } else {
+        Err(webcam::WebcamError::NotConfigured)
+    };
+
+    match result {
+        Ok(lines) => {
+            let lamport = app.net_node.tick_lamport();
+            // Add header
+            app.push_chat_ordered(
+                format!("{}{} shared an image:", timestamp, app.config.network.name),
+                lamport,
+            );
+            // Add each line of the ASCII art
+            for line in &lines {
+                app.push_chat_ordered(line.clone(), lamport);
+            }
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+
+            // Also send to peers as multi-line message
+            let img_msg = format!("[IMAGE]\n{}", lines.join("\n"));
+            if let Err(e) = app.net_node.send_chat(&img_msg, lamport).await {
+                eprintln!("Failed to send image: {}", e);
+            }
+            true
+        }
+        Err(e) => {
+            let err_msg = format!("{}*** Webcam error: {} ***", timestamp, e);
+            app.push_chat(err_msg);
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            false
+        }
+    }
+}
+
+/// Share a webcam snapshot and save it, with the last frame from the peer
+/// we're calling, as PNGs in the logging directory
+async fn handle_snapshot_save(app: &mut App, width: usize) {
+    let stem = format!("snapshot-{}", Local::now().format("%Y%m%d-%H%M%S"));
+    let in_logs = |name: String| match &app.config.logging.directory {
+        Some(dir) => Path::new(dir).join(name),
+        None => PathBuf::from(name),
+    };
+    let path = in_logs(format!("{}.png", stem));
+    let peer_frame = app
+        .current_video_frame
+        .as_ref()
+        .filter(|(from, _)| app.active_call.as_ref() == Some(from))
+        .map(|(from, frame)| (in_logs(format!("{}-{}.png", stem, from)), frame.clone()));
+
+    if !share_snapshot(app, width, Some(path.clone())).await {
+        return;
+    }
+    let timestamp = stamp::now();
+    app.push_chat(format!(
+        "{}*** Snapshot saved to {} ***",
+        timestamp,
+        path.display()
+    ));
+    if let Some((peer_path, frame)) = peer_frame {
+        let msg = match webcam::save_raw_frame(&frame, &peer_path) {
+            Ok(()) => format!("Peer's video saved to {}", peer_path.display()),
+            Err(e) => format!("Can't save peer's video: {}", e),
+        };
+        app.push_chat(format!("{}*** {} ***", timestamp, msg));
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Offer a file to a peer
+fn handle_send(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let msg = match (&mut app.transfers, args.split_once(' ')) {
+        (None, _) => format!(
+            "{}*** File transfers are disabled (set directory under [files]) ***",
+            timestamp
+        ),
+        (Some(_), None) => format!("{}*** Usage: /send <peer> <file> ***", timestamp),
+        (Some(transfers), Some((peer, path))) => {
+            let path = path.trim();
+            if !app.net_node.peers().iter().any(|p| p.name == peer) {
+                format!("{}*** Peer '{}' not found ***", timestamp, peer)
+            } else {
+                match transfers.send(peer, Path::new(path)) {
+                    Ok(name) => format!(
+                        "{}*** Offered {} to {}, waiting for them to accept ***",
+                        timestamp, name, peer
+                    ),
+                    Err(e) => format!("{}*** Can't send {}: {} ***", timestamp, path, e),
+                }
+            }
+        }
+    };
+    app.push_chat(msg);
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Accept a file offer (the latest one, or by its number in `/transfers`)
+fn handle_accept(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let msg = match &mut app.transfers {
+        None => format!("{}*** File transfers are disabled ***", timestamp),
+        Some(transfers) => {
+            let number = if args.is_empty() {
+                Ok(None)
+            } else {
+                args.parse::<usize>().map(Some)
+            };
+            match number {
+                Err(_) => format!("{}*** Usage: /accept [n] ***", timestamp),
+                Ok(number) => match transfers.accept(number) {
+                    Ok((peer, name)) => {
+                        format!("{}*** Receiving {} from {} ***", timestamp, name, peer)
+                    }
+                    Err(e) => format!("{}*** Can't accept: {} ***", timestamp, e),
+                },
+            }
+        }
+    };
+    app.push_chat(msg);
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Show a collapsed share in full (`/expand [n]`, the latest by default)
+fn handle_expand(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let number = if args.is_empty() {
+        Ok(None)
+    } else {
+        args.trim_start_matches('#').parse::<usize>().map(Some)
+    };
+    match number.map(|n| app.collapsed.get(n).cloned()) {
+        Err(_) => app.push_chat(format!("{}*** Usage: /expand [n] ***", timestamp)),
+        Ok(None) => app.push_chat(format!(
+            "{}*** No such collapsed message (only recent ones are kept) ***",
+            timestamp
+        )),
+        Ok(Some(share)) => {
+            app.push_chat(format!(
+                "{}*** Message {} from {} in full: ***",
+                timestamp, share.number, share.from
+            ));
+            for line in share.lines {
+                app.push_chat(line);
+            }
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// List offers and transfers, or cancel one (`/transfers cancel <n>`)
+fn handle_transfers(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let lines = match &mut app.transfers {
+        None => vec![format!("{}*** File transfers are disabled ***", timestamp)],
+        Some(transfers) if args.is_empty() => {
+            let list = transfers.list();
+            if list.is_empty() {
+                vec![format!("{}*** No file transfers ***", timestamp)]
+            } else {
+                std::iter::once(format!("{}*** File transfers: ***", timestamp))
+                    .chain(list.into_iter().map(|line| format!("  {}", line)))
+                    .collect()
+            }
+        }
+        Some(transfers) => match args
+            .strip_prefix("cancel")
+            .and_then(|n| n.trim().parse::<usize>().ok())
+        {
+            Some(number) => match transfers.cancel(number) {
+                Ok(done) => vec![format!("{}*** {} ***", timestamp, done)],
+                Err(e) => vec![format!("{}*** Can't cancel: {} ***", timestamp, e)],
+            },
+            None => vec![format!(
+                "{}*** Usage: /transfers [cancel <n>] ***",
+                timestamp
+            )],
+        },
+    };
+    for line in lines {
+        app.push_chat(line);
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Show, set or clear (`-`) the self-description peers see in `/whois`
+fn handle_profile(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let msg = if args.is_empty() {
+        match &app.config.network.profile {
+            Some(profile) if !profile.is_empty() => {
+                format!("{}*** Profile: {} ***", timestamp, profile)
+            }
+            _ => format!("{}*** No profile set ***", timestamp),
+        }
+    } else if args == "-" {
+        app.config.network.profile = None;
+        format!("{}*** Profile cleared ***", timestamp)
+    } else {
+        let profile: String = args.chars().take(MAX_PROFILE_LEN).collect();
+        let msg = format!("{}*** Profile set: {} ***", timestamp, profile);
+        app.config.network.profile = Some(profile);
+        msg
+    };
+    app.push_chat(msg);
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Longest topic we'll set, so it fits on the separator row
+const MAX_TOPIC_LEN: usize = 200;
+
+/// Show, set (and broadcast) or clear (`-`) the room topic
+fn handle_topic(app: &mut App, args: &str, width: usize) {
+    let timestamp = stamp::now();
+    if args.is_empty() {
+        let msg = match &app.topic {
+            Some(topic) if !topic.text.is_empty() => format!(
+                "{}*** Topic: {} (set by {}) ***",
+                timestamp, topic.text, topic.set_by
+            ),
+            _ => format!("{}*** No topic set ***", timestamp),
+        };
+        app.push_chat(msg);
+    } else {
+        let text = if args == "-" {
+            String::new()
+        } else {
+            args.chars().take(MAX_TOPIC_LEN).collect()
+        };
+        let topic = RoomTopic {
+            text,
+            set_by: app.config.network.name.clone(),
+            set_at: chrono::Utc::now().timestamp_millis(),
+        };
+        app.set_topic(topic.clone(), width);
+        if let Err(e) = futures::executor::block_on(app.net_node.broadcast(&Message::Topic(topic)))
+        {
+            app.push_chat(format!("{}*** Failed to send topic: {} ***", timestamp, e));
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Call a peer (or ourselves, as a camera preview) and switch to the Call tab.
+/// Calling back a peer that's calling us connects the call.
+async fn start_call(app: &mut App, peer_name: &str, width: usize) {
+    // Check if peer exists (or is self)
+    let peer_addr = app
+        .net_node
+        .peers()
+        .iter()
+        .find(|p| p.name == peer_name)
+        .map(|p| p.addr);
+    if peer_name != app.config.network.name && peer_addr.is_none() {
+        let timestamp = stamp::now();
+        app.push_chat(format!(
+            "{}*** Peer '{}' not found ***",
+            timestamp, peer_name
+        ));
+        app.chat_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+        return;
+    }
+
+    // Send CallRequest if calling a remote peer
+    if peer_name != app.config.network.name
+        && let Some(addr) = peer_addr
+    {
+        let msg = Message::CallRequest {
+            from: app.config.network.name.clone(),
+        };
+        if let Err(e) = app.net_node.send_to(&msg, addr).await {
+            eprintln!("Failed to send call request: {}", e);
+        }
+    }
+
+    app.active_call = Some(peer_name.to_string());
+    app.net_node.reset_video(peer_name);
+    app.incoming_call = None;
+    app.call_last_packet = Some(std::time::Instant::now());
+    app.switch_tab(Tab::Call);
+    app.last_rendered_frame = None;
+
+    // Start webcam
+    if let Some(cam) = &app.webcam {
+        cam.start().await;
+    }
+
+    // Redraw UI
+    let status = app.call_status().unwrap_or_default();
+    let gemini_available = app.ai_chat.is_some();
+    let tunes_available = app.tunes_available();
+    let _ = app.serial.write_str(&init_split_screen_with_tabs(
+        app.theme,
+        &app.config.network.name,
+        app.active_tab,
+        gemini_available,
+        tunes_available,
+        app.active_call.as_deref(),
+        app.unread,
+        Some(&status),
+        app.topic_text(),
+        app.status_shown.as_deref(),
+        width,
+    ));
+}
+
+/// Answer the call prompt: Y calls the caller back, N declines
+async fn answer_call(app: &mut App, accept: bool, width: usize) {
+    let Some((caller, _)) = app.incoming_call.take() else {
+        return;
+    };
+    if accept {
+        start_call(app, &caller, width).await;
+        return;
+    }
+    let msg = Message::CallReject {
+        from: app.config.network.name.clone(),
+        reason: "declined".to_string(),
+    };
+    if let Some(peer) = app.net_node.peers().iter().find(|p| p.name == caller)
+        && let Err(e) = app.net_node.send_to(&msg, peer.addr).await
+    {
+        eprintln!("Failed to send call rejection: {}", e);
+    }
+    let timestamp = stamp::now();
+    app.push_chat(format!(
+        "{}*** Declined the call from {} ***",
+        timestamp, caller
+    ));
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /pin: pin our last message (no argument), the nth latest message,
+/// or some text to the top of the chat area; `-` unpins
+/// Do what a key bound under `[keys]` does. Returns false if the action
+/// doesn't apply on this tab, and the key should do what it otherwise would.
+async fn run_action(
+    app: &mut App,
+    action: Action,
+    width: usize,
+    last_stats_refresh: &mut std::time::Instant,
+) -> bool {
+    match action {
+        Action::NextTab => next_tab(app, width, last_stats_refresh).await,
+        // Useful if the terminal reconnects
+        Action::Refresh => app.redraw_screen(width),
+        Action::HangUp if app.active_tab == Tab::Call => hang_up(app, width).await,
+        Action::ScrollUp | Action::ScrollDown => scroll(app, action == Action::ScrollUp),
+        Action::StopMusic if app.active_tab == Tab::Tunes => {
+            if let Some(ref tunes) = app.tunes_state {
+                tunes.stop();
+                let _ = app.serial.write_str(&tunes.render());
+            }
+        }
+        Action::Compose if app.active_tab.has_input() && !app.ai_processing => app.compose.start(),
+        Action::HangUp | Action::StopMusic | Action::Compose => return false,
+    }
+    true
+}
+
+/// Switch to the next tab and draw it
+async fn next_tab(app: &mut App, width: usize, last_stats_refresh: &mut std::time::Instant) {
+    let next = app.active_tab.next(
+        app.ai_chat.is_some(),
+        app.active_call.is_some(),
+        app.tunes_available(),
+    );
+    show_tab(app, next, width, last_stats_refresh).await;
+}
+
+/// A left click: on a tab in the tab bar switches to it, on a tune selects it
+async fn click(
+    app: &mut App,
+    row: usize,
+    col: usize,
+    width: usize,
+    last_stats_refresh: &mut std::time::Instant,
+) {
+    if row == 1 {
+        let tab = terminal::tab_at(
+            app.theme,
+            app.active_tab,
+            app.ai_chat.is_some(),
+            app.tunes_available(),
+            app.active_call.as_deref(),
+            app.unread,
+            col,
+        );
+        if let Some(tab) = tab.filter(|&tab| tab != app.active_tab) {
+            show_tab(app, tab, width, last_stats_refresh).await;
+        }
+    } else if app.active_tab == Tab::Tunes
+        && let Some(ref mut tunes) = app.tunes_state
+        && tunes.select_row(row)
+    {
+        let _ = app.serial.write_str(&tunes.render());
+    }
+}
+
+/// Switch to `tab` and draw it
+async fn show_tab(
+    app: &mut App,
+    tab: Tab,
+    width: usize,
+    last_stats_refresh: &mut std::time::Instant,
+) {
+    let prev_tab = app.active_tab;
+    let gemini_available = app.ai_chat.is_some();
+    let tunes_available = app.tunes_available();
+    app.switch_tab(tab);
+
+    // Reset video state when switching tabs
+    app.last_rendered_frame = None;
+
+    // Handle webcam state
+    if let Some(cam) = &app.webcam {
+        if app.active_tab == Tab::Call {
+            cam.start().await;
+        } else if prev_tab == Tab::Call && app.active_call.is_none() {
+            cam.stop().await;
+        }
+    }
+
+    // Redraw tab bar and content
+    let _ = app.serial.write_str(&redraw_tab_bar(
+        app.theme,
+        app.active_tab,
+        gemini_available,
+        tunes_available,
+        app.active_call.as_deref(),
+        app.unread,
+        width,
+    ));
+
+    match app.active_tab {
+        Tab::Chat => {
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                None,
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            let _ = app.serial.write_str(&redraw_input(
+                app.theme,
+                &app.config.network.name,
+                &app.line_buffer,
+                app.input_cursor,
+                width,
+            ));
+        }
+        Tab::Gemini => {
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                None,
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+            let _ = app.serial.write_str(&app.ai_buffer.render());
+            let _ = app.serial.write_str(&redraw_input(
+                app.theme,
+                &app.config.network.name,
+                &app.line_buffer,
+                app.input_cursor,
+                width,
+            ));
+        }
+        Tab::Call => {
+            let status = app.call_status();
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                status.as_deref(),
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+        }
+        Tab::Tunes => {
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                None,
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+            if let Some(ref tunes) = app.tunes_state {
+                tunes.redraw_cover();
+                let _ = app.serial.write_str(&tunes.render());
+            }
+        }
+        Tab::Stats => {
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                None,
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+            *last_stats_refresh = std::time::Instant::now();
+            let _ = app.serial.write_str(&render_stats(
+                &app.net_node.peer_stats(),
+                &app.peer_caps,
+                width,
+            ));
+        }
+        Tab::Todo => {
+            let _ = app.serial.write_str(&init_split_screen_with_tabs(
+                app.theme,
+                &app.config.network.name,
+                app.active_tab,
+                gemini_available,
+                tunes_available,
+                app.active_call.as_deref(),
+                app.unread,
+                None,
+                app.topic_text(),
+                app.status_shown.as_deref(),
+                width,
+            ));
+            let _ = app.serial.write_str(&app.todo.render());
+        }
+    }
+}
+
+/// The Escape key: give up a compose sequence, drop a paste being held,
+/// turn down a ringing call or stop a search, or else clear the input line
+async fn cancel(app: &mut App, width: usize) {
+    if app.compose.is_active() {
+        app.compose.cancel();
+    } else if app.active_tab == Tab::Chat && app.pending_paste.is_some() {
+        app.answer_paste(false).await;
+    } else if app.active_tab == Tab::Chat && app.incoming_call.is_some() {
+        answer_call(app, false, width).await;
+    } else if app.active_tab == Tab::Chat && app.chat_buffer.search_query().is_some() {
+        app.end_search(width);
+    } else if app.active_tab.has_input() && !app.ai_processing && !app.line_buffer.is_empty() {
+        app.line_buffer.clear();
+        app.input_cursor = 0;
+        app.history_index = None;
+        let _ = app.serial.write_str(&redraw_input(
+            app.theme,
+            &app.config.network.name,
+            &app.line_buffer,
+            app.input_cursor,
+            width,
+        ));
+    }
+}
+
+/// End the call, if there is one, and go back to the Chat tab
+async fn hang_up(app: &mut App, width: usize) {
+    if let Some(peer_name) = app.active_call.take() {
+        // Send hangup message
+        if peer_name != app.config.network.name
+            && let Some(peer) = app.net_node.peers().iter().find(|p| p.name == peer_name)
+        {
+            let msg = Message::CallHangup {
+                from: app.config.network.name.clone(),
+            };
+            if let Err(e) = futures::executor::block_on(app.net_node.send_to(&msg, peer.addr)) {
+                eprintln!("Failed to send hangup: {}", e);
+            }
+        }
+
+        // Notify local user
+        let timestamp = stamp::now();
+        app.push_chat(format!(
+            "{}*** Call with {} ended ***",
+            timestamp, peer_name
+        ));
+
+        app.net_node.reset_video(&peer_name);
+        app.last_rendered_frame = None;
+        app.call_last_packet = None;
+        app.call_connected = false;
+        // Stop webcam
+        if let Some(cam) = &app.webcam {
+            cam.stop().await;
+        }
+        // Switch back to Chat
+        app.switch_tab(Tab::Chat);
+        let gemini_available = app.ai_chat.is_some();
+        let tunes_available = app.tunes_available();
+        let _ = app.serial.write_str(&init_split_screen_with_tabs(
+            app.theme,
+            &app.config.network.name,
+            app.active_tab,
+            gemini_available,
+            tunes_available,
+            app.active_call.as_deref(),
+            app.unread,
+            None,
+            app.topic_text(),
+            app.status_shown.as_deref(),
+            width,
+        ));
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+        let _ = app.serial.write_str(&redraw_input(
+            app.theme,
+            &app.config.network.name,
+            &app.line_buffer,
+            app.input_cursor,
+            width,
+        ));
+    }
+}
+
+/// Scroll the buffer on the Chat or AI tab by a page, or page through the
+/// list on the Tunes or Todo tab
+fn scroll(app: &mut App, up: bool) {
+    if app.active_tab == Tab::Tunes {
+        if let Some(ref mut tunes) = app.tunes_state {
+            if up {
+                tunes.page_up();
+            } else {
+                tunes.page_down();
+            }
+            let _ = app.serial.write_str(&tunes.render());
+        }
+    } else if app.active_tab == Tab::Todo {
+        if up {
+            app.todo.page_up();
+        } else {
+            app.todo.page_down();
+        }
+        let _ = app.serial.write_str(&app.todo.render());
+    } else if app.active_tab.has_input() {
+        let active_buffer = if app.active_tab == Tab::Chat {
+            &mut app.chat_buffer
+        } else {
+            &mut app.ai_buffer
+        };
+        if up {
+            active_buffer.scroll_up(10);
+        } else {
+            active_buffer.scroll_down(10);
+        }
+        let _ = app.serial.write_str(&active_buffer.render());
+    }
+}
+
+fn handle_pin(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let text = if args == "-" {
+        String::new()
+    } else if args.is_empty() || args.parse::<usize>().is_ok() {
+        let entry = match args.parse::<usize>() {
+            Ok(back) => app.chat_history.latest(back, None),
+            Err(_) => app.chat_history.latest(1, Some(&app.config.network.name)),
+        };
+        let Some(entry) = entry else {
+            app.push_chat(format!("{}*** No such message to pin ***", timestamp));
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            return;
+        };
+        let first_line = entry.text.lines().next().unwrap_or_default();
+        match first_line.strip_prefix("\x01ACTION ") {
+            Some(action) => format!("* {} {}", entry.from, action),
+            None => format!("{}: {}", entry.from, first_line),
+        }
+    } else {
+        args.to_string()
+    };
+    let pin = RoomTopic {
+        text: text
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_TOPIC_LEN)
+            .collect(),
+        set_by: app.config.network.name.clone(),
+        set_at: chrono::Utc::now().timestamp_millis(),
+    };
+    // The pin belongs to the mesh window
+    app.in_mesh(|app| app.set_pin(pin.clone()));
+    app.windows.arrived(0);
+    if let Err(e) = futures::executor::block_on(app.net_node.broadcast(&Message::Pin(pin))) {
+        app.push_chat(format!("{}*** Failed to send pin: {} ***", timestamp, e));
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /ring: ask a peer's terminal to ring its bell
+fn handle_ring(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let msg = if args.is_empty() {
+        format!("{}*** Usage: /ring <peer> ***", timestamp)
+    } else {
+        match app.net_node.peers().iter().find(|p| p.name == args) {
+            Some(peer) => {
+                let ring = Message::Ring {
+                    from: app.config.network.name.clone(),
+                };
+                match futures::executor::block_on(app.net_node.send_to(&ring, peer.addr)) {
+                    Ok(()) => format!("{}*** Ringing {}... ***", timestamp, args),
+                    Err(e) => format!("{}*** Ring failed: {} ***", timestamp, e),
+                }
+            }
+            None => format!("{}*** Peer '{}' not found ***", timestamp, args),
+        }
+    };
+    app.push_chat(msg);
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /search: jump to the newest line in the scrollback containing the
+/// text, for n/p to step through the rest; no argument stops searching
+fn handle_search(app: &mut App, args: &str, width: usize) {
+    if args.is_empty() {
+        if app.chat_buffer.search_query().is_some() {
+            app.end_search(width);
+        } else {
+            let timestamp = stamp::now();
+            app.push_chat(format!(
+                "{}*** Usage: /search <text> (or Ctrl+F), then n/p for older/newer matches ***",
+                timestamp
+            ));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+        }
+        return;
+    }
+    if !app.search_chat(args, width) {
+        let timestamp = stamp::now();
+        app.push_chat(format!("{}*** No matches for \"{}\" ***", timestamp, args));
+        app.chat_buffer.scroll_to_bottom();
+        let _ = app.serial.write_str(&app.chat_buffer.render());
+    }
+}
+
+/// Handle /timer: start a countdown (`25m`, `90s`, `1h30m`) or a pomodoro
+/// session, for us or (`share`) everyone online; `stop` ends it and no
+/// argument shows what's left
+fn handle_timer(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let name = app.config.network.name.clone();
+    if args.is_empty() {
+        let now = std::time::Instant::now();
+        let msg = match &app.timer {
+            Some(timer) => format!("{}*** {} ***", timestamp, timer.display(now)),
+            None => format!(
+                "{}*** No timer running. Usage: /timer [share] <25m|pomodoro> [label] | stop ***",
+                timestamp
+            ),
+        };
+        app.push_chat(msg);
+    } else if args == "stop" {
+        match app.timer.take() {
+            Some(timer) => {
+                if timer.shared_by.as_deref() == Some(name.as_str()) {
+                    let msg = Message::TimerStop { from: name };
+                    let _ = futures::executor::block_on(app.net_node.broadcast(&msg));
+                }
+                app.push_chat(format!("{}*** Timer stopped ***", timestamp));
+            }
+            None => app.push_chat(format!("{}*** No timer running ***", timestamp)),
+        }
+    } else {
+        let (share, args) = match args.strip_prefix("share ") {
+            Some(rest) => (true, rest.trim()),
+            None => (false, args),
+        };
+        let (length, label) = args.split_once(' ').unwrap_or((args, ""));
+        let label: String = label.trim().chars().take(MAX_TIMER_LABEL).collect();
+        let kind = if length == "pomodoro" {
+            TimerKind::Pomodoro
+        } else if let Some(length) = timer::parse_duration(length) {
+            TimerKind::Countdown(length)
+        } else {
+            app.push_chat(format!(
+                "{}*** Usage: /timer [share] <25m|90s|1h30m|pomodoro> [label] | stop ***",
+                timestamp
+            ));
+            app.chat_buffer.scroll_to_bottom();
+            let _ = app.serial.write_str(&app.chat_buffer.render());
+            return;
+        };
+        app.timer = Some(Timer::new(
+            &label,
+            kind,
+            std::time::Instant::now(),
+            share.then(|| name.clone()),
+        ));
+        if share {
+            let msg = Message::TimerStart {
+                from: name,
+                label: label.clone(),
+                started_ms: chrono::Utc::now().timestamp_millis(),
+                length_secs: match kind {
+                    TimerKind::Countdown(length) => Some(length.as_secs() as u32),
+                    TimerKind::Pomodoro => None,
+                },
+            };
+            if let Err(e) = futures::executor::block_on(app.net_node.broadcast(&msg)) {
+                app.push_chat(format!("{}*** Failed to share timer: {} ***", timestamp, e));
+            }
+        }
+        let what = match kind {
+            TimerKind::Countdown(_) => format!("Timer set for {}", length),
+            TimerKind::Pomodoro => "Pomodoro started: 25 min work, 5 min break".to_string(),
+        };
+        app.push_chat(format!(
+            "{}*** {}{}{} ***",
+            timestamp,
+            what,
+            if label.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", label)
+            },
+            if share { " for everyone online" } else { "" }
+        ));
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Handle /todo: list the shared to-do list, or add, remove or clear items
+fn handle_todo(app: &mut App, args: &str) {
+    let timestamp = stamp::now();
+    let (command, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let now = chrono::Utc::now().timestamp_millis();
+    match command {
+        "" => {
+            let items = app.todo.items();
+            let mut lines = vec![if items.is_empty() {
+                format!("{}*** The to-do list is empty ***", timestamp)
+            } else {
+                format!("{}*** To-do list: ***", timestamp)
+            }];
+            for (i, item) in items.iter().enumerate() {
+                let check = if item.done { 'x' } else { ' ' };
+                lines.push(format!(
+                    "  {}. [{}] {} ({})",
+                    i + 1,
+                    check,
+                    item.text,
+                    item.added_by
+                ));
+            }
+            for line in lines {
+                app.push_chat(line);
+            }
+        }
+        "add" if !rest.is_empty() => {
+            let op = TodoOp::Add {
+                id: rand::random(),
+                text: rest.chars().take(MAX_TODO_LEN).collect(),
+                by: app.config.network.name.clone(),
+                at: now,
+            };
+            futures::executor::block_on(app.change_todo(op));
+            app.push_chat(format!(
+                "{}*** Added to the to-do list: {} ***",
+                timestamp, rest
+            ));
+        }
+        "remove" if rest.parse::<usize>().is_ok() => {
+            let n = rest.parse::<usize>().unwrap();
+            let Some(id) = n
+                .checked_sub(1)
+                .and_then(|i| app.todo.items().get(i).map(|item| item.id))
+            else {
+                app.push_chat(format!("{}*** No to-do item {} ***", timestamp, n));
+                let _ = app.serial.write_str(&app.chat_buffer.render());
+                return;
+            };
+            let op = TodoOp::Remove {
+                id,
+                by: app.config.network.name.clone(),
+                at: now,
+            };
+            futures::executor::block_on(app.change_todo(op));
+            app.push_chat(format!("{}*** Removed to-do item {} ***", timestamp, n));
+        }
+        "clear" => {
+            let removed = futures::executor::block_on(app.clear_done_todos());
+            app.push_chat(format!(
+                "{}*** Removed {} checked to-do item(s) ***",
+                timestamp, removed
+            ));
+        }
+        _ => {
+            app.push_chat(format!(
+                "{}*** Usage: /todo [add <text>|remove <n>|clear] ***",
+                timestamp
+            ));
+        }
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Save the current screen as a PNG (in the log directory unless a path is given)
+fn handle_screenshot(app: &mut App, args: &str) {
+    let path = if args.is_empty() {
+        let name = format!("wormhole-{}.png", Local::now().format("%Y%m%d-%H%M%S"));
+        match &app.config.logging.directory {
+            Some(dir) => std::path::Path::new(dir).join(name),
+            None => PathBuf::from(name),
+        }
+    } else {
+        PathBuf::from(args)
+    };
+
+    let timestamp = stamp::now();
+    match graphics::save_png(app.serial.screen().frame(), &path) {
+        Ok(()) => app.push_chat(format!(
+            "{}*** Screenshot saved to {} ***",
+            timestamp,
+            path.display()
+        )),
+        Err(e) => app.push_chat(format!("{}*** Screenshot failed: {} ***", timestamp, e)),
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Write a JSON snapshot of the session for bug reports (see `--load-state`)
+fn handle_dump_state(app: &mut App) {
+    let timestamp = stamp::now();
+    match app
+        .dump_state()
+        .save(app.config.logging.directory.as_deref())
+    {
+        Ok(path) => app.push_chat(format!(
+            "{}*** State dumped to {} ***",
+            timestamp,
+            path.display()
+        )),
+        Err(e) => app.push_chat(format!("{}*** State dump failed: {} ***", timestamp, e)),
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Show the sizes of long-lived buffers and caches
+fn handle_memstats(app: &mut App) {
+    let timestamp = stamp::now();
+    let net = app.net_node.cache_stats();
+    let rss = match resident_memory_kb() {
+        Some(kb) => format!("{} KB", kb),
+        None => "unavailable".to_string(),
+    };
+    let video = app
+        .current_video_frame
+        .as_ref()
+        .map(|(_, f)| f.pixels.len())
+        .unwrap_or(0);
+    let ai_history = app.ai_chat.as_ref().map(|g| g.history_len()).unwrap_or(0);
+
+    let lines = [
+        format!("{}*** Memory: RSS {} ***", timestamp, rss),
+        format!(
+            "{}*** Buffers: chat {} lines, AI {} lines, input history {}/{}, AI history {} messages ***",
+            timestamp,
+            app.chat_buffer.recent_lines(usize::MAX).len(),
+            app.ai_buffer.recent_lines(usize::MAX).len(),
+            app.input_history.len(),
+            app.input_history.limit(),
+            ai_history
+        ),
+        format!(
+            "{}*** Network: {} peers, {} known addresses, {} recently left, {} partial frames ({} bytes), video frame {} bytes, {} video keyframes ***",
+            timestamp,
+            net.peers,
+            net.known_addrs,
+            net.recently_left,
+            net.fragment_buffers,
+            net.fragment_bytes,
+            video,
+            net.video_coders
+        ),
+    ];
+    for line in lines {
+        app.push_chat(line);
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
+
+/// Resident set size from /proc (Linux only)
+fn resident_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// List the release every peer runs (`/versions`)
+fn handle_versions(app: &mut App) {
+    let timestamp = stamp::now();
+    let mut lines = vec![format!(
+        "{}*** You run Wormhole {} (protocol {}) ***",
+        timestamp, APP_VERSION, PROTOCOL_VERSION
+    )];
+    for peer in app.net_node.peers() {
+        lines.push(format!(
+            "{}***   {}: {} ***",
+            timestamp,
+            peer.name,
+            peer.version.as_deref().unwrap_or("unknown")
+        ));
+    }
+    for (addr, protocol) in app.net_node.incompatible_peers() {
+        lines.push(format!(
+            "{}***   {}: protocol {} (incompatible) ***",
+            timestamp, addr, protocol
+        ));
+    }
+    for line in lines {
+        app.push_chat(line);
+    }
+    app.chat_buffer.scroll_to_bottom();
+    let _ = app.serial.write_str(&app.chat_buffer.render());
+}
@@ -0,0 +1,110 @@
+//! Motion alerts from an idle webcam (`motion` under `[webcam]`).
+//!
+//! While the Call tab shows our own picture and no call is up, each frame is
+//! shrunk to a coarse grid and compared with the one before; when enough of
+//! the grid changes, a line and a snapshot go into the chat, at most once a
+//! minute. Cheap enough for a Pi, and it turns the idle rig into a retro
+//! security monitor.
+
+use std::time::{Duration, Instant};
+
+use image::GrayImage;
+use image::imageops::{self, FilterType};
+
+use crate::webcam::RawFrame;
+
+/// Grid the frames are compared on
+const GRID: (u32, u32) = (32, 18);
+/// How much a cell's brightness has to change to count
+const CELL_CHANGE: u8 = 24;
+/// Share of cells that have to change, in percent
+const MOVED_PERCENT: usize = 3;
+/// Quietest spell between alerts
+pub const MOTION_COOLDOWN: Duration = Duration::from_secs(60);
+/// A frame older than this (from before a visit to another tab) isn't
+/// compared with
+const STALE: Duration = Duration::from_secs(5);
+
+/// Compares successive frames for movement
+#[derive(Debug, Default)]
+pub struct MotionDetector {
+    /// The last frame's grid and when it came
+    previous: Option<(GrayImage, Instant)>,
+    last_alert: Option<Instant>,
+}
+
+impl MotionDetector {
+    /// Whether `frame` moved enough since the last one to raise an alert
+    pub fn check(&mut self, frame: &RawFrame, now: Instant) -> bool {
+        let Some(image) = GrayImage::from_raw(
+            frame.width as u32,
+            frame.height as u32,
+            frame.pixels.clone(),
+        ) else {
+            return false;
+        };
+        let grid = imageops::resize(&image, GRID.0, GRID.1, FilterType::Triangle);
+        let Some((previous, _)) = self
+            .previous
+            .replace((grid.clone(), now))
+            .filter(|(_, at)| now.duration_since(*at) < STALE)
+        else {
+            return false;
+        };
+        let changed = grid
+            .pixels()
+            .zip(previous.pixels())
+            .filter(|(a, b)| a[0].abs_diff(b[0]) > CELL_CHANGE)
+            .count();
+        let moved = changed * 100 >= MOVED_PERCENT * (GRID.0 * GRID.1) as usize;
+        let quiet = self
+            .last_alert
+            .is_none_or(|at| now.duration_since(at) >= MOTION_COOLDOWN);
+        if moved && quiet {
+            self.last_alert = Some(now);
+        }
+        moved && quiet
+    }
+
+    /// Forget the last frame, so watching starts afresh (after a call, say)
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(fill: impl Fn(u32, u32) -> u8) -> RawFrame {
+        let image = GrayImage::from_fn(320, 180, |x, y| image::Luma([fill(x, y)]));
+        RawFrame {
+            width: 320,
+            height: 180,
+            pixels: image.into_raw(),
+        }
+    }
+
+    #[test]
+    fn test_motion_alerts() {
+        let mut detector = MotionDetector::default();
+        let start = Instant::now();
+        let empty = frame(|_, _| 40);
+        // Someone walks in on the left
+        let visitor = frame(|x, _| if x < 80 { 200 } else { 40 });
+
+        assert!(!detector.check(&empty, start));
+        assert!(!detector.check(&frame(|x, _| 40 + (x % 2) as u8 * 10), start));
+        assert!(detector.check(&visitor, start));
+        // Back out again, but within the cooldown
+        assert!(!detector.check(&empty, start + Duration::from_secs(2)));
+
+        detector.reset();
+        let later = start + MOTION_COOLDOWN;
+        assert!(!detector.check(&visitor, later));
+        assert!(detector.check(&empty, later));
+        // Nor is a frame from long ago compared with
+        let much_later = later + MOTION_COOLDOWN * 2;
+        assert!(!detector.check(&visitor, much_later));
+    }
+}
@@ -0,0 +1,63 @@
+//! Mouse and locator clicks (`mouse` under `[terminal]`).
+//!
+//! Modern emulators report clicks with xterm's mouse tracking, asked for in
+//! its SGR coding (`ESC [ < b ; x ; y M`) so wide screens work; a VT340 with
+//! a locator sends DEC locator reports instead, once locator reporting is
+//! turned on in character cells. Either way a left click on a tab in the tab
+//! bar switches to it, and one on a tune in the Tunes list selects it. The
+//! reports are parsed by [`EscapeParser`](crate::input::EscapeParser).
+
+/// Which kind of click reports to ask the terminal for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mouse {
+    /// None (the default)
+    Off,
+    /// xterm mouse tracking
+    Xterm,
+    /// DEC locator reports (VT340)
+    Locator,
+}
+
+/// The `mouse` settings
+pub const MOUSE_NAMES: &[&str] = &["off", "xterm", "locator"];
+
+/// Turn both kinds of report off, on the way out
+pub const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1000l\x1b[0'z";
+
+impl Mouse {
+    /// The setting named `name`, one of [`MOUSE_NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(Mouse::Off),
+            "xterm" => Some(Mouse::Xterm),
+            "locator" => Some(Mouse::Locator),
+            _ => None,
+        }
+    }
+
+    /// What asks the terminal for its reports
+    pub fn on_sequence(self) -> &'static str {
+        match self {
+            Mouse::Off => "",
+            // Button presses and releases, SGR coded
+            Mouse::Xterm => "\x1b[?1000h\x1b[?1006h",
+            // Reports in character cells (DECELR), on button down (DECSLE)
+            Mouse::Locator => "\x1b[1;2'z\x1b[1'{",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mouse_names() {
+        for name in MOUSE_NAMES {
+            assert!(Mouse::from_name(name).is_some());
+        }
+        assert_eq!(Mouse::from_name("XTerm"), Some(Mouse::Xterm));
+        assert_eq!(Mouse::from_name("trackball"), None);
+        assert!(Mouse::Off.on_sequence().is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+//! Protocol debugging: a message log and pcap capture of peer traffic.
+//!
+//! With `--debug-protocol`, every datagram [`super::PeerSocket`] sends or
+//! receives is described in a text log (direction, peer, message type, size or
+//! the decode error). `--pcap` additionally writes the raw datagrams to a
+//! capture file Wireshark can open; each is wrapped in a synthetic IP/UDP
+//! header, since the socket only sees payloads. Relayed traffic is logged as
+//! the app sees it: unwrapped, from the original peer.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::Local;
+
+use super::{Message, codec};
+
+/// pcap link type for raw IPv4/IPv6 packets
+const LINKTYPE_RAW: u32 = 101;
+
+/// Which way a datagram went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Where protocol debugging output goes
+pub struct ProtocolTap {
+    log: Option<File>,
+    pcap: Option<PcapWriter<File>>,
+    /// Our socket address, used as the local end in pcap records
+    local: SocketAddr,
+}
+
+impl ProtocolTap {
+    /// Open the message log and/or pcap file (either may be None)
+    pub fn open(log: Option<&Path>, pcap: Option<&Path>) -> io::Result<Self> {
+        let log = match log {
+            Some(path) => {
+                if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Some(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            None => None,
+        };
+        let pcap = match pcap {
+            Some(path) => Some(PcapWriter::new(File::create(path)?)?),
+            None => None,
+        };
+        Ok(Self {
+            log,
+            pcap,
+            local: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        })
+    }
+
+    /// Set the local address recorded in pcap packets
+    pub fn set_local_addr(&mut self, addr: SocketAddr) {
+        self.local = addr;
+    }
+
+    /// Record one datagram. Write errors are reported once and disable that output.
+    pub fn record(&mut self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        if let Some(log) = &mut self.log {
+            let line = format!(
+                "{} {}\n",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                describe(direction, peer, data)
+            );
+            if let Err(e) = log.write_all(line.as_bytes()) {
+                eprintln!("Protocol log disabled: {}", e);
+                self.log = None;
+            }
+        }
+        if let Some(pcap) = &mut self.pcap {
+            let (src, dst) = match direction {
+                Direction::Sent => (self.local, peer),
+                Direction::Received => (peer, self.local),
+            };
+            if let Err(e) = pcap.write_packet(SystemTime::now(), src, dst, data) {
+                eprintln!("Packet capture disabled: {}", e);
+                self.pcap = None;
+            }
+        }
+    }
+}
+
+/// One log line (without the timestamp)
+fn describe(direction: Direction, peer: SocketAddr, data: &[u8]) -> String {
+    let arrow = match direction {
+        Direction::Sent => "TX ->",
+        Direction::Received => "RX <-",
+    };
+    match codec::decode::<Message>(data) {
+        Ok(msg) => format!("{} {} {} ({} bytes)", arrow, peer, msg.kind(), data.len()),
+        Err(e) => format!(
+            "{} {} undecodable ({} bytes): {}",
+            arrow,
+            peer,
+            data.len(),
+            e
+        ),
+    }
+}
+
+/// Writes a classic (microsecond) pcap file
+struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    fn new(mut out: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend(0xa1b2_c3d4u32.to_le_bytes());
+        header.extend(2u16.to_le_bytes());
+        header.extend(4u16.to_le_bytes());
+        header.extend(0i32.to_le_bytes()); // thiszone
+        header.extend(0u32.to_le_bytes()); // sigfigs
+        header.extend(65_535u32.to_le_bytes()); // snaplen
+        header.extend(LINKTYPE_RAW.to_le_bytes());
+        out.write_all(&header)?;
+        out.flush()?;
+        Ok(Self { out })
+    }
+
+    fn write_packet(
+        &mut self,
+        time: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let packet = ip_udp_packet(src, dst, payload);
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + packet.len());
+        record.extend((since_epoch.as_secs() as u32).to_le_bytes());
+        record.extend(since_epoch.subsec_micros().to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend((packet.len() as u32).to_le_bytes());
+        record.extend(packet);
+        self.out.write_all(&record)?;
+        self.out.flush()
+    }
+}
+
+/// Wrap a payload in IPv4 (or IPv6, if either end is IPv6) and UDP headers
+fn ip_udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend(src.port().to_be_bytes());
+    udp.extend(dst.port().to_be_bytes());
+    udp.extend(udp_len.to_be_bytes());
+    // A zero checksum means "not computed" (Wireshark flags it for IPv6, harmlessly)
+    udp.extend(0u16.to_be_bytes());
+    udp.extend_from_slice(payload);
+
+    match (v4(src.ip()), v4(dst.ip())) {
+        (Some(src), Some(dst)) => {
+            let mut ip = Vec::with_capacity(20 + udp.len());
+            ip.extend([0x45, 0]);
+            ip.extend((20 + udp_len).to_be_bytes());
+            ip.extend([0, 0, 0x40, 0, 64, 17, 0, 0]);
+            ip.extend(src.octets());
+            ip.extend(dst.octets());
+            let checksum = ipv4_checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            ip.extend(udp);
+            ip
+        }
+        _ => {
+            let mut ip = Vec::with_capacity(40 + udp.len());
+            ip.extend([0x60, 0, 0, 0]);
+            ip.extend(udp_len.to_be_bytes());
+            ip.extend([17, 64]);
+            ip.extend(v6(src.ip()).octets());
+            ip.extend(v6(dst.ip()).octets());
+            ip.extend(udp);
+            ip
+        }
+    }
+}
+
+/// The address as IPv4, treating an unspecified IPv6 address as 0.0.0.0
+fn v4(ip: IpAddr) -> Option<Ipv4Addr> {
+    match ip {
+        IpAddr::V4(v4) => Some(v4),
+        IpAddr::V6(v6) if v6.is_unspecified() => Some(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(v6) => v6.to_ipv4_mapped(),
+    }
+}
+
+fn v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let peer: SocketAddr = "10.0.0.2:7890".parse().unwrap();
+        let ping = Message::Ping { seq: 1 }.to_bytes();
+        assert_eq!(
+            describe(Direction::Sent, peer, &ping),
+            format!("TX -> 10.0.0.2:7890 Ping ({} bytes)", ping.len())
+        );
+        let mut newer = ping.clone();
+        newer[2] += 1;
+        assert!(describe(Direction::Received, peer, &newer).contains("undecodable"));
+    }
+
+    #[test]
+    fn test_pcap_records() {
+        let mut out = Vec::new();
+        {
+            let mut pcap = PcapWriter::new(&mut out).unwrap();
+            let src: SocketAddr = "10.0.0.1:7890".parse().unwrap();
+            let dst: SocketAddr = "10.0.0.2:7891".parse().unwrap();
+            pcap.write_packet(UNIX_EPOCH, src, dst, b"hi").unwrap();
+        }
+        assert_eq!(&out[..4], &0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(out[20..24], LINKTYPE_RAW.to_le_bytes());
+
+        // Record header, then a 20 byte IPv4 header, 8 byte UDP header and payload
+        let packet = &out[24 + 16..];
+        assert_eq!(packet.len(), 30);
+        assert_eq!(out[24 + 8..24 + 12], 30u32.to_le_bytes());
+        assert_eq!(ipv4_checksum(&packet[..20]), 0);
+        assert_eq!(&packet[20..22], &7890u16.to_be_bytes());
+        assert_eq!(&packet[28..], b"hi");
+
+        // A mixed-family pair is written as IPv6
+        let v6_packet = ip_udp_packet(
+            "[::]:7890".parse().unwrap(),
+            "[2001:db8::1]:7890".parse().unwrap(),
+            b"hi",
+        );
+        assert_eq!(v6_packet[0] >> 4, 6);
+        assert_eq!(v6_packet.len(), 50);
+    }
+}
@@ -0,0 +1,245 @@
+//! Binary serde format for the peer protocol.
+//!
+//! Every datagram is framed as `MAGIC | version | length | payload`, where the
+//! length is a LEB128 varint. The payload is [postcard], a compact,
+//! non-self-describing serde encoding: integers, lengths and enum variant
+//! indices are varints, `Option` is a one-byte tag followed by the value,
+//! and structs and tuples are just their fields in order.
+//!
+//! Because field names and types aren't on the wire, both ends must agree on
+//! the message definitions. Any incompatible change to [`super::Message`] has
+//! to bump [`PROTOCOL_VERSION`].
+
+use serde::Serialize;
+use serde::de::{self, DeserializeOwned, Visitor};
+
+/// Magic bytes identifying a Wormhole peer message
+pub const MAGIC: &[u8; 2] = b"WH";
+
+/// Wire format version (1 was the original hand-rolled encoding, 3 added
+/// release versions to `Join` and `DiscoveryAnnounce`, 4 added BLAKE3 hashes
+/// to video fragments and file transfers, 5 added a reason to `CallReject`, 6
+/// added a relay hop count to `Chat`, 7 added message ids to `Chat` for
+/// `ChatEdit`, 8 added delta-coded video to `VideoFrameFragment`, 9 moved the
+/// payload to postcard)
+pub const PROTOCOL_VERSION: u8 = 9;
+
+/// Serialize a value into a framed datagram
+pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let payload = postcard::to_allocvec(value)?;
+
+    let mut buf = Vec::with_capacity(payload.len() + 8);
+    buf.extend_from_slice(MAGIC);
+    buf.push(PROTOCOL_VERSION);
+    write_varint(&mut buf, payload.len() as u64);
+    buf.extend(payload);
+    Ok(buf)
+}
+
+/// The protocol version in a datagram's header, whatever version it is
+pub fn frame_version(data: &[u8]) -> Option<u8> {
+    data.strip_prefix(MAGIC)?.first().copied()
+}
+
+/// Deserialize a framed datagram written by [`encode`]
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, CodecError> {
+    let rest = data.strip_prefix(MAGIC).ok_or(CodecError::Magic)?;
+    let (&version, rest) = rest.split_first().ok_or(CodecError::Eof)?;
+    if version != PROTOCOL_VERSION {
+        return Err(CodecError::Version(version));
+    }
+
+    let (len, rest) = read_varint(rest)?;
+    let payload = rest.get(..len as usize).ok_or(CodecError::Eof)?;
+    if rest.len() > payload.len() {
+        return Err(CodecError::Trailing);
+    }
+
+    let (value, unused) = postcard::take_from_bytes(payload)?;
+    if !unused.is_empty() {
+        return Err(CodecError::Trailing);
+    }
+    Ok(value)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Read a varint written by [`write_varint`], returning it and what follows
+fn read_varint(data: &[u8]) -> Result<(u64, &[u8]), CodecError> {
+    let mut n = 0u64;
+    for (i, shift) in (0..64).step_by(7).enumerate() {
+        let byte = *data.get(i).ok_or(CodecError::Eof)?;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((n, &data[i + 1..]));
+        }
+    }
+    Err(CodecError::Invalid("varint too long".into()))
+}
+
+/// Serde adapter that sends byte buffers as one length-prefixed run rather than
+/// element by element
+pub mod raw {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(super::ByteBufVisitor)
+    }
+}
+
+/// Serde adapter that LZ4-compresses byte buffers (raw video pixels)
+pub mod lz4 {
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&lz4_flex::compress_prepend_size(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let compressed = deserializer.deserialize_byte_buf(super::ByteBufVisitor)?;
+        lz4_flex::decompress_size_prepended(&compressed).map_err(D::Error::custom)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a byte buffer")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(v)
+    }
+}
+
+/// Errors from encoding or decoding messages
+#[derive(Debug, PartialEq, Eq)]
+pub enum CodecError {
+    /// Not a Wormhole message
+    Magic,
+    /// Sent by a peer speaking another protocol version
+    Version(u8),
+    /// Ran out of data mid-message
+    Eof,
+    /// Bytes left over after the message
+    Trailing,
+    /// Malformed value, or a type the format can't express
+    Invalid(String),
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Magic => write!(f, "not a Wormhole message"),
+            CodecError::Version(v) => {
+                write!(f, "protocol version {} (expected {})", v, PROTOCOL_VERSION)
+            }
+            CodecError::Eof => write!(f, "truncated message"),
+            CodecError::Trailing => write!(f, "trailing bytes after message"),
+            CodecError::Invalid(e) => write!(f, "invalid message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<postcard::Error> for CodecError {
+    fn from(e: postcard::Error) -> Self {
+        match e {
+            postcard::Error::DeserializeUnexpectedEnd => CodecError::Eof,
+            e => CodecError::Invalid(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Sample {
+        Unit,
+        Tuple(u8, i64),
+        Struct {
+            name: String,
+            flag: bool,
+            maybe: Option<u32>,
+            list: Vec<(char, f32)>,
+            #[serde(with = "raw")]
+            bytes: Vec<u8>,
+        },
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let values = vec![
+            Sample::Unit,
+            Sample::Tuple(7, -1),
+            Sample::Struct {
+                name: "zoë".to_string(),
+                flag: true,
+                maybe: Some(300),
+                list: vec![('x', 1.5), ('─', -0.25)],
+                bytes: vec![0; 200],
+            },
+        ];
+        for value in values {
+            let bytes = encode(&value).unwrap();
+            assert_eq!(decode::<Sample>(&bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_framing() {
+        let bytes = encode(&Sample::Tuple(1, 2)).unwrap();
+        assert_eq!(&bytes[..2], MAGIC);
+        assert_eq!(bytes[2], PROTOCOL_VERSION);
+        assert_eq!(bytes[3] as usize, bytes.len() - 4);
+
+        // Truncated, padded, foreign and other-version datagrams are rejected
+        assert_eq!(
+            decode::<Sample>(&bytes[..bytes.len() - 1]),
+            Err(CodecError::Eof)
+        );
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert_eq!(decode::<Sample>(&padded), Err(CodecError::Trailing));
+        assert_eq!(decode::<Sample>(b"\x01\x05Alice"), Err(CodecError::Magic));
+        let mut newer = bytes.clone();
+        newer[2] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            decode::<Sample>(&newer),
+            Err(CodecError::Version(PROTOCOL_VERSION + 1))
+        );
+        assert_eq!(frame_version(&newer), Some(PROTOCOL_VERSION + 1));
+        assert_eq!(frame_version(b"\x01\x05Alice"), None);
+    }
+
+    #[test]
+    fn test_varint() {
+        for n in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, n);
+            assert_eq!(read_varint(&buf), Ok((n, &[][..])));
+        }
+    }
+}
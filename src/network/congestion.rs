@@ -0,0 +1,328 @@
+//! Congestion control for call video.
+//!
+//! The receiving side of a call sends a [`Message::VideoReport`] every
+//! [`REPORT_INTERVAL`] with the running count of video fragments (and bytes) it
+//! has received from us. Comparing the change in that count with what we sent
+//! over the same interval gives the delivery rate and achieved throughput. When
+//! fragments go missing the sender steps down a quality ladder (fewer frames per
+//! second, then half resolution) and climbs back up once the path stays clean.
+//!
+//! [`Message::VideoReport`]: super::Message::VideoReport
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How often the receiver reports what it got
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Step down when more than this fraction of fragments is lost
+const LOSS_BACKOFF: f64 = 0.10;
+
+/// An interval with at most this much loss counts as clean
+const LOSS_CLEAN: f64 = 0.02;
+
+/// Clean intervals needed before stepping back up
+const CLEAN_REPORTS_TO_STEP_UP: u32 = 3;
+
+/// Sending with no report for this long means the reports are being lost too
+const REPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// One rung of the quality ladder
+struct Level {
+    /// Fraction of the configured frame rate
+    rate: f64,
+    /// Resolution divisor applied before sending
+    scale: u8,
+}
+
+const LEVELS: [Level; 6] = [
+    Level {
+        rate: 1.0,
+        scale: 1,
+    },
+    Level {
+        rate: 0.75,
+        scale: 1,
+    },
+    Level {
+        rate: 0.5,
+        scale: 1,
+    },
+    Level {
+        rate: 0.5,
+        scale: 2,
+    },
+    Level {
+        rate: 0.25,
+        scale: 2,
+    },
+    Level {
+        rate: 0.125,
+        scale: 2,
+    },
+];
+
+/// A receiver report, with our own sent count when it arrived
+#[derive(Debug, Clone, Copy)]
+struct Report {
+    at: Instant,
+    received: u32,
+    received_bytes: u32,
+    sent: u32,
+}
+
+/// Paces and sizes the video we send to the peer we're calling
+#[derive(Debug)]
+pub struct VideoSender {
+    /// Call peer the state belongs to
+    peer: Option<String>,
+    /// Index into [`LEVELS`]
+    level: usize,
+    /// Running fragment count sent to the peer
+    sent: u32,
+    last_sent: Option<Instant>,
+    last_report: Option<Report>,
+    /// When we last heard a report, or started sending without one
+    last_feedback: Option<Instant>,
+    clean: u32,
+    /// Delivered bytes per second over the last report interval
+    throughput: Option<f64>,
+    /// Fragment loss over the last report interval (0.0 - 1.0)
+    loss: Option<f64>,
+}
+
+impl VideoSender {
+    pub fn new() -> Self {
+        Self {
+            peer: None,
+            level: 0,
+            sent: 0,
+            last_sent: None,
+            last_report: None,
+            last_feedback: None,
+            clean: 0,
+            throughput: None,
+            loss: None,
+        }
+    }
+
+    /// Start over at full quality when the call peer changes
+    pub fn begin(&mut self, peer: &str) {
+        if self.peer.as_deref() != Some(peer) {
+            *self = Self::new();
+            self.peer = Some(peer.to_string());
+        }
+    }
+
+    /// Forget the call once it's over, so the next one starts fresh
+    pub fn end(&mut self) {
+        if self.peer.is_some() {
+            *self = Self::new();
+        }
+    }
+
+    /// Whether a frame should go out now, given the configured frame delay
+    pub fn should_send(&mut self, frame_delay: Duration) -> bool {
+        let now = Instant::now();
+        if self
+            .last_feedback
+            .is_some_and(|t| now.duration_since(t) >= REPORT_TIMEOUT)
+        {
+            self.step_down();
+            self.last_feedback = Some(now);
+        }
+        let interval = frame_delay.div_f64(LEVELS[self.level].rate);
+        // Allow a little slack so the main loop's own jitter doesn't skip frames
+        self.last_sent
+            .is_none_or(|t| now.duration_since(t) + frame_delay / 4 >= interval)
+    }
+
+    /// Resolution divisor for the next frame
+    pub fn scale(&self) -> u8 {
+        LEVELS[self.level].scale
+    }
+
+    /// Note a frame that went out as `fragments` datagrams
+    pub fn record_sent(&mut self, fragments: u32) {
+        let now = Instant::now();
+        self.sent = self.sent.wrapping_add(fragments);
+        self.last_sent = Some(now);
+        self.last_feedback.get_or_insert(now);
+    }
+
+    /// Handle a receiver report (running counts, wrapping)
+    pub fn on_report(&mut self, received: u32, received_bytes: u32) {
+        let now = Instant::now();
+        let report = Report {
+            at: now,
+            received,
+            received_bytes,
+            sent: self.sent,
+        };
+        self.last_feedback = Some(now);
+        let Some(prev) = self.last_report.replace(report) else {
+            return;
+        };
+
+        let sent = report.sent.wrapping_sub(prev.sent);
+        if sent == 0 {
+            return;
+        }
+        let received = report.received.wrapping_sub(prev.received);
+        let bytes = report.received_bytes.wrapping_sub(prev.received_bytes);
+        let elapsed = now.duration_since(prev.at).as_secs_f64();
+        if elapsed > 0.0 {
+            self.throughput = Some(bytes as f64 / elapsed);
+        }
+
+        // Fragments still in flight can make an interval look slightly over 100%
+        let loss = 1.0 - (received as f64 / sent as f64).min(1.0);
+        self.loss = Some(loss);
+        if loss > LOSS_BACKOFF {
+            self.step_down();
+        } else if loss <= LOSS_CLEAN {
+            self.clean += 1;
+            if self.clean >= CLEAN_REPORTS_TO_STEP_UP && self.level > 0 {
+                self.level -= 1;
+                self.clean = 0;
+            }
+        } else {
+            self.clean = 0;
+        }
+    }
+
+    fn step_down(&mut self) {
+        self.level = (self.level + 1).min(LEVELS.len() - 1);
+        self.clean = 0;
+    }
+}
+
+impl Default for VideoSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for VideoSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = &LEVELS[self.level];
+        write!(f, "{:.0}% FPS", level.rate * 100.0)?;
+        if level.scale > 1 {
+            write!(f, ", 1/{} res", level.scale)?;
+        }
+        if let Some(loss) = self.loss {
+            write!(f, ", {:.0}% loss", loss * 100.0)?;
+        }
+        if let Some(throughput) = self.throughput {
+            write!(f, ", {:.1} KB/s delivered", throughput / 1024.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the video we receive from the peer we're calling, for reports
+#[derive(Debug)]
+pub struct VideoReceiver {
+    peer: Option<String>,
+    fragments: u32,
+    bytes: u32,
+    last_report: Instant,
+}
+
+impl VideoReceiver {
+    pub fn new() -> Self {
+        Self {
+            peer: None,
+            fragments: 0,
+            bytes: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Count a fragment from `from`; counting restarts when the sender changes
+    pub fn record(&mut self, from: &str, bytes: usize) {
+        if self.peer.as_deref() != Some(from) {
+            *self = Self::new();
+            self.peer = Some(from.to_string());
+        }
+        self.fragments = self.fragments.wrapping_add(1);
+        self.bytes = self.bytes.wrapping_add(bytes as u32);
+    }
+
+    /// The running (fragments, bytes) to report to `peer`, if a report is due
+    pub fn report_due(&mut self, peer: &str) -> Option<(u32, u32)> {
+        if self.peer.as_deref() != Some(peer) || self.last_report.elapsed() < REPORT_INTERVAL {
+            return None;
+        }
+        self.last_report = Instant::now();
+        Some((self.fragments, self.bytes))
+    }
+}
+
+impl Default for VideoReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backs_off_under_loss() {
+        let mut sender = VideoSender::new();
+        sender.begin("bob");
+        sender.on_report(0, 0);
+
+        // Half the fragments lost
+        sender.record_sent(100);
+        sender.on_report(50, 50 * 1400);
+        assert_eq!(sender.level, 1);
+        sender.record_sent(100);
+        sender.on_report(100, 100 * 1400);
+        sender.record_sent(100);
+        sender.on_report(150, 150 * 1400);
+        assert_eq!(sender.level, 3);
+        assert_eq!(sender.scale(), 2);
+
+        // A clean path climbs back up one rung at a time
+        for n in 1..=CLEAN_REPORTS_TO_STEP_UP {
+            sender.record_sent(100);
+            sender.on_report(150 + n * 100, 0);
+        }
+        assert_eq!(sender.level, 2);
+        assert_eq!(sender.scale(), 1);
+    }
+
+    #[test]
+    fn test_paces_frames_by_level() {
+        let delay = Duration::from_millis(200);
+        let mut sender = VideoSender::new();
+        sender.begin("bob");
+        assert!(sender.should_send(delay));
+        sender.record_sent(1);
+        sender.level = LEVELS.len() - 1;
+        assert!(!sender.should_send(delay));
+
+        // Calling someone else resets to full quality
+        sender.begin("carol");
+        assert_eq!(sender.level, 0);
+        assert!(sender.should_send(delay));
+    }
+
+    #[test]
+    fn test_receiver_counts_per_sender() {
+        let mut receiver = VideoReceiver::new();
+        receiver.record("bob", 1000);
+        receiver.record("bob", 400);
+        receiver.last_report -= REPORT_INTERVAL;
+        assert_eq!(receiver.report_due("carol"), None);
+        assert_eq!(receiver.report_due("bob"), Some((2, 1400)));
+        assert_eq!(receiver.report_due("bob"), None);
+
+        receiver.record("carol", 10);
+        receiver.last_report -= REPORT_INTERVAL;
+        assert_eq!(receiver.report_due("carol"), Some((1, 10)));
+    }
+}
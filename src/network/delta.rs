@@ -0,0 +1,186 @@
+//! Delta coding for video frames.
+//!
+//! A webcam pointed at someone sitting at a desk sends much the same picture
+//! frame after frame. So rather than the pixels themselves, most frames go out
+//! as their XOR against the last keyframe, with the runs of zero bytes (the
+//! pixels that didn't change) squeezed out, before the usual LZ4. Each delta
+//! is against the keyframe rather than the frame before it, so a lost frame
+//! only costs itself; a lost keyframe costs the deltas up to the next one,
+//! which goes out every [`KEYFRAME_INTERVAL`] frames and whenever the frame
+//! size changes.
+
+/// Frames sent per keyframe
+pub const KEYFRAME_INTERVAL: u32 = 30;
+
+/// The frame deltas are taken against
+#[derive(Debug)]
+struct Keyframe {
+    id: u8,
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+}
+
+/// Codes the frames sent to one peer
+#[derive(Debug, Default)]
+pub struct DeltaEncoder {
+    keyframe: Option<Keyframe>,
+    /// Frames sent since the keyframe, counting it
+    since_key: u32,
+}
+
+impl DeltaEncoder {
+    /// Code frame `frame_id`: the keyframe it's a delta against (None if it's
+    /// a keyframe itself) and the bytes to send
+    pub fn encode(
+        &mut self,
+        frame_id: u8,
+        width: u16,
+        height: u16,
+        pixels: &[u8],
+    ) -> (Option<u8>, Vec<u8>) {
+        if let Some(key) = &self.keyframe
+            && (key.width, key.height) == (width, height)
+            && key.pixels.len() == pixels.len()
+            && self.since_key < KEYFRAME_INTERVAL
+        {
+            self.since_key += 1;
+            return (Some(key.id), pack(&xor(&key.pixels, pixels)));
+        }
+        self.keyframe = Some(Keyframe {
+            id: frame_id,
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        });
+        self.since_key = 1;
+        (None, pixels.to_vec())
+    }
+
+    /// Make the next frame a keyframe, as when the last one never went out
+    pub fn force_keyframe(&mut self) {
+        self.keyframe = None;
+    }
+}
+
+/// Decodes the frames from one peer
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    keyframe: Option<Keyframe>,
+}
+
+impl DeltaDecoder {
+    /// The pixels of a delta against keyframe `key_id`, or None if that isn't
+    /// the keyframe we have
+    pub fn apply(&self, key_id: u8, width: u16, height: u16, data: &[u8]) -> Option<Vec<u8>> {
+        let key = self
+            .keyframe
+            .as_ref()
+            .filter(|k| k.id == key_id && (k.width, k.height) == (width, height))?;
+        let diff = unpack(data, key.pixels.len())?;
+        Some(xor(&key.pixels, &diff))
+    }
+
+    /// Keep a keyframe (once its pixels have checked out) for later deltas
+    pub fn set_keyframe(&mut self, id: u8, width: u16, height: u16, pixels: Vec<u8>) {
+        self.keyframe = Some(Keyframe {
+            id,
+            width,
+            height,
+            pixels,
+        });
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+}
+
+/// Squeeze out runs of zeros: each run becomes a zero byte and its length
+/// (1-255); other bytes are left as they are
+fn pack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == 0 {
+            let run = data[i..].iter().take(255).take_while(|&&b| b == 0).count();
+            out.extend([0, run as u8]);
+            i += run;
+        } else {
+            out.push(data[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Undo [`pack`], or None if it doesn't come to `len` bytes
+fn unpack(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        if b == 0 {
+            let run = *bytes.next()? as usize;
+            out.resize(out.len() + run, 0);
+        } else {
+            out.push(b);
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deltas_roundtrip() {
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+        let mut frame: Vec<u8> = (0..=255).cycle().take(4000).collect();
+
+        for frame_id in 0..=KEYFRAME_INTERVAL as u8 + 1 {
+            // Someone moving about in one corner
+            frame[frame_id as usize] = frame_id.wrapping_mul(7);
+            let (key_id, data) = encoder.encode(frame_id, 80, 50, &frame);
+            let pixels = match key_id {
+                None => {
+                    decoder.set_keyframe(frame_id, 80, 50, data.clone());
+                    data
+                }
+                Some(id) => {
+                    assert!(data.len() < 100, "{} bytes", data.len());
+                    decoder.apply(id, 80, 50, &data).unwrap()
+                }
+            };
+            assert_eq!(pixels, frame);
+            let keyframe = frame_id == 0 || frame_id == KEYFRAME_INTERVAL as u8;
+            assert_eq!(key_id.is_none(), keyframe, "frame {}", frame_id);
+        }
+
+        // A new size starts a new keyframe
+        assert_eq!(encoder.encode(40, 40, 25, &frame[..1000]).0, None);
+    }
+
+    #[test]
+    fn test_delta_needs_its_keyframe() {
+        let mut encoder = DeltaEncoder::default();
+        let decoder = DeltaDecoder::default();
+        encoder.encode(1, 4, 2, &[9; 8]);
+        let (key_id, data) = encoder.encode(2, 4, 2, &[9, 9, 0, 9, 9, 9, 9, 1]);
+        assert_eq!(key_id, Some(1));
+        assert_eq!(decoder.apply(1, 4, 2, &data), None);
+
+        let mut decoder = DeltaDecoder::default();
+        decoder.set_keyframe(1, 4, 2, vec![9; 8]);
+        assert_eq!(
+            decoder.apply(1, 4, 2, &data),
+            Some(vec![9, 9, 0, 9, 9, 9, 9, 1])
+        );
+        assert_eq!(decoder.apply(1, 2, 4, &data), None);
+        assert_eq!(unpack(&[0, 200], 8), None);
+    }
+}
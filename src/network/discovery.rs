@@ -4,22 +4,39 @@
 //! 1. Each peer periodically broadcasts an Announce message to all subnet broadcast addresses
 //! 2. When a peer receives an Announce, it replies directly to the sender
 //! 3. Discovered peers are sent to the application via a channel
+//!
+//! IPv6 has no broadcast, so the same Announce is also multicast to the
+//! link-local all-nodes group (`ff02::1`) on every IPv6 interface.
+//!
+//! Alongside broadcast, peers advertise and browse `_wormhole._udp` via mDNS
+//! (see [`super::mdns`]) for networks that filter broadcast traffic.
+//!
+//! The port, announcement interval and broadcast address can be changed with
+//! [`DiscoverySettings`] (from `[network]`) for networks where the defaults are
+//! blocked or too chatty.
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
-/// Discovery broadcast port
-pub const DISCOVERY_PORT: u16 = 7891;
+use super::mdns;
+use super::socket::{interface_index, with_port};
+use crate::watchdog::Heartbeat;
 
-/// How often to send discovery announcements
-pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+/// Default discovery broadcast port
+pub const DEFAULT_DISCOVERY_PORT: u16 = 7891;
+
+/// Default interval between discovery announcements
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
 
 /// How long before a peer is considered stale
 pub const PEER_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// IPv6 link-local all-nodes multicast group
+const ALL_NODES_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
 /// Magic bytes to identify wormhole discovery packets
 const MAGIC: &[u8; 8] = b"ACMSWRMH";
 
@@ -103,6 +120,98 @@ fn get_broadcast_addresses(bind_ip: Ipv4Addr) -> Vec<Ipv4Addr> {
     addrs
 }
 
+/// Get the IPv4 addresses to advertise in mDNS A records
+fn get_local_addresses(bind_ip: Ipv4Addr) -> Vec<Ipv4Addr> {
+    if bind_ip != Ipv4Addr::UNSPECIFIED {
+        return vec![bind_ip];
+    }
+
+    get_if_addrs::get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter(|iface| !iface.is_loopback())
+                .filter_map(|iface| match iface.ip() {
+                    IpAddr::V4(ipv4) => Some(ipv4),
+                    IpAddr::V6(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get the indices of non-loopback interfaces that have an IPv6 address
+fn get_ipv6_interfaces() -> Vec<u32> {
+    let mut indices = Vec::new();
+    if let Ok(interfaces) = get_if_addrs::get_if_addrs() {
+        for iface in interfaces {
+            if iface.is_loopback() || !iface.ip().is_ipv6() {
+                continue;
+            }
+            if let Some(index) = interface_index(&iface.name)
+                && !indices.contains(&index)
+            {
+                indices.push(index);
+            }
+        }
+    }
+    indices
+}
+
+/// Create an IPv6-only socket bound to the discovery port
+fn create_ipv6_socket(port: u16) -> std::io::Result<UdpSocket> {
+    let socket2 = socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket2.set_only_v6(true)?;
+    socket2.set_reuse_address(true)?;
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+    socket2.bind(&addr.into())?;
+    socket2.set_multicast_loop_v6(true)?;
+    socket2.set_nonblocking(true)?;
+    UdpSocket::from_std(socket2.into())
+}
+
+/// Create a socket bound to the mDNS port and joined to the multicast group
+fn create_mdns_socket(bind_ip: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    let socket2 = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+
+    // Share the port with any system responder (Avahi, mDNSResponder)
+    socket2.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket2.set_reuse_port(true)?;
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), mdns::MDNS_PORT);
+    socket2.bind(&addr.into())?;
+    socket2.join_multicast_v4(&mdns::MDNS_ADDR, &bind_ip)?;
+    if bind_ip != Ipv4Addr::UNSPECIFIED {
+        socket2.set_multicast_if_v4(&bind_ip)?;
+    }
+    // Loop back so peers on the same machine see each other
+    socket2.set_multicast_loop_v4(true)?;
+    socket2.set_multicast_ttl_v4(255)?;
+    socket2.set_nonblocking(true)?;
+
+    UdpSocket::from_std(socket2.into())
+}
+
+/// Receive on an optional socket, never completing if it is absent
+async fn recv_optional(
+    socket: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Extract broadcast address from interface info
 fn get_broadcast_from_interface(iface: &get_if_addrs::Interface) -> Option<Ipv4Addr> {
     // get_if_addrs provides broadcast via the IfAddr enum
@@ -112,6 +221,27 @@ fn get_broadcast_from_interface(iface: &get_if_addrs::Interface) -> Option<Ipv4A
     }
 }
 
+/// How discovery announces itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoverySettings {
+    /// Port announcements are sent to and received on (every node must agree)
+    pub port: u16,
+    /// How often to announce
+    pub interval: Duration,
+    /// Broadcast here instead of on each interface's subnet
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_DISCOVERY_PORT,
+            interval: DEFAULT_ANNOUNCE_INTERVAL,
+            broadcast: None,
+        }
+    }
+}
+
 /// Discovered peer information
 #[derive(Debug, Clone)]
 pub struct DiscoveredPeer {
@@ -122,9 +252,21 @@ pub struct DiscoveredPeer {
 /// Peer discovery service
 pub struct Discovery {
     socket: Arc<UdpSocket>,
-    our_name: String,
+    /// Our name, which `/nick` can change while running
+    our_name: std::sync::RwLock<String>,
     our_port: u16,
+    settings: DiscoverySettings,
     broadcast_addrs: Vec<Ipv4Addr>,
+    /// IPv6 discovery socket (None if IPv6 is unavailable)
+    socket_v6: Option<Arc<UdpSocket>>,
+    /// Interfaces to multicast IPv6 announcements on
+    ipv6_interfaces: Vec<u32>,
+    /// mDNS socket (None if the mDNS port couldn't be bound)
+    mdns_socket: Option<Arc<UdpSocket>>,
+    /// Addresses advertised in our mDNS A records
+    local_addrs: Vec<Ipv4Addr>,
+    /// Wakes the discovery task for an out-of-schedule announcement
+    announce_now: tokio::sync::Notify,
 }
 
 impl Discovery {
@@ -137,9 +279,10 @@ impl Discovery {
         name: String,
         listen_port: u16,
         bind_ip: Ipv4Addr,
+        settings: DiscoverySettings,
     ) -> Result<Self, super::NetworkError> {
         // Create a simple UDP socket bound to the discovery port
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DISCOVERY_PORT);
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), settings.port);
 
         // Use socket2 for SO_REUSEADDR (allows binding even if port is in TIME_WAIT)
         let socket2 = socket2::Socket::new(
@@ -170,40 +313,100 @@ impl Discovery {
             super::NetworkError::Bind(format!("Tokio socket conversion failed: {}", e))
         })?;
 
-        // Get broadcast addresses based on bind_ip
-        let broadcast_addrs = get_broadcast_addresses(bind_ip);
+        // Get broadcast addresses based on bind_ip, unless one is configured
+        let broadcast_addrs = match settings.broadcast {
+            Some(broadcast) => vec![broadcast, Ipv4Addr::LOCALHOST],
+            None => get_broadcast_addresses(bind_ip),
+        };
+
+        // IPv6 discovery only makes sense when not pinned to an IPv4 interface
+        let socket_v6 = if bind_ip == Ipv4Addr::UNSPECIFIED {
+            create_ipv6_socket(settings.port).ok().map(Arc::new)
+        } else {
+            None
+        };
+
+        // mDNS is best-effort: broadcast discovery still works without it
+        let mdns_socket = match create_mdns_socket(bind_ip) {
+            Ok(s) => Some(Arc::new(s)),
+            Err(e) => {
+                eprintln!("mDNS unavailable: {}", e);
+                None
+            }
+        };
 
         Ok(Self {
             socket: Arc::new(socket),
-            our_name: name,
+            our_name: std::sync::RwLock::new(name),
             our_port: listen_port,
+            settings,
             broadcast_addrs,
+            socket_v6,
+            ipv6_interfaces: get_ipv6_interfaces(),
+            mdns_socket,
+            local_addrs: get_local_addresses(bind_ip),
+            announce_now: tokio::sync::Notify::new(),
         })
     }
 
+    /// Ask the discovery task to announce (and query) right away, e.g. after a resume
+    pub fn request_announce(&self) {
+        self.announce_now.notify_one();
+    }
+
     /// Send an announcement to all broadcast addresses
     pub async fn announce(&self) {
         let msg = DiscoveryMessage {
-            name: self.our_name.clone(),
+            name: self.name(),
             port: self.our_port,
         };
         let data = msg.to_bytes();
 
         // Send to all known broadcast addresses (ignore errors)
         for addr in &self.broadcast_addrs {
-            let dest = SocketAddr::new(IpAddr::V4(*addr), DISCOVERY_PORT);
+            let dest = SocketAddr::new(IpAddr::V4(*addr), self.settings.port);
             let _ = self.socket.send_to(&data, dest).await;
         }
+
+        // Multicast to all IPv6 nodes on each link, plus loopback for local peers
+        if let Some(socket) = &self.socket_v6 {
+            for &index in &self.ipv6_interfaces {
+                let dest = SocketAddrV6::new(ALL_NODES_V6, self.settings.port, 0, index);
+                let _ = socket.send_to(&data, SocketAddr::V6(dest)).await;
+            }
+            let dest = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), self.settings.port);
+            let _ = socket.send_to(&data, dest).await;
+        }
+
+        // Browse for other peers and advertise ourselves via mDNS
+        if let Some(socket) = &self.mdns_socket {
+            let _ = socket
+                .send_to(&mdns::build_query(), mdns::multicast_addr())
+                .await;
+            self.announce_mdns().await;
+        }
+    }
+
+    /// Multicast our mDNS service announcement
+    pub async fn announce_mdns(&self) {
+        if let Some(socket) = &self.mdns_socket {
+            let data = mdns::build_announcement(&self.name(), self.our_port, &self.local_addrs);
+            let _ = socket.send_to(&data, mdns::multicast_addr()).await;
+        }
     }
 
     /// Send an announcement directly to a specific address (for unicast reply)
     pub async fn announce_to(&self, target: SocketAddr) {
         let msg = DiscoveryMessage {
-            name: self.our_name.clone(),
+            name: self.name(),
             port: self.our_port,
         };
         let data = msg.to_bytes();
-        let _ = self.socket.send_to(&data, target).await;
+        let socket = match (&self.socket_v6, target) {
+            (Some(v6), SocketAddr::V6(_)) => v6,
+            _ => &self.socket,
+        };
+        let _ = socket.send_to(&data, target).await;
     }
 
     /// Get the socket for use in select!
@@ -211,26 +414,89 @@ impl Discovery {
         Arc::clone(&self.socket)
     }
 
+    /// Get the IPv6 discovery socket, if IPv6 is available
+    pub fn socket_v6(&self) -> Option<Arc<UdpSocket>> {
+        self.socket_v6.clone()
+    }
+
+    /// Get the mDNS socket, if mDNS is available
+    pub fn mdns_socket(&self) -> Option<Arc<UdpSocket>> {
+        self.mdns_socket.clone()
+    }
+
     /// Get our name
-    pub fn name(&self) -> &str {
-        &self.our_name
+    pub fn name(&self) -> String {
+        self.our_name.read().unwrap().clone()
+    }
+
+    /// Whether an announcement is our own echo rather than another node that
+    /// happens to share our name (which we need to see to sort out the clash)
+    fn is_own_announcement(&self, name: &str, port: u16, ip: IpAddr) -> bool {
+        if name != self.name() || port != self.our_port {
+            return false;
+        }
+        match ip {
+            IpAddr::V4(v4) => v4.is_loopback() || self.local_addrs.contains(&v4),
+            // We don't track our IPv6 addresses; assume a same-name, same-port echo is ours
+            IpAddr::V6(_) => true,
+        }
     }
+
+    /// Announce under a new name from now on
+    pub fn set_name(&self, name: &str) {
+        *self.our_name.write().unwrap() = name.to_string();
+    }
+}
+
+/// Handle a discovery announcement received on any discovery socket
+async fn handle_announcement(
+    discovery: &Discovery,
+    peer_tx: &mpsc::Sender<DiscoveredPeer>,
+    data: &[u8],
+    addr: SocketAddr,
+) {
+    let Some(msg) = DiscoveryMessage::from_bytes(data) else {
+        return;
+    };
+
+    // Ignore our own announcements
+    if discovery.is_own_announcement(&msg.name, msg.port, addr.ip()) {
+        return;
+    }
+
+    // Build the peer's actual address (their IP, their app port).
+    // with_port keeps the scope of link-local IPv6 senders.
+    let peer = DiscoveredPeer {
+        name: msg.name,
+        addr: with_port(addr, msg.port),
+    };
+    let _ = peer_tx.send(peer).await;
+
+    // Reply directly to the sender's discovery port so they see us too
+    discovery
+        .announce_to(with_port(addr, discovery.settings.port))
+        .await;
 }
 
-/// Run the discovery service, returning discovered peers via channel
+/// Run the discovery service, returning discovered peers via channel.
+/// `heartbeat` is marked busy while it handles each announcement, for the
+/// watchdog.
 pub async fn run_discovery(
     discovery: Arc<Discovery>,
     peer_tx: mpsc::Sender<DiscoveredPeer>,
     mut shutdown: tokio::sync::watch::Receiver<bool>,
+    heartbeat: Heartbeat,
 ) {
     let socket = discovery.socket();
-    let mut announce_interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+    let socket_v6 = discovery.socket_v6();
+    let mdns_socket = discovery.mdns_socket();
+    let mut announce_interval = tokio::time::interval(discovery.settings.interval);
     // Skip the first immediate tick so we control initial timing
     announce_interval.tick().await;
 
-    let our_name = discovery.name().to_string();
-
     let mut buf = [0u8; 256];
+    let mut buf_v6 = [0u8; 256];
+    let mut mdns_buf = [0u8; 1500];
 
     // Send initial announcement after a tiny delay
     let mut initial_announce_done = false;
@@ -238,48 +504,83 @@ pub async fn run_discovery(
     tokio::pin!(initial_announce_delay);
 
     loop {
+        heartbeat.idle();
         tokio::select! {
             // Initial announcement after a tiny delay (ensures receive loop is active)
             _ = &mut initial_announce_delay, if !initial_announce_done => {
+                heartbeat.busy();
                 initial_announce_done = true;
                 let _ = discovery.announce().await;
             }
 
             // Periodic announcements
             _ = announce_interval.tick() => {
+                heartbeat.busy();
+                let _ = discovery.announce().await;
+            }
+
+            // Announcements requested by the app
+            _ = discovery.announce_now.notified() => {
+                heartbeat.busy();
                 let _ = discovery.announce().await;
+                announce_interval.reset();
             }
 
             // Receive discovery messages
             result = socket.recv_from(&mut buf) => {
+                heartbeat.busy();
                 match result {
                     Ok((len, addr)) => {
                         if len == 0 {
                             tokio::time::sleep(Duration::from_millis(10)).await;
                             continue;
                         }
+                        handle_announcement(&discovery, &peer_tx, &buf[..len], addr).await;
+                    }
+                    Err(_) => {
+                        // Brief pause on receive errors to avoid busy loop
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+
+            // Receive IPv6 discovery messages
+            result = recv_optional(socket_v6.as_deref(), &mut buf_v6) => {
+                heartbeat.busy();
+                match result {
+                    Ok((len, addr)) => {
+                        handle_announcement(&discovery, &peer_tx, &buf_v6[..len], addr).await;
+                    }
+                    Err(_) => {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+
+            // Receive mDNS queries and announcements
+            result = recv_optional(mdns_socket.as_deref(), &mut mdns_buf) => {
+                heartbeat.busy();
+                match result {
+                    Ok((len, addr)) => {
+                        let packet = &mdns_buf[..len];
+                        if mdns::is_service_query(packet) {
+                            discovery.announce_mdns().await;
+                            continue;
+                        }
 
-                        if let Some(msg) = DiscoveryMessage::from_bytes(&buf[..len]) {
-                            // Ignore our own announcements
-                            if msg.name == our_name {
+                        for found in mdns::parse_announcement(packet) {
+                            let ip = found.ip.map(IpAddr::V4).unwrap_or(addr.ip());
+                            if discovery.is_own_announcement(&found.name, found.port, ip) {
                                 continue;
                             }
-
-                            // Build the peer's actual address (their IP, their app port)
-                            let peer_addr = SocketAddr::new(addr.ip(), msg.port);
                             let peer = DiscoveredPeer {
-                                name: msg.name,
-                                addr: peer_addr,
+                                name: found.name,
+                                addr: SocketAddr::new(ip, found.port),
                             };
                             let _ = peer_tx.send(peer).await;
-
-                            // Reply directly to the sender's discovery port so they see us too
-                            let reply_addr = SocketAddr::new(addr.ip(), DISCOVERY_PORT);
-                            discovery.announce_to(reply_addr).await;
                         }
                     }
                     Err(_) => {
-                        // Brief pause on receive errors to avoid busy loop
                         tokio::time::sleep(Duration::from_millis(100)).await;
                     }
                 }
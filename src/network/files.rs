@@ -0,0 +1,1316 @@
+//! Peer-to-peer file transfers (`/send`, `/accept`), resumable across restarts.
+//!
+//! The sender offers a file with a `FileOffer`. Once the receiver accepts, it
+//! pulls the file in [`CHUNK_SIZE`] pieces by sending a `FileRequest` for a
+//! window of the chunks it is still missing, re-requesting any that don't
+//! arrive. Because the receiver drives, resuming is the same as carrying on:
+//! both sides keep their half of every transfer (which chunks have arrived,
+//! which file is being served) in a state file in the files directory, and
+//! chunks are written straight into a `.part` file as they arrive. After either
+//! side restarts, the receiver asks for the missing chunks as soon as the peer
+//! is back online.
+//!
+//! Chunks travel LZ4-compressed with a BLAKE3 hash of their contents; one that
+//! doesn't decompress or match is left missing and asked for again. The offer
+//! carries the hash of the whole file, which the finished `.part` file must
+//! match before it's moved into place.
+//!
+//! Files offered for a Tunes sync are paced to the `sync_rate` limit, and the
+//! receiver takes them without asking: it said which ones it wanted, and
+//! [`FileTransfers::expect`]ed them into the Tunes directory.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+use super::blake3::{self, HASH_LEN, short_hex};
+
+/// File bytes per chunk (one chunk per datagram, clear of fragmentation)
+pub const CHUNK_SIZE: usize = 1024;
+
+/// Largest file we'll offer or accept
+pub const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// Chunks asked for in one request
+const WINDOW: usize = 32;
+
+/// Chunks that haven't arrived after this long are asked for again
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Unanswered offers are repeated this often (offers aren't acknowledged)
+const OFFER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often progress is written to the state file while chunks arrive
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Transfer state, kept in the files directory
+const STATE_FILE: &str = ".wormhole-transfers.json";
+
+/// Which chunks of a file have arrived
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChunkSet {
+    total: u32,
+    bits: Vec<u64>,
+}
+
+impl ChunkSet {
+    fn new(total: u32) -> Self {
+        Self {
+            total,
+            bits: vec![0; (total as usize).div_ceil(64)],
+        }
+    }
+
+    fn contains(&self, chunk: u32) -> bool {
+        self.bits
+            .get(chunk as usize / 64)
+            .is_some_and(|word| word & (1u64 << (chunk % 64)) != 0)
+    }
+
+    /// Mark a chunk as arrived. False if it already had, or is out of range.
+    fn insert(&mut self, chunk: u32) -> bool {
+        if chunk >= self.total || self.contains(chunk) {
+            return false;
+        }
+        self.bits[chunk as usize / 64] |= 1u64 << (chunk % 64);
+        true
+    }
+
+    fn count(&self) -> u32 {
+        self.bits.iter().map(|word| word.count_ones()).sum()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count() == self.total
+    }
+
+    fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.total).filter(|&chunk| !self.contains(chunk))
+    }
+}
+
+/// Number of chunks in a file of `size` bytes
+fn chunk_count(size: u64) -> u32 {
+    size.div_ceil(CHUNK_SIZE as u64) as u32
+}
+
+/// Length of chunk `index` in a file of `size` bytes
+fn chunk_len(size: u64, index: u32) -> usize {
+    let start = index as u64 * CHUNK_SIZE as u64;
+    size.saturating_sub(start).min(CHUNK_SIZE as u64) as usize
+}
+
+/// An offer we haven't accepted yet
+#[derive(Debug, Clone)]
+struct Offer {
+    id: u64,
+    peer: String,
+    name: String,
+    size: u64,
+    hash: [u8; HASH_LEN],
+}
+
+/// A file we're serving to a peer
+#[derive(Debug, Serialize, Deserialize)]
+struct Outgoing {
+    id: u64,
+    peer: String,
+    path: PathBuf,
+    name: String,
+    size: u64,
+    hash: [u8; HASH_LEN],
+    /// Whether the peer has asked for any of it yet
+    accepted: bool,
+    /// Chunks sent so far, retransmissions included
+    served: u32,
+    /// Paced to the rate limit (a Tunes sync)
+    #[serde(default)]
+    limited: bool,
+    #[serde(skip)]
+    offered_at: Option<Instant>,
+}
+
+/// A file we're receiving from a peer
+#[derive(Debug, Serialize, Deserialize)]
+struct Incoming {
+    id: u64,
+    peer: String,
+    name: String,
+    size: u64,
+    /// What the whole file must hash to
+    hash: [u8; HASH_LEN],
+    have: ChunkSet,
+    /// Where the finished file goes, if not the files directory
+    #[serde(default)]
+    dest: Option<PathBuf>,
+    /// Chunks that arrived damaged and were asked for again
+    #[serde(skip)]
+    corrupt: u32,
+    #[serde(skip)]
+    part: Option<File>,
+    /// Chunks asked for in the latest request that haven't arrived
+    #[serde(skip)]
+    requested: Vec<u32>,
+    #[serde(skip)]
+    requested_at: Option<Instant>,
+    /// The furthest chunk asked for so far (chunks are asked for in order,
+    /// so anything up to it being asked for again is a retransmission)
+    #[serde(skip)]
+    furthest_requested: Option<u32>,
+}
+
+/// An offer to take without asking when it comes
+#[derive(Debug, Clone)]
+struct Expected {
+    peer: String,
+    hash: [u8; HASH_LEN],
+    dest: PathBuf,
+}
+
+/// Paces rate-limited transfers: chunks a peer asks for beyond the allowance
+/// aren't sent, and it asks for them again later
+#[derive(Debug)]
+struct Throttle {
+    /// Bytes per second (0 = unlimited)
+    rate: u64,
+    allowance: f64,
+    at: Instant,
+}
+
+impl Throttle {
+    /// Starting with a second's worth
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            allowance: Self::burst(rate),
+            at: Instant::now(),
+        }
+    }
+
+    /// Most that can be saved up: a second's worth, and at least a chunk
+    fn burst(rate: u64) -> f64 {
+        rate.max(CHUNK_SIZE as u64) as f64
+    }
+
+    /// How many of `wanted` chunks may be sent now
+    fn take(&mut self, wanted: usize) -> usize {
+        if self.rate == 0 {
+            return wanted;
+        }
+        let now = Instant::now();
+        let burst = Self::burst(self.rate);
+        let earned = now.duration_since(self.at).as_secs_f64() * self.rate as f64;
+        self.allowance = (self.allowance + earned).min(burst);
+        self.at = now;
+        let count = ((self.allowance / CHUNK_SIZE as f64) as usize).min(wanted);
+        self.allowance -= (count * CHUNK_SIZE) as f64;
+        count
+    }
+}
+
+/// Contents of the state file
+#[derive(Default, Deserialize)]
+struct SavedTransfers {
+    outgoing: Vec<Outgoing>,
+    incoming: Vec<Incoming>,
+}
+
+/// Something for the chat to report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferEvent {
+    Offered {
+        peer: String,
+        name: String,
+        size: u64,
+        number: usize,
+    },
+    Received {
+        peer: String,
+        path: PathBuf,
+    },
+    Delivered {
+        peer: String,
+        name: String,
+    },
+    Cancelled {
+        peer: String,
+        name: String,
+    },
+    /// The first damaged chunk of a transfer (it's asked for again)
+    Corrupted {
+        peer: String,
+        name: String,
+    },
+    Failed {
+        peer: String,
+        name: String,
+        error: String,
+    },
+}
+
+impl fmt::Display for TransferEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferEvent::Offered {
+                peer,
+                name,
+                size,
+                number,
+            } => write!(
+                f,
+                "{} offers {} ({}) - /accept {} to receive it",
+                peer,
+                name,
+                format_size(*size),
+                number
+            ),
+            TransferEvent::Received { peer, path } => {
+                write!(f, "Received {} from {}", path.display(), peer)
+            }
+            TransferEvent::Delivered { peer, name } => write!(f, "{} received {}", peer, name),
+            TransferEvent::Cancelled { peer, name } => {
+                write!(f, "Transfer of {} with {} was cancelled", name, peer)
+            }
+            TransferEvent::Corrupted { peer, name } => write!(
+                f,
+                "Part of {} from {} arrived corrupted - asking for it again",
+                name, peer
+            ),
+            TransferEvent::Failed { peer, name, error } => {
+                write!(f, "Transfer of {} with {} failed: {}", name, peer, error)
+            }
+        }
+    }
+}
+
+/// Errors from starting, accepting or cancelling transfers
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    NotAFile(PathBuf),
+    TooLarge(u64),
+    NoSuchTransfer,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Io(e) => write!(f, "{}", e),
+            FileError::NotAFile(path) => write!(f, "{} is not a file", path.display()),
+            FileError::TooLarge(size) => write!(
+                f,
+                "{} is over the {} limit",
+                format_size(*size),
+                format_size(MAX_FILE_SIZE)
+            ),
+            FileError::NoSuchTransfer => write!(f, "no such transfer"),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        FileError::Io(e)
+    }
+}
+
+/// Every file transfer in progress, with its state file
+pub struct FileTransfers {
+    dir: PathBuf,
+    /// Our name, as sent in messages
+    name: String,
+    offers: Vec<Offer>,
+    incoming: Vec<Incoming>,
+    outgoing: Vec<Outgoing>,
+    /// Messages to send, by peer name
+    outbox: Vec<(String, Message)>,
+    events: Vec<TransferEvent>,
+    /// Chunks asked for again since the last call, by peer name
+    retransmissions: Vec<(String, u32)>,
+    expected: Vec<Expected>,
+    throttle: Throttle,
+    /// Progress made since the state file was last written
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl FileTransfers {
+    /// Use `dir` for received files, picking up any transfers saved there
+    pub fn open(dir: &Path, name: String) -> Result<Self, FileError> {
+        fs::create_dir_all(dir)?;
+        let saved = match fs::read_to_string(dir.join(STATE_FILE)) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable {}: {}", STATE_FILE, e);
+                SavedTransfers::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SavedTransfers::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            name,
+            offers: Vec::new(),
+            incoming: saved.incoming,
+            outgoing: saved.outgoing,
+            outbox: Vec::new(),
+            events: Vec::new(),
+            retransmissions: Vec::new(),
+            expected: Vec::new(),
+            throttle: Throttle::new(0),
+            dirty: false,
+            last_saved: Instant::now(),
+        })
+    }
+
+    /// Use a new name in everything we send from now on
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Transfers picked up from the state file (or otherwise under way)
+    pub fn active(&self) -> usize {
+        self.incoming.len() + self.outgoing.len()
+    }
+
+    /// Pace rate-limited transfers to `bytes_per_sec` (0 = unlimited)
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.throttle = Throttle::new(bytes_per_sec);
+    }
+
+    /// Offer the file at `path` to `peer`. Returns the name it's offered under.
+    pub fn send(&mut self, peer: &str, path: &Path) -> Result<String, FileError> {
+        self.offer(peer, path, false)
+    }
+
+    /// Offer a file paced to the rate limit
+    pub fn send_limited(&mut self, peer: &str, path: &Path) -> Result<String, FileError> {
+        self.offer(peer, path, true)
+    }
+
+    /// Take `peer`'s offer of the file hashing to `hash` without asking,
+    /// saving it in `dest`
+    pub fn expect(&mut self, peer: &str, hash: [u8; HASH_LEN], dest: &Path) {
+        if let Some(i) = self
+            .offers
+            .iter()
+            .position(|o| o.peer == peer && o.hash == hash)
+        {
+            let offer = self.offers.remove(i);
+            self.take_offer(offer, Some(dest.to_path_buf()));
+            return;
+        }
+        if self
+            .incoming
+            .iter()
+            .any(|t| t.peer == peer && t.hash == hash)
+        {
+            return;
+        }
+        self.expected.push(Expected {
+            peer: peer.to_string(),
+            hash,
+            dest: dest.to_path_buf(),
+        });
+    }
+
+    fn offer(&mut self, peer: &str, path: &Path, limited: bool) -> Result<String, FileError> {
+        let meta = fs::metadata(path)?;
+        if !meta.is_file() {
+            return Err(FileError::NotAFile(path.to_path_buf()));
+        }
+        if meta.len() > MAX_FILE_SIZE {
+            return Err(FileError::TooLarge(meta.len()));
+        }
+        // Saved absolute, so a resume after restarting elsewhere still finds it
+        let path = fs::canonicalize(path)?;
+        let name = safe_file_name(&path.file_name().unwrap_or_default().to_string_lossy());
+        let hash = blake3::hash_file(&path)?;
+        self.outgoing.push(Outgoing {
+            id: rand::random(),
+            peer: peer.to_string(),
+            path,
+            name: name.clone(),
+            size: meta.len(),
+            hash,
+            accepted: false,
+            served: 0,
+            limited,
+            offered_at: None,
+        });
+        self.save();
+        Ok(name)
+    }
+
+    /// Accept an offer by its number (the latest if None).
+    /// Returns the sender and file name.
+    pub fn accept(&mut self, number: Option<usize>) -> Result<(String, String), FileError> {
+        let index = match number {
+            Some(n) => n.checked_sub(1).filter(|&i| i < self.offers.len()),
+            None => self.offers.len().checked_sub(1),
+        }
+        .ok_or(FileError::NoSuchTransfer)?;
+        let offer = self.offers.remove(index);
+        self.start_incoming(&offer, None)?;
+        Ok((offer.peer, offer.name))
+    }
+
+    /// Accept an expected offer, or report why it couldn't be
+    fn take_offer(&mut self, offer: Offer, dest: Option<PathBuf>) {
+        if let Err(e) = self.start_incoming(&offer, dest) {
+            self.events.push(TransferEvent::Failed {
+                peer: offer.peer,
+                name: offer.name,
+                error: e.to_string(),
+            });
+        }
+    }
+
+    fn start_incoming(&mut self, offer: &Offer, dest: Option<PathBuf>) -> Result<(), FileError> {
+        let part = self.part_path(offer.id, &offer.name);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&part)?;
+        file.set_len(offer.size)?;
+        self.incoming.push(Incoming {
+            id: offer.id,
+            peer: offer.peer.clone(),
+            name: offer.name.clone(),
+            size: offer.size,
+            hash: offer.hash,
+            have: ChunkSet::new(chunk_count(offer.size)),
+            dest,
+            corrupt: 0,
+            part: Some(file),
+            requested: Vec::new(),
+            requested_at: None,
+            furthest_requested: None,
+        });
+        self.save();
+        Ok(())
+    }
+
+    /// Cancel an offer or transfer by its number in [`Self::list`].
+    /// Returns a description of what was cancelled.
+    pub fn cancel(&mut self, number: usize) -> Result<String, FileError> {
+        let mut index = number.checked_sub(1).ok_or(FileError::NoSuchTransfer)?;
+        let (id, peer, description) = if index < self.offers.len() {
+            let offer = self.offers.remove(index);
+            let description = format!("Declined {} from {}", offer.name, offer.peer);
+            (offer.id, offer.peer, description)
+        } else {
+            index -= self.offers.len();
+            if index < self.incoming.len() {
+                let transfer = self.incoming.remove(index);
+                let _ = fs::remove_file(self.part_path(transfer.id, &transfer.name));
+                let description =
+                    format!("Stopped receiving {} from {}", transfer.name, transfer.peer);
+                (transfer.id, transfer.peer, description)
+            } else {
+                index -= self.incoming.len();
+                if index >= self.outgoing.len() {
+                    return Err(FileError::NoSuchTransfer);
+                }
+                let transfer = self.outgoing.remove(index);
+                let description = format!("Stopped sending {} to {}", transfer.name, transfer.peer);
+                (transfer.id, transfer.peer, description)
+            }
+        };
+        let msg = Message::FileCancel {
+            from: self.name.clone(),
+            id,
+        };
+        self.outbox.push((peer, msg));
+        self.save();
+        Ok(description)
+    }
+
+    /// One line per offer and transfer, numbered for `/accept` and cancelling
+    pub fn list(&self) -> Vec<String> {
+        let offers = self.offers.iter().map(|offer| {
+            format!(
+                "offer from {}: {} ({})",
+                offer.peer,
+                offer.name,
+                format_size(offer.size)
+            )
+        });
+        let incoming = self.incoming.iter().map(|transfer| {
+            let corrupt = match transfer.corrupt {
+                0 => String::new(),
+                n => format!(" ({} corrupted chunks asked for again)", n),
+            };
+            format!(
+                "receiving {} from {}: {}%{}",
+                transfer.name,
+                transfer.peer,
+                percent(transfer.have.count(), transfer.have.total),
+                corrupt
+            )
+        });
+        let outgoing = self.outgoing.iter().map(|transfer| {
+            if transfer.accepted {
+                format!(
+                    "sending {} to {}: {} chunks of {} sent",
+                    transfer.name,
+                    transfer.peer,
+                    transfer.served,
+                    chunk_count(transfer.size)
+                )
+            } else {
+                format!(
+                    "sending {} to {}: waiting for them to accept",
+                    transfer.name, transfer.peer
+                )
+            }
+        });
+        offers
+            .chain(incoming)
+            .chain(outgoing)
+            .enumerate()
+            .map(|(i, line)| format!("{}. {}", i + 1, line))
+            .collect()
+    }
+
+    /// Handle a `File*` message from a peer
+    pub fn handle(&mut self, msg: Message) {
+        match msg {
+            Message::FileOffer {
+                from,
+                id,
+                name,
+                size,
+                hash,
+            } => self.on_offer(from, id, &name, size, hash),
+            Message::FileRequest { from, id, chunks } => self.on_request(&from, id, &chunks),
+            Message::FileChunk {
+                from,
+                id,
+                index,
+                data,
+                hash,
+            } => self.on_chunk(&from, id, index, &data, &hash),
+            Message::FileDone { from, id } => {
+                if let Some(i) = self
+                    .outgoing
+                    .iter()
+                    .position(|t| t.id == id && t.peer == from)
+                {
+                    let transfer = self.outgoing.remove(i);
+                    self.events.push(TransferEvent::Delivered {
+                        peer: from,
+                        name: transfer.name,
+                    });
+                    self.save();
+                }
+            }
+            Message::FileCancel { from, id } => self.on_cancel(from, id),
+            _ => {}
+        }
+    }
+
+    fn on_offer(&mut self, peer: String, id: u64, name: &str, size: u64, hash: [u8; HASH_LEN]) {
+        // Offers are repeated until answered
+        if self.offers.iter().any(|o| o.id == id) || self.incoming.iter().any(|t| t.id == id) {
+            return;
+        }
+        let name = safe_file_name(name);
+        if size > MAX_FILE_SIZE {
+            let msg = Message::FileCancel {
+                from: self.name.clone(),
+                id,
+            };
+            self.outbox.push((peer.clone(), msg));
+            self.events.push(TransferEvent::Failed {
+                peer,
+                name,
+                error: FileError::TooLarge(size).to_string(),
+            });
+            return;
+        }
+        let offer = Offer {
+            id,
+            peer: peer.clone(),
+            name: name.clone(),
+            size,
+            hash,
+        };
+        if let Some(i) = self
+            .expected
+            .iter()
+            .position(|e| e.peer == peer && e.hash == hash)
+        {
+            let expected = self.expected.remove(i);
+            self.take_offer(offer, Some(expected.dest));
+            return;
+        }
+        self.offers.push(offer);
+        self.events.push(TransferEvent::Offered {
+            peer,
+            name,
+            size,
+            number: self.offers.len(),
+        });
+    }
+
+    fn on_request(&mut self, peer: &str, id: u64, chunks: &[u32]) {
+        let Some(i) = self
+            .outgoing
+            .iter()
+            .position(|t| t.id == id && t.peer == peer)
+        else {
+            // Not ours (any more): stop the receiver asking
+            let msg = Message::FileCancel {
+                from: self.name.clone(),
+                id,
+            };
+            self.outbox.push((peer.to_string(), msg));
+            return;
+        };
+        let mut count = chunks.len().min(WINDOW);
+        if self.outgoing[i].limited {
+            count = self.throttle.take(count);
+            if count == 0 {
+                return;
+            }
+        }
+        match read_chunks(&self.outgoing[i], &chunks[..count]) {
+            Ok(read) => {
+                let transfer = &mut self.outgoing[i];
+                transfer.served = transfer.served.saturating_add(read.len() as u32);
+                if !transfer.accepted {
+                    transfer.accepted = true;
+                    self.dirty = true;
+                }
+                for (index, data) in read {
+                    let msg = Message::FileChunk {
+                        from: self.name.clone(),
+                        id,
+                        index,
+                        data: lz4_flex::compress_prepend_size(&data),
+                        hash: blake3::hash(&data),
+                    };
+                    self.outbox.push((peer.to_string(), msg));
+                }
+            }
+            Err(e) => {
+                let transfer = self.outgoing.remove(i);
+                let msg = Message::FileCancel {
+                    from: self.name.clone(),
+                    id,
+                };
+                self.outbox.push((peer.to_string(), msg));
+                self.events.push(TransferEvent::Failed {
+                    peer: peer.to_string(),
+                    name: transfer.name,
+                    error: e.to_string(),
+                });
+                self.save();
+            }
+        }
+    }
+
+    fn on_chunk(&mut self, peer: &str, id: u64, index: u32, data: &[u8], hash: &[u8; HASH_LEN]) {
+        let Some(i) = self
+            .incoming
+            .iter()
+            .position(|t| t.id == id && t.peer == peer)
+        else {
+            return;
+        };
+        let part_path = self.part_path(id, &self.incoming[i].name);
+        let transfer = &mut self.incoming[i];
+        if index >= transfer.have.total {
+            return;
+        }
+        transfer.requested.retain(|&c| c != index);
+        if transfer.have.contains(index) {
+            return;
+        }
+        let data = match lz4_flex::decompress_size_prepended(data) {
+            Ok(data)
+                if data.len() == chunk_len(transfer.size, index)
+                    && blake3::hash(&data) == *hash =>
+            {
+                data
+            }
+            _ => {
+                // Left missing, so it's in the next request again
+                transfer.corrupt += 1;
+                if transfer.corrupt == 1 {
+                    let event = TransferEvent::Corrupted {
+                        peer: transfer.peer.clone(),
+                        name: transfer.name.clone(),
+                    };
+                    self.events.push(event);
+                }
+                if self.incoming[i].requested.is_empty() {
+                    self.request_window(i);
+                }
+                return;
+            }
+        };
+        if let Err(e) = write_chunk(transfer, &part_path, index, &data) {
+            self.fail_incoming(i, e.to_string());
+            return;
+        }
+        transfer.have.insert(index);
+        self.dirty = true;
+
+        if transfer.have.is_complete() {
+            self.finish(i);
+        } else if transfer.requested.is_empty() {
+            // The whole window is in: ask for the next one straight away
+            self.request_window(i);
+        }
+    }
+
+    fn on_cancel(&mut self, peer: String, id: u64) {
+        let name = if let Some(i) = self
+            .offers
+            .iter()
+            .position(|o| o.id == id && o.peer == peer)
+        {
+            self.offers.remove(i).name
+        } else if let Some(i) = self
+            .incoming
+            .iter()
+            .position(|t| t.id == id && t.peer == peer)
+        {
+            let transfer = self.incoming.remove(i);
+            let _ = fs::remove_file(self.part_path(transfer.id, &transfer.name));
+            transfer.name
+        } else if let Some(i) = self
+            .outgoing
+            .iter()
+            .position(|t| t.id == id && t.peer == peer)
+        {
+            self.outgoing.remove(i).name
+        } else {
+            return;
+        };
+        self.events.push(TransferEvent::Cancelled { peer, name });
+        self.save();
+    }
+
+    /// Send what's due: requests for missing chunks, repeated offers and a
+    /// checkpoint of the state file. `online` says whether a peer is reachable.
+    pub fn poll(&mut self, online: impl Fn(&str) -> bool) {
+        for i in 0..self.incoming.len() {
+            let transfer = &self.incoming[i];
+            if online(&transfer.peer)
+                && transfer
+                    .requested_at
+                    .is_none_or(|t| t.elapsed() >= REQUEST_TIMEOUT)
+            {
+                self.request_window(i);
+            }
+        }
+        for transfer in &mut self.outgoing {
+            if !transfer.accepted
+                && online(&transfer.peer)
+                && transfer
+                    .offered_at
+                    .is_none_or(|t| t.elapsed() >= OFFER_INTERVAL)
+            {
+                transfer.offered_at = Some(Instant::now());
+                let msg = Message::FileOffer {
+                    from: self.name.clone(),
+                    id: transfer.id,
+                    name: transfer.name.clone(),
+                    size: transfer.size,
+                    hash: transfer.hash,
+                };
+                self.outbox.push((transfer.peer.clone(), msg));
+            }
+        }
+        if self.dirty && self.last_saved.elapsed() >= CHECKPOINT_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// Messages waiting to go out, as (peer name, message)
+    pub fn take_outbox(&mut self) -> Vec<(String, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Things that happened since the last call
+    pub fn take_events(&mut self) -> Vec<TransferEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Chunks asked for again since the last call, as (peer name, count)
+    pub fn take_retransmissions(&mut self) -> Vec<(String, u32)> {
+        std::mem::take(&mut self.retransmissions)
+    }
+
+    /// Follow a peer's change of name
+    pub fn rename_peer(&mut self, old: &str, new: &str) {
+        let peers = self
+            .offers
+            .iter_mut()
+            .map(|o| &mut o.peer)
+            .chain(self.incoming.iter_mut().map(|t| &mut t.peer))
+            .chain(self.outgoing.iter_mut().map(|t| &mut t.peer))
+            .chain(self.expected.iter_mut().map(|e| &mut e.peer));
+        for peer in peers {
+            if peer == old {
+                *peer = new.to_string();
+                self.dirty = true;
+            }
+        }
+    }
+
+    fn request_window(&mut self, i: usize) {
+        let transfer = &mut self.incoming[i];
+        transfer.requested = transfer.have.missing().take(WINDOW).collect();
+        transfer.requested_at = Some(Instant::now());
+        let again = match transfer.furthest_requested {
+            Some(furthest) => transfer
+                .requested
+                .iter()
+                .filter(|&&c| c <= furthest)
+                .count(),
+            None => 0,
+        };
+        if again > 0 {
+            self.retransmissions
+                .push((transfer.peer.clone(), again as u32));
+        }
+        if let Some(&last) = transfer.requested.last() {
+            transfer.furthest_requested = transfer.furthest_requested.max(Some(last));
+        }
+        let msg = Message::FileRequest {
+            from: self.name.clone(),
+            id: transfer.id,
+            chunks: transfer.requested.clone(),
+        };
+        self.outbox.push((transfer.peer.clone(), msg));
+    }
+
+    /// Check a complete file against the offered hash, move it into place and
+    /// tell the sender
+    fn finish(&mut self, i: usize) {
+        let mut transfer = self.incoming.remove(i);
+        let part = self.part_path(transfer.id, &transfer.name);
+        let checked = match transfer.part.take() {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+        .and_then(|_| blake3::hash_file(&part));
+        let result = match checked {
+            Ok(hash) if hash != transfer.hash => {
+                // Every chunk checked out, so the sender's file changed under it
+                let _ = fs::remove_file(&part);
+                let msg = Message::FileCancel {
+                    from: self.name.clone(),
+                    id: transfer.id,
+                };
+                self.outbox.push((transfer.peer.clone(), msg));
+                Err(format!(
+                    "checksum mismatch (expected {}, got {}), so it was discarded",
+                    short_hex(&transfer.hash),
+                    short_hex(&hash)
+                ))
+            }
+            Ok(_) => {
+                let dir = transfer.dest.as_deref().unwrap_or(&self.dir);
+                let path = unique_path(dir, &transfer.name);
+                move_file(&part, &path)
+                    .map(|_| path)
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(path) => {
+                let msg = Message::FileDone {
+                    from: self.name.clone(),
+                    id: transfer.id,
+                };
+                self.outbox.push((transfer.peer.clone(), msg));
+                self.events.push(TransferEvent::Received {
+                    peer: transfer.peer,
+                    path,
+                });
+            }
+            Err(error) => self.events.push(TransferEvent::Failed {
+                peer: transfer.peer,
+                name: transfer.name,
+                error,
+            }),
+        }
+        self.save();
+    }
+
+    fn fail_incoming(&mut self, i: usize, error: String) {
+        let transfer = self.incoming.remove(i);
+        let msg = Message::FileCancel {
+            from: self.name.clone(),
+            id: transfer.id,
+        };
+        self.outbox.push((transfer.peer.clone(), msg));
+        self.events.push(TransferEvent::Failed {
+            peer: transfer.peer,
+            name: transfer.name,
+            error,
+        });
+        self.save();
+    }
+
+    fn part_path(&self, id: u64, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{:016x}.part", name, id))
+    }
+
+    /// Write the state file. Received data is synced first, so the file never
+    /// claims chunks that aren't on disk.
+    fn save(&mut self) {
+        self.dirty = false;
+        self.last_saved = Instant::now();
+        for transfer in &self.incoming {
+            if let Some(part) = &transfer.part {
+                let _ = part.sync_data();
+            }
+        }
+        let saved = SavedTransfersRef {
+            outgoing: &self.outgoing,
+            incoming: &self.incoming,
+        };
+        let path = self.dir.join(STATE_FILE);
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_string(&saved)
+            .map_err(io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = result {
+            eprintln!("Failed to save file transfer state: {}", e);
+        }
+    }
+}
+
+impl Drop for FileTransfers {
+    fn drop(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+}
+
+/// [`SavedTransfers`], borrowed for writing
+#[derive(Serialize)]
+struct SavedTransfersRef<'a> {
+    outgoing: &'a [Outgoing],
+    incoming: &'a [Incoming],
+}
+
+/// Read the requested chunks of a file being served
+fn read_chunks(transfer: &Outgoing, chunks: &[u32]) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut file = File::open(&transfer.path)?;
+    if file.metadata()?.len() != transfer.size {
+        return Err(io::Error::other(
+            "the file has changed since it was offered",
+        ));
+    }
+    let mut read = Vec::with_capacity(chunks.len());
+    for &index in chunks {
+        let len = chunk_len(transfer.size, index);
+        if len == 0 {
+            continue;
+        }
+        let mut data = vec![0; len];
+        file.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))?;
+        file.read_exact(&mut data)?;
+        read.push((index, data));
+    }
+    Ok(read)
+}
+
+/// Write a received chunk into the `.part` file, reopening it after a restart
+fn write_chunk(transfer: &mut Incoming, path: &Path, index: u32, data: &[u8]) -> io::Result<()> {
+    if transfer.part.is_none() {
+        transfer.part = Some(OpenOptions::new().write(true).open(path)?);
+    }
+    let part = transfer.part.as_mut().expect("opened above");
+    part.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))?;
+    part.write_all(data)
+}
+
+/// Rename, or copy if `to` is on another filesystem (a Tunes directory can be)
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// A peer-supplied file name, reduced to a plain name inside the files directory
+fn safe_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "file".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// `dir/name`, or `dir/name (2).ext` and so on if that's taken
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    (2..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn percent(part: u32, total: u32) -> u32 {
+    if total == 0 {
+        100
+    } else {
+        (part as u64 * 100 / total as u64) as u32
+    }
+}
+
+/// "512 bytes", "3.2 KB", "1.5 MB"
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} bytes", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deliver everything in `from`'s outbox to `to`, as if over a lossless link
+    fn exchange(from: &mut FileTransfers, to: &mut FileTransfers) -> usize {
+        let outbox = from.take_outbox();
+        let count = outbox.len();
+        for (_, msg) in outbox {
+            to.handle(msg);
+        }
+        count
+    }
+
+    fn offered_number(transfers: &mut FileTransfers) -> usize {
+        match transfers.take_events().as_slice() {
+            [TransferEvent::Offered { number, .. }] => *number,
+            other => panic!("expected an offer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_chunk_set() {
+        let mut set = ChunkSet::new(70);
+        assert!(set.insert(0));
+        assert!(set.insert(69));
+        assert!(!set.insert(69));
+        assert!(!set.insert(70));
+        assert_eq!(set.count(), 2);
+        assert_eq!(set.missing().next(), Some(1));
+        assert_eq!(set.missing().count(), 68);
+        assert!(!set.is_complete());
+    }
+
+    #[test]
+    fn test_safe_file_name() {
+        assert_eq!(safe_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(safe_file_name("C:\\Users\\bob\\notes.txt"), "notes.txt");
+        assert_eq!(safe_file_name(".bashrc"), "bashrc");
+        assert_eq!(safe_file_name("a\x1b[2Jb"), "a[2Jb");
+        assert_eq!(safe_file_name(".."), "file");
+    }
+
+    #[test]
+    fn test_transfer_resumes_after_restart() {
+        let alice_dir = tempfile::tempdir().unwrap();
+        let bob_dir = tempfile::tempdir().unwrap();
+        let source = alice_dir.path().join("tape.bin");
+        let contents: Vec<u8> = (0..CHUNK_SIZE * 100 + 17).map(|i| i as u8).collect();
+        fs::write(&source, &contents).unwrap();
+
+        let mut alice = FileTransfers::open(alice_dir.path(), "alice".to_string()).unwrap();
+        let mut bob = FileTransfers::open(bob_dir.path(), "bob".to_string()).unwrap();
+        assert_eq!(alice.send("bob", &source).unwrap(), "tape.bin");
+        alice.poll(|_| true);
+        exchange(&mut alice, &mut bob);
+        let number = offered_number(&mut bob);
+        assert_eq!(
+            bob.accept(Some(number)).unwrap(),
+            ("alice".to_string(), "tape.bin".to_string())
+        );
+
+        // One window arrives, then both sides stop without a clean shutdown
+        bob.poll(|_| true);
+        exchange(&mut bob, &mut alice);
+        exchange(&mut alice, &mut bob);
+        bob.save();
+        alice.save();
+        bob.take_outbox();
+        std::mem::forget(bob);
+        std::mem::forget(alice);
+
+        let mut alice = FileTransfers::open(alice_dir.path(), "alice".to_string()).unwrap();
+        let mut bob = FileTransfers::open(bob_dir.path(), "bob".to_string()).unwrap();
+        assert_eq!((alice.active(), bob.active()), (1, 1));
+        assert!(bob.list()[0].contains(&format!("{}%", WINDOW * 100 / 101)));
+
+        // Only the missing chunks are asked for
+        bob.poll(|_| true);
+        let outbox = bob.take_outbox();
+        match outbox.as_slice() {
+            [(peer, Message::FileRequest { chunks, .. })] => {
+                assert_eq!(peer, "alice");
+                assert_eq!(chunks[0], WINDOW as u32);
+            }
+            other => panic!("expected a request, got {:?}", other),
+        }
+        for (_, msg) in outbox {
+            alice.handle(msg);
+        }
+        while exchange(&mut alice, &mut bob) + exchange(&mut bob, &mut alice) > 0 {}
+
+        let path = match bob.take_events().as_slice() {
+            [TransferEvent::Received { path, .. }] => path.clone(),
+            other => panic!("expected the file, got {:?}", other),
+        };
+        assert_eq!(path, bob_dir.path().join("tape.bin"));
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        assert!(matches!(
+            alice.take_events().as_slice(),
+            [TransferEvent::Delivered { .. }]
+        ));
+        assert_eq!((alice.active(), bob.active()), (0, 0));
+        // Only the finished file and the state file are left behind
+        assert_eq!(fs::read_dir(bob_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_corruption_is_caught() {
+        let alice_dir = tempfile::tempdir().unwrap();
+        let bob_dir = tempfile::tempdir().unwrap();
+        let source = alice_dir.path().join("notes.txt");
+        let contents = vec![b'x'; CHUNK_SIZE * 3];
+        fs::write(&source, &contents).unwrap();
+
+        let mut alice = FileTransfers::open(alice_dir.path(), "alice".to_string()).unwrap();
+        let mut bob = FileTransfers::open(bob_dir.path(), "bob".to_string()).unwrap();
+        alice.send("bob", &source).unwrap();
+        alice.poll(|_| true);
+        exchange(&mut alice, &mut bob);
+        let number = offered_number(&mut bob);
+        bob.accept(Some(number)).unwrap();
+
+        // A chunk damaged on the way is dropped and asked for again
+        bob.poll(|_| true);
+        exchange(&mut bob, &mut alice);
+        for (_, mut msg) in alice.take_outbox() {
+            if let Message::FileChunk { index: 1, data, .. } = &mut msg {
+                *data.last_mut().unwrap() ^= 1;
+            }
+            bob.handle(msg);
+        }
+        assert!(matches!(
+            bob.take_events().as_slice(),
+            [TransferEvent::Corrupted { .. }]
+        ));
+        assert!(bob.list()[0].contains("1 corrupted chunks"));
+        match bob.take_outbox().as_slice() {
+            [(_, Message::FileRequest { chunks, .. })] => assert_eq!(chunks, &[1]),
+            other => panic!("expected a request, got {:?}", other),
+        }
+        assert_eq!(bob.take_retransmissions(), [("alice".to_string(), 1)]);
+
+        // The file changes (keeping its size) after being offered: the chunks
+        // are intact, but the whole doesn't match the offer
+        fs::write(&source, vec![b'y'; CHUNK_SIZE * 3]).unwrap();
+        bob.request_window(0);
+        while exchange(&mut bob, &mut alice) + exchange(&mut alice, &mut bob) > 0 {}
+        match bob.take_events().as_slice() {
+            [TransferEvent::Failed { error, .. }] => assert!(error.contains("checksum")),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+        assert!(matches!(
+            alice.take_events().as_slice(),
+            [TransferEvent::Cancelled { .. }]
+        ));
+        // Nothing corrupt is left behind
+        assert_eq!(fs::read_dir(bob_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_unknown_transfer_is_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut alice = FileTransfers::open(dir.path(), "alice".to_string()).unwrap();
+        alice.handle(Message::FileRequest {
+            from: "bob".to_string(),
+            id: 7,
+            chunks: vec![0],
+        });
+        assert!(matches!(
+            alice.take_outbox().as_slice(),
+            [(_, Message::FileCancel { id: 7, .. })]
+        ));
+    }
+
+    #[test]
+    fn test_expected_offer_is_taken_and_paced() {
+        let alice_dir = tempfile::tempdir().unwrap();
+        let bob_dir = tempfile::tempdir().unwrap();
+        let tunes_dir = tempfile::tempdir().unwrap();
+        let source = alice_dir.path().join("song.ogg");
+        let contents: Vec<u8> = (0..CHUNK_SIZE * 10).map(|i| (i / 7) as u8).collect();
+        fs::write(&source, &contents).unwrap();
+
+        let mut alice = FileTransfers::open(alice_dir.path(), "alice".to_string()).unwrap();
+        let mut bob = FileTransfers::open(bob_dir.path(), "bob".to_string()).unwrap();
+        alice.set_rate_limit(4 * CHUNK_SIZE as u64);
+        bob.expect("alice", blake3::hash(&contents), tunes_dir.path());
+        alice.send_limited("bob", &source).unwrap();
+        alice.poll(|_| true);
+        exchange(&mut alice, &mut bob);
+        // Taken without an offer to /accept
+        assert!(bob.take_events().is_empty());
+        assert_eq!(bob.active(), 1);
+
+        // The first window is cut to the allowance
+        bob.poll(|_| true);
+        exchange(&mut bob, &mut alice);
+        assert_eq!(exchange(&mut alice, &mut bob), 4);
+
+        alice.set_rate_limit(0);
+        bob.incoming[0].requested_at = None;
+        while {
+            bob.poll(|_| true);
+            exchange(&mut bob, &mut alice) + exchange(&mut alice, &mut bob) > 0
+        } && bob.active() > 0
+        {}
+        let path = match bob.take_events().as_slice() {
+            [TransferEvent::Received { path, .. }] => path.clone(),
+            other => panic!("expected the file, got {:?}", other),
+        };
+        assert_eq!(path, tunes_dir.path().join("song.ogg"));
+        assert_eq!(fs::read(&path).unwrap(), contents);
+    }
+}
@@ -0,0 +1,312 @@
+//! Recent chat history, for backfilling peers that join mid-conversation.
+//!
+//! Every node remembers the last few minutes of chat. A node that joins sends a
+//! `HistoryRequest` to the peers it meets; those that have opted in (by setting
+//! `history_minutes`) reply with their recent messages split into
+//! `HistoryChunk`s small enough for a single datagram.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Most messages remembered (and sent in a backfill)
+const MAX_HISTORY_ENTRIES: usize = 200;
+
+/// Payload budget for the entries in one chunk, to stay clear of fragmentation
+const CHUNK_BUDGET: usize = 1200;
+
+/// Each requester is answered at most this often
+const REQUEST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A partial backfill is abandoned if it hasn't completed in this long
+const BACKFILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// We only ask for history this soon after starting up
+const BACKFILL_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// A chat message as remembered for backfill
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the message was seen, in ms since the Unix epoch
+    pub unix_ms: i64,
+    pub from: String,
+    /// Message text as sent on the wire (actions keep their `\x01ACTION` prefix)
+    pub text: String,
+}
+
+impl HistoryEntry {
+    /// Bytes the entry takes up in a chunk
+    fn encoded_len(&self) -> usize {
+        8 + 1 + self.from.len() + 2 + self.text.len()
+    }
+}
+
+/// The last few minutes of chat
+#[derive(Debug)]
+pub struct ChatHistory {
+    entries: VecDeque<HistoryEntry>,
+    /// How long messages are kept (zero = history sharing disabled)
+    window: Duration,
+    /// When each requester was last answered
+    answered: HashMap<SocketAddr, Instant>,
+}
+
+impl ChatHistory {
+    /// Create a history that keeps (and shares) `minutes` of chat
+    pub fn new(minutes: u32) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            window: Duration::from_secs(minutes as u64 * 60),
+            answered: HashMap::new(),
+        }
+    }
+
+    /// Whether we answer history requests
+    pub fn sharing(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Remember a chat message. Images aren't worth the bandwidth and are skipped.
+    ///
+    /// Messages are kept even when sharing is off, so a backfill we receive can
+    /// skip the ones we've already seen.
+    pub fn record(&mut self, from: &str, text: &str) {
+        if text.starts_with("[IMAGE]\n") {
+            return;
+        }
+        if self.entries.len() >= MAX_HISTORY_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            unix_ms: chrono::Utc::now().timestamp_millis(),
+            from: from.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    /// The `back`th most recent message (1 = the latest), from `from` if given
+    pub fn latest(&self, back: usize, from: Option<&str>) -> Option<&HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|e| from.is_none_or(|name| e.from == name))
+            .nth(back.checked_sub(1)?)
+    }
+
+    /// Entries still inside the history window, oldest first
+    fn recent(&self, now_ms: i64) -> impl Iterator<Item = &HistoryEntry> {
+        let cutoff = now_ms - self.window.as_millis() as i64;
+        self.entries.iter().filter(move |e| e.unix_ms >= cutoff)
+    }
+
+    /// Whether we already have this message (so a backfill doesn't repeat it)
+    pub fn contains(&self, from: &str, text: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.from == from && e.text == text)
+    }
+
+    /// The chunks to send in reply to a request from `addr`, or None if sharing is
+    /// off or that address was answered too recently
+    pub fn answer(&mut self, addr: SocketAddr) -> Option<Vec<Vec<HistoryEntry>>> {
+        if !self.sharing() {
+            return None;
+        }
+        let now = Instant::now();
+        self.answered
+            .retain(|_, at| now.duration_since(*at) < REQUEST_INTERVAL);
+        if self.answered.contains_key(&addr) {
+            return None;
+        }
+        self.answered.insert(addr, now);
+
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        Some(chunk(self.recent(now_ms).cloned().collect()))
+    }
+
+    /// Drop messages that have aged out of the window
+    pub fn expire(&mut self) {
+        if !self.sharing() {
+            // Only the entry cap applies
+            return;
+        }
+        let cutoff = chrono::Utc::now().timestamp_millis() - self.window.as_millis() as i64;
+        while self.entries.front().is_some_and(|e| e.unix_ms < cutoff) {
+            self.entries.pop_front();
+        }
+    }
+}
+
+/// Split entries into chunks that each fit the datagram budget (at least one chunk)
+fn chunk(entries: Vec<HistoryEntry>) -> Vec<Vec<HistoryEntry>> {
+    let mut chunks = vec![Vec::new()];
+    let mut size = 0;
+    for entry in entries {
+        let len = entry.encoded_len();
+        let current = chunks.last_mut().unwrap();
+        if !current.is_empty() && (size + len > CHUNK_BUDGET || current.len() == u8::MAX as usize) {
+            chunks.push(Vec::new());
+            size = 0;
+        }
+        size += len;
+        chunks.last_mut().unwrap().push(entry);
+    }
+    chunks
+}
+
+/// Reassembles the chunks of one backfill
+#[derive(Debug)]
+pub struct Backfill {
+    created: Instant,
+    /// Peer whose history we're receiving, once the first chunk arrives
+    source: Option<String>,
+    parts: Vec<Option<Vec<HistoryEntry>>>,
+    started: Option<Instant>,
+    /// Set once a backfill has been shown; later ones are ignored
+    done: bool,
+}
+
+impl Backfill {
+    pub fn new() -> Self {
+        Self {
+            created: Instant::now(),
+            source: None,
+            parts: Vec::new(),
+            started: None,
+            done: false,
+        }
+    }
+
+    /// Whether we still want history from peers: only shortly after startup,
+    /// and only until one has arrived
+    pub fn wanted(&self) -> bool {
+        !self.done && self.created.elapsed() < BACKFILL_WINDOW
+    }
+
+    /// Add a chunk. Returns the complete history (oldest first) once every part
+    /// from the same peer has arrived.
+    pub fn accept(
+        &mut self,
+        from: &str,
+        part: u8,
+        total: u8,
+        entries: Vec<HistoryEntry>,
+    ) -> Option<Vec<HistoryEntry>> {
+        if self.done || total == 0 || part >= total {
+            return None;
+        }
+        // Start over if the backfill we were assembling stalled
+        if self
+            .started
+            .is_some_and(|t| t.elapsed() >= BACKFILL_TIMEOUT)
+        {
+            self.source = None;
+        }
+        match &self.source {
+            Some(source) if source != from => return None,
+            Some(_) => {}
+            None => {
+                self.source = Some(from.to_string());
+                self.parts = vec![None; total as usize];
+                self.started = Some(Instant::now());
+            }
+        }
+        if self.parts.len() != total as usize {
+            return None;
+        }
+        self.parts[part as usize] = Some(entries);
+
+        if !self.parts.iter().all(Option::is_some) {
+            return None;
+        }
+        let entries: Vec<HistoryEntry> = self.parts.drain(..).flatten().flatten().collect();
+        self.source = None;
+        if entries.is_empty() {
+            // That peer had nothing to share; keep listening for others
+            return None;
+        }
+        self.done = true;
+        Some(entries)
+    }
+}
+
+impl Default for Backfill {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str) -> HistoryEntry {
+        HistoryEntry {
+            unix_ms: 0,
+            from: "bob".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_chunking() {
+        assert_eq!(chunk(Vec::new()), vec![Vec::new()]);
+
+        let long = "x".repeat(500);
+        let chunks = chunk(vec![entry(&long), entry(&long), entry(&long), entry("hi")]);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), 2);
+        assert_eq!(chunks[1].len(), 2);
+    }
+
+    #[test]
+    fn test_latest() {
+        let mut history = ChatHistory::new(0);
+        history.record("bob", "one");
+        history.record("carol", "two");
+        history.record("bob", "three");
+        assert_eq!(history.latest(1, None).unwrap().text, "three");
+        assert_eq!(history.latest(2, None).unwrap().text, "two");
+        assert_eq!(history.latest(2, Some("bob")).unwrap().text, "one");
+        assert!(history.latest(0, None).is_none());
+        assert!(history.latest(2, Some("carol")).is_none());
+    }
+
+    #[test]
+    fn test_answer_is_rate_limited() {
+        let addr: SocketAddr = "10.0.0.2:7890".parse().unwrap();
+        let mut disabled = ChatHistory::new(0);
+        disabled.record("bob", "hi");
+        assert!(disabled.contains("bob", "hi"));
+        assert!(disabled.answer(addr).is_none());
+
+        let mut history = ChatHistory::new(10);
+        history.record("bob", "hi");
+        history.record("bob", "[IMAGE]\n####");
+        let chunks = history.answer(addr).unwrap();
+        assert_eq!(chunks[0].len(), 1);
+        assert_eq!(chunks[0][0].text, "hi");
+        assert!(history.answer(addr).is_none());
+    }
+
+    #[test]
+    fn test_backfill_reassembly() {
+        let mut backfill = Backfill::new();
+        // A peer with nothing to share doesn't end the backfill
+        assert!(backfill.accept("dave", 0, 1, Vec::new()).is_none());
+        assert!(backfill.wanted());
+
+        assert!(backfill.accept("bob", 1, 2, vec![entry("two")]).is_none());
+        // Another peer's reply doesn't get mixed in
+        assert!(
+            backfill
+                .accept("carol", 0, 1, vec![entry("other")])
+                .is_none()
+        );
+        let all = backfill.accept("bob", 0, 2, vec![entry("one")]).unwrap();
+        assert_eq!(all, vec![entry("one"), entry("two")]);
+        assert!(!backfill.wanted());
+    }
+}
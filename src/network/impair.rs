@@ -0,0 +1,251 @@
+//! Simulated network impairment (`--impair`), for testing and demos.
+//!
+//! [`super::PeerSocket`] can drop, delay and reorder peer datagrams to exercise
+//! fragment reassembly, congestion control and ping loss on a clean LAN.
+//! Outgoing datagrams are only ever dropped; delays are applied as datagrams
+//! arrive, so a slow simulated link never blocks the sender. Decisions come
+//! from a seeded RNG, so a given seed replays the same pattern.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Extra delay for a datagram picked to arrive out of order
+const REORDER_DELAY: Duration = Duration::from_millis(50);
+
+/// What to do to peer traffic
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Impairment {
+    /// Fraction of datagrams dropped in each direction (0.0 - 1.0)
+    pub loss: f64,
+    /// Delay added to every received datagram
+    pub latency: Duration,
+    /// Random extra delay, up to this much
+    pub jitter: Duration,
+    /// Fraction of received datagrams held back so later ones overtake them
+    pub reorder: f64,
+    pub seed: u64,
+}
+
+impl FromStr for Impairment {
+    type Err = String;
+
+    /// Parse e.g. `loss=5%,latency=200ms,jitter=50ms,reorder=2%,seed=7`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut impairment = Impairment::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", setting))?;
+            match key.trim() {
+                "loss" => impairment.loss = parse_fraction(value)?,
+                "latency" => impairment.latency = parse_millis(value)?,
+                "jitter" => impairment.jitter = parse_millis(value)?,
+                "reorder" => impairment.reorder = parse_fraction(value)?,
+                "seed" => {
+                    impairment.seed = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid seed '{}'", value))?
+                }
+                other => return Err(format!("unknown impairment '{}'", other)),
+            }
+        }
+        Ok(impairment)
+    }
+}
+
+impl fmt::Display for Impairment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% loss, {}ms latency, {}ms jitter, {:.0}% reordered (seed {})",
+            self.loss * 100.0,
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.reorder * 100.0,
+            self.seed
+        )
+    }
+}
+
+/// "5%" or "0.05"
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let fraction = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => value.parse::<f64>(),
+    }
+    .map_err(|_| format!("invalid fraction '{}'", value))?;
+    if (0.0..=1.0).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err(format!("'{}' is not between 0% and 100%", value))
+    }
+}
+
+/// "200ms" or "200"
+fn parse_millis(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    value
+        .strip_suffix("ms")
+        .unwrap_or(value)
+        .trim()
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid duration '{}'", value))
+}
+
+/// A received datagram waiting out its simulated delay
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Held {
+    due: Instant,
+    /// Arrival order, so equal due times keep their order
+    seq: u64,
+    addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+/// Applies an [`Impairment`] to datagrams
+#[derive(Debug)]
+pub struct Impairer {
+    impairment: Impairment,
+    rng: StdRng,
+    held: BinaryHeap<Reverse<Held>>,
+    seq: u64,
+}
+
+impl Impairer {
+    pub fn new(impairment: Impairment) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(impairment.seed),
+            impairment,
+            held: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Whether an outgoing datagram should be dropped
+    pub fn drop_outgoing(&mut self) -> bool {
+        self.rng.random_bool(self.impairment.loss)
+    }
+
+    /// How long to hold a received datagram, or None to drop it
+    fn incoming_delay(&mut self) -> Option<Duration> {
+        if self.rng.random_bool(self.impairment.loss) {
+            return None;
+        }
+        let mut delay = self.impairment.latency;
+        if !self.impairment.jitter.is_zero() {
+            delay += self.impairment.jitter.mul_f64(self.rng.random::<f64>());
+        }
+        if self.rng.random_bool(self.impairment.reorder) {
+            delay += REORDER_DELAY;
+        }
+        Some(delay)
+    }
+
+    /// Take a received datagram. Returns it straight back if it should be
+    /// delivered now; otherwise it's dropped or held for [`Self::take_due`].
+    pub fn receive(&mut self, addr: SocketAddr, data: &[u8]) -> Option<(SocketAddr, Vec<u8>)> {
+        let delay = self.incoming_delay()?;
+        if delay.is_zero() && self.held.is_empty() {
+            return Some((addr, data.to_vec()));
+        }
+        self.seq += 1;
+        self.held.push(Reverse(Held {
+            due: Instant::now() + delay,
+            seq: self.seq,
+            addr,
+            data: data.to_vec(),
+        }));
+        self.take_due()
+    }
+
+    /// The next held datagram whose delay is up
+    pub fn take_due(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        if self.held.peek()?.0.due > Instant::now() {
+            return None;
+        }
+        let Reverse(held) = self.held.pop()?;
+        Some((held.addr, held.data))
+    }
+
+    /// When the next held datagram is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.held.peek().map(|held| held.0.due)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let impairment: Impairment = "loss=5%, latency=200ms,jitter=50,reorder=0.5,seed=7"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            impairment,
+            Impairment {
+                loss: 0.05,
+                latency: Duration::from_millis(200),
+                jitter: Duration::from_millis(50),
+                reorder: 0.5,
+                seed: 7,
+            }
+        );
+        assert!("loss=150%".parse::<Impairment>().is_err());
+        assert!("speed=fast".parse::<Impairment>().is_err());
+        assert!("latency".parse::<Impairment>().is_err());
+    }
+
+    #[test]
+    fn test_loss_is_deterministic() {
+        let impairment: Impairment = "loss=30%,seed=42".parse().unwrap();
+        let pattern = |mut impairer: Impairer| -> Vec<bool> {
+            (0..200).map(|_| impairer.drop_outgoing()).collect()
+        };
+        let first = pattern(Impairer::new(impairment.clone()));
+        assert_eq!(first, pattern(Impairer::new(impairment)));
+        let dropped = first.iter().filter(|&&d| d).count();
+        assert!((30..90).contains(&dropped), "{} dropped", dropped);
+    }
+
+    #[test]
+    fn test_delay_and_reorder() {
+        let addr: SocketAddr = "10.0.0.2:7890".parse().unwrap();
+
+        // No impairment passes datagrams straight through
+        let mut clean = Impairer::new(Impairment::default());
+        assert_eq!(clean.receive(addr, b"a"), Some((addr, b"a".to_vec())));
+
+        // Every datagram held back: nothing is due until the delay passes
+        let mut slow = Impairer::new("latency=20ms,reorder=100%".parse().unwrap());
+        assert_eq!(slow.receive(addr, b"a"), None);
+        assert!(slow.next_due().unwrap() > Instant::now() + Duration::from_millis(60));
+        assert_eq!(slow.take_due(), None);
+
+        // Datagrams picked for reordering are overtaken by later ones
+        let mut jumbled = Impairer::new("reorder=50%,seed=3".parse().unwrap());
+        let mut delivered = Vec::new();
+        for n in 0..20u8 {
+            if let Some((_, data)) = jumbled.receive(addr, &[n]) {
+                delivered.push(data[0]);
+            }
+        }
+        std::thread::sleep(REORDER_DELAY * 2);
+        while let Some((_, data)) = jumbled.take_due() {
+            delivered.push(data[0]);
+        }
+        assert_eq!(delivered.len(), 20);
+        assert!(delivered.windows(2).any(|w| w[0] > w[1]));
+    }
+}
@@ -0,0 +1,46 @@
+//! NAT keepalives and public address refresh.
+//!
+//! A NAT forgets an idle UDP mapping after somewhere between 30 seconds and a
+//! few minutes, after which a quiet peer's packets stop arriving. Every node
+//! sends each peer a tiny `Keepalive` on a fixed interval: that keeps the
+//! mapping open, marks the sender as alive, and lets a peer whose NAT gave it
+//! a new mapping be followed to its new address.
+//!
+//! If our own public address changes (a new DHCP lease, a NAT reboot), peers
+//! would keep sending to the old one, so STUN is re-run now and then and a
+//! change is announced to every known peer.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+/// Default seconds between keepalives to each peer (`keepalive` in `[network]`)
+pub const DEFAULT_KEEPALIVE_SECS: u64 = 15;
+
+/// How often STUN is re-run to spot a change of public address
+const STUN_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Re-run STUN in the background every [`STUN_REFRESH_INTERVAL`], sending each
+/// public address found
+pub fn spawn_public_addr_monitor(port: u16) -> mpsc::Receiver<SocketAddr> {
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(STUN_REFRESH_INTERVAL).await;
+            // STUN blocks for up to a few seconds per server
+            let result =
+                tokio::task::spawn_blocking(move || super::discover_public_endpoint(port)).await;
+            match result {
+                Ok(Ok(addr)) => {
+                    if tx.send(addr).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Err(e)) => eprintln!("STUN refresh failed: {}", e),
+                Err(_) => return,
+            }
+        }
+    });
+    rx
+}
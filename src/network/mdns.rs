@@ -0,0 +1,372 @@
+//! Minimal mDNS (multicast DNS) service advertisement and browsing.
+//!
+//! Many Wi-Fi access points filter subnet broadcasts but pass multicast DNS,
+//! so peers also advertise themselves as `<name>._wormhole._udp.local`:
+//! 1. Each peer periodically multicasts a query for `_wormhole._udp.local`
+//!    alongside an unsolicited announcement of its own service instance
+//! 2. When a peer sees a query for the service, it multicasts its announcement
+//! 3. Announcements from other peers (PTR + SRV, optionally A) become discovered peers
+//!
+//! Only the handful of record types needed for this are encoded and decoded.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+/// mDNS multicast group
+pub const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// mDNS port
+pub const MDNS_PORT: u16 = 5353;
+
+/// Service type advertised by wormhole peers
+pub const SERVICE_TYPE: &str = "_wormhole._udp.local";
+
+/// TTL for advertised records, in seconds
+const RECORD_TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Cache-flush bit, set on records unique to this host
+const CLASS_FLUSH: u16 = 0x8000;
+
+/// Destination address for mDNS packets
+pub fn multicast_addr() -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT))
+}
+
+/// A wormhole service instance found via mDNS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsPeer {
+    pub name: String,
+    /// Address from an A record, if the responder included one
+    pub ip: Option<Ipv4Addr>,
+    pub port: u16,
+}
+
+/// Build a query for all wormhole service instances
+pub fn build_query() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(64);
+    write_header(&mut buf, 0x0000, 1, 0, 0);
+    write_name(&mut buf, SERVICE_TYPE);
+    buf.extend(TYPE_PTR.to_be_bytes());
+    buf.extend(CLASS_IN.to_be_bytes());
+    buf
+}
+
+/// Build a response announcing our service instance
+pub fn build_announcement(name: &str, port: u16, addrs: &[Ipv4Addr]) -> Vec<u8> {
+    let instance = format!("{}.{}", sanitize_label(name), SERVICE_TYPE);
+    let host = format!("{}.local", sanitize_label(name));
+
+    let mut buf = Vec::with_capacity(256);
+    write_header(&mut buf, 0x8400, 0, 3, addrs.len() as u16);
+
+    // PTR: service type -> instance
+    let mut rdata = Vec::new();
+    write_name(&mut rdata, &instance);
+    write_record(&mut buf, SERVICE_TYPE, TYPE_PTR, CLASS_IN, &rdata);
+
+    // SRV: instance -> host:port
+    let mut rdata = Vec::new();
+    rdata.extend(0u16.to_be_bytes()); // priority
+    rdata.extend(0u16.to_be_bytes()); // weight
+    rdata.extend(port.to_be_bytes());
+    write_name(&mut rdata, &host);
+    write_record(
+        &mut buf,
+        &instance,
+        TYPE_SRV,
+        CLASS_IN | CLASS_FLUSH,
+        &rdata,
+    );
+
+    // TXT: protocol version
+    let txt = format!("v={}", env!("CARGO_PKG_VERSION"));
+    let mut rdata = vec![txt.len() as u8];
+    rdata.extend(txt.as_bytes());
+    write_record(
+        &mut buf,
+        &instance,
+        TYPE_TXT,
+        CLASS_IN | CLASS_FLUSH,
+        &rdata,
+    );
+
+    // A: host -> each address
+    for addr in addrs {
+        write_record(
+            &mut buf,
+            &host,
+            TYPE_A,
+            CLASS_IN | CLASS_FLUSH,
+            &addr.octets(),
+        );
+    }
+
+    buf
+}
+
+/// Check whether a packet is a query asking for wormhole services
+pub fn is_service_query(packet: &[u8]) -> bool {
+    let Some(header) = Header::parse(packet) else {
+        return false;
+    };
+    if header.flags & 0x8000 != 0 {
+        return false;
+    }
+
+    let mut pos = 12;
+    for _ in 0..header.qdcount {
+        let Some((qname, next)) = read_name(packet, pos) else {
+            return false;
+        };
+        if next + 4 > packet.len() {
+            return false;
+        }
+        let qtype = u16::from_be_bytes([packet[next], packet[next + 1]]);
+        if qname.eq_ignore_ascii_case(SERVICE_TYPE) && (qtype == TYPE_PTR || qtype == 255) {
+            return true;
+        }
+        pos = next + 4;
+    }
+    false
+}
+
+/// Extract wormhole service instances from a response packet
+pub fn parse_announcement(packet: &[u8]) -> Vec<MdnsPeer> {
+    let Some(header) = Header::parse(packet) else {
+        return Vec::new();
+    };
+    if header.flags & 0x8000 == 0 {
+        return Vec::new();
+    }
+
+    // Skip any questions
+    let mut pos = 12;
+    for _ in 0..header.qdcount {
+        match read_name(packet, pos) {
+            Some((_, next)) => pos = next + 4,
+            None => return Vec::new(),
+        }
+    }
+
+    let mut instances: Vec<String> = Vec::new();
+    let mut services: Vec<(String, u16, String)> = Vec::new();
+    let mut hosts: Vec<(String, Ipv4Addr)> = Vec::new();
+
+    let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+    for _ in 0..records {
+        let Some((rname, next)) = read_name(packet, pos) else {
+            break;
+        };
+        if next + 10 > packet.len() {
+            break;
+        }
+        let rtype = u16::from_be_bytes([packet[next], packet[next + 1]]);
+        let rdlen = u16::from_be_bytes([packet[next + 8], packet[next + 9]]) as usize;
+        let rdata_start = next + 10;
+        if rdata_start + rdlen > packet.len() {
+            break;
+        }
+
+        match rtype {
+            TYPE_PTR if rname.eq_ignore_ascii_case(SERVICE_TYPE) => {
+                if let Some((target, _)) = read_name(packet, rdata_start) {
+                    instances.push(target);
+                }
+            }
+            TYPE_SRV if rdlen >= 7 => {
+                let port = u16::from_be_bytes([packet[rdata_start + 4], packet[rdata_start + 5]]);
+                if let Some((target, _)) = read_name(packet, rdata_start + 6) {
+                    services.push((rname, port, target));
+                }
+            }
+            TYPE_A if rdlen == 4 => {
+                let o = &packet[rdata_start..rdata_start + 4];
+                hosts.push((rname, Ipv4Addr::new(o[0], o[1], o[2], o[3])));
+            }
+            _ => {}
+        }
+
+        pos = rdata_start + rdlen;
+    }
+
+    let suffix = format!(".{}", SERVICE_TYPE);
+    instances
+        .iter()
+        .filter_map(|instance| {
+            let name = instance
+                .strip_suffix(&suffix)
+                .or_else(|| instance.strip_suffix(&suffix.to_ascii_uppercase()))?;
+            let (_, port, target) = services
+                .iter()
+                .find(|(srv, _, _)| srv.eq_ignore_ascii_case(instance))?;
+            let ip = hosts
+                .iter()
+                .find(|(host, _)| host.eq_ignore_ascii_case(target))
+                .map(|(_, ip)| *ip);
+            Some(MdnsPeer {
+                name: name.to_string(),
+                ip,
+                port: *port,
+            })
+        })
+        .collect()
+}
+
+/// Parsed DNS message header
+struct Header {
+    flags: u16,
+    qdcount: u16,
+    ancount: u16,
+    nscount: u16,
+    arcount: u16,
+}
+
+impl Header {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 12 {
+            return None;
+        }
+        let field = |i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+        Some(Self {
+            flags: field(2),
+            qdcount: field(4),
+            ancount: field(6),
+            nscount: field(8),
+            arcount: field(10),
+        })
+    }
+}
+
+fn write_header(buf: &mut Vec<u8>, flags: u16, qdcount: u16, ancount: u16, arcount: u16) {
+    buf.extend(0u16.to_be_bytes()); // mDNS uses ID 0
+    buf.extend(flags.to_be_bytes());
+    buf.extend(qdcount.to_be_bytes());
+    buf.extend(ancount.to_be_bytes());
+    buf.extend(0u16.to_be_bytes()); // nscount
+    buf.extend(arcount.to_be_bytes());
+}
+
+/// Write a dotted name as DNS labels (no compression)
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        let bytes = &label.as_bytes()[..label.len().min(63)];
+        buf.push(bytes.len() as u8);
+        buf.extend_from_slice(bytes);
+    }
+    buf.push(0);
+}
+
+fn write_record(buf: &mut Vec<u8>, name: &str, rtype: u16, class: u16, rdata: &[u8]) {
+    write_name(buf, name);
+    buf.extend(rtype.to_be_bytes());
+    buf.extend(class.to_be_bytes());
+    buf.extend(RECORD_TTL.to_be_bytes());
+    buf.extend((rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(rdata);
+}
+
+/// Read a possibly-compressed name starting at `pos`.
+/// Returns the dotted name and the position just after it in the original data.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    // Bound pointer chasing so malformed packets can't loop forever
+    let mut jumps = 0;
+
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            end.get_or_insert(pos + 1);
+            break;
+        }
+        if len & 0xC0 == 0xC0 {
+            let target = ((len & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            pos = target;
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        pos += 1 + len;
+    }
+
+    Some((labels.join("."), end?))
+}
+
+/// Make a peer name safe to use as a single DNS label
+fn sanitize_label(name: &str) -> String {
+    name.chars()
+        .map(|c| if c == '.' { '-' } else { c })
+        .take(63)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_detection() {
+        assert!(is_service_query(&build_query()));
+        // Our own announcements are responses, not queries
+        assert!(!is_service_query(&build_announcement("node", 7890, &[])));
+    }
+
+    #[test]
+    fn test_announcement_roundtrip() {
+        let ip = Ipv4Addr::new(192, 168, 1, 20);
+        let packet = build_announcement("test-node", 7890, &[ip]);
+        let peers = parse_announcement(&packet);
+        assert_eq!(
+            peers,
+            vec![MdnsPeer {
+                name: "test-node".to_string(),
+                ip: Some(ip),
+                port: 7890,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        // Response with the PTR target and SRV name pointing back into the packet
+        let mut packet = Vec::new();
+        write_header(&mut packet, 0x8400, 0, 2, 0);
+        let service_offset = packet.len() as u8;
+        write_name(&mut packet, SERVICE_TYPE);
+        packet.extend(TYPE_PTR.to_be_bytes());
+        packet.extend(CLASS_IN.to_be_bytes());
+        packet.extend(RECORD_TTL.to_be_bytes());
+        packet.extend(7u16.to_be_bytes());
+        let instance_offset = packet.len() as u8;
+        packet.extend([4, b'b', b'e', b'p', b'o', 0xC0, service_offset]);
+
+        packet.extend([0xC0, instance_offset]);
+        packet.extend(TYPE_SRV.to_be_bytes());
+        packet.extend(CLASS_IN.to_be_bytes());
+        packet.extend(RECORD_TTL.to_be_bytes());
+        packet.extend(8u16.to_be_bytes());
+        packet.extend([0, 0, 0, 0, 0x1E, 0xD2, 0xC0, instance_offset]);
+
+        let peers = parse_announcement(&packet);
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].name, "bepo");
+        assert_eq!(peers[0].port, 7890);
+        assert_eq!(peers[0].ip, None);
+    }
+
+    #[test]
+    fn test_pointer_loop_rejected() {
+        let packet = [0u8, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0xC0, 12];
+        assert!(read_name(&packet, 12).is_none());
+        assert!(parse_announcement(&packet).is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+//! Multi-hop relaying of chat through a partially connected mesh.
+//!
+//! With A↔B and B↔C but no direct path between A and C, B passes each chat
+//! line on to its other peers so A and C still see each other. Every line
+//! carries a hop budget that each relay decrements, and every node remembers
+//! the lines it has seen (by sender, Lamport time and text), so copies that
+//! arrive by several routes are shown once and relaying stops where the line
+//! has already been.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Hops a chat line may travel, counting the first
+pub const MESH_TTL: u8 = 4;
+
+/// Lines are remembered this long (far longer than any copy takes to arrive)
+const SEEN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Most lines remembered at once (the oldest are forgotten first)
+const MAX_SEEN: usize = 4096;
+
+/// Chat lines already shown or relayed
+#[derive(Debug, Default)]
+pub struct SeenChats {
+    /// (sender, Lamport time, text hash) -> when first seen
+    seen: HashMap<(String, u64, u64), Instant>,
+}
+
+impl SeenChats {
+    /// Note a line, returning false if it was seen before. The text is part
+    /// of the key so a restarted sender's reused Lamport times aren't mistaken
+    /// for copies.
+    pub fn insert(&mut self, from: &str, lamport: u64, text: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let key = (from.to_string(), lamport, hasher.finish());
+        let now = Instant::now();
+        if self.seen.contains_key(&key) {
+            return false;
+        }
+        if self.seen.len() >= MAX_SEEN {
+            self.seen
+                .retain(|_, seen| now.duration_since(*seen) < SEEN_TIMEOUT);
+            if self.seen.len() >= MAX_SEEN
+                && let Some(oldest) = self
+                    .seen
+                    .iter()
+                    .min_by_key(|(_, seen)| **seen)
+                    .map(|(key, _)| key.clone())
+            {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_chats() {
+        let mut seen = SeenChats::default();
+        assert!(seen.insert("alice", 7, "hi"));
+        assert!(!seen.insert("alice", 7, "hi"));
+        // Same Lamport time, but another sender or other words
+        assert!(seen.insert("bob", 7, "hi"));
+        assert!(seen.insert("alice", 7, "hello"));
+
+        for i in 0..MAX_SEEN as u64 {
+            seen.insert("carol", i, "spam");
+        }
+        assert_eq!(seen.seen.len(), MAX_SEEN);
+    }
+}
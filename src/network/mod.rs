@@ -1,41 +1,102 @@
 //! Networking module for peer-to-peer communication.
 //!
 //! Uses UDP for low-latency messaging with STUN for NAT traversal
-//! and UPnP for port forwarding when available.
+//! and UPnP for port forwarding when available. The peer socket is
+//! dual-stack, so IPv4 and IPv6 peers share one port.
 
-use std::collections::{HashMap, HashSet};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
-use tokio::net::UdpSocket;
 
+use serde::{Deserialize, Serialize};
+
+mod blake3;
+mod capture;
+mod codec;
+mod congestion;
+mod delta;
 mod discovery;
+mod files;
+mod history;
+mod impair;
+mod keepalive;
+mod mdns;
+mod mesh;
+mod receipts;
+mod relay;
+mod roster;
+mod scheduler;
+mod socket;
+mod stats;
 mod stun;
+mod todo;
+mod tunesync;
 mod upnp;
-
-pub use discovery::{DiscoveredPeer, Discovery, PEER_TIMEOUT, run_discovery};
+mod version;
+mod whois;
+
+pub use blake3::HASH_LEN;
+use delta::{DeltaDecoder, DeltaEncoder};
+
+pub use capture::ProtocolTap;
+pub use codec::frame_version;
+pub use congestion::{VideoReceiver, VideoSender};
+pub use discovery::{
+    DEFAULT_ANNOUNCE_INTERVAL, DEFAULT_DISCOVERY_PORT, DiscoveredPeer, Discovery,
+    DiscoverySettings, PEER_TIMEOUT, run_discovery,
+};
+pub use files::{FileTransfers, TransferEvent};
+pub use history::{Backfill, ChatHistory, HistoryEntry};
+pub use impair::Impairment;
+pub use keepalive::{DEFAULT_KEEPALIVE_SECS, spawn_public_addr_monitor};
+pub use mesh::MESH_TTL;
+pub use receipts::Receipts;
+pub use relay::{DEFAULT_RELAY_PORT, run_relay};
+pub use roster::Roster;
+pub use socket::{PeerSocket, RecvBatch, parse_peer_addr, with_port};
+pub use stats::{PeerStats, Traffic};
 pub use stun::discover_public_endpoint;
+pub use todo::{MAX_TODO_LEN, TodoItem, TodoList, TodoOp};
+pub use tunesync::{SyncTrack, TunesSync, list_parts, scan_library, want_parts};
 pub use upnp::setup_port_forward;
+pub use version::{APP_VERSION, PROTOCOL_VERSION, is_newer_release};
+pub use whois::{
+    CAP_ACCENTS, CAP_AUDIO, CAP_COLOR, CAP_FILES, CAP_VIDEO, Caps, MAX_PROFILE_LEN, WhoisInfo,
+};
 
 /// Message types for the protocol
-#[derive(Debug, Clone)]
+///
+/// Sent with the serde codec in [`codec`]; variants are identified by position,
+/// so add new ones at the end and bump [`codec::PROTOCOL_VERSION`] for any
+/// other change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Message {
-    /// Text chat message
-    Chat { from: String, text: String },
+    /// Text chat message, with the sender's Lamport timestamp (if it keeps one)
+    /// and the hops it may still be relayed (see [`mesh`]). The id is random,
+    /// picked by the sender, for `ChatEdit` to refer to.
+    Chat {
+        from: String,
+        id: u64,
+        text: String,
+        lamport: Option<u64>,
+        ttl: u8,
+    },
     /// Ping to check connectivity
     Ping { seq: u32 },
     /// Pong response
     Pong { seq: u32 },
-    /// Join notification
-    Join { name: String },
+    /// Join notification, with the sender's Wormhole release
+    Join { name: String, version: String },
     /// Leave notification
     Leave { name: String },
     /// Call request
     CallRequest { from: String },
     /// Call hangup notification
     CallHangup { from: String },
-    /// Call rejected (busy)
-    CallReject { from: String },
+    /// Call rejected, and why ("busy", "declined")
+    CallReject { from: String, reason: String },
     /// Stream frame (ASCII art lines) - deprecated, kept for compatibility
     StreamFrame { from: String, lines: Vec<String> },
     /// Video frame (raw grayscale image data for receiver-side rendering)
@@ -43,6 +104,7 @@ pub enum Message {
         from: String,
         width: u16,
         height: u16,
+        #[serde(with = "codec::lz4")]
         pixels: Vec<u8>,
     },
     /// Video frame fragment (for large frames that exceed UDP MTU)
@@ -53,364 +115,224 @@ pub enum Message {
         frame_id: u8,        // Unique ID for this frame (wraps around)
         fragment_idx: u8,    // Which fragment this is (0-indexed)
         total_fragments: u8, // Total number of fragments
-        data: Vec<u8>,       // Compressed pixel data fragment
+        key_id: Option<u8>,  // Keyframe this is a delta against (None for a keyframe)
+        #[serde(with = "codec::raw")]
+        data: Vec<u8>, // Compressed pixel data fragment
+        hash: [u8; HASH_LEN], // BLAKE3 of the whole frame's pixels
     },
     /// Discovery announce (sent to main port as fallback for SO_REUSEPORT issues)
-    DiscoveryAnnounce { name: String, port: u16 },
+    DiscoveryAnnounce {
+        name: String,
+        port: u16,
+        version: String,
+    },
+    /// Sender's wall clock (ms since the Unix epoch), for spotting clock skew
+    ClockSync { from: String, unix_ms: i64 },
+    /// Ask a peer for its recent chat history (sent on joining)
+    HistoryRequest { from: String },
+    /// One part of a reply to a history request
+    HistoryChunk {
+        from: String,
+        part: u8,
+        total: u8,
+        entries: Vec<HistoryEntry>,
+    },
+    /// The room topic (sent when it changes and to peers that join)
+    Topic(RoomTopic),
+    /// Running count of the video fragments (and bytes) received from a call peer
+    VideoReport {
+        from: String,
+        fragments: u32,
+        bytes: u32,
+    },
+    /// Ask a peer to describe itself (`/whois`)
+    WhoisRequest { from: String },
+    /// A peer's description of itself
+    WhoisReply(WhoisInfo),
+    /// The sender changed its name (`/nick`)
+    Nick { old: String, new: String },
+    /// Reply to a `Join` under our own name; `since` is when the sender started
+    /// (ms since the Unix epoch), so the newer of the two nodes renames itself
+    NickInUse { name: String, since: i64 },
+    /// Offer a file (repeated until the receiver answers), with the BLAKE3
+    /// hash the received file must match
+    FileOffer {
+        from: String,
+        id: u64,
+        name: String,
+        size: u64,
+        hash: [u8; HASH_LEN],
+    },
+    /// Ask for chunks of an accepted file
+    FileRequest {
+        from: String,
+        id: u64,
+        chunks: Vec<u32>,
+    },
+    /// One chunk of a file, LZ4-compressed, with the BLAKE3 hash of its
+    /// uncompressed bytes
+    FileChunk {
+        from: String,
+        id: u64,
+        index: u32,
+        #[serde(with = "codec::raw")]
+        data: Vec<u8>,
+        hash: [u8; HASH_LEN],
+    },
+    /// The whole file arrived
+    FileDone { from: String, id: u64 },
+    /// A transfer was cancelled, or the sender doesn't know it
+    FileCancel { from: String, id: u64 },
+    /// Acknowledges the sender's chat message with this Lamport time
+    ChatAck { from: String, lamport: u64 },
+    /// Keeps the NAT mapping to a peer open and tells it we're still here
+    Keepalive { from: String },
+    /// The pinned line (sent when it changes and to peers that join). It
+    /// travels and is superseded just like the topic.
+    Pin(RoomTopic),
+    /// Changes to the shared to-do list (sent as they're made, and the whole
+    /// list to peers that join)
+    Todo { from: String, ops: Vec<TodoOp> },
+    /// A timer started for everyone (`/timer share`), from `started_ms` by the
+    /// sender's clock; no length means a pomodoro session
+    TimerStart {
+        from: String,
+        label: String,
+        started_ms: i64,
+        length_secs: Option<u32>,
+    },
+    /// The sender stopped the timer it shared
+    TimerStop { from: String },
+    /// Asks the recipient's terminal to ring its bell (`/ring`)
+    Ring { from: String },
+    /// Whether the bell was rung (the recipient's `ring` setting decides)
+    RingReply { from: String, rang: bool },
+    /// The sender corrected its chat message `id` to `text`, or deleted it if
+    /// the text is empty (`/edit`); relayed like chat
+    ChatEdit {
+        from: String,
+        id: u64,
+        text: String,
+        ttl: u8,
+    },
+    /// Part of the sender's Tunes listing for `/tunes sync` (`reply` when
+    /// answering one; None if it doesn't sync)
+    TunesList {
+        from: String,
+        reply: bool,
+        tracks: Option<Vec<SyncTrack>>,
+    },
+    /// The tracks, by hash, the sender wants from the recipient's listing
+    TunesWant {
+        from: String,
+        hashes: Vec<[u8; HASH_LEN]>,
+    },
+    /// A line typed in a chat window other than the mesh's: `to` is a
+    /// `#channel`, broadcast to every peer, or the recipient's name
+    WindowChat {
+        from: String,
+        to: String,
+        text: String,
+    },
+    /// A picture shared in chat (`/imagefile`), as raw grayscale for each
+    /// peer to render for its own terminal
+    ImageShare {
+        from: String,
+        width: u16,
+        height: u16,
+        #[serde(with = "codec::lz4")]
+        pixels: Vec<u8>,
+        lamport: u64,
+    },
 }
 
 impl Message {
-    /// Serialize message to bytes
+    /// Serialize message to a framed datagram
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        match self {
-            Message::Chat { from, text } => {
-                buf.push(0x01);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-                buf.extend((text.len() as u16).to_be_bytes());
-                buf.extend(text.as_bytes());
-            }
-            Message::Ping { seq } => {
-                buf.push(0x02);
-                buf.extend(seq.to_be_bytes());
-            }
-            Message::Pong { seq } => {
-                buf.push(0x03);
-                buf.extend(seq.to_be_bytes());
-            }
-            Message::Join { name } => {
-                buf.push(0x04);
-                buf.push(name.len() as u8);
-                buf.extend(name.as_bytes());
-            }
-            Message::Leave { name } => {
-                buf.push(0x05);
-                buf.push(name.len() as u8);
-                buf.extend(name.as_bytes());
-            }
-            Message::CallRequest { from } => {
-                buf.push(0x07);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-            }
-            Message::CallHangup { from } => {
-                buf.push(0x08);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-            }
-            Message::CallReject { from } => {
-                buf.push(0x09);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-            }
-            Message::StreamFrame { from, lines } => {
-                buf.push(0x06);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-                buf.push(lines.len() as u8);
-                for line in lines {
-                    buf.extend((line.len() as u16).to_be_bytes());
-                    buf.extend(line.as_bytes());
-                }
-            }
-            Message::VideoFrame {
-                from,
-                width,
-                height,
-                pixels,
-            } => {
-                buf.push(0x0A);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-                buf.extend(width.to_be_bytes());
-                buf.extend(height.to_be_bytes());
-                // Store uncompressed size, then LZ4 compressed data
-                buf.extend((pixels.len() as u32).to_be_bytes());
-                let compressed = lz4_flex::compress_prepend_size(pixels);
-                buf.extend((compressed.len() as u32).to_be_bytes());
-                buf.extend(&compressed);
-            }
-            Message::VideoFrameFragment {
-                from,
-                width,
-                height,
-                frame_id,
-                fragment_idx,
-                total_fragments,
-                data,
-            } => {
-                buf.push(0x0C);
-                buf.push(from.len() as u8);
-                buf.extend(from.as_bytes());
-                buf.extend(width.to_be_bytes());
-                buf.extend(height.to_be_bytes());
-                buf.push(*frame_id);
-                buf.push(*fragment_idx);
-                buf.push(*total_fragments);
-                buf.extend((data.len() as u32).to_be_bytes());
-                buf.extend(data);
-            }
-            Message::DiscoveryAnnounce { name, port } => {
-                buf.push(0x0B);
-                buf.extend(port.to_be_bytes());
-                buf.push(name.len() as u8);
-                buf.extend(name.as_bytes());
-            }
-        }
-        buf
+        codec::encode(self).expect("messages only use types the codec supports")
     }
 
-    /// Deserialize message from bytes
+    /// Deserialize a datagram (None for foreign, malformed or other-version packets)
     pub fn from_bytes(data: &[u8]) -> Option<Self> {
-        if data.is_empty() {
-            return None;
-        }
-
-        match data[0] {
-            0x01 => {
-                // Chat message
-                if data.len() < 4 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len + 2 {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-                let text_len =
-                    u16::from_be_bytes([data[2 + from_len], data[3 + from_len]]) as usize;
-                if data.len() < 4 + from_len + text_len {
-                    return None;
-                }
-                let text = String::from_utf8_lossy(&data[4 + from_len..4 + from_len + text_len])
-                    .to_string();
-                Some(Message::Chat { from, text })
-            }
-            0x02 => {
-                // Ping
-                if data.len() < 5 {
-                    return None;
-                }
-                let seq = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
-                Some(Message::Ping { seq })
-            }
-            0x03 => {
-                // Pong
-                if data.len() < 5 {
-                    return None;
-                }
-                let seq = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
-                Some(Message::Pong { seq })
-            }
-            0x04 => {
-                // Join
-                if data.len() < 2 {
-                    return None;
-                }
-                let name_len = data[1] as usize;
-                if data.len() < 2 + name_len {
-                    return None;
-                }
-                let name = String::from_utf8_lossy(&data[2..2 + name_len]).to_string();
-                Some(Message::Join { name })
-            }
-            0x05 => {
-                // Leave
-                if data.len() < 2 {
-                    return None;
-                }
-                let name_len = data[1] as usize;
-                if data.len() < 2 + name_len {
-                    return None;
-                }
-                let name = String::from_utf8_lossy(&data[2..2 + name_len]).to_string();
-                Some(Message::Leave { name })
-            }
-            0x07 => {
-                // CallRequest
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-                Some(Message::CallRequest { from })
-            }
-            0x08 => {
-                // CallHangup
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-                Some(Message::CallHangup { from })
-            }
-            0x09 => {
-                // CallReject
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-                Some(Message::CallReject { from })
-            }
-            0x06 => {
-                // StreamFrame
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len + 1 {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-
-                let mut offset = 2 + from_len;
-                let num_lines = data[offset] as usize;
-                offset += 1;
-
-                let mut lines = Vec::with_capacity(num_lines);
-                for _ in 0..num_lines {
-                    if data.len() < offset + 2 {
-                        return None;
-                    }
-                    let line_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
-                    offset += 2;
-
-                    if data.len() < offset + line_len {
-                        return None;
-                    }
-                    let line =
-                        String::from_utf8_lossy(&data[offset..offset + line_len]).to_string();
-                    lines.push(line);
-                    offset += line_len;
-                }
-
-                Some(Message::StreamFrame { from, lines })
-            }
-            0x0A => {
-                // VideoFrame (LZ4 compressed)
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len + 12 {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-
-                let mut offset = 2 + from_len;
-                let width = u16::from_be_bytes([data[offset], data[offset + 1]]);
-                offset += 2;
-                let height = u16::from_be_bytes([data[offset], data[offset + 1]]);
-                offset += 2;
-                // Uncompressed size (for validation)
-                let _uncompressed_len = u32::from_be_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]) as usize;
-                offset += 4;
-                // Compressed size
-                let compressed_len = u32::from_be_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]) as usize;
-                offset += 4;
-
-                if data.len() < offset + compressed_len {
-                    return None;
-                }
-                let compressed = &data[offset..offset + compressed_len];
+        codec::decode(data).ok()
+    }
 
-                // Decompress the pixel data
-                let pixels = match lz4_flex::decompress_size_prepended(compressed) {
-                    Ok(p) => p,
-                    Err(_) => return None,
-                };
+    /// Variant name, for protocol debug logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Chat { .. } => "Chat",
+            Message::Ping { .. } => "Ping",
+            Message::Pong { .. } => "Pong",
+            Message::Join { .. } => "Join",
+            Message::Leave { .. } => "Leave",
+            Message::CallRequest { .. } => "CallRequest",
+            Message::CallHangup { .. } => "CallHangup",
+            Message::CallReject { .. } => "CallReject",
+            Message::StreamFrame { .. } => "StreamFrame",
+            Message::VideoFrame { .. } => "VideoFrame",
+            Message::VideoFrameFragment { .. } => "VideoFrameFragment",
+            Message::ImageShare { .. } => "ImageShare",
+            Message::DiscoveryAnnounce { .. } => "DiscoveryAnnounce",
+            Message::ClockSync { .. } => "ClockSync",
+            Message::HistoryRequest { .. } => "HistoryRequest",
+            Message::HistoryChunk { .. } => "HistoryChunk",
+            Message::Topic(_) => "Topic",
+            Message::VideoReport { .. } => "VideoReport",
+            Message::WhoisRequest { .. } => "WhoisRequest",
+            Message::WhoisReply(_) => "WhoisReply",
+            Message::Nick { .. } => "Nick",
+            Message::NickInUse { .. } => "NickInUse",
+            Message::FileOffer { .. } => "FileOffer",
+            Message::FileRequest { .. } => "FileRequest",
+            Message::FileChunk { .. } => "FileChunk",
+            Message::FileDone { .. } => "FileDone",
+            Message::FileCancel { .. } => "FileCancel",
+            Message::ChatAck { .. } => "ChatAck",
+            Message::Keepalive { .. } => "Keepalive",
+            Message::Pin(_) => "Pin",
+            Message::Todo { .. } => "Todo",
+            Message::TimerStart { .. } => "TimerStart",
+            Message::TimerStop { .. } => "TimerStop",
+            Message::Ring { .. } => "Ring",
+            Message::RingReply { .. } => "RingReply",
+            Message::ChatEdit { .. } => "ChatEdit",
+            Message::TunesList { .. } => "TunesList",
+            Message::TunesWant { .. } => "TunesWant",
+            Message::WindowChat { .. } => "WindowChat",
+        }
+    }
+}
 
-                Some(Message::VideoFrame {
-                    from,
-                    width,
-                    height,
-                    pixels,
-                })
-            }
-            0x0B => {
-                // DiscoveryAnnounce
-                if data.len() < 4 {
-                    return None;
-                }
-                let port = u16::from_be_bytes([data[1], data[2]]);
-                let name_len = data[3] as usize;
-                if data.len() < 4 + name_len {
-                    return None;
-                }
-                let name = String::from_utf8_lossy(&data[4..4 + name_len]).to_string();
-                Some(Message::DiscoveryAnnounce { name, port })
-            }
-            0x0C => {
-                // VideoFrameFragment
-                if data.len() < 2 {
-                    return None;
-                }
-                let from_len = data[1] as usize;
-                if data.len() < 2 + from_len + 11 {
-                    return None;
-                }
-                let from = String::from_utf8_lossy(&data[2..2 + from_len]).to_string();
-
-                let mut offset = 2 + from_len;
-                let width = u16::from_be_bytes([data[offset], data[offset + 1]]);
-                offset += 2;
-                let height = u16::from_be_bytes([data[offset], data[offset + 1]]);
-                offset += 2;
-                let frame_id = data[offset];
-                offset += 1;
-                let fragment_idx = data[offset];
-                offset += 1;
-                let total_fragments = data[offset];
-                offset += 1;
-                let data_len = u32::from_be_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]) as usize;
-                offset += 4;
-
-                if data.len() < offset + data_len {
-                    return None;
-                }
-                let frag_data = data[offset..offset + data_len].to_vec();
+/// A room topic and who set it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomTopic {
+    pub text: String,
+    pub set_by: String,
+    /// When it was set, in ms since the Unix epoch
+    pub set_at: i64,
+}
 
-                Some(Message::VideoFrameFragment {
-                    from,
-                    width,
-                    height,
-                    frame_id,
-                    fragment_idx,
-                    total_fragments,
-                    data: frag_data,
-                })
-            }
-            _ => None,
-        }
+impl RoomTopic {
+    /// Whether this topic replaces `other`: the most recently set topic wins
+    /// everywhere, with the setter's name breaking ties
+    pub fn supersedes(&self, other: &RoomTopic) -> bool {
+        (self.set_at, &self.set_by) > (other.set_at, &other.set_by)
     }
 }
 
+/// `name` with the lowest numeric suffix ("name-2", "name-3", ...) not taken.
+/// An existing suffix is replaced rather than stacked.
+pub fn next_free_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let base = match name.rsplit_once('-') {
+        Some((base, n)) if !base.is_empty() && n.parse::<u32>().is_ok() => base,
+        _ => name,
+    };
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|candidate| candidate != name && !taken(candidate))
+        .unwrap()
+}
+
 /// Peer connection state
 #[derive(Debug, Clone)]
 pub struct Peer {
@@ -420,11 +342,110 @@ pub struct Peer {
     pub addr: SocketAddr,
     /// Last time we heard from this peer
     pub last_seen: std::time::Instant,
+    /// How far the peer's wall clock is ahead of ours, in seconds (None = not reported)
+    pub clock_offset: Option<i64>,
+    /// Round-trip time and loss measured with pings
+    pub latency: Latency,
+    /// Wormhole release the peer announced (None = not reported)
+    pub version: Option<String>,
+}
+
+/// Ping results for one peer
+#[derive(Debug, Clone, Default)]
+pub struct Latency {
+    /// Smoothed round-trip time (None = no pong yet)
+    pub rtt: Option<Duration>,
+    /// Whether each of the last [`PING_HISTORY`] pings was answered, oldest first
+    answered: VecDeque<bool>,
+}
+
+impl Latency {
+    /// Record a pong that arrived after `sample`
+    fn record_pong(&mut self, sample: Duration) {
+        // Exponential moving average, weighted like TCP's SRTT
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+        self.record(true);
+    }
+
+    fn record(&mut self, answered: bool) {
+        if self.answered.len() >= PING_HISTORY {
+            self.answered.pop_front();
+        }
+        self.answered.push_back(answered);
+    }
+
+    /// Percentage of recent pings that went unanswered (None = none sent yet)
+    pub fn loss_percent(&self) -> Option<u32> {
+        if self.answered.is_empty() {
+            return None;
+        }
+        let lost = self.answered.iter().filter(|a| !**a).count();
+        Some((lost * 100 / self.answered.len()) as u32)
+    }
+}
+
+impl std::fmt::Display for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rtt {
+            Some(rtt) => write!(f, "{}ms", rtt.as_millis())?,
+            None => write!(f, "no reply")?,
+        }
+        match self.loss_percent() {
+            Some(loss) if loss > 0 => write!(f, ", {}% loss", loss),
+            _ => Ok(()),
+        }
+    }
 }
 
+/// How often each peer is pinged
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A ping without a pong after this long counts as lost
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of recent pings the loss estimate covers
+const PING_HISTORY: usize = 20;
+
 /// Grace period after a peer leaves before we accept discovery from them again
 const LEAVE_GRACE_PERIOD: Duration = Duration::from_secs(2);
 
+/// Peer clocks further apart than this (in seconds) get a warning
+pub const CLOCK_SKEW_WARNING: i64 = 60;
+
+/// Most bytes of queued traffic sent per flush without an upload limit, so
+/// one peer's burst is interleaved with the others' over a few loop ticks
+const MAX_FLUSH_BYTES: usize = 64 * 1024;
+
+/// Incomplete video frames are dropped after this long
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most video frames reassembled at once (a misbehaving peer can't grow this without bound)
+const MAX_FRAGMENT_BUFFERS: usize = 32;
+
+/// Most senders whose video keyframes we keep (the least recently used go first)
+const MAX_VIDEO_DECODERS: usize = 8;
+
+/// Forget addresses we haven't connected to in this long
+const KNOWN_ADDR_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Most remembered peer addresses (oldest are forgotten first)
+const MAX_KNOWN_ADDRS: usize = 1024;
+
+/// Sizes of the node's caches, for `/memstats`
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub peers: usize,
+    pub known_addrs: usize,
+    pub recently_left: usize,
+    pub fragment_buffers: usize,
+    pub fragment_bytes: usize,
+    /// Peers we keep a video keyframe for, sending and receiving
+    pub video_coders: usize,
+}
+
 /// Buffer for reassembling fragmented video frames
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -434,19 +455,33 @@ struct FragmentBuffer {
     height: u16,
     frame_id: u8,
     total_fragments: u8,
+    /// Keyframe the frame is a delta against (None for a keyframe)
+    key_id: Option<u8>,
     fragments: Vec<Option<Vec<u8>>>,
+    /// What the reassembled pixels must hash to
+    hash: [u8; HASH_LEN],
     received_at: Instant,
 }
 
 impl FragmentBuffer {
-    fn new(from: String, width: u16, height: u16, frame_id: u8, total_fragments: u8) -> Self {
+    fn new(
+        from: String,
+        width: u16,
+        height: u16,
+        frame_id: u8,
+        total_fragments: u8,
+        key_id: Option<u8>,
+        hash: [u8; HASH_LEN],
+    ) -> Self {
         Self {
             from,
             width,
             height,
             frame_id,
             total_fragments,
+            key_id,
             fragments: vec![None; total_fragments as usize],
+            hash,
             received_at: Instant::now(),
         }
     }
@@ -461,6 +496,8 @@ impl FragmentBuffer {
         self.fragments.iter().all(|f| f.is_some())
     }
 
+    /// The frame's data (its pixels, or a delta), or None if it doesn't
+    /// decompress
     fn reassemble(&self) -> Option<Vec<u8>> {
         if !self.is_complete() {
             return None;
@@ -473,31 +510,66 @@ impl FragmentBuffer {
             .copied()
             .collect();
 
-        // Decompress
         lz4_flex::decompress_size_prepended(&compressed).ok()
     }
 }
 
 /// Network node for P2P communication
 pub struct NetworkNode {
-    socket: Arc<UdpSocket>,
+    socket: Arc<PeerSocket>,
     local_addr: SocketAddr,
     public_addr: Option<SocketAddr>,
     peers: Vec<Peer>,
-    /// Set of all peer addresses we've ever connected to (persists across disconnects)
-    known_addrs: HashSet<SocketAddr>,
+    /// Peer addresses we've connected to and when we last saw them (persists across disconnects)
+    known_addrs: HashMap<SocketAddr, Instant>,
     /// Addresses that recently sent Leave messages (addr -> time of leave)
     recently_left: HashMap<SocketAddr, Instant>,
     name: String,
     /// Fragment buffers for reassembling video frames (keyed by (peer_name, frame_id))
     fragment_buffers: HashMap<(String, u8), FragmentBuffer>,
+    /// Delta coding for the video we send each peer
+    video_encoders: HashMap<SocketAddr, DeltaEncoder>,
+    /// And for the video each peer sends us (by name), with when each last
+    /// decoded a frame
+    video_decoders: HashMap<String, (DeltaDecoder, Instant)>,
+    /// When we last sent an allocation request to the relay (None = never)
+    relay_last_refresh: Option<Instant>,
+    /// Lamport clock for ordering chat across the mesh
+    lamport: AtomicU64,
+    /// Sequence number for the next ping
+    ping_seq: u32,
+    /// Pings awaiting a pong (seq -> peer address and send time)
+    pending_pings: HashMap<u32, (SocketAddr, Instant)>,
+    /// When we last pinged everyone (None = never)
+    last_ping: Option<Instant>,
+    /// Addresses whose datagrams use another protocol version (addr -> version)
+    incompatible: HashMap<SocketAddr, u8>,
+    /// Newer releases we've already suggested upgrading to
+    newer_releases: HashSet<String>,
+    /// How often peers are sent a keepalive (None = never)
+    keepalive_interval: Option<Duration>,
+    /// When we last sent keepalives (None = never)
+    last_keepalive: Option<Instant>,
+    /// Frame rates, lost frames and retransmissions by peer, for the Stats tab
+    link_stats: stats::LinkStats,
+    /// Chat lines already seen, so relayed copies are dropped
+    seen_chats: mesh::SeenChats,
+    /// Corrections already seen, by message id
+    seen_edits: mesh::SeenChats,
+    /// Pass peers' chat on to our other peers
+    mesh_relay: bool,
+    /// Video fragments and file chunks waiting for their turn
+    send_queue: scheduler::SendQueue,
+    /// Most bytes per second of queued traffic sent (None = no limit)
+    upload_limit: Option<usize>,
+    /// Bytes the upload limit allows right now, and when that was worked out
+    upload_allowance: (f64, Instant),
 }
 
 impl NetworkNode {
     /// Create a new network node
     pub async fn new(name: String, port: u16) -> Result<Self, NetworkError> {
-        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
-        let socket = UdpSocket::bind(bind_addr)
+        let socket = PeerSocket::bind(port)
             .await
             .map_err(|e| NetworkError::Bind(e.to_string()))?;
 
@@ -510,27 +582,83 @@ impl NetworkNode {
             local_addr,
             public_addr: None,
             peers: Vec::new(),
-            known_addrs: HashSet::new(),
+            known_addrs: HashMap::new(),
             recently_left: HashMap::new(),
             name,
             fragment_buffers: HashMap::new(),
+            video_encoders: HashMap::new(),
+            video_decoders: HashMap::new(),
+            relay_last_refresh: None,
+            lamport: AtomicU64::new(0),
+            ping_seq: 0,
+            pending_pings: HashMap::new(),
+            last_ping: None,
+            keepalive_interval: Some(Duration::from_secs(DEFAULT_KEEPALIVE_SECS)),
+            last_keepalive: None,
+            incompatible: HashMap::new(),
+            newer_releases: HashSet::new(),
+            link_stats: stats::LinkStats::default(),
+            seen_chats: mesh::SeenChats::default(),
+            seen_edits: mesh::SeenChats::default(),
+            mesh_relay: true,
+            send_queue: scheduler::SendQueue::default(),
+            upload_limit: None,
+            upload_allowance: (0.0, Instant::now()),
         })
     }
 
+    /// Send protocol debug output for all peer traffic to `tap`
+    pub fn set_protocol_tap(&self, tap: ProtocolTap) {
+        self.socket.set_tap(tap);
+    }
+
+    /// Simulate a lossy, slow network for all peer traffic
+    pub fn set_impairment(&self, impairment: Impairment) {
+        self.socket.set_impairment(impairment);
+    }
+
     /// Set the public address (from STUN discovery)
     pub fn set_public_addr(&mut self, addr: SocketAddr) {
         self.public_addr = Some(addr);
     }
 
+    /// Take a public address from a STUN refresh. Returns the previous one if
+    /// our public IP has changed (STUN's port is its own, so only the IP counts).
+    pub fn refresh_public_addr(&mut self, addr: SocketAddr) -> Option<SocketAddr> {
+        let old = self.public_addr.replace(addr)?;
+        (old.ip() != addr.ip()).then_some(old)
+    }
+
+    /// Relay peers' chat lines to our other peers (on by default)
+    pub fn set_mesh_relay(&mut self, relay: bool) {
+        self.mesh_relay = relay;
+    }
+
+    /// Send queued video and file traffic at most this many bytes per second
+    pub fn set_upload_limit(&mut self, bytes_per_sec: Option<usize>) {
+        self.upload_limit = bytes_per_sec;
+    }
+
+    /// Send peers a keepalive this often (None = not at all)
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive_interval = interval;
+    }
+
     /// Add a peer by address
     pub fn add_peer(&mut self, name: String, addr: SocketAddr) {
         // Don't add ourselves
-        if Some(addr) == self.public_addr || addr == self.local_addr {
+        if Some(addr) == self.public_addr
+            || addr == self.local_addr
+            || Some(addr) == self.socket.relayed_addr()
+        {
             return;
         }
 
-        // Track this address permanently
-        self.known_addrs.insert(addr);
+        // Remember this address across disconnects
+        if !self.known_addrs.contains_key(&addr) && self.known_addrs.len() >= MAX_KNOWN_ADDRS {
+            evict_oldest(&mut self.known_addrs);
+        }
+        self.known_addrs.insert(addr, Instant::now());
 
         // Update existing peer or add new one
         if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr) {
@@ -541,14 +669,46 @@ impl NetworkNode {
                 name,
                 addr,
                 last_seen: std::time::Instant::now(),
+                clock_offset: None,
+                latency: Latency::default(),
+                version: None,
             });
         }
     }
 
+    /// Rename the peer at `addr`, returning its old name
+    pub fn rename_peer(&mut self, addr: SocketAddr, name: String) -> Option<String> {
+        let peer = self.peers.iter_mut().find(|p| p.addr == addr)?;
+        self.link_stats.rename_peer(&peer.name, &name);
+        Some(std::mem::replace(&mut peer.name, name))
+    }
+
+    /// Whether a peer other than the one at `addr` goes by `name`
+    pub fn name_taken(&self, name: &str, addr: Option<SocketAddr>) -> bool {
+        self.peers
+            .iter()
+            .any(|p| p.name == name && Some(p.addr) != addr)
+    }
+
+    /// Use a new name in everything we send from now on
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     /// Remove a peer by address and record their departure time
     pub fn remove_peer(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.iter().find(|p| p.addr == addr) {
+            self.link_stats.forget(&peer.name);
+            self.video_decoders.remove(&peer.name);
+        }
+        self.socket.forget_traffic(addr);
+        self.send_queue.forget(addr);
+        self.video_encoders.remove(&addr);
         self.peers.retain(|p| p.addr != addr);
-        self.recently_left.insert(addr, Instant::now());
+        let now = Instant::now();
+        self.recently_left
+            .retain(|_, left_at| now.duration_since(*left_at) < LEAVE_GRACE_PERIOD);
+        self.recently_left.insert(addr, now);
     }
 
     /// Remove stale peers (not seen in the given duration)
@@ -558,6 +718,11 @@ impl NetworkNode {
         let mut pruned = Vec::new();
         self.peers.retain(|p| {
             if now.duration_since(p.last_seen) >= timeout {
+                self.link_stats.forget(&p.name);
+                self.socket.forget_traffic(p.addr);
+                self.send_queue.forget(p.addr);
+                self.video_encoders.remove(&p.addr);
+                self.video_decoders.remove(&p.name);
                 pruned.push(p.clone());
                 false
             } else {
@@ -587,7 +752,7 @@ impl NetworkNode {
 
     /// Check if we've ever connected to a peer at this address
     pub fn knows_peer(&self, addr: SocketAddr) -> bool {
-        self.known_addrs.contains(&addr)
+        self.known_addrs.contains_key(&addr)
     }
 
     /// Check if a peer recently left (within grace period)
@@ -607,114 +772,577 @@ impl NetworkNode {
         }
     }
 
-    /// Send a message to a specific peer
-    pub async fn send_to(&self, msg: &Message, addr: SocketAddr) -> Result<(), NetworkError> {
-        let data = msg.to_bytes();
-        self.socket
-            .send_to(&data, addr)
-            .await
-            .map_err(|e| NetworkError::Send(e.to_string()))?;
-        Ok(())
+    /// Recover after a suspend or stall: give every peer a fresh timeout window,
+    /// renew the relay allocation and re-announce ourselves to everyone we know
+    /// (peers may have timed us out while we were away)
+    pub async fn resume(&mut self) {
+        let now = Instant::now();
+        for peer in &mut self.peers {
+            peer.last_seen = now;
+        }
+        self.relay_last_refresh = None;
+        self.maintain_relay().await;
+        // Pings in flight across the suspend weren't really lost
+        self.pending_pings.clear();
+        self.last_ping = None;
+        self.reannounce().await;
     }
 
-    /// Send a video frame, fragmenting if necessary to fit within UDP MTU
-    /// Max safe UDP payload is ~60KB, we use 50KB to be conservative
-    pub async fn send_video_frame(
-        &self,
-        from: &str,
-        width: u16,
-        height: u16,
-        pixels: &[u8],
-        frame_id: u8,
-        addr: SocketAddr,
-    ) -> Result<(), NetworkError> {
-        // Compress the pixels first
-        let compressed = lz4_flex::compress_prepend_size(pixels);
+    /// Announce ourselves to every address we've connected to, so peers pick
+    /// up a new address and NATs on the way open a fresh mapping
+    pub async fn reannounce(&mut self) {
+        let addrs: Vec<SocketAddr> = self.known_addrs.keys().copied().collect();
+        for addr in addrs {
+            let _ = self.send_announce(addr).await;
+        }
+    }
 
-        // Max fragment size - use 1400 bytes to stay under typical MTU (1500)
-        // and avoid IP-level fragmentation which causes packet loss
-        const MAX_FRAGMENT_SIZE: usize = 1400;
+    /// Announce ourselves to a peer we knew in an earlier session, which joins
+    /// back if it's online. The address is remembered for later re-announcements.
+    pub async fn announce_to(&mut self, addr: SocketAddr) -> Result<(), NetworkError> {
+        if !self.known_addrs.contains_key(&addr) && self.known_addrs.len() >= MAX_KNOWN_ADDRS {
+            evict_oldest(&mut self.known_addrs);
+        }
+        self.known_addrs.insert(addr, Instant::now());
+        self.send_announce(addr).await
+    }
 
-        if compressed.len() <= MAX_FRAGMENT_SIZE {
-            // Can send as a single fragment
-            let msg = Message::VideoFrameFragment {
-                from: from.to_string(),
-                width,
-                height,
-                frame_id,
-                fragment_idx: 0,
-                total_fragments: 1,
-                data: compressed,
-            };
-            self.send_to(&msg, addr).await
-        } else {
-            // Need to fragment
-            let total_fragments = compressed.len().div_ceil(MAX_FRAGMENT_SIZE);
-            if total_fragments > 255 {
-                return Err(NetworkError::Send(
-                    "Frame too large to fragment".to_string(),
-                ));
-            }
+    async fn send_announce(&self, addr: SocketAddr) -> Result<(), NetworkError> {
+        let announce = Message::DiscoveryAnnounce {
+            name: self.name.clone(),
+            port: self.local_addr.port(),
+            version: APP_VERSION.to_string(),
+        };
+        self.send_to(&announce, addr).await
+    }
 
-            for (idx, chunk) in compressed.chunks(MAX_FRAGMENT_SIZE).enumerate() {
-                let msg = Message::VideoFrameFragment {
-                    from: from.to_string(),
-                    width,
-                    height,
-                    frame_id,
-                    fragment_idx: idx as u8,
-                    total_fragments: total_fragments as u8,
-                    data: chunk.to_vec(),
-                };
-                self.send_to(&msg, addr).await?;
-            }
-            Ok(())
+    /// Send every peer a keepalive when due
+    pub async fn maintain_keepalives(&mut self) {
+        let Some(interval) = self.keepalive_interval else {
+            return;
+        };
+        if self.last_keepalive.is_some_and(|t| t.elapsed() < interval) {
+            return;
+        }
+        self.last_keepalive = Some(Instant::now());
+        let msg = Message::Keepalive {
+            from: self.name.clone(),
+        };
+        for peer in &self.peers {
+            let _ = self.send_to(&msg, peer.addr).await;
         }
     }
 
-    /// Process a video frame fragment. Returns Some(VideoFrame) if the frame is now complete.
-    #[allow(clippy::too_many_arguments)]
-    pub fn process_fragment(
-        &mut self,
-        from: String,
-        width: u16,
-        height: u16,
-        frame_id: u8,
-        fragment_idx: u8,
-        total_fragments: u8,
-        data: Vec<u8>,
-    ) -> Option<Message> {
-        // Clean up old fragment buffers (older than 2 seconds)
+    /// A keepalive arrived from `name` at `addr`. If the peer has gone quiet at
+    /// another address (its NAT gave it a new mapping), it's moved here and
+    /// the old address is returned.
+    pub fn peer_alive(&mut self, name: &str, addr: SocketAddr) -> Option<SocketAddr> {
+        if self.peers.iter().any(|p| p.addr == addr) {
+            self.touch_peer(addr);
+            return None;
+        }
+        // Only follow a peer whose old address has missed a couple of keepalives
+        let quiet = self.keepalive_interval? * 2;
+        let peer = self
+            .peers
+            .iter_mut()
+            .find(|p| p.name == name && p.last_seen.elapsed() >= quiet)?;
+        let old = std::mem::replace(&mut peer.addr, addr);
+        peer.last_seen = Instant::now();
+        self.known_addrs.insert(addr, Instant::now());
+        Some(old)
+    }
+
+    /// Drop expired entries from the address, departure and fragment caches
+    pub fn expire_caches(&mut self) {
         let now = Instant::now();
-        self.fragment_buffers
-            .retain(|_, buf| now.duration_since(buf.received_at) < Duration::from_secs(2));
+        self.expire_fragments(now);
+        self.recently_left
+            .retain(|_, left_at| now.duration_since(*left_at) < LEAVE_GRACE_PERIOD);
 
-        // Key is (peer_name, frame_id) to allow multiple frames to be assembled in parallel
-        let key = (from.clone(), frame_id);
+        // Addresses of current peers stay fresh; the rest age out
+        for peer in &self.peers {
+            if let Some(seen) = self.known_addrs.get_mut(&peer.addr) {
+                *seen = peer.last_seen;
+            }
+        }
+        self.known_addrs
+            .retain(|_, seen| now.duration_since(*seen) < KNOWN_ADDR_TTL);
+    }
 
-        // Get or create buffer for this frame
-        let buffer = self.fragment_buffers.entry(key.clone()).or_insert_with(|| {
-            FragmentBuffer::new(from.clone(), width, height, frame_id, total_fragments)
+    /// Current cache sizes
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            peers: self.peers.len(),
+            known_addrs: self.known_addrs.len(),
+            recently_left: self.recently_left.len(),
+            fragment_buffers: self.fragment_buffers.len(),
+            fragment_bytes: self
+                .fragment_buffers
+                .values()
+                .flat_map(|buf| buf.fragments.iter().flatten())
+                .map(|f| f.len())
+                .sum(),
+            video_coders: self.video_encoders.len() + self.video_decoders.len(),
+        }
+    }
+
+    /// Start the video to and from `name` afresh, when a call starts or ends,
+    /// so neither side codes against a keyframe from an earlier call
+    pub fn reset_video(&mut self, name: &str) {
+        if let Some(peer) = self.peers.iter().find(|p| p.name == name) {
+            self.video_encoders.remove(&peer.addr);
+        }
+        self.video_decoders.remove(name);
+    }
+
+    /// Ask a peer to backfill our chat with its recent history
+    pub async fn request_history(&self, addr: SocketAddr) -> Result<(), NetworkError> {
+        let msg = Message::HistoryRequest {
+            from: self.name.clone(),
+        };
+        self.send_to(&msg, addr).await
+    }
+
+    /// Send recent history to a peer that asked for it
+    pub async fn send_history(
+        &self,
+        chunks: Vec<Vec<HistoryEntry>>,
+        addr: SocketAddr,
+    ) -> Result<(), NetworkError> {
+        let total = chunks.len() as u8;
+        for (part, entries) in chunks.into_iter().enumerate() {
+            let msg = Message::HistoryChunk {
+                from: self.name.clone(),
+                part: part as u8,
+                total,
+                entries,
+            };
+            self.send_to(&msg, addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the room topic to one peer
+    pub async fn send_topic(
+        &self,
+        topic: &RoomTopic,
+        addr: SocketAddr,
+    ) -> Result<(), NetworkError> {
+        self.send_to(&Message::Topic(topic.clone()), addr).await
+    }
+
+    /// Send the pinned line to one peer
+    pub async fn send_pin(&self, pin: &RoomTopic, addr: SocketAddr) -> Result<(), NetworkError> {
+        self.send_to(&Message::Pin(pin.clone()), addr).await
+    }
+
+    /// Tell a peer what time we think it is
+    pub async fn send_clock(&self, addr: SocketAddr) -> Result<(), NetworkError> {
+        self.send_to(&self.clock_message(), addr).await
+    }
+
+    /// Tell every peer what time we think it is (e.g. after our clock was stepped)
+    pub async fn broadcast_clock(&self) -> Result<(), NetworkError> {
+        self.broadcast(&self.clock_message()).await
+    }
+
+    fn clock_message(&self) -> Message {
+        Message::ClockSync {
+            from: self.name.clone(),
+            unix_ms: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Record a peer's reported clock. Returns the offset in seconds when it has
+    /// just drifted past [`CLOCK_SKEW_WARNING`], so the warning is shown once.
+    pub fn record_peer_clock(&mut self, addr: SocketAddr, unix_ms: i64) -> Option<i64> {
+        let offset = (unix_ms - chrono::Utc::now().timestamp_millis()) / 1000;
+        let peer = self.peers.iter_mut().find(|p| p.addr == addr)?;
+        let skewed = |o: i64| o.abs() > CLOCK_SKEW_WARNING;
+        let was_skewed = peer.clock_offset.is_some_and(skewed);
+        peer.clock_offset = Some(offset);
+        (skewed(offset) && !was_skewed).then_some(offset)
+    }
+
+    /// Ping every peer when due, counting pings that went unanswered as lost
+    pub async fn maintain_pings(&mut self) {
+        if self.last_ping.is_some_and(|t| t.elapsed() < PING_INTERVAL) {
+            return;
+        }
+        let now = Instant::now();
+        self.last_ping = Some(now);
+
+        let mut lost = Vec::new();
+        self.pending_pings.retain(|_, (addr, sent)| {
+            let expired = now.duration_since(*sent) >= PING_TIMEOUT;
+            if expired {
+                lost.push(*addr);
+            }
+            !expired
+        });
+        for addr in lost {
+            if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr) {
+                peer.latency.record(false);
+            }
+        }
+
+        let addrs: Vec<SocketAddr> = self.peers.iter().map(|p| p.addr).collect();
+        for addr in addrs {
+            self.ping_seq = self.ping_seq.wrapping_add(1);
+            let seq = self.ping_seq;
+            if self.send_to(&Message::Ping { seq }, addr).await.is_ok() {
+                self.pending_pings.insert(seq, (addr, now));
+            }
+        }
+    }
+
+    /// Record the release a peer announced. Returns true the first time a peer
+    /// turns out to run a newer release than ours.
+    pub fn set_peer_version(&mut self, addr: SocketAddr, version: String) -> bool {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr) {
+            peer.version = Some(version.clone());
+        }
+        is_newer_release(&version, APP_VERSION) && self.newer_releases.insert(version)
+    }
+
+    /// Note a datagram from `addr` in another protocol version. Returns true if
+    /// that address (or its version) is new to us.
+    pub fn note_incompatible(&mut self, addr: SocketAddr, protocol: u8) -> bool {
+        self.incompatible.insert(addr, protocol) != Some(protocol)
+    }
+
+    /// Addresses we've heard from in other protocol versions
+    pub fn incompatible_peers(&self) -> impl Iterator<Item = (SocketAddr, u8)> + '_ {
+        self.incompatible.iter().map(|(addr, v)| (*addr, *v))
+    }
+
+    /// Match a pong to its ping and update that peer's round-trip time
+    pub fn record_pong(&mut self, addr: SocketAddr, seq: u32, received: Instant) {
+        let Some((sent_to, sent)) = self.pending_pings.remove(&seq) else {
+            return;
+        };
+        if sent_to != addr {
+            return;
+        }
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.addr == addr) {
+            peer.latency
+                .record_pong(received.saturating_duration_since(sent));
+        }
+    }
+
+    /// Round-trip stats for the named peer
+    pub fn peer_latency(&self, name: &str) -> Option<&Latency> {
+        self.peers
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| &p.latency)
+    }
+
+    /// Send a message to a specific peer
+    pub async fn send_to(&self, msg: &Message, addr: SocketAddr) -> Result<(), NetworkError> {
+        let data = msg.to_bytes();
+        self.socket
+            .send_to(&data, addr)
+            .await
+            .map_err(|e| NetworkError::Send(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Queue bulk traffic (video, file chunks) to be sent in turn with other
+    /// peers' (see [`scheduler`]). Returns false if the peer's queue is full.
+    pub fn queue_to(&mut self, msg: &Message, addr: SocketAddr) -> bool {
+        self.send_queue.push(addr, msg.to_bytes())
+    }
+
+    /// Send what the upload limit allows of the queued traffic, round-robin
+    /// between peers. Every datagram is tried; the first error is returned.
+    pub async fn flush_send_queue(&mut self) -> Result<(), NetworkError> {
+        if self.send_queue.is_empty() {
+            return Ok(());
+        }
+        let budget = match self.upload_limit {
+            Some(rate) => {
+                let (allowance, since) = &mut self.upload_allowance;
+                let now = Instant::now();
+                // Up to a tenth of a second's worth (and at least a datagram)
+                // can be saved up for a burst
+                let burst = (rate / 10).max(1500) as f64;
+                *allowance = (*allowance + now.duration_since(*since).as_secs_f64() * rate as f64)
+                    .min(burst);
+                *since = now;
+                if *allowance < 1.0 {
+                    return Ok(());
+                }
+                *allowance as usize
+            }
+            None => MAX_FLUSH_BYTES,
+        };
+        let mut result = Ok(());
+        let mut sent = 0;
+        for (addr, data) in self.send_queue.take(budget) {
+            sent += data.len();
+            if let Err(e) = self.socket.send_to(&data, addr).await
+                && result.is_ok()
+            {
+                result = Err(NetworkError::Send(e.to_string()));
+            }
+        }
+        if self.upload_limit.is_some() {
+            self.upload_allowance.0 -= sent as f64;
+        }
+        result
+    }
+
+    /// Send a video frame, delta coded against the peer's last keyframe and
+    /// fragmented if necessary to fit within UDP MTU. The fragments take their
+    /// turn with other peers' traffic. Returns the number of fragments queued:
+    /// none if the peer's queue has no room for the whole frame, in which case
+    /// the frame is skipped and the next one is a keyframe.
+    pub async fn send_video_frame(
+        &mut self,
+        from: &str,
+        width: u16,
+        height: u16,
+        pixels: &[u8],
+        frame_id: u8,
+        addr: SocketAddr,
+    ) -> Result<u32, NetworkError> {
+        // Code against the keyframe, then compress
+        let encoder = self.video_encoders.entry(addr).or_default();
+        let (key_id, data) = encoder.encode(frame_id, width, height, pixels);
+        let compressed = lz4_flex::compress_prepend_size(&data);
+        let hash = blake3::hash(pixels);
+
+        // Max fragment size - use 1400 bytes to stay under typical MTU (1500)
+        // and avoid IP-level fragmentation which causes packet loss
+        const MAX_FRAGMENT_SIZE: usize = 1400;
+
+        let total_fragments = compressed.len().div_ceil(MAX_FRAGMENT_SIZE);
+        if total_fragments > 255 {
+            encoder.force_keyframe();
+            return Err(NetworkError::Send(
+                "Frame too large to fragment".to_string(),
+            ));
+        }
+        let fragments: Vec<Vec<u8>> = compressed
+            .chunks(MAX_FRAGMENT_SIZE)
+            .enumerate()
+            .map(|(idx, chunk)| {
+                Message::VideoFrameFragment {
+                    from: from.to_string(),
+                    width,
+                    height,
+                    frame_id,
+                    fragment_idx: idx as u8,
+                    total_fragments: total_fragments as u8,
+                    key_id,
+                    data: chunk.to_vec(),
+                    hash,
+                }
+                .to_bytes()
+            })
+            .collect();
+
+        // Part of a frame is no use to the peer, so queue all of it or none
+        let bytes: usize = fragments.iter().map(Vec::len).sum();
+        if bytes > self.send_queue.room(addr) {
+            encoder.force_keyframe();
+            self.flush_send_queue().await?;
+            return Ok(0);
+        }
+        let mut queued = 0;
+        for fragment in fragments {
+            if self.send_queue.push(addr, fragment) {
+                queued += 1;
+            }
+        }
+        if queued < total_fragments
+            && let Some(encoder) = self.video_encoders.get_mut(&addr)
+        {
+            encoder.force_keyframe();
+        }
+        self.flush_send_queue().await?;
+        Ok(queued as u32)
+    }
+
+    /// Tell a peer that joined under our name that we had it first
+    pub async fn send_nick_in_use(&self, since: i64, addr: SocketAddr) -> Result<(), NetworkError> {
+        let msg = Message::NickInUse {
+            name: self.name.clone(),
+            since,
+        };
+        self.send_to(&msg, addr).await
+    }
+
+    /// Ask a peer for its `/whois` card
+    pub async fn request_whois(&self, addr: SocketAddr) -> Result<(), NetworkError> {
+        let msg = Message::WhoisRequest {
+            from: self.name.clone(),
+        };
+        self.send_to(&msg, addr).await
+    }
+
+    /// Tell a call peer how much of its video has arrived
+    pub async fn send_video_report(
+        &self,
+        fragments: u32,
+        bytes: u32,
+        addr: SocketAddr,
+    ) -> Result<(), NetworkError> {
+        let msg = Message::VideoReport {
+            from: self.name.clone(),
+            fragments,
+            bytes,
+        };
+        self.send_to(&msg, addr).await
+    }
+
+    /// Drop partial video frames that have waited too long for their fragments
+    fn expire_fragments(&mut self, now: Instant) {
+        let link_stats = &mut self.link_stats;
+        self.fragment_buffers.retain(|_, buf| {
+            let fresh = now.duration_since(buf.received_at) < FRAGMENT_TIMEOUT;
+            if !fresh {
+                link_stats.reassembly_failed(&buf.from);
+            }
+            fresh
+        });
+    }
+
+    /// Process a video frame fragment. Returns Some(VideoFrame) if the frame is
+    /// now complete, or an error if it was completed but arrived corrupted. A
+    /// delta against a keyframe we missed is dropped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_fragment(
+        &mut self,
+        from: String,
+        width: u16,
+        height: u16,
+        frame_id: u8,
+        fragment_idx: u8,
+        total_fragments: u8,
+        key_id: Option<u8>,
+        data: Vec<u8>,
+        hash: [u8; HASH_LEN],
+    ) -> Result<Option<Message>, NetworkError> {
+        // Clean up old fragment buffers
+        let now = Instant::now();
+        self.expire_fragments(now);
+
+        // Key is (peer_name, frame_id) to allow multiple frames to be assembled in parallel
+        let key = (from.clone(), frame_id);
+
+        // Make room by dropping the oldest partial frame
+        if !self.fragment_buffers.contains_key(&key)
+            && self.fragment_buffers.len() >= MAX_FRAGMENT_BUFFERS
+            && let Some(oldest) = self
+                .fragment_buffers
+                .iter()
+                .min_by_key(|(_, buf)| buf.received_at)
+                .map(|(key, _)| key.clone())
+        {
+            self.fragment_buffers.remove(&oldest);
+            self.link_stats.reassembly_failed(&oldest.0);
+        }
+
+        // Get or create buffer for this frame
+        let buffer = self.fragment_buffers.entry(key.clone()).or_insert_with(|| {
+            FragmentBuffer::new(
+                from.clone(),
+                width,
+                height,
+                frame_id,
+                total_fragments,
+                key_id,
+                hash,
+            )
         });
 
         // Add the fragment
         buffer.add_fragment(fragment_idx, data);
 
         // Check if complete and reassemble
-        if buffer.is_complete()
-            && let Some(pixels) = buffer.reassemble()
+        if !buffer.is_complete() {
+            return Ok(None);
+        }
+        let data = buffer.reassemble();
+        let key_id = buffer.key_id;
+        self.fragment_buffers.remove(&key);
+        // Make room by forgetting the sender we've heard from least lately
+        if !self.video_decoders.contains_key(&from)
+            && self.video_decoders.len() >= MAX_VIDEO_DECODERS
+            && let Some(oldest) = self
+                .video_decoders
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(name, _)| name.clone())
         {
-            // Remove the buffer
-            self.fragment_buffers.remove(&key);
-            return Some(Message::VideoFrame {
-                from,
-                width,
-                height,
-                pixels,
-            });
+            self.video_decoders.remove(&oldest);
+        }
+        let (decoder, used) = self
+            .video_decoders
+            .entry(from.clone())
+            .or_insert_with(|| (DeltaDecoder::default(), now));
+        *used = now;
+        let pixels = match (data, key_id) {
+            (Some(data), Some(id)) => match decoder.apply(id, width, height, &data) {
+                Some(pixels) => Some(pixels),
+                None => return Ok(None),
+            },
+            (data, _) => data,
         }
+        .filter(|pixels| blake3::hash(pixels) == hash);
+        if key_id.is_none()
+            && let Some(pixels) = &pixels
+        {
+            decoder.set_keyframe(frame_id, width, height, pixels.clone());
+        }
+        match pixels {
+            Some(pixels) => {
+                self.link_stats.frame_completed(&from, now);
+                Ok(Some(Message::VideoFrame {
+                    from,
+                    width,
+                    height,
+                    pixels,
+                }))
+            }
+            None => {
+                self.link_stats.reassembly_failed(&from);
+                Err(NetworkError::Corrupt(format!("video frame from {}", from)))
+            }
+        }
+    }
+
+    /// Count file chunks that had to be asked of a peer again
+    pub fn note_retransmissions(&mut self, peer: &str, chunks: u32) {
+        self.link_stats.retransmitted(peer, chunks);
+    }
+
+    /// Traffic, frame rate and losses for each peer online, by name
+    pub fn peer_stats(&self) -> Vec<PeerStats> {
+        let now = Instant::now();
+        let mut stats: Vec<PeerStats> = self
+            .peers
+            .iter()
+            .map(|p| PeerStats {
+                name: p.name.clone(),
+                addr: p.addr,
+                traffic: self.socket.traffic(p.addr),
+                fps: self.link_stats.fps(&p.name, now),
+                reassembly_failures: self.link_stats.reassembly_failures(&p.name),
+                retransmissions: self.link_stats.retransmissions(&p.name),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
 
-        None
+    /// Start every peer's counters again from zero
+    pub fn reset_stats(&mut self) {
+        self.link_stats.clear();
+        self.socket.clear_traffic();
     }
 
     /// Broadcast a message to all peers
@@ -726,17 +1354,189 @@ impl NetworkNode {
         Ok(())
     }
 
-    /// Send a chat message to all peers
-    pub async fn send_chat(&self, text: &str) -> Result<(), NetworkError> {
+    /// Send a chat message to all peers, stamped with a time from
+    /// [`Self::tick_lamport`]. Returns the message's id, for [`Self::send_chat_edit`].
+    pub async fn send_chat(&mut self, text: &str, lamport: u64) -> Result<u64, NetworkError> {
+        // Copies relayed back to us are recognised and dropped
+        self.seen_chats.insert(&self.name, lamport, text);
+        let id = rand::random();
         let msg = Message::Chat {
             from: self.name.clone(),
+            id,
+            text: text.to_string(),
+            lamport: Some(lamport),
+            ttl: MESH_TTL,
+        };
+        self.broadcast(&msg).await?;
+        Ok(id)
+    }
+
+    /// Share a picture with every peer
+    pub async fn send_image(
+        &mut self,
+        width: u16,
+        height: u16,
+        pixels: &[u8],
+        lamport: u64,
+    ) -> Result<(), NetworkError> {
+        let msg = Message::ImageShare {
+            from: self.name.clone(),
+            width,
+            height,
+            pixels: pixels.to_vec(),
+            lamport,
+        };
+        self.broadcast(&msg).await
+    }
+
+    /// Correct our chat message `id` to `text` (or delete it, if empty) for
+    /// every peer
+    pub async fn send_chat_edit(&mut self, id: u64, text: &str) -> Result<(), NetworkError> {
+        self.seen_edits.insert(&self.name, id, text);
+        let msg = Message::ChatEdit {
+            from: self.name.clone(),
+            id,
             text: text.to_string(),
+            ttl: MESH_TTL,
         };
         self.broadcast(&msg).await
     }
 
+    /// Take in a peer's correction, passing it on like [`Self::accept_chat`].
+    /// Returns false for a copy of one already seen.
+    pub async fn accept_chat_edit(&mut self, from: &str, id: u64, text: &str, ttl: u8) -> bool {
+        if !self.seen_edits.insert(from, id, text) {
+            return false;
+        }
+        if self.mesh_relay && ttl > 1 {
+            let msg = Message::ChatEdit {
+                from: from.to_string(),
+                id,
+                text: text.to_string(),
+                ttl: ttl - 1,
+            };
+            let data = msg.to_bytes();
+            for peer in self.peers.iter().filter(|p| p.name != from) {
+                let _ = self.socket.send_to(&data, peer.addr).await;
+            }
+        }
+        true
+    }
+
+    /// Take in a peer's chat line, passing it on to our other peers while it
+    /// has hops left. Returns false for a copy of a line already seen.
+    pub async fn accept_chat(
+        &mut self,
+        from: &str,
+        id: u64,
+        text: &str,
+        lamport: Option<u64>,
+        ttl: u8,
+    ) -> bool {
+        // Without a Lamport time a line can't be told from its copies, so it
+        // is shown but never relayed
+        let Some(lamport) = lamport else {
+            return true;
+        };
+        if !self.seen_chats.insert(from, lamport, text) {
+            return false;
+        }
+        if self.mesh_relay && ttl > 1 {
+            let msg = Message::Chat {
+                from: from.to_string(),
+                id,
+                text: text.to_string(),
+                lamport: Some(lamport),
+                ttl: ttl - 1,
+            };
+            let data = msg.to_bytes();
+            for peer in self.peers.iter().filter(|p| p.name != from) {
+                let _ = self.socket.send_to(&data, peer.addr).await;
+            }
+        }
+        true
+    }
+
+    /// Acknowledge a peer's chat message (sent at `lamport` by its clock)
+    pub async fn send_chat_ack(&self, lamport: u64, addr: SocketAddr) -> Result<(), NetworkError> {
+        let msg = Message::ChatAck {
+            from: self.name.clone(),
+            lamport,
+        };
+        self.send_to(&msg, addr).await
+    }
+
+    /// Advance the Lamport clock for a local event and return the new time
+    pub fn tick_lamport(&self) -> u64 {
+        self.lamport.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Merge a received Lamport timestamp into our clock and return the new time
+    ///
+    /// Messages from peers that don't send one are treated as local events.
+    pub fn observe_lamport(&self, remote: Option<u64>) -> u64 {
+        if let Some(remote) = remote {
+            self.lamport.fetch_max(remote, Ordering::Relaxed);
+        }
+        self.tick_lamport()
+    }
+
+    /// Route traffic for otherwise unreachable peers through a relay server
+    pub fn enable_relay(&mut self, server: SocketAddr) {
+        self.socket.set_relay(server);
+        self.relay_last_refresh = None;
+    }
+
+    /// Request or refresh our relay allocation when it is due
+    pub async fn maintain_relay(&mut self) {
+        let Some(server) = self.socket.relay_server() else {
+            return;
+        };
+        if self
+            .relay_last_refresh
+            .is_some_and(|t| t.elapsed() < relay::REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.relay_last_refresh = Some(Instant::now());
+
+        let packet = relay::RelayPacket::Allocate {
+            name: self.name.clone(),
+            token: self.socket.relay_token(),
+        };
+        if let Err(e) = self.socket.send_to(&packet.to_bytes(), server).await {
+            eprintln!("Failed to contact relay {}: {}", server, e);
+        }
+    }
+
+    /// Other relay clients announced by the relay since the last call.
+    /// Peers we already reach directly under the same name are left out.
+    pub fn take_relay_members(&self) -> Vec<DiscoveredPeer> {
+        self.socket
+            .take_relay_members()
+            .into_iter()
+            .filter(|(name, addr)| {
+                !self
+                    .peers
+                    .iter()
+                    .any(|p| p.name == *name && p.addr != *addr)
+            })
+            .map(|(name, addr)| DiscoveredPeer { name, addr })
+            .collect()
+    }
+
+    /// Whether the node can reach IPv6 peers
+    pub fn is_dual_stack(&self) -> bool {
+        self.socket.is_dual_stack()
+    }
+
+    /// Size the socket's kernel buffers (see [`PeerSocket::set_buffer_sizes`])
+    pub fn set_buffer_sizes(&self, recv: usize, send: usize) -> std::io::Result<(usize, usize)> {
+        self.socket.set_buffer_sizes(recv, send)
+    }
+
     /// Get a clone of the socket for async operations
-    pub fn socket(&self) -> Arc<UdpSocket> {
+    pub fn socket(&self) -> Arc<PeerSocket> {
         Arc::clone(&self.socket)
     }
 
@@ -745,8 +1545,10 @@ impl NetworkNode {
         // Send a join message
         let msg = Message::Join {
             name: self.name.clone(),
+            version: APP_VERSION.to_string(),
         };
         self.send_to(&msg, addr).await?;
+        self.send_clock(addr).await?;
 
         // Add peer with unknown name for now
         self.add_peer("unknown".to_string(), addr);
@@ -754,10 +1556,55 @@ impl NetworkNode {
     }
 }
 
+/// Remove the entry with the oldest timestamp
+fn evict_oldest<K: Clone + std::hash::Hash + Eq>(map: &mut HashMap<K, Instant>) {
+    if let Some(oldest) = map.iter().min_by_key(|(_, t)| **t).map(|(k, _)| k.clone()) {
+        map.remove(&oldest);
+    }
+}
+
 #[derive(Debug)]
 pub enum PeerEvent {
-    Joined { name: String, addr: SocketAddr },
-    Left { name: String, addr: SocketAddr },
+    Joined {
+        name: String,
+        addr: SocketAddr,
+        version: String,
+    },
+    Left {
+        name: String,
+        addr: SocketAddr,
+    },
+    /// A pong arrived in reply to one of our pings
+    Pong {
+        addr: SocketAddr,
+        seq: u32,
+        received: Instant,
+    },
+    /// A peer asked for our recent chat history
+    HistoryRequested {
+        addr: SocketAddr,
+    },
+    /// A peer changed its name
+    Renamed {
+        addr: SocketAddr,
+        new: String,
+    },
+    /// A datagram arrived in a protocol version we can't read
+    Incompatible {
+        addr: SocketAddr,
+        protocol: u8,
+    },
+    /// A peer reported its wall clock
+    Clock {
+        name: String,
+        addr: SocketAddr,
+        unix_ms: i64,
+    },
+    /// A peer sent a keepalive
+    Alive {
+        name: String,
+        addr: SocketAddr,
+    },
 }
 
 #[derive(Debug)]
@@ -766,6 +1613,7 @@ pub enum NetworkError {
     Send(String),
     Stun(String),
     Upnp(String),
+    Corrupt(String),
 }
 
 impl std::fmt::Display for NetworkError {
@@ -775,6 +1623,7 @@ impl std::fmt::Display for NetworkError {
             NetworkError::Send(e) => write!(f, "failed to send: {}", e),
             NetworkError::Stun(e) => write!(f, "STUN error: {}", e),
             NetworkError::Upnp(e) => write!(f, "UPnP error: {}", e),
+            NetworkError::Corrupt(what) => write!(f, "corrupted {}", what),
         }
     }
 }
@@ -789,19 +1638,443 @@ mod tests {
     fn test_message_roundtrip() {
         let msg = Message::Chat {
             from: "Alice".to_string(),
+            id: 42,
             text: "Hello, world!".to_string(),
+            lamport: Some(7),
+            ttl: MESH_TTL,
         };
         let bytes = msg.to_bytes();
         let decoded = Message::from_bytes(&bytes).unwrap();
         match decoded {
-            Message::Chat { from, text } => {
+            Message::Chat {
+                from,
+                id,
+                text,
+                lamport,
+                ttl,
+            } => {
                 assert_eq!(from, "Alice");
+                assert_eq!(id, 42);
                 assert_eq!(text, "Hello, world!");
+                assert_eq!(lamport, Some(7));
+                assert_eq!(ttl, MESH_TTL);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_chat_without_lamport() {
+        let msg = Message::Chat {
+            from: "Bob".to_string(),
+            id: 0,
+            text: "hi".to_string(),
+            lamport: None,
+            ttl: 1,
+        };
+        let bytes = msg.to_bytes();
+        match Message::from_bytes(&bytes) {
+            Some(Message::Chat { text, lamport, .. }) => {
+                assert_eq!(text, "hi");
+                assert_eq!(lamport, None);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    /// One of every message type
+    fn sample_messages() -> Vec<Message> {
+        let from = || "Alice".to_string();
+        vec![
+            Message::Chat {
+                from: from(),
+                id: u64::MAX,
+                text: "héllo".to_string(),
+                lamport: Some(u64::MAX),
+                ttl: u8::MAX,
+            },
+            Message::Ping { seq: 1 },
+            Message::Pong { seq: u32::MAX },
+            Message::Join {
+                name: from(),
+                version: "0.3.0".to_string(),
+            },
+            Message::Leave { name: from() },
+            Message::CallRequest { from: from() },
+            Message::CallHangup { from: from() },
+            Message::CallReject {
+                from: from(),
+                reason: "declined".to_string(),
+            },
+            Message::StreamFrame {
+                from: from(),
+                lines: vec!["##".to_string(), String::new()],
+            },
+            Message::VideoFrame {
+                from: from(),
+                width: 2,
+                height: 2,
+                pixels: vec![9; 4],
+            },
+            Message::VideoFrameFragment {
+                from: from(),
+                width: 80,
+                height: 44,
+                frame_id: 255,
+                fragment_idx: 1,
+                total_fragments: 2,
+                key_id: Some(254),
+                data: vec![1, 2, 3],
+                hash: [0xab; HASH_LEN],
+            },
+            Message::DiscoveryAnnounce {
+                name: from(),
+                port: 7890,
+                version: "0.3.0".to_string(),
+            },
+            Message::ClockSync {
+                from: from(),
+                unix_ms: i64::MIN,
+            },
+            Message::HistoryRequest { from: from() },
+            Message::HistoryChunk {
+                from: from(),
+                part: 0,
+                total: 1,
+                entries: Vec::new(),
+            },
+            Message::Topic(RoomTopic {
+                text: "Retro night".to_string(),
+                set_by: from(),
+                set_at: 1,
+            }),
+            Message::VideoReport {
+                from: from(),
+                fragments: 42,
+                bytes: 58_800,
+            },
+            Message::WhoisRequest { from: from() },
+            Message::WhoisReply(WhoisInfo {
+                name: from(),
+                profile: "Sysop".to_string(),
+                version: "0.0.0".to_string(),
+                terminal: "vt100, ascii".to_string(),
+                uptime_secs: 60,
+                capabilities: vec!["webcam".to_string()],
+            }),
+            Message::Nick {
+                old: from(),
+                new: "Bobby".to_string(),
+            },
+            Message::NickInUse {
+                name: from(),
+                since: 1_700_000_000_000,
+            },
+            Message::FileOffer {
+                from: from(),
+                id: u64::MAX,
+                name: "notes.txt".to_string(),
+                size: 3000,
+                hash: [1; HASH_LEN],
+            },
+            Message::FileRequest {
+                from: from(),
+                id: 1,
+                chunks: vec![0, 2],
+            },
+            Message::FileChunk {
+                from: from(),
+                id: 1,
+                index: 2,
+                data: vec![7; 952],
+                hash: [2; HASH_LEN],
+            },
+            Message::FileDone {
+                from: from(),
+                id: 1,
+            },
+            Message::FileCancel {
+                from: from(),
+                id: 1,
+            },
+            Message::ChatAck {
+                from: from(),
+                lamport: 9,
+            },
+            Message::Keepalive { from: from() },
+            Message::Pin(RoomTopic {
+                text: "Wi-Fi: hunter2".to_string(),
+                set_by: from(),
+                set_at: 2,
+            }),
+            Message::Todo {
+                from: from(),
+                ops: vec![
+                    TodoOp::Add {
+                        id: 3,
+                        text: "buy milk".to_string(),
+                        by: from(),
+                        at: 4,
+                    },
+                    TodoOp::Toggle {
+                        id: 3,
+                        done: true,
+                        by: from(),
+                        at: 5,
+                    },
+                    TodoOp::Remove {
+                        id: 3,
+                        by: from(),
+                        at: 6,
+                    },
+                ],
+            },
+            Message::TimerStart {
+                from: from(),
+                label: "Standup".to_string(),
+                started_ms: 7,
+                length_secs: Some(900),
+            },
+            Message::TimerStop { from: from() },
+            Message::Ring { from: from() },
+            Message::RingReply {
+                from: from(),
+                rang: true,
+            },
+            Message::ChatEdit {
+                from: from(),
+                id: 7,
+                text: String::new(),
+                ttl: 1,
+            },
+            Message::TunesList {
+                from: from(),
+                reply: true,
+                tracks: Some(vec![SyncTrack {
+                    name: "song.ogg".to_string(),
+                    size: 4_000_000,
+                    hash: [3; HASH_LEN],
+                }]),
+            },
+            Message::TunesWant {
+                from: from(),
+                hashes: vec![[3; HASH_LEN]],
+            },
+            Message::WindowChat {
+                from: from(),
+                to: "#retro".to_string(),
+                text: "anyone for a game?".to_string(),
+            },
+            Message::ImageShare {
+                from: from(),
+                width: 4,
+                height: 2,
+                pixels: vec![128; 8],
+                lamport: 10,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_every_message_roundtrips() {
+        let samples = sample_messages();
+        // Adding a variant breaks this match until it gets a sample above
+        let covered: std::collections::HashSet<_> = samples
+            .iter()
+            .map(|msg| match msg {
+                Message::Chat { .. } => 0,
+                Message::Ping { .. } => 1,
+                Message::Pong { .. } => 2,
+                Message::Join { .. } => 3,
+                Message::Leave { .. } => 4,
+                Message::CallRequest { .. } => 5,
+                Message::CallHangup { .. } => 6,
+                Message::CallReject { .. } => 7,
+                Message::StreamFrame { .. } => 8,
+                Message::VideoFrame { .. } => 9,
+                Message::VideoFrameFragment { .. } => 10,
+                Message::DiscoveryAnnounce { .. } => 11,
+                Message::ClockSync { .. } => 12,
+                Message::HistoryRequest { .. } => 13,
+                Message::HistoryChunk { .. } => 14,
+                Message::Topic(_) => 15,
+                Message::VideoReport { .. } => 16,
+                Message::WhoisRequest { .. } => 17,
+                Message::WhoisReply(_) => 18,
+                Message::Nick { .. } => 19,
+                Message::NickInUse { .. } => 20,
+                Message::FileOffer { .. } => 21,
+                Message::FileRequest { .. } => 22,
+                Message::FileChunk { .. } => 23,
+                Message::FileDone { .. } => 24,
+                Message::FileCancel { .. } => 25,
+                Message::ChatAck { .. } => 26,
+                Message::Keepalive { .. } => 27,
+                Message::Pin(_) => 28,
+                Message::Todo { .. } => 29,
+                Message::TimerStart { .. } => 30,
+                Message::TimerStop { .. } => 31,
+                Message::Ring { .. } => 32,
+                Message::RingReply { .. } => 33,
+                Message::ChatEdit { .. } => 34,
+                Message::TunesList { .. } => 35,
+                Message::TunesWant { .. } => 36,
+                Message::WindowChat { .. } => 37,
+                Message::ImageShare { .. } => 38,
+            })
+            .collect();
+        assert_eq!(covered.len(), 39);
+
+        for msg in samples {
+            let bytes = msg.to_bytes();
+            assert_eq!(Message::from_bytes(&bytes), Some(msg.clone()));
+            // Every truncation is rejected rather than misread
+            for len in 0..bytes.len() {
+                assert_eq!(Message::from_bytes(&bytes[..len]), None, "{:?}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_legacy_encoding() {
+        // A chat message in the original hand-rolled format
+        assert_eq!(Message::from_bytes(b"\x01\x03Bob\x00\x02hi"), None);
+    }
+
+    #[test]
+    fn test_history_chunk_roundtrip() {
+        let msg = Message::HistoryChunk {
+            from: "Alice".to_string(),
+            part: 1,
+            total: 3,
+            entries: vec![HistoryEntry {
+                unix_ms: 1_700_000_000_000,
+                from: "Bob".to_string(),
+                text: "\x01ACTION waves".to_string(),
+            }],
+        };
+        match Message::from_bytes(&msg.to_bytes()) {
+            Some(Message::HistoryChunk {
+                from,
+                part,
+                total,
+                entries,
+            }) => {
+                assert_eq!(from, "Alice");
+                assert_eq!((part, total), (1, 3));
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].unix_ms, 1_700_000_000_000);
+                assert_eq!(entries[0].from, "Bob");
+                assert_eq!(entries[0].text, "\x01ACTION waves");
             }
             _ => panic!("Wrong message type"),
         }
     }
 
+    #[test]
+    fn test_next_free_name() {
+        assert_eq!(next_free_name("bob", |_| false), "bob-2");
+        assert_eq!(next_free_name("bob-2", |_| false), "bob-3");
+        assert_eq!(next_free_name("bob", |n| n == "bob-2"), "bob-3");
+        assert_eq!(next_free_name("r2-d2", |_| false), "r2-d2-2");
+        assert_eq!(next_free_name("-5", |_| false), "-5-2");
+    }
+
+    #[test]
+    fn test_topic_supersedes() {
+        let topic = |text: &str, set_by: &str, set_at| RoomTopic {
+            text: text.to_string(),
+            set_by: set_by.to_string(),
+            set_at,
+        };
+        assert!(topic("new", "bob", 2).supersedes(&topic("old", "carol", 1)));
+        assert!(!topic("old", "carol", 1).supersedes(&topic("new", "bob", 2)));
+        // Simultaneous changes settle on the same topic on every peer
+        assert!(topic("b", "bob", 5).supersedes(&topic("a", "alice", 5)));
+        assert!(!topic("a", "alice", 5).supersedes(&topic("b", "bob", 5)));
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut latency = Latency::default();
+        assert_eq!(latency.to_string(), "no reply");
+
+        latency.record_pong(Duration::from_millis(80));
+        assert_eq!(latency.rtt, Some(Duration::from_millis(80)));
+        latency.record_pong(Duration::from_millis(160));
+        assert_eq!(latency.rtt, Some(Duration::from_millis(90)));
+
+        latency.record(false);
+        latency.record(false);
+        assert_eq!(latency.loss_percent(), Some(50));
+        assert_eq!(latency.to_string(), "90ms, 50% loss");
+
+        // Only recent pings count towards the loss estimate
+        for _ in 0..PING_HISTORY {
+            latency.record(true);
+        }
+        assert_eq!(latency.loss_percent(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_pong_updates_rtt() {
+        let mut node = NetworkNode::new("Alice".to_string(), 0).await.unwrap();
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        node.add_peer("Bob".to_string(), addr);
+        let sent = Instant::now();
+        node.pending_pings.insert(1, (addr, sent));
+
+        // A pong from someone else for the same seq is ignored
+        let other: SocketAddr = "127.0.0.1:10".parse().unwrap();
+        node.pending_pings.insert(2, (addr, sent));
+        node.record_pong(other, 2, sent + Duration::from_millis(5));
+        assert_eq!(node.peer_latency("Bob").unwrap().rtt, None);
+
+        node.record_pong(addr, 1, sent + Duration::from_millis(25));
+        assert_eq!(
+            node.peer_latency("Bob").unwrap().rtt,
+            Some(Duration::from_millis(25))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_follows_moved_peer() {
+        let mut node = NetworkNode::new("Alice".to_string(), 0).await.unwrap();
+        node.set_keepalive(Some(Duration::ZERO));
+        let old: SocketAddr = "203.0.113.5:7890".parse().unwrap();
+        let new: SocketAddr = "203.0.113.5:40112".parse().unwrap();
+        node.add_peer("Bob".to_string(), old);
+
+        assert_eq!(node.peer_alive("Bob", old), None);
+        // Bob's NAT remapped him: his keepalives now come from another port
+        assert_eq!(node.peer_alive("Bob", new), Some(old));
+        assert_eq!(node.peers()[0].addr, new);
+        // Someone else isn't mistaken for a peer
+        assert_eq!(node.peer_alive("Mallory", old), None);
+        assert_eq!(node.peer_count(), 1);
+
+        node.set_public_addr("198.51.100.1:50000".parse().unwrap());
+        assert_eq!(
+            node.refresh_public_addr("198.51.100.1:50001".parse().unwrap()),
+            None
+        );
+        assert!(
+            node.refresh_public_addr("198.51.100.9:50001".parse().unwrap())
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lamport_clock() {
+        let node = NetworkNode::new("Alice".to_string(), 0).await.unwrap();
+        assert_eq!(node.tick_lamport(), 1);
+        // A peer that is ahead pulls our clock forward
+        assert_eq!(node.observe_lamport(Some(10)), 11);
+        // One that is behind doesn't pull it back
+        assert_eq!(node.observe_lamport(Some(3)), 12);
+        assert_eq!(node.observe_lamport(None), 13);
+    }
+
     #[test]
     fn test_ping_pong_roundtrip() {
         let ping = Message::Ping { seq: 42 };
@@ -838,4 +2111,143 @@ mod tests {
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[test]
+    fn test_clock_sync_roundtrip() {
+        let msg = Message::ClockSync {
+            from: "Carol".to_string(),
+            unix_ms: -1_234_567,
+        };
+        match Message::from_bytes(&msg.to_bytes()) {
+            Some(Message::ClockSync { from, unix_ms }) => {
+                assert_eq!(from, "Carol");
+                assert_eq!(unix_ms, -1_234_567);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew_warns_once() {
+        let mut node = NetworkNode::new("test".to_string(), 0).await.unwrap();
+        let addr: SocketAddr = "10.0.0.9:7890".parse().unwrap();
+        node.add_peer("dave".to_string(), addr);
+
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        assert_eq!(node.record_peer_clock(addr, now_ms), None);
+        let ahead = node.record_peer_clock(addr, now_ms + 300_000);
+        assert!(ahead.is_some_and(|o| (299..=300).contains(&o)));
+        assert_eq!(node.record_peer_clock(addr, now_ms + 300_000), None);
+    }
+
+    #[tokio::test]
+    async fn test_fragment_buffers_are_bounded() {
+        let mut node = NetworkNode::new("test".to_string(), 0).await.unwrap();
+        for frame_id in 0..=255u8 {
+            // First fragment of a two-fragment frame that never completes
+            let result = node.process_fragment(
+                "flood".to_string(),
+                80,
+                24,
+                frame_id,
+                0,
+                2,
+                None,
+                vec![0; 100],
+                [0; HASH_LEN],
+            );
+            assert!(matches!(result, Ok(None)));
+        }
+        let stats = node.cache_stats();
+        assert_eq!(stats.fragment_buffers, MAX_FRAGMENT_BUFFERS);
+        assert_eq!(stats.fragment_bytes, MAX_FRAGMENT_BUFFERS * 100);
+    }
+
+    #[tokio::test]
+    async fn test_video_frame_skipped_when_queue_full() {
+        let mut node = NetworkNode::new("test".to_string(), 0).await.unwrap();
+        // Nothing drains the queue
+        node.set_upload_limit(Some(1));
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let frame = || -> Vec<u8> { (0..640 * 480).map(|_| rand::random()).collect() };
+
+        let sent = node
+            .send_video_frame("test", 640, 480, &frame(), 0, addr)
+            .await
+            .unwrap();
+        assert!(sent > 200);
+        // No room for all of the next one, so none of it goes
+        let room = node.send_queue.room(addr);
+        let sent = node
+            .send_video_frame("test", 640, 480, &frame(), 1, addr)
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+        assert_eq!(node.send_queue.room(addr), room);
+    }
+
+    #[tokio::test]
+    async fn test_video_decoders_are_bounded() {
+        let mut node = NetworkNode::new("test".to_string(), 0).await.unwrap();
+        let pixels = vec![0; 80 * 24];
+        for n in 0..20 {
+            let frame = node.process_fragment(
+                format!("flood{}", n),
+                80,
+                24,
+                0,
+                0,
+                1,
+                None,
+                lz4_flex::compress_prepend_size(&pixels),
+                blake3::hash(&pixels),
+            );
+            assert!(matches!(frame, Ok(Some(_))));
+        }
+        assert_eq!(node.cache_stats().video_coders, MAX_VIDEO_DECODERS);
+
+        // A peer that goes takes its keyframe with it
+        let addr: SocketAddr = "10.0.0.9:7890".parse().unwrap();
+        node.add_peer("flood19".to_string(), addr);
+        node.remove_peer(addr);
+        assert_eq!(node.cache_stats().video_coders, MAX_VIDEO_DECODERS - 1);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_frame_is_reported() {
+        let mut node = NetworkNode::new("test".to_string(), 0).await.unwrap();
+        let pixels: Vec<u8> = (0..=255).collect();
+        let hash = blake3::hash(&pixels);
+        let compressed = lz4_flex::compress_prepend_size(&pixels);
+        let result = node.process_fragment(
+            "bob".to_string(),
+            16,
+            16,
+            1,
+            0,
+            1,
+            None,
+            compressed.clone(),
+            hash,
+        );
+        assert!(matches!(result, Ok(Some(Message::VideoFrame { pixels: p, .. })) if p == pixels));
+
+        // A flipped bit that still decompresses is caught by the hash
+        let mut damaged = compressed;
+        *damaged.last_mut().unwrap() ^= 1;
+        let result = node.process_fragment("bob".to_string(), 16, 16, 2, 0, 1, None, damaged, hash);
+        assert!(matches!(result, Err(NetworkError::Corrupt(_))));
+        assert_eq!(node.cache_stats().fragment_buffers, 0);
+    }
+
+    #[test]
+    fn test_evict_oldest() {
+        let now = Instant::now();
+        let mut map = HashMap::new();
+        map.insert("new", now);
+        map.insert("old", now - Duration::from_secs(10));
+        evict_oldest(&mut map);
+        assert!(map.contains_key("new"));
+        assert!(!map.contains_key("old"));
+    }
 }
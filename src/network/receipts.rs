@@ -0,0 +1,111 @@
+//! Delivery receipts for our own chat lines.
+//!
+//! Every node answers a chat message with a `ChatAck` carrying the message's
+//! Lamport time. We note which peers were online when each of our lines went
+//! out; once all of them have acknowledged it (or gone), the line is delivered
+//! and its pending marker comes off.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Lines still unconfirmed after this long keep their marker
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Most lines awaiting confirmation (the oldest are given up on first)
+const MAX_PENDING: usize = 100;
+
+/// A line waiting for acknowledgements
+#[derive(Debug)]
+struct Pending {
+    waiting: HashSet<String>,
+    sent: Instant,
+}
+
+/// Our lines that not every peer has confirmed yet, by Lamport time
+#[derive(Debug, Default)]
+pub struct Receipts {
+    pending: HashMap<u64, Pending>,
+}
+
+impl Receipts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for `peers` to acknowledge the line sent at `lamport`
+    pub fn expect(&mut self, lamport: u64, peers: impl IntoIterator<Item = String>) {
+        let now = Instant::now();
+        self.pending
+            .retain(|_, p| now.duration_since(p.sent) < RECEIPT_TIMEOUT);
+        if self.pending.len() >= MAX_PENDING
+            && let Some(oldest) = self.pending.iter().min_by_key(|(_, p)| p.sent)
+        {
+            let oldest = *oldest.0;
+            self.pending.remove(&oldest);
+        }
+        self.pending.insert(
+            lamport,
+            Pending {
+                waiting: peers.into_iter().collect(),
+                sent: now,
+            },
+        );
+    }
+
+    /// Note `from`'s acknowledgement. True once everyone has confirmed the line.
+    pub fn confirm(&mut self, lamport: u64, from: &str) -> bool {
+        let Some(pending) = self.pending.get_mut(&lamport) else {
+            return false;
+        };
+        pending.waiting.remove(from);
+        if pending.waiting.is_empty() {
+            self.pending.remove(&lamport);
+            return true;
+        }
+        false
+    }
+
+    /// Stop waiting on a peer that left. Returns the lines that are now delivered.
+    pub fn peer_gone(&mut self, name: &str) -> Vec<u64> {
+        let mut delivered = Vec::new();
+        self.pending.retain(|lamport, pending| {
+            pending.waiting.remove(name);
+            if pending.waiting.is_empty() {
+                delivered.push(*lamport);
+                return false;
+            }
+            true
+        });
+        delivered
+    }
+
+    /// Follow a peer's change of name
+    pub fn rename_peer(&mut self, old: &str, new: &str) {
+        for pending in self.pending.values_mut() {
+            if pending.waiting.remove(old) {
+                pending.waiting.insert(new.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmed_by_everyone() {
+        let mut receipts = Receipts::new();
+        receipts.expect(5, ["bob".to_string(), "carol".to_string()]);
+        assert!(!receipts.confirm(5, "bob"));
+        assert!(!receipts.confirm(4, "carol"));
+        assert!(receipts.confirm(5, "carol"));
+        // Late duplicates are ignored
+        assert!(!receipts.confirm(5, "bob"));
+
+        receipts.expect(6, ["bob".to_string(), "carol".to_string()]);
+        receipts.rename_peer("carol", "carrie");
+        assert!(!receipts.confirm(6, "carrie"));
+        assert_eq!(receipts.peer_gone("bob"), vec![6]);
+    }
+}
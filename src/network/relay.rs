@@ -0,0 +1,486 @@
+//! TURN-style packet relay for peers that can't reach each other directly.
+//!
+//! Symmetric NATs pick a new external port for every destination, so hole
+//! punching fails for some peer pairs. A relay (`wormhole --relay`) running on a
+//! public host fixes this:
+//! 1. A client sends `Allocate` to the relay's main port; the relay binds a fresh
+//!    UDP port for it and replies with `Allocated`, listing every allocation
+//! 2. Anything sent to an allocated port is wrapped in `Data` (with the original
+//!    sender's address) and forwarded to the owning client
+//! 3. The client replies by sending `Send` (target address + payload) to the
+//!    main port, and the relay transmits the payload from the allocated port
+//!
+//! Other peers therefore see a relayed client at an ordinary address
+//! (relay IP + allocated port) and need no relay support of their own.
+//! Clients re-send `Allocate` periodically; idle allocations expire.
+//!
+//! The relay is open to anyone who can reach it, so it guards itself:
+//! `Allocated` carries a random token that refreshes must repeat, so nobody
+//! else can take over a name from another address; each IP gets only a few
+//! allocations; and `Send` only reaches addresses that have already sent to
+//! that allocation, so the relay can't be used to spray traffic at strangers.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use tokio::net::UdpSocket;
+
+/// Default UDP port for relay servers
+pub const DEFAULT_RELAY_PORT: u16 = 7892;
+
+/// How often clients refresh their allocation
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Allocations not refreshed within this time are released
+const ALLOCATION_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Most allocations the relay holds at once
+const MAX_ALLOCATIONS: usize = 256;
+
+/// Most allocations held for one client IP
+const MAX_PER_IP: usize = 4;
+
+/// Magic bytes identifying relay control packets
+const MAGIC: &[u8; 6] = b"WRMRLY";
+
+/// Relay control packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayPacket {
+    /// Client -> relay: allocate (or refresh) a relayed port under this name,
+    /// with the token from the last `Allocated` (0 for a new allocation)
+    Allocate { name: String, token: u64 },
+    /// Relay -> client: our allocated port and token, plus all current
+    /// (name, port) allocations
+    Allocated {
+        port: u16,
+        token: u64,
+        members: Vec<(String, u16)>,
+    },
+    /// Client -> relay: transmit `payload` to `peer` from our allocated port
+    Send { peer: SocketAddr, payload: Vec<u8> },
+    /// Relay -> client: `payload` arrived at our allocated port from `peer`
+    Data { peer: SocketAddr, payload: Vec<u8> },
+}
+
+impl RelayPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(MAGIC);
+        match self {
+            RelayPacket::Allocate { name, token } => {
+                buf.push(0x01);
+                buf.extend(token.to_be_bytes());
+                write_str(&mut buf, name);
+            }
+            RelayPacket::Allocated {
+                port,
+                token,
+                members,
+            } => {
+                buf.push(0x02);
+                buf.extend(port.to_be_bytes());
+                buf.extend(token.to_be_bytes());
+                buf.push(members.len().min(255) as u8);
+                for (name, port) in members.iter().take(255) {
+                    buf.extend(port.to_be_bytes());
+                    write_str(&mut buf, name);
+                }
+            }
+            RelayPacket::Send { peer, payload } => {
+                buf.push(0x03);
+                write_addr(&mut buf, peer);
+                buf.extend_from_slice(payload);
+            }
+            RelayPacket::Data { peer, payload } => {
+                buf.push(0x04);
+                write_addr(&mut buf, peer);
+                buf.extend_from_slice(payload);
+            }
+        }
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let rest = data.strip_prefix(MAGIC)?;
+        let (&kind, rest) = rest.split_first()?;
+        match kind {
+            0x01 => {
+                let (token, rest) = read_u64(rest)?;
+                let (name, _) = read_str(rest)?;
+                Some(RelayPacket::Allocate { name, token })
+            }
+            0x02 => {
+                let port = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+                let (token, rest) = read_u64(&rest[2..])?;
+                let count = *rest.first()? as usize;
+                let mut rest = &rest[1..];
+                let mut members = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let member_port = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+                    let (name, next) = read_str(&rest[2..])?;
+                    members.push((name, member_port));
+                    rest = next;
+                }
+                Some(RelayPacket::Allocated {
+                    port,
+                    token,
+                    members,
+                })
+            }
+            0x03 => {
+                let (peer, payload) = read_addr(rest)?;
+                Some(RelayPacket::Send {
+                    peer,
+                    payload: payload.to_vec(),
+                })
+            }
+            0x04 => {
+                let (peer, payload) = read_addr(rest)?;
+                Some(RelayPacket::Data {
+                    peer,
+                    payload: payload.to_vec(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn read_u64(data: &[u8]) -> Option<(u64, &[u8])> {
+    let bytes: [u8; 8] = data.get(..8)?.try_into().ok()?;
+    Some((u64::from_be_bytes(bytes), &data[8..]))
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    let bytes = &s.as_bytes()[..s.len().min(255)];
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_str(data: &[u8]) -> Option<(String, &[u8])> {
+    let len = *data.first()? as usize;
+    let bytes = data.get(1..1 + len)?;
+    Some((String::from_utf8_lossy(bytes).to_string(), &data[1 + len..]))
+}
+
+fn write_addr(buf: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(6);
+            buf.extend(ip.octets());
+        }
+    }
+    buf.extend(addr.port().to_be_bytes());
+}
+
+fn read_addr(data: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (&family, rest) = data.split_first()?;
+    let (ip, rest) = match family {
+        4 => {
+            let octets: [u8; 4] = rest.get(..4)?.try_into().ok()?;
+            (IpAddr::V4(Ipv4Addr::from(octets)), &rest[4..])
+        }
+        6 => {
+            let octets: [u8; 16] = rest.get(..16)?.try_into().ok()?;
+            (IpAddr::V6(Ipv6Addr::from(octets)), &rest[16..])
+        }
+        _ => return None,
+    };
+    let port = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+    Some((SocketAddr::new(ip, port), &rest[2..]))
+}
+
+/// A relayed port owned by one client
+struct Allocation {
+    socket: Arc<UdpSocket>,
+    port: u16,
+    /// Client's current address (updated if their NAT mapping changes)
+    client: Arc<Mutex<SocketAddr>>,
+    /// Must come with refreshes from a new address
+    token: u64,
+    /// Addresses that have sent to this port, and when they last did; the
+    /// only ones `Send` may reach
+    peers: Arc<Mutex<HashMap<SocketAddr, Instant>>>,
+    last_seen: Instant,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Print a timestamped relay log line
+fn log(message: &str) {
+    println!("[{}] {}", Local::now().format("%I:%M%p"), message);
+}
+
+/// Run a relay server on `port` until the process exits
+pub async fn run_relay(port: u16) -> Result<(), super::NetworkError> {
+    let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    let main = Arc::new(
+        UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| super::NetworkError::Bind(e.to_string()))?,
+    );
+    log(&format!("Relay listening on UDP port {}", port));
+
+    let mut allocations: HashMap<String, Allocation> = HashMap::new();
+    let mut expiry = tokio::time::interval(Duration::from_secs(10));
+    let mut buf = [0u8; 65535];
+
+    loop {
+        tokio::select! {
+            result = main.recv_from(&mut buf) => {
+                let Ok((len, from)) = result else {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                };
+                match RelayPacket::from_bytes(&buf[..len]) {
+                    Some(RelayPacket::Allocate { name, token }) => {
+                        if let Err(e) = allocate(&main, &mut allocations, name, token, from).await {
+                            log(&format!("Allocation for {} failed: {}", from, e));
+                        }
+                    }
+                    Some(RelayPacket::Send { peer, payload }) => {
+                        if let Some(alloc) = allocations
+                            .values()
+                            .find(|a| *a.client.lock().unwrap() == from)
+                            && alloc.peers.lock().unwrap().contains_key(&peer)
+                        {
+                            let _ = alloc.socket.send_to(&payload, peer).await;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            _ = expiry.tick() => {
+                allocations.retain(|name, alloc| {
+                    let alive = alloc.last_seen.elapsed() < ALLOCATION_TIMEOUT;
+                    if !alive {
+                        alloc.task.abort();
+                        log(&format!("Released port {} for {}", alloc.port, name));
+                    }
+                    alive
+                });
+                for alloc in allocations.values() {
+                    alloc
+                        .peers
+                        .lock()
+                        .unwrap()
+                        .retain(|_, seen| seen.elapsed() < ALLOCATION_TIMEOUT);
+                }
+            }
+        }
+    }
+}
+
+/// Create or refresh a client's allocation and reply with the member list.
+/// A name already allocated is only refreshed from its client's address or
+/// with its token; anything else is refused without a reply.
+async fn allocate(
+    main: &Arc<UdpSocket>,
+    allocations: &mut HashMap<String, Allocation>,
+    name: String,
+    token: u64,
+    from: SocketAddr,
+) -> std::io::Result<()> {
+    if let Some(alloc) = allocations.get_mut(&name) {
+        let mut client = alloc.client.lock().unwrap();
+        if *client != from && token != alloc.token {
+            log(&format!("Refused {} for {} (not its owner)", name, from));
+            return Ok(());
+        }
+        *client = from;
+        drop(client);
+        alloc.last_seen = Instant::now();
+    } else {
+        let held = allocations
+            .values()
+            .filter(|a| a.client.lock().unwrap().ip() == from.ip())
+            .count();
+        if allocations.len() >= MAX_ALLOCATIONS || held >= MAX_PER_IP {
+            log(&format!(
+                "Refused {} for {} (too many allocations)",
+                name, from
+            ));
+            return Ok(());
+        }
+        let socket = Arc::new(UdpSocket::bind(SocketAddr::new(main.local_addr()?.ip(), 0)).await?);
+        let port = socket.local_addr()?.port();
+        let client = Arc::new(Mutex::new(from));
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let task = tokio::spawn(forward_to_client(
+            Arc::clone(&socket),
+            Arc::clone(main),
+            Arc::clone(&client),
+            Arc::clone(&peers),
+        ));
+        log(&format!("Allocated port {} for {} ({})", port, name, from));
+        allocations.insert(
+            name.clone(),
+            Allocation {
+                socket,
+                port,
+                client,
+                token: rand::random::<u64>().max(1),
+                peers,
+                last_seen: Instant::now(),
+                task,
+            },
+        );
+    }
+
+    let port = allocations[&name].port;
+    let token = allocations[&name].token;
+    let members = allocations
+        .iter()
+        .filter(|(other, _)| **other != name)
+        .map(|(other, alloc)| (other.clone(), alloc.port))
+        .collect();
+    let reply = RelayPacket::Allocated {
+        port,
+        token,
+        members,
+    };
+    main.send_to(&reply.to_bytes(), from).await?;
+    Ok(())
+}
+
+/// Wrap everything arriving at an allocated port and pass it to its client,
+/// noting each sender as one the client may answer
+async fn forward_to_client(
+    socket: Arc<UdpSocket>,
+    main: Arc<UdpSocket>,
+    client: Arc<Mutex<SocketAddr>>,
+    peers: Arc<Mutex<HashMap<SocketAddr, Instant>>>,
+) {
+    let mut buf = [0u8; 65535];
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        };
+        peers.lock().unwrap().insert(peer, Instant::now());
+        let packet = RelayPacket::Data {
+            peer,
+            payload: buf[..len].to_vec(),
+        };
+        let target = *client.lock().unwrap();
+        let _ = main.send_to(&packet.to_bytes(), target).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let packets = [
+            RelayPacket::Allocate {
+                name: "alice".to_string(),
+                token: 0,
+            },
+            RelayPacket::Allocated {
+                port: 40000,
+                token: 0x0123_4567_89ab_cdef,
+                members: vec![("bob".to_string(), 40001), ("carol".to_string(), 40002)],
+            },
+            RelayPacket::Send {
+                peer: "203.0.113.5:7890".parse().unwrap(),
+                payload: vec![1, 2, 3],
+            },
+            RelayPacket::Data {
+                peer: "[2001:db8::1]:7890".parse().unwrap(),
+                payload: b"hello".to_vec(),
+            },
+        ];
+        for packet in packets {
+            assert_eq!(RelayPacket::from_bytes(&packet.to_bytes()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn test_rejects_other_traffic() {
+        assert_eq!(RelayPacket::from_bytes(b"\x01\x05Alice"), None);
+        assert_eq!(RelayPacket::from_bytes(b"WRMRLY\x03\x04\x01"), None);
+    }
+
+    /// Whether `socket` stays quiet for a moment
+    async fn nothing_arrives(socket: &UdpSocket) -> bool {
+        let mut buf = [0u8; 1500];
+        tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf))
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn test_relay_forwards_between_clients() {
+        let relay_port = {
+            let probe = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().port()
+        };
+        tokio::spawn(run_relay(relay_port));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let relay: SocketAddr = format!("127.0.0.1:{}", relay_port).parse().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let outsider = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut buf = [0u8; 1500];
+
+        // Allocate a relayed port
+        let allocate = RelayPacket::Allocate {
+            name: "alice".to_string(),
+            token: 0,
+        };
+        client.send_to(&allocate.to_bytes(), relay).await.unwrap();
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        let Some(RelayPacket::Allocated {
+            port,
+            token,
+            members,
+        }) = RelayPacket::from_bytes(&buf[..len])
+        else {
+            panic!("expected Allocated");
+        };
+        assert!(members.is_empty());
+        assert_ne!(token, 0);
+
+        // Someone else can't take the name over without the token
+        stranger.send_to(&allocate.to_bytes(), relay).await.unwrap();
+        assert!(nothing_arrives(&stranger).await);
+
+        // Nor can the client send to an address that never sent to it
+        let send = RelayPacket::Send {
+            peer: stranger.local_addr().unwrap(),
+            payload: b"spam".to_vec(),
+        };
+        client.send_to(&send.to_bytes(), relay).await.unwrap();
+        assert!(nothing_arrives(&stranger).await);
+
+        // Traffic to the allocated port arrives wrapped at the client
+        let relayed: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        outsider.send_to(b"ping", relayed).await.unwrap();
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        let Some(RelayPacket::Data { peer, payload }) = RelayPacket::from_bytes(&buf[..len]) else {
+            panic!("expected Data");
+        };
+        assert_eq!(peer, outsider.local_addr().unwrap());
+        assert_eq!(payload, b"ping");
+
+        // Replies come back to the outsider from the allocated port
+        let send = RelayPacket::Send {
+            peer,
+            payload: b"pong".to_vec(),
+        };
+        client.send_to(&send.to_bytes(), relay).await.unwrap();
+        let (len, from) = outsider.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        assert_eq!(from, relayed);
+    }
+}
@@ -0,0 +1,181 @@
+//! The peer roster: peers we've talked to, remembered across restarts.
+//!
+//! Each named peer's last address and when it was last online are kept in a
+//! small JSON file (`roster` under `[network]`). On startup every remembered
+//! peer is sent a discovery announcement, and any that are online join back,
+//! so internet peers don't have to be configured by hand and LAN peers are
+//! back before the next discovery round.
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::Peer;
+
+/// Peers remembered at most (the longest unseen are forgotten first)
+const MAX_ENTRIES: usize = 64;
+
+/// Peers not seen for this long are forgotten
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A remembered peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    pub name: String,
+    pub addr: SocketAddr,
+    /// When the peer was last online (Unix milliseconds)
+    pub last_seen_ms: i64,
+}
+
+/// Known peers, saved to a state file
+#[derive(Debug)]
+pub struct Roster {
+    path: PathBuf,
+    entries: Vec<RosterEntry>,
+    /// Set when last-seen times have moved on since the last save
+    dirty: bool,
+}
+
+impl Roster {
+    /// Load the roster from `path`. A missing file is an empty roster; an
+    /// unreadable one is reported and replaced.
+    pub fn load(path: &Path) -> Self {
+        let entries = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable peer roster {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                eprintln!("Failed to read peer roster {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        let mut roster = Self {
+            path: path.to_path_buf(),
+            entries,
+            dirty: false,
+        };
+        roster.forget_stale(chrono::Utc::now().timestamp_millis());
+        roster
+    }
+
+    /// Remembered peers, most recently seen first
+    pub fn entries(&self) -> &[RosterEntry] {
+        &self.entries
+    }
+
+    /// Record the peers online now. A new peer or a changed address is saved
+    /// straight away; fresher last-seen times wait for the next save.
+    pub fn update(&mut self, peers: &[Peer]) {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let mut changed = false;
+        // Peers we haven't heard a Join from yet have no name to remember
+        for peer in peers.iter().filter(|p| p.name != "unknown") {
+            match self.entries.iter_mut().find(|e| e.name == peer.name) {
+                Some(entry) => {
+                    changed |= entry.addr != peer.addr;
+                    entry.addr = peer.addr;
+                    entry.last_seen_ms = now_ms;
+                }
+                None => {
+                    self.entries.push(RosterEntry {
+                        name: peer.name.clone(),
+                        addr: peer.addr,
+                        last_seen_ms: now_ms,
+                    });
+                    changed = true;
+                }
+            }
+            self.dirty = true;
+        }
+        if changed {
+            self.forget_stale(now_ms);
+            self.save();
+        }
+    }
+
+    /// Sort by last seen and drop entries that are too old or too many
+    fn forget_stale(&mut self, now_ms: i64) {
+        let cutoff = now_ms - MAX_AGE.as_millis() as i64;
+        self.entries.retain(|e| e.last_seen_ms >= cutoff);
+        self.entries
+            .sort_by_key(|e| std::cmp::Reverse(e.last_seen_ms));
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    /// Write the state file
+    pub fn save(&mut self) {
+        self.dirty = false;
+        let tmp = self.path.with_extension("json.tmp");
+        let result = serde_json::to_string_pretty(&self.entries)
+            .map_err(io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, &self.path));
+        if let Err(e) = result {
+            eprintln!("Failed to save peer roster {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Drop for Roster {
+    fn drop(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Latency;
+    use std::time::Instant;
+
+    fn peer(name: &str, addr: &str) -> Peer {
+        Peer {
+            name: name.to_string(),
+            addr: addr.parse().unwrap(),
+            last_seen: Instant::now(),
+            clock_offset: None,
+            latency: Latency::default(),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_roster_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peers.json");
+
+        let mut roster = Roster::load(&path);
+        assert!(roster.entries().is_empty());
+        roster.update(&[
+            peer("alice", "203.0.113.5:7890"),
+            peer("unknown", "192.0.2.1:7890"),
+        ]);
+        roster.update(&[peer("alice", "203.0.113.9:7890")]);
+        drop(roster);
+
+        let roster = Roster::load(&path);
+        assert_eq!(roster.entries().len(), 1);
+        assert_eq!(roster.entries()[0].name, "alice");
+        assert_eq!(
+            roster.entries()[0].addr,
+            "203.0.113.9:7890".parse().unwrap()
+        );
+
+        // Peers gone for a month are forgotten
+        let stale = RosterEntry {
+            name: "bob".to_string(),
+            addr: "192.0.2.7:7890".parse().unwrap(),
+            last_seen_ms: chrono::Utc::now().timestamp_millis() - MAX_AGE.as_millis() as i64 - 1,
+        };
+        fs::write(&path, serde_json::to_string(&[stale]).unwrap()).unwrap();
+        assert!(Roster::load(&path).entries().is_empty());
+    }
+}
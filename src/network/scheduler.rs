@@ -0,0 +1,153 @@
+//! Fair scheduling of bulk traffic between peers.
+//!
+//! Video fragments and file chunks don't go straight to the socket: they wait
+//! in a queue per peer, and the queues are drained in turn (deficit round
+//! robin), a quantum of bytes per peer per turn. A peer being sent a big file
+//! therefore can't starve another's video call. Each peer's queue is capped,
+//! so traffic for a peer that can't keep up is dropped (and re-requested or
+//! adapted to, as on a congested link) instead of piling up.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+/// Bytes each peer may send per turn (about one datagram)
+const QUANTUM: usize = 1500;
+
+/// Most bytes waiting for one peer
+pub const PEER_QUEUE_CAP: usize = 512 * 1024;
+
+/// Datagrams waiting for one peer
+#[derive(Debug)]
+struct PeerQueue {
+    addr: SocketAddr,
+    datagrams: VecDeque<Vec<u8>>,
+    bytes: usize,
+    /// Bytes this peer may still send before the next peer's turn
+    deficit: usize,
+}
+
+/// Bulk datagrams waiting to go out, by peer
+#[derive(Debug, Default)]
+pub struct SendQueue {
+    /// Peers with something queued, in turn order
+    peers: VecDeque<PeerQueue>,
+}
+
+impl SendQueue {
+    /// Queue a datagram for `addr`. Returns false if the peer's queue is full
+    /// and the datagram was dropped.
+    pub fn push(&mut self, addr: SocketAddr, data: Vec<u8>) -> bool {
+        let queue = match self.peers.iter_mut().position(|q| q.addr == addr) {
+            Some(i) => &mut self.peers[i],
+            None => {
+                self.peers.push_back(PeerQueue {
+                    addr,
+                    datagrams: VecDeque::new(),
+                    bytes: 0,
+                    deficit: 0,
+                });
+                self.peers.back_mut().unwrap()
+            }
+        };
+        if queue.bytes + data.len() > PEER_QUEUE_CAP && !queue.datagrams.is_empty() {
+            return false;
+        }
+        queue.bytes += data.len();
+        queue.datagrams.push_back(data);
+        true
+    }
+
+    /// Bytes that can still be queued for `addr` before its queue is full
+    pub fn room(&self, addr: SocketAddr) -> usize {
+        self.peers
+            .iter()
+            .find(|q| q.addr == addr)
+            .map_or(PEER_QUEUE_CAP, |q| PEER_QUEUE_CAP.saturating_sub(q.bytes))
+    }
+
+    /// Take about `budget` bytes of datagrams, interleaving peers fairly
+    pub fn take(&mut self, budget: usize) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut taken = Vec::new();
+        let mut bytes = 0;
+        while bytes < budget
+            && let Some(mut queue) = self.peers.pop_front()
+        {
+            queue.deficit += QUANTUM;
+            while bytes < budget
+                && let Some(len) = queue.datagrams.front().map(Vec::len)
+                && len <= queue.deficit
+            {
+                let data = queue.datagrams.pop_front().unwrap();
+                queue.deficit -= len;
+                queue.bytes -= len;
+                bytes += len;
+                taken.push((queue.addr, data));
+            }
+            // A peer that has caught up starts its next burst without credit
+            if !queue.datagrams.is_empty() {
+                self.peers.push_back(queue);
+            }
+        }
+        taken
+    }
+
+    /// Drop everything queued for a peer that's gone
+    pub fn forget(&mut self, addr: SocketAddr) {
+        self.peers.retain(|q| q.addr != addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(queue: &SendQueue, addr: SocketAddr) -> usize {
+        queue
+            .peers
+            .iter()
+            .find(|q| q.addr == addr)
+            .map_or(0, |q| q.bytes)
+    }
+
+    #[test]
+    fn test_round_robin_with_caps() {
+        let alice: SocketAddr = "192.0.2.1:7890".parse().unwrap();
+        let bob: SocketAddr = "192.0.2.2:7890".parse().unwrap();
+        let mut queue = SendQueue::default();
+
+        // A greedy transfer to alice queued ahead of bob's video
+        for _ in 0..100 {
+            assert!(queue.push(alice, vec![b'a'; 1400]));
+        }
+        for _ in 0..3 {
+            assert!(queue.push(bob, vec![b'b'; 700]));
+        }
+        let order: Vec<u8> = queue
+            .take(usize::MAX)
+            .into_iter()
+            .take(6)
+            .map(|(_, data)| data[0])
+            .collect();
+        // bob's quantum carries two of his smaller datagrams per turn
+        assert_eq!(order, b"abbaba");
+        assert!(queue.is_empty());
+
+        // A peer's queue stops growing at its cap; the others are unaffected
+        while queue.push(alice, vec![0; 1400]) {}
+        assert!(queued(&queue, alice) <= PEER_QUEUE_CAP);
+        assert!(queue.room(alice) < 1400);
+        assert_eq!(queue.room(bob), PEER_QUEUE_CAP);
+        assert!(queue.push(bob, vec![0; 1400]));
+
+        // A budget limits each take, but everyone still gets a turn
+        let taken = queue.take(2000);
+        assert_eq!(taken.len(), 2);
+        assert_ne!(taken[0].0, taken[1].0);
+        queue.forget(alice);
+        assert_eq!(queued(&queue, alice), 0);
+    }
+}
@@ -0,0 +1,572 @@
+//! Dual-stack UDP socket for peer traffic.
+//!
+//! The main socket is bound to `[::]` with `IPV6_V6ONLY` disabled so one socket
+//! serves both IPv4 and IPv6 peers. IPv4 peers then show up as v4-mapped IPv6
+//! addresses (`::ffff:a.b.c.d`); [`PeerSocket`] hides this by mapping addresses
+//! on the way out and unmapping them on the way in, so the rest of the app only
+//! ever sees plain IPv4 or real IPv6 addresses. Hosts without IPv6 fall back to
+//! a plain IPv4 socket.
+//!
+//! When a relay is configured, [`PeerSocket`] also unwraps relayed `Data`
+//! packets and wraps replies to relayed peers, so relayed peers look like any
+//! other address to the rest of the app (see [`super::relay`]).
+//!
+//! Bursts of video fragments can outrun the default kernel receive buffer on
+//! a Pi, so the buffer sizes are configurable (`recv_buffer` and `send_buffer`
+//! under `[network]`) and [`PeerSocket::recv_batch`] drains every datagram
+//! already queued before the receive task dispatches any of them.
+//!
+//! With `--impair`, datagrams are also dropped, delayed and reordered here to
+//! simulate a bad network (see [`super::impair`]).
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+
+use super::capture::{Direction, ProtocolTap};
+use super::impair::{Impairer, Impairment};
+use super::relay::RelayPacket;
+use super::stats::{Traffic, TrafficCounters};
+
+/// Forget relayed peers we haven't heard from in this long
+const RELAYED_PEER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Most datagrams taken from the socket per [`PeerSocket::recv_batch`]
+pub const RECV_BATCH: usize = 64;
+
+/// Largest datagram we accept
+const MAX_DATAGRAM: usize = 65535;
+
+/// Client-side relay state
+struct RelayState {
+    server: SocketAddr,
+    /// Our allocated port on the relay, once the relay has replied
+    allocated_port: Option<u16>,
+    /// Proves the allocation is ours when we refresh it (0 until allocated)
+    token: u64,
+    /// Peers whose traffic reached us through the relay (must be answered through it)
+    relayed_peers: HashMap<SocketAddr, Instant>,
+    /// Other relay clients from the latest allocation reply, not yet collected
+    new_members: Vec<(String, SocketAddr)>,
+}
+
+/// UDP socket that accepts IPv4 and IPv6 peers
+pub struct PeerSocket {
+    socket: UdpSocket,
+    dual_stack: bool,
+    relay: Mutex<Option<RelayState>>,
+    /// Protocol debug output (`--debug-protocol`/`--pcap`)
+    tap: Mutex<Option<ProtocolTap>>,
+    /// Simulated network impairment (`--impair`)
+    impair: Mutex<Option<Impairer>>,
+    /// Datagrams and bytes to and from each peer, for the Stats tab
+    traffic: Mutex<TrafficCounters>,
+}
+
+impl PeerSocket {
+    /// Bind on all interfaces, preferring a dual-stack IPv6 socket
+    pub async fn bind(port: u16) -> io::Result<Self> {
+        let (socket, dual_stack) = match bind_dual_stack(port) {
+            Ok(socket) => (socket, true),
+            Err(_) => {
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+                (UdpSocket::bind(addr).await?, false)
+            }
+        };
+        Ok(Self {
+            socket,
+            dual_stack,
+            relay: Mutex::new(None),
+            tap: Mutex::new(None),
+            impair: Mutex::new(None),
+            traffic: Mutex::new(TrafficCounters::default()),
+        })
+    }
+
+    /// Ask for kernel buffers of `recv` and `send` bytes (0 keeps the OS
+    /// default). Returns the sizes granted, which the kernel may cap
+    /// (`net.core.rmem_max`/`wmem_max` on Linux) or report doubled.
+    pub fn set_buffer_sizes(&self, recv: usize, send: usize) -> io::Result<(usize, usize)> {
+        let socket = socket2::SockRef::from(&self.socket);
+        if recv > 0 {
+            socket.set_recv_buffer_size(recv)?;
+        }
+        if send > 0 {
+            socket.set_send_buffer_size(send)?;
+        }
+        Ok((socket.recv_buffer_size()?, socket.send_buffer_size()?))
+    }
+
+    /// Whether this socket can reach IPv6 peers
+    pub fn is_dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Log (and optionally capture) every datagram from now on
+    pub fn set_tap(&self, mut tap: ProtocolTap) {
+        if let Ok(addr) = self.local_addr() {
+            tap.set_local_addr(addr);
+        }
+        *self.tap.lock().unwrap() = Some(tap);
+    }
+
+    fn record(&self, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        match direction {
+            Direction::Sent => self.traffic.lock().unwrap().sent(peer, data.len()),
+            Direction::Received => self.traffic.lock().unwrap().received(peer, data.len()),
+        }
+        if let Some(tap) = self.tap.lock().unwrap().as_mut() {
+            tap.record(direction, peer, data);
+        }
+    }
+
+    /// What has been exchanged with `addr` so far
+    pub fn traffic(&self, addr: SocketAddr) -> Traffic {
+        self.traffic.lock().unwrap().get(addr)
+    }
+
+    /// Stop counting traffic for a peer that's gone
+    pub fn forget_traffic(&self, addr: SocketAddr) {
+        self.traffic.lock().unwrap().forget(addr);
+    }
+
+    pub fn clear_traffic(&self) {
+        self.traffic.lock().unwrap().clear();
+    }
+
+    /// Drop, delay and reorder peer datagrams from now on
+    pub fn set_impairment(&self, impairment: Impairment) {
+        *self.impair.lock().unwrap() = Some(Impairer::new(impairment));
+    }
+
+    /// Route traffic through a relay server
+    pub fn set_relay(&self, server: SocketAddr) {
+        *self.relay.lock().unwrap() = Some(RelayState {
+            server,
+            allocated_port: None,
+            token: 0,
+            relayed_peers: HashMap::new(),
+            new_members: Vec::new(),
+        });
+    }
+
+    /// The relay server, if one is configured
+    pub fn relay_server(&self) -> Option<SocketAddr> {
+        self.relay.lock().unwrap().as_ref().map(|r| r.server)
+    }
+
+    /// Our address as seen through the relay (relay IP + allocated port)
+    pub fn relayed_addr(&self) -> Option<SocketAddr> {
+        let relay = self.relay.lock().unwrap();
+        let relay = relay.as_ref()?;
+        Some(with_port(relay.server, relay.allocated_port?))
+    }
+
+    /// The token our relay allocation was given, to repeat when refreshing it
+    pub fn relay_token(&self) -> u64 {
+        self.relay.lock().unwrap().as_ref().map_or(0, |r| r.token)
+    }
+
+    /// Take relay clients announced since the last call, as (name, relayed address)
+    pub fn take_relay_members(&self) -> Vec<(String, SocketAddr)> {
+        match self.relay.lock().unwrap().as_mut() {
+            Some(relay) => std::mem::take(&mut relay.new_members),
+            None => Vec::new(),
+        }
+    }
+
+    /// Send a datagram, wrapping it for the relay if the peer is only reachable that way
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.record(Direction::Sent, addr, buf);
+        if let Some(impair) = self.impair.lock().unwrap().as_mut()
+            && impair.drop_outgoing()
+        {
+            // Lost on the simulated network: the sender can't tell
+            return Ok(buf.len());
+        }
+        let relay_server = self.relay.lock().unwrap().as_ref().and_then(|relay| {
+            relay
+                .relayed_peers
+                .contains_key(&addr)
+                .then_some(relay.server)
+        });
+        match relay_server {
+            Some(server) => {
+                let packet = RelayPacket::Send {
+                    peer: addr,
+                    payload: buf.to_vec(),
+                };
+                self.send_direct(&packet.to_bytes(), server).await?;
+                Ok(buf.len())
+            }
+            None => self.send_direct(buf, addr).await,
+        }
+    }
+
+    /// Send a datagram, mapping IPv4 destinations when the socket is dual-stack
+    async fn send_direct(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let target = match addr {
+            SocketAddr::V4(v4) if self.dual_stack => {
+                SocketAddr::V6(SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0))
+            }
+            SocketAddr::V6(_) if !self.dual_stack => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "IPv6 is not available on this host",
+                ));
+            }
+            _ => addr,
+        };
+        self.socket.send_to(buf, target).await
+    }
+
+    /// Receive a datagram, reporting IPv4 senders as plain IPv4 addresses.
+    /// Relayed packets are unwrapped and reported as coming from the original sender.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        if self.impair.lock().unwrap().is_none() {
+            return self.recv_unimpaired(buf).await;
+        }
+        loop {
+            let next_due = {
+                let mut guard = self.impair.lock().unwrap();
+                let impair = guard.as_mut().expect("impairment is never removed");
+                if let Some((addr, data)) = impair.take_due() {
+                    return Ok((deliver(buf, &data), addr));
+                }
+                impair.next_due()
+            };
+            let sleep = async {
+                match next_due {
+                    Some(due) => tokio::time::sleep_until(due.into()).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let mut datagram = vec![0u8; buf.len()];
+            tokio::select! {
+                received = self.recv_unimpaired(&mut datagram) => {
+                    let (len, addr) = received?;
+                    let mut guard = self.impair.lock().unwrap();
+                    let impair = guard.as_mut().expect("impairment is never removed");
+                    if let Some((addr, data)) = impair.receive(addr, &datagram[..len]) {
+                        return Ok((deliver(buf, &data), addr));
+                    }
+                }
+                _ = sleep => {}
+            }
+        }
+    }
+
+    /// Receive a datagram that's already queued, without waiting. Held
+    /// datagrams are released while impaired, but nothing new is read.
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        if let Some(impair) = self.impair.lock().unwrap().as_mut() {
+            return Ok(impair
+                .take_due()
+                .map(|(addr, data)| (deliver(buf, &data), addr)));
+        }
+        loop {
+            let (len, addr) = match self.socket.try_recv_from(buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if let Some(received) = self.accept(buf, len, addr) {
+                return Ok(Some(received));
+            }
+        }
+    }
+
+    /// Wait for a datagram, then take every other one already queued (up to
+    /// [`RECV_BATCH`]) so a burst leaves the kernel buffer in one go
+    pub async fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.clear();
+        let (len, addr) = self.recv_from(&mut batch.scratch).await?;
+        batch.push(len, addr);
+        while batch.len() < RECV_BATCH {
+            match self.try_recv_from(&mut batch.scratch)? {
+                Some((len, addr)) => batch.push(len, addr),
+                None => break,
+            }
+        }
+        Ok(batch.len())
+    }
+
+    /// Receive a datagram as it arrived, before any simulated impairment
+    async fn recv_unimpaired(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let (len, addr) = self.socket.recv_from(buf).await?;
+            if let Some(received) = self.accept(buf, len, addr) {
+                return Ok(received);
+            }
+        }
+    }
+
+    /// Unwrap a raw datagram in `buf`, returning its length and sender, or
+    /// `None` if it was relay signalling rather than peer traffic
+    fn accept(&self, buf: &mut [u8], len: usize, addr: SocketAddr) -> Option<(usize, SocketAddr)> {
+        let addr = canonical_addr(addr);
+        if self.relay_server() != Some(addr) {
+            self.record(Direction::Received, addr, &buf[..len]);
+            return Some((len, addr));
+        }
+
+        match RelayPacket::from_bytes(&buf[..len]) {
+            Some(RelayPacket::Data { peer, payload }) => {
+                let peer = canonical_addr(peer);
+                self.mark_relayed(peer);
+                buf[..payload.len()].copy_from_slice(&payload);
+                self.record(Direction::Received, peer, &payload);
+                Some((payload.len(), peer))
+            }
+            Some(RelayPacket::Allocated {
+                port,
+                token,
+                members,
+            }) => {
+                self.record_allocation(port, token, members);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn mark_relayed(&self, peer: SocketAddr) {
+        if let Some(relay) = self.relay.lock().unwrap().as_mut() {
+            let now = Instant::now();
+            relay
+                .relayed_peers
+                .retain(|_, seen| now.duration_since(*seen) < RELAYED_PEER_TIMEOUT);
+            relay.relayed_peers.insert(peer, now);
+        }
+    }
+
+    fn record_allocation(&self, port: u16, token: u64, members: Vec<(String, u16)>) {
+        if let Some(relay) = self.relay.lock().unwrap().as_mut() {
+            relay.allocated_port = Some(port);
+            relay.token = token;
+            relay.new_members = members
+                .into_iter()
+                .map(|(name, port)| (name, with_port(relay.server, port)))
+                .collect();
+        }
+    }
+}
+
+/// Datagrams taken from the socket together by [`PeerSocket::recv_batch`]
+pub struct RecvBatch {
+    /// Every datagram's bytes, back to back
+    data: Vec<u8>,
+    datagrams: Vec<(Range<usize>, SocketAddr)>,
+    /// Where each datagram is received before it's appended
+    scratch: Vec<u8>,
+}
+
+impl RecvBatch {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            datagrams: Vec::with_capacity(RECV_BATCH),
+            scratch: vec![0; MAX_DATAGRAM],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+
+    /// Each datagram and its sender, in the order they arrived
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.datagrams
+            .iter()
+            .map(|(range, addr)| (&self.data[range.clone()], *addr))
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.datagrams.clear();
+    }
+
+    /// Keep the first `len` bytes of the scratch buffer
+    fn push(&mut self, len: usize, addr: SocketAddr) {
+        let start = self.data.len();
+        self.data.extend_from_slice(&self.scratch[..len]);
+        self.datagrams.push((start..self.data.len(), addr));
+    }
+}
+
+impl Default for RecvBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copy a held datagram into the caller's buffer
+fn deliver(buf: &mut [u8], data: &[u8]) -> usize {
+    let len = data.len().min(buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+fn bind_dual_stack(port: u16) -> io::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket.set_only_v6(false)?;
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Convert a v4-mapped IPv6 address back to plain IPv4
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Return `addr` with a different port, keeping any IPv6 scope ID
+pub fn with_port(mut addr: SocketAddr, port: u16) -> SocketAddr {
+    addr.set_port(port);
+    addr
+}
+
+/// Parse a peer address from the config or a command.
+///
+/// Accepts `1.2.3.4:7890`, `[2001:db8::1]:7890`, link-local addresses with an
+/// interface scope (`[fe80::1%eth0]:7890`), bare IPs (using `default_port`) and
+/// `hostname:port`.
+pub fn parse_peer_addr(s: &str, default_port: u16) -> Option<SocketAddr> {
+    let s = s.trim();
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, default_port));
+    }
+
+    // IPv6 with a named interface scope, bracketed or bare
+    let (host, port) = match s.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest.split_once(']')?;
+            let port = match after.strip_prefix(':') {
+                Some(p) => p.parse().ok()?,
+                None if after.is_empty() => default_port,
+                None => return None,
+            };
+            (host, port)
+        }
+        None => (s, default_port),
+    };
+    if let Some((ip, scope)) = host.split_once('%')
+        && let Ok(ip) = ip.parse::<Ipv6Addr>()
+    {
+        let scope_id = scope.parse().ok().or_else(|| interface_index(scope))?;
+        return Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)));
+    }
+
+    // Hostname (requires an explicit port)
+    s.to_socket_addrs().ok()?.next()
+}
+
+/// Look up an interface index by name (e.g. "eth0")
+pub fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_addr() {
+        let mapped: SocketAddr = "[::ffff:192.168.1.5]:7890".parse().unwrap();
+        assert_eq!(canonical_addr(mapped), "192.168.1.5:7890".parse().unwrap());
+
+        let v6: SocketAddr = "[2001:db8::1]:7890".parse().unwrap();
+        assert_eq!(canonical_addr(v6), v6);
+    }
+
+    #[test]
+    fn test_parse_peer_addr() {
+        assert_eq!(
+            parse_peer_addr("10.0.0.1:7000", 7890),
+            Some("10.0.0.1:7000".parse().unwrap())
+        );
+        assert_eq!(
+            parse_peer_addr("[2001:db8::1]:7000", 7890),
+            Some("[2001:db8::1]:7000".parse().unwrap())
+        );
+        assert_eq!(
+            parse_peer_addr("2001:db8::1", 7890),
+            Some("[2001:db8::1]:7890".parse().unwrap())
+        );
+        assert_eq!(
+            parse_peer_addr("[fe80::1%3]:7000", 7890),
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                "fe80::1".parse().unwrap(),
+                7000,
+                0,
+                3
+            )))
+        );
+        assert_eq!(parse_peer_addr("[::1", 7890), None);
+    }
+
+    #[test]
+    fn test_with_port_keeps_scope() {
+        let addr = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 7891, 0, 2));
+        match with_port(addr, 7890) {
+            SocketAddr::V6(v6) => {
+                assert_eq!(v6.port(), 7890);
+                assert_eq!(v6.scope_id(), 2);
+            }
+            SocketAddr::V4(_) => panic!("expected IPv6"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch_drains_a_burst() {
+        let receiver = PeerSocket::bind(0).await.unwrap();
+        let sender = PeerSocket::bind(0).await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let target = SocketAddr::from(([127, 0, 0, 1], port));
+        for i in 0..(RECV_BATCH as u8 + 6) {
+            sender.send_to(&[i; 3], target).await.unwrap();
+        }
+        // Loopback delivery is immediate, but give the kernel a moment
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut batch = RecvBatch::new();
+        assert_eq!(receiver.recv_batch(&mut batch).await.unwrap(), RECV_BATCH);
+        let first: Vec<&[u8]> = batch.iter().map(|(data, _)| data).take(2).collect();
+        assert_eq!(first, [&[0u8; 3][..], &[1u8; 3][..]]);
+        assert_eq!(receiver.recv_batch(&mut batch).await.unwrap(), 6);
+        let (data, from) = batch.iter().last().unwrap();
+        assert_eq!(data, [RECV_BATCH as u8 + 5; 3]);
+        assert_eq!(from.port(), sender.local_addr().unwrap().port());
+        assert!(receiver.try_recv_from(&mut [0; 16]).unwrap().is_none());
+    }
+}
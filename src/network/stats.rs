@@ -0,0 +1,183 @@
+//! Per-peer link statistics for the Stats tab.
+//!
+//! The socket counts datagrams and bytes each way by address, relayed traffic
+//! included once it's unwrapped. The node adds what only it can tell, by peer
+//! name: video frames completed (for the frame rate), frames that never
+//! reassembled, and file chunks that had to be sent again.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The video frame rate is averaged over this long
+const FPS_WINDOW: Duration = Duration::from_secs(5);
+
+/// Most addresses counted at once (strangers past this aren't tracked)
+const MAX_TRACKED: usize = 256;
+
+/// Datagrams and bytes exchanged with one address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+}
+
+/// Traffic counted by the socket
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    by_addr: HashMap<SocketAddr, Traffic>,
+}
+
+impl TrafficCounters {
+    pub fn sent(&mut self, addr: SocketAddr, len: usize) {
+        if let Some(traffic) = self.entry(addr) {
+            traffic.bytes_sent += len as u64;
+            traffic.messages_sent += 1;
+        }
+    }
+
+    pub fn received(&mut self, addr: SocketAddr, len: usize) {
+        if let Some(traffic) = self.entry(addr) {
+            traffic.bytes_received += len as u64;
+            traffic.messages_received += 1;
+        }
+    }
+
+    fn entry(&mut self, addr: SocketAddr) -> Option<&mut Traffic> {
+        if self.by_addr.len() >= MAX_TRACKED && !self.by_addr.contains_key(&addr) {
+            return None;
+        }
+        Some(self.by_addr.entry(addr).or_default())
+    }
+
+    pub fn get(&self, addr: SocketAddr) -> Traffic {
+        self.by_addr.get(&addr).copied().unwrap_or_default()
+    }
+
+    pub fn forget(&mut self, addr: SocketAddr) {
+        self.by_addr.remove(&addr);
+    }
+
+    pub fn clear(&mut self) {
+        self.by_addr.clear();
+    }
+}
+
+/// What the node counts for one peer
+#[derive(Debug, Default)]
+struct PeerCounters {
+    /// When recent video frames were completed
+    frames: VecDeque<Instant>,
+    reassembly_failures: u64,
+    retransmissions: u64,
+}
+
+/// Counters the node keeps by peer name
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    peers: HashMap<String, PeerCounters>,
+}
+
+impl LinkStats {
+    /// A video frame from `peer` was reassembled
+    pub fn frame_completed(&mut self, peer: &str, now: Instant) {
+        let frames = &mut self.counters(peer).frames;
+        frames.push_back(now);
+        while frames
+            .front()
+            .is_some_and(|&t| now.duration_since(t) > FPS_WINDOW)
+        {
+            frames.pop_front();
+        }
+    }
+
+    /// A video frame from `peer` was lost or arrived damaged
+    pub fn reassembly_failed(&mut self, peer: &str) {
+        self.counters(peer).reassembly_failures += 1;
+    }
+
+    /// `n` file chunks were asked of `peer` again
+    pub fn retransmitted(&mut self, peer: &str, n: u32) {
+        self.counters(peer).retransmissions += n as u64;
+    }
+
+    /// Frames per second from `peer` over the last few seconds
+    pub fn fps(&self, peer: &str, now: Instant) -> f32 {
+        let Some(counters) = self.peers.get(peer) else {
+            return 0.0;
+        };
+        let recent = counters
+            .frames
+            .iter()
+            .filter(|&&t| now.duration_since(t) <= FPS_WINDOW)
+            .count();
+        recent as f32 / FPS_WINDOW.as_secs_f32()
+    }
+
+    pub fn reassembly_failures(&self, peer: &str) -> u64 {
+        self.peers.get(peer).map_or(0, |c| c.reassembly_failures)
+    }
+
+    pub fn retransmissions(&self, peer: &str) -> u64 {
+        self.peers.get(peer).map_or(0, |c| c.retransmissions)
+    }
+
+    pub fn rename_peer(&mut self, old: &str, new: &str) {
+        if let Some(counters) = self.peers.remove(old) {
+            self.peers.insert(new.to_string(), counters);
+        }
+    }
+
+    pub fn forget(&mut self, peer: &str) {
+        self.peers.remove(peer);
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    fn counters(&mut self, peer: &str) -> &mut PeerCounters {
+        if !self.peers.contains_key(peer) && self.peers.len() >= MAX_TRACKED {
+            self.peers.clear();
+        }
+        self.peers.entry(peer.to_string()).or_default()
+    }
+}
+
+/// One peer's row on the Stats tab
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStats {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub traffic: Traffic,
+    /// Video frames per second arriving from the peer
+    pub fps: f32,
+    pub reassembly_failures: u64,
+    pub retransmissions: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fps_window() {
+        let mut stats = LinkStats::default();
+        let start = Instant::now();
+        for i in 0..50 {
+            stats.frame_completed("alice", start + Duration::from_millis(i * 100));
+        }
+        let now = start + Duration::from_millis(4900);
+        assert_eq!(stats.fps("alice", now), 10.0);
+        // Frames older than the window no longer count
+        assert_eq!(stats.fps("alice", now + Duration::from_secs(3)), 4.2);
+        assert_eq!(stats.fps("bob", now), 0.0);
+
+        stats.reassembly_failed("alice");
+        stats.rename_peer("alice", "al");
+        assert_eq!(stats.reassembly_failures("al"), 1);
+        assert_eq!(stats.reassembly_failures("alice"), 0);
+    }
+}
@@ -18,9 +18,23 @@ const STUN_SERVERS: &[&str] = &[
 /// Discover our public IP and port using STUN
 /// Note: This uses an ephemeral port for the STUN query, so the returned port
 /// may differ from the actual listening port. The public IP is the main value here.
+///
+/// IPv4 is tried first; on IPv6-only networks the query is repeated over IPv6.
 pub fn discover_public_endpoint(_local_port: u16) -> Result<SocketAddr, super::NetworkError> {
+    let mut last_error = None;
+    for bind_addr in ["0.0.0.0:0", "[::]:0"] {
+        match discover_with_socket(bind_addr) {
+            Ok(addr) => return Ok(addr),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| super::NetworkError::Stun("All STUN servers failed".to_string())))
+}
+
+fn discover_with_socket(bind_addr: &str) -> Result<SocketAddr, super::NetworkError> {
     // Bind to an ephemeral port (0) to avoid conflicts with our main socket
-    let socket = UdpSocket::bind("0.0.0.0:0")
+    let socket = UdpSocket::bind(bind_addr)
         .map_err(|e| super::NetworkError::Stun(format!("Failed to bind socket: {}", e)))?;
 
     socket
@@ -40,11 +54,12 @@ pub fn discover_public_endpoint(_local_port: u16) -> Result<SocketAddr, super::N
 }
 
 fn try_stun_server(socket: &UdpSocket, server: &str) -> Result<SocketAddr, super::NetworkError> {
-    // Resolve server address
+    // Resolve server address, matching the socket's address family
+    let want_v6 = socket.local_addr().map(|a| a.is_ipv6()).unwrap_or(false);
     let server_addr = server
         .to_socket_addrs()
         .map_err(|e| super::NetworkError::Stun(format!("DNS resolution failed: {}", e)))?
-        .next()
+        .find(|a| a.is_ipv6() == want_v6)
         .ok_or_else(|| super::NetworkError::Stun("No address for STUN server".to_string()))?;
 
     // Create STUN binding request
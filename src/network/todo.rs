@@ -0,0 +1,404 @@
+//! The shared to-do list, kept in step between peers.
+//!
+//! Every change is an operation on an item with a random ID: add, check off
+//! (or uncheck), remove. Operations are broadcast as they're made and replayed
+//! to peers that join, and applying them twice or out of order ends with the
+//! same list everywhere: the done state is last writer wins (by time, then
+//! name), a removed item is kept as a tombstone so a late add or toggle can't
+//! bring it back, and a toggle that arrives before its add is held until the
+//! add does. Tombstones and held toggles are forgotten after a month, or
+//! sooner if there are too many. The list is saved to a JSON file (`todo`
+//! under `[network]`).
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest item text, in characters
+pub const MAX_TODO_LEN: usize = 200;
+
+/// Most items on the list (adds past this are ignored)
+const MAX_ITEMS: usize = 256;
+
+/// Tombstones are forgotten after this long
+const TOMBSTONE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Most tombstones and held toggles remembered (the oldest go first)
+const MAX_TOMBSTONES: usize = 1024;
+
+/// One change to the list. Times are ms since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TodoOp {
+    Add {
+        id: u64,
+        text: String,
+        by: String,
+        at: i64,
+    },
+    /// Check the item off, or uncheck it
+    Toggle {
+        id: u64,
+        done: bool,
+        by: String,
+        at: i64,
+    },
+    Remove {
+        id: u64,
+        by: String,
+        at: i64,
+    },
+}
+
+/// An item on the list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoItem {
+    pub id: u64,
+    pub text: String,
+    pub added_by: String,
+    pub added_at: i64,
+    pub done: bool,
+    /// Who last checked or unchecked the item (empty = nobody yet)
+    pub done_by: String,
+    pub done_at: i64,
+    /// When the item was removed; removed items are hidden but remembered
+    pub removed_at: Option<i64>,
+    /// Checked or unchecked before its add reached us; hidden until it does
+    #[serde(default)]
+    pub pending: bool,
+}
+
+impl TodoItem {
+    /// Whether the item is on the list, rather than a tombstone or a held toggle
+    fn is_live(&self) -> bool {
+        self.removed_at.is_none() && !self.pending
+    }
+
+    /// When a tombstone or held toggle was last touched
+    fn ghost_at(&self) -> i64 {
+        self.removed_at.unwrap_or(self.done_at)
+    }
+}
+
+/// The to-do list, optionally saved to a file
+#[derive(Debug, Default)]
+pub struct TodoList {
+    path: Option<PathBuf>,
+    /// Items and tombstones, in the order they were added
+    items: Vec<TodoItem>,
+}
+
+impl TodoList {
+    /// Load the list from `path`. A missing file is an empty list; an
+    /// unreadable one is reported and replaced.
+    pub fn load(path: &Path) -> Self {
+        let items = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable to-do list {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                eprintln!("Failed to read to-do list {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        let mut list = Self {
+            path: Some(path.to_path_buf()),
+            items,
+        };
+        list.prune();
+        list
+    }
+
+    /// Items still on the list, oldest first
+    pub fn items(&self) -> Vec<&TodoItem> {
+        self.items.iter().filter(|item| item.is_live()).collect()
+    }
+
+    /// Forget tombstones and held toggles that are old, or past
+    /// [`MAX_TOMBSTONES`], so peers can't grow the list without end
+    fn prune(&mut self) {
+        let cutoff = chrono::Utc::now().timestamp_millis() - TOMBSTONE_AGE.as_millis() as i64;
+        self.items
+            .retain(|item| item.is_live() || item.ghost_at() >= cutoff);
+
+        let mut ghosts: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| !item.is_live())
+            .map(|(n, item)| (item.ghost_at(), n))
+            .collect();
+        if ghosts.len() > MAX_TOMBSTONES {
+            ghosts.sort_unstable();
+            let excess = ghosts.len() - MAX_TOMBSTONES;
+            let forget: HashSet<usize> = ghosts[..excess].iter().map(|&(_, n)| n).collect();
+            let mut n = 0;
+            self.items.retain(|_| {
+                n += 1;
+                !forget.contains(&(n - 1))
+            });
+        }
+    }
+
+    /// Apply operations from us or a peer, saving the list if they changed it.
+    /// Returns true if anything changed.
+    pub fn apply(&mut self, ops: &[TodoOp]) -> bool {
+        let mut changed = false;
+        for op in ops {
+            changed |= self.apply_one(op);
+        }
+        if changed {
+            self.prune();
+            self.save();
+        }
+        changed
+    }
+
+    fn apply_one(&mut self, op: &TodoOp) -> bool {
+        match op {
+            TodoOp::Add { id, text, by, at } => {
+                let live = self.items.iter().filter(|i| i.is_live()).count();
+                let existing = self.items.iter().position(|i| i.id == *id);
+                if existing.is_some_and(|n| !self.items[n].pending) || live >= MAX_ITEMS {
+                    return false;
+                }
+                // Text comes from peers: no control characters, and not too much
+                let text: String = text
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .take(MAX_TODO_LEN)
+                    .collect();
+                match existing {
+                    // Its check arrived first and is kept
+                    Some(n) => {
+                        let item = &mut self.items[n];
+                        item.text = text;
+                        item.added_by = by.clone();
+                        item.added_at = *at;
+                        item.pending = false;
+                    }
+                    None => self.items.push(TodoItem {
+                        id: *id,
+                        text,
+                        added_by: by.clone(),
+                        added_at: *at,
+                        done: false,
+                        done_by: String::new(),
+                        done_at: 0,
+                        removed_at: None,
+                        pending: false,
+                    }),
+                }
+                true
+            }
+            TodoOp::Toggle { id, done, by, at } => {
+                let Some(item) = self.items.iter_mut().find(|i| i.id == *id) else {
+                    // Toggled before the add reached us: hold on to it for the add
+                    self.items.push(TodoItem {
+                        id: *id,
+                        text: String::new(),
+                        added_by: String::new(),
+                        added_at: 0,
+                        done: *done,
+                        done_by: by.clone(),
+                        done_at: *at,
+                        removed_at: None,
+                        pending: true,
+                    });
+                    return true;
+                };
+                if item.removed_at.is_some() {
+                    return false;
+                }
+                if (*at, by) <= (item.done_at, &item.done_by) {
+                    return false;
+                }
+                item.done = *done;
+                item.done_by = by.clone();
+                item.done_at = *at;
+                true
+            }
+            TodoOp::Remove { id, by, at } => {
+                match self.items.iter_mut().find(|i| i.id == *id) {
+                    Some(item) if item.removed_at.is_some() => false,
+                    Some(item) => {
+                        item.removed_at = Some(*at);
+                        item.text.clear();
+                        item.pending = false;
+                        true
+                    }
+                    // Removed before the add reached us: remember it so the add doesn't stick
+                    None => {
+                        self.items.push(TodoItem {
+                            id: *id,
+                            text: String::new(),
+                            added_by: by.clone(),
+                            added_at: *at,
+                            done: false,
+                            done_by: String::new(),
+                            done_at: 0,
+                            removed_at: Some(*at),
+                            pending: false,
+                        });
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    /// The whole list as operations, to bring a peer that joins up to date
+    pub fn ops(&self) -> Vec<TodoOp> {
+        let mut ops = Vec::new();
+        for item in &self.items {
+            if let Some(at) = item.removed_at {
+                ops.push(TodoOp::Remove {
+                    id: item.id,
+                    by: item.added_by.clone(),
+                    at,
+                });
+                continue;
+            }
+            if !item.pending {
+                ops.push(TodoOp::Add {
+                    id: item.id,
+                    text: item.text.clone(),
+                    by: item.added_by.clone(),
+                    at: item.added_at,
+                });
+            }
+            if !item.done_by.is_empty() {
+                ops.push(TodoOp::Toggle {
+                    id: item.id,
+                    done: item.done,
+                    by: item.done_by.clone(),
+                    at: item.done_at,
+                });
+            }
+        }
+        ops
+    }
+
+    /// Write the list file (if there is one)
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_string_pretty(&self.items)
+            .map_err(io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = result {
+            eprintln!("Failed to save to-do list {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn add(id: u64, text: &str, at: i64) -> TodoOp {
+        TodoOp::Add {
+            id,
+            text: text.to_string(),
+            by: "alice".to_string(),
+            at,
+        }
+    }
+
+    fn toggle(id: u64, done: bool, by: &str, at: i64) -> TodoOp {
+        TodoOp::Toggle {
+            id,
+            done,
+            by: by.to_string(),
+            at,
+        }
+    }
+
+    #[test]
+    fn test_ops_converge_in_any_order() {
+        // Recent, so no tombstone is old enough to forget
+        let t = chrono::Utc::now().timestamp_millis();
+        let remove = TodoOp::Remove {
+            id: 2,
+            by: "bob".to_string(),
+            at: t + 30,
+        };
+        let ops = [
+            add(1, "milk", t + 10),
+            add(2, "eggs", t + 11),
+            toggle(1, true, "bob", t + 20),
+            toggle(1, false, "alice", t + 15),
+            remove,
+        ];
+        let mut forward = TodoList::default();
+        assert!(forward.apply(&ops));
+        let mut backward = TodoList::default();
+        let reversed: Vec<_> = ops.iter().rev().cloned().collect();
+        backward.apply(&reversed);
+        // A peer that joins is replayed the whole list
+        let mut joined = TodoList::default();
+        joined.apply(&backward.ops());
+
+        for list in [&forward, &backward, &joined] {
+            let items = list.items();
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].text, "milk");
+            // bob's later check wins over alice's earlier uncheck
+            assert!(items[0].done);
+        }
+        // Replaying changes nothing, and the removed item stays removed
+        assert!(!forward.apply(&ops));
+        assert!(!forward.apply(&[add(2, "eggs", t + 11)]));
+    }
+
+    #[test]
+    fn test_tombstones_are_bounded() {
+        let mut list = TodoList::default();
+        let now = chrono::Utc::now().timestamp_millis();
+        let removes: Vec<_> = (0..MAX_TOMBSTONES as u64 + 100)
+            .map(|id| TodoOp::Remove {
+                id,
+                by: "mallory".to_string(),
+                at: now + id as i64,
+            })
+            .collect();
+        list.apply(&removes);
+        list.apply(&[toggle(1 << 40, true, "mallory", now)]);
+        assert_eq!(list.items.len(), MAX_TOMBSTONES);
+        // The oldest went first
+        assert!(list.items.iter().all(|item| item.id >= 100));
+
+        // Old ones go whatever the count
+        list.apply(&[TodoOp::Remove {
+            id: 1 << 41,
+            by: "mallory".to_string(),
+            at: 0,
+        }]);
+        assert!(list.items.iter().all(|item| item.id != 1 << 41));
+    }
+
+    #[test]
+    fn test_saved_across_restarts() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("todo.json");
+        let mut list = TodoList::load(&path);
+        list.apply(&[
+            add(7, "water the \x1b[5mplants", 1),
+            toggle(7, true, "bob", 2),
+        ]);
+
+        let list = TodoList::load(&path);
+        let items = list.items();
+        assert_eq!(items[0].text, "water the [5mplants");
+        assert_eq!(items[0].done_by, "bob");
+    }
+}
@@ -0,0 +1,291 @@
+//! Delta sync of Tunes directories (`/tunes sync <peer>`).
+//!
+//! Both sides opt in with `sync` under `[tunes]`. The one starting a sync
+//! sends a `TunesList` of its tracks and the other answers with its own; each
+//! then asks for the tracks it hasn't got with a `TunesWant`, and the owner
+//! offers them as ordinary file transfers paced to `sync_rate`, which land in
+//! the asker's Tunes directory. Tracks are matched by the BLAKE3 hash of their
+//! contents, so one that's only been renamed isn't fetched again.
+//!
+//! Listings go in parts small enough for one datagram, and each part stands
+//! alone: a lost one just means fewer tracks are fetched this time round.
+//!
+//! Hashing a library takes a while, so the hashes are kept in a cache file in
+//! the Tunes directory and only worked out again for files whose size or
+//! modification time has changed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::blake3::{self, HASH_LEN};
+
+/// Track hashes, kept in the Tunes directory
+const CACHE_FILE: &str = ".wormhole-tunes.json";
+
+/// Payload budget for one listing or want part, to stay clear of fragmentation
+const CHUNK_BUDGET: usize = 1200;
+
+/// A sync is forgotten this long after it started (the transfers it set off
+/// carry on regardless)
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// One track in a listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncTrack {
+    /// File name in the Tunes directory
+    pub name: String,
+    pub size: u64,
+    pub hash: [u8; HASH_LEN],
+}
+
+impl SyncTrack {
+    /// Bytes the track takes up in a listing part
+    fn encoded_len(&self) -> usize {
+        2 + self.name.len() + 8 + HASH_LEN
+    }
+}
+
+/// A track's hash as cached, with what it was worked out from
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedTrack {
+    size: u64,
+    /// Modification time, in seconds since the Unix epoch
+    modified: u64,
+    hash: [u8; HASH_LEN],
+}
+
+/// Hash the named tracks in `dir`, reusing cached hashes of unchanged files
+pub fn scan_library(dir: &Path, names: &[String]) -> Vec<SyncTrack> {
+    let cache_path = dir.join(CACHE_FILE);
+    let mut cache: HashMap<String, CachedTrack> = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    let mut changed = false;
+    let mut tracks = Vec::with_capacity(names.len());
+    for name in names {
+        let path = dir.join(name);
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let hash = match cache.get(name) {
+            Some(cached) if cached.size == meta.len() && cached.modified == modified => cached.hash,
+            _ => match blake3::hash_file(&path) {
+                Ok(hash) => {
+                    let cached = CachedTrack {
+                        size: meta.len(),
+                        modified,
+                        hash,
+                    };
+                    cache.insert(name.clone(), cached);
+                    changed = true;
+                    hash
+                }
+                Err(e) => {
+                    eprintln!("Skipping {} in the tunes sync: {}", path.display(), e);
+                    continue;
+                }
+            },
+        };
+        tracks.push(SyncTrack {
+            name: name.clone(),
+            size: meta.len(),
+            hash,
+        });
+    }
+    // Forget tracks that have gone
+    let before = cache.len();
+    cache.retain(|name, _| names.contains(name));
+    if changed || cache.len() != before {
+        let tmp = cache_path.with_extension("json.tmp");
+        let result = serde_json::to_string(&cache)
+            .map_err(std::io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, &cache_path));
+        if let Err(e) = result {
+            eprintln!("Failed to save the tunes hash cache: {}", e);
+        }
+    }
+    tracks
+}
+
+/// Split a listing into parts that fit a datagram (an empty library is one
+/// empty part, so the peer still hears back)
+pub fn list_parts(tracks: &[SyncTrack]) -> Vec<Vec<SyncTrack>> {
+    let mut parts = vec![Vec::new()];
+    let mut size = 0;
+    for track in tracks {
+        let len = track.encoded_len();
+        if size + len > CHUNK_BUDGET && !parts.last().unwrap().is_empty() {
+            parts.push(Vec::new());
+            size = 0;
+        }
+        size += len;
+        parts.last_mut().unwrap().push(track.clone());
+    }
+    parts
+}
+
+/// Split the hashes of wanted tracks into parts that fit a datagram
+pub fn want_parts(hashes: &[[u8; HASH_LEN]]) -> Vec<Vec<[u8; HASH_LEN]>> {
+    hashes
+        .chunks(CHUNK_BUDGET / HASH_LEN)
+        .map(<[_]>::to_vec)
+        .collect()
+}
+
+/// A sync with one peer
+#[derive(Debug)]
+struct Session {
+    started: Instant,
+    /// Our tracks, as listed to the peer
+    library: Vec<SyncTrack>,
+    /// Tracks asked for, so a repeated listing doesn't ask again
+    wanted: HashSet<[u8; HASH_LEN]>,
+}
+
+/// The syncs under way, by peer name
+#[derive(Debug, Default)]
+pub struct TunesSync {
+    sessions: HashMap<String, Session>,
+}
+
+impl TunesSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start syncing with `peer`, with our `library` of tracks. Returns false
+    /// if a sync with it was already under way.
+    pub fn start(&mut self, peer: &str, library: Vec<SyncTrack>) -> bool {
+        if self.is_active(peer) {
+            return false;
+        }
+        let session = Session {
+            started: Instant::now(),
+            library,
+            wanted: HashSet::new(),
+        };
+        self.sessions.insert(peer.to_string(), session);
+        true
+    }
+
+    /// Whether a sync with `peer` is under way
+    pub fn is_active(&mut self, peer: &str) -> bool {
+        self.sessions
+            .retain(|_, s| s.started.elapsed() < SESSION_TIMEOUT);
+        self.sessions.contains_key(peer)
+    }
+
+    pub fn end(&mut self, peer: &str) {
+        self.sessions.remove(peer);
+    }
+
+    /// Our listing for `peer`
+    pub fn library(&self, peer: &str) -> &[SyncTrack] {
+        self.sessions
+            .get(peer)
+            .map_or(&[], |s| s.library.as_slice())
+    }
+
+    /// The tracks in part of `peer`'s listing that we haven't got or asked
+    /// for yet (they're noted as asked for)
+    pub fn missing(&mut self, peer: &str, theirs: &[SyncTrack]) -> Vec<SyncTrack> {
+        let Some(session) = self.sessions.get_mut(peer) else {
+            return Vec::new();
+        };
+        let ours: HashSet<_> = session.library.iter().map(|t| t.hash).collect();
+        theirs
+            .iter()
+            .filter(|t| !ours.contains(&t.hash) && session.wanted.insert(t.hash))
+            .cloned()
+            .collect()
+    }
+
+    /// The track in our listing for `peer` that hashes to `hash`
+    pub fn track(&self, peer: &str, hash: &[u8; HASH_LEN]) -> Option<&SyncTrack> {
+        self.library(peer).iter().find(|t| t.hash == *hash)
+    }
+
+    /// Follow a peer's change of name
+    pub fn rename_peer(&mut self, old: &str, new: &str) {
+        if let Some(session) = self.sessions.remove(old) {
+            self.sessions.insert(new.to_string(), session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library_and_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.ogg"), b"first").unwrap();
+        fs::write(dir.path().join("b.ogg"), b"second").unwrap();
+        let names = vec!["a.ogg".to_string(), "b.ogg".to_string()];
+        let ours = scan_library(dir.path(), &names);
+        assert_eq!(ours.len(), 2);
+        assert_eq!(ours[1].hash, blake3::hash(b"second"));
+        // The second scan comes from the cache, and agrees
+        assert!(dir.path().join(CACHE_FILE).exists());
+        assert_eq!(scan_library(dir.path(), &names), ours);
+
+        let theirs = vec![
+            ours[0].clone(),
+            SyncTrack {
+                name: "renamed.ogg".to_string(),
+                ..ours[1].clone()
+            },
+            SyncTrack {
+                name: "c.ogg".to_string(),
+                size: 5,
+                hash: blake3::hash(b"third"),
+            },
+        ];
+        let mut sync = TunesSync::new();
+        assert!(sync.start("bob", ours));
+        assert!(!sync.start("bob", Vec::new()));
+        let missing = sync.missing("bob", &theirs);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].name, "c.ogg");
+        // Asked for once only
+        assert!(sync.missing("bob", &theirs).is_empty());
+        assert!(sync.track("bob", &blake3::hash(b"first")).is_some());
+        assert!(sync.missing("carol", &theirs).is_empty());
+    }
+
+    #[test]
+    fn test_parts_fit_a_datagram() {
+        let tracks: Vec<SyncTrack> = (0..100)
+            .map(|i| SyncTrack {
+                name: format!("Track number {} of the big album.flac", i),
+                size: 40 << 20,
+                hash: [i as u8; HASH_LEN],
+            })
+            .collect();
+        let parts = list_parts(&tracks);
+        assert!(parts.len() > 1);
+        assert_eq!(parts.iter().map(Vec::len).sum::<usize>(), 100);
+        for part in &parts {
+            assert!(part.iter().map(SyncTrack::encoded_len).sum::<usize>() <= CHUNK_BUDGET);
+        }
+        assert_eq!(list_parts(&[]), vec![Vec::new()]);
+        let hashes: Vec<_> = tracks.iter().map(|t| t.hash).collect();
+        assert!(
+            want_parts(&hashes)
+                .iter()
+                .all(|p| p.len() * HASH_LEN <= CHUNK_BUDGET)
+        );
+    }
+}
@@ -1,4 +1,6 @@
 //! UPnP port forwarding support.
+//!
+//! IGD only maps IPv4 ports; IPv6 peers are reached directly on the dual-stack socket.
 
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::time::Duration;
@@ -0,0 +1,47 @@
+//! Software and protocol versions advertised between peers.
+//!
+//! Peers announce their Wormhole release in `Join` and `DiscoveryAnnounce`. A
+//! peer speaking another protocol version can't be understood at all, but its
+//! datagrams still carry the version in their header (see
+//! [`super::codec::frame_version`]), so we can at least say why it's silent.
+
+pub use super::codec::PROTOCOL_VERSION;
+
+/// The Wormhole release we run
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Parse "major.minor.patch" (pre-release suffixes are ignored)
+fn parse(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
+}
+
+/// Whether `theirs` is a newer major or minor release than `ours`
+/// (patch releases don't warrant an upgrade nag)
+pub fn is_newer_release(theirs: &str, ours: &str) -> bool {
+    match (parse(theirs), parse(ours)) {
+        (Some((a_major, a_minor, _)), Some((b_major, b_minor, _))) => {
+            (a_major, a_minor) > (b_major, b_minor)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newer_release() {
+        assert!(is_newer_release("0.4.0", "0.3.9"));
+        assert!(is_newer_release("1.0", "0.9.2"));
+        assert!(!is_newer_release("0.3.5", "0.3.0"));
+        assert!(!is_newer_release("0.2.0", "0.3.0"));
+        assert!(!is_newer_release("0.4.0-beta", "0.4.0"));
+        assert!(!is_newer_release("garbage", "0.3.0"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Finger-style peer profiles for `/whois`.
+//!
+//! A `WhoisRequest` is answered with a [`WhoisInfo`] describing the node: the
+//! self-description set with `/profile`, the software version, the terminal it
+//! drives, how long it has been running and what it can do.
+//!
+//! Every peer is asked for one when it joins, and a node that turns a feature
+//! on or off sends its card round again, so the few features that decide
+//! whether `/call` or `/send` will work are known ahead of time and shown as
+//! [`Caps`] next to the peer's name. Only cards asked for with `/whois` are
+//! shown in the chat.
+
+use serde::{Deserialize, Serialize};
+
+/// Longest profile we'll set or show
+pub const MAX_PROFILE_LEN: usize = 200;
+
+/// Widest a card's contents get, so it fits an 80 column chat area
+const CARD_WIDTH: usize = 56;
+
+/// What a node says about itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhoisInfo {
+    pub name: String,
+    /// Self-description set with `/profile` (empty if none)
+    pub profile: String,
+    /// Wormhole version
+    pub version: String,
+    /// Terminal mode and image renderer, e.g. "vt340, sixel"
+    pub terminal: String,
+    pub uptime_secs: u64,
+    /// Optional features the node has enabled
+    pub capabilities: Vec<String>,
+}
+
+/// Capability names for the features [`Caps`] shows
+pub const CAP_VIDEO: &str = "webcam";
+pub const CAP_AUDIO: &str = "tunes";
+pub const CAP_FILES: &str = "file transfer";
+pub const CAP_COLOR: &str = "color";
+/// The terminal shows accented Latin letters (and so can be sent them)
+pub const CAP_ACCENTS: &str = "accents";
+
+/// The features that matter before calling or sending to a peer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Caps {
+    pub video: bool,
+    pub audio: bool,
+    pub files: bool,
+    pub color: bool,
+    pub accents: bool,
+}
+
+impl Caps {
+    pub fn from_info(info: &WhoisInfo) -> Self {
+        let has = |name| info.capabilities.iter().any(|c| c == name);
+        Self {
+            video: has(CAP_VIDEO),
+            audio: has(CAP_AUDIO),
+            files: has(CAP_FILES),
+            color: has(CAP_COLOR),
+            accents: has(CAP_ACCENTS),
+        }
+    }
+
+    /// `[VAFC]`, with a letter for each feature the peer has (empty if none)
+    pub fn badge(&self) -> String {
+        let letters: String = [
+            (self.video, 'V'),
+            (self.audio, 'A'),
+            (self.files, 'F'),
+            (self.color, 'C'),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, letter)| letter)
+        .collect();
+        if letters.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", letters)
+        }
+    }
+}
+
+impl WhoisInfo {
+    /// Render as a boxed card of plain ASCII lines for the chat buffer
+    pub fn card(&self) -> Vec<String> {
+        let mut rows = Vec::new();
+        let profile = if self.profile.is_empty() {
+            "(no profile)"
+        } else {
+            &self.profile
+        };
+        let capabilities = if self.capabilities.is_empty() {
+            "none".to_string()
+        } else {
+            self.capabilities.join(", ")
+        };
+        for (label, value) in [
+            ("Profile", profile),
+            ("Version", &self.version),
+            ("Terminal", &self.terminal),
+            ("Uptime", &format_uptime(self.uptime_secs)),
+            ("Features", &capabilities),
+        ] {
+            for (i, line) in wrap(value, CARD_WIDTH - 10).into_iter().enumerate() {
+                let label = if i == 0 { label } else { "" };
+                rows.push(format!("{:<10}{}", label, line));
+            }
+        }
+
+        let inner = rows
+            .iter()
+            .map(|r| r.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(self.name.chars().count() + 2);
+        let title = format!(" {} ", self.name);
+        let mut lines = vec![format!(
+            "+-{}{}-+",
+            title,
+            "-".repeat(inner.saturating_sub(title.chars().count()))
+        )];
+        for row in rows {
+            let pad = inner - row.chars().count();
+            lines.push(format!("| {}{} |", row, " ".repeat(pad)));
+        }
+        lines.push(format!("+-{}-+", "-".repeat(inner)));
+        lines
+    }
+}
+
+/// "3d 4h", "2h 13m" or "5m"
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+/// Word-wrap to `width` characters, with control characters stripped
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let word: String = word.chars().filter(|c| !c.is_control()).collect();
+        for piece in word.chars().collect::<Vec<_>>().chunks(width) {
+            let piece: String = piece.iter().collect();
+            if !line.is_empty() && line.chars().count() + 1 + piece.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&piece);
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_layout() {
+        let info = WhoisInfo {
+            name: "bob".to_string(),
+            profile: "Sysop of the museum VT340. ".repeat(4),
+            version: "0.3.0".to_string(),
+            terminal: "vt340, sixel".to_string(),
+            uptime_secs: 2 * 3600 + 13 * 60,
+            capabilities: vec!["webcam".to_string(), "AI".to_string()],
+        };
+        let card = info.card();
+        assert!(card[0].starts_with("+- bob -"));
+        assert!(card.iter().any(|l| l.contains("Uptime    2h 13m")));
+        assert!(card.iter().any(|l| l.contains("Features  webcam, AI")));
+        // The long profile wraps inside the box
+        assert!(card.len() > 7);
+        let width = card[0].chars().count();
+        assert!(width <= CARD_WIDTH + 4);
+        assert!(card.iter().all(|l| l.chars().count() == width));
+    }
+
+    #[test]
+    fn test_caps() {
+        let mut info = WhoisInfo {
+            name: "bob".to_string(),
+            profile: String::new(),
+            version: "0.3.0".to_string(),
+            terminal: "vt340, sixel".to_string(),
+            uptime_secs: 0,
+            capabilities: vec![
+                CAP_VIDEO.to_string(),
+                "AI".to_string(),
+                CAP_FILES.to_string(),
+            ],
+        };
+        let caps = Caps::from_info(&info);
+        assert!(caps.video && caps.files && !caps.audio && !caps.color);
+        assert_eq!(caps.badge(), "[VF]");
+        info.capabilities.clear();
+        assert_eq!(Caps::from_info(&info).badge(), "");
+    }
+
+    #[test]
+    fn test_uptime() {
+        assert_eq!(format_uptime(59), "0m");
+        assert_eq!(format_uptime(3 * 86_400 + 4 * 3600 + 59), "3d 4h");
+    }
+}
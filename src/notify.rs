@@ -0,0 +1,132 @@
+//! Per-peer notifications (`[contacts]`).
+//!
+//! Each line under `[contacts]` names a peer and how their messages are
+//! announced, so you can tell who it is from across the room: a bell rhythm
+//! such as `2-1` (two quick rings, a pause, one more), a sound played on the
+//! host (`sound:/path/to/doorbell.wav`), or `none`. Peers not listed ring
+//! once when they mention you, as before.
+//!
+//! A terminal runs back-to-back BELs together, so the rings of a pattern are
+//! spaced out by [`Bell`] and sent from the main loop as they fall due.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// What `/ring` sounds like: ring-ring, ring-ring, ring-ring
+pub const RING_PATTERN: &[u32] = &[2, 2, 2];
+
+/// Gap between rings in a group
+const RING_GAP: Duration = Duration::from_millis(250);
+
+/// Pause between groups
+const GROUP_GAP: Duration = Duration::from_millis(700);
+
+/// Most rings in a group, and groups in a pattern
+const MAX_RINGS: u32 = 9;
+const MAX_GROUPS: usize = 6;
+
+/// How a contact's messages are announced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    /// Groups of rings, e.g. `[2, 1]` for `2-1`
+    Bells(Vec<u32>),
+    /// A sound file played on the host
+    Sound(PathBuf),
+    /// No notification at all
+    Silent,
+}
+
+/// Parse a `[contacts]` value: `3`, `2-1`, `sound:<file>` or `none`
+pub fn parse_alert(text: &str) -> Result<Alert, String> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("none") {
+        return Ok(Alert::Silent);
+    }
+    if let Some(path) = text.strip_prefix("sound:") {
+        let path = path.trim();
+        if path.is_empty() {
+            return Err("sound: needs a file".to_string());
+        }
+        return Ok(Alert::Sound(PathBuf::from(path)));
+    }
+    let groups = text
+        .split('-')
+        .map(|group| match group.trim().parse::<u32>() {
+            Ok(rings @ 1..=MAX_RINGS) => Ok(rings),
+            _ => Err(format!(
+                "'{}' should be rings like 2-1 (1 to {} per group), sound:<file> or none",
+                text, MAX_RINGS
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if groups.len() > MAX_GROUPS {
+        return Err(format!("'{}' has more than {} groups", text, MAX_GROUPS));
+    }
+    Ok(Alert::Bells(groups))
+}
+
+/// Rings waiting to be sent
+#[derive(Debug, Default)]
+pub struct Bell {
+    pending: VecDeque<Instant>,
+}
+
+impl Bell {
+    /// Queue the rings of a pattern, after any still to come
+    pub fn ring(&mut self, groups: &[u32], now: Instant) {
+        let mut at = self.pending.back().map_or(now, |&last| last + GROUP_GAP);
+        for (i, &rings) in groups.iter().enumerate() {
+            if i > 0 {
+                at += GROUP_GAP - RING_GAP;
+            }
+            for _ in 0..rings {
+                self.pending.push_back(at);
+                at += RING_GAP;
+            }
+        }
+    }
+
+    /// Take the rings due by `now`: send a BEL if true
+    pub fn due(&mut self, now: Instant) -> bool {
+        let mut due = false;
+        while self.pending.front().is_some_and(|&at| at <= now) {
+            self.pending.pop_front();
+            due = true;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_alert() {
+        assert_eq!(parse_alert("3"), Ok(Alert::Bells(vec![3])));
+        assert_eq!(parse_alert(" 2-1 "), Ok(Alert::Bells(vec![2, 1])));
+        assert_eq!(parse_alert("None"), Ok(Alert::Silent));
+        assert_eq!(
+            parse_alert("sound:/usr/share/sounds/bell.wav"),
+            Ok(Alert::Sound(PathBuf::from("/usr/share/sounds/bell.wav")))
+        );
+        for bad in ["", "0", "2-", "10", "loud", "sound:", "1-1-1-1-1-1-1"] {
+            assert!(parse_alert(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_rings_are_spaced_out() {
+        let start = Instant::now();
+        let mut bell = Bell::default();
+        bell.ring(&[2, 1], start);
+        assert!(bell.due(start));
+        assert!(!bell.due(start + RING_GAP / 2));
+        assert!(bell.due(start + RING_GAP));
+        // The pause before the second group
+        assert!(!bell.due(start + RING_GAP * 2));
+        assert!(bell.due(start + RING_GAP + GROUP_GAP));
+        assert!(!bell.due(start + GROUP_GAP * 10));
+    }
+}
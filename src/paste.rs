@@ -0,0 +1,139 @@
+//! Pastes: many lines arriving at once.
+//!
+//! Pasted into the input line, every Return would send a message of its own,
+//! flooding each peer's screen. Emulators with bracketed paste mark where a
+//! paste starts and ends (`ESC [ 200 ~` and `ESC [ 201 ~`, once asked with
+//! `ESC [ ? 2004 h`); on a real terminal a paste comes from a terminal
+//! server or a PC on the serial line, and is told from typing by its speed:
+//! a Return with more text straight after it in the same read is more than
+//! anyone types. Either way the lines are gathered up, and if there's more
+//! than one they're held until `y` sends them as one message, or `n` drops
+//! them.
+
+use std::time::{Duration, Instant};
+
+use crate::input::{InputEvent, KeyMap, parse_byte};
+
+/// Ask the terminal to mark pastes
+pub const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+/// Stop marking them
+pub const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+
+/// A paste told by its speed is over after this long without a byte
+const BURST_GAP: Duration = Duration::from_millis(300);
+
+/// A bracketed paste whose end marker went missing is given up on after this
+const BRACKET_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Lines kept from one paste; the rest are dropped
+pub const MAX_PASTE_LINES: usize = 100;
+
+/// Whether what follows a Return in the same read looks like more of a paste
+/// than the line ending's own LF
+pub fn burst_follows(rest: &[u8]) -> bool {
+    rest.iter().any(|&b| b != b'\r' && b != b'\n')
+}
+
+/// A paste being gathered
+#[derive(Debug)]
+pub struct Paste {
+    text: String,
+    bracketed: bool,
+    last_input: Instant,
+    /// The last byte was a CR, so an LF after it ends nothing new
+    after_cr: bool,
+}
+
+impl Paste {
+    /// Start gathering after `typed`, what was in the input line
+    pub fn new(typed: &str, bracketed: bool, now: Instant) -> Self {
+        Self {
+            text: typed.to_string(),
+            bracketed,
+            last_input: now,
+            after_cr: false,
+        }
+    }
+
+    pub fn is_bracketed(&self) -> bool {
+        self.bracketed
+    }
+
+    /// Add a byte of the paste, translated with `keymap`. Control
+    /// characters besides the line endings are dropped, and tabs become
+    /// spaces.
+    pub fn push(&mut self, byte: u8, keymap: &KeyMap, now: Instant) {
+        self.last_input = now;
+        let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+        match parse_byte(byte, keymap) {
+            InputEvent::Enter if byte == b'\n' && after_cr => {}
+            InputEvent::Enter => self.text.push('\n'),
+            InputEvent::Char(c) => self.text.push(c),
+            InputEvent::Space | InputEvent::Tab => self.text.push(' '),
+            _ => {}
+        }
+    }
+
+    /// Whether the paste is over without an end marker: the input has gone
+    /// quiet
+    pub fn settled(&self, now: Instant) -> bool {
+        let wait = if self.bracketed {
+            BRACKET_TIMEOUT
+        } else {
+            BURST_GAP
+        };
+        now.saturating_duration_since(self.last_input) >= wait
+    }
+
+    /// The lines pasted, without the blank ones at either end, and up to
+    /// [`MAX_PASTE_LINES`] of them
+    pub fn finish(self) -> Vec<String> {
+        let lines: Vec<&str> = self.text.lines().collect();
+        let start = lines.iter().position(|l| !l.trim().is_empty());
+        let end = lines.iter().rposition(|l| !l.trim().is_empty());
+        match (start, end) {
+            (Some(start), Some(end)) => lines[start..=end]
+                .iter()
+                .take(MAX_PASTE_LINES)
+                .map(|l| l.trim_end().to_string())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste_gathers_lines() {
+        let start = Instant::now();
+        let keymap = KeyMap::default();
+        let mut paste = Paste::new("fn main() {", false, start);
+        for &b in b"\r\n\tprintln!();\r\n}\r\n\r\n\x07" {
+            paste.push(b, &keymap, start);
+        }
+        assert!(!paste.settled(start + BURST_GAP / 2));
+        assert!(paste.settled(start + BURST_GAP));
+        assert_eq!(paste.finish(), vec!["fn main() {", " println!();", "}"]);
+
+        // Only the line ending after a Return: typing, not a paste
+        assert!(!burst_follows(b"\n"));
+        assert!(burst_follows(b"\nmore"));
+    }
+
+    #[test]
+    fn test_paste_is_capped() {
+        let start = Instant::now();
+        let mut paste = Paste::new("", true, start);
+        for _ in 0..MAX_PASTE_LINES * 2 {
+            for &b in b"spam\r" {
+                paste.push(b, &KeyMap::default(), start);
+            }
+        }
+        // A bracketed paste waits longer for its end marker
+        assert!(!paste.settled(start + BURST_GAP));
+        assert_eq!(paste.finish().len(), MAX_PASTE_LINES);
+    }
+}
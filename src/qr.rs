@@ -0,0 +1,490 @@
+//! QR codes for `/qr`, so a URL mentioned on the VT220 can be picked up
+//! with a phone.
+//!
+//! A plain encoder: byte mode, low error correction (the screen doesn't get
+//! dirty the way a printed code does), versions 1 to 10. The code is drawn
+//! with the user's own renderer, each module as near square as its cells
+//! allow: one half block, a pair of ASCII or DRCS cells, a few sixels.
+
+use std::fmt;
+
+use image::{GrayImage, Luma};
+
+use crate::graphics::Renderer;
+
+/// Largest version we make (57x57 modules)
+const MAX_VERSION: usize = 10;
+
+/// Light modules around the code. The standard asks for four; two is
+/// plenty for a phone and keeps version 4 on a 24-line screen.
+const QUIET_ZONE: usize = 2;
+
+/// Per version: (error correction bytes per block, data bytes per block of
+/// each group as (blocks, bytes)), at level L
+const BLOCKS: [(usize, [(usize, usize); 2]); MAX_VERSION] = [
+    (7, [(1, 19), (0, 0)]),
+    (10, [(1, 34), (0, 0)]),
+    (15, [(1, 55), (0, 0)]),
+    (20, [(1, 80), (0, 0)]),
+    (26, [(1, 108), (0, 0)]),
+    (18, [(2, 68), (0, 0)]),
+    (20, [(2, 78), (0, 0)]),
+    (24, [(2, 97), (0, 0)]),
+    (30, [(2, 116), (0, 0)]),
+    (18, [(2, 68), (2, 69)]),
+];
+
+/// Where the alignment patterns go, per version
+const ALIGNMENT: [&[usize]; MAX_VERSION] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// Why text couldn't be encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QrError {
+    /// More bytes than the largest version holds
+    TooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QrError::TooLong { len, max } => {
+                write!(f, "{} bytes is too long for a QR code (max {})", len, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QrError {}
+
+/// An encoded QR code
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    size: usize,
+    /// Dark modules, row by row
+    modules: Vec<bool>,
+    /// Modules that belong to the fixed patterns rather than the data
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `text` in the smallest version that holds it
+    pub fn encode(text: &str) -> Result<Self, QrError> {
+        let data = text.as_bytes();
+        let version = (1..=MAX_VERSION)
+            .find(|&v| capacity(v) >= data.len())
+            .ok_or(QrError::TooLong {
+                len: data.len(),
+                max: capacity(MAX_VERSION),
+            })?;
+        let codewords = add_error_correction(version, &data_codewords(version, data));
+
+        let size = version * 4 + 17;
+        let mut qr = Self {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        qr.draw_patterns(version);
+        qr.draw_codewords(&codewords);
+
+        // Keep the mask that leaves the fewest confusing patterns
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format(mask);
+                let penalty = qr.penalty();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        qr.apply_mask(mask);
+        qr.draw_format(mask);
+        Ok(qr)
+    }
+
+    /// Modules across (and down)
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column `x`, row `y` is dark
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    /// Draw with `renderer`: light modules lit, as on paper, dark ones dark
+    pub fn render(&self, renderer: &dyn Renderer) -> Vec<String> {
+        let (cell_w, cell_h) = renderer.cell_size();
+        // Source pixels per module: one per dot where a cell has several,
+        // else a whole cell, doubled across to make it square
+        let (module_w, module_h) = match renderer.name() {
+            "halfblock" | "braille" => (1, 1),
+            _ if renderer.is_bitmap() => (4, 4),
+            _ => (cell_w * 2, cell_h),
+        };
+        let modules = (self.size + QUIET_ZONE * 2) as u32;
+        let cols = (modules * module_w).div_ceil(cell_w);
+        let rows = (modules * module_h).div_ceil(cell_h);
+        let image = GrayImage::from_fn(cols * cell_w, rows * cell_h, |x, y| {
+            let (mx, my) = ((x / module_w) as usize, (y / module_h) as usize);
+            let dark = (QUIET_ZONE..QUIET_ZONE + self.size).contains(&mx)
+                && (QUIET_ZONE..QUIET_ZONE + self.size).contains(&my)
+                && self.get(mx - QUIET_ZONE, my - QUIET_ZONE);
+            Luma([if dark { 0 } else { 255 }])
+        });
+        renderer.render(&image, cols, rows)
+    }
+
+    /// Finder, timing and alignment patterns, and room for the format and
+    /// version bits
+    fn draw_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set(6, i, i % 2 == 0);
+            self.set(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (px, py) = (x as i32 + dx, y as i32 + dy);
+                    if (0..size as i32).contains(&px) && (0..size as i32).contains(&py) {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set(px as usize, py as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+        let positions = ALIGNMENT[version - 1];
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Not over the finders
+                if (i, j) == (0, 0) || (i, j) == (0, last) || (i, j) == (last, 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set(
+                            (x as i32 + dx) as usize,
+                            (y as i32 + dy) as usize,
+                            dist != 1,
+                        );
+                    }
+                }
+            }
+        }
+        self.draw_format(0);
+        if version >= 7 {
+            let bits = bch(version as u32, 0x1F25, 12);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set(a, b, dark);
+                self.set(b, a, dark);
+            }
+        }
+    }
+
+    /// The error correction level (L) and mask, twice over
+    fn draw_format(&mut self, mask: u32) {
+        let size = self.size;
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        for i in 0..6 {
+            self.set(8, i, bit(i));
+        }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set(8, size - 8, true);
+    }
+
+    /// Fill the rest in two-column strips, zigzagging up and down from the
+    /// bottom right
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..size {
+                let y = if upward { size - 1 - vert } else { vert };
+                for x in [right, right - 1] {
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flip the data modules picked out by `mask` (doing it twice undoes it)
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                if flip && !self.function[i] {
+                    self.modules[i] = !self.modules[i];
+                }
+            }
+        }
+    }
+
+    /// How hard the code is to read: long runs, solid blocks, things that
+    /// look like finders and too much of one colour
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let lines: Vec<Vec<bool>> = (0..size)
+            .map(|y| (0..size).map(|x| self.get(x, y)).collect())
+            .chain((0..size).map(|x| (0..size).map(|y| self.get(x, y)).collect()))
+            .collect();
+        const FINDER: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+
+        let mut penalty = 0;
+        for line in &lines {
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            for window in line.windows(FINDER.len()) {
+                if window == FINDER || window.iter().rev().eq(FINDER.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if self.get(x + 1, y) == dark
+                    && self.get(x, y + 1) == dark
+                    && self.get(x + 1, y + 1) == dark
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&m| m).count();
+        let k = (dark * 20)
+            .abs_diff(total * 10)
+            .div_ceil(total)
+            .saturating_sub(1);
+        penalty + k * 10
+    }
+}
+
+/// Bytes version `version` holds in byte mode
+fn capacity(version: usize) -> usize {
+    let (_, groups) = BLOCKS[version - 1];
+    let data: usize = groups.iter().map(|(n, len)| n * len).sum();
+    // Less the mode and the length
+    (data * 8 - 4 - count_bits(version)) / 8
+}
+
+/// Size of the length field
+fn count_bits(version: usize) -> usize {
+    if version < 10 { 8 } else { 16 }
+}
+
+/// `data` in byte mode, padded out to fill the version
+fn data_codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let (_, groups) = BLOCKS[version - 1];
+    let capacity_bits = groups.iter().map(|(n, len)| n * len).sum::<usize>() * 8;
+
+    let mut bits: Vec<bool> = Vec::with_capacity(capacity_bits);
+    let mut push = |value: u32, len: usize| {
+        for i in (0..len).rev() {
+            bits.push((value >> i) & 1 != 0);
+        }
+    };
+    push(0b0100, 4);
+    push(data.len() as u32, count_bits(version));
+    for &b in data {
+        push(b as u32, 8);
+    }
+    let terminator = (capacity_bits - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut bytes: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, &b| acc << 1 | b as u8))
+        .collect();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if bytes.len() * 8 >= capacity_bits {
+            break;
+        }
+        bytes.push(pad);
+    }
+    bytes
+}
+
+/// Split into blocks, add each one's error correction and interleave them
+fn add_error_correction(version: usize, data: &[u8]) -> Vec<u8> {
+    let (ec_len, groups) = BLOCKS[version - 1];
+    let divisor = rs_divisor(ec_len);
+    let mut blocks: Vec<&[u8]> = Vec::new();
+    let mut rest = data;
+    for (count, len) in groups {
+        for _ in 0..count {
+            let (block, tail) = rest.split_at(len);
+            blocks.push(block);
+            rest = tail;
+        }
+    }
+    let ecc: Vec<Vec<u8>> = blocks.iter().map(|b| rs_remainder(b, &divisor)).collect();
+
+    let longest = blocks.iter().map(|b| b.len()).max().unwrap_or(0);
+    let mut out = Vec::new();
+    for i in 0..longest {
+        out.extend(blocks.iter().filter_map(|b| b.get(i)));
+    }
+    for i in 0..ec_len {
+        out.extend(ecc.iter().map(|e| e[i]));
+    }
+    out
+}
+
+/// Multiply in GF(256), modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+/// The Reed-Solomon generator polynomial of `degree`, highest term dropped
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+/// The error correction bytes for `data`
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// `value` followed by its BCH check bits
+fn bch(value: u32, generator: u32, check_bits: u32) -> u32 {
+    let mut rem = value;
+    for _ in 0..check_bits {
+        rem = (rem << 1) ^ ((rem >> (check_bits - 1)) * generator);
+    }
+    value << check_bits | rem
+}
+
+/// The 15 format bits for level L and `mask`
+fn format_bits(mask: u32) -> u32 {
+    // Level L is 01
+    bch(0b01 << 3 | mask, 0x537, 10) ^ 0x5412
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_correction() {
+        // The worked "HELLO WORLD" example (version 1, level M)
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, &rs_divisor(10)),
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(format_bits(0), 0b111011111000100);
+        assert_eq!(format_bits(4), 0b110011000101111);
+        // Version 7's version bits
+        assert_eq!(bch(7, 0x1F25, 12), 0b000111110010010100);
+    }
+
+    #[test]
+    fn test_qr_versions() {
+        assert_eq!(capacity(1), 17);
+        assert_eq!(capacity(4), 78);
+        assert_eq!(capacity(10), 271);
+        assert_eq!(QrCode::encode("https://example.com").unwrap().size(), 25);
+        let qr = QrCode::encode(&"x".repeat(200)).unwrap();
+        assert_eq!(qr.size(), 53);
+        // Finder corners dark, their separators light
+        let last = qr.size() - 1;
+        for (x, y) in [(0, 0), (last, 0), (0, last)] {
+            assert!(qr.get(x, y));
+        }
+        assert!(!qr.get(7, 7));
+        assert!(matches!(
+            QrCode::encode(&"x".repeat(300)),
+            Err(QrError::TooLong { len: 300, max: 271 })
+        ));
+    }
+}
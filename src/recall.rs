@@ -0,0 +1,168 @@
+//! Input history: the lines sent from the Chat and AI tabs, for Up and Down
+//! to bring back.
+//!
+//! Each tab keeps its own list, so a prompt typed to the AI doesn't turn up
+//! among chat messages. With a `[logging]` directory the lists are saved to
+//! `input-history.json` there after every line and read back at startup, so
+//! they last across restarts. `input_history` under `[terminal]` sets how
+//! many lines each tab keeps.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::terminal::Tab;
+
+/// Lines kept per tab if `input_history` isn't set
+pub const DEFAULT_INPUT_HISTORY: usize = 500;
+
+/// The history file's name in the logging directory
+pub const FILE_NAME: &str = "input-history.json";
+
+/// The list a tab's lines go in
+fn list_name(tab: Tab) -> &'static str {
+    match tab {
+        Tab::Gemini => "ai",
+        _ => "chat",
+    }
+}
+
+/// Each tab's sent lines, oldest first
+#[derive(Debug)]
+pub struct InputHistory {
+    lists: BTreeMap<String, Vec<String>>,
+    limit: usize,
+    /// Where the lists are saved (None if they aren't)
+    path: Option<PathBuf>,
+}
+
+impl InputHistory {
+    /// An empty history that isn't saved
+    pub fn new(limit: usize) -> Self {
+        Self {
+            lists: BTreeMap::new(),
+            limit,
+            path: None,
+        }
+    }
+
+    /// Load the history saved in the logging directory `dir`. A missing file
+    /// is an empty history; an unreadable one is reported and replaced.
+    pub fn load(dir: &Path, limit: usize) -> Self {
+        let path = dir.join(FILE_NAME);
+        let lists = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!(
+                    "Ignoring unreadable input history {}: {}",
+                    path.display(),
+                    e
+                );
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                eprintln!("Failed to read input history {}: {}", path.display(), e);
+                BTreeMap::new()
+            }
+        };
+        let mut history = Self {
+            lists,
+            limit,
+            path: Some(path),
+        };
+        for list in history.lists.values_mut() {
+            let skip = list.len().saturating_sub(limit);
+            list.drain(..skip);
+        }
+        history
+    }
+
+    /// Lines kept per tab
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The lines sent from `tab`, oldest first
+    pub fn entries(&self, tab: Tab) -> &[String] {
+        self.lists.get(list_name(tab)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Lines kept across all tabs
+    pub fn len(&self) -> usize {
+        self.lists.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remember a line sent from `tab` (unless it repeats the last one) and
+    /// save the history
+    pub fn push(&mut self, tab: Tab, line: &str) {
+        let list = self.lists.entry(list_name(tab).to_string()).or_default();
+        if list.last().map(String::as_str) == Some(line) || self.limit == 0 {
+            return;
+        }
+        list.push(line.to_string());
+        if list.len() > self.limit {
+            list.remove(0);
+        }
+        self.save();
+    }
+
+    /// Replace `tab`'s lines, as a preview session does from a state dump
+    pub fn restore(&mut self, tab: Tab, mut lines: Vec<String>) {
+        let skip = lines.len().saturating_sub(self.limit);
+        lines.drain(..skip);
+        self.lists.insert(list_name(tab).to_string(), lines);
+    }
+
+    /// Write the history file
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_string_pretty(&self.lists)
+            .map_err(io::Error::other)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(e) = result {
+            eprintln!("Failed to save input history {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_per_tab() {
+        let mut history = InputHistory::new(3);
+        for line in ["one", "two", "two", "three", "four"] {
+            history.push(Tab::Chat, line);
+        }
+        history.push(Tab::Gemini, "what is a VT220?");
+        assert_eq!(history.entries(Tab::Chat), ["two", "three", "four"]);
+        assert_eq!(history.entries(Tab::Gemini), ["what is a VT220?"]);
+        assert_eq!(history.len(), 4);
+        assert!(InputHistory::new(3).entries(Tab::Chat).is_empty());
+    }
+
+    #[test]
+    fn test_history_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = InputHistory::load(dir.path(), 10);
+        assert!(history.is_empty());
+        history.push(Tab::Chat, "hello");
+        history.push(Tab::Chat, "/who");
+        history.push(Tab::Gemini, "hi");
+
+        // A smaller limit keeps the newest lines
+        let history = InputHistory::load(dir.path(), 1);
+        assert_eq!(history.entries(Tab::Chat), ["/who"]);
+        assert_eq!(history.entries(Tab::Gemini), ["hi"]);
+    }
+}
@@ -0,0 +1,210 @@
+//! Screensaver: blank the terminal after a spell without input.
+//!
+//! A CRT showing the same borders day and night burns them into its
+//! phosphor. After `screensaver` minutes under `[terminal]` without a key
+//! from the terminal, the screen is cleared and left dark, or shows the clock
+//! or the name drifting round it a step every few seconds, so no cell stays
+//! lit for long. Any key puts the full UI back; the key itself does nothing
+//! else.
+//!
+//! While the screen is blanked the rest of the app's output is held back
+//! (see [`crate::serial::Serial::set_held`]): chat still arrives in the
+//! buffers, and the screen is redrawn from them on waking.
+
+use std::time::{Duration, Instant};
+
+use crate::terminal::{esc, stamp};
+
+/// Screensaver styles accepted in the config
+pub const SCREENSAVER_STYLES: &[&str] = &["blank", "clock", "logo"];
+
+/// How often the clock or logo moves
+const DRIFT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What the logo style shows
+const LOGO: &str = "W O R M H O L E";
+
+/// What's shown on the blanked screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Blank,
+    Clock,
+    Logo,
+}
+
+impl Style {
+    /// Parse a style from its config name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blank" => Some(Style::Blank),
+            "clock" => Some(Style::Clock),
+            "logo" => Some(Style::Logo),
+            _ => None,
+        }
+    }
+
+    fn text(self) -> Option<String> {
+        match self {
+            Style::Blank => None,
+            Style::Clock => Some(stamp::clock()),
+            Style::Logo => Some(LOGO.to_string()),
+        }
+    }
+}
+
+/// Whether the screen is blanked, and where the drifting text is
+#[derive(Debug)]
+pub struct Screensaver {
+    /// Time without input before blanking (None = never)
+    timeout: Option<Duration>,
+    style: Style,
+    last_input: Instant,
+    active: bool,
+    /// Row and column of the text's left end (1-based)
+    pos: (usize, usize),
+    /// Which way it drifts
+    step: (isize, isize),
+    /// Where the text was drawn and how long it was, to erase it
+    drawn: Option<(usize, usize, usize)>,
+    moved_at: Instant,
+}
+
+impl Screensaver {
+    pub fn new(timeout: Option<Duration>, style: Style, now: Instant) -> Self {
+        Self {
+            timeout,
+            style,
+            last_input: now,
+            active: false,
+            pos: (2, 2),
+            step: (1, 3),
+            drawn: None,
+            moved_at: now,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// A key arrived from the terminal. Returns true if it woke the screen,
+    /// which then needs redrawing.
+    pub fn input(&mut self, now: Instant) -> bool {
+        self.last_input = now;
+        std::mem::replace(&mut self.active, false)
+    }
+
+    /// Blank the screen if it's been idle long enough, or move the drifting
+    /// text along. Returns what to send to the terminal, if anything.
+    pub fn tick(&mut self, now: Instant, width: usize, rows: usize) -> Option<String> {
+        if !self.active {
+            let timeout = self.timeout?;
+            if now.saturating_duration_since(self.last_input) < timeout {
+                return None;
+            }
+            self.active = true;
+            self.drawn = None;
+            self.moved_at = now;
+            let mut output = String::from(esc::CURSOR_HIDE);
+            output.push_str(esc::CLEAR_SCREEN);
+            output.push_str(&self.draw(width, rows));
+            return Some(output);
+        }
+        if self.style == Style::Blank
+            || now.saturating_duration_since(self.moved_at) < DRIFT_INTERVAL
+        {
+            return None;
+        }
+        self.moved_at = now;
+        self.drift(width, rows);
+        Some(self.draw(width, rows))
+    }
+
+    /// Erase the text where it was and draw it where it is now
+    fn draw(&mut self, width: usize, rows: usize) -> String {
+        let Some(text) = self.style.text() else {
+            return String::new();
+        };
+        let mut output = String::new();
+        if let Some((row, col, len)) = self.drawn.take() {
+            output.push_str(&esc::cursor_to(row, col));
+            output.push_str(&" ".repeat(len));
+        }
+        let len = text.chars().count();
+        let (row, col) = self.clamp(width, rows, len);
+        output.push_str(&esc::cursor_to(row, col));
+        output.push_str(&text);
+        self.drawn = Some((row, col, len));
+        output
+    }
+
+    /// Move a step, bouncing off the edges of the screen
+    fn drift(&mut self, width: usize, rows: usize) {
+        let len = self.drawn.map_or(1, |(_, _, len)| len);
+        let max_col = width.saturating_sub(len).max(1);
+        let (row, col) = self.pos;
+        let next = |at: usize, step: &mut isize, max: usize| {
+            let moved = at as isize + *step;
+            if moved < 1 || moved > max as isize {
+                *step = -*step;
+            }
+            (at as isize + *step).clamp(1, max as isize) as usize
+        };
+        let row = next(row, &mut self.step.0, rows.max(1));
+        let col = next(col, &mut self.step.1, max_col);
+        self.pos = (row, col);
+    }
+
+    /// The position, kept on screen for text of `len` columns
+    fn clamp(&mut self, width: usize, rows: usize, len: usize) -> (usize, usize) {
+        let max_col = width.saturating_sub(len).max(1);
+        self.pos = (
+            self.pos.0.clamp(1, rows.max(1)),
+            self.pos.1.clamp(1, max_col),
+        );
+        self.pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blanks_after_idle_and_wakes_on_a_key() {
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let mut saver = Screensaver::new(Some(minute), Style::Logo, start);
+        assert_eq!(saver.tick(start + minute / 2, 80, 24), None);
+        // A key starts the wait over
+        assert!(!saver.input(start + minute / 2));
+        assert_eq!(saver.tick(start + minute, 80, 24), None);
+
+        let blanked = saver.tick(start + minute * 2, 80, 24).unwrap();
+        assert!(blanked.contains(esc::CLEAR_SCREEN));
+        assert!(blanked.contains(LOGO));
+        assert!(saver.is_active());
+        // Nothing more until it's time to move
+        assert_eq!(saver.tick(start + minute * 2, 80, 24), None);
+        assert!(saver.input(start + minute * 3));
+        assert!(!saver.is_active());
+
+        let mut never = Screensaver::new(None, Style::Blank, start);
+        assert_eq!(never.tick(start + minute * 600, 80, 24), None);
+    }
+
+    #[test]
+    fn test_drift_stays_on_screen() {
+        let start = Instant::now();
+        let mut saver = Screensaver::new(Some(Duration::ZERO), Style::Logo, start);
+        saver.tick(start, 80, 24).unwrap();
+        for i in 1..200 {
+            let moved = saver.tick(start + DRIFT_INTERVAL * i, 80, 24).unwrap();
+            // The old text is erased before the new is drawn
+            assert!(moved.contains(&" ".repeat(LOGO.len())));
+            let (row, col, len) = saver.drawn.unwrap();
+            assert!((1..=24).contains(&row));
+            assert!(col >= 1 && col + len <= 80, "column {}", col);
+        }
+    }
+}
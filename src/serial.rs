@@ -1,10 +1,12 @@
 //! Serial port communication module.
 
 use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
 use std::time::Duration;
 
 use crate::config::SerialConfig;
+use crate::terminal::{Charset, Controls, VirtualScreen};
 
 /// Default timeout for serial port operations
 const DEFAULT_TIMEOUT_MS: u64 = 10;
@@ -12,7 +14,17 @@ const DEFAULT_TIMEOUT_MS: u64 = 10;
 /// A wrapper around a serial port connection with reconnection support
 pub struct Serial {
     port: Option<Box<dyn SerialPort>>,
+    /// Keystrokes waiting to be read, for a loopback terminal (None = real port)
+    loopback: Option<VecDeque<u8>>,
     config: SerialConfig,
+    /// What the terminal should be showing, given everything written so far
+    screen: VirtualScreen,
+    /// What the terminal can show beyond ASCII
+    charset: Charset,
+    /// Whether output is being held back (while the screensaver is on)
+    held: bool,
+    /// How control sequences go out
+    controls: Controls,
 }
 
 impl Serial {
@@ -21,10 +33,36 @@ impl Serial {
         let port = Self::open_port(config)?;
         Ok(Self {
             port: Some(port),
+            loopback: None,
             config: config.clone(),
+            screen: VirtualScreen::default(),
+            charset: Charset::Utf8,
+            held: false,
+            controls: Controls::SevenBit,
         })
     }
 
+    /// An in-memory terminal, for tests: output only reaches the virtual
+    /// screen, and reads return whatever was queued with [`Self::type_keys`]
+    pub fn loopback(config: &SerialConfig) -> Self {
+        Self {
+            port: None,
+            loopback: Some(VecDeque::new()),
+            config: config.clone(),
+            screen: VirtualScreen::default(),
+            charset: Charset::Utf8,
+            held: false,
+            controls: Controls::SevenBit,
+        }
+    }
+
+    /// Queue keystrokes for a loopback terminal to read
+    pub fn type_keys(&mut self, keys: &str) {
+        if let Some(input) = &mut self.loopback {
+            input.extend(keys.bytes());
+        }
+    }
+
     /// Internal helper to open the port
     fn open_port(config: &SerialConfig) -> Result<Box<dyn SerialPort>, SerialError> {
         serialport::new(&config.port, config.baud_rate)
@@ -42,11 +80,14 @@ impl Serial {
 
     /// Check if the serial port is currently connected
     pub fn is_connected(&self) -> bool {
-        self.port.is_some()
+        self.port.is_some() || self.loopback.is_some()
     }
 
     /// Attempt to reconnect to the serial port
     pub fn reconnect(&mut self) -> Result<(), SerialError> {
+        if self.loopback.is_some() {
+            return Ok(());
+        }
         // Close existing port if any
         self.port = None;
 
@@ -61,8 +102,22 @@ impl Serial {
         self.port = None;
     }
 
+    /// Lend the port out, for a file transfer run on another thread. It reads
+    /// as disconnected, so nothing gets drawn, until [`Self::give_back`].
+    pub fn lend(&mut self) -> Option<Box<dyn SerialPort>> {
+        self.port.take()
+    }
+
+    /// Take back a port lent with [`Self::lend`]
+    pub fn give_back(&mut self, port: Box<dyn SerialPort>) {
+        self.port = Some(port);
+    }
+
     /// Clear the input buffer
     pub fn clear_input(&mut self) -> Result<(), SerialError> {
+        if let Some(input) = &mut self.loopback {
+            input.clear();
+        }
         match self.port.as_mut() {
             Some(port) => port
                 .clear(serialport::ClearBuffer::Input)
@@ -71,16 +126,53 @@ impl Serial {
         }
     }
 
-    /// Write a string to the serial port
+    /// Write a string to the serial port (dropped while output is held)
     pub fn write_str(&mut self, s: &str) -> Result<(), SerialError> {
+        if self.held {
+            return Ok(());
+        }
+        self.write_through(s)
+    }
+
+    /// Hold back what [`Self::write_str`] sends from now on, or stop holding
+    /// it. Held output is dropped: the screen is redrawn once it's released.
+    pub fn set_held(&mut self, held: bool) {
+        self.held = held;
+    }
+
+    /// Write a string even while output is held (the screensaver itself, the
+    /// bell, and copies to the printer port)
+    pub fn write_through(&mut self, s: &str) -> Result<(), SerialError> {
+        self.screen.feed(s);
+        if self.loopback.is_some() {
+            return Ok(());
+        }
         let port = self.port.as_mut().ok_or(SerialError::Disconnected)?;
-        port.write_all(s.as_bytes()).map_err(SerialError::Write)?;
+        let s = self.charset.encode(s);
+        let bytes = self.controls.encode(s.as_bytes());
+        port.write_all(&bytes).map_err(SerialError::Write)?;
         port.flush().map_err(SerialError::Write)?;
         Ok(())
     }
 
+    /// Bytes written but still waiting to go out of the UART (0 if that
+    /// can't be told)
+    pub fn backlog(&self) -> u32 {
+        self.port
+            .as_ref()
+            .and_then(|port| port.bytes_to_write().ok())
+            .unwrap_or(0)
+    }
+
     /// Read available bytes from the serial port (non-blocking style with timeout)
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        if let Some(input) = &mut self.loopback {
+            let n = buf.len().min(input.len());
+            for (byte, key) in buf.iter_mut().zip(input.drain(..n)) {
+                *byte = key;
+            }
+            return Ok(n);
+        }
         let port = self.port.as_mut().ok_or(SerialError::Disconnected)?;
 
         // Check if any bytes are available before blocking on read
@@ -98,6 +190,35 @@ impl Serial {
         }
     }
 
+    /// Map non-ASCII text written from now on to what the terminal can show
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Send control sequences in 7-bit or 8-bit form from now on
+    pub fn set_controls(&mut self, controls: Controls) {
+        self.controls = controls;
+    }
+
+    pub fn controls(&self) -> Controls {
+        self.controls
+    }
+
+    /// The shadow copy of the terminal screen
+    pub fn screen(&self) -> &VirtualScreen {
+        &self.screen
+    }
+
+    /// Start a blank shadow copy of a screen this size (after a change of
+    /// layout or columns; the screen is redrawn next)
+    pub fn set_screen_size(&mut self, width: usize, height: usize) {
+        self.screen = VirtualScreen::new(width, height);
+    }
+
     /// Get the port path
     pub fn port_path(&self) -> &str {
         &self.config.port
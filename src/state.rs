@@ -0,0 +1,195 @@
+//! Session state dumps for debugging.
+//!
+//! `/dump-state` writes a JSON snapshot of the running session (peers, tab
+//! buffers, call state and the configuration) so a reported UI bug can be
+//! attached to an issue. `--load-state` replays such a snapshot into an offline
+//! preview session to reproduce it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::terminal::Tab;
+
+/// Bumped when the dump layout changes incompatibly
+pub const STATE_DUMP_VERSION: u32 = 1;
+
+/// A snapshot of the session
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateDump {
+    pub version: u32,
+    /// Wormhole version that wrote the dump
+    pub app_version: String,
+    pub created: String,
+    pub active_tab: Tab,
+    pub renderer: String,
+    pub peers: Vec<PeerState>,
+    pub chat: BufferState,
+    pub ai: BufferState,
+    pub call: CallState,
+    pub input: InputState,
+    /// The configuration, with secrets removed (informational only, never loaded)
+    pub config: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerState {
+    pub name: String,
+    pub addr: String,
+    /// Seconds since the peer was last heard from
+    pub last_seen_secs: u64,
+}
+
+/// The contents of a chat buffer, as already-wrapped lines
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BufferState {
+    pub lines: Vec<String>,
+    pub scroll_offset: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CallState {
+    pub active_call: Option<String>,
+    pub connected: bool,
+    pub last_packet_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InputState {
+    pub line: String,
+    pub cursor: usize,
+    pub history: Vec<String>,
+}
+
+impl StateDump {
+    /// Write the dump as pretty-printed JSON into `dir` (or the working directory)
+    pub fn save(&self, dir: Option<&str>) -> Result<PathBuf, StateError> {
+        let name = format!(
+            "wormhole-state-{}.json",
+            Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let path = match dir {
+            Some(dir) => {
+                fs::create_dir_all(dir).map_err(StateError::Io)?;
+                Path::new(dir).join(name)
+            }
+            None => PathBuf::from(name),
+        };
+        let json = serde_json::to_string_pretty(self).map_err(StateError::Json)?;
+        fs::write(&path, json).map_err(StateError::Io)?;
+        Ok(path)
+    }
+
+    /// Read a dump written by [`StateDump::save`]
+    pub fn load(path: &Path) -> Result<Self, StateError> {
+        let json = fs::read_to_string(path).map_err(StateError::Io)?;
+        let dump: Self = serde_json::from_str(&json).map_err(StateError::Json)?;
+        if dump.version != STATE_DUMP_VERSION {
+            return Err(StateError::Version(dump.version));
+        }
+        Ok(dump)
+    }
+}
+
+/// The configuration as JSON, without the API key
+pub fn redacted_config(config: &Config) -> serde_json::Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    if let Some(key) = value.pointer_mut("/ai/api_key")
+        && !key.is_null()
+    {
+        *key = serde_json::Value::String("<redacted>".to_string());
+    }
+    value
+}
+
+/// Errors from saving or loading state dumps
+#[derive(Debug)]
+pub enum StateError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{}", e),
+            StateError::Json(e) => write!(f, "Invalid state dump: {}", e),
+            StateError::Version(v) => write!(
+                f,
+                "Unsupported state dump version {} (expected {})",
+                v, STATE_DUMP_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Io(e) => Some(e),
+            StateError::Json(e) => Some(e),
+            StateError::Version(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_dump() -> StateDump {
+        StateDump {
+            version: STATE_DUMP_VERSION,
+            app_version: "0.0.0".to_string(),
+            created: "2025-01-01T00:00:00".to_string(),
+            active_tab: Tab::Gemini,
+            renderer: "ascii".to_string(),
+            peers: vec![PeerState {
+                name: "bob".to_string(),
+                addr: "10.0.0.2:7890".to_string(),
+                last_seen_secs: 3,
+            }],
+            chat: BufferState {
+                lines: vec!["[10:00AM] bob: hi".to_string()],
+                scroll_offset: 0,
+            },
+            ai: BufferState::default(),
+            call: CallState::default(),
+            input: InputState {
+                line: "hel".to_string(),
+                cursor: 3,
+                history: vec!["/who".to_string()],
+            },
+            config: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("wormhole-state-test-{}", std::process::id()));
+        let path = sample_dump().save(dir.to_str()).unwrap();
+        let loaded = StateDump::load(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(loaded.active_tab, Tab::Gemini);
+        assert_eq!(loaded.peers[0].name, "bob");
+        assert_eq!(loaded.chat.lines, vec!["[10:00AM] bob: hi"]);
+        assert_eq!(loaded.input.cursor, 3);
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let dir = std::env::temp_dir().join(format!("wormhole-state-ver-{}", std::process::id()));
+        let mut dump = sample_dump();
+        dump.version = STATE_DUMP_VERSION + 1;
+        let path = dump.save(dir.to_str()).unwrap();
+        let result = StateDump::load(&path);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(matches!(result, Err(StateError::Version(_))));
+    }
+}
@@ -1,14 +1,130 @@
 //! Chat buffer with scrollback support.
 
+use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use super::esc;
-use super::{CHAT_REGION_START, CHAT_VISIBLE_LINES, MAX_SCROLLBACK};
-use crate::graphics::{DecGraphicsChar, ENTER_DEC_GRAPHICS, EXIT_DEC_GRAPHICS};
+use super::layout::chat_visible_lines;
+use super::{CHAT_REGION_START, MAX_SCROLLBACK};
+use super::{Theme, color, esc};
+use crate::graphics::{DecGraphicsChar, Frame, render_frame_diff};
+
+/// How many recent lines a late message can be inserted above
+const REORDER_WINDOW: usize = 50;
+
+/// Ends our own lines until every peer has confirmed delivery
+const PENDING_MARKER: &str = " *";
+
+/// Drawn above the first line that arrived while the buffer's tab was away
+const NEW_MESSAGES: &str = "---- new messages ----";
+
+/// Whether the terminal has DECSTBM scroll regions (ANSI.SYS and its kin
+/// don't), set once at startup from the mode
+static SCROLL_REGIONS: AtomicBool = AtomicBool::new(true);
+
+/// Let [`ChatBuffer::render_update`] scroll the chat area, or make it draw
+/// every change in full
+pub fn set_scroll_regions(enabled: bool) {
+    SCROLL_REGIONS.store(enabled, Ordering::Relaxed);
+}
 
 /// Calculate visible length of a string (ignoring escape codes)
 pub(crate) fn visible_len(s: &str) -> usize {
-    s.chars().filter(|&c| c != '\x0E' && c != '\x0F').count()
+    let mut len = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x0E' | '\x0F' => {}
+            '\x1b' if chars.peek() == Some(&'[') => {
+                // Skip CSI sequences (e.g. SGR colours) up to the final byte
+                chars.next();
+                for p in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&p) {
+                        break;
+                    }
+                }
+            }
+            _ => len += 1,
+        }
+    }
+    len
+}
+
+/// Byte offset in `s` just past its first `cells` visible characters, so a
+/// split there never lands inside a character or an escape sequence
+fn visible_prefix(s: &str, cells: usize) -> usize {
+    let mut len = 0;
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\x0E' | '\x0F' => {}
+            '\x1b' if chars.peek().map(|&(_, p)| p) == Some('[') => {
+                chars.next();
+                for (_, p) in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&p) {
+                        break;
+                    }
+                }
+            }
+            _ if len == cells => return i,
+            _ => len += 1,
+        }
+    }
+    s.len()
+}
+
+/// The SGR attributes still on at the end of `line`, to carry onto the line
+/// it wraps to
+fn open_attrs(line: &str) -> String {
+    let mut open = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("\x1b[") {
+        let params = &rest[start + 2..];
+        let Some(end) = params.find(|c: char| ('\x40'..='\x7e').contains(&c)) else {
+            break;
+        };
+        let sequence = &rest[start..start + 2 + end + 1];
+        if params[end..].starts_with('m') {
+            if matches!(&params[..end], "" | "0") {
+                open.clear();
+            } else {
+                open.push_str(sequence);
+            }
+        }
+        rest = &rest[start + sequence.len()..];
+    }
+    open
+}
+
+/// A scrollback search in progress
+struct Search {
+    query: String,
+    /// Line of the current match
+    line: usize,
+}
+
+/// Whether `line` contains `query`, ignoring ASCII case
+fn line_matches(line: &str, query: &str) -> bool {
+    line.to_ascii_lowercase()
+        .contains(&query.to_ascii_lowercase())
+}
+
+/// `line` with each occurrence of `query` in reverse video
+fn highlight(line: &str, query: &str) -> String {
+    let lower = line.to_ascii_lowercase();
+    let query = query.to_ascii_lowercase();
+    let mut output = String::new();
+    let mut rest = 0;
+    // ASCII lowercasing keeps byte offsets, so matches in `lower` index `line`
+    for (start, found) in lower.match_indices(&query) {
+        output.push_str(&line[rest..start]);
+        output.push_str(esc::REVERSE);
+        output.push_str(&line[start..start + found.len()]);
+        output.push_str(esc::RESET_ATTRS);
+        rest = start + found.len();
+    }
+    output.push_str(&line[rest..]);
+    output
 }
 
 /// Chat buffer with scrollback support
@@ -17,6 +133,26 @@ pub struct ChatBuffer {
     lines: VecDeque<String>,
     /// Current scroll offset (0 = viewing most recent, >0 = scrolled up)
     scroll_offset: usize,
+    /// Lamport time of the message each line belongs to (parallel to `lines`)
+    keys: VecDeque<u64>,
+    /// Index of the "new messages" divider line, if one is drawn
+    divider: Option<usize>,
+    /// The buffer's tab isn't being viewed
+    hidden: bool,
+    /// The next line to arrive gets a divider above it
+    divider_due: bool,
+    /// Line held on the first row of the chat area, above the scrolling lines
+    pin: Option<String>,
+    /// The search being stepped through, if any
+    search: Option<Search>,
+    /// How the borders are drawn
+    theme: Theme,
+    /// The rows of messages as last rendered, inside the borders, for
+    /// [`render_update`] to compare with (None when the screen may show
+    /// something else)
+    ///
+    /// [`render_update`]: Self::render_update
+    shown: Option<Vec<String>>,
     /// Terminal width for wrapping
     width: usize,
 }
@@ -27,13 +163,122 @@ impl ChatBuffer {
         Self {
             lines: VecDeque::with_capacity(MAX_SCROLLBACK),
             scroll_offset: 0,
+            keys: VecDeque::with_capacity(MAX_SCROLLBACK),
+            divider: None,
+            hidden: false,
+            divider_due: false,
+            pin: None,
+            search: None,
+            theme: Theme::default(),
+            shown: None,
             width,
         }
     }
 
+    /// Draw the borders (and the pin) in `theme`
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.shown = None;
+    }
+
+    /// Hold a line on the first row (None to let the messages have it back)
+    pub fn set_pin(&mut self, pin: Option<String>) {
+        self.pin = pin;
+        self.fit_rows();
+    }
+
+    /// The chat area has fewer or more rows (the input area was resized):
+    /// keep the scroll position in range
+    pub fn fit_rows(&mut self) {
+        self.shown = None;
+        let max_offset = self.lines.len().saturating_sub(self.rows());
+        self.scroll_offset = self.scroll_offset.min(max_offset);
+    }
+
+    /// Rows of messages shown (one fewer while a line is pinned)
+    fn rows(&self) -> usize {
+        chat_visible_lines() - usize::from(self.pin.is_some())
+    }
+
+    /// Screen row of the first message shown
+    fn first_row(&self) -> usize {
+        CHAT_REGION_START + usize::from(self.pin.is_some())
+    }
+
+    /// The buffer's tab was switched away from: the old divider goes, and a
+    /// new one marks the first line to arrive from now on
+    pub fn leave(&mut self) {
+        if let Some(i) = self.divider.take() {
+            // Below a scrolled-up view, the view moves down with it
+            if i >= self.lines.len() - self.scroll_offset {
+                self.scroll_offset -= 1;
+            }
+            self.lines.remove(i);
+            self.keys.remove(i);
+            if let Some(search) = &mut self.search
+                && search.line > i
+            {
+                search.line -= 1;
+            }
+        }
+        self.hidden = true;
+        self.divider_due = true;
+        self.shown = None;
+    }
+
+    /// The buffer's tab is shown again. If the divider has scrolled off the
+    /// top it's brought back into view, so reading picks up where it stopped;
+    /// otherwise the scroll position is left as it was.
+    pub fn enter(&mut self) {
+        self.hidden = false;
+        self.shown = None;
+        self.divider_due = false;
+        let Some(i) = self.divider else {
+            return;
+        };
+        let end = self.lines.len() - self.scroll_offset;
+        if i < end.saturating_sub(self.rows()) {
+            self.scroll_offset = self.lines.len().saturating_sub(i + self.rows());
+        }
+    }
+
+    /// Put in the divider if this is the first line since the tab was left
+    fn place_divider(&mut self, at: usize) {
+        if !self.divider_due {
+            return;
+        }
+        self.divider_due = false;
+        let max_len = self.width - 4;
+        let pad = max_len.saturating_sub(NEW_MESSAGES.len()) / 2;
+        let key = if at > 0 { self.keys[at - 1] } else { 0 };
+        self.lines
+            .insert(at, format!("{}{}", " ".repeat(pad), NEW_MESSAGES));
+        self.keys.insert(at, key);
+        self.follow_insert(at, 1);
+        self.divider = Some(at);
+    }
+
+    /// Lines were inserted at `at`: keep the divider and a scrolled-up view
+    /// where they were
+    fn follow_insert(&mut self, at: usize, n: usize) {
+        if let Some(i) = &mut self.divider
+            && *i >= at
+        {
+            *i += n;
+        }
+        if let Some(search) = &mut self.search
+            && search.line >= at
+        {
+            search.line += n;
+        }
+        if self.scroll_offset > 0 {
+            self.scroll_offset += n;
+        }
+    }
+
     /// Check if the buffer has enough lines to fill the screen
     pub fn is_full(&self) -> bool {
-        self.lines.len() > CHAT_VISIBLE_LINES
+        self.lines.len() > self.rows()
     }
 
     /// Append a character, handling wrapping with indentation
@@ -42,7 +287,7 @@ impl ChatBuffer {
         let max_len = self.width - 4;
 
         if self.lines.is_empty() {
-            self.lines.push_back(String::new());
+            self.push_raw(String::new());
         }
 
         let last_idx = self.lines.len() - 1;
@@ -68,14 +313,16 @@ impl ChatBuffer {
             }
 
             if moved {
-                self.lines[last_idx] = truncated_line;
                 let mut new_line = String::from(indent);
+                new_line.push_str(&open_attrs(&truncated_line));
+                self.lines[last_idx] = truncated_line;
                 new_line.push_str(&word_to_move);
                 new_line.push(ch);
                 self.push_raw(new_line);
             } else {
                 // Character wrap
                 let mut new_line = String::from(indent);
+                new_line.push_str(&open_attrs(&self.lines[last_idx]));
                 new_line.push(ch);
                 self.push_raw(new_line);
             }
@@ -86,11 +333,139 @@ impl ChatBuffer {
         }
     }
 
+    /// Append an escape sequence (bold, say) to the line being typed, taking
+    /// no room
+    pub fn type_attrs(&mut self, sequence: &str) {
+        match self.lines.back_mut() {
+            Some(line) => line.push_str(sequence),
+            None => self.push_raw(sequence.to_string()),
+        }
+    }
+
     /// Add a message to the buffer, wrapping if necessary
     pub fn push(&mut self, message: String) {
+        for line in self.wrap(&message) {
+            self.push_raw(line);
+        }
+    }
+
+    /// Add a message at its place in logical order
+    ///
+    /// Lines carry the Lamport time of the message they belong to. The message
+    /// goes after the last line with an equal or earlier time, so chat that
+    /// arrived out of order across the mesh is slotted back into sequence.
+    /// Only the last [`REORDER_WINDOW`] lines are searched; anything older has
+    /// already been read and is left alone.
+    pub fn push_ordered(&mut self, message: String, key: u64) {
+        let floor = self.lines.len().saturating_sub(REORDER_WINDOW);
+        let mut at = self.lines.len();
+        while at > floor && self.keys[at - 1] > key {
+            at -= 1;
+        }
+        self.place_divider(at);
+        if self.divider == Some(at) {
+            at += 1;
+        }
+        let wrapped = self.wrap(&message);
+        let n = wrapped.len();
+        for (i, line) in wrapped.into_iter().enumerate() {
+            self.lines.insert(at + i, line);
+            self.keys.insert(at + i, key);
+        }
+        self.follow_insert(at, n);
+        self.trim();
+    }
+
+    /// Add one of our own messages, marked as not yet delivered
+    pub fn push_pending(&mut self, message: String, key: u64) {
+        self.push_ordered(message + PENDING_MARKER, key);
+    }
+
+    /// Take the pending marker off the message with this Lamport time.
+    /// Returns false if it isn't (or is no longer) in the buffer.
+    pub fn mark_delivered(&mut self, key: u64) -> bool {
+        let marker = PENDING_MARKER.trim_start();
+        let Some(i) = (0..self.lines.len())
+            .rev()
+            .find(|&i| self.keys[i] == key && self.lines[i].ends_with(marker))
+        else {
+            return false;
+        };
+        let line = self.lines[i]
+            .strip_suffix(marker)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string();
+        if line.is_empty() {
+            // The marker had wrapped onto a line of its own
+            self.lines.remove(i);
+            self.keys.remove(i);
+            if let Some(d) = &mut self.divider
+                && *d > i
+            {
+                *d -= 1;
+            }
+        } else {
+            self.lines[i] = line;
+        }
+        true
+    }
+
+    /// Put `new` in place of the message `old` that was pushed with this key
+    /// (still marked pending or not). Returns false if it isn't (or is no
+    /// longer) in the buffer.
+    pub fn replace(&mut self, key: u64, old: &str, new: &str) -> bool {
+        let pending = format!("{}{}", old, PENDING_MARKER);
+        let Some((at, n)) = [pending.as_str(), old].into_iter().find_map(|message| {
+            let wrapped = self.wrap(message);
+            let at = (0..=self.lines.len().saturating_sub(wrapped.len()))
+                .rev()
+                .find(|&i| {
+                    self.keys[i] == key
+                        && wrapped
+                            .iter()
+                            .enumerate()
+                            .all(|(j, line)| self.lines[i + j] == *line)
+                })?;
+            Some((at, wrapped.len()))
+        }) else {
+            return false;
+        };
+        let wrapped = self.wrap(new);
+        let m = wrapped.len();
+        for _ in 0..n {
+            self.lines.remove(at);
+            self.keys.remove(at);
+        }
+        for (i, line) in wrapped.into_iter().enumerate() {
+            self.lines.insert(at + i, line);
+            self.keys.insert(at + i, key);
+        }
+        // Lines below move by the difference
+        let shift = |i: usize| {
+            if i >= at + n {
+                i + m - n
+            } else {
+                i.min(at + m - 1)
+            }
+        };
+        self.divider = self.divider.map(shift);
+        if let Some(search) = &mut self.search {
+            search.line = shift(search.line);
+        }
+        if self.scroll_offset > 0 {
+            self.scroll_offset = (self.scroll_offset + m).saturating_sub(n);
+        }
+        self.trim();
+        true
+    }
+
+    /// Word-wrap a message into display lines
+    fn wrap(&self, message: &str) -> Vec<String> {
+        let mut wrapped = Vec::new();
         if message.is_empty() {
-            self.push_raw(String::new());
-            return;
+            wrapped.push(String::new());
+            return wrapped;
         }
 
         let max_len = self.width - 4; // "│ " on left, " │" on right
@@ -101,24 +476,28 @@ impl ChatBuffer {
 
             for word in line.split(' ') {
                 let space_len = if first_word { 0 } else { 1 };
-                let word_len = word.len();
+                let word_len = visible_len(word);
 
-                if current_line.len() + space_len + word_len > max_len {
+                if visible_len(&current_line) + space_len + word_len > max_len {
                     // Line full, push it
-                    if !current_line.is_empty() {
-                        self.push_raw(current_line);
-                        current_line = String::new();
+                    if visible_len(&current_line) > 0 {
+                        // Whatever's on carries onto the next line
+                        let carried = open_attrs(&current_line);
+                        wrapped.push(std::mem::replace(&mut current_line, carried));
                         // first_word becomes true for the new line, but we immediately add the current word
                         // so it will become false again at the end of this iteration.
                     }
 
                     // Now handle the word
-                    if word.len() > max_len {
+                    if word_len > max_len {
                         // Word too long, split it
                         let mut remaining = word;
-                        while remaining.len() > max_len {
-                            self.push_raw(remaining[..max_len].to_string());
-                            remaining = &remaining[max_len..];
+                        while visible_len(remaining) > max_len {
+                            let at = visible_prefix(remaining, max_len);
+                            current_line.push_str(&remaining[..at]);
+                            let carried = open_attrs(&current_line);
+                            wrapped.push(std::mem::replace(&mut current_line, carried));
+                            remaining = &remaining[at..];
                         }
                         current_line.push_str(remaining);
                         first_word = false;
@@ -139,23 +518,39 @@ impl ChatBuffer {
 
             // Push the last line
             if !current_line.is_empty() || line.is_empty() {
-                self.push_raw(current_line);
+                wrapped.push(current_line);
             }
         }
+        wrapped
     }
 
     /// Internal helper to push a single line and handle capacity
     fn push_raw(&mut self, line: String) {
+        self.place_divider(self.lines.len());
+        let key = self.keys.back().copied().unwrap_or(0);
         self.lines.push_back(line);
+        self.keys.push_back(key);
+        self.follow_insert(self.lines.len() - 1, 1);
+        self.trim();
+    }
 
-        // Remove old lines if over capacity
+    /// Drop the oldest lines beyond the scrollback limit
+    fn trim(&mut self) {
         while self.lines.len() > MAX_SCROLLBACK {
             self.lines.pop_front();
-            // Adjust scroll offset if we removed lines we were viewing
-            if self.scroll_offset > 0 {
-                self.scroll_offset = self.scroll_offset.saturating_sub(1);
+            self.keys.pop_front();
+            self.divider = self.divider.and_then(|i| i.checked_sub(1));
+            // A match that scrolls out of the buffer ends the search
+            if let Some(search) = &mut self.search {
+                match search.line.checked_sub(1) {
+                    Some(line) => search.line = line,
+                    None => self.search = None,
+                }
             }
         }
+        // A scrolled-up view can't go above the oldest line
+        let max_offset = self.lines.len().saturating_sub(self.rows());
+        self.scroll_offset = self.scroll_offset.min(max_offset);
     }
 
     /// Update the last line in the buffer (useful for streaming)
@@ -175,12 +570,102 @@ impl ChatBuffer {
     /// Clear the chat buffer
     pub fn clear(&mut self) {
         self.lines.clear();
+        self.keys.clear();
+        self.divider = None;
+        self.search = None;
         self.scroll_offset = 0;
     }
 
+    /// Find the newest line containing `query` (ignoring case) and scroll it
+    /// into view, highlighted. Returns which match it is (1 = newest) and how
+    /// many there are, or None if nothing matched.
+    pub fn search(&mut self, query: &str) -> Option<(usize, usize)> {
+        self.search = None;
+        let line = self.lines.iter().rposition(|l| line_matches(l, query))?;
+        self.search = Some(Search {
+            query: query.to_string(),
+            line,
+        });
+        self.show_line(line);
+        self.search_position()
+    }
+
+    /// Step to the next older (or newer) match, staying put at either end
+    pub fn search_step(&mut self, older: bool) -> Option<(usize, usize)> {
+        let search = self.search.as_ref()?;
+        let found = if older {
+            self.lines
+                .range(..search.line)
+                .rposition(|l| line_matches(l, &search.query))
+        } else {
+            self.lines
+                .range(search.line + 1..)
+                .position(|l| line_matches(l, &search.query))
+                .map(|i| i + search.line + 1)
+        };
+        if let Some(line) = found {
+            if let Some(search) = &mut self.search {
+                search.line = line;
+            }
+            self.show_line(line);
+        }
+        self.search_position()
+    }
+
+    /// Stop highlighting the match (the view stays where it is)
+    pub fn end_search(&mut self) {
+        self.search = None;
+    }
+
+    /// The query being searched for
+    pub fn search_query(&self) -> Option<&str> {
+        self.search.as_ref().map(|s| s.query.as_str())
+    }
+
+    /// Which match is current (1 = newest) and how many there are
+    fn search_position(&self) -> Option<(usize, usize)> {
+        let search = self.search.as_ref()?;
+        let matches: Vec<usize> = (0..self.lines.len())
+            .filter(|&i| line_matches(&self.lines[i], &search.query))
+            .collect();
+        let newer = matches.iter().filter(|&&i| i > search.line).count();
+        Some((newer + 1, matches.len()))
+    }
+
+    /// Scroll so `line` is in the middle of the view (or as near as it gets)
+    fn show_line(&mut self, line: usize) {
+        let total = self.lines.len();
+        let end = (line + self.rows() / 2 + 1).clamp(self.rows().min(total), total);
+        self.scroll_offset = total - end;
+    }
+
+    /// A line as drawn: the current search match highlighted, or coloured
+    fn draw_line<'a>(&self, index: usize, line: &'a str) -> Cow<'a, str> {
+        match &self.search {
+            Some(search) if search.line == index => Cow::Owned(highlight(line, &search.query)),
+            _ => color::paint(line),
+        }
+    }
+
+    /// Current scroll offset (0 = viewing most recent)
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll_offset
+    }
+
+    /// Replace the contents with already-wrapped lines (e.g. from a state dump)
+    pub fn restore(&mut self, lines: Vec<String>, scroll_offset: usize) {
+        let skip = lines.len().saturating_sub(MAX_SCROLLBACK);
+        self.lines = lines.into_iter().skip(skip).collect();
+        self.keys = std::iter::repeat_n(0, self.lines.len()).collect();
+        self.divider = None;
+        self.search = None;
+        self.scroll_offset = 0;
+        self.scroll_up(scroll_offset);
+    }
+
     /// Scroll up by n lines
     pub fn scroll_up(&mut self, n: usize) {
-        let max_offset = self.lines.len().saturating_sub(CHAT_VISIBLE_LINES);
+        let max_offset = self.lines.len().saturating_sub(self.rows());
         self.scroll_offset = (self.scroll_offset + n).min(max_offset);
     }
 
@@ -189,23 +674,36 @@ impl ChatBuffer {
         self.scroll_offset = self.scroll_offset.saturating_sub(n);
     }
 
-    /// Scroll to bottom (most recent messages)
+    /// Scroll to bottom (most recent messages). A hidden buffer keeps its
+    /// place until its tab is shown again.
     pub fn scroll_to_bottom(&mut self) {
-        self.scroll_offset = 0;
+        if !self.hidden {
+            self.scroll_offset = 0;
+        }
+    }
+
+    /// Get the most recent `n` lines in the buffer, oldest first
+    pub fn recent_lines(&self, n: usize) -> Vec<&str> {
+        let start = self.lines.len().saturating_sub(n);
+        self.lines.iter().skip(start).map(|s| s.as_str()).collect()
+    }
+
+    /// Indices of the lines in the display window
+    fn visible_range(&self) -> (usize, usize) {
+        // scroll_offset=0 means show the last rows()
+        // scroll_offset=N means show N lines earlier
+        let end = self.lines.len().saturating_sub(self.scroll_offset);
+        (end.saturating_sub(self.rows()), end)
     }
 
     /// Get the lines currently visible in the display window
-    fn visible_lines(&self) -> Vec<&str> {
+    pub fn visible_lines(&self) -> Vec<&str> {
         let total = self.lines.len();
         if total == 0 {
             return vec![];
         }
 
-        // Calculate the range of lines to show
-        // scroll_offset=0 means show the last CHAT_VISIBLE_LINES
-        // scroll_offset=N means show N lines earlier
-        let end = total.saturating_sub(self.scroll_offset);
-        let start = end.saturating_sub(CHAT_VISIBLE_LINES);
+        let (start, end) = self.visible_range();
 
         self.lines
             .iter()
@@ -216,157 +714,239 @@ impl ChatBuffer {
     }
 
     /// Render the last n visible lines
-    pub fn render_bottom_lines(&self, n: usize) -> String {
-        use DecGraphicsChar::VerticalLine;
-
-        let visible = self.visible_lines();
-        if visible.is_empty() {
+    pub fn render_bottom_lines(&mut self, n: usize) -> String {
+        let shown = self.visible_lines().len();
+        if shown == 0 {
             return String::new();
         }
-
-        let count = n.min(visible.len());
-        let start_idx = visible.len() - count;
-
+        let rows = self.draw_rows();
         let mut output = String::new();
         output.push_str(esc::SAVE_CURSOR);
-
-        for i in 0..count {
-            let row_idx = start_idx + i;
-            let screen_row = CHAT_REGION_START + row_idx;
-            let line = visible[row_idx];
-            let max_len = self.width - 4;
-
-            output.push_str(&esc::cursor_to(screen_row, 1));
-
-            // Left border
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
-            output.push(' ');
-
-            // Content
-            output.push_str(line);
-            // Pad
-            let vis_len = visible_len(line);
-            for _ in vis_len..max_len {
-                output.push(' ');
+        for (i, row) in rows
+            .into_iter()
+            .enumerate()
+            .take(shown)
+            .skip(shown - n.min(shown))
+        {
+            output.push_str(&esc::cursor_to(self.first_row() + i, 1));
+            output.push_str(&self.bordered(&row));
+            // The rest of the area is as it was
+            if let Some(drawn) = self.shown.as_mut().and_then(|d| d.get_mut(i)) {
+                *drawn = row;
             }
-
-            // Right border
-            output.push(' ');
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
         }
-
         output.push_str(esc::RESTORE_CURSOR);
         output
     }
 
     /// Render only the last visible line (optimization for streaming)
-    pub fn render_last_line(&self) -> String {
-        use DecGraphicsChar::VerticalLine;
-
-        let visible = self.visible_lines();
-        if visible.is_empty() {
-            return String::new();
-        }
+    pub fn render_last_line(&mut self) -> String {
+        self.render_bottom_lines(1)
+    }
 
-        let row_idx = visible.len() - 1;
-        let screen_row = CHAT_REGION_START + row_idx;
-        let line = visible[row_idx];
+    /// What's between the borders on one row of the chat area: the line at
+    /// `index` (or nothing) padded out to the width
+    fn draw_row(&self, index: usize, line: Option<&str>) -> String {
         let max_len = self.width - 4;
-
-        let mut output = String::new();
-        output.push_str(esc::SAVE_CURSOR);
-        output.push_str(&esc::cursor_to(screen_row, 1));
-
-        // Left border
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(VerticalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
-        output.push(' ');
-
-        // Content
-        output.push_str(line);
-        // Pad
-        let vis_len = visible_len(line);
+        let mut row = String::from(" ");
+        let vis_len = match line {
+            Some(line) => {
+                row.push_str(&self.draw_line(index, line));
+                if line.contains('\x1b') {
+                    // Nothing still on spills into the padding
+                    row.push_str(esc::RESET_ATTRS);
+                }
+                visible_len(line)
+            }
+            None => 0,
+        };
+        // Pad to clear old content
         for _ in vis_len..max_len {
-            output.push(' ');
+            row.push(' ');
         }
+        row.push(' ');
+        row
+    }
 
-        // Right border
-        output.push(' ');
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(VerticalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
+    /// A row from [`draw_row`](Self::draw_row) with the borders either side
+    fn bordered(&self, row: &str) -> String {
+        let border = self.theme.border(&[DecGraphicsChar::VerticalLine]);
+        format!("{}{}{}", border, row, border)
+    }
 
-        output.push_str(esc::RESTORE_CURSOR);
-        output
+    /// Rows from [`draw_row`](Self::draw_row) as cells, if every one fills
+    /// the width (a character the terminal draws double-width can't be
+    /// diffed cell by cell)
+    fn frame(&self, rows: &[String]) -> Option<Frame> {
+        let frame = Frame::from_strings(rows);
+        frame
+            .rows
+            .iter()
+            .all(|row| row.len() == self.width - 2)
+            .then_some(frame)
+    }
+
+    /// Every row of messages, as [`draw_row`](Self::draw_row) draws them
+    fn draw_rows(&self) -> Vec<String> {
+        let visible = self.visible_lines();
+        let first = self.visible_range().0;
+        (0..self.rows())
+            .map(|i| self.draw_row(first + i, visible.get(i).copied()))
+            .collect()
     }
 
     /// Render the entire chat area
-    pub fn render(&self) -> String {
+    pub fn render(&mut self) -> String {
         use DecGraphicsChar::VerticalLine;
 
         let mut output = String::new();
-        let visible = self.visible_lines();
         let max_len = self.width - 4;
 
         // Save cursor
         output.push_str(esc::SAVE_CURSOR);
 
         // Draw each row in the chat area
-        for row_idx in 0..CHAT_VISIBLE_LINES {
-            let screen_row = CHAT_REGION_START + row_idx;
-            output.push_str(&esc::cursor_to(screen_row, 1));
-
-            // Left border
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+        if let Some(pin) = &self.pin {
+            output.push_str(&esc::cursor_to(CHAT_REGION_START, 1));
+            output.push_str(&self.theme.border(&[VerticalLine]));
             output.push(' ');
-
-            // Content or empty
-            if row_idx < visible.len() {
-                let line = visible[row_idx];
-                output.push_str(line);
-                // Pad to clear old content
-                let vis_len = visible_len(line);
-                for _ in vis_len..max_len {
-                    output.push(' ');
-                }
-            } else {
-                // Empty line
-                for _ in 0..max_len {
-                    output.push(' ');
-                }
+            let text: String = pin.chars().take(max_len).collect();
+            if self.theme.reverse {
+                output.push_str(esc::REVERSE);
+            }
+            output.push_str(&text);
+            for _ in text.chars().count()..max_len {
+                output.push(' ');
+            }
+            if self.theme.reverse {
+                output.push_str(esc::RESET_ATTRS);
             }
-
-            // Right border
             output.push(' ');
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&self.theme.border(&[VerticalLine]));
         }
 
+        let rows = self.draw_rows();
+        for (i, row) in rows.iter().enumerate() {
+            output.push_str(&esc::cursor_to(self.first_row() + i, 1));
+            output.push_str(&self.bordered(row));
+        }
+        self.shown = Some(rows);
+
         // Restore cursor
         output.push_str(esc::RESTORE_CURSOR);
 
         output
     }
+
+    /// Draw what's changed since the chat area was last rendered. When the
+    /// lines have only moved up, as they do when a message arrives, the area
+    /// is scrolled inside a DECSTBM scroll region and just the new rows are
+    /// drawn: at 9600 baud that's one row's worth of bytes instead of the
+    /// whole area. Otherwise only the cells that changed are drawn, so a
+    /// delivery marker or an edited line costs a few bytes too.
+    pub fn render_update(&mut self) -> String {
+        let Some(shown) = self.shown.take() else {
+            return self.render();
+        };
+        let rows = self.draw_rows();
+        if shown == rows {
+            self.shown = Some(rows);
+            return String::new();
+        }
+        let n = rows.len();
+        let Some(k) = (n == shown.len() && SCROLL_REGIONS.load(Ordering::Relaxed))
+            .then(|| (1..n).find(|&k| shown[k..] == rows[..n - k]))
+            .flatten()
+        else {
+            return self.render_changed(&shown, rows);
+        };
+
+        let top = self.first_row();
+        let bottom = top + n - 1;
+        let mut output = String::from(esc::SAVE_CURSOR);
+        output.push_str(&esc::set_scroll_region(top, bottom));
+        output.push_str(&esc::cursor_to(bottom, 1));
+        for _ in 0..k {
+            output.push_str(esc::INDEX);
+        }
+        output.push_str(&esc::reset_scroll_region());
+        for (i, row) in rows.iter().enumerate().skip(n - k) {
+            output.push_str(&esc::cursor_to(top + i, 1));
+            output.push_str(&self.bordered(row));
+        }
+        output.push_str(esc::RESTORE_CURSOR);
+        self.shown = Some(rows);
+        output
+    }
+
+    /// Draw the cells of `rows` that differ from `shown`, or the whole area
+    /// if they can't be compared
+    fn render_changed(&mut self, shown: &[String], rows: Vec<String>) -> String {
+        let (Some(old), Some(new)) = (self.frame(shown), self.frame(&rows)) else {
+            return self.render();
+        };
+        let mut output = String::from(esc::SAVE_CURSOR);
+        output.push_str(&render_frame_diff(
+            &new,
+            Some(&old),
+            self.first_row(),
+            2,
+            esc::cursor_to,
+        ));
+        output.push_str(esc::RESTORE_CURSOR);
+        self.shown = Some(rows);
+        output
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_attrs_carry_over_wraps() {
+        assert_eq!(open_attrs("plain"), "");
+        assert_eq!(open_attrs("\x1b[0m\x1b[1mbold"), "\x1b[1m");
+        assert_eq!(open_attrs("\x1b[1mbold\x1b[0m done"), "");
+
+        let mut buffer = ChatBuffer::new(20);
+        buffer.push("\x1b[1mone two three four five\x1b[0m six".to_string());
+        let lines = buffer.recent_lines(2);
+        assert_eq!(lines[0], "\x1b[1mone two three");
+        assert_eq!(lines[1], "\x1b[1mfour five\x1b[0m six");
+
+        let mut buffer = ChatBuffer::new(12);
+        buffer.type_attrs(esc::REVERSE);
+        for ch in "abcde fgh".chars() {
+            buffer.type_char(ch, "  ");
+        }
+        assert_eq!(buffer.recent_lines(2), ["\x1b[7mabcde", "  \x1b[7mfgh"]);
+    }
+
+    #[test]
+    fn test_splits_long_words_by_character() {
+        let mut buffer = ChatBuffer::new(20);
+        buffer.push("[01:00PM] bob: ".to_string() + &"—".repeat(40));
+        let lines = buffer.recent_lines(4);
+        assert_eq!(lines[0], "[01:00PM] bob:");
+        assert_eq!(lines[1], "—".repeat(16));
+        assert_eq!(lines[3], "—".repeat(8));
+
+        let mut buffer = ChatBuffer::new(8);
+        buffer.push("\x1b[1mabcdefgh\x1b[0m".to_string());
+        let lines = buffer.recent_lines(2);
+        assert_eq!(lines[0], "\x1b[1mabcd");
+        assert_eq!(lines[1], "\x1b[1mefgh\x1b[0m");
+    }
+
     #[test]
     fn test_visible_len() {
         assert_eq!(visible_len("hello"), 5);
         assert_eq!(visible_len("hello world"), 11);
         // Shift in/out characters should not count
         assert_eq!(visible_len("a\x0Eb\x0Fc"), 3);
+        // SGR colour sequences should not count
+        assert_eq!(visible_len("\x1b[48;5;240m  \x1b[0m"), 2);
     }
 
     #[test]
@@ -418,6 +998,16 @@ mod tests {
         assert!(visible.last().unwrap().contains("29"));
     }
 
+    #[test]
+    fn test_recent_lines() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..5 {
+            buf.push(format!("Line {}", i));
+        }
+        assert_eq!(buf.recent_lines(2), vec!["Line 3", "Line 4"]);
+        assert_eq!(buf.recent_lines(10).len(), 5);
+    }
+
     #[test]
     fn test_clear() {
         let mut buf = ChatBuffer::new(80);
@@ -426,4 +1016,225 @@ mod tests {
         buf.clear();
         assert!(buf.visible_lines().is_empty());
     }
+
+    #[test]
+    fn test_push_ordered() {
+        let mut buf = ChatBuffer::new(80);
+        buf.push_ordered("one".to_string(), 1);
+        buf.push_ordered("three".to_string(), 3);
+        // Arrived late, but was sent before "three"
+        buf.push_ordered("two".to_string(), 2);
+        // Same time as "two": keeps arrival order
+        buf.push_ordered("two again".to_string(), 2);
+        // Unstamped lines follow whatever is last
+        buf.push("notice".to_string());
+        assert_eq!(
+            buf.recent_lines(5),
+            vec!["one", "two", "two again", "three", "notice"]
+        );
+    }
+
+    #[test]
+    fn test_mark_delivered() {
+        let mut buf = ChatBuffer::new(80);
+        buf.push_pending("[12:00PM] me: hello".to_string(), 4);
+        buf.push_ordered("[12:00PM] bob: hi *".to_string(), 5);
+        assert_eq!(buf.recent_lines(1), vec!["[12:00PM] bob: hi *"]);
+        assert!(buf.mark_delivered(4));
+        assert!(!buf.mark_delivered(4));
+        assert_eq!(
+            buf.recent_lines(2),
+            vec!["[12:00PM] me: hello", "[12:00PM] bob: hi *"]
+        );
+
+        // A marker wrapped onto its own line goes with it
+        let mut buf = ChatBuffer::new(20);
+        buf.push_pending("x".repeat(16), 1);
+        assert_eq!(buf.recent_lines(2), vec!["x".repeat(16), "*".to_string()]);
+        assert!(buf.mark_delivered(1));
+        assert_eq!(buf.recent_lines(2), vec!["x".repeat(16)]);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut buf = ChatBuffer::new(40);
+        buf.push_pending("[12:00PM] me: helo".to_string(), 4);
+        buf.push("*** bob joined ***".to_string());
+        assert!(buf.replace(4, "[12:00PM] me: helo", "[12:00PM] me: hello [edited]"));
+        assert_eq!(
+            buf.recent_lines(2),
+            vec!["[12:00PM] me: hello [edited]", "*** bob joined ***"]
+        );
+
+        // Grows onto a second line, pushing the rest down
+        let old = "[12:00PM] me: hello [edited]";
+        let new = "[12:00PM] me: hello there, everyone [edited]";
+        assert!(buf.replace(4, old, new));
+        assert_eq!(buf.recent_lines(3).len(), 3);
+        assert_eq!(buf.recent_lines(1), vec!["*** bob joined ***"]);
+        assert!(!buf.replace(4, old, "[12:00PM] me: [deleted]"));
+        assert!(!buf.replace(5, new, "[12:00PM] me: [deleted]"));
+        assert!(buf.replace(4, new, "[12:00PM] me: [deleted]"));
+        assert_eq!(
+            buf.recent_lines(2),
+            vec!["[12:00PM] me: [deleted]", "*** bob joined ***"]
+        );
+    }
+
+    #[test]
+    fn test_new_messages_divider() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..5 {
+            buf.push(format!("Line {}", i));
+        }
+        // Nothing new while away: no divider
+        buf.leave();
+        buf.enter();
+        assert_eq!(buf.recent_lines(1), vec!["Line 4"]);
+
+        buf.leave();
+        for i in 5..60 {
+            buf.push(format!("Line {}", i));
+        }
+        buf.enter();
+        // The divider is scrolled back to the top of the window
+        let visible = buf.visible_lines();
+        assert!(visible[0].ends_with(NEW_MESSAGES));
+        assert_eq!(visible[1], "Line 5");
+
+        // Leaving again takes the old divider away
+        buf.leave();
+        buf.push("Line 60".to_string());
+        let dividers = buf.lines.iter().filter(|l| l.ends_with(NEW_MESSAGES));
+        assert_eq!(dividers.count(), 1);
+        assert_eq!(buf.recent_lines(2)[1], "Line 60");
+    }
+
+    #[test]
+    fn test_scrolled_view_stays_put() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..30 {
+            buf.push(format!("Line {}", i));
+        }
+        buf.scroll_up(5);
+        let before: Vec<String> = buf.visible_lines().iter().map(|l| l.to_string()).collect();
+        buf.push("Line 30".to_string());
+        buf.push_ordered("late".to_string(), 0);
+        assert_eq!(buf.visible_lines(), before);
+
+        // Nor does a hidden buffer jump to new arrivals
+        buf.leave();
+        buf.push("Line 31".to_string());
+        buf.scroll_to_bottom();
+        buf.enter();
+        assert_eq!(buf.visible_lines(), before);
+
+        // At the bottom, new lines scroll into view
+        buf.scroll_to_bottom();
+        buf.push("Line 32".to_string());
+        assert_eq!(buf.visible_lines().last(), Some(&"Line 32"));
+    }
+
+    #[test]
+    fn test_pin_takes_first_row() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..30 {
+            buf.push(format!("Line {}", i));
+        }
+        buf.set_pin(Some("Wi-Fi: hunter2".to_string()));
+        let visible = buf.visible_lines();
+        assert_eq!(visible.len(), chat_visible_lines() - 1);
+        assert_eq!(visible.last(), Some(&"Line 29"));
+        assert!(buf.render().contains("Wi-Fi: hunter2"));
+
+        buf.set_pin(None);
+        assert_eq!(buf.visible_lines().len(), chat_visible_lines());
+    }
+
+    #[test]
+    fn test_search_steps_through_matches() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..60 {
+            let text = if i % 20 == 5 { "Lunch?" } else { "chatter" };
+            buf.push(format!("Line {} {}", i, text));
+        }
+        assert_eq!(buf.search("nope"), None);
+        assert_eq!(buf.search("LUNCH"), Some((1, 3)));
+        assert!(buf.visible_lines().contains(&"Line 45 Lunch?"));
+        assert!(buf.render().contains(&format!(
+            "Line 45 {}Lunch{}?",
+            esc::REVERSE,
+            esc::RESET_ATTRS
+        )));
+
+        // n goes back in time, p forward, stopping at either end
+        assert_eq!(buf.search_step(true), Some((2, 3)));
+        assert_eq!(buf.search_step(true), Some((3, 3)));
+        assert_eq!(buf.search_step(true), Some((3, 3)));
+        assert!(buf.visible_lines().contains(&"Line 5 Lunch?"));
+        assert_eq!(buf.search_step(false), Some((2, 3)));
+        assert!(buf.visible_lines().contains(&"Line 25 Lunch?"));
+
+        buf.end_search();
+        assert_eq!(buf.search_step(true), None);
+        assert!(!buf.render().contains(esc::REVERSE));
+    }
+
+    #[test]
+    fn test_render_update_scrolls() {
+        let mut buf = ChatBuffer::new(80);
+        for i in 0..30 {
+            buf.push(format!("Line {}", i));
+        }
+        // Nothing recorded yet: drawn in full
+        let full = buf.render_update();
+        assert!(full.contains("Line 29"));
+        assert_eq!(buf.render_update(), "");
+
+        // One new line scrolls the region a row and draws only that row
+        buf.push("Line 30".to_string());
+        let update = buf.render_update();
+        let top = CHAT_REGION_START;
+        let bottom = top + chat_visible_lines() - 1;
+        assert!(update.contains(&esc::set_scroll_region(top, bottom)));
+        assert_eq!(update.matches(esc::INDEX).count(), 1);
+        assert!(update.contains("Line 30"));
+        assert!(!update.contains("Line 29"));
+        assert!(update.len() < full.len() / 5);
+
+        // Scrolling back up isn't a shift up: only the digits that differ
+        buf.scroll_up(5);
+        let update = buf.render_update();
+        assert!(!update.contains(esc::INDEX) && !update.contains("Line"));
+        assert!(update.len() < full.len());
+        // Nor is anything after the screen was given to another tab
+        buf.scroll_to_bottom();
+        buf.render();
+        buf.leave();
+        buf.enter();
+        buf.push("Line 31".to_string());
+        assert!(!buf.render_update().contains(esc::INDEX));
+    }
+
+    #[test]
+    fn test_render_update_draws_changed_cells() {
+        let mut buf = ChatBuffer::new(80);
+        buf.push("[12:00PM] bob: hi".to_string());
+        buf.push_pending("[12:00PM] me: hello".to_string(), 4);
+        buf.render_update();
+
+        // The delivery marker goes: one blank drawn over the star
+        buf.mark_delivered(4);
+        let update = buf.render_update();
+        let row = buf.first_row() + 1;
+        assert_eq!(
+            update,
+            format!(
+                "{}{} {}",
+                esc::SAVE_CURSOR,
+                esc::cursor_to(row, 23),
+                esc::RESTORE_CURSOR
+            )
+        );
+    }
 }
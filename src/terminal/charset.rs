@@ -0,0 +1,177 @@
+//! Non-ASCII text for terminals that can't show UTF-8.
+//!
+//! Peers and Gemini send smart quotes, dashes and accented names, which a
+//! VT220 shows as a spray of 8-bit junk. Everything written to the terminal
+//! passes through [`Charset::encode`] instead: on the VT220 and later, Latin
+//! letters and symbols come from the DEC Supplemental set (designated into
+//! G2 at startup and picked one character at a time with SS2, so it works
+//! over a 7-bit line too), and anything else becomes its nearest ASCII
+//! character. Each character becomes exactly one on screen, so the borders
+//! stay where the layout put them.
+
+use std::borrow::Cow;
+
+/// Designate DEC Supplemental Graphic into G2
+pub const DESIGNATE_SUPPLEMENTAL_G2: &str = "\x1b*<";
+
+/// Single shift 2: the next character comes from G2
+const SS2: &str = "\x1bN";
+
+/// What the terminal can show beyond ASCII
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Anything (modern emulators)
+    Utf8,
+    /// DEC Multinational: ASCII plus DEC Supplemental (VT220 and later)
+    Multinational,
+    /// ASCII only (VT100, ANSI-BBS terminals)
+    Ascii,
+}
+
+impl Charset {
+    /// The character set a terminal mode has
+    pub fn for_mode(mode: &str) -> Self {
+        match mode {
+            "xterm" => Charset::Utf8,
+            "vt220" | "vt340" | "vt525" => Charset::Multinational,
+            _ => Charset::Ascii,
+        }
+    }
+
+    /// What to send once at startup so [`encode`](Self::encode) works
+    pub fn init_sequence(self) -> &'static str {
+        match self {
+            Charset::Multinational => DESIGNATE_SUPPLEMENTAL_G2,
+            _ => "",
+        }
+    }
+
+    /// Text as the terminal can show it
+    pub fn encode(self, text: &str) -> Cow<'_, str> {
+        if self == Charset::Utf8 || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut out = String::with_capacity(text.len() + 8);
+        for c in text.chars() {
+            if c.is_ascii() {
+                out.push(c);
+            } else if self == Charset::Multinational
+                && let Some(code) = supplemental(c)
+            {
+                out.push_str(SS2);
+                out.push((code - 0x80) as char);
+            } else {
+                out.push(to_ascii(c));
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+/// Latin-1 codes that DEC Supplemental leaves empty or gives to Œ, œ, Ÿ and ÿ
+const NOT_LATIN1: [u8; 18] = [
+    0xA4, 0xA6, 0xA8, 0xAC, 0xAD, 0xAE, 0xAF, 0xB4, 0xB8, 0xBE, 0xD0, 0xD7, 0xDD, 0xDE, 0xF0, 0xF7,
+    0xFD, 0xFE,
+];
+
+/// The DEC Supplemental code for `c`, if it has one. It's Latin-1 but for a
+/// few codes that DEC left empty or gave to Œ, œ, Ÿ and ÿ.
+fn supplemental(c: char) -> Option<u8> {
+    match c {
+        'Œ' => Some(0xD7),
+        'œ' => Some(0xF7),
+        'Ÿ' => Some(0xDD),
+        'ÿ' => Some(0xFD),
+        '\u{A1}'..='\u{FF}' => {
+            let code = c as u32 as u8;
+            (!NOT_LATIN1.contains(&code)).then_some(code)
+        }
+        _ => None,
+    }
+}
+
+/// The character for a DEC Supplemental code, as an 8-bit terminal's
+/// keyboard sends it (None for the codes DEC left empty)
+pub fn from_supplemental(code: u8) -> Option<char> {
+    match code {
+        0xD7 => Some('Œ'),
+        0xF7 => Some('œ'),
+        0xDD => Some('Ÿ'),
+        0xFD => Some('ÿ'),
+        0xA1..=0xFF if !NOT_LATIN1.contains(&code) => Some(code as char),
+        _ => None,
+    }
+}
+
+/// The nearest ASCII character: `“` is `"`, `—` is `-`, `é` is `e`
+fn to_ascii(c: char) -> char {
+    match c {
+        '‘' | '’' | '‚' | '′' | '´' | '`' => '\'',
+        '“' | '”' | '„' | '″' | '«' | '»' => '"',
+        '‐' | '‑' | '‒' | '–' | '—' | '―' | '−' | '\u{AD}' => '-',
+        '…' | '·' | '•' | '∙' => '.',
+        '\u{A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' => ' ',
+        '×' => 'x',
+        '÷' => '/',
+        '©' => 'c',
+        '®' => 'r',
+        '°' => 'o',
+        '£' | '€' | '¥' | '¢' => '$',
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => 'A',
+        'à'..='å' | 'ā' | 'ă' | 'ą' | 'ª' => 'a',
+        'Æ' => 'A',
+        'æ' => 'a',
+        'Ç' | 'Ć' | 'Č' => 'C',
+        'ç' | 'ć' | 'č' => 'c',
+        'Ð' | 'Ď' => 'D',
+        'ð' | 'ď' => 'd',
+        'È'..='Ë' | 'Ē' | 'Ę' | 'Ě' => 'E',
+        'è'..='ë' | 'ē' | 'ę' | 'ě' => 'e',
+        'Ì'..='Ï' | 'Ī' | 'İ' => 'I',
+        'ì'..='ï' | 'ī' | 'ı' => 'i',
+        'Ł' => 'L',
+        'ł' => 'l',
+        'Ñ' | 'Ń' | 'Ň' => 'N',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'Ò'..='Ö' | 'Ø' | 'Ő' | 'Œ' => 'O',
+        'ò'..='ö' | 'ø' | 'ő' | 'œ' | 'º' => 'o',
+        'Ř' => 'R',
+        'ř' => 'r',
+        'Ś' | 'Š' | 'Ş' => 'S',
+        'ś' | 'š' | 'ş' | 'ß' => 's',
+        'Ť' | 'Þ' => 'T',
+        'ť' | 'þ' => 't',
+        'Ù'..='Ü' | 'Ů' | 'Ű' => 'U',
+        'ù'..='ü' | 'ů' | 'ű' | 'µ' => 'u',
+        'Ý' | 'Ÿ' => 'Y',
+        'ý' | 'ÿ' => 'y',
+        'Ź' | 'Ż' | 'Ž' => 'Z',
+        'ź' | 'ż' | 'ž' => 'z',
+        _ => '?',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let text = "“Café” — naïve…";
+        assert_eq!(Charset::Utf8.encode(text), text);
+        assert_eq!(Charset::Ascii.encode(text), "\"Cafe\" - naive.");
+        assert_eq!(
+            Charset::Multinational.encode(text),
+            "\"Caf\x1bNi\" - na\x1bNove."
+        );
+        // One character on screen for each one sent, whatever it was
+        assert_eq!(Charset::Ascii.encode("日本").chars().count(), 2);
+        assert_eq!(Charset::Ascii.encode("plain"), "plain");
+        assert!(matches!(Charset::Ascii.encode("plain"), Cow::Borrowed(_)));
+        assert_eq!(Charset::Multinational.encode("œ"), "\x1bNw");
+        for c in ['é', 'Œ', 'ÿ', 'ß'] {
+            assert_eq!(from_supplemental(supplemental(c).unwrap()), Some(c));
+        }
+        assert_eq!(from_supplemental(0xA4), None);
+    }
+}
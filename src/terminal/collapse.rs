@@ -0,0 +1,96 @@
+//! Collapsed rendering for large multi-line chat shares.
+//!
+//! An `[IMAGE]` share or a block of figlet text can be taller than the whole
+//! chat window. Peers' shares over [`COLLAPSE_OVER`] lines are shown as their
+//! first [`PREVIEW_LINES`] lines and a `[+22 more lines, /expand 4]` hint, and
+//! kept here so `/expand` can show them in full.
+
+use std::collections::VecDeque;
+
+/// Lines of a collapsed share shown in the chat
+pub const PREVIEW_LINES: usize = 3;
+
+/// Shares with more lines than this are collapsed
+pub const COLLAPSE_OVER: usize = 6;
+
+/// Collapsed shares kept for `/expand` (the oldest are forgotten first)
+const MAX_SHARES: usize = 50;
+
+/// A collapsed share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub number: usize,
+    pub from: String,
+    pub lines: Vec<String>,
+}
+
+/// Recent collapsed shares, numbered for `/expand`
+#[derive(Debug, Default)]
+pub struct CollapsedShares {
+    shares: VecDeque<Share>,
+    next: usize,
+}
+
+impl CollapsedShares {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The lines to show for a share from `from`: all of them if it's short,
+    /// otherwise a preview and a hint, keeping the rest for [`Self::get`]
+    pub fn add(&mut self, from: &str, lines: Vec<String>) -> Vec<String> {
+        if lines.len() <= COLLAPSE_OVER {
+            return lines;
+        }
+        self.next += 1;
+        let mut shown = lines[..PREVIEW_LINES].to_vec();
+        shown.push(format!(
+            "[+{} more lines, /expand {}]",
+            lines.len() - PREVIEW_LINES,
+            self.next
+        ));
+        if self.shares.len() >= MAX_SHARES {
+            self.shares.pop_front();
+        }
+        self.shares.push_back(Share {
+            number: self.next,
+            from: from.to_string(),
+            lines,
+        });
+        shown
+    }
+
+    /// A collapsed share by number (the latest if None)
+    pub fn get(&self, number: Option<usize>) -> Option<&Share> {
+        match number {
+            Some(n) => self.shares.iter().find(|share| share.number == n),
+            None => self.shares.back(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn art(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("line {}", i)).collect()
+    }
+
+    #[test]
+    fn test_collapse_and_expand() {
+        let mut shares = CollapsedShares::new();
+        assert_eq!(shares.add("bob", art(COLLAPSE_OVER)), art(COLLAPSE_OVER));
+        assert_eq!(shares.get(None), None);
+
+        let shown = shares.add("bob", art(25));
+        assert_eq!(shown.len(), PREVIEW_LINES + 1);
+        assert_eq!(shown[0], "line 0");
+        assert_eq!(shown[PREVIEW_LINES], "[+22 more lines, /expand 1]");
+        shares.add("carol", art(10));
+
+        assert_eq!(shares.get(Some(1)).unwrap().lines, art(25));
+        assert_eq!(shares.get(None).unwrap().from, "carol");
+        assert_eq!(shares.get(Some(3)), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! SGR colour for ANSI and VT525 terminals.
+//!
+//! With `mode = ansi` or `mode = vt525` chat lines are coloured as they're
+//! drawn: timestamps, system messages, and each nickname in a colour of its
+//! own (picked from the name, so a peer looks the same on every screen and
+//! after a restart). The active tab is highlighted in colour instead of
+//! reverse video. Lines are stored uncoloured, so the monochrome modes and
+//! logs are unaffected. Only the eight standard colours (SGR 30-37) are used,
+//! which every colour terminal since the VT525 understands.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::esc;
+
+/// Whether this terminal takes colour (set once at startup from the mode)
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Timestamps
+const TIMESTAMP: &str = "\x1b[36m";
+
+/// `*** ... ***` system messages
+const SYSTEM: &str = "\x1b[33m";
+
+/// The active tab: bright white on blue
+const TAB_HIGHLIGHT: &str = "\x1b[1;37;44m";
+
+/// Nickname colours, plain then bold (yellow and cyan are kept for system
+/// messages and timestamps)
+const NICK_COLORS: &[&str] = &[
+    "\x1b[31m",
+    "\x1b[32m",
+    "\x1b[34m",
+    "\x1b[35m",
+    "\x1b[1;31m",
+    "\x1b[1;32m",
+    "\x1b[1;34m",
+    "\x1b[1;35m",
+    "\x1b[1;36m",
+    "\x1b[1;33m",
+];
+
+/// Longest text before ": " taken for a nickname (names are cut to 16)
+const MAX_NICK_LEN: usize = 16;
+
+/// Whether a terminal mode takes colour
+pub fn mode_has_color(mode: &str) -> bool {
+    matches!(mode, "ansi" | "vt525")
+}
+
+/// Turn colour on or off for everything drawn from now on
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// How the active tab is highlighted
+pub fn tab_highlight() -> &'static str {
+    if enabled() {
+        TAB_HIGHLIGHT
+    } else {
+        esc::REVERSE
+    }
+}
+
+/// A nickname's colour, the same wherever and whenever it's drawn
+pub fn nick_color(name: &str) -> &'static str {
+    // FNV-1a: stable across runs and machines, unlike the std hasher
+    let hash = name.bytes().fold(0x811c_9dc5u32, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    });
+    NICK_COLORS[hash as usize % NICK_COLORS.len()]
+}
+
+/// A chat line as drawn: coloured if colour is on
+pub fn paint(line: &str) -> Cow<'_, str> {
+    if enabled() {
+        Cow::Owned(colorize(line))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Colour the timestamp, a system message or the sender's name in a chat
+/// line. Lines that don't start with a timestamp (wrapped continuations,
+/// images) are left alone.
+pub fn colorize(line: &str) -> String {
+    let Some(end) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.find("] "))
+        .filter(|&end| end <= 10 && line[1..end + 1].contains(':'))
+    else {
+        return line.to_string();
+    };
+    // "[09:41AM]" (or "[21:41:05]"), then the rest after its space
+    let (stamp, rest) = (&line[..end + 2], &line[end + 3..]);
+    let mut out = format!("{}{}{}", TIMESTAMP, stamp, esc::RESET_ATTRS);
+    out.push(' ');
+
+    if rest.starts_with("***") {
+        out.push_str(SYSTEM);
+        out.push_str(rest);
+        out.push_str(esc::RESET_ATTRS);
+    } else if let Some(action) = rest.strip_prefix("* ") {
+        // "/me" actions: "* name waves"
+        let (name, tail) = action.split_once(' ').unwrap_or((action, ""));
+        out.push_str("* ");
+        out.push_str(nick_color(name));
+        out.push_str(name);
+        out.push_str(esc::RESET_ATTRS);
+        if !tail.is_empty() {
+            out.push(' ');
+            out.push_str(tail);
+        }
+    } else if let Some((name, text)) = rest.split_once(": ")
+        && !name.is_empty()
+        && name.chars().count() <= MAX_NICK_LEN
+    {
+        out.push_str(nick_color(name));
+        out.push_str(name);
+        out.push_str(esc::RESET_ATTRS);
+        out.push_str(": ");
+        out.push_str(text);
+    } else {
+        out.push_str(rest);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::buffer::visible_len;
+
+    #[test]
+    fn test_colorize() {
+        let line = "[09:41AM] alice: hello there";
+        let colored = colorize(line);
+        assert!(colored.starts_with(TIMESTAMP));
+        assert!(colored.contains(&format!("{}alice\x1b[0m: hello there", nick_color("alice"))));
+        // Colour takes no columns
+        assert_eq!(visible_len(&colored), line.len());
+
+        let system = colorize("[09:41AM] *** bob has joined ***");
+        assert!(system.contains(&format!("{}*** bob has joined ***", SYSTEM)));
+        let action = colorize("[09:42AM] * bob waves");
+        assert!(action.contains(&format!(
+            "* {}bob{} waves",
+            nick_color("bob"),
+            esc::RESET_ATTRS
+        )));
+
+        // Continuations and images are left as they are
+        assert_eq!(colorize("  of a long message"), "  of a long message");
+        assert_eq!(colorize("[IMAGE] @@@@"), "[IMAGE] @@@@");
+
+        // Each name keeps its colour
+        assert_eq!(nick_color("alice"), nick_color("alice"));
+        assert!(mode_has_color("vt525") && !mode_has_color("vt220"));
+    }
+}
@@ -0,0 +1,113 @@
+//! 7-bit or 8-bit C1 controls (`controls` under `[terminal]`).
+//!
+//! Every sequence in the app is written in its 7-bit form, `ESC [` for CSI,
+//! `ESC P` for DCS and so on. A VT220 set up for 8-bit controls takes those
+//! too, but some mangle them once a national replacement character set is
+//! active, so for those terminals the pairs are folded into the single C1
+//! bytes (0x80-0x9F) on the way out. With `auto` the terminal's answer to
+//! Device Attributes decides: one in 8-bit mode answers with a C1 CSI.
+//!
+//! Whichever way output goes, the terminal is asked to send its own controls
+//! (keys, reports) in 7-bit form, which is all the input parser knows.
+
+use std::borrow::Cow;
+
+/// Control modes accepted in the config
+pub const CONTROL_MODES: &[&str] = &["auto", "7bit", "8bit"];
+
+/// S7C1T: the terminal sends 7-bit controls
+pub const SEND_7BIT_CONTROLS: &str = "\x1b F";
+
+const ESC: u8 = 0x1b;
+
+/// The 8-bit CSI a terminal in 8-bit mode answers with
+const C1_CSI: u8 = 0x9b;
+
+/// How control sequences are sent to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Controls {
+    /// `ESC` and a character (`ESC [`), which every terminal takes
+    #[default]
+    SevenBit,
+    /// Single C1 bytes (0x9B for CSI)
+    EightBit,
+}
+
+impl Controls {
+    /// What a terminal's reply to Device Attributes says it's set up for
+    pub fn from_reply(reply: &[u8]) -> Self {
+        if reply.contains(&C1_CSI) {
+            Controls::EightBit
+        } else {
+            Controls::SevenBit
+        }
+    }
+
+    /// Output as the terminal wants it: in 8-bit mode, `ESC` followed by a
+    /// character from `@` to `_` becomes the one C1 byte it stands for
+    pub fn encode(self, bytes: &[u8]) -> Cow<'_, [u8]> {
+        if self == Controls::SevenBit || !bytes.contains(&ESC) {
+            return Cow::Borrowed(bytes);
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match (bytes[i], bytes.get(i + 1)) {
+                (ESC, Some(&next @ 0x40..=0x5f)) => {
+                    out.push(next + 0x40);
+                    i += 2;
+                }
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+/// Input with any C1 CSI, SS3 or DCS spelled out in 7-bit form, for the
+/// parsers that only know that (a terminal's reply sent before it heard
+/// [`SEND_7BIT_CONTROLS`])
+pub fn seven_bit(input: &[u8]) -> Cow<'_, [u8]> {
+    if !input.iter().any(|b| (0x80..=0x9f).contains(b)) {
+        return Cow::Borrowed(input);
+    }
+    let mut out = Vec::with_capacity(input.len() + 4);
+    for &byte in input {
+        if (0x80..=0x9f).contains(&byte) {
+            out.push(ESC);
+            out.push(byte - 0x40);
+        } else {
+            out.push(byte);
+        }
+    }
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controls() {
+        let output = b"\x1b[2J\x1b7\x1bPq#0!5~\x1b\\\x1b*<plain";
+        assert_eq!(Controls::SevenBit.encode(output), &output[..]);
+        // CSI, DCS and ST fold up; DECSC and the G2 designation don't
+        assert_eq!(
+            Controls::EightBit.encode(output),
+            &b"\x9b2J\x1b7\x90q#0!5~\x9c\x1b*<plain"[..]
+        );
+        assert!(matches!(
+            Controls::EightBit.encode(b"plain"),
+            Cow::Borrowed(_)
+        ));
+
+        // A DA1 reply from a terminal in 8-bit mode
+        let reply = b"\x9b?62;1;6c";
+        assert_eq!(Controls::from_reply(reply), Controls::EightBit);
+        assert_eq!(seven_bit(reply), &b"\x1b[?62;1;6c"[..]);
+        assert_eq!(Controls::from_reply(b"\x1b[?62;1;6c"), Controls::SevenBit);
+    }
+}
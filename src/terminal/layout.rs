@@ -0,0 +1,259 @@
+//! The screen layout: how many rows the terminal has and how they're shared.
+//!
+//! Row 1 is the tab bar and, if the layout has one, the last row is the
+//! status bar. The rows between are the box the tabs draw in: Chat and AI
+//! split it into the chat area, a separator and the input area, which is 2
+//! rows by default and can grow to 5 (`input_rows` under `[terminal]`, or
+//! Ctrl+Up/Down), so long AI prompts fit; the chat area gives up the rows it
+//! takes.
+//!
+//! A [`Layout`] describes the lot, so a 48-line VT420 or a 25-line PC
+//! emulator gets the whole screen. A few are built in, more can be named
+//! under `[layouts]`, and `/layout` swaps between them while running. Like
+//! the colour setting, the layout in use is global, since every part of the
+//! screen needs it.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{CHAT_REGION_START, TERMINAL_HEIGHT};
+
+/// Fewest and most rows the input area can have
+pub const MIN_INPUT_ROWS: usize = 2;
+pub const MAX_INPUT_ROWS: usize = 5;
+
+/// Fewest and most rows on the screen (a VT420 goes up to 72)
+pub const MIN_SCREEN_ROWS: usize = 12;
+pub const MAX_SCREEN_ROWS: usize = 72;
+
+/// The layouts that need no configuring, and the one used when none is named
+pub const BUILTIN_LAYOUTS: &[(&str, Layout)] = &[
+    ("standard", Layout::new(TERMINAL_HEIGHT, None, true)),
+    ("pc", Layout::new(25, None, true)),
+    ("tall", Layout::new(48, Some(4), true)),
+    ("bare", Layout::new(TERMINAL_HEIGHT, None, false)),
+];
+pub const DEFAULT_LAYOUT: &str = "standard";
+
+static SCREEN_ROWS: AtomicUsize = AtomicUsize::new(TERMINAL_HEIGHT);
+static STATUS_BAR: AtomicBool = AtomicBool::new(true);
+static INPUT_ROWS: AtomicUsize = AtomicUsize::new(MIN_INPUT_ROWS);
+
+/// The rows of a screen and how they're used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Rows on the screen
+    pub rows: usize,
+    /// Rows in the input area (None keeps the ones in use)
+    pub input_rows: Option<usize>,
+    /// Whether the bottom row is the status bar (without it, the box runs to
+    /// the bottom of the screen)
+    pub status_bar: bool,
+}
+
+impl Layout {
+    pub const fn new(rows: usize, input_rows: Option<usize>, status_bar: bool) -> Self {
+        Self {
+            rows,
+            input_rows,
+            status_bar,
+        }
+    }
+
+    /// A built-in layout by name
+    pub fn builtin(name: &str) -> Option<Layout> {
+        BUILTIN_LAYOUTS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, layout)| *layout)
+    }
+
+    /// Last row inside the box
+    fn region_end(&self) -> usize {
+        self.rows - usize::from(self.status_bar)
+    }
+
+    /// First row of an input area of `input_rows` rows
+    fn input_row_start(&self, input_rows: usize) -> usize {
+        self.region_end() + 1 - input_rows
+    }
+}
+
+/// The error from a layout that doesn't parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutError(String);
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl FromStr for Layout {
+    type Err = LayoutError;
+
+    /// `rows=48 input_rows=4 status_bar=true`: anything left out is as in the
+    /// standard layout
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut layout = Layout::new(TERMINAL_HEIGHT, None, true);
+        for setting in s.split(|c: char| c.is_whitespace() || c == ',') {
+            if setting.is_empty() {
+                continue;
+            }
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| LayoutError(format!("expected key=value, got '{}'", setting)))?;
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| LayoutError(format!("{} should be a number", key)))
+            };
+            match key {
+                "rows" => layout.rows = number()?,
+                "input_rows" => layout.input_rows = Some(number()?),
+                "status_bar" => {
+                    layout.status_bar = match value {
+                        "true" | "yes" | "on" | "1" => true,
+                        "false" | "no" | "off" | "0" => false,
+                        _ => return Err(LayoutError("status_bar should be true or false".into())),
+                    }
+                }
+                _ => return Err(LayoutError(format!("unknown setting '{}'", key))),
+            }
+        }
+        if !(MIN_SCREEN_ROWS..=MAX_SCREEN_ROWS).contains(&layout.rows) {
+            return Err(LayoutError(format!(
+                "rows should be {} to {}",
+                MIN_SCREEN_ROWS, MAX_SCREEN_ROWS
+            )));
+        }
+        if let Some(rows) = layout.input_rows
+            && !(MIN_INPUT_ROWS..=MAX_INPUT_ROWS).contains(&rows)
+        {
+            return Err(LayoutError(format!(
+                "input_rows should be {} to {}",
+                MIN_INPUT_ROWS, MAX_INPUT_ROWS
+            )));
+        }
+        Ok(layout)
+    }
+}
+
+/// Switch to `layout`. The screen needs redrawing afterwards.
+pub fn apply(layout: Layout) {
+    SCREEN_ROWS.store(layout.rows, Ordering::Relaxed);
+    STATUS_BAR.store(layout.status_bar, Ordering::Relaxed);
+    if let Some(rows) = layout.input_rows {
+        set_input_rows(rows);
+    }
+}
+
+/// The layout in use
+fn current() -> Layout {
+    Layout::new(
+        SCREEN_ROWS.load(Ordering::Relaxed),
+        Some(input_rows()),
+        STATUS_BAR.load(Ordering::Relaxed),
+    )
+}
+
+/// Rows on the screen
+pub fn screen_rows() -> usize {
+    SCREEN_ROWS.load(Ordering::Relaxed)
+}
+
+/// The status bar's row, if the layout has one
+pub fn status_row() -> Option<usize> {
+    STATUS_BAR
+        .load(Ordering::Relaxed)
+        .then(|| SCREEN_ROWS.load(Ordering::Relaxed))
+}
+
+/// Last row inside the box, for the tabs without an input area
+pub fn region_end() -> usize {
+    current().region_end()
+}
+
+/// Rows inside the box
+pub fn region_rows() -> usize {
+    region_end() - CHAT_REGION_START + 1
+}
+
+/// Rows in the input area
+pub fn input_rows() -> usize {
+    INPUT_ROWS.load(Ordering::Relaxed)
+}
+
+/// Resize the input area, within its limits. Returns the rows it now has.
+pub fn set_input_rows(rows: usize) -> usize {
+    let rows = rows.clamp(MIN_INPUT_ROWS, MAX_INPUT_ROWS);
+    INPUT_ROWS.store(rows, Ordering::Relaxed);
+    rows
+}
+
+/// First row of the input area
+pub fn input_row_start() -> usize {
+    current().input_row_start(input_rows())
+}
+
+/// Last row of the input area
+pub fn input_row_end() -> usize {
+    region_end()
+}
+
+/// The separator between chat and input
+pub fn separator_row() -> usize {
+    input_row_start() - 1
+}
+
+/// Last row of the chat area
+pub fn chat_region_end() -> usize {
+    separator_row() - 1
+}
+
+/// Rows in the chat area
+pub fn chat_visible_lines() -> usize {
+    chat_region_end() - CHAT_REGION_START + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_rows_leave_room_for_chat() {
+        let standard = Layout::builtin(DEFAULT_LAYOUT).unwrap();
+        // The default split: chat on rows 2-20, separator 21, input 22-23
+        assert_eq!(standard.input_row_start(MIN_INPUT_ROWS), 22);
+        // Fully grown, the chat keeps 16 rows
+        assert_eq!(
+            standard.input_row_start(MAX_INPUT_ROWS) - 2 - CHAT_REGION_START + 1,
+            16
+        );
+        // The smallest screen still has a few rows of chat
+        let small = Layout::new(MIN_SCREEN_ROWS, None, true);
+        assert!(small.input_row_start(MAX_INPUT_ROWS) - 2 >= CHAT_REGION_START + 3);
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        let tall: Layout = "rows=48 input_rows=4".parse().unwrap();
+        assert_eq!(tall, Layout::builtin("tall").unwrap());
+        // Without a status bar the input runs to the bottom row
+        let bare: Layout = "rows=25, status_bar=off".parse().unwrap();
+        assert_eq!(bare.region_end(), 25);
+        assert_eq!(bare.input_row_start(2), 24);
+        assert_eq!(
+            "".parse::<Layout>(),
+            Ok(Layout::builtin("standard").unwrap())
+        );
+
+        assert!("rows=8".parse::<Layout>().is_err());
+        assert!("input_rows=9".parse::<Layout>().is_err());
+        assert!("columns=132".parse::<Layout>().is_err());
+        assert!("rows".parse::<Layout>().is_err());
+    }
+}
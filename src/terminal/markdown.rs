@@ -0,0 +1,317 @@
+//! Markdown in the AI's replies, drawn the way a terminal can.
+//!
+//! `**strong**` and headings come out bold, `*emphasis*` and `` `code` `` in
+//! reverse video, lines of a fenced code block indented (the fences
+//! themselves dropped), and `*` or `+` list items as `-` bullets. Replies
+//! arrive a few characters at a time, so [`Markdown::feed`] holds back only
+//! what it can't decide yet, like a `*` that may be the start of `**`.
+
+use super::esc;
+
+/// Put before each line of a code block
+const CODE_INDENT: &str = "    ";
+
+/// What a line turned out to be, from how it starts
+enum LineKind {
+    /// ```` ``` ````, opening or closing a code block
+    Fence,
+    /// A list item: the indentation and marker to drop before its text
+    Bullet {
+        indent: usize,
+        marker: usize,
+    },
+    /// `#` to `######` and a space
+    Heading {
+        marker: usize,
+    },
+    /// `---`, `***` or `___` alone
+    Rule,
+    Plain,
+}
+
+/// How the line starting `text` reads. None until enough of it has arrived
+/// to tell (`complete` once the line has ended, or the reply has).
+fn line_kind(text: &[char], complete: bool) -> Option<LineKind> {
+    let indent = text.iter().take_while(|&&c| c == ' ').count();
+    let rest = &text[indent..];
+
+    if rest.starts_with(&['`', '`', '`']) {
+        return Some(LineKind::Fence);
+    }
+    if let Some(&first) = rest.first()
+        && matches!(first, '-' | '*' | '_')
+        && rest.iter().all(|&c| c == first)
+    {
+        // A rule, or the start of a bullet or bold
+        return match complete {
+            true if rest.len() >= 3 => Some(LineKind::Rule),
+            true => Some(LineKind::Plain),
+            false => None,
+        };
+    }
+    match rest {
+        [] | ['`'] | ['`', '`'] if !complete => return None,
+        ['-' | '*' | '+', ' ', ..] => return Some(LineKind::Bullet { indent, marker: 2 }),
+        ['+'] if !complete => return None,
+        _ => {}
+    }
+    let hashes = rest.iter().take_while(|&&c| c == '#').count();
+    if (1..=6).contains(&hashes) {
+        match rest.get(hashes) {
+            Some(' ') => return Some(LineKind::Heading { marker: hashes + 1 }),
+            None if !complete => return None,
+            _ => {}
+        }
+    }
+    Some(LineKind::Plain)
+}
+
+/// Renders a reply's markdown as it streams in
+#[derive(Debug)]
+pub struct Markdown {
+    /// Arrived but not yet rendered
+    pending: Vec<char>,
+    /// The last character rendered, to tell where a word starts or ends
+    prev: char,
+    line_start: bool,
+    code_block: bool,
+    /// The rest of a fence line (its language name) is dropped
+    skip_line: bool,
+    strong: bool,
+    emphasis: bool,
+    code: bool,
+    heading: bool,
+}
+
+impl Default for Markdown {
+    fn default() -> Self {
+        Self {
+            pending: Vec::new(),
+            prev: '\n',
+            line_start: true,
+            code_block: false,
+            skip_line: false,
+            strong: false,
+            emphasis: false,
+            code: false,
+            heading: false,
+        }
+    }
+}
+
+impl Markdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render a whole reply at once
+    pub fn render(text: &str) -> String {
+        let mut markdown = Self::new();
+        let mut output = markdown.feed(text);
+        output.push_str(&markdown.finish());
+        output
+    }
+
+    /// Render the next piece of a reply, as much of it as can be told yet
+    pub fn feed(&mut self, text: &str) -> String {
+        self.pending.extend(text.chars());
+        self.run(false)
+    }
+
+    /// Render whatever is left once the reply has ended
+    pub fn finish(&mut self) -> String {
+        let mut output = self.run(true);
+        output.push_str(&self.end_line());
+        *self = Self::default();
+        output
+    }
+
+    fn run(&mut self, done: bool) -> String {
+        let mut output = String::new();
+        while !self.pending.is_empty() {
+            let Some(consumed) = self.step(done, &mut output) else {
+                break;
+            };
+            if consumed > 0 {
+                self.prev = self.pending[consumed - 1];
+                self.pending.drain(..consumed);
+            }
+        }
+        output
+    }
+
+    /// Render from the start of `pending`, returning how many characters
+    /// were used, or None if more are needed to tell what they are
+    fn step(&mut self, done: bool, output: &mut String) -> Option<usize> {
+        let c = self.pending[0];
+        if self.skip_line {
+            if c == '\n' {
+                self.skip_line = false;
+                self.line_start = true;
+            }
+            return Some(1);
+        }
+        if self.line_start {
+            return self.start_line(done, output);
+        }
+        if c == '\n' {
+            output.push_str(&self.end_line());
+            output.push('\n');
+            self.line_start = true;
+            return Some(1);
+        }
+        if self.code_block {
+            output.push(c);
+            return Some(1);
+        }
+        if c == '`' {
+            self.code = !self.code;
+            output.push_str(&self.style());
+            return Some(1);
+        }
+        if self.code || !matches!(c, '*' | '_') {
+            output.push(c);
+            return Some(1);
+        }
+
+        // A run of * or _: emphasis, strong, or just the characters
+        let run = self.pending.iter().take_while(|&&p| p == c).count();
+        if run == self.pending.len() && !done {
+            return None;
+        }
+        let next = self.pending.get(run).copied().unwrap_or(' ');
+        let opens = !next.is_whitespace();
+        let closes = !self.prev.is_whitespace();
+        let inside_word = c == '_' && self.prev.is_alphanumeric() && next.is_alphanumeric();
+        let (width, on) = if run >= 2 {
+            (2, &mut self.strong)
+        } else {
+            (1, &mut self.emphasis)
+        };
+        if !inside_word && if *on { closes } else { opens } {
+            *on = !*on;
+            output.push_str(&self.style());
+        } else {
+            output.extend(std::iter::repeat_n(c, run));
+            return Some(run);
+        }
+        Some(width)
+    }
+
+    /// Decide what the line starting `pending` is, rendering its start
+    fn start_line(&mut self, done: bool, output: &mut String) -> Option<usize> {
+        let end = self.pending.iter().position(|&c| c == '\n');
+        let line = &self.pending[..end.unwrap_or(self.pending.len())];
+        let kind = line_kind(line, done || end.is_some())?;
+        self.line_start = false;
+
+        if self.code_block {
+            if let LineKind::Fence = kind {
+                self.code_block = false;
+                self.skip_line = true;
+                return Some(0);
+            }
+            output.push_str(CODE_INDENT);
+            return Some(0);
+        }
+        match kind {
+            LineKind::Fence => {
+                self.code_block = true;
+                self.skip_line = true;
+                Some(0)
+            }
+            LineKind::Bullet { indent, marker } => {
+                output.push_str(&" ".repeat(indent));
+                output.push_str("- ");
+                Some(indent + marker)
+            }
+            LineKind::Heading { marker } => {
+                self.heading = true;
+                output.push_str(&self.style());
+                Some(marker)
+            }
+            LineKind::Rule => {
+                output.push_str("----------");
+                Some(line.len())
+            }
+            LineKind::Plain => Some(0),
+        }
+    }
+
+    /// Close whatever is still open at the end of a line
+    fn end_line(&mut self) -> String {
+        let open = self.strong || self.emphasis || self.code || self.heading;
+        self.strong = false;
+        self.emphasis = false;
+        self.code = false;
+        self.heading = false;
+        if open {
+            esc::RESET_ATTRS.to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// The attributes for what's open now, from scratch
+    fn style(&self) -> String {
+        let mut style = String::from(esc::RESET_ATTRS);
+        if self.strong || self.heading {
+            style.push_str(esc::BOLD);
+        }
+        if self.emphasis || self.code {
+            style.push_str(esc::REVERSE);
+        }
+        style
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` rendered with the escapes spelled out
+    fn plain(text: &str) -> String {
+        Markdown::render(text)
+            .replace(esc::RESET_ATTRS, "</>")
+            .replace(esc::BOLD, "<b>")
+            .replace(esc::REVERSE, "<r>")
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(plain("a **bold** word"), "a </><b>bold</> word");
+        assert_eq!(
+            plain("an *em* and `code`"),
+            "an </><r>em</> and </><r>code</>"
+        );
+        assert_eq!(plain("snake_case_name * 2"), "snake_case_name * 2");
+        assert_eq!(plain("# Title\nText"), "</><b>Title</>\nText");
+        assert_eq!(
+            plain("* one\n  + two\n3. three"),
+            "- one\n  - two\n3. three"
+        );
+        assert_eq!(
+            plain("Run:\n```sh\nls *.rs\n```\nDone"),
+            "Run:\n    ls *.rs\nDone"
+        );
+        assert_eq!(plain("Above\n---\nBelow"), "Above\n----------\nBelow");
+        // Left open at the end of a line
+        assert_eq!(plain("**never closed\nnext"), "</><b>never closed</>\nnext");
+    }
+
+    #[test]
+    fn test_streamed_in_pieces() {
+        let text = "# Hi\nSome **bold** and `code`:\n```\nx = 1\n```\n- item *one*";
+        let whole = Markdown::render(text);
+        for size in 1..5 {
+            let mut markdown = Markdown::new();
+            let chars: Vec<char> = text.chars().collect();
+            let mut output = String::new();
+            for piece in chars.chunks(size) {
+                output.push_str(&markdown.feed(&piece.iter().collect::<String>()));
+            }
+            output.push_str(&markdown.finish());
+            assert_eq!(output, whole, "in pieces of {}", size);
+        }
+    }
+}
@@ -3,70 +3,114 @@
 //! This module provides:
 //! - Terminal escape sequences and constants
 //! - Chat buffer with scrollback support
+//! - Markdown in AI replies drawn with bold and reverse video
+//! - SGR colour for ANSI and VT525 terminals
+//! - Timestamps on chat lines
+//! - Non-ASCII text mapped to what the terminal can show
+//! - 7-bit or 8-bit C1 controls
 //! - UI rendering (tab bar, input area, borders)
-//! - Stream/video frame rendering
+//! - The screen layout: its height and the split between the chat and input areas
+//! - UI themes (DEC or ASCII borders, reverse video, hints)
+//! - Stream/video frame rendering, paced to what the serial link keeps up with
+//! - The Stats tab's per-peer table
+//! - Transparent printing to the terminal's printer port
+//! - A shadow copy of the screen for screenshots
 
 mod buffer;
+mod charset;
+mod collapse;
+pub mod color;
+pub mod controls;
+pub mod layout;
+mod markdown;
+mod pacing;
+mod print;
 mod render;
+mod screen;
+pub mod stamp;
+mod stats;
+mod theme;
 mod ui;
 
-pub use buffer::ChatBuffer;
+pub use buffer::{ChatBuffer, set_scroll_regions};
+pub use charset::{Charset, from_supplemental};
+pub use collapse::CollapsedShares;
+pub use controls::Controls;
+pub use markdown::Markdown;
+pub use pacing::FramePacer;
+pub use print::transparent_print;
 pub use render::{generate_waiting_for_peer_frame, render_stream};
+pub use screen::VirtualScreen;
+pub use stats::render_stats;
+pub use theme::{THEME_NAMES, Theme};
 pub use ui::{
     cleanup_split_screen, init_split_screen_with_tabs, max_input_length, redraw_input,
-    redraw_tab_bar,
+    redraw_separator, redraw_status_bar, redraw_tab_bar, tab_at,
 };
 
-use crate::graphics::get_drcs_load_sequence;
+use crate::graphics::DrcsFont;
+use crate::mouse::Mouse;
 
 /// Escape sequence to switch to 132 column mode
 pub const ENTER_132_COL_MODE: &str = "\x1b[?3h";
 /// Escape sequence to switch to 80 column mode
 pub const EXIT_132_COL_MODE: &str = "\x1b[?3l";
 
-/// Get the initialization sequence for the terminal
-pub fn get_init_sequence(use_drcs: bool, use_132_cols: bool) -> String {
-    let mut output = String::new();
+/// Get the initialization sequence for the terminal, loading `drcs` on
+/// terminals with soft fonts
+pub fn get_init_sequence(
+    drcs: Option<&DrcsFont>,
+    use_132_cols: bool,
+    charset: Charset,
+    mouse: Mouse,
+) -> String {
+    let mut output = String::from(charset.init_sequence());
+    // The VT220 and later can send 8-bit controls, which the input parser
+    // doesn't know: ask for 7-bit ones
+    if charset == Charset::Multinational {
+        output.push_str(controls::SEND_7BIT_CONTROLS);
+    }
+    // Emulators with bracketed paste mark pastes; terminals without it ignore
+    // the private mode
+    output.push_str(crate::paste::BRACKETED_PASTE_ON);
+    output.push_str(mouse.on_sequence());
     if use_132_cols {
         output.push_str(ENTER_132_COL_MODE);
     } else {
         output.push_str(EXIT_132_COL_MODE);
     }
 
-    if use_drcs {
-        output.push_str(&get_drcs_load_sequence());
+    if let Some(font) = drcs {
+        output.push_str(&font.load_sequence());
     }
     output
 }
 
-/// VT220 terminal dimensions (80x24 is standard)
+/// VT220 terminal dimensions (80x24 is standard; other heights are a
+/// [`layout::Layout`])
 pub const TERMINAL_HEIGHT: usize = 24;
 
-/// Layout with borders:
+/// Layout with borders (in the standard layout with the default 2-row input
+/// area; see [`layout`]):
 /// Row 1: Top border with tabs
 /// Rows 2-20: Chat display area (19 lines)
 /// Row 21: Separator border
 /// Rows 22-23: Input area (2 lines for wrapped input)
 /// Row 24: Bottom border
 pub const CHAT_REGION_START: usize = 2;
-pub const CHAT_REGION_END: usize = 20;
-pub const CHAT_VISIBLE_LINES: usize = CHAT_REGION_END - CHAT_REGION_START + 1; // 19 lines
-pub const CALL_REGION_END: usize = 23;
-pub const CALL_VISIBLE_LINES: usize = CALL_REGION_END - CHAT_REGION_START + 1; // 22 lines
-pub const INPUT_ROW_START: usize = 22;
-pub const INPUT_ROW_END: usize = 23;
-pub const INPUT_ROWS: usize = INPUT_ROW_END - INPUT_ROW_START + 1; // 2 rows
 
 /// Maximum scrollback buffer size
 pub const MAX_SCROLLBACK: usize = 10_000;
 
 /// Tab identifiers
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Tab {
     Chat = 0,
     Call = 1,
     Tunes = 2,
     Gemini = 3,
+    Stats = 4,
+    Todo = 5,
 }
 
 impl Tab {
@@ -80,7 +124,7 @@ impl Tab {
                 } else if gemini_available {
                     Tab::Gemini
                 } else {
-                    Tab::Chat
+                    Tab::Todo
                 }
             }
             Tab::Call => {
@@ -89,17 +133,56 @@ impl Tab {
                 } else if gemini_available {
                     Tab::Gemini
                 } else {
-                    Tab::Chat
+                    Tab::Todo
                 }
             }
             Tab::Tunes => {
                 if gemini_available {
                     Tab::Gemini
                 } else {
-                    Tab::Chat
+                    Tab::Todo
                 }
             }
-            Tab::Gemini => Tab::Chat,
+            Tab::Gemini => Tab::Todo,
+            Tab::Todo => Tab::Stats,
+            Tab::Stats => Tab::Chat,
+        }
+    }
+
+    /// Whether the tab has an input line
+    pub fn has_input(self) -> bool {
+        matches!(self, Tab::Chat | Tab::Gemini)
+    }
+}
+
+/// What arrived on each tab while it wasn't showing, for the badges in the
+/// tab bar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Unread([usize; 6]);
+
+impl Unread {
+    /// Something new on `tab`
+    pub fn mark(&mut self, tab: Tab) {
+        self.0[tab as usize] += 1;
+    }
+
+    /// The tab has been looked at
+    pub fn clear(&mut self, tab: Tab) {
+        self.0[tab as usize] = 0;
+    }
+
+    pub fn count(&self, tab: Tab) -> usize {
+        self.0[tab as usize]
+    }
+
+    /// The badge after a tab's label: the number of messages on Chat and AI,
+    /// a `*` on tabs that change rather than collect messages
+    pub fn badge(&self, tab: Tab) -> Option<String> {
+        match (tab, self.count(tab)) {
+            (_, 0) => None,
+            (Tab::Chat | Tab::Gemini, n) if n > 99 => Some(" 99+".to_string()),
+            (Tab::Chat | Tab::Gemini, n) => Some(format!(" {}", n)),
+            _ => Some("*".to_string()),
         }
     }
 }
@@ -124,19 +207,58 @@ pub mod esc {
     /// Reverse video
     pub const REVERSE: &str = "\x1b[7m";
 
+    /// Bold
+    pub const BOLD: &str = "\x1b[1m";
+
     /// Save cursor position
     pub const SAVE_CURSOR: &str = "\x1b7";
 
     /// Restore cursor position
     pub const RESTORE_CURSOR: &str = "\x1b8";
 
+    /// Enter printer controller (transparent print) mode
+    pub const PRINTER_CONTROLLER_ON: &str = "\x1b[5i";
+
+    /// Exit printer controller (transparent print) mode
+    pub const PRINTER_CONTROLLER_OFF: &str = "\x1b[4i";
+
     /// Move cursor to specific position (1-indexed)
     pub fn cursor_to(row: usize, col: usize) -> String {
         format!("\x1b[{};{}H", row, col)
     }
 
+    /// Index (IND): move down a row, scrolling the region at its bottom
+    pub const INDEX: &str = "\x1bD";
+
+    /// Set the scroll region (DECSTBM) to rows `top` to `bottom`
+    pub fn set_scroll_region(top: usize, bottom: usize) -> String {
+        format!("\x1b[{};{}r", top, bottom)
+    }
+
     /// Reset scroll region to full screen
     pub fn reset_scroll_region() -> String {
         "\x1b[r".to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unread_badges() {
+        let mut unread = Unread::default();
+        assert_eq!(unread.badge(Tab::Chat), None);
+        unread.mark(Tab::Chat);
+        unread.mark(Tab::Chat);
+        unread.mark(Tab::Todo);
+        assert_eq!(unread.badge(Tab::Chat).as_deref(), Some(" 2"));
+        assert_eq!(unread.badge(Tab::Todo).as_deref(), Some("*"));
+        for _ in 0..100 {
+            unread.mark(Tab::Gemini);
+        }
+        assert_eq!(unread.badge(Tab::Gemini).as_deref(), Some(" 99+"));
+        unread.clear(Tab::Chat);
+        assert_eq!(unread.count(Tab::Chat), 0);
+    }
+}
@@ -0,0 +1,93 @@
+//! Pacing video to what the serial link keeps up with.
+//!
+//! A frame that only changes a few cells is a few hundred bytes; a new
+//! scene at full width, or a sixel picture, can be many kilobytes. Rather
+//! than guessing a frame size from the baud rate once, each frame drawn is
+//! timed: how many bytes it came to, how long the write took (a full driver
+//! buffer blocks it) and what was still queued for the UART afterwards. The
+//! next frame waits until all that has had time to go out, so a busy
+//! picture drops frames instead of lagging ever further behind, and a quiet
+//! one runs at the full camera rate.
+
+use std::time::{Duration, Instant};
+
+/// Times when the next video frame can be drawn
+#[derive(Debug)]
+pub struct FramePacer {
+    /// The shortest wait: the camera's frame rate
+    min_delay: Duration,
+    /// The wait now, from the frames drawn lately
+    delay: Duration,
+    /// What the link carries, at ten bits a byte
+    bytes_per_sec: f64,
+    last_frame: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn new(min_delay: Duration, baud_rate: u32) -> Self {
+        Self {
+            min_delay,
+            delay: min_delay,
+            bytes_per_sec: (baud_rate / 10).max(1) as f64,
+            last_frame: None,
+        }
+    }
+
+    /// Whether enough time has gone by since the last frame to draw another
+    pub fn ready(&self, now: Instant) -> bool {
+        self.last_frame
+            .is_none_or(|at| now.duration_since(at) >= self.delay)
+    }
+
+    /// A frame of `bytes` was written starting at `now`, taking `write_time`,
+    /// and left `backlog` bytes waiting to go out
+    pub fn record(&mut self, now: Instant, bytes: usize, write_time: Duration, backlog: u32) {
+        self.last_frame = Some(now);
+        let drain = Duration::from_secs_f64((bytes + backlog as usize) as f64 / self.bytes_per_sec);
+        let target = drain.max(write_time).max(self.min_delay);
+        // Back off at once, speed up gently
+        self.delay = if target > self.delay {
+            target
+        } else {
+            (self.delay * 3 + target) / 4
+        };
+    }
+
+    /// Frames a second at the current pace
+    pub fn fps(&self) -> f64 {
+        1.0 / self.delay.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing_follows_frame_size() {
+        let min = Duration::from_millis(200);
+        // 1920 bytes a second
+        let mut pacer = FramePacer::new(min, 19200);
+        let start = Instant::now();
+        assert!(pacer.ready(start));
+
+        // A whole new picture takes two seconds to go out
+        pacer.record(start, 3840, Duration::from_millis(5), 0);
+        assert!(!pacer.ready(start + Duration::from_secs(1)));
+        assert!(pacer.ready(start + Duration::from_secs(2)));
+
+        // Small updates bring the rate back up, but not all at once
+        let later = start + Duration::from_secs(2);
+        pacer.record(later, 100, Duration::from_millis(1), 0);
+        assert!(!pacer.ready(later + min));
+        for _ in 0..100 {
+            pacer.record(later, 100, Duration::from_millis(1), 0);
+        }
+        assert!(pacer.ready(later + min));
+        assert!((pacer.fps() - 5.0).abs() < 0.1);
+
+        // A backlog in the UART slows it down again
+        pacer.record(later, 100, Duration::ZERO, 1820);
+        assert!(!pacer.ready(later + Duration::from_millis(900)));
+    }
+}
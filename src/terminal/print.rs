@@ -0,0 +1,75 @@
+//! DEC transparent print (printer controller mode) support.
+//!
+//! While printer controller mode is active, the terminal passes everything it
+//! receives straight to its AUX/printer port without displaying it. Whatever is
+//! attached there (a printer, or a computer capturing text) receives the output.
+//!
+//! - Enter printer controller mode: `CSI 5 i`
+//! - Exit printer controller mode: `CSI 4 i`
+//!
+//! Each capture is wrapped in its own on/off pair so that screen updates sent
+//! between captures are still displayed normally.
+
+use super::esc;
+
+/// Build a transparent print sequence that sends `lines` to the printer port.
+///
+/// Shift-in/shift-out characters and escape sequences (the chat's colours
+/// and bold) are removed and characters drawn from the DEC graphics set are
+/// replaced with plain ASCII, since the printer won't have the terminal's
+/// character sets loaded.
+pub fn transparent_print<S: AsRef<str>>(lines: &[S]) -> String {
+    let mut output = String::new();
+    output.push_str(esc::PRINTER_CONTROLLER_ON);
+
+    for line in lines {
+        let mut in_graphics = false;
+        let mut chars = line.as_ref().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x0E' => in_graphics = true,
+                '\x0F' => in_graphics = false,
+                '\x1b' if chars.peek() == Some(&'[') => {
+                    // Skip CSI sequences up to the final byte
+                    chars.next();
+                    for p in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&p) {
+                            break;
+                        }
+                    }
+                }
+                ' ' => output.push(' '),
+                _ if in_graphics => output.push('#'),
+                _ if c.is_control() => {}
+                _ => output.push(c),
+            }
+        }
+        output.push_str("\r\n");
+    }
+
+    output.push_str(esc::PRINTER_CONTROLLER_OFF);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transparent_print_wraps_lines() {
+        let output = transparent_print(&["hello", "world"]);
+        assert_eq!(output, "\x1b[5ihello\r\nworld\r\n\x1b[4i");
+    }
+
+    #[test]
+    fn test_transparent_print_strips_graphics() {
+        let output = transparent_print(&["a\x0Eaa \x0Fb\x07"]);
+        assert_eq!(output, "\x1b[5ia## b\r\n\x1b[4i");
+    }
+
+    #[test]
+    fn test_transparent_print_strips_colours() {
+        let output = transparent_print(&["\x1b[1;32mbob\x1b[0m: hi \x1b[7mthere\x1b[m"]);
+        assert_eq!(output, "\x1b[5ibob: hi there\r\n\x1b[4i");
+    }
+}
@@ -1,12 +1,13 @@
 //! Stream/video frame rendering.
 
+use super::CHAT_REGION_START;
 use super::esc;
-use super::{CALL_REGION_END, CALL_VISIBLE_LINES, CHAT_REGION_START};
+use super::layout::{region_end, region_rows};
 use crate::graphics::{Frame, render_frame_diff};
 
-/// Check if content is sixel data (starts with DCS = ESC P)
+/// Check if content is sixel data (sequences starting with DCS = ESC P)
 fn is_sixel_data(lines: &[String]) -> bool {
-    lines.len() == 1 && lines[0].starts_with("\x1bP")
+    !lines.is_empty() && lines.iter().all(|line| line.starts_with("\x1bP"))
 }
 
 /// Render a stream frame to the content area using cell-based differential rendering.
@@ -15,8 +16,8 @@ fn is_sixel_data(lines: &[String]) -> bool {
 /// with the previous frame, and emits minimal escape sequences to update only
 /// the changed cells. This works correctly with hybrid ASCII/DEC graphics.
 ///
-/// For sixel graphics (VT340), the content is rendered as a bitmap block with
-/// cursor positioning, bypassing cell-based diffing.
+/// For sixel graphics (VT340), the content is rendered as bitmap strips with
+/// cursor positioning, only the strips that changed being sent.
 pub fn render_stream(
     _sender: &str,
     lines: &[String],
@@ -25,7 +26,7 @@ pub fn render_stream(
 ) -> (String, Frame) {
     // Check if this is sixel data
     if is_sixel_data(lines) {
-        return render_sixel_stream(&lines[0], prev_frame, width);
+        return render_sixel_stream(lines, prev_frame, width);
     }
 
     // Parse lines into structured cells
@@ -36,7 +37,7 @@ pub fn render_stream(
     let frame_width = current_frame.width();
 
     // Use integer division for centering, but ensure we don't start before CHAT_REGION_START
-    let start_row = CHAT_REGION_START + (CALL_VISIBLE_LINES.saturating_sub(frame_height)) / 2;
+    let start_row = CHAT_REGION_START + (region_rows().saturating_sub(frame_height)) / 2;
     let start_col = (width.saturating_sub(frame_width)) / 2 + 1; // 1-based
 
     // Check if centering has changed (dimensions mismatch)
@@ -49,7 +50,7 @@ pub fn render_stream(
         prev_for_diff,
         start_row,
         start_col,
-        CALL_REGION_END,
+        region_end(),
     );
 
     (output, current_frame)
@@ -117,40 +118,34 @@ pub fn generate_waiting_for_peer_frame(peer_name: &str) -> Vec<String> {
 
 /// Render sixel graphics data with cursor positioning.
 ///
-/// Sixel is a bitmap format that can't use cell-based diffing.
-/// We position the cursor at the top-left of the display area and output
-/// the sixel data directly. The terminal handles the bitmap rendering.
+/// Sixel is a bitmap format that can't use cell-based diffing. Each line is
+/// a sequence drawing one row of the picture (ReGIS sends one for the
+/// whole), output after a cursor move to the top-left of its row.
 ///
-/// For frame-level diffing, we store a hash of the sixel data in a special
-/// "marker" Frame that can be compared for equality.
+/// For diffing, we store a hash of each sequence as a row of a special
+/// "marker" Frame; rows that hash the same as last time aren't sent again.
 fn render_sixel_stream(
-    sixel_data: &str,
+    sixel_data: &[String],
     prev_frame: Option<&Frame>,
     _width: usize,
 ) -> (String, Frame) {
-    // Create a marker frame for this sixel data
-    // We use a special frame with a single cell containing a hash-like marker
-    // This allows us to detect if the sixel content has changed
     let marker = create_sixel_marker_frame(sixel_data);
+    // A picture of a different height is drawn afresh
+    let prev = prev_frame.filter(|prev| prev.height() == marker.height());
 
-    // Check if we can skip rendering (same sixel data as before)
-    if let Some(prev) = prev_frame
-        && *prev == marker
-    {
-        // Content unchanged, skip rendering
-        return (String::new(), marker);
-    }
-
-    // Calculate positioning for sixel image
     // Row 2 is where content starts (row 1 is the tab bar)
     // Column 2 is where content starts (column 1 is the left border)
     let start_row = CHAT_REGION_START;
     let start_col = 2; // Start after left border
 
-    // Build output: position cursor, then sixel data
-    let mut output = String::with_capacity(sixel_data.len() + 20);
-    output.push_str(&esc::cursor_to(start_row, start_col));
-    output.push_str(sixel_data);
+    let mut output = String::new();
+    for (i, strip) in sixel_data.iter().enumerate() {
+        if prev.is_some_and(|prev| prev.rows[i] == marker.rows[i]) {
+            continue;
+        }
+        output.push_str(&esc::cursor_to(start_row + i, start_col));
+        output.push_str(strip);
+    }
 
     (output, marker)
 }
@@ -158,37 +153,23 @@ fn render_sixel_stream(
 /// Create a marker Frame for sixel data comparison.
 ///
 /// Since we can't parse sixel into cells, we create a special marker frame
-/// that stores a simple hash of the sixel data. Two frames with identical
-/// sixel data will compare equal.
-fn create_sixel_marker_frame(sixel_data: &str) -> Frame {
+/// with one row per sequence, holding a hash of it. Rows of identical sixel
+/// data compare equal.
+fn create_sixel_marker_frame(sixel_data: &[String]) -> Frame {
     use crate::graphics::Cell;
-
-    // Create a simple hash by sampling characters from the sixel data
-    // This is fast and sufficient for detecting changes
-    let len = sixel_data.len();
-    let sample_size = 8.min(len);
-
-    let mut cells = Vec::with_capacity(sample_size + 2);
-
-    // Add a marker prefix to distinguish from regular frames
-    cells.push(Cell::ascii('\x1b')); // ESC
-    cells.push(Cell::ascii('P')); // 'P' for sixel DCS
-
-    // Sample characters from the sixel data for comparison
-    if len > 0 {
-        let step = len / sample_size.max(1);
-        for i in 0..sample_size {
-            let idx = (i * step).min(len - 1);
-            if let Some(ch) = sixel_data.chars().nth(idx) {
-                cells.push(Cell::ascii(ch));
-            }
-        }
-    }
-
-    // Also include length as part of the "hash"
-    for digit in len.to_string().chars() {
-        cells.push(Cell::ascii(digit));
-    }
-
-    Frame { rows: vec![cells] }
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let rows = sixel_data
+        .iter()
+        .map(|strip| {
+            let mut hasher = DefaultHasher::new();
+            strip.hash(&mut hasher);
+            // A marker prefix to distinguish from regular frames
+            [Cell::ascii('\x1b'), Cell::ascii('P')]
+                .into_iter()
+                .chain(format!("{:016x}", hasher.finish()).chars().map(Cell::ascii))
+                .collect()
+        })
+        .collect();
+    Frame { rows }
 }
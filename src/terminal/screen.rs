@@ -0,0 +1,365 @@
+//! Shadow copy of what the terminal is showing.
+//!
+//! Everything written to the serial port is fed through a small VT220-subset
+//! interpreter that keeps a [`Frame`] of the visible screen, so the current UI
+//! can be captured (e.g. by `/screenshot`) without asking the terminal for it.
+//! Only the sequences Wormhole itself emits are understood; DCS strings (Sixel,
+//! ReGIS, DRCS loads) and printer controller output are skipped.
+
+use crate::graphics::{Cell, CharMode, Frame, parse_sgr};
+
+use super::TERMINAL_HEIGHT;
+
+/// Parser state between calls to [`VirtualScreen::feed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// ESC ( or ESC ) (or ESC * / ESC +) - waiting for the character set
+    /// final byte
+    Designate {
+        g1: bool,
+    },
+    Csi,
+    /// Inside a DCS string, until ST
+    Dcs,
+    DcsEscape,
+    /// Printer controller mode (CSI 5 i), until CSI 4 i
+    Printer,
+}
+
+/// The terminal screen as a grid of cells
+pub struct VirtualScreen {
+    frame: Frame,
+    row: usize,
+    col: usize,
+    /// Cursor is past the last column; the next character wraps
+    pending_wrap: bool,
+    /// G0 holds DEC Special Graphics (ESC ( 0)
+    g0_graphics: bool,
+    /// G1 is invoked into GL (SHIFT OUT)
+    shifted_out: bool,
+    colors: (Option<u8>, Option<u8>),
+    reverse: bool,
+    saved: (usize, usize),
+    scroll_top: usize,
+    scroll_bottom: usize,
+    state: State,
+    params: String,
+    /// Tail of printer controller output, to spot CSI 4 i
+    printer_tail: [char; 4],
+}
+
+impl VirtualScreen {
+    /// Create a blank screen
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            frame: Frame::with_dimensions(width, height),
+            row: 0,
+            col: 0,
+            pending_wrap: false,
+            g0_graphics: false,
+            shifted_out: false,
+            colors: (None, None),
+            reverse: false,
+            saved: (0, 0),
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            state: State::Ground,
+            params: String::new(),
+            printer_tail: [' '; 4],
+        }
+    }
+
+    /// The current screen contents
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Interpret output sent to the terminal
+    pub fn feed(&mut self, s: &str) {
+        for c in s.chars() {
+            self.feed_char(c);
+        }
+    }
+
+    fn feed_char(&mut self, c: char) {
+        match self.state {
+            State::Ground => self.ground(c),
+            State::Escape => self.escape(c),
+            State::Designate { g1 } => {
+                if !g1 {
+                    self.g0_graphics = c == '0';
+                }
+                self.state = State::Ground;
+            }
+            State::Csi => {
+                if ('\x40'..='\x7e').contains(&c) {
+                    let params = std::mem::take(&mut self.params);
+                    self.state = State::Ground;
+                    self.csi(&params, c);
+                } else {
+                    self.params.push(c);
+                }
+            }
+            State::Dcs => {
+                if c == '\x1b' {
+                    self.state = State::DcsEscape;
+                } else if c == '\u{9c}' {
+                    self.state = State::Ground;
+                }
+            }
+            State::DcsEscape => {
+                self.state = if c == '\\' { State::Ground } else { State::Dcs };
+            }
+            State::Printer => {
+                self.printer_tail.rotate_left(1);
+                self.printer_tail[3] = c;
+                if self.printer_tail == ['\x1b', '[', '4', 'i'] {
+                    self.printer_tail = [' '; 4];
+                    self.state = State::Ground;
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.state = State::Escape,
+            '\r' => {
+                self.col = 0;
+                self.pending_wrap = false;
+            }
+            '\n' | '\x0b' | '\x0c' => self.line_feed(),
+            '\x08' => {
+                self.col = self.col.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            '\x0e' => self.shifted_out = true,
+            '\x0f' => self.shifted_out = false,
+            c if c.is_control() => {}
+            c => self.put(c),
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        self.state = State::Ground;
+        match c {
+            '[' => self.state = State::Csi,
+            'P' => self.state = State::Dcs,
+            '(' => self.state = State::Designate { g1: false },
+            // G1 to G3 aren't tracked: only G0's graphics set is drawn
+            ')' | '*' | '+' => self.state = State::Designate { g1: true },
+            '7' => self.saved = (self.row, self.col),
+            '8' => {
+                (self.row, self.col) = self.saved;
+                self.pending_wrap = false;
+            }
+            'D' => self.line_feed(),
+            'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            'M' => self.reverse_index(),
+            'c' => *self = Self::new(self.width(), self.height()),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, params: &str, final_byte: char) {
+        let private = params.starts_with('?');
+        let nums: Vec<usize> = params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize| nums.get(i).copied().filter(|&n| n > 0).unwrap_or(1);
+
+        match final_byte {
+            'H' | 'f' => {
+                self.row = (arg(0) - 1).min(self.height() - 1);
+                self.col = (arg(1) - 1).min(self.width() - 1);
+                self.pending_wrap = false;
+            }
+            'A' => self.row = self.row.saturating_sub(arg(0)),
+            'B' => self.row = (self.row + arg(0)).min(self.height() - 1),
+            'C' => self.col = (self.col + arg(0)).min(self.width() - 1),
+            'D' => self.col = self.col.saturating_sub(arg(0)),
+            'J' => self.erase_display(nums[0]),
+            'K' => self.erase_line(nums[0]),
+            'm' => {
+                self.colors = parse_sgr(params, self.colors);
+                for n in params.split(';') {
+                    match n {
+                        "" | "0" | "27" => self.reverse = false,
+                        "7" => self.reverse = true,
+                        _ => {}
+                    }
+                }
+            }
+            'r' => {
+                let top = arg(0) - 1;
+                let bottom = nums
+                    .get(1)
+                    .copied()
+                    .filter(|&n| n > 0)
+                    .unwrap_or(self.height());
+                if top < bottom && bottom <= self.height() {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom - 1;
+                }
+                self.row = 0;
+                self.col = 0;
+            }
+            'h' | 'l' if private && nums.contains(&3) => {
+                // DECCOLM: switching column mode clears the screen
+                let width = if final_byte == 'h' { 132 } else { 80 };
+                *self = Self {
+                    colors: self.colors,
+                    g0_graphics: self.g0_graphics,
+                    ..Self::new(width, self.height())
+                };
+            }
+            'i' if !private && nums[0] == 5 => self.state = State::Printer,
+            _ => {}
+        }
+        self.pending_wrap &= matches!(final_byte, 'm');
+    }
+
+    fn put(&mut self, c: char) {
+        if self.pending_wrap {
+            self.col = 0;
+            self.line_feed();
+            self.pending_wrap = false;
+        }
+        let graphics = self.shifted_out || (self.g0_graphics && c.is_ascii());
+        let (fg, bg) = if self.reverse {
+            (self.colors.1.or(Some(0)), self.colors.0.or(Some(7)))
+        } else {
+            self.colors
+        };
+        self.frame.rows[self.row][self.col] = Cell {
+            char: c,
+            mode: if graphics {
+                CharMode::DecGraphics
+            } else {
+                CharMode::Ascii
+            },
+            fg,
+            bg,
+            bold: false,
+            reverse: false,
+        };
+        if self.col + 1 < self.width() {
+            self.col += 1;
+        } else {
+            self.pending_wrap = true;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.row == self.scroll_bottom {
+            self.frame.rows.remove(self.scroll_top);
+            let blank = vec![Cell::space(); self.width()];
+            self.frame.rows.insert(self.scroll_bottom, blank);
+        } else if self.row + 1 < self.height() {
+            self.row += 1;
+        }
+        self.pending_wrap = false;
+    }
+
+    fn reverse_index(&mut self) {
+        if self.row == self.scroll_top {
+            self.frame.rows.remove(self.scroll_bottom);
+            let blank = vec![Cell::space(); self.width()];
+            self.frame.rows.insert(self.scroll_top, blank);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let (row, col) = (self.row, self.col);
+        let range = match mode {
+            0 => row + 1..self.height(),
+            1 => 0..row,
+            _ => 0..self.height(),
+        };
+        for r in range {
+            self.frame.rows[r].fill(Cell::space());
+        }
+        match mode {
+            0 => self.frame.rows[row][col..].fill(Cell::space()),
+            1 => self.frame.rows[row][..=col].fill(Cell::space()),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let line = &mut self.frame.rows[self.row];
+        match mode {
+            0 => line[self.col..].fill(Cell::space()),
+            1 => line[..=self.col].fill(Cell::space()),
+            _ => line.fill(Cell::space()),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.frame.width()
+    }
+
+    fn height(&self) -> usize {
+        self.frame.height()
+    }
+}
+
+impl Default for VirtualScreen {
+    fn default() -> Self {
+        Self::new(80, TERMINAL_HEIGHT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_text(screen: &VirtualScreen, row: usize) -> String {
+        screen.frame().rows[row].iter().map(|c| c.char).collect()
+    }
+
+    #[test]
+    fn test_cursor_and_erase() {
+        let mut screen = VirtualScreen::new(10, 3);
+        screen.feed("\x1b[2J\x1b[2;3Hhi\x1b[1;1Habc\x1b[1;2H\x1b[K");
+        assert_eq!(row_text(&screen, 0), "a         ");
+        assert_eq!(row_text(&screen, 1), "  hi      ");
+    }
+
+    #[test]
+    fn test_graphics_and_colors() {
+        let mut screen = VirtualScreen::new(4, 1);
+        screen.feed("\x1b(0q\x1b(B\x0e$\x0f\x1b[0;38;5;196mx\x1b[7my");
+        let row = &screen.frame().rows[0];
+        assert_eq!(row[0], Cell::dec_graphics('q'));
+        assert_eq!(row[1], Cell::dec_graphics('$'));
+        assert_eq!(row[2], Cell::ascii('x').with_fg(Some(196)));
+        assert_eq!(row[3].bg, Some(196));
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut screen = VirtualScreen::new(3, 4);
+        screen.feed("top\x1b[2;3r\x1b[2;1Ha\r\nb\r\nc\x1b[4;1Hend");
+        assert_eq!(row_text(&screen, 0), "top");
+        assert_eq!(row_text(&screen, 1), "b  ");
+        assert_eq!(row_text(&screen, 2), "c  ");
+        assert_eq!(row_text(&screen, 3), "end");
+    }
+
+    #[test]
+    fn test_skips_dcs_and_printer_output() {
+        let mut screen = VirtualScreen::new(4, 1);
+        screen.feed("\x1bPq#0;2;0;0;0~~\x1b\\a\x1b[5ihidden\r\n\x1b[4ib");
+        assert_eq!(row_text(&screen, 0), "ab  ");
+    }
+}
@@ -0,0 +1,85 @@
+//! Timestamps on chat, AI and system lines (`timestamp_format` under
+//! `[terminal]`).
+//!
+//! Lines start with the time in brackets, `[03:45PM] `, or in 24-hour time,
+//! with seconds, or not at all. Like the colour setting it's global, set once
+//! at startup, since lines are stamped all over the place.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+
+/// Timestamp formats accepted in the config
+pub const TIMESTAMP_FORMATS: &[&str] = &["12h", "24h", "12h-seconds", "24h-seconds", "none"];
+
+/// strftime patterns for each of [`TIMESTAMP_FORMATS`] (empty for none)
+const PATTERNS: &[&str] = &["%I:%M%p", "%H:%M", "%I:%M:%S%p", "%H:%M:%S", ""];
+
+/// Index into [`PATTERNS`]
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// Stamp lines in the named format from now on. Returns false for a
+/// name that isn't one of [`TIMESTAMP_FORMATS`].
+pub fn set_format(name: &str) -> bool {
+    match TIMESTAMP_FORMATS.iter().position(|&f| f == name) {
+        Some(i) => {
+            FORMAT.store(i as u8, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+fn pattern() -> &'static str {
+    PATTERNS[FORMAT.load(Ordering::Relaxed) as usize]
+}
+
+/// The start of a line stamped now: `[03:45PM] `, or nothing
+pub fn now() -> String {
+    at(&Local::now())
+}
+
+/// The start of a line stamped at `time`
+pub fn at<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    match pattern() {
+        "" => String::new(),
+        pattern => format!("[{}] ", time.format(pattern)),
+    }
+}
+
+/// The time for the status bar's clock, which shows even with timestamps off
+pub fn clock() -> String {
+    match pattern() {
+        "" => Local::now().format(PATTERNS[0]).to_string(),
+        pattern => Local::now().format(pattern).to_string(),
+    }
+}
+
+/// Read a timestamp written in any of the formats
+pub fn parse(text: &str) -> Option<NaiveTime> {
+    PATTERNS
+        .iter()
+        .filter(|p| !p.is_empty())
+        .find_map(|p| NaiveTime::parse_from_str(text, p).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(PATTERNS.len(), TIMESTAMP_FORMATS.len());
+        let time = NaiveTime::from_hms_opt(15, 45, 12).unwrap();
+        for pattern in PATTERNS.iter().filter(|p| !p.is_empty()) {
+            let text = time.format(pattern).to_string();
+            let parsed = parse(&text).unwrap();
+            assert_eq!(parsed.format("%H:%M").to_string(), "15:45", "{}", pattern);
+        }
+        assert_eq!(parse("03:45PM"), NaiveTime::from_hms_opt(15, 45, 0));
+        assert_eq!(parse("soon"), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! The Stats tab: a table of per-peer link counters.
+
+use std::collections::HashMap;
+
+use super::layout::{region_end, region_rows};
+use super::{CHAT_REGION_START, esc};
+use crate::network::{Caps, PeerStats};
+
+/// Width of the Peer column
+const NAME_WIDTH: usize = 16;
+
+/// Table rows below the header (the bottom row is the status line)
+fn visible_lines() -> usize {
+    region_rows() - 2
+}
+
+/// "512B", "3.2K", "1.5M", "2.0G"
+fn format_bytes(bytes: u64) -> String {
+    const K: f64 = 1024.0;
+    let b = bytes as f64;
+    if bytes < 1024 {
+        format!("{}B", bytes)
+    } else if b < K * K {
+        format!("{:.1}K", b / K)
+    } else if b < K * K * K {
+        format!("{:.1}M", b / (K * K))
+    } else {
+        format!("{:.1}G", b / (K * K * K))
+    }
+}
+
+/// Write `text` at the start of `row`, clipped and padded to the content width
+fn put_row(output: &mut String, row: usize, text: &str, width: usize) {
+    let content_width = width - 2;
+    output.push_str(&esc::cursor_to(row, 2));
+    let text: String = text.chars().take(content_width).collect();
+    let pad = content_width - text.chars().count();
+    output.push_str(&text);
+    output.push_str(&" ".repeat(pad));
+}
+
+/// Draw the Stats tab inside the full-screen box, with each peer's
+/// capability badge (from `caps`) after its name
+pub fn render_stats(stats: &[PeerStats], caps: &HashMap<String, Caps>, width: usize) -> String {
+    let mut output = String::new();
+    let header = format!(
+        " {:<16} {:>8} {:>8} {:>15} {:>5} {:>5} {:>6}",
+        "Peer", "Sent", "Recv", "Msgs out/in", "FPS", "Lost", "Resent"
+    );
+    output.push_str(esc::BOLD);
+    put_row(&mut output, CHAT_REGION_START, &header, width);
+    output.push_str(esc::RESET_ATTRS);
+
+    for i in 0..visible_lines() {
+        let line = match stats.get(i) {
+            Some(peer) => {
+                let badge = caps.get(&peer.name).map(Caps::badge).unwrap_or_default();
+                let name: String = if badge.is_empty() {
+                    peer.name.chars().take(NAME_WIDTH).collect()
+                } else {
+                    // Shorten the name rather than the badge
+                    let room = NAME_WIDTH - badge.len() - 1;
+                    format!(
+                        "{} {}",
+                        peer.name.chars().take(room).collect::<String>(),
+                        badge
+                    )
+                };
+                let messages = format!(
+                    "{}/{}",
+                    peer.traffic.messages_sent, peer.traffic.messages_received
+                );
+                format!(
+                    " {:<16} {:>8} {:>8} {:>15} {:>5.1} {:>5} {:>6}",
+                    name,
+                    format_bytes(peer.traffic.bytes_sent),
+                    format_bytes(peer.traffic.bytes_received),
+                    messages,
+                    peer.fps,
+                    peer.reassembly_failures,
+                    peer.retransmissions
+                )
+            }
+            None => String::new(),
+        };
+        put_row(&mut output, CHAT_REGION_START + 1 + i, &line, width);
+    }
+
+    let status = match stats.len() {
+        0 => "(No peers online)".to_string(),
+        n if n > visible_lines() => format!(
+            " {} peers ({} not shown) | Reset <Ctrl+C>",
+            n,
+            n - visible_lines()
+        ),
+        1 => " 1 peer | Reset <Ctrl+C>".to_string(),
+        n => format!(" {} peers | Reset <Ctrl+C>", n),
+    };
+    output.push_str("\x1b[2m"); // Dim attribute
+    put_row(&mut output, region_end(), &status, width);
+    output.push_str(esc::RESET_ATTRS);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Traffic;
+
+    #[test]
+    fn test_render_stats() {
+        let stats = [PeerStats {
+            name: "alice".to_string(),
+            addr: "192.0.2.1:7890".parse().unwrap(),
+            traffic: Traffic {
+                bytes_sent: 1536,
+                bytes_received: 3 * 1024 * 1024,
+                messages_sent: 12,
+                messages_received: 340,
+            },
+            fps: 14.8,
+            reassembly_failures: 2,
+            retransmissions: 5,
+        }];
+        let caps = HashMap::from([(
+            "alice".to_string(),
+            Caps {
+                video: true,
+                files: true,
+                ..Caps::default()
+            },
+        )]);
+        let output = render_stats(&stats, &caps, 80);
+        let row = output.split("\x1b[").find(|s| s.contains("alice")).unwrap();
+        for field in ["alice [VF]", "1.5K", "3.0M", "12/340", "14.8", " 2", " 5"] {
+            assert!(row.contains(field), "{} missing from {:?}", field, row);
+        }
+        assert!(output.contains(" 1 peer | Reset"));
+        assert!(render_stats(&[], &HashMap::new(), 80).contains("(No peers online)"));
+    }
+}
@@ -0,0 +1,115 @@
+//! UI themes (`theme` under `[terminal]`).
+//!
+//! The theme decides how the screen furniture is drawn: box borders in DEC
+//! Special Graphics or plain ASCII (`+`, `-`, `|`) for terminals and
+//! emulators without the graphics set, whether the active tab is picked out
+//! in reverse video, and whether key hints are shown in the tab bar.
+
+use super::{color, esc};
+use crate::graphics::{DecGraphicsChar, ENTER_DEC_GRAPHICS, EXIT_DEC_GRAPHICS};
+
+/// Theme names accepted in the config
+pub const THEME_NAMES: &[&str] = &["dec", "ascii", "plain"];
+
+/// How the UI is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Borders in DEC Special Graphics (false = ASCII)
+    pub dec_borders: bool,
+    /// Highlight the active tab in reverse video (false = brackets only)
+    pub reverse: bool,
+    /// Show `<Tab>` and `^Refresh / ^Clear` in the tab bar
+    pub hints: bool,
+}
+
+impl Theme {
+    /// Line-drawing borders, reverse video and hints: the VT100 look
+    pub const DEC: Theme = Theme {
+        dec_borders: true,
+        reverse: true,
+        hints: true,
+    };
+
+    /// The same with ASCII borders
+    pub const ASCII: Theme = Theme {
+        dec_borders: false,
+        ..Theme::DEC
+    };
+
+    /// ASCII borders and nothing else: no attributes, no hints
+    pub const PLAIN: Theme = Theme {
+        dec_borders: false,
+        reverse: false,
+        hints: false,
+    };
+
+    /// The theme called `name` in the config
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "dec" => Some(Theme::DEC),
+            "ascii" => Some(Theme::ASCII),
+            "plain" => Some(Theme::PLAIN),
+            _ => None,
+        }
+    }
+
+    /// Border characters, switching to the graphics set and back if need be
+    pub fn border(&self, chars: &[DecGraphicsChar]) -> String {
+        if self.dec_borders {
+            let mut output = String::from(ENTER_DEC_GRAPHICS);
+            output.extend(chars.iter().map(|c| c.as_dec_char()));
+            output.push_str(EXIT_DEC_GRAPHICS);
+            output
+        } else {
+            chars.iter().map(|c| c.as_ascii_char()).collect()
+        }
+    }
+
+    /// `count` of the same border character
+    pub fn border_run(&self, c: DecGraphicsChar, count: usize) -> String {
+        self.border(&vec![c; count])
+    }
+
+    /// The attribute the active tab is drawn with (empty for none)
+    pub fn tab_highlight(&self) -> &'static str {
+        if color::enabled() || self.reverse {
+            color::tab_highlight()
+        } else {
+            ""
+        }
+    }
+
+    /// Undo `tab_highlight`
+    pub fn tab_highlight_end(&self) -> &'static str {
+        if self.tab_highlight().is_empty() {
+            ""
+        } else {
+            esc::RESET_ATTRS
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DEC
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DecGraphicsChar::*;
+
+    #[test]
+    fn test_borders() {
+        let corners = [UpperLeftCorner, HorizontalLine, UpperRightCorner];
+        assert_eq!(Theme::DEC.border(&corners), "\x1b(0lqk\x1b(B");
+        assert_eq!(Theme::ASCII.border(&corners), "+-+");
+        assert_eq!(Theme::PLAIN.border_run(VerticalLine, 2), "||");
+        assert_eq!(Theme::PLAIN.tab_highlight(), "");
+        for name in THEME_NAMES {
+            assert!(Theme::from_name(name).is_some());
+        }
+        assert_eq!(Theme::from_name("neon"), None);
+    }
+}
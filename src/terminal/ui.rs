@@ -1,33 +1,36 @@
 //! UI components: tab bar, input area, borders.
 
+use super::CHAT_REGION_START;
 use super::Tab;
-use super::esc;
-use super::{
-    CHAT_REGION_END, CHAT_REGION_START, INPUT_ROW_END, INPUT_ROW_START, INPUT_ROWS, TERMINAL_HEIGHT,
+use super::layout::{
+    chat_region_end, input_row_end, input_row_start, input_rows, region_end, screen_rows,
+    separator_row, status_row,
 };
-use crate::graphics::{DecGraphicsChar, ENTER_DEC_GRAPHICS, EXIT_DEC_GRAPHICS};
+use super::{Theme, Unread, esc};
+use crate::graphics::DecGraphicsChar;
 
 /// Draw a horizontal line with optional left/right connectors
-fn draw_horizontal_line(left: DecGraphicsChar, right: DecGraphicsChar, width: usize) -> String {
-    use DecGraphicsChar::HorizontalLine;
-
-    let mut output = String::new();
-    output.push_str(ENTER_DEC_GRAPHICS);
-    output.push(left.as_dec_char());
-    for _ in 0..width - 2 {
-        output.push(HorizontalLine.as_dec_char());
-    }
-    output.push(right.as_dec_char());
-    output.push_str(EXIT_DEC_GRAPHICS);
-    output
+fn draw_horizontal_line(
+    theme: Theme,
+    left: DecGraphicsChar,
+    right: DecGraphicsChar,
+    width: usize,
+) -> String {
+    let mut chars = vec![DecGraphicsChar::HorizontalLine; width];
+    chars[0] = left;
+    chars[width - 1] = right;
+    theme.border(&chars)
 }
 
-/// Draw the top border with tab indicators
+/// Draw the top border with tab indicators, and unread badges on the tabs
+/// not showing
 fn draw_tab_bar(
+    theme: Theme,
     active_tab: Tab,
     gemini_available: bool,
     tunes_available: bool,
     active_call: Option<&str>,
+    unread: Unread,
     width: usize,
 ) -> String {
     use DecGraphicsChar::*;
@@ -36,167 +39,263 @@ fn draw_tab_bar(
     output.push_str(&esc::cursor_to(1, 1));
 
     // Start with upper left corner
-    output.push_str(ENTER_DEC_GRAPHICS);
-    output.push(UpperLeftCorner.as_dec_char());
-    output.push_str(EXIT_DEC_GRAPHICS);
-
-    // Helper to draw a tab
-    let draw_tab = |label: &str, is_active: bool, is_next: bool| -> String {
-        if is_active {
-            format!("{}[{}]{}", esc::REVERSE, label, esc::RESET_ATTRS)
-        } else if is_next {
-            format!(" {} <Tab> ", label)
-        } else {
-            format!(" {} ", label)
-        }
-    };
-
-    // Determine next tab for hint
-    let next_tab = active_tab.next(gemini_available, active_call.is_some(), tunes_available);
-
-    // Chat Tab
-    output.push_str(&draw_tab(
-        "Chat",
-        active_tab == Tab::Chat,
-        next_tab == Tab::Chat,
-    ));
+    output.push_str(&theme.border(&[UpperLeftCorner]));
 
-    // Call Tab (if active)
-    if let Some(peer_name) = active_call {
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(HorizontalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
-
-        let label = format!("Call ({})", peer_name);
-        output.push_str(&draw_tab(
-            &label,
-            active_tab == Tab::Call,
-            next_tab == Tab::Call,
-        ));
-    }
+    // Width used so far: the corner
+    let mut visible_len = 1;
 
-    // Tunes Tab (if available)
-    if tunes_available {
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(HorizontalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
-
-        output.push_str(&draw_tab(
-            "Tunes",
-            active_tab == Tab::Tunes,
-            next_tab == Tab::Tunes,
-        ));
-    }
-
-    // AI Tab (if available)
-    if gemini_available {
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(HorizontalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
-
-        output.push_str(&draw_tab(
-            "AI",
-            active_tab == Tab::Gemini,
-            next_tab == Tab::Gemini,
-        ));
+    let tabs = tab_labels(
+        theme,
+        active_tab,
+        gemini_available,
+        tunes_available,
+        active_call,
+        unread,
+    );
+    for (i, (tab, text)) in tabs.into_iter().enumerate() {
+        if i > 0 {
+            output.push_str(&theme.border(&[HorizontalLine]));
+            visible_len += 1;
+        }
+        visible_len += text.chars().count();
+        if tab == active_tab {
+            output.push_str(theme.tab_highlight());
+            output.push_str(&text);
+            output.push_str(theme.tab_highlight_end());
+        } else {
+            output.push_str(&text);
+        }
     }
 
-    // Hints: ^Refresh / ^Clear
+    // Hints: ^Refresh / ^Clear, which go when the tabs need the room
     let hints = " ^Refresh / ^Clear ";
-
-    // Calculate used length
-    let mut visible_len = 1; // Corner
-
-    // Chat
-    visible_len += if active_tab == Tab::Chat {
-        6
-    } else if next_tab == Tab::Chat {
-        12
+    let hints = if theme.hints && visible_len + hints.len() < width {
+        hints
     } else {
-        6
+        ""
     };
-
-    // Call
-    if let Some(peer_name) = active_call {
-        visible_len += 1; // Separator
-        let label_len = 7 + peer_name.len();
-        let tab_len = label_len + 2;
-        let next_len = label_len + 8;
-        visible_len += if active_tab == Tab::Call {
-            tab_len
-        } else if next_tab == Tab::Call {
-            next_len
-        } else {
-            tab_len
-        };
-    }
-
-    // Tunes
-    if tunes_available {
-        visible_len += 1; // Separator
-        visible_len += if active_tab == Tab::Tunes {
-            7 // "[Tunes]"
-        } else if next_tab == Tab::Tunes {
-            13 // " Tunes <Tab> "
-        } else {
-            7 // " Tunes "
-        };
-    }
-
-    // AI
-    if gemini_available {
-        visible_len += 1; // Separator
-        visible_len += if active_tab == Tab::Gemini {
-            4
-        } else if next_tab == Tab::Gemini {
-            10
-        } else {
-            4
-        };
-    }
-
     visible_len += hints.len();
     visible_len += 1; // Right corner
 
     // Fill with horizontal line
     let remaining = width.saturating_sub(visible_len);
 
-    output.push_str(ENTER_DEC_GRAPHICS);
-    for _ in 0..remaining {
-        output.push(HorizontalLine.as_dec_char());
-    }
-    output.push_str(EXIT_DEC_GRAPHICS);
-
+    output.push_str(&theme.border_run(HorizontalLine, remaining));
     output.push_str(hints);
-
-    output.push_str(ENTER_DEC_GRAPHICS);
-    output.push(UpperRightCorner.as_dec_char());
-    output.push_str(EXIT_DEC_GRAPHICS);
+    output.push_str(&theme.border(&[UpperRightCorner]));
 
     output
 }
 
+/// The tabs shown in the tab bar, in order, with the text each takes up
+fn tab_labels(
+    theme: Theme,
+    active_tab: Tab,
+    gemini_available: bool,
+    tunes_available: bool,
+    active_call: Option<&str>,
+    unread: Unread,
+) -> Vec<(Tab, String)> {
+    // Determine next tab for hint (none if the theme hides hints)
+    let next_tab = Some(active_tab.next(gemini_available, active_call.is_some(), tunes_available))
+        .filter(|_| theme.hints);
+
+    let call_label = active_call.map(|peer_name| format!("Call ({})", peer_name));
+    let mut tabs = vec![(Tab::Chat, "Chat")];
+    if let Some(label) = &call_label {
+        tabs.push((Tab::Call, label.as_str()));
+    }
+    if tunes_available {
+        tabs.push((Tab::Tunes, "Tunes"));
+    }
+    if gemini_available {
+        tabs.push((Tab::Gemini, "AI"));
+    }
+    tabs.push((Tab::Todo, "Todo"));
+    tabs.push((Tab::Stats, "Stats"));
+
+    tabs.into_iter()
+        .map(|(tab, label)| {
+            let badge = unread.badge(tab).filter(|_| tab != active_tab);
+            let label = format!("{}{}", label, badge.as_deref().unwrap_or(""));
+            let text = if tab == active_tab {
+                format!("[{}]", label)
+            } else if next_tab == Some(tab) {
+                format!(" {} <Tab> ", label)
+            } else {
+                format!(" {} ", label)
+            };
+            (tab, text)
+        })
+        .collect()
+}
+
+/// The tab whose label is at column `col` (1-based) of the tab bar, for a
+/// mouse click
+pub fn tab_at(
+    theme: Theme,
+    active_tab: Tab,
+    gemini_available: bool,
+    tunes_available: bool,
+    active_call: Option<&str>,
+    unread: Unread,
+    col: usize,
+) -> Option<Tab> {
+    let tabs = tab_labels(
+        theme,
+        active_tab,
+        gemini_available,
+        tunes_available,
+        active_call,
+        unread,
+    );
+    // After the corner
+    let mut start = 2;
+    for (tab, text) in tabs {
+        let end = start + text.chars().count();
+        if (start..end).contains(&col) {
+            return Some(tab);
+        }
+        // And the line between tabs
+        start = end + 1;
+    }
+    None
+}
+
 /// Redraw just the tab bar (for switching tabs without full redraw)
 pub fn redraw_tab_bar(
+    theme: Theme,
     active_tab: Tab,
     gemini_available: bool,
     tunes_available: bool,
     active_call: Option<&str>,
+    unread: Unread,
     width: usize,
 ) -> String {
     let mut output = String::new();
     output.push_str(esc::SAVE_CURSOR);
     output.push_str(&draw_tab_bar(
+        theme,
         active_tab,
         gemini_available,
         tunes_available,
         active_call,
+        unread,
         width,
     ));
     output.push_str(esc::RESTORE_CURSOR);
     output
 }
 
+/// Draw the separator between the chat area and the input, with the room
+/// topic on the left and a running timer (or the search status) on the right
+fn draw_separator(theme: Theme, topic: Option<&str>, timer: Option<&str>, width: usize) -> String {
+    use DecGraphicsChar::*;
+
+    let mut output = esc::cursor_to(separator_row(), 1);
+    if topic.is_none() && timer.is_none() {
+        output.push_str(&draw_horizontal_line(theme, LeftTee, RightTee, width));
+        return output;
+    }
+
+    // Both come from peers: drop control characters
+    let timer: Option<String> = timer.map(|t| t.chars().filter(|c| !c.is_control()).collect());
+    // The timer's label, its spaces and the line after it
+    let timer_len = timer.as_ref().map_or(0, |t| t.chars().count() + 3);
+
+    // Fit the topic between the tees, leaving room for the timer
+    let max_len = width.saturating_sub(6 + timer_len);
+    let label = topic.map(|topic| {
+        let mut label: String = format!("Topic: {}", topic)
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect();
+        if label.chars().count() > max_len {
+            label = label.chars().take(max_len.saturating_sub(3)).collect();
+            label.push_str("...");
+        }
+        label
+    });
+    let label_len = label.as_ref().map_or(0, |l| l.chars().count() + 2);
+
+    output.push_str(&theme.border(&[LeftTee, HorizontalLine]));
+    if let Some(label) = &label {
+        output.push(' ');
+        output.push_str(label);
+        output.push(' ');
+    }
+    output.push_str(&theme.border_run(
+        HorizontalLine,
+        width.saturating_sub(3 + label_len + timer_len),
+    ));
+    if let Some(timer) = &timer {
+        output.push(' ');
+        output.push_str(timer);
+        output.push(' ');
+        output.push_str(&theme.border(&[HorizontalLine]));
+    }
+    output.push_str(&theme.border(&[RightTee]));
+    output
+}
+
+/// Redraw just the separator row (when the topic changes, the timer ticks or
+/// a search steps to another match)
+pub fn redraw_separator(
+    theme: Theme,
+    topic: Option<&str>,
+    timer: Option<&str>,
+    width: usize,
+) -> String {
+    let mut output = String::new();
+    output.push_str(esc::SAVE_CURSOR);
+    output.push_str(&draw_separator(theme, topic, timer, width));
+    output.push_str(esc::RESTORE_CURSOR);
+    output
+}
+
+/// Draw the bottom border, with the status bar (clock, peers, call, unread)
+/// in it if there is one. Layouts without a status row have no bottom border.
+fn draw_status_bar(theme: Theme, status: Option<&str>, width: usize) -> String {
+    use DecGraphicsChar::*;
+
+    let Some(row) = status_row() else {
+        return String::new();
+    };
+    let mut output = esc::cursor_to(row, 1);
+    let Some(status) = status else {
+        output.push_str(&draw_horizontal_line(
+            theme,
+            LowerLeftCorner,
+            LowerRightCorner,
+            width,
+        ));
+        return output;
+    };
+    let status: String = status.chars().take(width.saturating_sub(6)).collect();
+    output.push_str(&theme.border(&[LowerLeftCorner, HorizontalLine]));
+    output.push(' ');
+    output.push_str(&status);
+    output.push(' ');
+    output.push_str(&theme.border_run(
+        HorizontalLine,
+        width.saturating_sub(5 + status.chars().count()),
+    ));
+    output.push_str(&theme.border(&[LowerRightCorner]));
+    output
+}
+
+/// Redraw just the status bar (once a second, when what it shows changes)
+pub fn redraw_status_bar(theme: Theme, status: Option<&str>, width: usize) -> String {
+    if status_row().is_none() {
+        return String::new();
+    }
+    let mut output = String::new();
+    output.push_str(esc::SAVE_CURSOR);
+    output.push_str(&draw_status_bar(theme, status, width));
+    output.push_str(esc::RESTORE_CURSOR);
+    output
+}
+
 /// Calculate the maximum input length based on prompt size
 pub fn max_input_length(client_name: &str, width: usize) -> usize {
     let prompt = format!("[{}] ", client_name);
@@ -206,19 +305,24 @@ pub fn max_input_length(client_name: &str, width: usize) -> usize {
     // First row: content width minus prompt
     let first_row_capacity = input_content_width - prompt_len;
     // Subsequent rows: full content width
-    let other_rows_capacity = input_content_width * (INPUT_ROWS - 1);
+    let other_rows_capacity = input_content_width * (input_rows() - 1);
 
     first_row_capacity + other_rows_capacity
 }
 
 /// Initialize the split-screen UI with borders and tab support
+#[allow(clippy::too_many_arguments)]
 pub fn init_split_screen_with_tabs(
+    theme: Theme,
     client_name: &str,
     active_tab: Tab,
     gemini_available: bool,
     tunes_available: bool,
     active_call: Option<&str>,
+    unread: Unread,
     call_status: Option<&str>,
+    topic: Option<&str>,
+    status_bar: Option<&str>,
     width: usize,
 ) -> String {
     use DecGraphicsChar::*;
@@ -232,88 +336,68 @@ pub fn init_split_screen_with_tabs(
 
     // Row 1: Top border with tabs
     output.push_str(&draw_tab_bar(
+        theme,
         active_tab,
         gemini_available,
         tunes_available,
         active_call,
+        unread,
         width,
     ));
 
-    if active_tab == Tab::Call || active_tab == Tab::Tunes {
-        // Draw full box for Call/Tunes (no split)
-        // Rows 2-23: Left and right borders
-        for row in 2..=23 {
+    if !active_tab.has_input() {
+        // Draw full box for Call/Tunes/Stats (no split)
+        // Rows 2-23 (by default): Left and right borders
+        for row in CHAT_REGION_START..=region_end() {
             output.push_str(&esc::cursor_to(row, 1));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
             output.push_str(&esc::cursor_to(row, width));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
         }
 
-        // Row 24: Bottom border
-        output.push_str(&esc::cursor_to(24, 1));
-        output.push_str(&draw_horizontal_line(
-            LowerLeftCorner,
-            LowerRightCorner,
-            width,
-        ));
+        // Row 24: Bottom border and status bar
+        output.push_str(&draw_status_bar(theme, status_bar, width));
 
         // Draw status message if provided
         if let Some(status) = call_status {
-            output.push_str(&esc::cursor_to(23, 3)); // Inside the box
+            output.push_str(&esc::cursor_to(region_end(), 3)); // Inside the box
             output.push_str(status);
         }
 
         // Hide cursor
         output.push_str(esc::CURSOR_HIDE);
     } else {
-        // Rows 2-19: Left and right borders for chat area
-        for row in CHAT_REGION_START..=CHAT_REGION_END {
+        // Rows 2-20: Left and right borders for chat area
+        for row in CHAT_REGION_START..=chat_region_end() {
             output.push_str(&esc::cursor_to(row, 1));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
             output.push_str(&esc::cursor_to(row, width));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
         }
 
-        // Row 21: Separator ├────────────────────┤
-        output.push_str(&esc::cursor_to(21, 1));
-        output.push_str(&draw_horizontal_line(LeftTee, RightTee, width));
+        // Row 21 (by default): Separator ├─ Topic ─────────────┤
+        let topic = topic.filter(|_| active_tab == Tab::Chat);
+        output.push_str(&draw_separator(theme, topic, None, width));
 
-        // Rows 21-23: Input area borders
-        for row in INPUT_ROW_START..=INPUT_ROW_END {
+        // Rows 22-23 (or more): Input area borders
+        for row in input_row_start()..=input_row_end() {
             output.push_str(&esc::cursor_to(row, 1));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
             output.push_str(&esc::cursor_to(row, width));
-            output.push_str(ENTER_DEC_GRAPHICS);
-            output.push(VerticalLine.as_dec_char());
-            output.push_str(EXIT_DEC_GRAPHICS);
+            output.push_str(&theme.border(&[VerticalLine]));
         }
 
         // Draw prompt on first input row
-        output.push_str(&esc::cursor_to(INPUT_ROW_START, 2));
+        output.push_str(&esc::cursor_to(input_row_start(), 2));
         output.push_str(&prompt);
 
-        // Row 24: Bottom border └────────────────────┘
-        output.push_str(&esc::cursor_to(24, 1));
-        output.push_str(&draw_horizontal_line(
-            LowerLeftCorner,
-            LowerRightCorner,
-            width,
-        ));
+        // Row 24: Bottom border └─ 03:45PM | 2 peers ───────┘
+        output.push_str(&draw_status_bar(theme, status_bar, width));
 
         // No scroll region - we manage scrolling ourselves via ChatBuffer
 
         // Position cursor at input area (after prompt)
-        output.push_str(&esc::cursor_to(INPUT_ROW_START, 2 + prompt.len()));
+        output.push_str(&esc::cursor_to(input_row_start(), 2 + prompt.len()));
 
         // Show cursor
         output.push_str(esc::CURSOR_SHOW);
@@ -323,7 +407,13 @@ pub fn init_split_screen_with_tabs(
 }
 
 /// Redraw the input line with current buffer content and cursor position
-pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: usize) -> String {
+pub fn redraw_input(
+    theme: Theme,
+    client_name: &str,
+    buffer: &str,
+    cursor_pos: usize,
+    width: usize,
+) -> String {
     use DecGraphicsChar::VerticalLine;
 
     let prompt = format!("[{}] ", client_name);
@@ -349,7 +439,7 @@ pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: u
     remaining = &remaining[split_idx..];
 
     // Subsequent rows get full width
-    for _ in 1..INPUT_ROWS {
+    for _ in 1..input_rows() {
         if remaining.is_empty() {
             row_contents.push("");
         } else {
@@ -361,13 +451,11 @@ pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: u
 
     // Draw each input row
     for (i, content) in row_contents.iter().enumerate() {
-        let row = INPUT_ROW_START + i;
+        let row = input_row_start() + i;
 
         // Move to row, draw left border
         output.push_str(&esc::cursor_to(row, 1));
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(VerticalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
+        output.push_str(&theme.border(&[VerticalLine]));
 
         // First row has prompt
         if i == 0 {
@@ -389,16 +477,14 @@ pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: u
 
         // Draw right border
         output.push(' ');
-        output.push_str(ENTER_DEC_GRAPHICS);
-        output.push(VerticalLine.as_dec_char());
-        output.push_str(EXIT_DEC_GRAPHICS);
+        output.push_str(&theme.border(&[VerticalLine]));
     }
 
     // Calculate cursor position
     // cursor_pos is index in buffer (0 to buffer.len())
     let (cursor_row, cursor_col) = if cursor_pos <= first_row_capacity {
         // Cursor on first row
-        (INPUT_ROW_START, 2 + prompt_len + cursor_pos)
+        (input_row_start(), 2 + prompt_len + cursor_pos)
     } else {
         // Calculate which row and column
         let chars_after_first = cursor_pos - first_row_capacity;
@@ -406,12 +492,12 @@ pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: u
         let mut col_in_row = chars_after_first % input_content_width;
 
         // Clamp to last row if we go past it (e.g. cursor at very end of full buffer)
-        if row_index >= INPUT_ROWS {
-            row_index = INPUT_ROWS - 1;
+        if row_index >= input_rows() {
+            row_index = input_rows() - 1;
             col_in_row = input_content_width;
         }
 
-        (INPUT_ROW_START + row_index, 2 + col_in_row)
+        (input_row_start() + row_index, 2 + col_in_row)
     };
 
     output.push_str(&esc::cursor_to(cursor_row, cursor_col));
@@ -423,6 +509,8 @@ pub fn redraw_input(client_name: &str, buffer: &str, cursor_pos: usize, width: u
 pub fn cleanup_split_screen(width: usize) -> String {
     let mut output = String::new();
     output.push_str(&esc::reset_scroll_region());
+    output.push_str(crate::paste::BRACKETED_PASTE_OFF);
+    output.push_str(crate::mouse::MOUSE_OFF);
     output.push_str(esc::CLEAR_SCREEN);
 
     let sad_mac = [
@@ -440,7 +528,7 @@ pub fn cleanup_split_screen(width: usize) -> String {
     ];
 
     let total_lines = sad_mac.len() + 2 + messages.len(); // +2 for spacing
-    let start_row = (TERMINAL_HEIGHT - total_lines) / 2;
+    let start_row = (screen_rows() - total_lines) / 2;
 
     for (i, line) in sad_mac.iter().enumerate() {
         let padding = (width - line.len()) / 2;
@@ -456,6 +544,6 @@ pub fn cleanup_split_screen(width: usize) -> String {
     }
 
     // Move cursor to bottom to be clean
-    output.push_str(&esc::cursor_to(TERMINAL_HEIGHT, 1));
+    output.push_str(&esc::cursor_to(screen_rows(), 1));
     output
 }
@@ -0,0 +1,154 @@
+//! A synthetic video source, for trying calls without a camera.
+//!
+//! With `device = test` under `[webcam]`, frames come from here instead of a
+//! device: colour bars (a gray ramp once rendered), a ball bouncing along the
+//! strip below them and the time of day, so movement, delta coding,
+//! fragmentation and every renderer can be checked end to end. Calling
+//! yourself shows the pattern straight back.
+
+use std::time::Duration;
+
+use chrono::Local;
+use image::{Rgb, RgbImage};
+
+/// The `device` value that picks the test pattern
+pub const TEST_DEVICE: &str = "test";
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 360;
+
+/// SMPTE-style bars, left to right
+const BARS: [[u8; 3]; 7] = [
+    [192, 192, 192],
+    [192, 192, 0],
+    [0, 192, 192],
+    [0, 192, 0],
+    [192, 0, 192],
+    [192, 0, 0],
+    [0, 0, 192],
+];
+
+/// Digits and a colon in a 3x5 font, one row per byte (bit 2 is the left)
+const GLYPHS: [[u8; 5]; 11] = [
+    [7, 5, 5, 5, 7],
+    [2, 6, 2, 2, 7],
+    [7, 1, 7, 4, 7],
+    [7, 1, 7, 1, 7],
+    [5, 5, 7, 1, 1],
+    [7, 4, 7, 1, 7],
+    [7, 4, 7, 5, 7],
+    [7, 1, 1, 1, 1],
+    [7, 5, 7, 5, 7],
+    [7, 5, 7, 1, 7],
+    [0, 2, 0, 2, 0],
+];
+
+/// Size of a font pixel
+const SCALE: u32 = 6;
+
+const BALL_RADIUS: u32 = 24;
+/// How far the ball moves each second
+const BALL_SPEED: f32 = 240.0;
+
+/// Whether `device` names the test pattern
+pub fn is_test_device(device: &str) -> bool {
+    device.eq_ignore_ascii_case(TEST_DEVICE)
+}
+
+/// The pattern `elapsed` into the stream
+pub fn frame(elapsed: Duration) -> RgbImage {
+    let bars_height = HEIGHT * 2 / 3;
+    let mut image = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        if y < bars_height {
+            Rgb(BARS[(x * BARS.len() as u32 / WIDTH) as usize])
+        } else {
+            Rgb([16, 16, 16])
+        }
+    });
+
+    // Back and forth along the strip under the bars
+    let travel = (WIDTH - 2 * BALL_RADIUS) as f32;
+    let along = (elapsed.as_secs_f32() * BALL_SPEED) % (2.0 * travel);
+    let offset = if along < travel {
+        along
+    } else {
+        2.0 * travel - along
+    };
+    let centre_x = BALL_RADIUS as f32 + offset;
+    let centre_y = (bars_height + HEIGHT) as f32 / 2.0;
+    for y in bars_height..HEIGHT {
+        for x in 0..WIDTH {
+            let (dx, dy) = (x as f32 - centre_x, y as f32 - centre_y);
+            if dx * dx + dy * dy <= (BALL_RADIUS * BALL_RADIUS) as f32 {
+                image.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+    }
+
+    draw_text(&mut image, &Local::now().format("%H:%M:%S").to_string());
+    image
+}
+
+/// Write `text` (digits and colons) in black on white, top left
+fn draw_text(image: &mut RgbImage, text: &str) {
+    let glyphs: Vec<&[u8; 5]> = text
+        .chars()
+        .filter_map(|c| match c {
+            '0'..='9' => Some(&GLYPHS[c as usize - '0' as usize]),
+            ':' => Some(&GLYPHS[10]),
+            _ => None,
+        })
+        .collect();
+    let (left, top) = (SCALE * 2, SCALE * 2);
+    let box_width = glyphs.len() as u32 * 4 * SCALE + SCALE;
+    for y in top - SCALE..top + 6 * SCALE {
+        for x in left - SCALE..(left + box_width).min(WIDTH) {
+            image.put_pixel(x, y, Rgb([255, 255, 255]));
+        }
+    }
+    for (i, glyph) in glyphs.iter().enumerate() {
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (4 >> col) == 0 {
+                    continue;
+                }
+                let x0 = left + (i as u32 * 4 + col) * SCALE;
+                let y0 = top + row as u32 * SCALE;
+                for y in y0..y0 + SCALE {
+                    for x in x0..(x0 + SCALE).min(WIDTH) {
+                        image.put_pixel(x, y, Rgb([0, 0, 0]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_moves() {
+        assert!(is_test_device("Test"));
+        assert!(!is_test_device("/dev/video0"));
+
+        let first = frame(Duration::ZERO);
+        let later = frame(Duration::from_millis(500));
+        assert_eq!(first.dimensions(), (WIDTH, HEIGHT));
+        // The bars stay put; the ball doesn't
+        let bar_row = HEIGHT / 2;
+        for x in 0..WIDTH {
+            assert_eq!(first.get_pixel(x, bar_row), later.get_pixel(x, bar_row));
+        }
+        let ball_row = HEIGHT * 5 / 6;
+        assert_ne!(
+            (0..WIDTH)
+                .map(|x| first.get_pixel(x, ball_row))
+                .collect::<Vec<_>>(),
+            (0..WIDTH)
+                .map(|x| later.get_pixel(x, ball_row))
+                .collect::<Vec<_>>()
+        );
+    }
+}
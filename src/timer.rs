@@ -0,0 +1,224 @@
+//! Countdown timers and pomodoro sessions (`/timer`).
+//!
+//! A timer counts down on the separator row and rings the bell when it's
+//! done. Pomodoro mode alternates 25 minutes of work with 5-minute breaks
+//! until it's stopped. A shared timer is started on every peer online from
+//! the same start time, so everyone's breaks begin together: each node works
+//! out the current phase from the time since the start rather than waiting
+//! for the previous phase to end.
+
+use std::time::{Duration, Instant};
+
+/// Pomodoro work period
+pub const POMODORO_WORK: Duration = Duration::from_secs(25 * 60);
+
+/// Pomodoro break
+pub const POMODORO_BREAK: Duration = Duration::from_secs(5 * 60);
+
+/// Longest timer accepted (a day)
+const MAX_TIMER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a timer counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerKind {
+    Countdown(Duration),
+    /// Work and breaks, repeating
+    Pomodoro,
+}
+
+/// Something that happened to a running timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// The countdown reached zero
+    Done,
+    /// A pomodoro work period or break began
+    WorkStarted,
+    BreakStarted,
+}
+
+/// A running timer
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub label: String,
+    pub kind: TimerKind,
+    pub started_at: Instant,
+    /// Who started it for everyone (None = a timer of our own)
+    pub shared_by: Option<String>,
+    /// Pomodoro phases begun so far (even = work, odd = break)
+    phase: u64,
+}
+
+impl Timer {
+    pub fn new(
+        label: &str,
+        kind: TimerKind,
+        started_at: Instant,
+        shared_by: Option<String>,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            kind,
+            started_at,
+            shared_by,
+            phase: 0,
+        }
+    }
+
+    /// Check for the end of the countdown or of a pomodoro phase. Phases
+    /// skipped while we weren't looking are reported once, as the current one.
+    pub fn tick(&mut self, now: Instant) -> Option<TimerEvent> {
+        let elapsed = now.saturating_duration_since(self.started_at);
+        match self.kind {
+            TimerKind::Countdown(length) => (elapsed >= length).then_some(TimerEvent::Done),
+            TimerKind::Pomodoro => {
+                let phase = pomodoro_phase(elapsed).0;
+                if phase == self.phase {
+                    return None;
+                }
+                self.phase = phase;
+                Some(if phase.is_multiple_of(2) {
+                    TimerEvent::WorkStarted
+                } else {
+                    TimerEvent::BreakStarted
+                })
+            }
+        }
+    }
+
+    /// What the separator row shows: "Tea 04:59" or "Pomodoro work 24:59"
+    pub fn display(&self, now: Instant) -> String {
+        let elapsed = now.saturating_duration_since(self.started_at);
+        let (name, remaining) = match self.kind {
+            TimerKind::Countdown(length) => {
+                let name = if self.label.is_empty() {
+                    "Timer".to_string()
+                } else {
+                    self.label.clone()
+                };
+                (name, length.saturating_sub(elapsed))
+            }
+            TimerKind::Pomodoro => {
+                let (phase, remaining) = pomodoro_phase(elapsed);
+                let name = if self.label.is_empty() {
+                    "Pomodoro"
+                } else {
+                    &self.label
+                };
+                let part = if phase.is_multiple_of(2) {
+                    "work"
+                } else {
+                    "break"
+                };
+                (format!("{} {}", name, part), remaining)
+            }
+        };
+        format!("{} {}", name, format_remaining(remaining))
+    }
+}
+
+/// The pomodoro phase `elapsed` falls in (even = work, odd = break) and the
+/// time left in it
+fn pomodoro_phase(elapsed: Duration) -> (u64, Duration) {
+    let cycle = POMODORO_WORK + POMODORO_BREAK;
+    let cycles = (elapsed.as_secs() / cycle.as_secs()) as u32;
+    let into_cycle = elapsed - cycle * cycles;
+    if into_cycle < POMODORO_WORK {
+        (cycles as u64 * 2, POMODORO_WORK - into_cycle)
+    } else {
+        (cycles as u64 * 2 + 1, cycle - into_cycle)
+    }
+}
+
+/// "04:59", or "1:04:59" past an hour (rounded up, so a timer shows 00:00
+/// only when it's done)
+fn format_remaining(remaining: Duration) -> String {
+    let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Parse a timer length: "25m", "90s", "1h30m", or a bare number of minutes
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    if let Ok(minutes) = text.parse::<u64>() {
+        return Some(Duration::from_secs(minutes.checked_mul(60)?)).filter(valid);
+    }
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total)).filter(valid)
+}
+
+fn valid(length: &Duration) -> bool {
+    !length.is_zero() && *length <= MAX_TIMER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("25m"), Some(Duration::from_secs(1500)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("10"), Some(Duration::from_secs(600)));
+        for bad in ["", "0", "m", "5x", "25m3", "48h"] {
+            assert_eq!(parse_duration(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_countdown_and_pomodoro() {
+        let start = Instant::now();
+        let mut timer = Timer::new(
+            "Tea",
+            TimerKind::Countdown(Duration::from_secs(240)),
+            start,
+            None,
+        );
+        assert_eq!(
+            timer.display(start + Duration::from_millis(500)),
+            "Tea 04:00"
+        );
+        assert_eq!(timer.tick(start + Duration::from_secs(239)), None);
+        assert_eq!(
+            timer.tick(start + Duration::from_secs(240)),
+            Some(TimerEvent::Done)
+        );
+
+        let mut pomodoro = Timer::new("", TimerKind::Pomodoro, start, None);
+        assert_eq!(pomodoro.tick(start), None);
+        assert_eq!(pomodoro.display(start), "Pomodoro work 25:00");
+        let on_break = start + POMODORO_WORK + Duration::from_secs(1);
+        assert_eq!(pomodoro.tick(on_break), Some(TimerEvent::BreakStarted));
+        assert_eq!(pomodoro.tick(on_break), None);
+        assert_eq!(pomodoro.display(on_break), "Pomodoro break 04:59");
+        let second_cycle = start + POMODORO_WORK + POMODORO_BREAK;
+        assert_eq!(pomodoro.tick(second_cycle), Some(TimerEvent::WorkStarted));
+        // A shared session started earlier picks up mid-phase
+        let joined = Timer::new("", TimerKind::Pomodoro, start, Some("alice".to_string()));
+        assert_eq!(
+            joined.display(second_cycle + Duration::from_secs(3600)),
+            "Pomodoro work 25:00"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Todo tab: the to-do list shared with peers.
+
+use crate::network::{TodoItem, TodoList, TodoOp};
+use crate::terminal::{esc, layout};
+
+/// Todo display area bounds (full box, rows 2-23 in the standard layout)
+const TODO_REGION_START: usize = 2;
+
+/// Visible lines for the list (minus 1 for status line at bottom)
+fn visible_lines() -> usize {
+    layout::region_end() - TODO_REGION_START
+}
+
+/// State for the Todo tab
+pub struct TodoState {
+    /// The shared list
+    list: TodoList,
+    /// Currently selected index
+    selected: usize,
+    /// Scroll offset for display
+    scroll_offset: usize,
+    /// Terminal width
+    width: usize,
+}
+
+impl TodoState {
+    pub fn new(list: TodoList, width: usize) -> Self {
+        Self {
+            list,
+            selected: 0,
+            scroll_offset: 0,
+            width,
+        }
+    }
+
+    /// Items on the list, oldest first
+    pub fn items(&self) -> Vec<&TodoItem> {
+        self.list.items()
+    }
+
+    /// The whole list as operations, for a peer that joins
+    pub fn ops(&self) -> Vec<TodoOp> {
+        self.list.ops()
+    }
+
+    /// Apply changes from us or a peer. Returns true if the list changed.
+    pub fn apply(&mut self, ops: &[TodoOp]) -> bool {
+        if !self.list.apply(ops) {
+            return false;
+        }
+        // Keep the selection on the list
+        let len = self.list.items().len();
+        self.selected = self.selected.min(len.saturating_sub(1));
+        self.ensure_visible();
+        true
+    }
+
+    /// Move selection up
+    pub fn move_up(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+            self.ensure_visible();
+        }
+    }
+
+    /// Move selection down
+    pub fn move_down(&mut self) {
+        if self.selected + 1 < self.list.items().len() {
+            self.selected += 1;
+            self.ensure_visible();
+        }
+    }
+
+    /// Page up
+    pub fn page_up(&mut self) {
+        self.selected = self.selected.saturating_sub(visible_lines());
+        self.ensure_visible();
+    }
+
+    /// Page down
+    pub fn page_down(&mut self) {
+        let len = self.list.items().len();
+        if len > 0 {
+            self.selected = (self.selected + visible_lines()).min(len - 1);
+            self.ensure_visible();
+        }
+    }
+
+    /// Ensure selected item is visible
+    fn ensure_visible(&mut self) {
+        if self.selected < self.scroll_offset {
+            self.scroll_offset = self.selected;
+        } else if self.selected >= self.scroll_offset + visible_lines() {
+            self.scroll_offset = self.selected - visible_lines() + 1;
+        }
+    }
+
+    /// The selected item
+    pub fn selected_item(&self) -> Option<&TodoItem> {
+        self.list.items().get(self.selected).copied()
+    }
+
+    /// Render the list to terminal output
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        // Content area: column 2 to column (width-1), leaving column 1 and width for borders
+        let content_width = self.width - 2;
+        let items = self.list.items();
+
+        for i in 0..visible_lines() {
+            output.push_str(&esc::cursor_to(TODO_REGION_START + i, 2));
+
+            let idx = self.scroll_offset + i;
+            let line = match items.get(idx) {
+                Some(item) => {
+                    let check = if item.done { 'x' } else { ' ' };
+                    let by = format!("  ({})", item.added_by);
+                    let max_text = content_width.saturating_sub(7 + by.chars().count());
+                    let text: String = if item.text.chars().count() > max_text {
+                        let truncated: String =
+                            item.text.chars().take(max_text.saturating_sub(3)).collect();
+                        format!("{}...", truncated)
+                    } else {
+                        item.text.clone()
+                    };
+                    format!(" [{}] {}{}", check, text, by)
+                }
+                None => String::new(),
+            };
+            let padlen = content_width.saturating_sub(line.chars().count());
+            if idx == self.selected && idx < items.len() {
+                // Highlight selected item
+                output.push_str(esc::REVERSE);
+                output.push_str(&line);
+                output.push_str(&" ".repeat(padlen));
+                output.push_str(esc::RESET_ATTRS);
+            } else {
+                output.push_str(&line);
+                output.push_str(&" ".repeat(padlen));
+            }
+        }
+
+        // Show status line at bottom
+        let status = if items.is_empty() {
+            "(Nothing to do - add items with /todo add <text> in Chat)".to_string()
+        } else {
+            let done = items.iter().filter(|item| item.done).count();
+            format!(
+                " {}/{} | {} done | Check off <Enter> | Remove checked <Ctrl+C>",
+                self.selected + 1,
+                items.len(),
+                done
+            )
+        };
+        let status: String = status.chars().take(content_width).collect();
+        output.push_str(&esc::cursor_to(layout::region_end(), 2));
+        output.push_str("\x1b[2m"); // Dim attribute
+        output.push_str(&status);
+        output.push_str(&" ".repeat(content_width.saturating_sub(status.chars().count())));
+        output.push_str(esc::RESET_ATTRS);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_follows_the_list() {
+        let mut todo = TodoState::new(TodoList::default(), 80);
+        assert!(todo.render().contains("Nothing to do"));
+        let ops: Vec<_> = (0..3)
+            .map(|id| TodoOp::Add {
+                id,
+                text: format!("item {}", id),
+                by: "alice".to_string(),
+                at: id as i64,
+            })
+            .collect();
+        assert!(todo.apply(&ops));
+        todo.move_down();
+        todo.move_down();
+        todo.move_down();
+        assert_eq!(todo.selected_item().unwrap().text, "item 2");
+        assert!(todo.render().contains(" [ ] item 2  (alice)"));
+
+        // Removing the selected last item moves the selection up
+        todo.apply(&[TodoOp::Remove {
+            id: 2,
+            by: "bob".to_string(),
+            at: 9,
+        }]);
+        assert_eq!(todo.selected_item().unwrap().text, "item 1");
+        assert!(todo.render().contains(" 2/2 | 0 done"));
+    }
+}
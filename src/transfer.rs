@@ -0,0 +1,629 @@
+//! File transfer to the terminal's host over the serial link.
+//!
+//! When the "terminal" is really a vintage computer running a terminal program,
+//! files can be pushed to it with one of the classic serial file transfer
+//! protocols. The user starts a receive on their side, and we act as the sender.
+//!
+//! Supported protocols:
+//! - XMODEM (128-byte blocks, CRC-16 or 8-bit checksum, chosen by the receiver)
+//! - Kermit (basic send: type 1 block check, control prefixing, no windowing)
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the receiver to start before giving up
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for a response to a single block/packet
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of retransmissions for a single block/packet
+const MAX_RETRIES: u32 = 10;
+
+// XMODEM control characters
+const SOH: u8 = 0x01;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// CP/M end-of-file marker used to pad the final block
+const CPMEOF: u8 = 0x1A;
+/// Receiver's request for CRC-16 mode
+const CRC_REQUEST: u8 = b'C';
+/// XMODEM block payload size
+const XMODEM_BLOCK_SIZE: usize = 128;
+
+/// File transfer protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Xmodem,
+    Kermit,
+}
+
+impl Protocol {
+    /// Parse a protocol name (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "xmodem" | "x" => Some(Protocol::Xmodem),
+            "kermit" | "k" => Some(Protocol::Kermit),
+            _ => None,
+        }
+    }
+
+    /// Display name for status messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Xmodem => "XMODEM",
+            Protocol::Kermit => "Kermit",
+        }
+    }
+}
+
+/// Error type for file transfers
+#[derive(Debug)]
+pub enum TransferError {
+    /// Reading the file or talking to the serial port failed
+    Io(io::Error),
+    /// The receiver never started, or stopped responding
+    Timeout,
+    /// The receiver cancelled the transfer
+    Cancelled,
+    /// A block was rejected too many times
+    TooManyRetries,
+    /// The receiver reported an error (Kermit 'E' packet)
+    Remote(String),
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::Io(e) => write!(f, "I/O error: {}", e),
+            TransferError::Timeout => write!(f, "timed out waiting for receiver"),
+            TransferError::Cancelled => write!(f, "cancelled by receiver"),
+            TransferError::TooManyRetries => write!(f, "too many retries"),
+            TransferError::Remote(msg) => write!(f, "receiver error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+impl From<io::Error> for TransferError {
+    fn from(e: io::Error) -> Self {
+        TransferError::Io(e)
+    }
+}
+
+/// Parse `/download` arguments: an optional protocol name followed by a file path.
+/// Defaults to XMODEM when no protocol is given.
+pub fn parse_download_args(args: &str) -> (Protocol, &str) {
+    let args = args.trim();
+    if let Some((first, rest)) = args.split_once(' ')
+        && let Some(protocol) = Protocol::from_name(first)
+    {
+        return (protocol, rest.trim());
+    }
+    (Protocol::Xmodem, args)
+}
+
+/// Send a file from disk using the given protocol.
+/// Returns the number of file bytes sent.
+pub fn send_file<P: Read + Write>(
+    port: &mut P,
+    protocol: Protocol,
+    path: &Path,
+) -> Result<usize, TransferError> {
+    let data = std::fs::read(path)?;
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "FILE".to_string());
+
+    match protocol {
+        Protocol::Xmodem => xmodem_send(port, &data, START_TIMEOUT)?,
+        Protocol::Kermit => kermit_send(port, &filename, &data)?,
+    }
+    Ok(data.len())
+}
+
+/// Read a single byte, waiting up to `timeout`.
+/// Returns None on timeout. Serial read timeouts are treated as "no data yet".
+fn read_byte<P: Read>(port: &mut P, timeout: Duration) -> io::Result<Option<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1];
+
+    loop {
+        match port.read(&mut buf) {
+            Ok(1) => return Ok(Some(buf[0])),
+            Ok(_) => std::thread::sleep(Duration::from_millis(1)),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+    }
+}
+
+/// Compute the CRC-16/XMODEM checksum (polynomial 0x1021, initial value 0)
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Build a single XMODEM block (SOH, block number, complement, payload, check)
+fn xmodem_block(block_num: u8, payload: &[u8], use_crc: bool) -> Vec<u8> {
+    let mut data = [CPMEOF; XMODEM_BLOCK_SIZE];
+    data[..payload.len()].copy_from_slice(payload);
+
+    let mut block = Vec::with_capacity(XMODEM_BLOCK_SIZE + 5);
+    block.push(SOH);
+    block.push(block_num);
+    block.push(!block_num);
+    block.extend_from_slice(&data);
+
+    if use_crc {
+        block.extend_from_slice(&crc16_xmodem(&data).to_be_bytes());
+    } else {
+        let checksum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        block.push(checksum);
+    }
+    block
+}
+
+/// Send data with XMODEM. The receiver picks CRC ('C') or checksum (NAK) mode.
+fn xmodem_send<P: Read + Write>(
+    port: &mut P,
+    data: &[u8],
+    start_timeout: Duration,
+) -> Result<(), TransferError> {
+    // Wait for the receiver to start
+    let deadline = Instant::now() + start_timeout;
+    let use_crc = loop {
+        match read_byte(port, Duration::from_secs(1))? {
+            Some(CRC_REQUEST) => break true,
+            Some(NAK) => break false,
+            Some(CAN) => return Err(TransferError::Cancelled),
+            _ => {
+                if Instant::now() >= deadline {
+                    return Err(TransferError::Timeout);
+                }
+            }
+        }
+    };
+
+    // Send each block, retransmitting on NAK
+    let mut block_num: u8 = 1;
+    for chunk in data.chunks(XMODEM_BLOCK_SIZE) {
+        let block = xmodem_block(block_num, chunk, use_crc);
+        xmodem_send_with_ack(port, &block)?;
+        block_num = block_num.wrapping_add(1);
+    }
+
+    // Signal end of transmission
+    xmodem_send_with_ack(port, &[EOT])
+}
+
+/// Send a block and wait for ACK, retrying on NAK or timeout
+fn xmodem_send_with_ack<P: Read + Write>(port: &mut P, block: &[u8]) -> Result<(), TransferError> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(block)?;
+        port.flush()?;
+
+        match read_byte(port, BLOCK_TIMEOUT)? {
+            Some(ACK) => return Ok(()),
+            // Two CANs in a row cancel the transfer
+            Some(CAN) if read_byte(port, Duration::from_secs(1))? == Some(CAN) => {
+                return Err(TransferError::Cancelled);
+            }
+            // NAK, noise or timeout - resend
+            _ => {}
+        }
+    }
+
+    // Tell the receiver we're giving up
+    let _ = port.write_all(&[CAN, CAN, CAN]);
+    Err(TransferError::TooManyRetries)
+}
+
+// Kermit protocol
+
+/// Kermit packet start marker
+const KERMIT_MARK: u8 = SOH;
+/// Packet terminator we ask the receiver to use, and that we send
+const KERMIT_EOL: u8 = b'\r';
+/// Control prefix character
+const KERMIT_QCTL: u8 = b'#';
+/// Maximum packet length we'll send (the protocol maximum for short packets)
+const KERMIT_MAXL: u8 = 94;
+/// Timeout (seconds) we ask the receiver to use
+const KERMIT_TIME: u8 = 10;
+
+/// Encode a small number as a printable Kermit character
+fn tochar(x: u8) -> u8 {
+    x + 32
+}
+
+/// Decode a printable Kermit character back to a number
+fn unchar(c: u8) -> u8 {
+    c.wrapping_sub(32)
+}
+
+/// Toggle the control bit of a character (used for control prefixing)
+fn ctl(c: u8) -> u8 {
+    c ^ 64
+}
+
+/// Type 1 (single character) block check over the LEN..DATA fields
+fn kermit_check(bytes: &[u8]) -> u8 {
+    let sum: u32 = bytes.iter().map(|&b| b as u32).sum();
+    tochar(((sum + ((sum & 192) / 64)) & 63) as u8)
+}
+
+/// Build a complete Kermit packet
+fn kermit_packet(seq: u8, packet_type: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(data.len() + 6);
+    packet.push(KERMIT_MARK);
+    // LEN counts SEQ, TYPE, DATA and CHECK
+    packet.push(tochar((data.len() + 3) as u8));
+    packet.push(tochar(seq % 64));
+    packet.push(packet_type);
+    packet.extend_from_slice(data);
+    let check = kermit_check(&packet[1..]);
+    packet.push(check);
+    packet.push(KERMIT_EOL);
+    packet
+}
+
+/// Encode one file byte with control prefixing
+fn kermit_encode_byte(byte: u8, out: &mut Vec<u8>) {
+    let low = byte & 0x7F;
+    if low < 32 || low == 127 {
+        out.push(KERMIT_QCTL);
+        out.push(ctl(byte));
+    } else if low == KERMIT_QCTL {
+        out.push(KERMIT_QCTL);
+        out.push(byte);
+    } else {
+        out.push(byte);
+    }
+}
+
+/// Split file data into encoded packet payloads of at most `max_len` bytes,
+/// never splitting a prefixed pair across packets
+fn kermit_encode_data(data: &[u8], max_len: usize) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    let mut current = Vec::with_capacity(max_len);
+    let mut encoded = Vec::with_capacity(2);
+
+    for &byte in data {
+        encoded.clear();
+        kermit_encode_byte(byte, &mut encoded);
+        if current.len() + encoded.len() > max_len {
+            payloads.push(std::mem::take(&mut current));
+        }
+        current.extend_from_slice(&encoded);
+    }
+    if !current.is_empty() {
+        payloads.push(current);
+    }
+    payloads
+}
+
+/// A packet received from the Kermit receiver
+struct KermitReply {
+    seq: u8,
+    packet_type: u8,
+    data: Vec<u8>,
+}
+
+/// Read one packet from the receiver. Returns None on timeout or a bad checksum.
+fn kermit_read_packet<P: Read>(
+    port: &mut P,
+    timeout: Duration,
+) -> Result<Option<KermitReply>, TransferError> {
+    let deadline = Instant::now() + timeout;
+
+    // Skip until a packet start
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match read_byte(port, remaining)? {
+            Some(KERMIT_MARK) => break,
+            Some(_) => continue,
+            None => return Ok(None),
+        }
+    }
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let Some(len_char) = read_byte(port, remaining)? else {
+        return Ok(None);
+    };
+    let len = unchar(len_char) as usize;
+    if !(3..=94).contains(&len) {
+        return Ok(None);
+    }
+
+    let mut body = Vec::with_capacity(len);
+    while body.len() < len {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match read_byte(port, remaining)? {
+            Some(b) => body.push(b),
+            None => return Ok(None),
+        }
+    }
+
+    // Verify the block check (LEN + everything but the check itself)
+    let mut checked = Vec::with_capacity(len);
+    checked.push(len_char);
+    checked.extend_from_slice(&body[..len - 1]);
+    if kermit_check(&checked) != body[len - 1] {
+        return Ok(None);
+    }
+
+    Ok(Some(KermitReply {
+        seq: unchar(body[0]),
+        packet_type: body[1],
+        data: body[2..len - 1].to_vec(),
+    }))
+}
+
+/// Send a packet and wait for a matching ACK. Returns the ACK's data field.
+fn kermit_exchange<P: Read + Write>(
+    port: &mut P,
+    seq: u8,
+    packet_type: u8,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, TransferError> {
+    let packet = kermit_packet(seq, packet_type, data);
+    let seq = seq % 64;
+
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&packet)?;
+        port.flush()?;
+
+        if let Some(reply) = kermit_read_packet(port, timeout)? {
+            match reply.packet_type {
+                b'Y' if reply.seq == seq => return Ok(reply.data),
+                // A NAK for the next packet implies an ACK for this one
+                b'N' if reply.seq == (seq + 1) % 64 => return Ok(Vec::new()),
+                b'E' => {
+                    return Err(TransferError::Remote(
+                        String::from_utf8_lossy(&reply.data).into_owned(),
+                    ));
+                }
+                // NAK or stale ACK - resend
+                _ => {}
+            }
+        }
+    }
+
+    // Tell the receiver we're giving up
+    let _ = port.write_all(&kermit_packet(seq, b'E', b"Too many retries"));
+    Err(TransferError::TooManyRetries)
+}
+
+/// Convert a filename to the conservative "normal form" (uppercase, one dot)
+fn kermit_filename(name: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut seen_dot = false;
+    for c in name.chars().rev() {
+        let c = c.to_ascii_uppercase();
+        if c == '.' && !seen_dot {
+            seen_dot = true;
+            out.push(b'.');
+        } else if c.is_ascii_alphanumeric() {
+            out.push(c as u8);
+        } else {
+            out.push(b'X');
+        }
+    }
+    out.reverse();
+    out
+}
+
+/// Send a file with Kermit: Send-Init, File-Header, Data..., EOF, Break
+fn kermit_send<P: Read + Write>(
+    port: &mut P,
+    filename: &str,
+    data: &[u8],
+) -> Result<(), TransferError> {
+    // Send-Init with our parameters: MAXL, TIME, NPAD, PADC, EOL, QCTL
+    let init = [
+        tochar(KERMIT_MAXL),
+        tochar(KERMIT_TIME),
+        tochar(0),
+        ctl(0),
+        tochar(KERMIT_EOL),
+        KERMIT_QCTL,
+    ];
+
+    // The receiver may take a while to be started by the user, so the
+    // Send-Init gets the long start timeout spread across its retries
+    let start_per_try = START_TIMEOUT / MAX_RETRIES;
+    let params = kermit_exchange(port, 0, b'S', &init, start_per_try)?;
+
+    // Honour the receiver's maximum packet length (first parameter)
+    let their_maxl = params
+        .first()
+        .map(|&c| unchar(c))
+        .filter(|&l| l >= 10)
+        .unwrap_or(80)
+        .min(KERMIT_MAXL);
+    let max_data = their_maxl as usize - 3;
+
+    let mut seq: u8 = 1;
+    let mut name = kermit_filename(filename);
+    name.truncate(max_data);
+    kermit_exchange(port, seq, b'F', &name, BLOCK_TIMEOUT)?;
+    seq = (seq + 1) % 64;
+
+    for payload in kermit_encode_data(data, max_data) {
+        kermit_exchange(port, seq, b'D', &payload, BLOCK_TIMEOUT)?;
+        seq = (seq + 1) % 64;
+    }
+
+    kermit_exchange(port, seq, b'Z', &[], BLOCK_TIMEOUT)?;
+    seq = (seq + 1) % 64;
+    kermit_exchange(port, seq, b'B', &[], BLOCK_TIMEOUT)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A scripted serial link: reads come from `input`, writes go to `output`
+    struct MockPort {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl MockPort {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: input.iter().copied().collect(),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.pop_front() {
+                Some(b) => {
+                    buf[0] = b;
+                    Ok(1)
+                }
+                None => Err(io::Error::new(io::ErrorKind::TimedOut, "no data")),
+            }
+        }
+    }
+
+    impl Write for MockPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_crc16_xmodem() {
+        assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+        assert_eq!(crc16_xmodem(&[]), 0);
+    }
+
+    #[test]
+    fn test_xmodem_block_padding() {
+        let block = xmodem_block(1, b"hi", false);
+        assert_eq!(block.len(), 3 + XMODEM_BLOCK_SIZE + 1);
+        assert_eq!(&block[..5], &[SOH, 1, 0xFE, b'h', b'i']);
+        assert!(block[5..3 + XMODEM_BLOCK_SIZE].iter().all(|&b| b == CPMEOF));
+    }
+
+    #[test]
+    fn test_xmodem_send_crc() {
+        // Receiver asks for CRC, ACKs two blocks and the EOT
+        let mut port = MockPort::new(&[CRC_REQUEST, ACK, ACK, ACK]);
+        let data = vec![0x55u8; 200];
+        xmodem_send(&mut port, &data, Duration::from_secs(1)).unwrap();
+
+        let block_len = 3 + XMODEM_BLOCK_SIZE + 2;
+        assert_eq!(port.output.len(), block_len * 2 + 1);
+        assert_eq!(port.output[block_len + 1], 2); // second block number
+        assert_eq!(*port.output.last().unwrap(), EOT);
+    }
+
+    #[test]
+    fn test_xmodem_resends_on_nak() {
+        let mut port = MockPort::new(&[NAK, NAK, ACK, ACK]);
+        xmodem_send(&mut port, b"data", Duration::from_secs(1)).unwrap();
+
+        // Checksum mode: block sent twice, then EOT
+        let block_len = 3 + XMODEM_BLOCK_SIZE + 1;
+        assert_eq!(port.output.len(), block_len * 2 + 1);
+    }
+
+    #[test]
+    fn test_xmodem_cancel() {
+        let mut port = MockPort::new(&[CRC_REQUEST, CAN, CAN]);
+        let result = xmodem_send(&mut port, b"data", Duration::from_secs(1));
+        assert!(matches!(result, Err(TransferError::Cancelled)));
+    }
+
+    #[test]
+    fn test_kermit_packet_check() {
+        // ACK for sequence 0 with no data: SOH, LEN, SEQ, TYPE, CHECK, EOL
+        let packet = kermit_packet(0, b'Y', &[]);
+        assert_eq!(packet, vec![SOH, b'#', b' ', b'Y', b'>', b'\r']);
+    }
+
+    #[test]
+    fn test_kermit_encode_data() {
+        let payloads = kermit_encode_data(b"A\r\n#", 4);
+        assert_eq!(payloads, vec![b"A#M".to_vec(), b"#J##".to_vec()]);
+    }
+
+    #[test]
+    fn test_kermit_send() {
+        // Script ACKs for S, F, D, Z, B (sequences 0-4)
+        let mut script = Vec::new();
+        for seq in 0..5 {
+            let ack = kermit_packet(seq, b'Y', &[]);
+            script.extend_from_slice(&ack);
+        }
+        let mut port = MockPort::new(&script);
+        kermit_send(&mut port, "notes.txt", b"hello").unwrap();
+
+        let sent = String::from_utf8_lossy(&port.output);
+        assert!(sent.contains("NOTES.TXT"));
+        assert!(sent.contains("hello"));
+    }
+
+    #[test]
+    fn test_kermit_remote_error() {
+        let script = kermit_packet(0, b'E', b"Disk full");
+        let mut port = MockPort::new(&script);
+        let result = kermit_send(&mut port, "a.txt", b"x");
+        assert!(matches!(result, Err(TransferError::Remote(msg)) if msg == "Disk full"));
+    }
+
+    #[test]
+    fn test_parse_download_args() {
+        assert_eq!(
+            parse_download_args("notes.txt"),
+            (Protocol::Xmodem, "notes.txt")
+        );
+        assert_eq!(
+            parse_download_args("kermit my file.txt"),
+            (Protocol::Kermit, "my file.txt")
+        );
+        assert_eq!(parse_download_args("  "), (Protocol::Xmodem, ""));
+    }
+
+    #[test]
+    fn test_protocol_from_name() {
+        assert_eq!(Protocol::from_name("XMODEM"), Some(Protocol::Xmodem));
+        assert_eq!(Protocol::from_name("kermit"), Some(Protocol::Kermit));
+        assert_eq!(Protocol::from_name("zmodem"), None);
+    }
+}
@@ -1,22 +1,26 @@
 //! Tunes tab: browse and play audio files from a directory.
 
+use std::cell::Cell;
 use std::fs::{self, File};
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
 
-use crate::terminal::esc;
+use crate::cover::{COVER_ROWS, find_cover, render_cover};
+use crate::graphics::Renderer;
+use crate::terminal::{esc, layout};
 
-/// Tunes display area bounds (full box, rows 2-23)
+/// Tunes display area bounds (full box, rows 2-23 in the standard layout)
 const TUNES_REGION_START: usize = 2;
-const TUNES_REGION_END: usize = 23;
 
 /// Visible lines for file listing (minus 1 for status line at bottom)
-const TUNES_VISIBLE_LINES: usize = TUNES_REGION_END - TUNES_REGION_START;
+fn visible_lines() -> usize {
+    layout::region_end() - TUNES_REGION_START
+}
 
 /// Supported audio file extensions
 const SUPPORTED_EXTENSIONS: &[&str] = &["wav", "mp3", "flac", "ogg"];
@@ -79,6 +83,12 @@ impl PlaybackTiming {
     }
 }
 
+/// Lock shared playback state. A panic in the playback thread poisons the
+/// locks it held, but what's behind them is still good.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Audio player that runs in a background thread
 pub struct AudioPlayer {
     /// Shared playback state
@@ -87,6 +97,8 @@ pub struct AudioPlayer {
     sink: Arc<Mutex<Option<Sink>>>,
     /// Playback timing info
     timing: Arc<Mutex<Option<PlaybackTiming>>>,
+    /// The thread watching for the end of the track
+    monitor: Mutex<Option<thread::JoinHandle<()>>>,
     /// Keep the stream alive (must not be dropped while playing)
     _stream: OutputStream,
     /// Stream handle for creating sinks
@@ -103,6 +115,7 @@ impl AudioPlayer {
             state: Arc::new(Mutex::new(PlaybackState::Stopped)),
             sink: Arc::new(Mutex::new(None)),
             timing: Arc::new(Mutex::new(None)),
+            monitor: Mutex::new(None),
             _stream: stream,
             stream_handle,
         })
@@ -135,39 +148,45 @@ impl AudioPlayer {
 
         // Update state
         {
-            let mut state = self.state.lock().unwrap();
+            let mut state = lock(&self.state);
             *state = PlaybackState::Playing(filename);
         }
 
         // Update timing
         {
-            let mut timing = self.timing.lock().unwrap();
+            let mut timing = lock(&self.timing);
             *timing = Some(PlaybackTiming::new(total_duration));
         }
 
         // Store sink for control
         {
-            let mut sink_guard = self.sink.lock().unwrap();
+            let mut sink_guard = lock(&self.sink);
             *sink_guard = Some(sink);
         }
 
-        // Start a thread to monitor playback completion
+        self.spawn_monitor();
+
+        Ok(())
+    }
+
+    /// Start a thread to monitor playback completion
+    fn spawn_monitor(&self) {
         let state_clone = Arc::clone(&self.state);
         let sink_clone = Arc::clone(&self.sink);
         let timing_clone = Arc::clone(&self.timing);
-        thread::spawn(move || {
+        let monitor = thread::spawn(move || {
             loop {
                 thread::sleep(std::time::Duration::from_millis(100));
 
-                let sink_guard = sink_clone.lock().unwrap();
+                let sink_guard = lock(&sink_clone);
                 if let Some(ref sink) = *sink_guard {
                     if sink.empty() {
                         drop(sink_guard);
-                        let mut state = state_clone.lock().unwrap();
+                        let mut state = lock(&state_clone);
                         *state = PlaybackState::Stopped;
-                        let mut timing = timing_clone.lock().unwrap();
+                        let mut timing = lock(&timing_clone);
                         *timing = None;
-                        let mut sink_guard = sink_clone.lock().unwrap();
+                        let mut sink_guard = lock(&sink_clone);
                         *sink_guard = None;
                         break;
                     }
@@ -176,47 +195,60 @@ impl AudioPlayer {
                 }
             }
         });
+        *lock(&self.monitor) = Some(monitor);
+    }
 
-        Ok(())
+    /// Whether the playback thread died partway through a track (it clears
+    /// the track when it finishes normally)
+    pub fn monitor_died(&self) -> bool {
+        lock(&self.monitor)
+            .as_ref()
+            .is_some_and(|monitor| monitor.is_finished())
+            && lock(&self.sink).is_some()
+    }
+
+    /// Start a new playback thread for the track that's playing
+    pub fn restart_monitor(&self) {
+        self.spawn_monitor();
     }
 
     /// Stop playback
     pub fn stop(&self) {
-        let mut sink_guard = self.sink.lock().unwrap();
+        let mut sink_guard = lock(&self.sink);
         if let Some(sink) = sink_guard.take() {
             sink.stop();
         }
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = lock(&self.state);
         *state = PlaybackState::Stopped;
 
-        let mut timing = self.timing.lock().unwrap();
+        let mut timing = lock(&self.timing);
         *timing = None;
     }
 
     /// Toggle pause/resume
     pub fn toggle_pause(&self) {
-        let sink_guard = self.sink.lock().unwrap();
+        let sink_guard = lock(&self.sink);
         if let Some(ref sink) = *sink_guard {
             if sink.is_paused() {
                 sink.play();
-                let mut state = self.state.lock().unwrap();
+                let mut state = lock(&self.state);
                 if let PlaybackState::Paused(filename) = state.clone() {
                     *state = PlaybackState::Playing(filename);
                 }
                 // Resume timing
-                let mut timing = self.timing.lock().unwrap();
+                let mut timing = lock(&self.timing);
                 if let Some(ref mut t) = *timing {
                     t.resume();
                 }
             } else {
                 sink.pause();
-                let mut state = self.state.lock().unwrap();
+                let mut state = lock(&self.state);
                 if let PlaybackState::Playing(filename) = state.clone() {
                     *state = PlaybackState::Paused(filename);
                 }
                 // Pause timing
-                let mut timing = self.timing.lock().unwrap();
+                let mut timing = lock(&self.timing);
                 if let Some(ref mut t) = *timing {
                     t.pause();
                 }
@@ -226,12 +258,12 @@ impl AudioPlayer {
 
     /// Get current playback state
     pub fn state(&self) -> PlaybackState {
-        self.state.lock().unwrap().clone()
+        lock(&self.state).clone()
     }
 
     /// Get remaining playback time
     pub fn remaining_time(&self) -> Option<Duration> {
-        let timing = self.timing.lock().unwrap();
+        let timing = lock(&self.timing);
         timing.as_ref().and_then(|t| t.remaining())
     }
 
@@ -262,11 +294,25 @@ pub struct TunesState {
     width: usize,
     /// Audio player
     player: Option<AudioPlayer>,
+    /// How pictures are drawn on this terminal
+    renderer: Arc<dyn Renderer>,
+    /// Cover art for the track last played, ready to send
+    cover: Option<Cover>,
+    /// Whether the cover is on screen, so it's only sent again when the
+    /// track changes or the screen has been cleared
+    cover_drawn: Cell<bool>,
+}
+
+/// A track's cover art, rendered for the terminal
+struct Cover {
+    track: String,
+    lines: Vec<String>,
+    cols: usize,
 }
 
 impl TunesState {
     /// Create a new TunesState from a directory path
-    pub fn new(directory: &str, width: usize) -> Self {
+    pub fn new(directory: &str, width: usize, renderer: Arc<dyn Renderer>) -> Self {
         let directory = PathBuf::from(directory);
         let files = Self::scan_directory(&directory);
 
@@ -286,6 +332,9 @@ impl TunesState {
             scroll_offset: 0,
             width,
             player,
+            renderer,
+            cover: None,
+            cover_drawn: Cell::new(false),
         }
     }
 
@@ -329,7 +378,7 @@ impl TunesState {
     }
 
     /// Scan directory for supported audio files (non-recursive)
-    fn scan_directory(directory: &Path) -> Vec<String> {
+    pub fn scan_directory(directory: &Path) -> Vec<String> {
         let mut files = Vec::new();
 
         if let Ok(entries) = fs::read_dir(directory) {
@@ -351,7 +400,6 @@ impl TunesState {
     }
 
     /// Refresh the file list from the directory
-    #[allow(dead_code)]
     pub fn refresh(&mut self) {
         self.files = Self::scan_directory(&self.directory);
 
@@ -384,8 +432,8 @@ impl TunesState {
 
     /// Page up
     pub fn page_up(&mut self) {
-        if self.selected >= TUNES_VISIBLE_LINES {
-            self.selected -= TUNES_VISIBLE_LINES;
+        if self.selected >= visible_lines() {
+            self.selected -= visible_lines();
         } else {
             self.selected = 0;
         }
@@ -395,7 +443,7 @@ impl TunesState {
     /// Page down
     pub fn page_down(&mut self) {
         if !self.files.is_empty() {
-            let new_pos = self.selected + TUNES_VISIBLE_LINES;
+            let new_pos = self.selected + visible_lines();
             if new_pos < self.files.len() {
                 self.selected = new_pos;
             } else {
@@ -405,12 +453,26 @@ impl TunesState {
         }
     }
 
+    /// Select the tune shown on screen row `row`, as a mouse click does.
+    /// Returns false if there's none there.
+    pub fn select_row(&mut self, row: usize) -> bool {
+        let Some(i) = row.checked_sub(TUNES_REGION_START) else {
+            return false;
+        };
+        let file_idx = self.scroll_offset + i;
+        if i >= visible_lines() || file_idx >= self.files.len() {
+            return false;
+        }
+        self.selected = file_idx;
+        true
+    }
+
     /// Ensure selected item is visible
     fn ensure_visible(&mut self) {
         if self.selected < self.scroll_offset {
             self.scroll_offset = self.selected;
-        } else if self.selected >= self.scroll_offset + TUNES_VISIBLE_LINES {
-            self.scroll_offset = self.selected - TUNES_VISIBLE_LINES + 1;
+        } else if self.selected >= self.scroll_offset + visible_lines() {
+            self.scroll_offset = self.selected - visible_lines() + 1;
         }
     }
 
@@ -434,10 +496,12 @@ impl TunesState {
     }
 
     /// Play the currently selected file
-    pub fn play_selected(&self) -> Result<(), String> {
+    pub fn play_selected(&mut self) -> Result<(), String> {
         if let Some(ref player) = self.player {
             if let Some(path) = self.selected_path() {
-                player.play(&path)
+                player.play(&path)?;
+                self.load_cover(&path);
+                Ok(())
             } else {
                 Err("No file selected".to_string())
             }
@@ -446,6 +510,32 @@ impl TunesState {
         }
     }
 
+    /// Find and render the cover art for a track that's started playing
+    fn load_cover(&mut self, path: &Path) {
+        let track = path.file_name().unwrap_or_default().to_string_lossy();
+        if self.cover.as_ref().is_some_and(|c| c.track == track) {
+            return;
+        }
+        self.cover_drawn.set(false);
+        // ReGIS draws at a fixed place, over the list
+        self.cover = if self.renderer.name() == "regis" {
+            None
+        } else {
+            find_cover(path)
+                .and_then(|data| render_cover(&data, self.renderer.as_ref()))
+                .map(|lines| Cover {
+                    track: track.to_string(),
+                    lines,
+                    cols: crate::cover::cover_cols(self.renderer.as_ref()) as usize,
+                })
+        };
+    }
+
+    /// The screen has been cleared: send the cover again with the next render
+    pub fn redraw_cover(&self) {
+        self.cover_drawn.set(false);
+    }
+
     /// Stop playback
     pub fn stop(&self) {
         if let Some(ref player) = self.player {
@@ -460,6 +550,11 @@ impl TunesState {
         }
     }
 
+    /// The audio player (None if there's no audio output)
+    pub fn player(&self) -> Option<&AudioPlayer> {
+        self.player.as_ref()
+    }
+
     /// Get current playback state
     pub fn playback_state(&self) -> PlaybackState {
         if let Some(ref player) = self.player {
@@ -504,11 +599,21 @@ impl TunesState {
             PlaybackState::Paused(f) => Some(f.as_str()),
             PlaybackState::Stopped => None,
         };
+        // The cover goes in the top right corner while its track plays,
+        // if there's room left for the names
+        let cover = self
+            .cover
+            .as_ref()
+            .filter(|c| playing_file == Some(c.track.as_str()) && content_width >= c.cols + 40);
 
         // Clear and render each visible line (leave last line for status)
-        for i in 0..TUNES_VISIBLE_LINES {
+        for i in 0..visible_lines() {
             let row = TUNES_REGION_START + i;
             output.push_str(&esc::cursor_to(row, 2));
+            let content_width = match cover {
+                Some(cover) if i < COVER_ROWS as usize => content_width - cover.cols - 1,
+                _ => content_width,
+            };
 
             let file_idx = self.scroll_offset + i;
             if file_idx < self.files.len() {
@@ -592,7 +697,7 @@ impl TunesState {
             }
         };
 
-        output.push_str(&esc::cursor_to(TUNES_REGION_END, 2));
+        output.push_str(&esc::cursor_to(layout::region_end(), 2));
         let status_display: String = if status.chars().count() > content_width {
             status.chars().take(content_width).collect()
         } else {
@@ -608,6 +713,20 @@ impl TunesState {
         }
         output.push_str(esc::RESET_ATTRS);
 
+        match cover {
+            Some(cover) if !self.cover_drawn.get() => {
+                let col = 2 + content_width - cover.cols;
+                for (i, line) in cover.lines.iter().enumerate() {
+                    output.push_str(&esc::cursor_to(TUNES_REGION_START + i, col));
+                    output.push_str(line);
+                }
+                self.cover_drawn.set(true);
+            }
+            Some(_) => {}
+            // The list has been drawn over it
+            None => self.cover_drawn.set(false),
+        }
+
         output
     }
 }
@@ -0,0 +1,221 @@
+//! Supervision of the background threads and tasks.
+//!
+//! The network receive task, LAN discovery, the webcam thread and the audio
+//! players' playback threads run on their own. If one panics or hangs, its
+//! part of the program would quietly stop working until wormhole is
+//! restarted, so the app checks on each about once a second and starts a
+//! fresh one in its place.
+//!
+//! "Died" is easy to see: the thread or task has finished while the app is
+//! still running. "Wedged" is a subsystem that took a job and hasn't finished
+//! it: each one marks a [`Heartbeat`] busy while it works and idle while it
+//! waits, so one that sits idle for hours is fine but one stuck halfway
+//! through a job for longer than its limit is not. A subsystem that keeps
+//! failing straight after a restart is restarted less and less often.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The first restart after a failure soon after the last one waits this long
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest wait between restarts of a subsystem that keeps failing
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A subsystem that has run this long since its restart is healthy again,
+/// and its next failure is restarted straight away
+const STABLE_AFTER: Duration = Duration::from_secs(120);
+
+/// What the heartbeats' times count from
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Whether a subsystem is in the middle of a job, and since when. Cloned
+/// into the thread or task, which marks it; the [`Watch`] reads it.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat(Arc<AtomicU64>);
+
+impl Heartbeat {
+    /// Started a job
+    pub fn busy(&self) {
+        // 0 is idle, so times count from 1ms after the epoch
+        let at = epoch().elapsed().as_millis() as u64 + 1;
+        self.0.store(at, Ordering::Relaxed);
+    }
+
+    /// Finished it, and waiting for the next
+    pub fn idle(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+
+    /// How long the job in hand has taken so far (None if idle)
+    pub fn busy_for(&self, now: Instant) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            at => {
+                let since = epoch() + Duration::from_millis(at - 1);
+                Some(now.saturating_duration_since(since))
+            }
+        }
+    }
+}
+
+/// What went wrong with a subsystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Its thread or task ended (most likely a panic)
+    Died,
+    /// Stuck on one job for this long
+    Wedged(Duration),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Died => write!(f, "stopped"),
+            Failure::Wedged(time) => write!(f, "hung for {}s", time.as_secs()),
+        }
+    }
+}
+
+/// The watchdog's view of one subsystem
+#[derive(Debug)]
+pub struct Watch {
+    /// What to call it in notices: "webcam thread"
+    pub name: &'static str,
+    /// Longest a job may take before the subsystem counts as wedged
+    limit: Duration,
+    heartbeat: Heartbeat,
+    /// When the current failure was first seen
+    failed_at: Option<Instant>,
+    /// How long to let the current failure stand before restarting
+    backoff: Duration,
+    restarted_at: Option<Instant>,
+    /// Restarts so far
+    pub restarts: u32,
+}
+
+impl Watch {
+    pub fn new(name: &'static str, limit: Duration) -> Self {
+        Self {
+            name,
+            limit,
+            heartbeat: Heartbeat::default(),
+            failed_at: None,
+            backoff: Duration::ZERO,
+            restarted_at: None,
+            restarts: 0,
+        }
+    }
+
+    /// The heartbeat for the running subsystem to mark
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// A fresh heartbeat for a restarted subsystem; the one it replaces may
+    /// still be held by a thread that's stuck
+    pub fn renew(&mut self) -> Heartbeat {
+        self.heartbeat = Heartbeat::default();
+        self.heartbeat()
+    }
+
+    /// The subsystem is waiting on the app (a full channel), not wedged:
+    /// start its job's clock again
+    pub fn excuse(&self) {
+        if self.heartbeat.busy_for(Instant::now()).is_some() {
+            self.heartbeat.busy();
+        }
+    }
+
+    /// Whether the subsystem should be restarted now, and why. `finished` is
+    /// whether its thread or task has ended.
+    pub fn check(&mut self, finished: bool, now: Instant) -> Option<Failure> {
+        let failure = if finished {
+            Failure::Died
+        } else {
+            match self.heartbeat.busy_for(now) {
+                Some(time) if time > self.limit => Failure::Wedged(time),
+                _ => {
+                    self.failed_at = None;
+                    return None;
+                }
+            }
+        };
+        if self.failed_at.is_none()
+            && self
+                .restarted_at
+                .is_some_and(|at| now.saturating_duration_since(at) >= STABLE_AFTER)
+        {
+            self.backoff = Duration::ZERO;
+        }
+        let failed_at = *self.failed_at.get_or_insert(now);
+        if now < failed_at + self.backoff {
+            return None;
+        }
+        // Failing again soon after the last restart waits longer next time
+        self.backoff = match self.restarted_at {
+            Some(at) if now.saturating_duration_since(at) < STABLE_AFTER => {
+                (self.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF)
+            }
+            _ => Duration::ZERO,
+        };
+        self.failed_at = None;
+        self.restarted_at = Some(now);
+        self.restarts += 1;
+        Some(failure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let start = Instant::now();
+        let mut watch = Watch::new("test task", Duration::from_secs(5));
+        let heartbeat = watch.heartbeat();
+
+        // Idle for ever is fine; busy for a while is fine
+        assert_eq!(watch.check(false, start + Duration::from_secs(3600)), None);
+        heartbeat.busy();
+        assert_eq!(watch.check(false, start + Duration::from_secs(2)), None);
+        heartbeat.idle();
+        assert_eq!(watch.check(false, start + Duration::from_secs(60)), None);
+
+        // Stuck on a job past the limit
+        heartbeat.busy();
+        let now = Instant::now() + Duration::from_secs(6);
+        assert!(matches!(watch.check(false, now), Some(Failure::Wedged(_))));
+        assert_eq!(watch.restarts, 1);
+
+        // The restarted one has a heartbeat of its own
+        let heartbeat = watch.renew();
+        assert_eq!(watch.check(false, now), None);
+
+        // Dying straight after a restart waits before the next one
+        assert_eq!(
+            watch.check(true, now + Duration::from_secs(1)),
+            Some(Failure::Died)
+        );
+        let now = now + Duration::from_secs(2);
+        assert_eq!(watch.check(true, now), None);
+        assert_eq!(watch.check(true, now + Duration::from_millis(999)), None);
+        assert_eq!(watch.check(true, now + MIN_BACKOFF), Some(Failure::Died));
+        assert_eq!(watch.restarts, 3);
+
+        // Running well for a while resets the backoff
+        let now = now + STABLE_AFTER * 2;
+        assert_eq!(watch.check(true, now), Some(Failure::Died));
+        assert_eq!(
+            watch.check(true, now + STABLE_AFTER * 2),
+            Some(Failure::Died)
+        );
+        drop(heartbeat);
+    }
+}
@@ -1,9 +1,9 @@
 //! Webcam capture and ASCII art conversion for VT100/VT220/VT340 terminals.
 
-use crate::graphics::{
-    DecGraphicsChar, SHIFT_IN, SHIFT_OUT, brightness_to_drcs_char, image_to_sixel,
-};
-use image::{DynamicImage, GenericImageView, imageops::FilterType};
+use crate::graphics::{Dither, Renderer};
+use crate::testcard;
+use crate::watchdog::Heartbeat;
+use image::{DynamicImage, GenericImageView, GrayImage, imageops::FilterType};
 use nokhwa::{
     Camera,
     pixel_format::RgbFormat,
@@ -11,33 +11,12 @@ use nokhwa::{
         CameraFormat, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution,
     },
 };
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 
-/// Rendering mode for webcam output
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum RenderMode {
-    /// Pure ASCII characters (VT100 compatible)
-    Ascii,
-    /// DRCS custom characters for smoother shading (VT220+)
-    Drcs,
-    /// Sixel bitmap graphics (VT340) with configurable gray levels
-    Sixel { shades: u8 },
-}
-
-impl RenderMode {
-    /// Create a RenderMode from terminal mode string and config
-    pub fn from_terminal_mode(mode: &str, sixel_shades: u8) -> Self {
-        match mode {
-            "vt340" => RenderMode::Sixel {
-                shades: sixel_shades.clamp(2, 64),
-            },
-            "vt220" => RenderMode::Drcs,
-            _ => RenderMode::Ascii,
-        }
-    }
-}
-
 /// Raw grayscale frame data for network transmission
 /// Contains pre-processed (resized, cropped, contrast-enhanced) grayscale pixels
 #[derive(Debug, Clone)]
@@ -51,45 +30,187 @@ pub struct RawFrame {
 #[allow(dead_code)]
 const ASCII_RAMP: &[char] = &[' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
 
-/// Convert a brightness value (0-255) to an enhanced character (char, is_dec_graphics)
-fn brightness_to_enhanced_char(brightness: u8) -> (char, bool) {
-    // Don't invert for light-on-dark terminals
-    // Bright (255) -> Dense char (@) -> White pixel
-    // Dark (0) -> Space ( ) -> Black pixel
-
-    // Enhanced ramp mixing ASCII and DEC graphics
-    // 0: Space (ASCII)
-    // 1: Bullet (DEC) - Small dot
-    // 2: . (ASCII)
-    // 3: : (ASCII)
-    // 4: + (ASCII)
-    // 5: Checkerboard (DEC) - 50%
-    // 6: # (ASCII)
-    // 7: @ (ASCII)
-
-    match brightness {
-        0..=40 => (' ', false),
-        41..=70 => (DecGraphicsChar::Bullet.as_dec_char(), true),
-        71..=100 => ('.', false),
-        101..=130 => (':', false),
-        131..=160 => ('+', false),
-        161..=190 => (DecGraphicsChar::Checkerboard.as_dec_char(), true),
-        191..=220 => ('#', false),
-        _ => ('@', false),
-    }
-}
-
 /// Height in terminal rows
 const IMAGE_HEIGHT: u32 = 16;
 /// Height in terminal rows for Call mode
 const CALL_IMAGE_HEIGHT: u32 = 22;
 
+/// Pictures shared in chat are sent at this fraction of sixel resolution, so
+/// one fits in a datagram
+const SHARE_DIVISOR: u8 = 2;
+
+/// Longest to wait for the webcam thread to capture a frame
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest to wait for a snapshot, which opens the device afresh
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Names accepted for the `aspect` config option
+pub const ASPECT_NAMES: &[&str] = &["fill", "fit"];
+
+/// What's done with a picture wider than the screen (`aspect` under `[webcam]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aspect {
+    /// Cut the sides off to fill the width
+    #[default]
+    Fill,
+    /// Shrink it to fit, with black bars above and below
+    Fit,
+}
+
+impl Aspect {
+    /// The handling named `name`, one of [`ASPECT_NAMES`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "fill" => Some(Aspect::Fill),
+            "fit" => Some(Aspect::Fit),
+            _ => None,
+        }
+    }
+}
+
+/// The part of the picture kept (`crop` under `[webcam]`), in percent of
+/// its width and height
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    /// Parse `left,top,width,height`, e.g. `25,0,50,100` for the middle half
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let parts: Vec<u32> = text
+            .split(',')
+            .map(|p| p.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("crop '{}' should be left,top,width,height", text))?;
+        let [left, top, width, height] = parts[..] else {
+            return Err(format!("crop '{}' should be left,top,width,height", text));
+        };
+        if width == 0 || height == 0 || left + width > 100 || top + height > 100 {
+            return Err(format!("crop '{}' doesn't fit inside the picture", text));
+        }
+        Ok(Self {
+            left,
+            top,
+            width,
+            height,
+        })
+    }
+
+    /// The cropped part of `image`
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (w, h) = image.dimensions();
+        let width = (w * self.width / 100).max(1);
+        let height = (h * self.height / 100).max(1);
+        image.crop_imm(w * self.left / 100, h * self.top / 100, width, height)
+    }
+}
+
+/// How frames are captured: `resolution`, `aspect` and `crop` under `[webcam]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureSettings {
+    /// What to ask the camera for; it may give something else
+    pub resolution: (u32, u32),
+    pub aspect: Aspect,
+    pub crop: Option<Crop>,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            resolution: (1280, 720),
+            aspect: Aspect::Fill,
+            crop: None,
+        }
+    }
+}
+
+impl CaptureSettings {
+    /// Parse the config options, e.g. `1280x720`, `fit` and `25,25,50,50`
+    pub fn parse(resolution: &str, aspect: &str, crop: Option<&str>) -> Result<Self, String> {
+        let resolution = resolution
+            .split_once(['x', 'X'])
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+            .filter(|&(w, h): &(u32, u32)| w > 0 && h > 0)
+            .ok_or_else(|| format!("resolution '{}' should be like 1280x720", resolution))?;
+        let aspect = Aspect::from_name(aspect).ok_or_else(|| {
+            format!(
+                "aspect '{}' should be one of: {}",
+                aspect,
+                ASPECT_NAMES.join(", ")
+            )
+        })?;
+        let crop = crop.map(Crop::parse).transpose()?;
+        Ok(Self {
+            resolution,
+            aspect,
+            crop,
+        })
+    }
+
+    /// `image` cut down to the crop region
+    fn crop(&self, image: DynamicImage) -> DynamicImage {
+        match &self.crop {
+            Some(crop) => crop.apply(&image),
+            None => image,
+        }
+    }
+}
+
+/// Open camera `index`, asking for `resolution` in MJPEG, then 640x480, then
+/// whatever it likes, for devices that refuse
+fn open_camera(index: u32, resolution: (u32, u32)) -> Result<Camera, WebcamError> {
+    let camera_index = CameraIndex::Index(index);
+    let (width, height) = resolution;
+    let mut last_error = None;
+    for wanted in [Some((width, height)), Some((640, 480)), None] {
+        let requested = match wanted {
+            Some((w, h)) => RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(
+                CameraFormat::new(Resolution::new(w, h), FrameFormat::MJPEG, 30),
+            )),
+            None => RequestedFormat::new::<RgbFormat>(RequestedFormatType::None),
+        };
+        match Camera::new(camera_index.clone(), requested) {
+            Ok(camera) => {
+                let got = camera.resolution();
+                if (got.width(), got.height()) != resolution {
+                    eprintln!(
+                        "Webcam gave {}x{} rather than {}x{}",
+                        got.width(),
+                        got.height(),
+                        width,
+                        height
+                    );
+                }
+                return Ok(camera);
+            }
+            Err(e) => {
+                eprintln!(
+                    "Webcam format {:?} failed ({}), trying fallback...",
+                    wanted, e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.map_or(WebcamError::NotConfigured, WebcamError::from))
+}
+
 /// Error type for webcam operations
 #[derive(Debug)]
 pub enum WebcamError {
     NokhwaError(nokhwa::NokhwaError),
     NotConfigured,
     InvalidDevice(String),
+    /// The webcam thread didn't answer in time
+    Timeout,
+    /// Turned off with `/disable webcam`
+    Disabled,
+    /// A snapshot couldn't be saved
+    Save(image::ImageError),
 }
 
 impl std::fmt::Display for WebcamError {
@@ -98,6 +219,9 @@ impl std::fmt::Display for WebcamError {
             WebcamError::NokhwaError(e) => write!(f, "Webcam error: {}", e),
             WebcamError::NotConfigured => write!(f, "Webcam not configured, sorry!"),
             WebcamError::InvalidDevice(s) => write!(f, "Invalid webcam device: {}", s),
+            WebcamError::Timeout => write!(f, "Webcam isn't answering"),
+            WebcamError::Disabled => write!(f, "Webcam is off (/enable webcam)"),
+            WebcamError::Save(e) => write!(f, "Can't save snapshot: {}", e),
         }
     }
 }
@@ -118,7 +242,7 @@ fn brightness_to_char(brightness: u8) -> char {
 }
 
 /// Parse a device path like "/dev/video0" to extract the camera index
-fn parse_device_index(device: &str) -> Result<u32, WebcamError> {
+pub fn parse_device_index(device: &str) -> Result<u32, WebcamError> {
     // Handle /dev/video0, /dev/video1, etc.
     if let Some(suffix) = device.strip_prefix("/dev/video") {
         suffix
@@ -132,31 +256,40 @@ fn parse_device_index(device: &str) -> Result<u32, WebcamError> {
     }
 }
 
-/// Capture a single frame from the webcam and convert to ASCII art lines
+/// Capture a single frame from the webcam and convert to ASCII art lines,
+/// saving it as a PNG at `save` too
 pub fn capture_ascii_snapshot(
     device: Option<&str>,
-    render_mode: RenderMode,
+    capture: &CaptureSettings,
+    renderer: Arc<dyn Renderer>,
     display_width: usize,
+    save: Option<&Path>,
 ) -> Result<Vec<String>, WebcamError> {
+    let image = capture_image(device, capture)?;
+    if let Some(path) = save {
+        image.save(path).map_err(WebcamError::Save)?;
+    }
+    Ok(image_to_output(
+        &image,
+        IMAGE_HEIGHT,
+        capture.aspect,
+        renderer.as_ref(),
+        display_width,
+    ))
+}
+
+/// Capture a single frame from the webcam, cropped
+pub fn capture_image(
+    device: Option<&str>,
+    capture: &CaptureSettings,
+) -> Result<DynamicImage, WebcamError> {
     let device = device.ok_or(WebcamError::NotConfigured)?;
+    if testcard::is_test_device(device) {
+        return Ok(capture.crop(DynamicImage::ImageRgb8(testcard::frame(Duration::ZERO))));
+    }
 
     let index = parse_device_index(device)?;
-    let camera_index = CameraIndex::Index(index);
-    // Request lower resolution (640x480) to reduce CPU usage
-    let format = CameraFormat::new(Resolution::new(640, 480), FrameFormat::MJPEG, 30);
-    let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(format));
-
-    let mut camera = match Camera::new(camera_index.clone(), requested) {
-        Ok(cam) => cam,
-        Err(e) => {
-            eprintln!(
-                "Snapshot: Preferred format failed ({}), trying fallback...",
-                e
-            );
-            let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::None);
-            Camera::new(camera_index, requested)?
-        }
-    };
+    let mut camera = open_camera(index, capture.resolution)?;
 
     // Start the camera stream
     eprintln!("Taking snapshot: Opening webcam stream...");
@@ -180,14 +313,7 @@ pub fn capture_ascii_snapshot(
     eprintln!("Snapshot complete: Closing webcam stream...");
     let _ = camera.stop_stream();
 
-    // Convert to our ASCII art
-    let image = DynamicImage::ImageRgb8(decoded);
-    Ok(image_to_output(
-        &image,
-        IMAGE_HEIGHT,
-        render_mode,
-        display_width,
-    ))
+    Ok(capture.crop(DynamicImage::ImageRgb8(decoded)))
 }
 
 #[allow(dead_code)]
@@ -195,7 +321,7 @@ enum WebcamCommand {
     Start,
     Stop,
     CaptureFrame {
-        render_mode: RenderMode,
+        renderer: Arc<dyn Renderer>,
         width: usize,
         reply: oneshot::Sender<Result<Vec<String>, WebcamError>>,
     },
@@ -205,53 +331,59 @@ enum WebcamCommand {
     },
     Snapshot {
         device: String,
-        render_mode: RenderMode,
+        renderer: Arc<dyn Renderer>,
         width: usize,
+        save: Option<PathBuf>,
         reply: oneshot::Sender<Result<Vec<String>, WebcamError>>,
     },
+    Still {
+        reply: oneshot::Sender<Result<DynamicImage, WebcamError>>,
+    },
+}
+
+/// Where a [`WebcamDevice`]'s frames come from
+enum Source {
+    Camera(Camera),
+    /// The test pattern, started at this time
+    Test(Instant),
 }
 
 /// A persistent webcam stream handler (internal)
 struct WebcamDevice {
-    camera: Camera,
+    source: Source,
+    capture: CaptureSettings,
 }
 
 impl WebcamDevice {
-    pub fn new(device: Option<&str>) -> Result<Self, WebcamError> {
+    pub fn new(device: Option<&str>, capture: CaptureSettings) -> Result<Self, WebcamError> {
         let device = device.ok_or(WebcamError::NotConfigured)?;
+        if testcard::is_test_device(device) {
+            return Ok(Self {
+                source: Source::Test(Instant::now()),
+                capture,
+            });
+        }
         let index = parse_device_index(device)?;
-        let camera_index = CameraIndex::Index(index);
-
-        // First try: Preference for 1280x720 MJPEG @ 30fps (16:9 aspect ratio)
-        let format = CameraFormat::new(Resolution::new(1280, 720), FrameFormat::MJPEG, 30);
-        let requested = RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(format));
-
-        let camera = match Camera::new(camera_index.clone(), requested) {
-            Ok(cam) => cam,
-            Err(e) => {
-                eprintln!("Preferred format failed ({}), trying fallback...", e);
-                // Fallback: Try 640x480
-                let format = CameraFormat::new(Resolution::new(640, 480), FrameFormat::MJPEG, 30);
-                let requested =
-                    RequestedFormat::new::<RgbFormat>(RequestedFormatType::Closest(format));
-                match Camera::new(camera_index.clone(), requested) {
-                    Ok(cam) => cam,
-                    Err(_) => {
-                        // Final fallback
-                        let requested =
-                            RequestedFormat::new::<RgbFormat>(RequestedFormatType::None);
-                        Camera::new(camera_index, requested)?
-                    }
-                }
-            }
-        };
+        Ok(Self {
+            source: Source::Camera(open_camera(index, capture.resolution)?),
+            capture,
+        })
+    }
 
-        Ok(Self { camera })
+    /// Whether a camera's stream is open (the test pattern has none)
+    fn is_streaming(&self) -> bool {
+        match &self.source {
+            Source::Camera(camera) => camera.is_stream_open(),
+            Source::Test(_) => false,
+        }
     }
 
     pub fn start(&mut self) -> Result<(), WebcamError> {
+        let Source::Camera(camera) = &mut self.source else {
+            return Ok(());
+        };
         eprintln!("Starting webcam stream...");
-        match self.camera.open_stream() {
+        match camera.open_stream() {
             Ok(_) => {
                 eprintln!("Webcam stream started successfully.");
                 Ok(())
@@ -264,9 +396,12 @@ impl WebcamDevice {
     }
 
     pub fn stop(&mut self) -> Result<(), WebcamError> {
-        if self.camera.is_stream_open() {
+        let Source::Camera(camera) = &mut self.source else {
+            return Ok(());
+        };
+        if camera.is_stream_open() {
             eprintln!("Stopping webcam stream...");
-            match self.camera.stop_stream() {
+            match camera.stop_stream() {
                 Ok(_) => {
                     eprintln!("Webcam stream stopped.");
                     Ok(())
@@ -283,46 +418,97 @@ impl WebcamDevice {
 
     pub fn capture_frame(
         &mut self,
-        render_mode: RenderMode,
+        renderer: Arc<dyn Renderer>,
         display_width: usize,
     ) -> Result<Vec<String>, WebcamError> {
-        let frame = self.camera.frame()?;
-        let decoded = frame.decode_image::<RgbFormat>()?;
-        let image = DynamicImage::ImageRgb8(decoded);
+        let image = self.grab()?;
         Ok(image_to_output(
             &image,
             CALL_IMAGE_HEIGHT,
-            render_mode,
+            self.capture.aspect,
+            renderer.as_ref(),
             display_width,
         ))
     }
 
     /// Capture a frame and return raw grayscale data for network transmission
     pub fn capture_raw_frame(&mut self, display_width: usize) -> Result<RawFrame, WebcamError> {
-        let frame = self.camera.frame()?;
-        let decoded = frame.decode_image::<RgbFormat>()?;
-        let image = DynamicImage::ImageRgb8(decoded);
-        Ok(image_to_raw_frame(&image, CALL_IMAGE_HEIGHT, display_width))
+        let image = self.grab()?;
+        Ok(image_to_raw_frame(
+            &image,
+            CALL_IMAGE_HEIGHT,
+            self.capture.aspect,
+            display_width,
+        ))
+    }
+
+    /// The next frame from the camera or test pattern, cropped
+    fn grab(&mut self) -> Result<DynamicImage, WebcamError> {
+        let image = match &mut self.source {
+            Source::Camera(camera) => {
+                let frame = camera.frame()?;
+                DynamicImage::ImageRgb8(frame.decode_image::<RgbFormat>()?)
+            }
+            Source::Test(started) => DynamicImage::ImageRgb8(testcard::frame(started.elapsed())),
+        };
+        Ok(self.capture.crop(image))
     }
 }
 
 /// Thread-safe handle to the webcam
 pub struct Webcam {
     tx: mpsc::Sender<WebcamCommand>,
+    device: Option<String>,
+    capture: CaptureSettings,
+    thread: thread::JoinHandle<()>,
+    /// Marked busy while the thread works on a command
+    heartbeat: Heartbeat,
 }
 
 impl Webcam {
-    pub fn new(device: Option<String>) -> Self {
+    pub fn new(device: Option<String>, capture: CaptureSettings, heartbeat: Heartbeat) -> Self {
+        let (tx, thread) = Self::spawn(device.clone(), capture, heartbeat.clone());
+        Self {
+            tx,
+            device,
+            capture,
+            thread,
+            heartbeat,
+        }
+    }
+
+    /// Whether the webcam thread has ended (it only does by panicking)
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Replace the webcam thread with a fresh one. A thread that's stuck
+    /// can't be stopped: it's left to finish on its own, and if it still has
+    /// the device open the new one can't open it and reports the webcam as
+    /// not configured.
+    pub fn restart(&mut self, heartbeat: Heartbeat) {
+        let (tx, thread) = Self::spawn(self.device.clone(), self.capture, heartbeat.clone());
+        self.tx = tx;
+        self.thread = thread;
+        self.heartbeat = heartbeat;
+    }
+
+    fn spawn(
+        device: Option<String>,
+        capture: CaptureSettings,
+        heartbeat: Heartbeat,
+    ) -> (mpsc::Sender<WebcamCommand>, thread::JoinHandle<()>) {
         let (tx, mut rx) = mpsc::channel(32);
 
-        thread::spawn(move || {
+        let thread = thread::spawn(move || {
             let mut device_instance = if let Some(dev) = &device {
-                WebcamDevice::new(Some(dev)).ok()
+                WebcamDevice::new(Some(dev), capture).ok()
             } else {
                 None
             };
 
             while let Some(cmd) = rx.blocking_recv() {
+                heartbeat.busy();
                 match cmd {
                     WebcamCommand::Start => {
                         if let Some(dev) = &mut device_instance {
@@ -335,12 +521,12 @@ impl Webcam {
                         }
                     }
                     WebcamCommand::CaptureFrame {
-                        render_mode,
+                        renderer,
                         width,
                         reply,
                     } => {
                         let res = if let Some(dev) = &mut device_instance {
-                            dev.capture_frame(render_mode, width)
+                            dev.capture_frame(renderer, width)
                         } else {
                             Err(WebcamError::NotConfigured)
                         };
@@ -356,20 +542,27 @@ impl Webcam {
                     }
                     WebcamCommand::Snapshot {
                         device,
-                        render_mode,
+                        renderer,
                         width,
+                        save,
                         reply,
                     } => {
                         // Stop stream if running to release device
                         let mut was_streaming = false;
                         if let Some(dev) = &mut device_instance
-                            && dev.camera.is_stream_open()
+                            && dev.is_streaming()
                         {
                             was_streaming = true;
                             let _ = dev.stop();
                         }
 
-                        let res = capture_ascii_snapshot(Some(&device), render_mode, width);
+                        let res = capture_ascii_snapshot(
+                            Some(&device),
+                            &capture,
+                            renderer,
+                            width,
+                            save.as_deref(),
+                        );
 
                         // Restart stream if it was running
                         if was_streaming && let Some(dev) = &mut device_instance {
@@ -378,67 +571,104 @@ impl Webcam {
 
                         let _ = reply.send(res);
                     }
+                    WebcamCommand::Still { reply } => {
+                        // A call's stream already has frames coming
+                        let res = match &mut device_instance {
+                            Some(dev) if dev.is_streaming() => dev.grab(),
+                            _ => capture_image(device.as_deref(), &capture),
+                        };
+                        let _ = reply.send(res);
+                    }
                 }
+                heartbeat.idle();
             }
         });
 
-        Self { tx }
+        (tx, thread)
     }
 
     pub async fn start(&self) {
-        let _ = self.tx.send(WebcamCommand::Start).await;
+        let _ = self.tx.try_send(WebcamCommand::Start);
     }
 
     pub async fn stop(&self) {
-        let _ = self.tx.send(WebcamCommand::Stop).await;
+        let _ = self.tx.try_send(WebcamCommand::Stop);
+    }
+
+    /// Send the thread a command and wait for its reply. A thread that's
+    /// already been stuck on one for a while isn't waited for again: the
+    /// watchdog will restart it.
+    async fn request<T>(
+        &self,
+        command: WebcamCommand,
+        reply: oneshot::Receiver<Result<T, WebcamError>>,
+        timeout: Duration,
+    ) -> Result<T, WebcamError> {
+        if self
+            .heartbeat
+            .busy_for(Instant::now())
+            .is_some_and(|time| time > FRAME_TIMEOUT)
+        {
+            return Err(WebcamError::Timeout);
+        }
+        let answer = async {
+            self.tx
+                .send(command)
+                .await
+                .map_err(|_| WebcamError::NotConfigured)?;
+            reply.await.map_err(|_| WebcamError::NotConfigured)?
+        };
+        tokio::time::timeout(timeout, answer)
+            .await
+            .unwrap_or(Err(WebcamError::Timeout))
     }
 
     /// Capture a pre-rendered frame (for local display only)
     #[allow(dead_code)]
     pub async fn capture_frame(
         &self,
-        render_mode: RenderMode,
+        renderer: Arc<dyn Renderer>,
         width: usize,
     ) -> Result<Vec<String>, WebcamError> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WebcamCommand::CaptureFrame {
-                render_mode,
-                width,
-                reply: tx,
-            })
-            .await
-            .map_err(|_| WebcamError::NotConfigured)?;
-        rx.await.map_err(|_| WebcamError::NotConfigured)?
+        let command = WebcamCommand::CaptureFrame {
+            renderer,
+            width,
+            reply: tx,
+        };
+        self.request(command, rx, FRAME_TIMEOUT).await
     }
 
     /// Capture a raw grayscale frame for network transmission
     pub async fn capture_raw_frame(&self, width: usize) -> Result<RawFrame, WebcamError> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WebcamCommand::CaptureRawFrame { width, reply: tx })
-            .await
-            .map_err(|_| WebcamError::NotConfigured)?;
-        rx.await.map_err(|_| WebcamError::NotConfigured)?
+        let command = WebcamCommand::CaptureRawFrame { width, reply: tx };
+        self.request(command, rx, FRAME_TIMEOUT).await
+    }
+
+    /// Capture a frame as a picture, at the camera's full resolution
+    pub async fn take_still(&self) -> Result<DynamicImage, WebcamError> {
+        let (tx, rx) = oneshot::channel();
+        let command = WebcamCommand::Still { reply: tx };
+        self.request(command, rx, SNAPSHOT_TIMEOUT).await
     }
 
     pub async fn take_snapshot(
         &self,
         device: String,
-        render_mode: RenderMode,
+        renderer: Arc<dyn Renderer>,
         width: usize,
+        save: Option<PathBuf>,
     ) -> Result<Vec<String>, WebcamError> {
         let (tx, rx) = oneshot::channel();
-        self.tx
-            .send(WebcamCommand::Snapshot {
-                device,
-                render_mode,
-                width,
-                reply: tx,
-            })
-            .await
-            .map_err(|_| WebcamError::NotConfigured)?;
-        rx.await.map_err(|_| WebcamError::NotConfigured)?
+        let command = WebcamCommand::Snapshot {
+            device,
+            renderer,
+            width,
+            save,
+            reply: tx,
+        };
+        self.request(command, rx, SNAPSHOT_TIMEOUT).await
     }
 }
 
@@ -506,7 +736,12 @@ fn enhance_contrast(image: &image::GrayImage) -> image::GrayImage {
 /// Process an image to raw grayscale frame data for network transmission
 /// Uses sixel-compatible resolution so receivers can render at full quality
 /// ASCII/DRCS receivers will downsample as needed
-fn image_to_raw_frame(image: &DynamicImage, height_rows: u32, display_width: usize) -> RawFrame {
+fn image_to_raw_frame(
+    image: &DynamicImage,
+    height_rows: u32,
+    aspect: Aspect,
+    display_width: usize,
+) -> RawFrame {
     // Use sixel-compatible resolution (18 pixels per row) for network transmission
     // This ensures sixel receivers get good quality
     // ASCII/DRCS receivers will downsample in raw_frame_to_output
@@ -514,18 +749,18 @@ fn image_to_raw_frame(image: &DynamicImage, height_rows: u32, display_width: usi
     let target_height = height_rows * PIXELS_PER_ROW;
 
     let (img_w, img_h) = image.dimensions();
-    let aspect = img_w as f32 / img_h as f32;
+    let ratio = img_w as f32 / img_h as f32;
 
     // Calculate width based on height and aspect ratio
     // Use nearly full display width, leaving small margin for UI
     const PIXELS_PER_COL: u32 = 10;
     let max_width = (display_width.saturating_sub(2) as u32) * PIXELS_PER_COL;
 
-    let ideal_width = (target_height as f32 * aspect) as u32;
-    let target_width = ideal_width.min(max_width);
+    let ideal_width = (target_height as f32 * ratio) as u32;
+    let target_width = ideal_width.min(max_width).max(1);
 
-    // Resize and crop to fill the target dimensions
-    let resized = image.resize_to_fill(target_width, target_height, FilterType::Triangle);
+    // Resize and crop (or letterbox) to the target dimensions
+    let resized = fit_to(image, target_width, target_height, aspect);
 
     // Convert to grayscale
     let gray = resized.to_luma8();
@@ -540,175 +775,154 @@ fn image_to_raw_frame(image: &DynamicImage, height_rows: u32, display_width: usi
     }
 }
 
+/// Load a picture (PNG, JPEG...) to share in chat
+pub fn image_file_to_raw_frame(
+    path: &Path,
+    display_width: usize,
+) -> Result<RawFrame, image::ImageError> {
+    let image = image::open(path)?;
+    let frame = image_to_raw_frame(&image, IMAGE_HEIGHT, Aspect::Fill, display_width);
+    Ok(scale_raw_frame(&frame, SHARE_DIVISOR))
+}
+
+/// Save a frame as a grayscale PNG
+pub fn save_raw_frame(frame: &RawFrame, path: &Path) -> Result<(), image::ImageError> {
+    let image = GrayImage::from_raw(
+        frame.width as u32,
+        frame.height as u32,
+        frame.pixels.clone(),
+    )
+    .ok_or_else(|| {
+        image::ImageError::Parameter(image::error::ParameterError::from_kind(
+            image::error::ParameterErrorKind::DimensionMismatch,
+        ))
+    })?;
+    image.save(path)
+}
+
+/// Shrink a frame by `divisor` in each dimension (1 returns it unchanged)
+pub fn scale_raw_frame(frame: &RawFrame, divisor: u8) -> RawFrame {
+    if divisor <= 1 {
+        return frame.clone();
+    }
+    let divisor = divisor as u32;
+    let Some(image) = GrayImage::from_raw(
+        frame.width as u32,
+        frame.height as u32,
+        frame.pixels.clone(),
+    ) else {
+        return frame.clone();
+    };
+    let width = (image.width() / divisor).max(1);
+    let height = (image.height() / divisor).max(1);
+    let scaled = image::imageops::resize(&image, width, height, FilterType::Triangle);
+    RawFrame {
+        width: width as u16,
+        height: height as u16,
+        pixels: scaled.into_raw(),
+    }
+}
+
 /// Render a raw grayscale frame to terminal output lines
 /// This allows the receiver to render according to their terminal capabilities
 /// Frame is expected to be at sixel resolution (18 pixels per row)
 pub fn raw_frame_to_output(
     frame: &RawFrame,
-    render_mode: RenderMode,
-    sixel_shades: u8,
+    renderer: &dyn Renderer,
+    dither: Dither,
 ) -> Vec<String> {
     let width = frame.width as u32;
     let height = frame.height as u32;
 
     // Frame is at sixel resolution: 18 pixels per terminal row
     const PIXELS_PER_ROW: u32 = 18;
-    let height_rows = height / PIXELS_PER_ROW;
 
-    // For sixel mode, reconstruct a DynamicImage and use sixel encoder
-    if let RenderMode::Sixel { shades: _ } = render_mode {
-        use crate::graphics::SixelConfig;
-        use image::GrayImage;
+    let Some(mut image) = GrayImage::from_raw(width, height, frame.pixels.clone()) else {
+        return vec!["[render error]".to_string()];
+    };
 
-        if let Some(gray_image) = GrayImage::from_raw(width, height, frame.pixels.clone()) {
-            let image = DynamicImage::ImageLuma8(gray_image);
-            let config = SixelConfig {
-                gray_levels: sixel_shades,
-                ..Default::default()
-            };
-            // Pass the frame directly - it's already at the right resolution
-            let sixel_output =
-                image_to_sixel(&image, height_rows, width as usize + 4, Some(&config));
-            return vec![sixel_output];
-        }
-        // Fallback if reconstruction fails
-        return vec!["[sixel render error]".to_string()];
+    // Senders drop to a lower resolution when the network is congested; scale
+    // those frames back up to fill the call area
+    if height > 0 && height < CALL_IMAGE_HEIGHT * PIXELS_PER_ROW {
+        let full_height = CALL_IMAGE_HEIGHT * PIXELS_PER_ROW;
+        let full_width = width * full_height / height;
+        image = image::imageops::resize(&image, full_width, full_height, FilterType::Triangle);
     }
+    gray_to_output(image, renderer, dither)
+}
 
-    // For ASCII/DRCS modes, we need to downsample from sixel resolution to character resolution
-    // Each character represents PIXELS_PER_ROW vertical pixels and ~9 horizontal pixels
-    let use_drcs = render_mode == RenderMode::Drcs;
-
-    // Calculate how many source pixels per character
-    let pixels_per_char_y = PIXELS_PER_ROW;
-    let pixels_per_char_x = 9u32; // Approximate character width in pixels
-
-    let char_cols = width / pixels_per_char_x;
-    let char_rows = height_rows;
-
-    let mut lines = Vec::with_capacity(char_rows as usize);
-
-    for row in 0..char_rows {
-        let mut line = String::with_capacity(char_cols as usize + 10);
-
-        if use_drcs {
-            line.push_str(SHIFT_OUT);
-
-            for col in 0..char_cols {
-                // Sample a block of pixels and average them
-                let x_start = col * pixels_per_char_x;
-                let y_start = row * pixels_per_char_y;
-
-                let mut sum = 0u32;
-                let mut count = 0u32;
-
-                for dy in 0..pixels_per_char_y {
-                    for dx in 0..pixels_per_char_x {
-                        let x = x_start + dx;
-                        let y = y_start + dy;
-                        if x < width && y < height {
-                            let idx = (y * width + x) as usize;
-                            if let Some(&p) = frame.pixels.get(idx) {
-                                sum += p as u32;
-                                count += 1;
-                            }
-                        }
-                    }
-                }
+/// Render a picture shared in chat (see [`image_file_to_raw_frame`]), shrunk
+/// if it's wider than our screen
+pub fn shared_image_to_output(
+    frame: &RawFrame,
+    renderer: &dyn Renderer,
+    dither: Dither,
+    display_width: usize,
+) -> Vec<String> {
+    const PIXELS_PER_COL: u32 = 10;
 
-                let avg = if count > 0 { (sum / count) as u8 } else { 0 };
-                let char = brightness_to_drcs_char(avg);
-                line.push(char);
-            }
+    let Some(image) = GrayImage::from_raw(
+        frame.width as u32,
+        frame.height as u32,
+        frame.pixels.clone(),
+    ) else {
+        return vec!["[render error]".to_string()];
+    };
+    if image.width() == 0 || image.height() == 0 {
+        return vec!["[render error]".to_string()];
+    }
 
-            line.push_str(SHIFT_IN);
-        } else {
-            // Enhanced ASCII mode
-            let mut current_is_dec = false;
-
-            for col in 0..char_cols {
-                // Sample a block of pixels and average them
-                let x_start = col * pixels_per_char_x;
-                let y_start = row * pixels_per_char_y;
-
-                let mut sum = 0u32;
-                let mut count = 0u32;
-
-                for dy in 0..pixels_per_char_y {
-                    for dx in 0..pixels_per_char_x {
-                        let x = x_start + dx;
-                        let y = y_start + dy;
-                        if x < width && y < height {
-                            let idx = (y * width + x) as usize;
-                            if let Some(&p) = frame.pixels.get(idx) {
-                                sum += p as u32;
-                                count += 1;
-                            }
-                        }
-                    }
-                }
+    // Back up to sixel resolution, narrow enough to clear the left border
+    let scale = SHARE_DIVISOR as u32;
+    let max_width = (display_width.saturating_sub(2) as u32 * PIXELS_PER_COL).max(1);
+    let width = (image.width() * scale).min(max_width);
+    let height = (image.height() * width / image.width()).max(1);
+    let image = image::imageops::resize(&image, width, height, FilterType::Triangle);
+    gray_to_output(image, renderer, dither)
+}
 
-                let avg = if count > 0 { (sum / count) as u8 } else { 0 };
-                let (char, is_dec) = brightness_to_enhanced_char(avg);
+/// Render a grayscale image at sixel resolution with `renderer`
+fn gray_to_output(mut image: GrayImage, renderer: &dyn Renderer, dither: Dither) -> Vec<String> {
+    const PIXELS_PER_ROW: u32 = 18;
+    const PIXELS_PER_COL: u32 = 10;
 
-                // Switch character set if needed
-                if is_dec && !current_is_dec {
-                    line.push_str(SHIFT_OUT);
-                    current_is_dec = true;
-                } else if !is_dec && current_is_dec {
-                    line.push_str(SHIFT_IN);
-                    current_is_dec = false;
-                }
+    let (width, height) = image.dimensions();
+    let height_rows = height / PIXELS_PER_ROW;
 
-                line.push(char);
-            }
+    // Bitmap renderers use the frame at full resolution. Character renderers
+    // sample ~9 horizontal pixels per character to fill slightly more columns.
+    let cols = if renderer.is_bitmap() {
+        width.div_ceil(PIXELS_PER_COL)
+    } else {
+        width / 9
+    };
 
-            // End line in normal character set
-            if current_is_dec {
-                line.push_str(SHIFT_IN);
-            }
+    // Round to the renderer's shades at one pixel per dot it draws, so each
+    // dot keeps the shade it was dithered to
+    let shades = renderer.shades();
+    if dither != Dither::None && !shades.is_empty() {
+        let (dots_x, dots_y) = renderer.dots_per_cell();
+        let (dots_w, dots_h) = (cols * dots_x, height_rows * dots_y);
+        if image.dimensions() != (dots_w, dots_h) {
+            image =
+                image::imageops::resize(&image, dots_w.max(1), dots_h.max(1), FilterType::Triangle);
         }
-
-        lines.push(line);
+        image = dither.apply(&image, &shades);
     }
 
-    lines
+    // Frame pixels were already contrast-enhanced by the sender
+    renderer.render(&image, cols, height_rows)
 }
 
-/// Convert an image to terminal output lines (ASCII, DRCS, or Sixel)
+/// Convert an image to terminal output lines using the given renderer
 fn image_to_output(
     image: &DynamicImage,
     height_rows: u32,
-    render_mode: RenderMode,
+    fit: Aspect,
+    renderer: &dyn Renderer,
     display_width: usize,
 ) -> Vec<String> {
-    // For sixel mode, we render directly to sixel format
-    if let RenderMode::Sixel { shades } = render_mode {
-        use crate::graphics::SixelConfig;
-        let config = SixelConfig {
-            gray_levels: shades,
-            ..Default::default()
-        };
-        let sixel_output = image_to_sixel(image, height_rows, display_width, Some(&config));
-        // Return as a single "line" - sixel handles its own positioning
-        return vec![sixel_output];
-    }
-
-    // For ASCII/DRCS modes, use character-based rendering
-    let use_drcs = render_mode == RenderMode::Drcs;
-
-    // Calculate target dimensions accounting for character aspect ratio (~2:1)
-    // We sample 2 vertical pixels for each character row
-    let target_height = height_rows * 2; // Double because we'll sample 2 rows per char
-
-    // Calculate target width based on image aspect ratio
-    // 1 char width = 1 unit, 1 char height = 2 units (roughly)
-    // So target_height pixels represents height_rows characters.
-    // To maintain aspect ratio:
-    // width_chars = height_chars * (image_w / image_h) * (char_h / char_w)
-    // width_chars = height_rows * (image_w / image_h) * 2.0
-    // Or simply: target_width_pixels = target_height_pixels * (image_w / image_h)
-    // Since we map 1 pixel to 1 char horizontally, and 2 pixels to 1 char vertically.
+    let (cell_w, cell_h) = renderer.cell_size();
 
     let (img_w, img_h) = image.dimensions();
     let mut aspect = img_w as f32 / img_h as f32;
@@ -718,97 +932,52 @@ fn image_to_output(
         aspect = aspect.max(1.77);
     }
 
-    // Calculate ideal width to maintain aspect ratio
-    let ideal_width = (target_height as f32 * aspect) as u32;
+    // Calculate ideal width in columns to maintain aspect ratio.
+    // A cell covers cell_w x cell_h source pixels, so the pixel width is
+    // (rows * cell_h) * aspect, divided back down into columns.
+    let ideal_cols = (height_rows as f32 * cell_h as f32 * aspect / cell_w as f32) as u32;
 
-    // Constrain to display width (minus padding)
-    let max_width = (display_width.saturating_sub(4)) as u32;
-    let target_width = ideal_width.min(max_width);
+    // Constrain to display width (bitmaps only need to clear the left border)
+    let margin = if renderer.is_bitmap() { 2 } else { 4 };
+    let max_cols = display_width.saturating_sub(margin) as u32;
+    let cols = ideal_cols.min(max_cols);
 
     // Resize and crop to fill the target dimensions FIRST
     // This drastically reduces the number of pixels for subsequent processing
-    let resized = image.resize_to_fill(target_width, target_height, FilterType::Triangle);
-
-    // Convert to grayscale
-    let gray = resized.to_luma8();
-
-    // Enhance contrast (now fast because image is tiny)
-    let enhanced = enhance_contrast(&gray);
-
-    let mut lines = Vec::with_capacity(height_rows as usize);
-
-    // Process 2 rows at a time, averaging them for each character row
-    for row in 0..height_rows {
-        let mut line = String::with_capacity(target_width as usize + 10);
+    let resized = fit_to(image, cols * cell_w, height_rows * cell_h, fit);
 
-        if use_drcs {
-            line.push_str(SHIFT_OUT);
+    // Convert to grayscale and enhance contrast (now fast because image is small)
+    let enhanced = enhance_contrast(&resized.to_luma8());
 
-            for col in 0..target_width {
-                // Average the two vertical pixels for this character position
-                let y1 = row * 2;
-                let y2 = row * 2 + 1;
-
-                let p1 = enhanced.get_pixel(col, y1)[0] as u16;
-                let p2 = if y2 < target_height {
-                    enhanced.get_pixel(col, y2)[0] as u16
-                } else {
-                    p1
-                };
-
-                let avg = ((p1 + p2) / 2) as u8;
-                let char = brightness_to_drcs_char(avg);
-                line.push(char);
-            }
-
-            line.push_str(SHIFT_IN);
-        } else {
-            // Enhanced ASCII mode (mix of ASCII and DEC graphics)
-            let mut current_is_dec = false;
-
-            for col in 0..target_width {
-                // Average the two vertical pixels for this character position
-                let y1 = row * 2;
-                let y2 = row * 2 + 1;
-
-                let p1 = enhanced.get_pixel(col, y1)[0] as u16;
-                let p2 = if y2 < target_height {
-                    enhanced.get_pixel(col, y2)[0] as u16
-                } else {
-                    p1
-                };
-
-                let avg = ((p1 + p2) / 2) as u8;
-                let (char, is_dec) = brightness_to_enhanced_char(avg);
-
-                if is_dec != current_is_dec {
-                    if is_dec {
-                        line.push_str(SHIFT_OUT);
-                    } else {
-                        line.push_str(SHIFT_IN);
-                    }
-                    current_is_dec = is_dec;
-                }
-                line.push(char);
-            }
+    renderer.render(&enhanced, cols, height_rows)
+}
 
-            if current_is_dec {
-                line.push_str(SHIFT_IN);
+/// `image` scaled to exactly `width` x `height`, cropped to fill it or
+/// shrunk to fit with black bars
+fn fit_to(image: &DynamicImage, width: u32, height: u32, aspect: Aspect) -> DynamicImage {
+    match aspect {
+        Aspect::Fill => image.resize_to_fill(width, height, FilterType::Triangle),
+        Aspect::Fit => {
+            let fitted = image.resize(width, height, FilterType::Triangle);
+            if fitted.dimensions() == (width, height) {
+                return fitted;
             }
+            let mut canvas = DynamicImage::new_rgb8(width, height);
+            let x = (width - fitted.width()) / 2;
+            let y = (height - fitted.height()) / 2;
+            image::imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+            canvas
         }
-
-        lines.push(line);
     }
-
-    lines
 }
 
-/// List available cameras (for debugging)
-#[allow(dead_code)]
+/// List available cameras, as "index: name" (for `/camera list`), and the
+/// test pattern
 pub fn list_cameras() -> Result<Vec<String>, WebcamError> {
     let cameras = nokhwa::query(nokhwa::utils::ApiBackend::Auto)?;
     Ok(cameras
         .iter()
         .map(|c| format!("{}: {}", c.index(), c.human_name()))
+        .chain([format!("{}: Test pattern", testcard::TEST_DEVICE)])
         .collect())
 }
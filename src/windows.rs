@@ -0,0 +1,291 @@
+//! Chat windows: the mesh chat, direct messages and channels, shown one at a
+//! time on the Chat tab.
+//!
+//! Like irssi's, the windows are numbered in the order they were opened, and
+//! Alt+number or `/window` switches between them. Window 1 is the mesh chat
+//! everyone on the LAN sees. A window named `#retro` is a channel: what's
+//! typed in it goes to every peer, but only those with a `#retro` window
+//! open show it. Any other name is a peer, and what's typed there goes to
+//! that peer alone; a direct message from a peer opens a window for them.
+//!
+//! Each window has its own scrollback. The one being shown lives in
+//! [`crate::app::App::chat_buffer`], where everything that draws the chat
+//! finds it, and the others wait here.
+
+use std::mem;
+
+use crate::terminal::{ChatBuffer, Theme};
+
+/// The window every session starts with
+pub const MESH_WINDOW: &str = "mesh";
+
+/// Windows that can be open at once (Alt+1 to Alt+0)
+pub const MAX_WINDOWS: usize = 10;
+
+/// Whether a window name is a channel rather than a peer
+pub fn is_channel(name: &str) -> bool {
+    name.starts_with('#')
+}
+
+/// Where what's typed in a window goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// The mesh chat
+    Mesh,
+    /// One peer, by name
+    Peer(&'a str),
+    /// Every peer, for those with the channel open
+    Channel(&'a str),
+}
+
+struct Window {
+    name: String,
+    /// The scrollback, while another window is shown
+    buffer: Option<ChatBuffer>,
+    /// Something arrived since the window was last shown
+    activity: bool,
+}
+
+/// The open windows and which one is shown
+pub struct Windows {
+    list: Vec<Window>,
+    current: usize,
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Windows {
+    /// Just the mesh window, shown
+    pub fn new() -> Self {
+        Self {
+            list: vec![Window {
+                name: MESH_WINDOW.to_string(),
+                buffer: None,
+                activity: false,
+            }],
+            current: 0,
+        }
+    }
+
+    /// The window shown (0 is the mesh)
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_mesh(&self) -> bool {
+        self.current == 0
+    }
+
+    /// Where what's typed in the window shown goes
+    pub fn target(&self) -> Target<'_> {
+        let name = &self.list[self.current].name;
+        match self.current {
+            0 => Target::Mesh,
+            _ if is_channel(name) => Target::Channel(name),
+            _ => Target::Peer(name),
+        }
+    }
+
+    /// The window called `name`
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|w| w.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Open a window called `name` (or find the one that's open), returning
+    /// its index. None if all [`MAX_WINDOWS`] are in use.
+    pub fn open(&mut self, name: &str, width: usize, theme: Theme) -> Option<usize> {
+        if let Some(index) = self.find(name) {
+            return Some(index);
+        }
+        if self.list.len() >= MAX_WINDOWS {
+            return None;
+        }
+        let mut buffer = ChatBuffer::new(width);
+        buffer.set_theme(theme);
+        buffer.leave();
+        self.list.push(Window {
+            name: name.to_string(),
+            buffer: Some(buffer),
+            activity: false,
+        });
+        Some(self.list.len() - 1)
+    }
+
+    /// Show window `to`, swapping its scrollback into `visible`. Returns
+    /// false if there's no such window.
+    pub fn switch(&mut self, to: usize, visible: &mut ChatBuffer) -> bool {
+        if to >= self.list.len() {
+            return false;
+        }
+        if to == self.current {
+            return true;
+        }
+        visible.leave();
+        let mut shown = self.list[to]
+            .buffer
+            .take()
+            .expect("hidden window has a buffer");
+        mem::swap(visible, &mut shown);
+        self.list[self.current].buffer = Some(shown);
+        visible.enter();
+        visible.fit_rows();
+        self.list[to].activity = false;
+        self.current = to;
+        true
+    }
+
+    /// Swap window `index`'s scrollback with `visible`; a second call puts
+    /// both back. Lets lines be pushed to a hidden window by code that only
+    /// knows the shown one.
+    pub fn swap(&mut self, index: usize, visible: &mut ChatBuffer) {
+        if let Some(buffer) = self.list[index].buffer.as_mut() {
+            mem::swap(visible, buffer);
+        }
+    }
+
+    /// Window `index`'s scrollback: `visible` if it's the one shown
+    pub fn buffer_mut<'a>(
+        &'a mut self,
+        index: usize,
+        visible: &'a mut ChatBuffer,
+    ) -> &'a mut ChatBuffer {
+        match self.list[index].buffer.as_mut() {
+            Some(buffer) => buffer,
+            None => visible,
+        }
+    }
+
+    /// Something arrived in window `index`. Returns true if it's the one
+    /// shown (the chat area needs redrawing); otherwise it's marked.
+    pub fn arrived(&mut self, index: usize) -> bool {
+        if index == self.current {
+            return true;
+        }
+        self.list[index].activity = true;
+        false
+    }
+
+    /// Close the window shown and go back to the mesh, returning the closed
+    /// window's name (None for the mesh, which stays open)
+    pub fn close(&mut self, visible: &mut ChatBuffer) -> Option<String> {
+        let closing = self.current;
+        if closing == 0 {
+            return None;
+        }
+        self.switch(0, visible);
+        Some(self.list.remove(closing).name)
+    }
+
+    /// A peer changed its name: its direct message window follows
+    pub fn rename_peer(&mut self, old: &str, new: &str) {
+        for window in self.list.iter_mut().skip(1) {
+            if !is_channel(&window.name) && window.name == old {
+                window.name = new.to_string();
+            }
+        }
+    }
+
+    /// The windows for `/window`: `[1:mesh] 2:bob* 3:#retro`, with the one
+    /// shown in brackets and those with something new starred
+    pub fn list_line(&self) -> String {
+        self.list
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let label = format!("{}:{}{}", i + 1, w.name, if w.activity { "*" } else { "" });
+                if i == self.current {
+                    format!("[{}]", label)
+                } else {
+                    label
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// For the status bar: the window shown, if it isn't the mesh, and the
+    /// numbers of those with something new
+    pub fn status(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if !self.is_mesh() {
+            let name = &self.list[self.current].name;
+            parts.push(format!("Win {}:{}", self.current + 1, name));
+        }
+        let active: Vec<String> = (0..self.list.len())
+            .filter(|&i| self.list[i].activity)
+            .map(|i| (i + 1).to_string())
+            .collect();
+        if !active.is_empty() {
+            parts.push(format!("Act: {}", active.join(",")));
+        }
+        (!parts.is_empty()).then(|| parts.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switch_keeps_each_scrollback() {
+        let mut visible = ChatBuffer::new(80);
+        visible.push("mesh line".to_string());
+        let mut windows = Windows::new();
+        let bob = windows.open("bob", 80, Theme::default()).unwrap();
+        assert_eq!(windows.open("Bob", 80, Theme::default()), Some(bob));
+        let retro = windows.open("#retro", 80, Theme::default()).unwrap();
+
+        windows
+            .buffer_mut(bob, &mut visible)
+            .push("from bob".to_string());
+        assert!(!windows.arrived(bob));
+        assert_eq!(windows.list_line(), "[1:mesh] 2:bob* 3:#retro");
+        assert_eq!(windows.status().as_deref(), Some("Act: 2"));
+
+        assert!(windows.switch(bob, &mut visible));
+        assert_eq!(windows.target(), Target::Peer("bob"));
+        assert_eq!(visible.recent_lines(1), vec!["from bob"]);
+        assert_eq!(windows.status().as_deref(), Some("Win 2:bob"));
+        // The mesh's lines wait, and can still be added to
+        windows.swap(0, &mut visible);
+        visible.push("while away".to_string());
+        windows.swap(0, &mut visible);
+        assert!(!windows.arrived(0));
+        assert_eq!(visible.recent_lines(1), vec!["from bob"]);
+
+        assert!(windows.switch(retro, &mut visible));
+        assert_eq!(windows.target(), Target::Channel("#retro"));
+        assert!(!windows.switch(7, &mut visible));
+        assert!(windows.switch(0, &mut visible));
+        assert_eq!(windows.target(), Target::Mesh);
+        assert!(visible.recent_lines(usize::MAX).contains(&"while away"));
+    }
+
+    #[test]
+    fn test_close_and_rename() {
+        let mut visible = ChatBuffer::new(80);
+        let mut windows = Windows::new();
+        assert_eq!(windows.close(&mut visible), None);
+        let bob = windows.open("bob", 80, Theme::default()).unwrap();
+        windows.open("#retro", 80, Theme::default()).unwrap();
+        windows.rename_peer("bob", "robert");
+        assert_eq!(windows.find("robert"), Some(bob));
+
+        windows.switch(bob, &mut visible);
+        assert_eq!(windows.close(&mut visible).as_deref(), Some("robert"));
+        // The windows after it move up a number
+        assert_eq!(windows.list_line(), "[1:mesh] 2:#retro");
+        assert!(windows.is_mesh());
+
+        for i in 0..MAX_WINDOWS {
+            windows.open(&format!("#{}", i), 80, Theme::default());
+        }
+        assert_eq!(windows.open("carol", 80, Theme::default()), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Two nodes on localhost, each with a loopback terminal, talking to each other
+//! (and a third, reachable only through one of them, for mesh relaying).
+//!
+//! Each node is a whole [`App`], so every message goes through the same
+//! dispatch as in the main loop and is drawn on a loopback serial port, and
+//! the tests can assert on what each person would see on their terminal.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+use wormhole::app::App;
+use wormhole::config::Config;
+use wormhole::graphics::Dither;
+use wormhole::network::{FileTransfers, Message};
+use wormhole::serial::Serial;
+use wormhole::terminal::render_stream;
+use wormhole::webcam::{RawFrame, raw_frame_to_output};
+
+/// How long a node waits for more datagrams before it considers itself caught up
+const QUIET: Duration = Duration::from_millis(100);
+
+/// Terminal width for every node
+const WIDTH: usize = 80;
+
+/// One side of the conversation
+struct Node {
+    app: App,
+    files: TempDir,
+    /// The screen shows the call's video rather than the chat
+    showing_video: bool,
+}
+
+impl Node {
+    async fn new(name: &str) -> Self {
+        let ini = format!(
+            "[serial]\nport = loopback-{}\n\n[network]\nname = {}\n\n[terminal]\nmode = vt100\nrenderer = ascii\n",
+            name, name
+        );
+        let config = Config::parse(&ini, Path::new("test.ini")).unwrap();
+        let serial = Serial::loopback(&config.serial);
+        let running = Arc::new(AtomicBool::new(true));
+        // A preview session keeps off the usual port and out of the LAN
+        let mut app = App::with_serial(config, serial, running, true, None, None)
+            .await
+            .unwrap();
+        let files = tempfile::tempdir().unwrap();
+        app.transfers = Some(FileTransfers::open(files.path(), name.to_string()).unwrap());
+        Self {
+            app,
+            files,
+            showing_video: false,
+        }
+    }
+
+    fn addr(&self) -> SocketAddr {
+        let port = self.app.net_node.socket().local_addr().unwrap().port();
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn peer_addr(&self, name: &str) -> SocketAddr {
+        self.app
+            .net_node
+            .peers()
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.addr)
+            .unwrap_or_else(|| panic!("{} doesn't know {}", self.app.config.network.name, name))
+    }
+
+    async fn connect(&mut self, other: &Node) {
+        self.app
+            .net_node
+            .connect_to_peer(other.addr())
+            .await
+            .unwrap();
+    }
+
+    async fn say(&mut self, text: &str) {
+        self.app.send_mesh_chat(text).await;
+    }
+
+    async fn call(&mut self, peer: &str) {
+        let msg = Message::CallRequest {
+            from: self.app.config.network.name.clone(),
+        };
+        self.app
+            .net_node
+            .send_to(&msg, self.peer_addr(peer))
+            .await
+            .unwrap();
+        self.app.active_call = Some(peer.to_string());
+    }
+
+    /// Send one video frame to the call peer
+    async fn send_frame(&mut self, frame: &RawFrame) {
+        let peer = self.app.active_call.clone().expect("not in a call");
+        let addr = self.peer_addr(&peer);
+        let name = self.app.config.network.name.clone();
+        self.app
+            .net_node
+            .send_video_frame(&name, frame.width, frame.height, &frame.pixels, 0, addr)
+            .await
+            .unwrap();
+        self.app.net_node.flush_send_queue().await.unwrap();
+    }
+
+    /// Handle messages until the link goes quiet, as the main loop would
+    async fn pump(&mut self) {
+        let mut last_arrival = Instant::now();
+        while last_arrival.elapsed() < QUIET {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            while let Ok(msg) = self.app.net_rx.try_recv() {
+                self.app.handle_message(msg, WIDTH).await;
+                last_arrival = Instant::now();
+            }
+            while let Ok(event) = self.app.peer_event_rx.try_recv() {
+                self.app.handle_peer_event(event).await;
+                last_arrival = Instant::now();
+            }
+        }
+        self.app.pump_transfers().await;
+        self.app.net_node.flush_send_queue().await.unwrap();
+
+        // The call's video, drawn as the Call tab draws it
+        if let Some((from, frame)) = self.app.current_video_frame.take() {
+            let lines = raw_frame_to_output(&frame, self.app.renderer.as_ref(), Dither::None);
+            let (output, _) = render_stream(&from, &lines, None, WIDTH);
+            self.app.serial.write_str(&output).unwrap();
+            self.showing_video = true;
+        }
+        if !self.showing_video {
+            let chat = self.app.chat_buffer.render();
+            self.app.serial.write_str(&chat).unwrap();
+        }
+    }
+
+    /// Everything on the terminal, one string per row
+    fn screen(&self) -> Vec<String> {
+        self.app.serial.screen().frame().to_strings()
+    }
+
+    fn sees(&self, text: &str) -> bool {
+        self.screen().iter().any(|row| row.contains(text))
+    }
+}
+
+/// Let both nodes catch up with each other
+async fn settle(a: &mut Node, b: &mut Node) {
+    for _ in 0..3 {
+        a.pump().await;
+        b.pump().await;
+    }
+}
+
+/// Two nodes that have joined each other
+async fn connected_pair() -> (Node, Node) {
+    let mut alice = Node::new("alice").await;
+    let mut bob = Node::new("bob").await;
+    alice.connect(&bob).await;
+    bob.connect(&alice).await;
+    settle(&mut alice, &mut bob).await;
+    (alice, bob)
+}
+
+#[tokio::test]
+async fn test_chat_relays_across_mesh() {
+    // alice and carol can only reach bob
+    let (mut alice, mut bob) = connected_pair().await;
+    let mut carol = Node::new("carol").await;
+    carol.connect(&bob).await;
+    bob.connect(&carol).await;
+    for _ in 0..3 {
+        for node in [&mut alice, &mut bob, &mut carol] {
+            node.pump().await;
+        }
+    }
+    assert!(alice.app.net_node.peers().iter().all(|p| p.name != "carol"));
+
+    alice.say("hello mesh").await;
+    carol.say("hi alice, via bob").await;
+    for _ in 0..3 {
+        for node in [&mut alice, &mut bob, &mut carol] {
+            node.pump().await;
+        }
+    }
+    for node in [&alice, &bob, &carol] {
+        let screen = node.screen();
+        let count = |text: &str| screen.iter().filter(|row| row.contains(text)).count();
+        // Exactly once each, however many routes a copy took
+        assert_eq!(count("alice: hello mesh"), 1, "{:#?}", screen);
+        assert_eq!(count("carol: hi alice, via bob"), 1, "{:#?}", screen);
+    }
+}
+
+#[tokio::test]
+async fn test_conversation_call_and_file_transfer() {
+    let (mut alice, mut bob) = connected_pair().await;
+    assert!(alice.sees("bob has joined"), "{:#?}", alice.screen());
+    assert!(bob.sees("alice has joined"), "{:#?}", bob.screen());
+
+    // Chat reaches both screens
+    alice.say("hi bob, got a minute?").await;
+    settle(&mut alice, &mut bob).await;
+    bob.say("sure, call me").await;
+    settle(&mut alice, &mut bob).await;
+    for node in [&alice, &bob] {
+        assert!(
+            node.sees("alice: hi bob, got a minute?"),
+            "{:#?}",
+            node.screen()
+        );
+        assert!(node.sees("bob: sure, call me"), "{:#?}", node.screen());
+    }
+
+    // A call rings, is answered by calling back, and carries video
+    alice.call("bob").await;
+    settle(&mut alice, &mut bob).await;
+    assert!(bob.sees("alice is calling you - press Y"));
+    bob.call("alice").await;
+    settle(&mut alice, &mut bob).await;
+    assert!(alice.sees("Call connected with bob"));
+
+    // Dark on the left, bright on the right
+    let (width, height) = (320, 144);
+    let pixels = (0..width * height)
+        .map(|i| if i % width < width / 2 { 0 } else { 255 })
+        .collect();
+    let frame = RawFrame {
+        width: width as u16,
+        height: height as u16,
+        pixels,
+    };
+    alice.send_frame(&frame).await;
+    settle(&mut alice, &mut bob).await;
+    assert!(bob.sees("@@@@"), "{:#?}", bob.screen());
+    // Hang up, back to the chat
+    for node in [&mut alice, &mut bob] {
+        node.app.active_call = None;
+        node.showing_video = false;
+    }
+
+    // A file offered by alice arrives intact in bob's files directory
+    let source = alice.files.path().join("notes.txt");
+    let contents: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+    std::fs::write(&source, &contents).unwrap();
+    let transfers = alice.app.transfers.as_mut().unwrap();
+    transfers.send("bob", &source).unwrap();
+    settle(&mut alice, &mut bob).await;
+    assert!(bob.sees("alice offers notes.txt"), "{:#?}", bob.screen());
+
+    bob.app.transfers.as_mut().unwrap().accept(None).unwrap();
+    for _ in 0..20 {
+        settle(&mut alice, &mut bob).await;
+        if bob.files.path().join("notes.txt").exists() {
+            break;
+        }
+    }
+    settle(&mut alice, &mut bob).await;
+    let received = std::fs::read(bob.files.path().join("notes.txt")).unwrap();
+    assert_eq!(received, contents);
+    assert!(bob.sees("notes.txt from alice"), "{:#?}", bob.screen());
+    assert!(
+        alice.sees("bob received notes.txt"),
+        "{:#?}",
+        alice.screen()
+    );
+}